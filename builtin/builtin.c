@@ -2,14 +2,4 @@
 #include "stdio.h"
 #include <stdbool.h>
 
-void _printf(char *msg)
-{
-    printf("%s", msg);
-}
-
-void _exit(int code)
-{
-    exit(code);
-}
-
 /* END builtins */
@@ -1,12 +1,466 @@
 /* START builtins */
 
-function _printf(msg) {
-  // Message is casted to string to prevent crash
-  process.stdout.write(msg.toString());
+function _now_millis() {
+  return Math.floor(performance.now());
 }
 
-function _exit(code) {
-  process.exit(code);
+function _now_micros() {
+  return Math.floor(performance.now() * 1000);
+}
+
+function _assert(condition, message, location) {
+  if (!condition) {
+    const details = message ? ": " + message : "";
+    _eprint("Assertion failed at " + location + details + "\n");
+    _exit(1);
+  }
+}
+
+function _panic(message, location) {
+  _eprint("Panicked at " + location + ": " + message + "\n");
+  _exit(1);
+}
+
+// Numbers are exact up to 2^53, so results beyond that are treated as overflows
+function _checked(result, operation, location) {
+  if (!Number.isSafeInteger(result)) {
+    _panic("attempt to " + operation + " with overflow", location);
+  }
+  return result;
+}
+
+function _add_checked(a, b, location) {
+  return _checked(a + b, "add", location);
+}
+
+function _sub_checked(a, b, location) {
+  return _checked(a - b, "subtract", location);
+}
+
+function _mul_checked(a, b, location) {
+  return _checked(a * b, "multiply", location);
+}
+
+function _check_index(arr, index, location) {
+  if (index < 0 || index >= arr.length) {
+    _panic(
+      "index out of bounds: the length is " + arr.length + " but the index is " + index,
+      location
+    );
+  }
+  return index;
+}
+
+// Bitwise operations on the low 32 bits of numbers, which return signed 32-bit
+// integers like the int of native code
+function _bit_and(a, b) {
+  return a & b;
+}
+
+function _bit_or(a, b) {
+  return a | b;
+}
+
+function _bit_xor(a, b) {
+  return a ^ b;
+}
+
+function _shift_left(n, amount) {
+  return n << amount;
+}
+
+function _shift_right(n, amount) {
+  return (n >>> amount) | 0;
+}
+
+function _count_ones(n) {
+  let count = 0;
+  for (let x = n >>> 0; x !== 0; x >>>= 1) {
+    count += x & 1;
+  }
+  return count;
+}
+
+function _leading_zeros(n) {
+  return Math.clz32(n);
+}
+
+function _trailing_zeros(n) {
+  return (n | 0) === 0 ? 32 : 31 - Math.clz32(n & -n);
+}
+
+function _rotate_left(n, amount) {
+  return (n << amount) | (n >>> (32 - (amount & 31)));
+}
+
+// Random numbers of xoshiro128**, computed with the 32-bit operations of the native
+// runtime, so a seeded program draws the same numbers with every backend
+function _random(max) {
+  if (max <= 0) {
+    return 0;
+  }
+  // Larger numbers do not fit into the int of native code
+  max = Math.min(max, 0x7fffffff);
+  const limit = 0x100000000 - (0x100000000 % max);
+  let x;
+  do {
+    x = _random_next();
+  } while (x >= limit);
+  return x % max;
+}
+
+function _random_seed(seed) {
+  // The state is expanded from the seed with SplitMix32
+  const state = new Uint32Array(4);
+  let z = seed >>> 0;
+  for (let i = 0; i < 4; i++) {
+    z = (z + 0x9e3779b9) >>> 0;
+    let x = Math.imul(z ^ (z >>> 16), 0x85ebca6b);
+    x = Math.imul(x ^ (x >>> 13), 0xc2b2ae35);
+    state[i] = x ^ (x >>> 16);
+  }
+  _random_state.words = state;
+}
+
+// Returns the state of the generator, which is seeded with the time until `_random_seed` is called
+function _random_state() {
+  if (_random_state.words === undefined) {
+    _random_seed(Date.now());
+  }
+  return _random_state.words;
+}
+
+function _random_rotl(x, k) {
+  return (x << k) | (x >>> (32 - k));
+}
+
+function _random_next() {
+  const s = _random_state();
+  const result = Math.imul(_random_rotl(Math.imul(s[1], 5), 7), 9) >>> 0;
+  const t = s[1] << 9;
+  s[2] ^= s[0];
+  s[3] ^= s[1];
+  s[1] ^= s[2];
+  s[0] ^= s[3];
+  s[2] ^= t;
+  s[3] = _random_rotl(s[3], 11);
+  return result;
+}
+
+function _int_to_str(n) {
+  return String(n);
+}
+
+// Strings are UTF-8 in every backend, so lengths and offsets count bytes. Strings of
+// JavaScript are UTF-16, so offsets are translated by walking their code points.
+
+// Returns the number of bytes of the UTF-8 encoding of a code point
+function _utf8_width(code) {
+  return code < 0x80 ? 1 : code < 0x800 ? 2 : code < 0x10000 ? 3 : 4;
+}
+
+// Returns the UTF-16 index of a byte offset, or -1 if the offset is inside of a character
+function _utf8_index(s, offset) {
+  let bytes = 0;
+  let i = 0;
+  while (bytes < offset && i < s.length) {
+    const code = s.codePointAt(i);
+    bytes += _utf8_width(code);
+    i += code > 0xffff ? 2 : 1;
+  }
+  return bytes === offset ? i : -1;
+}
+
+function _str_len(s) {
+  let bytes = 0;
+  for (let i = 0; i < s.length; i++) {
+    const code = s.codePointAt(i);
+    bytes += _utf8_width(code);
+    if (code > 0xffff) i++;
+  }
+  return bytes;
+}
+
+function _str_chars(s) {
+  return Array.from(s);
+}
+
+function _str_split(s, sep) {
+  return s.split(sep);
+}
+
+function _str_trim(s) {
+  return s.trim();
+}
+
+function _str_contains(s, sub) {
+  return s.includes(sub);
+}
+
+function _str_substring(s, start, end) {
+  const len = _str_len(s);
+  start = Math.min(Math.max(start, 0), len);
+  end = Math.min(Math.max(end, 0), len);
+  if (start > end) {
+    [start, end] = [end, start];
+  }
+  const from = _utf8_index(s, start);
+  const to = _utf8_index(s, end);
+  if (from < 0 || to < 0) {
+    throw new Error("byte index " + (from < 0 ? start : end) + " is not a character boundary");
+  }
+  return s.substring(from, to);
+}
+
+function _str_replace(s, from, to) {
+  return from === "" ? s : s.split(from).join(to);
+}
+
+function _str_join(parts, sep) {
+  return parts.join(sep);
+}
+
+// Finds the first match of a pattern at or after a byte offset, see `_regex_find` of the
+// native runtime. The `u` flag matches code points and `d` reports the offsets of groups.
+function _regex_find(pattern, text, start) {
+  let re;
+  try {
+    re = new RegExp(pattern, "dgu");
+  } catch (e) {
+    _eprint("Invalid regular expression '" + pattern + "': " + e.message.split("/dgu: ").pop() + "\n");
+    _exit(1);
+  }
+  re.lastIndex = _utf8_index(text, start);
+  const match = re.exec(text);
+  if (match === null) {
+    return [];
+  }
+  const offsets = [];
+  for (const indices of match.indices) {
+    if (indices === undefined) {
+      offsets.push(-1, -1);
+    } else {
+      offsets.push(_str_len(text.substring(0, indices[0])), _str_len(text.substring(0, indices[1])));
+    }
+  }
+  return offsets;
+}
+
+function _str_char_code(s, index) {
+  return new TextEncoder().encode(s)[index];
+}
+
+function _str_from_code_point(code) {
+  return String.fromCodePoint(code);
+}
+
+// Converts a value returned by JSON.parse into a Json value of the standard library
+function _json_from_js(value) {
+  const json = (fields) =>
+    new Json({
+      kind: "null",
+      boolean: false,
+      number: 0,
+      text: "",
+      items: [],
+      keys: [],
+      ...fields,
+    });
+  if (value === null) {
+    return json({});
+  }
+  if (Array.isArray(value)) {
+    return json({ kind: "array", items: value.map(_json_from_js) });
+  }
+  switch (typeof value) {
+    case "boolean":
+      return json({ kind: "bool", boolean: value });
+    case "number":
+      if (!Number.isInteger(value)) {
+        throw new Error("Fractional numbers are not supported");
+      }
+      return json({ kind: "number", number: value });
+    case "string":
+      return json({ kind: "string", text: value });
+    default: {
+      const keys = Object.keys(value);
+      return json({
+        kind: "object",
+        keys,
+        items: keys.map((key) => _json_from_js(value[key])),
+      });
+    }
+  }
+}
+
+function _vec_push(arr, value) {
+  arr.push(value);
+}
+
+function _vec_pop(arr) {
+  if (arr.length === 0) {
+    throw new Error("Cannot pop from an empty array");
+  }
+  return arr.pop();
+}
+
+function _vec_len(arr) {
+  return arr.length;
+}
+
+function _vec_slice(arr, start, end) {
+  const clamp = (i) => Math.min(Math.max(i, 0), arr.length);
+  return arr.slice(clamp(start), clamp(end));
+}
+
+function _vec_concat(a, b) {
+  return a.concat(b);
+}
+
+function _map_insert(map, key, value) {
+  map.set(key, value);
+}
+
+function _map_get(map, key) {
+  if (!map.has(key)) {
+    throw new Error("Key not found in map");
+  }
+  return map.get(key);
+}
+
+function _map_remove(map, key) {
+  map.delete(key);
+}
+
+function _map_contains(map, key) {
+  return map.has(key);
+}
+
+function _map_keys(map) {
+  return Array.from(map.keys());
+}
+
+function _map_len(map) {
+  return map.size;
+}
+
+// Reference to a field of an object or an element of an array
+function _ref(obj, key) {
+  return {
+    get value() {
+      return obj[key];
+    },
+    set value(v) {
+      obj[key] = v;
+    },
+  };
+}
+
+// Copies a struct that is passed to a function by value. Structs in its fields are
+// copied as well, since native code embeds them into the struct.
+function _copy_struct(value) {
+  const copy = Object.create(Object.getPrototypeOf(value));
+  for (const [key, field] of Object.entries(value)) {
+    copy[key] = _is_struct(field) ? _copy_struct(field) : field;
+  }
+  return copy;
+}
+
+function _is_struct(value) {
+  const plain = [Object.prototype, Array.prototype, Map.prototype];
+  return value instanceof Object && !plain.includes(Object.getPrototypeOf(value));
+}
+
+// Tasks run one at a time. A spawned call is queued and runs once the current task waits for
+// an empty channel, or the main function has returned. Structs are passed by value, so they
+// are copied when the call is spawned.
+function _spawn(func, args) {
+  const copies = args.map((arg) => (_is_struct(arg) ? _copy_struct(arg) : arg));
+  _tasks().push(() => func(...copies));
+}
+
+// Returns the calls that have been spawned but not started yet
+function _tasks() {
+  if (_tasks.queue === undefined) {
+    _tasks.queue = [];
+  }
+  return _tasks.queue;
+}
+
+// Runs the next task until it has finished, and returns whether there was one
+function _run_task() {
+  const task = _tasks().shift();
+  if (task === undefined) {
+    return false;
+  }
+  task();
+  return true;
+}
+
+function _run_tasks() {
+  while (_run_task()) {}
+}
+
+function _chan_new() {
+  return { values: [], closed: false };
+}
+
+function _chan_send(chan, value) {
+  if (chan.closed) {
+    throw new Error("Cannot send to a closed channel");
+  }
+  chan.values.push(value);
+}
+
+// Runs other tasks until a value has been sent to the channel, or it has been closed
+function _chan_receive(chan) {
+  while (chan.values.length === 0 && !chan.closed) {
+    if (!_run_task()) {
+      throw new Error("Deadlock: receiving from a channel that no task can send to anymore");
+    }
+  }
+  if (chan.values.length === 0) {
+    return _result_err("Channel is closed");
+  }
+  return _result_ok(chan.values.shift());
+}
+
+function _chan_close(chan) {
+  chan.closed = true;
+}
+
+function _chan_len(chan) {
+  return chan.values.length;
+}
+
+function _result_ok(value) {
+  return { ok: true, value: value };
+}
+
+function _result_err(error) {
+  return { ok: false, error: error };
+}
+
+function _result_is_ok(result) {
+  return result.ok;
+}
+
+function _result_is_error(result) {
+  return !result.ok;
+}
+
+function _result_value(result) {
+  if (!result.ok) {
+    throw new Error("Called value() on an error: " + result.error);
+  }
+  return result.value;
+}
+
+function _result_error(result) {
+  if (result.ok) {
+    throw new Error("Called error() on a successful result");
+  }
+  return result.error;
 }
 
 /* END builtins */
@@ -0,0 +1,95 @@
+/* START browser runtime */
+
+// The console prints whole lines, so output is buffered until a line is complete
+let _stdout = "";
+let _stderr = "";
+
+function _printf(msg) {
+  _stdout += msg.toString();
+  const lines = _stdout.split("\n");
+  _stdout = lines.pop();
+  lines.forEach((line) => console.log(line));
+}
+
+function _eprint(msg) {
+  _stderr += msg;
+  const lines = _stderr.split("\n");
+  _stderr = lines.pop();
+  lines.forEach((line) => console.error(line));
+}
+
+// Prints incomplete lines, once the program has finished
+function _flush() {
+  if (_stdout !== "") console.log(_stdout);
+  if (_stderr !== "") console.error(_stderr);
+  _stdout = "";
+  _stderr = "";
+}
+
+// A page can not be exited, so the program is stopped by an exception
+function _exit(code) {
+  _flush();
+  throw new Error("Program exited with code " + code);
+}
+
+function _read_line() {
+  return window.prompt() ?? "";
+}
+
+// The main thread of a page must not block, so the sleep is busy waiting
+function _sleep(ms) {
+  const end = performance.now() + ms;
+  while (performance.now() < end) {}
+}
+
+function _args() {
+  return [];
+}
+
+function _env(name) {
+  return "";
+}
+
+function _has_env(name) {
+  return false;
+}
+
+function _exec(cmd, args, output) {
+  throw new Error("Could not run '" + cmd + "': processes are not available in the browser");
+}
+
+function _read_file(path) {
+  throw new Error("Could not read file '" + path + "': files are not available in the browser");
+}
+
+function _write_file(path, content) {
+  throw new Error("Could not write file '" + path + "': files are not available in the browser");
+}
+
+function _append_file(path, content) {
+  throw new Error("Could not write file '" + path + "': files are not available in the browser");
+}
+
+function _file_exists(path) {
+  return false;
+}
+
+function _remove_file(path) {
+  throw new Error("Could not remove file '" + path + "': files are not available in the browser");
+}
+
+function _tcp_connect(host, port) {
+  throw new Error("Could not connect to " + host + ":" + port + ": sockets are not available in the browser");
+}
+
+function _tcp_read(socket) {
+  return "";
+}
+
+function _tcp_write(socket, data) {
+  return false;
+}
+
+function _tcp_close(socket) {}
+
+/* END browser runtime */
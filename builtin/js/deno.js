@@ -0,0 +1,122 @@
+/* START deno runtime */
+
+function _write_all(stream, msg) {
+  const bytes = new TextEncoder().encode(msg.toString());
+  let written = 0;
+  while (written < bytes.length) {
+    written += stream.writeSync(bytes.subarray(written));
+  }
+}
+
+function _printf(msg) {
+  _write_all(Deno.stdout, msg);
+}
+
+function _exit(code) {
+  Deno.exit(code);
+}
+
+function _eprint(msg) {
+  _write_all(Deno.stderr, msg);
+}
+
+function _read_line() {
+  // stdin is read byte by byte, so that no input after the line is consumed
+  const buf = new Uint8Array(1);
+  const bytes = [];
+  for (;;) {
+    const n = Deno.stdin.readSync(buf);
+    if (n === null || n === 0 || buf[0] === 10) break;
+    bytes.push(buf[0]);
+  }
+  return new TextDecoder().decode(new Uint8Array(bytes)).replace(/\r$/, "");
+}
+
+function _sleep(ms) {
+  if (ms > 0) {
+    Atomics.wait(new Int32Array(new SharedArrayBuffer(4)), 0, 0, ms);
+  }
+}
+
+function _args() {
+  return Deno.args;
+}
+
+function _env(name) {
+  return Deno.env.get(name) ?? "";
+}
+
+function _has_env(name) {
+  return Deno.env.has(name);
+}
+
+// See `_exec` of the native runtime
+function _exec(cmd, args, output) {
+  let result;
+  try {
+    result = new Deno.Command(cmd, { args, stdin: "inherit", stdout: "piped", stderr: "piped" }).outputSync();
+  } catch (e) {
+    output.push("", "Could not run '" + cmd + "'\n");
+    return 127;
+  }
+  const decoder = new TextDecoder();
+  output.push(decoder.decode(result.stdout), decoder.decode(result.stderr));
+  return result.code;
+}
+
+function _read_file(path) {
+  try {
+    return Deno.readTextFileSync(path);
+  } catch (e) {
+    throw new Error("Could not read file '" + path + "'");
+  }
+}
+
+function _write_file(path, content) {
+  try {
+    Deno.writeTextFileSync(path, content);
+  } catch (e) {
+    throw new Error("Could not write file '" + path + "'");
+  }
+}
+
+function _append_file(path, content) {
+  try {
+    Deno.writeTextFileSync(path, content, { append: true });
+  } catch (e) {
+    throw new Error("Could not write file '" + path + "'");
+  }
+}
+
+function _file_exists(path) {
+  try {
+    Deno.statSync(path);
+    return true;
+  } catch (e) {
+    return false;
+  }
+}
+
+function _remove_file(path) {
+  try {
+    Deno.removeSync(path);
+  } catch (e) {
+    throw new Error("Could not remove file '" + path + "'");
+  }
+}
+
+function _tcp_connect(host, port) {
+  throw new Error("Could not connect to " + host + ":" + port + ": sockets are not available with Deno");
+}
+
+function _tcp_read(socket) {
+  return "";
+}
+
+function _tcp_write(socket, data) {
+  return false;
+}
+
+function _tcp_close(socket) {}
+
+/* END deno runtime */
@@ -0,0 +1,231 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+function _exit(code) {
+  process.exit(code);
+}
+
+function _eprint(msg) {
+  process.stderr.write(msg);
+}
+
+function _read_line() {
+  // stdin is read byte by byte, so that no input after the line is consumed
+  const fs = require("fs");
+  const buf = Buffer.alloc(1);
+  const bytes = [];
+  for (;;) {
+    let n;
+    try {
+      n = fs.readSync(0, buf, 0, 1, null);
+    } catch (e) {
+      if (e.code === "EAGAIN") continue;
+      if (e.code === "EOF") break;
+      throw e;
+    }
+    if (n === 0 || buf[0] === 10) break;
+    bytes.push(buf[0]);
+  }
+  return Buffer.from(bytes).toString("utf8").replace(/\r$/, "");
+}
+
+function _sleep(ms) {
+  if (ms > 0) {
+    Atomics.wait(new Int32Array(new SharedArrayBuffer(4)), 0, 0, ms);
+  }
+}
+
+function _args() {
+  // Skips the paths of node and the script
+  return process.argv.slice(2);
+}
+
+function _env(name) {
+  return process.env[name] ?? "";
+}
+
+function _has_env(name) {
+  return process.env[name] !== undefined;
+}
+
+// See `_exec` of the native runtime
+function _exec(cmd, args, output) {
+  const result = require("child_process").spawnSync(cmd, args, {
+    encoding: "utf8",
+    stdio: ["inherit", "pipe", "pipe"],
+    maxBuffer: Infinity,
+  });
+  if (result.error !== undefined) {
+    output.push("", "Could not run '" + cmd + "'\n");
+    return 127;
+  }
+  output.push(result.stdout, result.stderr);
+  if (result.signal !== null) {
+    return 128 + require("os").constants.signals[result.signal];
+  }
+  return result.status;
+}
+
+function _read_file(path) {
+  try {
+    return require("fs").readFileSync(path, "utf8");
+  } catch (e) {
+    throw new Error("Could not read file '" + path + "'");
+  }
+}
+
+function _write_file(path, content) {
+  try {
+    require("fs").writeFileSync(path, content);
+  } catch (e) {
+    throw new Error("Could not write file '" + path + "'");
+  }
+}
+
+function _append_file(path, content) {
+  try {
+    require("fs").appendFileSync(path, content);
+  } catch (e) {
+    throw new Error("Could not write file '" + path + "'");
+  }
+}
+
+function _file_exists(path) {
+  return require("fs").existsSync(path);
+}
+
+function _remove_file(path) {
+  try {
+    require("fs").unlinkSync(path);
+  } catch (e) {
+    throw new Error("Could not remove file '" + path + "'");
+  }
+}
+
+// Sockets of Node.js are asynchronous, so they are driven by a worker thread. The program
+// waits for the worker to answer each call, and receives the answer from a message port.
+function _net_call(op, ...args) {
+  if (_net_call.port === undefined) {
+    const { Worker, MessageChannel } = require("worker_threads");
+    const { port1, port2 } = new MessageChannel();
+    const signal = new Int32Array(new SharedArrayBuffer(4));
+    const worker = new Worker(_net_worker_source(), {
+      eval: true,
+      workerData: { port: port2, signal },
+      transferList: [port2],
+    });
+    worker.unref();
+    port1.unref();
+    _net_call.port = port1;
+    _net_call.signal = signal;
+  }
+  Atomics.store(_net_call.signal, 0, 0);
+  _net_call.port.postMessage({ op, args });
+  Atomics.wait(_net_call.signal, 0, 0);
+  return require("worker_threads").receiveMessageOnPort(_net_call.port).message;
+}
+
+function _net_worker_source() {
+  return `
+    const net = require("net");
+    const { port, signal } = require("worker_threads").workerData;
+    const sockets = new Map();
+    let next = 0;
+
+    function reply(value) {
+      port.postMessage(value);
+      Atomics.store(signal, 0, 1);
+      Atomics.notify(signal, 0);
+    }
+
+    // Answers a pending read once data has arrived or the connection has ended.
+    // Characters that are split between chunks are decoded once they are complete.
+    function deliver(state) {
+      if (!state.reading || (state.chunks.length === 0 && !state.ended)) {
+        return;
+      }
+      const bytes = Buffer.concat(state.chunks);
+      state.chunks = [];
+      const text = state.decoder.decode(bytes, { stream: !state.ended });
+      if (text === "" && !state.ended) {
+        return;
+      }
+      state.reading = false;
+      reply(text);
+    }
+
+    port.on("message", ({ op, args }) => {
+      const state = sockets.get(args[0]);
+      switch (op) {
+        case "connect": {
+          const socket = net.connect({ host: args[0], port: args[1] });
+          const state = { socket, chunks: [], ended: false, reading: false, decoder: new TextDecoder() };
+          let connected = false;
+          socket.once("connect", () => {
+            connected = true;
+            sockets.set(next, state);
+            reply(next++);
+          });
+          socket.on("data", (chunk) => {
+            state.chunks.push(chunk);
+            deliver(state);
+          });
+          socket.on("close", () => {
+            state.ended = true;
+            deliver(state);
+          });
+          socket.on("error", () => {
+            if (!connected) {
+              reply(-1);
+            }
+          });
+          break;
+        }
+        case "read":
+          if (state === undefined) {
+            reply("");
+            break;
+          }
+          state.reading = true;
+          deliver(state);
+          break;
+        case "write":
+          if (state === undefined || state.ended) {
+            reply(false);
+            break;
+          }
+          state.socket.write(args[1], (error) => reply(!error));
+          break;
+        case "close":
+          if (state !== undefined) {
+            state.socket.destroy();
+            sockets.delete(args[0]);
+          }
+          reply(null);
+          break;
+      }
+    });
+  `;
+}
+
+function _tcp_connect(host, port) {
+  return _net_call("connect", host, port);
+}
+
+function _tcp_read(socket) {
+  return _net_call("read", socket);
+}
+
+function _tcp_write(socket, data) {
+  return _net_call("write", socket, data);
+}
+
+function _tcp_close(socket) {
+  _net_call("close", socket);
+}
+
+/* END node runtime */
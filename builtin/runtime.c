@@ -0,0 +1,1993 @@
+/* START runtime */
+/*
+ * Runtime functions of the C and QBE backends.
+ * Strings are NUL-terminated, arrays are growable vectors.
+ */
+#include <ctype.h>
+#include <errno.h>
+#include <netdb.h>
+#include <poll.h>
+#include <setjmp.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+
+/* Writing to a closed socket returns an error instead of raising SIGPIPE, where it is supported */
+#ifndef MSG_NOSIGNAL
+#define MSG_NOSIGNAL 0
+#endif
+
+/*
+ * Garbage collector. Every allocation of the runtime is tracked, and allocations
+ * that are no longer reachable are freed by a conservative mark-and-sweep collection:
+ * any word on the stack, in the data segment or in a reachable allocation that points
+ * into an allocation keeps it alive.
+ *
+ * The data segment can only be located on Linux. On other systems, memory is
+ * never collected.
+ */
+#if defined(__linux__)
+extern char __data_start[], _end[];
+#define GC_ENABLED 1
+#else
+#define GC_ENABLED 0
+#endif
+
+struct _gc_object
+{
+    char *start;
+    size_t size;
+    int marked;
+};
+
+/* Tracked allocations, sorted by their address during a collection */
+static struct _gc_object *_gc_objects;
+static size_t _gc_len;
+static size_t _gc_cap;
+/* Bytes allocated since the last collection, and the amount that triggers the next one */
+static size_t _gc_allocated;
+static size_t _gc_threshold = 1 << 20;
+/* Highest address of the stack that is scanned. Collections are disabled until it is set. */
+static char *_gc_stack_bottom;
+
+static int _gc_compare(const void *a, const void *b)
+{
+    char *x = ((const struct _gc_object *)a)->start;
+    char *y = ((const struct _gc_object *)b)->start;
+    return (x > y) - (x < y);
+}
+
+/* Returns the allocation that contains the address, or NULL */
+static struct _gc_object *_gc_find(char *p)
+{
+    size_t low = 0;
+    size_t high = _gc_len;
+    while (low < high)
+    {
+        size_t mid = low + (high - low) / 2;
+        if (_gc_objects[mid].start <= p)
+            low = mid + 1;
+        else
+            high = mid;
+    }
+    if (low == 0)
+        return NULL;
+    struct _gc_object *obj = &_gc_objects[low - 1];
+    return p < obj->start + obj->size ? obj : NULL;
+}
+
+static struct _gc_object **_gc_worklist;
+static size_t _gc_worklist_len;
+
+/* Marks the allocations referenced by the words of a memory range */
+static void _gc_mark_range(char *from, char *to)
+{
+    from = (char *)(((unsigned long)from + sizeof(long) - 1) & ~(sizeof(long) - 1));
+    for (char **p = (char **)from; (char *)(p + 1) <= to; p++)
+    {
+        struct _gc_object *obj = _gc_find(*p);
+        if (obj != NULL && !obj->marked)
+        {
+            obj->marked = 1;
+            _gc_worklist[_gc_worklist_len++] = obj;
+        }
+    }
+}
+
+void _gc_collect()
+{
+    if (!GC_ENABLED || _gc_stack_bottom == NULL)
+        return;
+
+    /* Spills the registers to the stack, so that they are scanned as well */
+    jmp_buf registers;
+    setjmp(registers);
+
+    qsort(_gc_objects, _gc_len, sizeof(struct _gc_object), _gc_compare);
+    /* Every object is added to the worklist at most once */
+    _gc_worklist = malloc((_gc_len > 0 ? _gc_len : 1) * sizeof(struct _gc_object *));
+    _gc_worklist_len = 0;
+
+    _gc_mark_range((char *)&registers, _gc_stack_bottom);
+#if GC_ENABLED
+    _gc_mark_range(__data_start, _end);
+#endif
+    while (_gc_worklist_len > 0)
+    {
+        struct _gc_object *obj = _gc_worklist[--_gc_worklist_len];
+        _gc_mark_range(obj->start, obj->start + obj->size);
+    }
+    free(_gc_worklist);
+
+    size_t live = 0;
+    size_t kept = 0;
+    for (size_t i = 0; i < _gc_len; i++)
+    {
+        if (_gc_objects[i].marked)
+        {
+            _gc_objects[i].marked = 0;
+            live += _gc_objects[i].size;
+            _gc_objects[kept++] = _gc_objects[i];
+        }
+        else
+        {
+            free(_gc_objects[i].start);
+        }
+    }
+    _gc_len = kept;
+    _gc_allocated = 0;
+    _gc_threshold = live > (1 << 20) ? live : (1 << 20);
+}
+
+/* Allocates zeroed memory that is freed once it is no longer reachable */
+void *_gc_alloc(size_t size)
+{
+    if (_gc_allocated > _gc_threshold)
+        _gc_collect();
+
+    size = size > 0 ? size : 1;
+    char *p = calloc(1, size);
+    if (_gc_len == _gc_cap)
+    {
+        _gc_cap = _gc_cap > 0 ? _gc_cap * 2 : 256;
+        _gc_objects = realloc(_gc_objects, _gc_cap * sizeof(struct _gc_object));
+    }
+    if (p == NULL || _gc_objects == NULL)
+    {
+        fprintf(stderr, "Out of memory\n");
+        exit(1);
+    }
+    _gc_objects[_gc_len++] = (struct _gc_object){p, size, 0};
+    _gc_allocated += size;
+    return p;
+}
+
+/* Moves an allocation to a new allocation of the given size. The old one is collected. */
+void *_gc_realloc(void *ptr, size_t old_size, size_t size)
+{
+    char *p = _gc_alloc(size);
+    memcpy(p, ptr, old_size < size ? old_size : size);
+    return p;
+}
+
+void _printf(char *msg)
+{
+    printf("%s", msg);
+}
+
+void _exit(int code)
+{
+    exit(code);
+}
+
+void _assert(int condition, char *message, char *location)
+{
+    if (condition)
+        return;
+    fflush(stdout);
+    if (*message)
+        fprintf(stderr, "Assertion failed at %s: %s\n", location, message);
+    else
+        fprintf(stderr, "Assertion failed at %s\n", location);
+    exit(1);
+}
+
+void _panic(char *message, char *location)
+{
+    fflush(stdout);
+    fprintf(stderr, "Panicked at %s: %s\n", location, message);
+    exit(1);
+}
+
+/* Checked integer arithmetic of builds with overflow checks */
+int _add_checked(int a, int b, char *location)
+{
+    int result;
+    if (__builtin_add_overflow(a, b, &result))
+        _panic("attempt to add with overflow", location);
+    return result;
+}
+
+int _sub_checked(int a, int b, char *location)
+{
+    int result;
+    if (__builtin_sub_overflow(a, b, &result))
+        _panic("attempt to subtract with overflow", location);
+    return result;
+}
+
+int _mul_checked(int a, int b, char *location)
+{
+    int result;
+    if (__builtin_mul_overflow(a, b, &result))
+        _panic("attempt to multiply with overflow", location);
+    return result;
+}
+
+/*
+ * Bitwise operations on the 32 bits of an int. The amounts of shifts and rotations
+ * are taken modulo 32, like the QBE backend and JavaScript do. The QBE backend lowers
+ * `_bit_and`, `_bit_or`, `_bit_xor` and the shifts to instructions instead of calling them.
+ */
+int _bit_and(int a, int b)
+{
+    return a & b;
+}
+
+int _bit_or(int a, int b)
+{
+    return a | b;
+}
+
+int _bit_xor(int a, int b)
+{
+    return a ^ b;
+}
+
+int _shift_left(int n, int amount)
+{
+    return (uint32_t)n << (amount & 31);
+}
+
+/* Shifts to the right, filling the top with zeros */
+int _shift_right(int n, int amount)
+{
+    return (uint32_t)n >> (amount & 31);
+}
+
+int _count_ones(int n)
+{
+    return __builtin_popcount((uint32_t)n);
+}
+
+int _leading_zeros(int n)
+{
+    return n == 0 ? 32 : __builtin_clz((uint32_t)n);
+}
+
+int _trailing_zeros(int n)
+{
+    return n == 0 ? 32 : __builtin_ctz((uint32_t)n);
+}
+
+int _rotate_left(int n, int amount)
+{
+    uint32_t x = n;
+    amount &= 31;
+    return (x << amount) | (x >> ((32 - amount) & 31));
+}
+
+/*
+ * Random numbers of xoshiro128**. JavaScript output computes them with the same 32-bit
+ * operations, so a seeded program draws the same numbers with every backend.
+ * Until `_random_seed` is called, the generator is seeded with the time.
+ */
+static uint32_t _random_state[4];
+static int _random_seeded = 0;
+
+static uint32_t _random_rotl(uint32_t x, int k)
+{
+    return (x << k) | (x >> (32 - k));
+}
+
+void _random_seed(int seed)
+{
+    /* The state is expanded from the seed with SplitMix32 */
+    uint32_t z = (uint32_t)seed;
+    for (int i = 0; i < 4; i++)
+    {
+        z += 0x9e3779b9;
+        uint32_t x = z;
+        x = (x ^ (x >> 16)) * 0x85ebca6b;
+        x = (x ^ (x >> 13)) * 0xc2b2ae35;
+        _random_state[i] = x ^ (x >> 16);
+    }
+    _random_seeded = 1;
+}
+
+static uint32_t _random_next()
+{
+    if (!_random_seeded)
+        _random_seed(time(NULL));
+    uint32_t *s = _random_state;
+    uint32_t result = _random_rotl(s[1] * 5, 7) * 9;
+    uint32_t t = s[1] << 9;
+    s[2] ^= s[0];
+    s[3] ^= s[1];
+    s[1] ^= s[2];
+    s[0] ^= s[3];
+    s[2] ^= t;
+    s[3] = _random_rotl(s[3], 11);
+    return result;
+}
+
+int _random(int max)
+{
+    if (max <= 0)
+        return 0;
+    /* Numbers from the incomplete range at the top are drawn again, so every result is equally likely */
+    uint64_t limit = 0x100000000 - 0x100000000 % max;
+    uint32_t x;
+    do
+        x = _random_next();
+    while (x >= limit);
+    return x % max;
+}
+
+/* Elements of any type are stored in 8 byte slots */
+struct vec
+{
+    long *data;
+    long len;
+    long cap;
+};
+
+/* Returns the index if it is within the bounds of the array, panics otherwise */
+int _check_index(struct vec *v, int index, char *location)
+{
+    if (index < 0 || index >= v->len)
+    {
+        char message[96];
+        snprintf(message, sizeof(message), "index out of bounds: the length is %ld but the index is %d", v->len, index);
+        _panic(message, location);
+    }
+    return index;
+}
+
+struct vec *_vec_new(long len)
+{
+    struct vec *v = _gc_alloc(sizeof(struct vec));
+    v->data = _gc_alloc((len > 0 ? len : 1) * sizeof(long));
+    v->len = len;
+    v->cap = len;
+    return v;
+}
+
+void _vec_push(struct vec *v, long value)
+{
+    if (v->len == v->cap)
+    {
+        long old_cap = v->cap > 0 ? v->cap : 1;
+        v->cap = v->cap > 0 ? v->cap * 2 : 4;
+        v->data = _gc_realloc(v->data, old_cap * sizeof(long), v->cap * sizeof(long));
+    }
+    v->data[v->len++] = value;
+}
+
+long _vec_pop(struct vec *v)
+{
+    if (v->len == 0)
+    {
+        fprintf(stderr, "Cannot pop from an empty array\n");
+        exit(1);
+    }
+    return v->data[--v->len];
+}
+
+int _vec_len(struct vec *v)
+{
+    return v->len;
+}
+
+struct vec *_vec_slice(struct vec *v, int start, int end)
+{
+    start = start < 0 ? 0 : (start > v->len ? v->len : start);
+    end = end < start ? start : (end > v->len ? v->len : end);
+    struct vec *res = _vec_new(end - start);
+    memcpy(res->data, v->data + start, (end - start) * sizeof(long));
+    return res;
+}
+
+struct vec *_vec_concat(struct vec *a, struct vec *b)
+{
+    struct vec *res = _vec_new(a->len + b->len);
+    memcpy(res->data, a->data, a->len * sizeof(long));
+    memcpy(res->data + a->len, b->data, b->len * sizeof(long));
+    return res;
+}
+
+static char *_str_copy(const char *s, size_t len)
+{
+    char *res = _gc_alloc(len + 1);
+    memcpy(res, s, len);
+    res[len] = '\0';
+    return res;
+}
+
+char *_int_to_str(int n)
+{
+    char *res = _gc_alloc(12);
+    sprintf(res, "%d", n);
+    return res;
+}
+
+int _str_len(char *s)
+{
+    return strlen(s);
+}
+
+struct vec *_str_split(char *s, char *sep)
+{
+    size_t sep_len = strlen(sep);
+    struct vec *v = _vec_new(0);
+    char *start = s;
+    if (sep_len > 0)
+    {
+        for (char *end = strstr(start, sep); end != NULL; end = strstr(start, sep))
+        {
+            _vec_push(v, (long)_str_copy(start, end - start));
+            start = end + sep_len;
+        }
+    }
+    _vec_push(v, (long)_str_copy(start, strlen(start)));
+    return v;
+}
+
+/* Returns 1 if a byte offset of a string is not inside of a UTF-8 encoded character */
+static int _str_is_boundary(char *s, int offset)
+{
+    return ((unsigned char)s[offset] & 0xc0) != 0x80;
+}
+
+/* Splits a string into its UTF-8 encoded characters */
+struct vec *_str_chars(char *s)
+{
+    struct vec *v = _vec_new(0);
+    while (*s)
+    {
+        size_t len = 1;
+        while (!_str_is_boundary(s, len))
+            len++;
+        _vec_push(v, (long)_str_copy(s, len));
+        s += len;
+    }
+    return v;
+}
+
+char *_str_trim(char *s)
+{
+    while (isspace((unsigned char)*s))
+        s++;
+    size_t len = strlen(s);
+    while (len > 0 && isspace((unsigned char)s[len - 1]))
+        len--;
+    return _str_copy(s, len);
+}
+
+int _str_contains(char *s, char *sub)
+{
+    return strstr(s, sub) != NULL;
+}
+
+char *_str_substring(char *s, int start, int end)
+{
+    int len = strlen(s);
+    start = start < 0 ? 0 : (start > len ? len : start);
+    end = end < 0 ? 0 : (end > len ? len : end);
+    if (start > end)
+    {
+        int tmp = start;
+        start = end;
+        end = tmp;
+    }
+    if (!_str_is_boundary(s, start) || !_str_is_boundary(s, end))
+    {
+        fprintf(stderr, "byte index %d is not a character boundary\n",
+                _str_is_boundary(s, start) ? end : start);
+        exit(1);
+    }
+    return _str_copy(s + start, end - start);
+}
+
+char *_str_replace(char *s, char *from, char *to)
+{
+    size_t from_len = strlen(from);
+    if (from_len == 0)
+        return _str_copy(s, strlen(s));
+
+    size_t to_len = strlen(to);
+    size_t count = 0;
+    for (char *p = strstr(s, from); p != NULL; p = strstr(p + from_len, from))
+        count++;
+
+    char *res = _gc_alloc(strlen(s) + count * to_len - count * from_len + 1);
+    char *out = res;
+    char *start = s;
+    for (char *p = strstr(s, from); p != NULL; p = strstr(start, from))
+    {
+        memcpy(out, start, p - start);
+        out += p - start;
+        memcpy(out, to, to_len);
+        out += to_len;
+        start = p + from_len;
+    }
+    strcpy(out, start);
+    return res;
+}
+
+/* Joins strings with a separator, copying every string once */
+char *_str_join(struct vec *parts, char *sep)
+{
+    size_t sep_len = strlen(sep);
+    size_t len = 0;
+    for (long i = 0; i < parts->len; i++)
+        len += strlen((char *)parts->data[i]) + (i > 0 ? sep_len : 0);
+
+    char *res = _gc_alloc(len + 1);
+    char *out = res;
+    for (long i = 0; i < parts->len; i++)
+    {
+        if (i > 0)
+        {
+            memcpy(out, sep, sep_len);
+            out += sep_len;
+        }
+        size_t part_len = strlen((char *)parts->data[i]);
+        memcpy(out, (char *)parts->data[i], part_len);
+        out += part_len;
+    }
+    *out = '\0';
+    return res;
+}
+
+/* Returns the byte of a string at an index */
+int _str_char_code(char *s, int index)
+{
+    return (unsigned char)s[index];
+}
+
+/* Returns the UTF-8 encoding of a code point */
+char *_str_from_code_point(int code)
+{
+    char *res = _gc_alloc(5);
+    if (code < 0x80)
+    {
+        res[0] = code;
+    }
+    else if (code < 0x800)
+    {
+        res[0] = 0xc0 | (code >> 6);
+        res[1] = 0x80 | (code & 0x3f);
+    }
+    else if (code < 0x10000)
+    {
+        res[0] = 0xe0 | (code >> 12);
+        res[1] = 0x80 | ((code >> 6) & 0x3f);
+        res[2] = 0x80 | (code & 0x3f);
+    }
+    else
+    {
+        res[0] = 0xf0 | (code >> 18);
+        res[1] = 0x80 | ((code >> 12) & 0x3f);
+        res[2] = 0x80 | ((code >> 6) & 0x3f);
+        res[3] = 0x80 | (code & 0x3f);
+    }
+    return res;
+}
+
+/* Returns the next line of stdin without the line break, or an empty string at the end of the input */
+char *_read_line()
+{
+    fflush(stdout);
+    char *line = NULL;
+    size_t cap = 0;
+    ssize_t len = getline(&line, &cap, stdin);
+    if (len == -1)
+    {
+        free(line);
+        return "";
+    }
+    while (len > 0 && (line[len - 1] == '\n' || line[len - 1] == '\r'))
+        len--;
+    char *res = _str_copy(line, len);
+    free(line);
+    return res;
+}
+
+static long _monotonic_micros()
+{
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec * 1000000 + ts.tv_nsec / 1000;
+}
+
+/* Microseconds since the start of the program. The start is recorded by the entry point */
+static long _elapsed_micros()
+{
+    static long start = -1;
+    long now = _monotonic_micros();
+    if (start == -1)
+        start = now;
+    return now - start;
+}
+
+/* Milliseconds since the start of the program */
+int _now_millis()
+{
+    return _elapsed_micros() / 1000;
+}
+
+/* Microseconds since the start of the program, e.g. to measure benchmarks */
+int _now_micros()
+{
+    return _elapsed_micros();
+}
+
+void _sleep(int ms)
+{
+    if (ms <= 0)
+        return;
+    fflush(stdout);
+    struct timespec ts = {ms / 1000, (ms % 1000) * 1000000L};
+    while (nanosleep(&ts, &ts) == -1)
+        ;
+}
+
+/* Command line arguments, captured by the entry point before main is called */
+static int _argc;
+static char **_argv;
+
+void _init_args(int argc, char **argv)
+{
+    _argc = argc;
+    _argv = argv;
+    /* The arguments are stored above the stack frames of the program */
+    _gc_stack_bottom = (char *)argv;
+    _elapsed_micros();
+}
+
+/* Returns the arguments of the program, without the name of the executable */
+struct vec *_args()
+{
+    long len = _argc > 1 ? _argc - 1 : 0;
+    struct vec *args = _vec_new(len);
+    for (long i = 0; i < len; i++)
+        args->data[i] = (long)_argv[i + 1];
+    return args;
+}
+
+char *_env(char *name)
+{
+    char *value = getenv(name);
+    return value == NULL ? "" : value;
+}
+
+int _has_env(char *name)
+{
+    return getenv(name) != NULL;
+}
+
+/*
+ * Runs a command and waits until it exits. Its output is read from two pipes, whichever
+ * has data, so that a command that fills one of them while the other is read does not block.
+ * Pushes the output and the error output to `output` and returns the exit code, which is
+ * 128 plus the signal if the command was killed and 127 if it could not be started.
+ */
+int _exec(char *cmd, struct vec *args, struct vec *output)
+{
+    int pipes[2][2];
+    if (pipe(pipes[0]) != 0 || pipe(pipes[1]) != 0)
+    {
+        fprintf(stderr, "Could not run '%s'\n", cmd);
+        exit(1);
+    }
+    char **argv = malloc((args->len + 2) * sizeof(char *));
+    argv[0] = cmd;
+    for (long i = 0; i < args->len; i++)
+        argv[i + 1] = (char *)args->data[i];
+    argv[args->len + 1] = NULL;
+
+    /* Buffered output would otherwise be written by both processes */
+    fflush(stdout);
+    fflush(stderr);
+    pid_t pid = fork();
+    if (pid == 0)
+    {
+        dup2(pipes[0][1], STDOUT_FILENO);
+        dup2(pipes[1][1], STDERR_FILENO);
+        for (int i = 0; i < 2; i++)
+        {
+            close(pipes[i][0]);
+            close(pipes[i][1]);
+        }
+        execvp(cmd, argv);
+        fprintf(stderr, "Could not run '%s'\n", cmd);
+        _Exit(127);
+    }
+    free(argv);
+
+    char *bufs[2] = {NULL, NULL};
+    size_t lens[2] = {0, 0};
+    size_t caps[2] = {0, 0};
+    struct pollfd fds[2];
+    for (int i = 0; i < 2; i++)
+    {
+        close(pipes[i][1]);
+        fds[i].fd = pid < 0 ? -1 : pipes[i][0];
+        fds[i].events = POLLIN;
+    }
+    while (fds[0].fd >= 0 || fds[1].fd >= 0)
+    {
+        if (poll(fds, 2, -1) < 0)
+        {
+            if (errno == EINTR)
+                continue;
+            break;
+        }
+        for (int i = 0; i < 2; i++)
+        {
+            if (fds[i].fd < 0 || fds[i].revents == 0)
+                continue;
+            if (caps[i] - lens[i] < 4096)
+            {
+                caps[i] = caps[i] * 2 + 4096;
+                bufs[i] = realloc(bufs[i], caps[i]);
+            }
+            ssize_t n = read(fds[i].fd, bufs[i] + lens[i], caps[i] - lens[i]);
+            if (n < 0 && errno == EINTR)
+                continue;
+            if (n <= 0)
+                fds[i].fd = -1;
+            else
+                lens[i] += n;
+        }
+    }
+    for (int i = 0; i < 2; i++)
+    {
+        close(pipes[i][0]);
+        _vec_push(output, (long)_str_copy(bufs[i] == NULL ? "" : bufs[i], lens[i]));
+        free(bufs[i]);
+    }
+
+    if (pid < 0)
+        return 127;
+    int status;
+    while (waitpid(pid, &status, 0) < 0)
+    {
+        if (errno != EINTR)
+            return 127;
+    }
+    if (WIFSIGNALED(status))
+        return 128 + WTERMSIG(status);
+    return WEXITSTATUS(status);
+}
+
+static void _io_error(const char *action, char *path)
+{
+    fprintf(stderr, "Could not %s file '%s'\n", action, path);
+    exit(1);
+}
+
+char *_read_file(char *path)
+{
+    FILE *file = fopen(path, "rb");
+    if (file == NULL)
+        _io_error("read", path);
+
+    size_t len = 0;
+    size_t cap = 4096;
+    char *content = _gc_alloc(cap);
+    size_t n;
+    while ((n = fread(content + len, 1, cap - len - 1, file)) > 0)
+    {
+        len += n;
+        if (cap - len - 1 == 0)
+        {
+            content = _gc_realloc(content, cap, cap * 2);
+            cap *= 2;
+        }
+    }
+    fclose(file);
+    content[len] = '\0';
+    return content;
+}
+
+static void _write(char *path, char *content, const char *mode)
+{
+    FILE *file = fopen(path, mode);
+    if (file == NULL || fputs(content, file) == EOF)
+        _io_error("write", path);
+    fclose(file);
+}
+
+void _write_file(char *path, char *content)
+{
+    _write(path, content, "wb");
+}
+
+void _append_file(char *path, char *content)
+{
+    _write(path, content, "ab");
+}
+
+int _file_exists(char *path)
+{
+    FILE *file = fopen(path, "rb");
+    if (file == NULL)
+        return 0;
+    fclose(file);
+    return 1;
+}
+
+void _remove_file(char *path)
+{
+    if (remove(path) != 0)
+        _io_error("remove", path);
+}
+
+/*
+ * TCP sockets, which are identified by their file descriptor. Reads return text, so the
+ * bytes of a character that is split between two reads are kept until the next one.
+ */
+struct _tcp_pending
+{
+    char bytes[4];
+    int len;
+};
+
+static struct _tcp_pending *_tcp_pending;
+static int _tcp_pending_len;
+
+static struct _tcp_pending *_tcp_pending_of(int fd)
+{
+    if (fd >= _tcp_pending_len)
+    {
+        int len = fd + 16;
+        _tcp_pending = realloc(_tcp_pending, len * sizeof(struct _tcp_pending));
+        memset(_tcp_pending + _tcp_pending_len, 0, (len - _tcp_pending_len) * sizeof(struct _tcp_pending));
+        _tcp_pending_len = len;
+    }
+    return &_tcp_pending[fd];
+}
+
+/* Returns the socket of a connection to a host, or -1 if it can not be connected */
+int _tcp_connect(char *host, int port)
+{
+    struct addrinfo hints = {0};
+    hints.ai_family = AF_UNSPEC;
+    hints.ai_socktype = SOCK_STREAM;
+    char service[12];
+    snprintf(service, sizeof(service), "%d", port);
+    struct addrinfo *addrs;
+    if (getaddrinfo(host, service, &hints, &addrs) != 0)
+        return -1;
+
+    int fd = -1;
+    for (struct addrinfo *addr = addrs; addr != NULL; addr = addr->ai_next)
+    {
+        fd = socket(addr->ai_family, addr->ai_socktype, addr->ai_protocol);
+        if (fd < 0)
+            continue;
+        if (connect(fd, addr->ai_addr, addr->ai_addrlen) == 0)
+            break;
+        close(fd);
+        fd = -1;
+    }
+    freeaddrinfo(addrs);
+    if (fd >= 0)
+        _tcp_pending_of(fd)->len = 0;
+    return fd;
+}
+
+/* Waits until data arrives and returns it, or returns an empty string once the connection has ended */
+char *_tcp_read(int fd)
+{
+    struct _tcp_pending *pending = _tcp_pending_of(fd);
+    char buf[65536];
+    for (;;)
+    {
+        memcpy(buf, pending->bytes, pending->len);
+        ssize_t n = recv(fd, buf + pending->len, sizeof(buf) - pending->len, 0);
+        if (n < 0 && errno == EINTR)
+            continue;
+        if (n <= 0)
+        {
+            pending->len = 0;
+            return "";
+        }
+        int len = pending->len + n;
+        /* The last character is complete unless its first byte announces more bytes than follow it */
+        int start = len - 1;
+        while (start > 0 && start > len - 4 && ((unsigned char)buf[start] & 0xc0) == 0x80)
+            start--;
+        unsigned char lead = buf[start];
+        int width = lead < 0xc0 ? 1 : lead < 0xe0 ? 2 : lead < 0xf0 ? 3 : 4;
+        int complete = start + width <= len ? len : start;
+        pending->len = len - complete;
+        memcpy(pending->bytes, buf + complete, pending->len);
+        if (complete > 0)
+            return _str_copy(buf, complete);
+    }
+}
+
+/* Sends all of the data, and returns whether it could be sent */
+int _tcp_write(int fd, char *data)
+{
+    size_t len = strlen(data);
+    while (len > 0)
+    {
+        ssize_t n = send(fd, data, len, MSG_NOSIGNAL);
+        if (n < 0 && errno == EINTR)
+            continue;
+        if (n <= 0)
+            return 0;
+        data += n;
+        len -= n;
+    }
+    return 1;
+}
+
+void _tcp_close(int fd)
+{
+    if (fd < 0)
+        return;
+    _tcp_pending_of(fd)->len = 0;
+    close(fd);
+}
+
+/* Results hold either a value in an 8 byte slot or an error message */
+struct result
+{
+    int ok;
+    long value;
+    char *error;
+};
+
+struct result *_result_ok(long value)
+{
+    struct result *r = _gc_alloc(sizeof(struct result));
+    r->ok = 1;
+    r->value = value;
+    r->error = NULL;
+    return r;
+}
+
+struct result *_result_err(char *error)
+{
+    struct result *r = _gc_alloc(sizeof(struct result));
+    r->ok = 0;
+    r->value = 0;
+    r->error = error;
+    return r;
+}
+
+int _result_is_ok(struct result *r)
+{
+    return r->ok;
+}
+
+int _result_is_error(struct result *r)
+{
+    return !r->ok;
+}
+
+long _result_value(struct result *r)
+{
+    if (!r->ok)
+    {
+        fprintf(stderr, "Called value() on an error: %s\n", r->error);
+        exit(1);
+    }
+    return r->value;
+}
+
+char *_result_error(struct result *r)
+{
+    if (r->ok)
+    {
+        fprintf(stderr, "Called error() on a successful result\n");
+        exit(1);
+    }
+    return r->error;
+}
+
+/*
+ * Tasks run one at a time. A spawned call is queued and runs once the current task waits for
+ * an empty channel, or the main function has returned. Each task is a function generated for
+ * the call, which reads the arguments from memory that holds their values.
+ */
+struct _task
+{
+    void (*run)(void *);
+    void *args;
+};
+
+/* Spawned calls that have not started yet, in a collected allocation that is reachable from here */
+static struct vec *_tasks;
+static long _tasks_next;
+
+void _spawn(void (*run)(void *), void *args)
+{
+    if (_tasks == NULL)
+        _tasks = _vec_new(0);
+    struct _task *task = _gc_alloc(sizeof(struct _task));
+    task->run = run;
+    task->args = args;
+    _vec_push(_tasks, (long)task);
+}
+
+/* Runs the next task until it has finished, and returns whether there was one */
+static int _run_task()
+{
+    if (_tasks == NULL || _tasks_next == _tasks->len)
+        return 0;
+    struct _task *task = (struct _task *)_tasks->data[_tasks_next];
+    _tasks->data[_tasks_next++] = 0;
+    /* The queue starts over once it has run empty, so that it does not keep growing */
+    if (_tasks_next == _tasks->len)
+        _tasks->len = _tasks_next = 0;
+    task->run(task->args);
+    return 1;
+}
+
+void _run_tasks()
+{
+    while (_run_task())
+        ;
+}
+
+/* Values of any type are stored in 8 byte slots, and received in the order they were sent */
+struct chan
+{
+    struct vec *values;
+    long next;
+    int closed;
+};
+
+struct chan *_chan_new()
+{
+    struct chan *c = _gc_alloc(sizeof(struct chan));
+    c->values = _vec_new(0);
+    return c;
+}
+
+void _chan_send(struct chan *c, long value)
+{
+    if (c->closed)
+    {
+        fprintf(stderr, "Cannot send to a closed channel\n");
+        exit(1);
+    }
+    _vec_push(c->values, value);
+}
+
+/* Runs other tasks until a value has been sent to the channel, or it has been closed */
+struct result *_chan_receive(struct chan *c)
+{
+    while (c->next == c->values->len && !c->closed)
+    {
+        if (!_run_task())
+        {
+            fflush(stdout);
+            fprintf(stderr, "Deadlock: receiving from a channel that no task can send to anymore\n");
+            exit(1);
+        }
+    }
+    if (c->next == c->values->len)
+        return _result_err("Channel is closed");
+    long value = c->values->data[c->next];
+    c->values->data[c->next++] = 0;
+    if (c->next == c->values->len)
+        c->values->len = c->next = 0;
+    return _result_ok(value);
+}
+
+void _chan_close(struct chan *c)
+{
+    c->closed = 1;
+}
+
+int _chan_len(struct chan *c)
+{
+    return c->values->len - c->next;
+}
+
+/*
+ * Atomics and locks are integers in memory of their own, which are changed with the atomic
+ * builtins of the C compiler. Only the native runtime implements them, since JavaScript has
+ * no shared memory. A mutex is 1 while it is locked, and a read-write lock counts its readers,
+ * or is -1 while it is written.
+ */
+void *_atomic_new(int value)
+{
+    int *atomic = _gc_alloc(sizeof(int));
+    *atomic = value;
+    return atomic;
+}
+
+int _atomic_load(void *atomic)
+{
+    return __atomic_load_n((int *)atomic, __ATOMIC_SEQ_CST);
+}
+
+void _atomic_store(void *atomic, int value)
+{
+    __atomic_store_n((int *)atomic, value, __ATOMIC_SEQ_CST);
+}
+
+/* Returns the value before the addition */
+int _atomic_add(void *atomic, int n)
+{
+    return __atomic_fetch_add((int *)atomic, n, __ATOMIC_SEQ_CST);
+}
+
+/* Replaces the value if it is the expected one, and returns whether it was replaced */
+int _atomic_compare_exchange(void *atomic, int expected, int desired)
+{
+    return __atomic_compare_exchange_n((int *)atomic, &expected, desired, 0, __ATOMIC_SEQ_CST,
+                                       __ATOMIC_SEQ_CST);
+}
+
+/* A lock can only be released by another task, so a task that waits for one runs the queued tasks */
+static void _lock_wait(char *lock)
+{
+    if (!_run_task())
+    {
+        fflush(stdout);
+        fprintf(stderr, "Deadlock: waiting for a %s that no task can release anymore\n", lock);
+        exit(1);
+    }
+}
+
+void _mutex_lock(void *mutex)
+{
+    while (!_atomic_compare_exchange(mutex, 0, 1))
+        _lock_wait("mutex");
+}
+
+int _mutex_try_lock(void *mutex)
+{
+    return _atomic_compare_exchange(mutex, 0, 1);
+}
+
+void _mutex_unlock(void *mutex)
+{
+    if (!_atomic_compare_exchange(mutex, 1, 0))
+    {
+        fprintf(stderr, "Cannot unlock a mutex that is not locked\n");
+        exit(1);
+    }
+}
+
+void _rwlock_read(void *lock)
+{
+    for (int readers = _atomic_load(lock);; readers = _atomic_load(lock))
+    {
+        if (readers < 0)
+            _lock_wait("lock");
+        else if (_atomic_compare_exchange(lock, readers, readers + 1))
+            return;
+    }
+}
+
+void _rwlock_write(void *lock)
+{
+    while (!_atomic_compare_exchange(lock, 0, -1))
+        _lock_wait("lock");
+}
+
+/* Releases the lock of the writer, or of one of the readers */
+void _rwlock_unlock(void *lock)
+{
+    for (int state = _atomic_load(lock);; state = _atomic_load(lock))
+    {
+        if (state == 0)
+        {
+            fprintf(stderr, "Cannot unlock a lock that is not locked\n");
+            exit(1);
+        }
+        if (_atomic_compare_exchange(lock, state, state < 0 ? 0 : state - 1))
+            return;
+    }
+}
+
+/*
+ * Maps are hash tables with open addressing and linear probing.
+ * Keys and values are stored in 8 byte slots. String keys are compared by their contents.
+ */
+enum slot_state
+{
+    SLOT_EMPTY,
+    SLOT_FULL,
+    SLOT_DELETED,
+};
+
+struct map
+{
+    long *keys;
+    long *values;
+    char *states;
+    /* Always a power of two */
+    long cap;
+    long len;
+    /* Full and deleted slots */
+    long used;
+    int string_keys;
+};
+
+static struct map *_map_alloc(long cap, int string_keys)
+{
+    struct map *m = _gc_alloc(sizeof(struct map));
+    m->keys = _gc_alloc(cap * sizeof(long));
+    m->values = _gc_alloc(cap * sizeof(long));
+    m->states = _gc_alloc(cap);
+    m->cap = cap;
+    m->len = 0;
+    m->used = 0;
+    m->string_keys = string_keys;
+    return m;
+}
+
+struct map *_map_new(int string_keys)
+{
+    return _map_alloc(8, string_keys);
+}
+
+static unsigned long _map_hash(struct map *m, long key)
+{
+    if (!m->string_keys)
+        return (unsigned long)key * 0x9E3779B97F4A7C15UL;
+
+    /* FNV-1a */
+    unsigned long hash = 0xcbf29ce484222325UL;
+    for (unsigned char *p = (unsigned char *)key; *p; p++)
+    {
+        hash ^= *p;
+        hash *= 0x100000001b3UL;
+    }
+    return hash;
+}
+
+static int _map_key_eq(struct map *m, long a, long b)
+{
+    return m->string_keys ? strcmp((char *)a, (char *)b) == 0 : a == b;
+}
+
+/* Returns the slot of a key, or -1 if the map does not contain it */
+static long _map_find(struct map *m, long key)
+{
+    long i = _map_hash(m, key) & (m->cap - 1);
+    while (m->states[i] != SLOT_EMPTY)
+    {
+        if (m->states[i] == SLOT_FULL && _map_key_eq(m, m->keys[i], key))
+            return i;
+        i = (i + 1) & (m->cap - 1);
+    }
+    return -1;
+}
+
+void _map_insert(struct map *m, long key, long value);
+
+static void _map_resize(struct map *m, long cap)
+{
+    long *keys = m->keys;
+    long *values = m->values;
+    char *states = m->states;
+    long old_cap = m->cap;
+
+    m->keys = _gc_alloc(cap * sizeof(long));
+    m->values = _gc_alloc(cap * sizeof(long));
+    m->states = _gc_alloc(cap);
+    m->cap = cap;
+    m->len = 0;
+    m->used = 0;
+    for (long i = 0; i < old_cap; i++)
+    {
+        if (states[i] == SLOT_FULL)
+            _map_insert(m, keys[i], values[i]);
+    }
+}
+
+void _map_insert(struct map *m, long key, long value)
+{
+    long found = _map_find(m, key);
+    if (found >= 0)
+    {
+        m->values[found] = value;
+        return;
+    }
+
+    /* Keep the load factor including deleted slots below 3/4 */
+    if ((m->used + 1) * 4 > m->cap * 3)
+        _map_resize(m, (m->len + 1) * 2 > m->cap ? m->cap * 2 : m->cap);
+
+    long i = _map_hash(m, key) & (m->cap - 1);
+    while (m->states[i] == SLOT_FULL)
+        i = (i + 1) & (m->cap - 1);
+    if (m->states[i] == SLOT_EMPTY)
+        m->used++;
+    m->states[i] = SLOT_FULL;
+    m->keys[i] = key;
+    m->values[i] = value;
+    m->len++;
+}
+
+long _map_get(struct map *m, long key)
+{
+    long i = _map_find(m, key);
+    if (i < 0)
+    {
+        fprintf(stderr, "Key not found in map\n");
+        exit(1);
+    }
+    return m->values[i];
+}
+
+void _map_remove(struct map *m, long key)
+{
+    long i = _map_find(m, key);
+    if (i >= 0)
+    {
+        m->states[i] = SLOT_DELETED;
+        m->len--;
+    }
+}
+
+int _map_contains(struct map *m, long key)
+{
+    return _map_find(m, key) >= 0;
+}
+
+struct vec *_map_keys(struct map *m)
+{
+    struct vec *keys = _vec_new(0);
+    for (long i = 0; i < m->cap; i++)
+    {
+        if (m->states[i] == SLOT_FULL)
+            _vec_push(keys, m->keys[i]);
+    }
+    return keys;
+}
+
+int _map_len(struct map *m)
+{
+    return m->len;
+}
+
+/*
+ * Regular expressions. A pattern is parsed into a tree, which is compiled into a graph
+ * of nodes and matched by backtracking. Like `RegExp` of JavaScript, the first
+ * alternative that matches wins and quantifiers are greedy unless followed by `?`,
+ * so both backends find the same matches. Characters are code points of UTF-8.
+ */
+enum _re_kind
+{
+    RE_CHAR,
+    RE_ANY,
+    RE_CLASS,
+    RE_START,
+    RE_END,
+    RE_GROUP,
+    RE_ALT,
+    RE_SEQ,
+    RE_REPEAT,
+    /* Kinds of the compiled graph only */
+    RE_SAVE,
+    RE_SPLIT,
+    RE_ENTER,
+    RE_STEP,
+    RE_BACK,
+    RE_MATCH,
+};
+
+/* A range of code points in a character class, or a class escape like `\d` if `escape` is set */
+struct _re_range
+{
+    int lo, hi;
+    char escape;
+};
+
+struct _re_node
+{
+    enum _re_kind kind;
+    /* The character of RE_CHAR, the group of RE_GROUP and RE_SAVE, the loop of repetitions */
+    int value;
+    /* RE_CLASS */
+    struct _re_range *ranges;
+    int ranges_len;
+    int negated;
+    /* Children of RE_GROUP, RE_ALT, RE_SEQ and RE_REPEAT */
+    struct _re_node **children;
+    int children_len;
+    /* RE_REPEAT and RE_STEP, max is -1 if unbounded */
+    int min, max, greedy;
+    /* The capture groups inside of a repetition, which are reset by every iteration */
+    int first_group, last_group;
+    /* The successor in the graph, the alternative of RE_SPLIT and the body of RE_STEP */
+    struct _re_node *next, *alt, *body;
+};
+
+struct _re_parser
+{
+    char *pattern;
+    char *p;
+    int groups;
+    int loops;
+    /* Every node is freed after matching */
+    struct _re_node **nodes;
+    int nodes_len, nodes_cap;
+};
+
+static void _re_error(struct _re_parser *re, char *message)
+{
+    fprintf(stderr, "Invalid regular expression '%s': %s\n", re->pattern, message);
+    exit(1);
+}
+
+static struct _re_node *_re_node(struct _re_parser *re, enum _re_kind kind)
+{
+    if (re->nodes_len == re->nodes_cap)
+    {
+        re->nodes_cap = re->nodes_cap ? re->nodes_cap * 2 : 16;
+        re->nodes = realloc(re->nodes, re->nodes_cap * sizeof(struct _re_node *));
+    }
+    struct _re_node *node = calloc(1, sizeof(struct _re_node));
+    node->kind = kind;
+    re->nodes[re->nodes_len++] = node;
+    return node;
+}
+
+static void _re_add_child(struct _re_node *node, struct _re_node *child)
+{
+    node->children = realloc(node->children, (node->children_len + 1) * sizeof(struct _re_node *));
+    node->children[node->children_len++] = child;
+}
+
+/* Decodes the code point at the start of a string and stores the number of its bytes */
+static int _re_decode(char *s, int *len)
+{
+    unsigned char c = s[0];
+    int code, n;
+    if (c < 0x80)
+        code = c, n = 1;
+    else if (c < 0xe0)
+        code = c & 0x1f, n = 2;
+    else if (c < 0xf0)
+        code = c & 0x0f, n = 3;
+    else
+        code = c & 0x07, n = 4;
+    for (int i = 1; i < n && s[i] != '\0'; i++)
+        code = (code << 6) | (s[i] & 0x3f);
+    *len = n;
+    return code;
+}
+
+static int _re_is_syntax(int c)
+{
+    return c != '\0' && strchr("^$\\.*+?()[]{}|/", c) != NULL;
+}
+
+/* Parses the character after a backslash. Returns the character, or 0 for class escapes like `\d` */
+static int _re_parse_escape(struct _re_parser *re, char *escape, int in_class)
+{
+    int c = *re->p;
+    *escape = 0;
+    if (c == '\0')
+        _re_error(re, "\\ at end of pattern");
+    re->p++;
+    switch (c)
+    {
+    case 'd':
+    case 'D':
+    case 'w':
+    case 'W':
+    case 's':
+    case 'S':
+        *escape = c;
+        return 0;
+    case 'n':
+        return '\n';
+    case 'r':
+        return '\r';
+    case 't':
+        return '\t';
+    case 'f':
+        return '\f';
+    case 'v':
+        return '\v';
+    }
+    if (_re_is_syntax(c) || (in_class && c == '-'))
+        return c;
+    _re_error(re, "Invalid escape");
+    return 0;
+}
+
+static int _re_is_space(int c)
+{
+    return (c >= '\t' && c <= '\r') || c == ' ' || c == 0xa0 || c == 0x1680 ||
+           (c >= 0x2000 && c <= 0x200a) || c == 0x2028 || c == 0x2029 || c == 0x202f ||
+           c == 0x205f || c == 0x3000 || c == 0xfeff;
+}
+
+static int _re_is_word(int c)
+{
+    return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '_';
+}
+
+static int _re_matches_escape(char escape, int c)
+{
+    switch (escape)
+    {
+    case 'd':
+        return c >= '0' && c <= '9';
+    case 'D':
+        return !(c >= '0' && c <= '9');
+    case 'w':
+        return _re_is_word(c);
+    case 'W':
+        return !_re_is_word(c);
+    case 's':
+        return _re_is_space(c);
+    default:
+        return !_re_is_space(c);
+    }
+}
+
+static void _re_add_range(struct _re_node *node, int lo, int hi, char escape)
+{
+    node->ranges = realloc(node->ranges, (node->ranges_len + 1) * sizeof(struct _re_range));
+    node->ranges[node->ranges_len++] = (struct _re_range){lo, hi, escape};
+}
+
+/* Parses a class item, which is a character or a class escape */
+static int _re_parse_class_item(struct _re_parser *re, char *escape)
+{
+    if (*re->p == '\\')
+    {
+        re->p++;
+        return _re_parse_escape(re, escape, 1);
+    }
+    int len;
+    int c = _re_decode(re->p, &len);
+    re->p += len;
+    *escape = 0;
+    return c;
+}
+
+static struct _re_node *_re_parse_class(struct _re_parser *re)
+{
+    struct _re_node *node = _re_node(re, RE_CLASS);
+    if (*re->p == '^')
+    {
+        node->negated = 1;
+        re->p++;
+    }
+    while (*re->p != ']')
+    {
+        if (*re->p == '\0')
+            _re_error(re, "Unterminated character class");
+        char escape;
+        int lo = _re_parse_class_item(re, &escape);
+        if (re->p[0] == '-' && re->p[1] != ']' && re->p[1] != '\0')
+        {
+            re->p++;
+            char hi_escape;
+            int hi = _re_parse_class_item(re, &hi_escape);
+            if (escape || hi_escape)
+                _re_error(re, "Invalid character class");
+            if (lo > hi)
+                _re_error(re, "Range out of order in character class");
+            _re_add_range(node, lo, hi, 0);
+        }
+        else
+        {
+            _re_add_range(node, lo, lo, escape);
+        }
+    }
+    re->p++;
+    return node;
+}
+
+static struct _re_node *_re_parse_alt(struct _re_parser *re);
+
+/* Parses a number of a quantifier like `{2,5}`, or returns -1 if there is none */
+static int _re_parse_number(struct _re_parser *re)
+{
+    if (!isdigit((unsigned char)*re->p))
+        return -1;
+    int n = 0;
+    while (isdigit((unsigned char)*re->p))
+    {
+        n = n * 10 + (*re->p - '0');
+        if (n > 100000)
+            _re_error(re, "Quantifier is too large");
+        re->p++;
+    }
+    return n;
+}
+
+/* Parses a quantifier after an atom. Returns 0 if there is none */
+static int _re_parse_quantifier(struct _re_parser *re, int *min, int *max)
+{
+    switch (*re->p)
+    {
+    case '*':
+        *min = 0, *max = -1;
+        break;
+    case '+':
+        *min = 1, *max = -1;
+        break;
+    case '?':
+        *min = 0, *max = 1;
+        break;
+    case '{':
+    {
+        re->p++;
+        *min = _re_parse_number(re);
+        if (*min < 0)
+            _re_error(re, "Lone quantifier brackets");
+        *max = *min;
+        if (*re->p == ',')
+        {
+            re->p++;
+            *max = _re_parse_number(re);
+        }
+        if (*re->p != '}')
+            _re_error(re, "Incomplete quantifier");
+        if (*max >= 0 && *max < *min)
+            _re_error(re, "numbers out of order in {} quantifier");
+        break;
+    }
+    default:
+        return 0;
+    }
+    re->p++;
+    return 1;
+}
+
+static struct _re_node *_re_parse_atom(struct _re_parser *re)
+{
+    int c = *re->p;
+    if (c == '*' || c == '+' || c == '?' || c == '{')
+        _re_error(re, "Nothing to repeat");
+    if (c == ']' || c == '}')
+        _re_error(re, "Lone quantifier brackets");
+    switch (c)
+    {
+    case '^':
+        re->p++;
+        return _re_node(re, RE_START);
+    case '$':
+        re->p++;
+        return _re_node(re, RE_END);
+    case '.':
+        re->p++;
+        return _re_node(re, RE_ANY);
+    case '[':
+        re->p++;
+        return _re_parse_class(re);
+    case '(':
+    {
+        re->p++;
+        struct _re_node *node = _re_node(re, RE_GROUP);
+        if (re->p[0] == '?')
+        {
+            if (re->p[1] != ':')
+                _re_error(re, "Invalid group");
+            re->p += 2;
+            node->value = -1;
+        }
+        else
+        {
+            node->value = ++re->groups;
+        }
+        _re_add_child(node, _re_parse_alt(re));
+        if (*re->p != ')')
+            _re_error(re, "Unterminated group");
+        re->p++;
+        return node;
+    }
+    case '\\':
+    {
+        re->p++;
+        char escape;
+        int code = _re_parse_escape(re, &escape, 0);
+        if (!escape)
+        {
+            struct _re_node *node = _re_node(re, RE_CHAR);
+            node->value = code;
+            return node;
+        }
+        struct _re_node *node = _re_node(re, RE_CLASS);
+        _re_add_range(node, 0, 0, escape);
+        return node;
+    }
+    }
+    int len;
+    struct _re_node *node = _re_node(re, RE_CHAR);
+    node->value = _re_decode(re->p, &len);
+    re->p += len;
+    return node;
+}
+
+static struct _re_node *_re_parse_seq(struct _re_parser *re)
+{
+    struct _re_node *seq = _re_node(re, RE_SEQ);
+    while (*re->p != '\0' && *re->p != '|' && *re->p != ')')
+    {
+        int first_group = re->groups + 1;
+        struct _re_node *atom = _re_parse_atom(re);
+        int min, max;
+        if (_re_parse_quantifier(re, &min, &max))
+        {
+            if (atom->kind == RE_START || atom->kind == RE_END)
+                _re_error(re, "Nothing to repeat");
+            struct _re_node *repeat = _re_node(re, RE_REPEAT);
+            repeat->min = min;
+            repeat->max = max;
+            repeat->greedy = 1;
+            if (*re->p == '?')
+            {
+                repeat->greedy = 0;
+                re->p++;
+            }
+            repeat->first_group = first_group;
+            repeat->last_group = re->groups;
+            repeat->value = re->loops++;
+            _re_add_child(repeat, atom);
+            atom = repeat;
+        }
+        _re_add_child(seq, atom);
+    }
+    return seq;
+}
+
+static struct _re_node *_re_parse_alt(struct _re_parser *re)
+{
+    struct _re_node *alt = _re_node(re, RE_ALT);
+    _re_add_child(alt, _re_parse_seq(re));
+    while (*re->p == '|')
+    {
+        re->p++;
+        _re_add_child(alt, _re_parse_seq(re));
+    }
+    return alt;
+}
+
+/* Compiles a tree into the graph that continues with `next` once the tree has matched */
+static struct _re_node *_re_compile(struct _re_parser *re, struct _re_node *tree, struct _re_node *next)
+{
+    switch (tree->kind)
+    {
+    case RE_SEQ:
+        for (int i = tree->children_len - 1; i >= 0; i--)
+            next = _re_compile(re, tree->children[i], next);
+        return next;
+    case RE_ALT:
+    {
+        struct _re_node *alternatives = _re_compile(re, tree->children[tree->children_len - 1], next);
+        for (int i = tree->children_len - 2; i >= 0; i--)
+        {
+            struct _re_node *split = _re_node(re, RE_SPLIT);
+            split->next = _re_compile(re, tree->children[i], next);
+            split->alt = alternatives;
+            alternatives = split;
+        }
+        return alternatives;
+    }
+    case RE_GROUP:
+    {
+        if (tree->value < 0)
+            return _re_compile(re, tree->children[0], next);
+        struct _re_node *end = _re_node(re, RE_SAVE);
+        end->value = tree->value * 2 + 1;
+        end->next = next;
+        struct _re_node *start = _re_node(re, RE_SAVE);
+        start->value = tree->value * 2;
+        start->next = _re_compile(re, tree->children[0], end);
+        return start;
+    }
+    case RE_REPEAT:
+    {
+        struct _re_node *step = _re_node(re, RE_STEP);
+        *step = *tree;
+        step->kind = RE_STEP;
+        step->children = NULL;
+        step->next = next;
+        struct _re_node *back = _re_node(re, RE_BACK);
+        back->value = tree->value;
+        back->min = tree->min;
+        back->next = step;
+        step->body = _re_compile(re, tree->children[0], back);
+        struct _re_node *enter = _re_node(re, RE_ENTER);
+        enter->value = tree->value;
+        enter->next = step;
+        return enter;
+    }
+    default:
+        tree->next = next;
+        return tree;
+    }
+}
+
+struct _re_state
+{
+    char *text;
+    int len;
+    /* Start and end offsets of each group, starting with the whole match */
+    int *saves;
+    /* Iterations of each active repetition, and the offset where the current one started */
+    int *counts;
+    int *starts;
+};
+
+static int _re_match(struct _re_state *st, struct _re_node *node, int pos);
+
+/* Matches another iteration of a repetition, resetting the groups inside of it */
+static int _re_iterate(struct _re_state *st, struct _re_node *step, int pos)
+{
+    int loop = step->value;
+    int count = st->counts[loop];
+    int start = st->starts[loop];
+    int first = step->first_group * 2;
+    int groups = (step->last_group - step->first_group + 1) * 2;
+    int *saved = groups > 0 ? malloc(groups * sizeof(int)) : NULL;
+    for (int i = 0; i < groups; i++)
+    {
+        saved[i] = st->saves[first + i];
+        st->saves[first + i] = -1;
+    }
+    st->counts[loop] = count + 1;
+    st->starts[loop] = pos;
+    int matched = _re_match(st, step->body, pos);
+    if (!matched)
+    {
+        st->counts[loop] = count;
+        st->starts[loop] = start;
+        for (int i = 0; i < groups; i++)
+            st->saves[first + i] = saved[i];
+    }
+    free(saved);
+    return matched;
+}
+
+static int _re_match(struct _re_state *st, struct _re_node *node, int pos)
+{
+    int len = 0;
+    int c = pos < st->len ? _re_decode(st->text + pos, &len) : -1;
+    switch (node->kind)
+    {
+    case RE_MATCH:
+        st->saves[1] = pos;
+        return 1;
+    case RE_CHAR:
+        return c == node->value && _re_match(st, node->next, pos + len);
+    case RE_ANY:
+        return c >= 0 && c != '\n' && c != '\r' && c != 0x2028 && c != 0x2029 &&
+               _re_match(st, node->next, pos + len);
+    case RE_CLASS:
+    {
+        if (c < 0)
+            return 0;
+        int found = 0;
+        for (int i = 0; i < node->ranges_len && !found; i++)
+        {
+            struct _re_range *r = &node->ranges[i];
+            found = r->escape ? _re_matches_escape(r->escape, c) : c >= r->lo && c <= r->hi;
+        }
+        return found != node->negated && _re_match(st, node->next, pos + len);
+    }
+    case RE_START:
+        return pos == 0 && _re_match(st, node->next, pos);
+    case RE_END:
+        return pos == st->len && _re_match(st, node->next, pos);
+    case RE_SAVE:
+    {
+        int old = st->saves[node->value];
+        st->saves[node->value] = pos;
+        if (_re_match(st, node->next, pos))
+            return 1;
+        st->saves[node->value] = old;
+        return 0;
+    }
+    case RE_SPLIT:
+        return _re_match(st, node->next, pos) || _re_match(st, node->alt, pos);
+    case RE_ENTER:
+    {
+        int count = st->counts[node->value];
+        int start = st->starts[node->value];
+        st->counts[node->value] = 0;
+        st->starts[node->value] = -1;
+        if (_re_match(st, node->next, pos))
+            return 1;
+        st->counts[node->value] = count;
+        st->starts[node->value] = start;
+        return 0;
+    }
+    case RE_BACK:
+        /* Like JavaScript, an iteration beyond the minimum must not match the empty string */
+        if (pos == st->starts[node->value] && st->counts[node->value] > node->min)
+            return 0;
+        return _re_match(st, node->next, pos);
+    case RE_STEP:
+    {
+        int count = st->counts[node->value];
+        if (count < node->min)
+            return _re_iterate(st, node, pos);
+        if (count == node->max)
+            return _re_match(st, node->next, pos);
+        if (node->greedy)
+            return _re_iterate(st, node, pos) || _re_match(st, node->next, pos);
+        return _re_match(st, node->next, pos) || _re_iterate(st, node, pos);
+    }
+    default:
+        return 0;
+    }
+}
+
+/*
+ * Finds the first match of a pattern that starts at or after a byte offset. Returns the start
+ * and end offsets of the match and of each capture group, which are -1 if the group did not
+ * participate in the match, or an empty array if there is no match.
+ */
+struct vec *_regex_find(char *pattern, char *text, int start)
+{
+    struct _re_parser re = {pattern, pattern, 0, 0, NULL, 0, 0};
+    struct _re_node *tree = _re_parse_alt(&re);
+    if (*re.p == ')')
+        _re_error(&re, "Unmatched ')'");
+
+    struct _re_node *root = _re_compile(&re, tree, _re_node(&re, RE_MATCH));
+
+    int saves_len = (re.groups + 1) * 2;
+    struct _re_state st = {text, strlen(text), malloc(saves_len * sizeof(int)),
+                           calloc(re.loops + 1, sizeof(int)), calloc(re.loops + 1, sizeof(int))};
+    struct vec *result = _vec_new(0);
+    for (int pos = start; pos >= 0 && pos <= st.len; pos++)
+    {
+        if (!_str_is_boundary(text, pos))
+            continue;
+        for (int i = 0; i < saves_len; i++)
+            st.saves[i] = -1;
+        st.saves[0] = pos;
+        if (_re_match(&st, root, pos))
+        {
+            for (int i = 0; i < saves_len; i += 2)
+            {
+                int unset = st.saves[i] < 0 || st.saves[i + 1] < 0;
+                _vec_push(result, unset ? -1 : st.saves[i]);
+                _vec_push(result, unset ? -1 : st.saves[i + 1]);
+            }
+            break;
+        }
+    }
+
+    for (int i = 0; i < re.nodes_len; i++)
+    {
+        free(re.nodes[i]->ranges);
+        free(re.nodes[i]->children);
+        free(re.nodes[i]);
+    }
+    free(re.nodes);
+    free(st.saves);
+    free(st.counts);
+    free(st.starts);
+    return result;
+}
+
+/* END runtime */
@@ -0,0 +1,26 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+int ackermann(int m, int n);
+void main() {
+int m = 3;
+int n = 3;
+println(ackermann(m,n));
+
+}
+int ackermann(int m, int n) {
+if (m == 0){
+return n + 1;
+}else if (n == 0){
+return ackermann(m - 1,1);
+}else {
+return ackermann(m - 1,ackermann(m,n - 1));
+}
+
+
+
+}
@@ -0,0 +1,41 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+/* END builtins */
+
+function main(){
+var m = 3;
+var n = 3;
+println(ackermann(m,n));
+}
+
+function ackermann(m, n){
+if (m === 0){
+return n + 1;
+}else if (n === 0){
+return ackermann(m - 1,1);
+}else {
+return ackermann(m - 1,ackermann(m,n - 1));
+}
+;
+;
+;
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
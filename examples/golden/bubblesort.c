@@ -0,0 +1,32 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+void main() {
+int arr[5] arr = [2, 5, 3, 1, 4];
+int n = _vec_len(arr);
+int c = 0;
+while (c < n) {
+int d = 0;
+while (d < ((n - c) - 1)) {
+int current = arr[d];
+int next = arr[d + 1];
+if (current > next){
+int swap = arr[d];
+arr[d] = arr[d + 1];
+arr[d + 1] = swap;
+}
+d += 1;
+
+}
+
+c += 1;
+
+}
+
+println(arr);
+
+}
@@ -0,0 +1,72 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+function _exit(code) {
+  process.exit(code);
+}
+
+function _eprint(msg) {
+  process.stderr.write(msg);
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+function _panic(message, location) {
+  _eprint("Panicked at " + location + ": " + message + "\n");
+  _exit(1);
+}
+
+function _check_index(arr, index, location) {
+  if (index < 0 || index >= arr.length) {
+    _panic(
+      "index out of bounds: the length is " + arr.length + " but the index is " + index,
+      location
+    );
+  }
+  return index;
+}
+
+function _vec_len(arr) {
+  return arr.length;
+}
+
+/* END builtins */
+
+function main(){
+var arr = [2, 5, 3, 1, 4];
+var n = _vec_len(arr);
+var c = 0;
+while (c < n) {
+var d = 0;
+while (d < ((n - c) - 1)) {
+var current = arr[_check_index(arr,d,"function 'main'")];
+var next = arr[_check_index(arr,d + 1,"function 'main'")];
+if (current > next){
+var swap = arr[_check_index(arr,d,"function 'main'")];
+arr[_check_index(arr,d,"function 'main'")] = arr[_check_index(arr,d + 1,"function 'main'")];
+arr[_check_index(arr,d + 1,"function 'main'")] = swap;
+};
+d += 1;
+}
+;
+c += 1;
+}
+;
+println(arr);
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
@@ -0,0 +1,19 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+int fib(int n);
+void main() {
+int num = 10;
+println(fib(num));
+
+}
+int fib(int n) {
+if (1 >= n){
+return n;
+}
+return fib(n - 1) + fib(n - 2);
+}
@@ -0,0 +1,34 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+/* END builtins */
+
+function main(){
+var num = 10;
+println(fib(num));
+}
+
+function fib(n){
+if (1 >= n){
+return n;
+};
+return fib(n - 1) + fib(n - 2);
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
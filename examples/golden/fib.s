@@ -0,0 +1,15 @@
+.intel_syntax noprefix
+.text
+.globl _main
+_main:
+push rbp
+mov rbp, rsp
+mov	rsp, rbp
+pop rbp
+ret
+
+.globl _fib
+_fib:
+push rbp
+mov rbp, rsp
+.data
\ No newline at end of file
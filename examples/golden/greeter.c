@@ -0,0 +1,15 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+char * greet(char * name);
+void main() {
+println(greet("World"));
+
+}
+char * greet(char * name) {
+return "Hello " + name;
+}
@@ -0,0 +1,11 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+void main() {
+println("Hello World");
+
+}
@@ -0,0 +1,26 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+/* END builtins */
+
+function main(){
+println("Hello World");
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
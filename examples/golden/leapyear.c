@@ -0,0 +1,23 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+void main() {
+int year = 2020;
+bool divisibleBy4 = (year % 4) == 0;
+bool divisibleBy100 = (year % 100) != 0;
+bool divisibleBy400 = (year % 400) == 0;
+bool ly = divisibleBy4 && divisibleBy100;
+if (ly || divisibleBy400){
+println("Leap year");
+
+}else {
+println("Not a leap year");
+
+}
+
+
+}
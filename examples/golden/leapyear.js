@@ -0,0 +1,37 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+/* END builtins */
+
+function main(){
+var year = 2020;
+var divisibleBy4 = (year % 4) === 0;
+var divisibleBy100 = (year % 100) !== 0;
+var divisibleBy400 = (year % 400) === 0;
+var ly = divisibleBy4 && divisibleBy100;
+if (ly || divisibleBy400){
+println("Leap year");
+}else {
+println("Not a leap year");
+}
+;
+;
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
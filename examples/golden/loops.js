@@ -0,0 +1,38 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+/* END builtins */
+
+function main(){
+var arr = ["One", "Two", "Three"];
+var loop_orig_x = arr;
+for (let iter_x = 0; iter_x < loop_orig_x.length; iter_x++){
+let x = loop_orig_x[iter_x];
+println(x);
+}
+;
+var loop_orig_fruit = ["Apple", "Strawberry", "Orange"];
+for (let iter_fruit = 0; iter_fruit < loop_orig_fruit.length; iter_fruit++){
+let fruit = loop_orig_fruit[iter_fruit];
+println(fruit);
+}
+;
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
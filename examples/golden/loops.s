@@ -0,0 +1,11 @@
+.intel_syntax noprefix
+.text
+.globl _main
+_main:
+push rbp
+mov rbp, rsp
+mov	rsp, rbp
+pop rbp
+ret
+
+.data
\ No newline at end of file
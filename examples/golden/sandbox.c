@@ -0,0 +1,18 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+void main();
+void main() {
+int number = 3;
+while (number != 0) {
+println(number);
+
+number = number - 1;
+}
+
+println("LIFTOFF!!!");
+
+}
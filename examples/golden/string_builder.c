@@ -0,0 +1,39 @@
+/* START builtins */
+#include "stdio.h"
+#include <stdbool.h>
+
+/* END builtins */
+/* runtime.c */
+char * concatenate(int pieces);
+char * build(int pieces);
+void main();
+char * concatenate(int pieces) {
+char * s = "";
+int i = 0;
+while (i < pieces) {
+s += "piece ";
+
+i += 1;
+
+}
+
+return s;
+}
+char * build(int pieces) {
+void builder = string_builder();
+int i = 0;
+while (i < pieces) {
+builder_append(builder,"piece ");
+
+i += 1;
+
+}
+
+return builder_to_string(builder);
+}
+void main() {
+println(build(3));
+
+println(build(100) == concatenate(100));
+
+}
@@ -0,0 +1,90 @@
+/* START node runtime */
+
+function _printf(msg) {
+  // Message is casted to string to prevent crash
+  process.stdout.write(msg.toString());
+}
+
+/* END node runtime */
+
+/* START builtins */
+
+function _str_join(parts, sep) {
+  return parts.join(sep);
+}
+
+function _vec_push(arr, value) {
+  arr.push(value);
+}
+
+// Copies a struct that is passed to a function by value. Structs in its fields are
+// copied as well, since native code embeds them into the struct.
+function _copy_struct(value) {
+  const copy = Object.create(Object.getPrototypeOf(value));
+  for (const [key, field] of Object.entries(value)) {
+    copy[key] = _is_struct(field) ? _copy_struct(field) : field;
+  }
+  return copy;
+}
+
+function _is_struct(value) {
+  const plain = [Object.prototype, Array.prototype, Map.prototype];
+  return value instanceof Object && !plain.includes(Object.getPrototypeOf(value));
+}
+
+/* END builtins */
+
+function StringBuilder(args) {
+this.parts = args.parts;
+}
+function concatenate(pieces){
+var s = "";
+var i = 0;
+while (i < pieces) {
+s += "piece ";
+i += 1;
+}
+;
+return s;
+}
+
+function build(pieces){
+var builder = string_builder();
+var i = 0;
+while (i < pieces) {
+builder_append(builder,"piece ");
+i += 1;
+}
+;
+return builder_to_string(builder);
+}
+
+function main(){
+println(build(3));
+println(build(100) === concatenate(100));
+}
+
+function string_builder(){
+return new StringBuilder({parts: [],});
+}
+
+function builder_append(builder, text){
+builder = _copy_struct(builder);
+var parts = builder.parts;
+_vec_push(parts,text);
+}
+
+function builder_to_string(builder){
+builder = _copy_struct(builder);
+return _str_join(builder.parts,"");
+}
+
+function print(arg){
+_printf(arg);
+}
+
+function println(msg){
+print(msg + "\n");
+}
+
+main();
\ No newline at end of file
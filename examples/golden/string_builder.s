@@ -0,0 +1,19 @@
+.intel_syntax noprefix
+.text
+.globl _concatenate
+_concatenate:
+push rbp
+mov rbp, rsp
+.globl _build
+_build:
+push rbp
+mov rbp, rsp
+.globl _main
+_main:
+push rbp
+mov rbp, rsp
+mov	rsp, rbp
+pop rbp
+ret
+
+.data
\ No newline at end of file
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    if let Ok(tokens) = antimony::lexer::tokenize(input) {
+        let _ = antimony::parser::parse(tokens, Some(input.to_owned()), "fuzz.sb".into());
+    }
+});
@@ -0,0 +1,221 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::{Attribute, Module};
+
+/// Functions that are inlined regardless of their size
+pub const INLINE: &str = "inline";
+/// Functions that are never inlined
+pub const NOINLINE: &str = "noinline";
+/// Test cases that are run by `sb test`
+pub const TEST: &str = "test";
+/// Benchmarks that are run by `sb bench`
+pub const BENCH: &str = "bench";
+/// Functions whose array accesses are not checked
+pub const UNSAFE_INDEX: &str = "unsafe_index";
+/// Functions that are only compiled for a target, e.g. `@cfg(target = "js")`
+pub const CFG: &str = "cfg";
+/// Functions that are part of the interface of the program, and are kept even if they are never called
+pub const EXPORT: &str = "export";
+/// Declarations whose use is reported with a warning, e.g. `@deprecated("use y")`
+pub const DEPRECATED: &str = "deprecated";
+/// Functions whose symbol is not qualified with the name of their module
+pub const NO_MANGLE: &str = "no_mangle";
+/// Functions whose calls with constant arguments are evaluated by the compiler
+pub const COMPTIME: &str = "comptime";
+/// Structs whose conversion functions are generated by the compiler, e.g. `@derive(json)`
+pub const DERIVE: &str = "derive";
+
+/// Arguments of `@derive`, which name the functions that are generated for a struct
+pub const DERIVE_JSON: &str = "json";
+pub const DERIVE_EQ: &str = "eq";
+pub const DERIVE_SHOW: &str = "show";
+pub const DERIVABLE: [&str; 3] = [DERIVE_JSON, DERIVE_EQ, DERIVE_SHOW];
+
+/// Kinds of declarations that can be annotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    Function,
+    Method,
+    Struct,
+}
+
+impl Item {
+    fn name(&self) -> &'static str {
+        match self {
+            Item::Function => "function",
+            Item::Method => "method",
+            Item::Struct => "struct",
+        }
+    }
+}
+
+/// Describes where an attribute can be used, and which arguments it takes
+pub struct AttributeSpec {
+    pub name: &'static str,
+    pub items: &'static [Item],
+    /// Keys of the arguments that can be passed
+    pub keys: &'static [&'static str],
+    /// Number of arguments without a key the attribute takes at most
+    pub positional: usize,
+}
+
+/// Registry of all attributes known to the compiler
+pub const ATTRIBUTES: [AttributeSpec; 11] = [
+    AttributeSpec {
+        name: INLINE,
+        items: &[Item::Function, Item::Method],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: NOINLINE,
+        items: &[Item::Function, Item::Method],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: TEST,
+        items: &[Item::Function],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: BENCH,
+        items: &[Item::Function],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: UNSAFE_INDEX,
+        items: &[Item::Function, Item::Method],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: CFG,
+        items: &[Item::Function, Item::Method],
+        keys: &["target"],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: EXPORT,
+        items: &[Item::Function],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: DEPRECATED,
+        items: &[Item::Function, Item::Struct],
+        keys: &[],
+        positional: 1,
+    },
+    AttributeSpec {
+        name: NO_MANGLE,
+        items: &[Item::Function],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: COMPTIME,
+        items: &[Item::Function],
+        keys: &[],
+        positional: 0,
+    },
+    AttributeSpec {
+        name: DERIVE,
+        items: &[Item::Struct],
+        keys: &[],
+        positional: DERIVABLE.len(),
+    },
+];
+
+/// Returns the specification of a known attribute
+pub fn lookup(name: &str) -> Option<&'static AttributeSpec> {
+    ATTRIBUTES.iter().find(|spec| spec.name == name)
+}
+
+/// Checks that every attribute of the module is known,
+/// is applied to a matching declaration and gets valid arguments
+pub fn check(module: &Module) -> Result<(), String> {
+    for func in &module.func {
+        check_item(&func.attributes, Item::Function, &func.name)?;
+    }
+    for def in &module.structs {
+        check_item(&def.attributes, Item::Struct, &def.name)?;
+        for method in &def.methods {
+            let name = format!("{}.{}", def.name, method.name);
+            check_item(&method.attributes, Item::Method, &name)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_item(attributes: &[Attribute], item: Item, name: &str) -> Result<(), String> {
+    for attribute in attributes {
+        let subject = format!("@{} on {} '{}'", attribute.name, item.name(), name);
+        let spec =
+            lookup(&attribute.name).ok_or_else(|| format!("Unknown attribute {}", subject))?;
+        if !spec.items.contains(&item) {
+            return Err(format!(
+                "Attribute {} is not allowed, it can only be applied to: {}",
+                subject,
+                spec.items
+                    .iter()
+                    .map(|item| item.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        let mut positional = 0;
+        for (key, _) in &attribute.arguments {
+            match key {
+                None if spec.positional == 0 => {
+                    return Err(format!(
+                        "Attribute {} takes no positional argument",
+                        subject
+                    ))
+                }
+                None if positional == 1 && spec.positional == 1 => {
+                    return Err(format!(
+                        "Attribute {} takes only one positional argument",
+                        subject
+                    ))
+                }
+                None if positional == spec.positional => {
+                    return Err(format!(
+                        "Attribute {} takes at most {} positional arguments",
+                        subject, spec.positional
+                    ))
+                }
+                None => positional += 1,
+                Some(key) if spec.keys.contains(&key.as_str()) => {}
+                Some(key) if spec.keys.is_empty() => {
+                    return Err(format!("Attribute {} takes no argument '{}'", subject, key))
+                }
+                Some(key) => {
+                    return Err(format!(
+                        "Unknown argument '{}' of attribute {}, expected '{}'",
+                        key,
+                        subject,
+                        spec.keys.join("', '")
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
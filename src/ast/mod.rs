@@ -1,6 +1,7 @@
 use crate::lexer::*;
 use core::convert::TryFrom;
 use std::collections::HashMap;
+use std::fmt;
 /**
  * Copyright 2021 Garrit Franke
  *
@@ -16,26 +17,34 @@ use std::collections::HashMap;
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use std::collections::HashSet;
-
+pub mod attributes;
 pub mod types;
+use serde::{Deserialize, Serialize};
 use types::Type;
 
 /// Table that contains all symbol and its types
 pub type SymbolTable = HashMap<String, Option<Type>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Module {
     pub path: String,
-    pub imports: HashSet<String>,
+    /// Imported paths -> position of the import statement
+    pub imports: HashMap<String, Position>,
+    /// Aliases of imports, e.g. `m` of `import "lib/math" as m` -> imported path
+    pub aliases: HashMap<String, String>,
+    /// Names of selective imports, e.g. `foo` of `import { foo } from "module"`
+    /// -> imported path and position of the name
+    pub selected: HashMap<String, (String, Position)>,
     pub func: Vec<Function>,
+    pub externs: Vec<ExternFunction>,
     pub structs: Vec<StructDef>,
-    pub globals: Vec<String>,
+    pub globals: Vec<Global>,
 }
 
 impl Module {
     pub fn merge_with(&mut self, mut other: Module) {
         self.func.append(&mut other.func);
+        self.externs.append(&mut other.externs);
         self.structs.append(&mut other.structs);
         self.globals.append(&mut other.globals)
     }
@@ -46,55 +55,264 @@ impl Module {
         for func in self.func.clone() {
             table.insert(func.name, func.ret_type);
         }
+        for func in &self.externs {
+            table.insert(func.name.clone(), func.ret_type.clone());
+        }
 
         table
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     pub name: String,
     pub arguments: Vec<Variable>,
     pub body: Statement,
     pub ret_type: Option<Type>,
+    pub attributes: Vec<Attribute>,
+    /// Public functions can be used by other modules, and are exported from the program
+    pub public: bool,
+    /// Async functions can await calls of other async functions, see `Expression::Await`
+    pub is_async: bool,
+}
+
+impl Function {
+    /// Returns true if the function is annotated with the given attribute
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attribute(name).is_some()
+    }
+
+    /// Returns the first attribute of the function with the given name
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attr| attr.name == name)
+    }
+}
+
+/// Declaration of a function that is implemented outside of Antimony,
+/// e.g. `extern "C" fn puts(s: string): int`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternFunction {
+    pub name: String,
+    /// Calling convention of the function, "C" or "js" for functions of JavaScript modules
+    pub abi: String,
+    pub arguments: Vec<Variable>,
+    pub ret_type: Option<Type>,
+    /// JavaScript module the function is imported from, e.g. `fs`
+    pub module: Option<String>,
+    /// Whether the JavaScript function returns a promise, which has to be awaited
+    pub is_async: bool,
+}
+
+/// Annotation of a declaration, e.g. `@inline`, `@deprecated("use y")` or `@cfg(target = "js")`
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: String,
+    /// (key, value) pairs of the arguments. Positional arguments have no key
+    pub arguments: Vec<(Option<String>, String)>,
 }
 
-#[derive(Debug, Clone)]
+impl Attribute {
+    /// Returns the value of the first positional argument
+    pub fn positional(&self) -> Option<&str> {
+        self.positionals().next()
+    }
+
+    /// Returns the values of all positional arguments
+    pub fn positionals(&self) -> impl Iterator<Item = &str> {
+        self.arguments
+            .iter()
+            .filter(|(key, _)| key.is_none())
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StructDef {
     pub name: String,
     pub fields: Vec<Variable>,
+    /// Values of fields that can be omitted when the structure is initialized
+    pub defaults: HashMap<String, Expression>,
     pub methods: Vec<Function>,
+    pub attributes: Vec<Attribute>,
+    /// Public structs can be used by other modules
+    pub public: bool,
+}
+
+impl StructDef {
+    /// Returns true if the struct is annotated with the given attribute
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attribute(name).is_some()
+    }
+
+    /// Returns the first attribute of the struct with the given name
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attr| attr.name == name)
+    }
+
+    /// Returns true if the struct derives the functions of the given name, e.g. `@derive(eq)`
+    pub fn derives(&self, name: &str) -> bool {
+        self.attributes
+            .iter()
+            .filter(|attr| attr.name == attributes::DERIVE)
+            .flat_map(Attribute::positionals)
+            .any(|derive| derive == name)
+    }
+
+    /// Returns true if the function belongs to the iteration protocol of the struct, e.g. `Stack_len`
+    pub fn is_protocol_function(&self, func: &Function) -> bool {
+        [PROTOCOL_LEN, PROTOCOL_GET]
+            .iter()
+            .any(|suffix| func.name == format!("{}_{}", self.name, suffix))
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+/// Suffix of the function that returns the number of elements of an iterable struct, e.g. `Stack_len`
+pub const PROTOCOL_LEN: &str = "len";
+/// Suffix of the function that returns the element of an iterable struct at an index, e.g. `Stack_get`
+pub const PROTOCOL_GET: &str = "get";
+
+/// Module-level variable, e.g. `let count = 0` or `const LIMIT = 10`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Global {
+    pub var: Variable,
+    pub value: Expression,
+    /// Constants can not be assigned to after their initialization
+    pub constant: bool,
+    /// Public globals can be used by other modules
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Variable {
     pub name: String,
     pub ty: Option<Type>,
+    /// Position of the declaration, if the variable originates from source code
+    pub pos: Option<Position>,
+    /// Arguments declared with `ref` share a structure with the caller, instead of receiving a copy
+    pub reference: bool,
+}
+
+// The position is not part of the identity of a variable
+/// Returns the words of inline code that may refer to variables or functions of the program
+pub fn inline_identifiers(code: &str) -> impl Iterator<Item = &str> {
+    code.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+}
+
+impl PartialEq for Variable {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.ty == other.ty
+    }
 }
 
+impl Eq for Variable {}
+
 impl AsRef<Variable> for Variable {
     fn as_ref(&self) -> &Self {
         self
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Statement {
     /// (Statements, Scoped variables)
     Block(Vec<Statement>, Vec<Variable>),
     Declare(Variable, Option<Expression>),
+    /// `static count = 0` declares a variable that keeps its value between calls of the function
+    Static(Variable, Expression),
+    /// `let { x, y } = point` binds fields of a struct to variables: ((field, variable), value)
+    Destructure(Vec<(String, Variable)>, Expression),
     Assign(Box<Expression>, Box<Expression>),
     Return(Option<Expression>),
     If(Expression, Box<Statement>, Option<Box<Statement>>),
     While(Expression, Box<Statement>),
     For(Variable, Expression, Box<Statement>),
+    /// `for (k, v) in pairs` iterates over the keys and values of a map,
+    /// or the indices and elements of an array: (key, value, iterable, body)
+    ForPair(Variable, Variable, Expression, Box<Statement>),
     Match(Expression, Vec<MatchArm>),
     Break,
     Continue,
     Exp(Expression),
+    /// Expression that is evaluated when the enclosing block is left
+    Defer(Expression),
+    /// `spawn f(x)` runs a call of a function as a task, once the current task waits for a channel
+    /// or the program's `main` function has returned. The arguments are evaluated right away.
+    Spawn(Expression),
+    /// Code of a backend that is passed through verbatim: (target, code), e.g. `js! { ... }`
+    Inline(String, String),
+    /// `unsafe { ... }`, a block in which raw pointers can be used
+    Unsafe(Box<Statement>),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+impl Statement {
+    /// Returns true if the statement contains a `return` of a call to the given
+    /// function with a matching number of arguments
+    pub fn has_tail_call(&self, name: &str, arity: usize) -> bool {
+        match self {
+            Statement::Block(statements, _) => statements
+                .iter()
+                .any(|stmt| stmt.has_tail_call(name, arity)),
+            Statement::Return(Some(Expression::FunctionCall(callee, args))) => {
+                callee == name && args.len() == arity
+            }
+            Statement::If(_, if_clause, else_clause) => {
+                if_clause.has_tail_call(name, arity)
+                    || else_clause
+                        .as_ref()
+                        .is_some_and(|stmt| stmt.has_tail_call(name, arity))
+            }
+            Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+                body.has_tail_call(name, arity)
+            }
+            Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+                MatchArm::Case(_, stmt) | MatchArm::Else(stmt) => stmt.has_tail_call(name, arity),
+            }),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the statement contains a `break` that leaves the innermost loop
+    /// around it
+    pub fn breaks(&self) -> bool {
+        match self {
+            Statement::Break => true,
+            Statement::Block(statements, _) => statements.iter().any(Statement::breaks),
+            Statement::If(_, if_branch, else_branch) => {
+                if_branch.breaks() || else_branch.as_deref().is_some_and(Statement::breaks)
+            }
+            Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+                MatchArm::Case(_, body) | MatchArm::Else(body) => body.breaks(),
+            }),
+            Statement::Unsafe(body) => body.breaks(),
+            // A `break` in a nested loop leaves the nested loop
+            _ => false,
+        }
+    }
+
+    /// Returns true if the statement contains an `unsafe` block
+    pub fn has_unsafe_block(&self) -> bool {
+        match self {
+            Statement::Unsafe(_) => true,
+            Statement::Block(statements, _) => statements.iter().any(Statement::has_unsafe_block),
+            Statement::If(_, if_branch, else_branch) => {
+                if_branch.has_unsafe_block()
+                    || else_branch
+                        .as_deref()
+                        .is_some_and(Statement::has_unsafe_block)
+            }
+            Statement::While(_, body)
+            | Statement::For(_, _, body)
+            | Statement::ForPair(_, _, _, body) => body.has_unsafe_block(),
+            Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+                MatchArm::Case(_, body) | MatchArm::Else(body) => body.has_unsafe_block(),
+            }),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Expression {
     Int(usize),
     Str(String),
@@ -108,7 +326,26 @@ pub enum Expression {
     ArrayAccess(String, Box<Expression>),
     BinOp(Box<Expression>, BinOp, Box<Expression>),
     StructInitialization(String, HashMap<String, Box<Expression>>),
+    /// (name, base, fields) of an initialization that copies the omitted fields from `base`
+    StructUpdate(String, Box<Expression>, HashMap<String, Box<Expression>>),
     FieldAccess(Box<Expression>, Box<Expression>),
+    /// (key, value) pairs of a map literal
+    Map(Vec<(Expression, Expression)>),
+    /// `expr?`, returns the error of a result from the current function
+    Try(Box<Expression>),
+    /// `await f()`, a call of an async function. The JavaScript backend waits for the
+    /// returned promise, the other backends call async functions like any other function.
+    Await(Box<Expression>),
+    /// `size_of<T>()`, the size of a value of the type in bytes
+    SizeOf(Type),
+    /// `&x`, a reference to a variable, field or element of an array
+    Reference(Box<Expression>),
+    /// `*r`, the value a reference refers to
+    Dereference(Box<Expression>),
+    /// `load<T>(address)` reads a value of the type from a raw pointer
+    Load(Type, Box<Expression>),
+    /// `store<T>(address, value)` writes a value of the type to a raw pointer: (type, address, value)
+    Store(Type, Box<Expression>, Box<Expression>),
 }
 
 impl TryFrom<Token> for Expression {
@@ -135,13 +372,27 @@ impl TryFrom<Token> for Expression {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum MatchArm {
     Case(Expression, Statement),
     Else(Statement),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+impl MatchArm {
+    /// Returns true if one of the arms of a `match` statement is taken for every value of
+    /// the subject, which is the case if there is an `else` arm, or if both `true` and
+    /// `false` are matched
+    pub fn is_exhaustive(arms: &[MatchArm]) -> bool {
+        let has_case = |value: bool| {
+            arms.iter()
+                .any(|arm| matches!(arm, MatchArm::Case(Expression::Bool(b), _) if *b == value))
+        };
+        arms.iter().any(|arm| matches!(arm, MatchArm::Else(_)))
+            || (has_case(true) && has_case(false))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum BinOp {
     Addition,
     Subtraction,
@@ -162,6 +413,52 @@ pub enum BinOp {
     DivideAssign,
 }
 
+impl BinOp {
+    /// Binding strength of the operator. Operators with a higher precedence are applied first.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinOp::AddAssign
+            | BinOp::SubtractAssign
+            | BinOp::MultiplyAssign
+            | BinOp::DivideAssign => 1,
+            BinOp::Or => 2,
+            BinOp::And => 3,
+            BinOp::Equal | BinOp::NotEqual => 4,
+            BinOp::LessThan
+            | BinOp::LessThanOrEqual
+            | BinOp::GreaterThan
+            | BinOp::GreaterThanOrEqual => 5,
+            BinOp::Addition | BinOp::Subtraction => 6,
+            BinOp::Multiplication | BinOp::Division | BinOp::Modulus => 7,
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            BinOp::Addition => "+",
+            BinOp::Subtraction => "-",
+            BinOp::Multiplication => "*",
+            BinOp::Division => "/",
+            BinOp::Modulus => "%",
+            BinOp::LessThan => "<",
+            BinOp::LessThanOrEqual => "<=",
+            BinOp::GreaterThan => ">",
+            BinOp::GreaterThanOrEqual => ">=",
+            BinOp::Equal => "==",
+            BinOp::NotEqual => "!=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::AddAssign => "+=",
+            BinOp::SubtractAssign => "-=",
+            BinOp::MultiplyAssign => "*=",
+            BinOp::DivideAssign => "/=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
 impl TryFrom<TokenKind> for BinOp {
     type Error = String;
     fn try_from(token: TokenKind) -> Result<BinOp, String> {
@@ -13,16 +13,28 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Type {
     Any,
     Int,
     Str,
     Bool,
     Array(Box<Type>, Option<usize>),
+    /// (key, value)
+    Map(Box<Type>, Box<Type>),
     Struct(String),
+    /// Either a value of the inner type or an error message, e.g. `int!`
+    Result(Box<Type>),
+    /// Reference to a value of the inner type, e.g. `&int`
+    Ref(Box<Type>),
+    /// Raw address of memory, which can only be used in `unsafe` blocks
+    Ptr,
+    /// Queue of values that tasks send to each other, e.g. `chan<int>`
+    Chan(Box<Type>),
 }
 
 impl TryFrom<String> for Type {
@@ -33,7 +45,26 @@ impl TryFrom<String> for Type {
             "string" => Ok(Self::Str),
             "any" => Ok(Self::Any),
             "bool" => Ok(Self::Bool),
+            "ptr" => Ok(Self::Ptr),
             name => Ok(Self::Struct(name.to_string())),
         }
     }
 }
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Int => write!(f, "int"),
+            Type::Str => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array(ty, _) => write!(f, "{}[]", ty),
+            Type::Map(key, value) => write!(f, "map<{}, {}>", key, value),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Result(ty) => write!(f, "{}!", ty),
+            Type::Ref(ty) => write!(f, "&{}", ty),
+            Type::Ptr => write!(f, "ptr"),
+            Type::Chan(ty) => write!(f, "chan<{}>", ty),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use lazy_static::lazy_static;
+use regex::Regex;
+
+#[cfg(test)]
+mod tests;
+
+lazy_static! {
+    static ref COMMENT: Regex = Regex::new(r"(?s)/\*.*?\*/|//[^\n]*").unwrap();
+    static ref PREPROCESSOR: Regex = Regex::new(r"(?m)^[ \t]*#([^\n]*\\\n)*[^\n]*").unwrap();
+    /// Attributes and compiler specific macros, e.g. `__attribute__((pure))` or `__nonnull ((1))`
+    static ref ATTRIBUTE: Regex = Regex::new(r"\b__\w+\s*\(\((.*?)\)\)").unwrap();
+    static ref PROTOTYPE: Regex =
+        Regex::new(r"^(?P<ret>.*?)(?P<name>[A-Za-z_]\w*)\s*\((?P<params>[^()]*)\)[\s\w]*$")
+            .unwrap();
+}
+
+/// Keywords of Antimony that are valid identifiers in C
+const KEYWORDS: &[&str] = &[
+    "fn", "let", "in", "match", "new", "import", "self", "extern", "true", "false", "ref", "unsafe",
+];
+
+/// Words that only qualify a type and can be ignored
+const QUALIFIERS: &[&str] = &[
+    "extern",
+    "static",
+    "inline",
+    "__inline",
+    "const",
+    "volatile",
+    "restrict",
+    "__restrict",
+];
+
+/// Words that make up integer types
+const INTEGER_WORDS: &[&str] = &["int", "long", "short", "char", "signed", "unsigned"];
+
+/// Generates `extern` declarations for the function prototypes of a C header.
+/// Declarations that can not be represented in Antimony are emitted as comments.
+pub fn generate(header: &str) -> String {
+    let mut out = String::new();
+    for statement in statements(header) {
+        if statement.starts_with("typedef") || !statement.contains('(') {
+            continue;
+        }
+        match convert(&statement) {
+            Ok(declaration) => out += &format!("{}\n", declaration),
+            Err(reason) => out += &format!("// Skipped `{}`: {}\n", statement, reason),
+        }
+    }
+    out
+}
+
+/// Splits a header into top level declarations, without comments, preprocessor
+/// directives and definitions enclosed in braces
+fn statements(header: &str) -> Vec<String> {
+    let header = COMMENT.replace_all(header, " ");
+    let header = PREPROCESSOR.replace_all(&header, " ");
+
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    // Depth of braces whose contents are skipped
+    let mut skipped = 0;
+    // Number of `extern "C" {` blocks, whose contents are declarations
+    let mut transparent = 0;
+
+    for c in header.chars() {
+        match c {
+            '{' if skipped == 0 && current.trim() == "extern \"C\"" => {
+                transparent += 1;
+                current.clear();
+            }
+            '{' => skipped += 1,
+            '}' if skipped > 0 => {
+                skipped -= 1;
+                if skipped == 0 && current.contains('(') {
+                    // Inline function definition
+                    current.clear();
+                }
+            }
+            '}' if transparent > 0 => transparent -= 1,
+            ';' if skipped == 0 => {
+                statements.push(normalize(&current));
+                current.clear();
+            }
+            c if skipped == 0 => current.push(c),
+            _ => {}
+        }
+    }
+
+    statements.retain(|s| !s.is_empty());
+    statements
+}
+
+/// Collapses whitespace and removes attributes
+fn normalize(statement: &str) -> String {
+    let statement = ATTRIBUTE.replace_all(statement, " ");
+    statement
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn convert(prototype: &str) -> Result<String, String> {
+    let captures = PROTOTYPE
+        .captures(prototype)
+        .ok_or("function pointers are not supported")?;
+    let name = &captures["name"];
+    if KEYWORDS.contains(&name) {
+        return Err(format!("'{}' is a reserved keyword", name));
+    }
+
+    let ret = convert_type(&captures["ret"])?;
+    let mut arguments = Vec::new();
+    let params = captures["params"].trim();
+    if !params.is_empty() && params != "void" {
+        for (index, param) in params.split(',').enumerate() {
+            arguments.push(convert_parameter(param.trim(), index)?);
+        }
+    }
+
+    let ret = match ret {
+        Some(ty) => format!(": {}", ty),
+        None => String::new(),
+    };
+    Ok(format!(
+        "extern \"C\" fn {}({}){}",
+        name,
+        arguments.join(", "),
+        ret
+    ))
+}
+
+/// Converts a parameter like `const char *s` to `s: string`.
+/// Unnamed parameters are named after their position.
+fn convert_parameter(param: &str, index: usize) -> Result<String, String> {
+    if param == "..." {
+        return Err("variadic functions are not supported".into());
+    }
+    if param.contains('[') {
+        return Err("array parameters are not supported".into());
+    }
+
+    let words: Vec<&str> = param
+        .split(|c: char| c.is_whitespace() || c == '*')
+        .filter(|w| !w.is_empty())
+        .collect();
+    let (ty, name) = match words.last() {
+        Some(last) if words.len() > 1 && !is_type_word(last) => {
+            let end = param.rfind(last).unwrap_or(param.len());
+            (&param[..end], last.to_string())
+        }
+        _ => (param, format!("arg{}", index)),
+    };
+    let name = if KEYWORDS.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        name
+    };
+
+    match convert_type(ty)? {
+        Some(ty) => Ok(format!("{}: {}", name, ty)),
+        None => Err("parameters of type void are not supported".into()),
+    }
+}
+
+fn is_type_word(word: &str) -> bool {
+    INTEGER_WORDS.contains(&word)
+        || QUALIFIERS.contains(&word)
+        || ["void", "bool", "_Bool", "float", "double", "struct", "enum"].contains(&word)
+        || word.ends_with("_t")
+}
+
+/// Returns the Antimony type of a C type, or `None` for `void`
+fn convert_type(ty: &str) -> Result<Option<&'static str>, String> {
+    let pointers = ty.matches('*').count();
+    let words: Vec<&str> = ty
+        .split(|c: char| c.is_whitespace() || c == '*')
+        .filter(|w| !w.is_empty() && !QUALIFIERS.contains(w))
+        .collect();
+    let unsupported = || Err(format!("type '{}' is not supported", ty.trim()));
+
+    match (words.as_slice(), pointers) {
+        (["char"], 1) => Ok(Some("string")),
+        (["void"], 0) => Ok(None),
+        (["bool"], 0) | (["_Bool"], 0) => Ok(Some("bool")),
+        (["float"], 0) | (["double"], 0) | (["long", "double"], 0) => {
+            Err("floating point types are not supported".into())
+        }
+        ([word], 0) if word.ends_with("_t") && (word.contains("int") || word.contains("size")) => {
+            Ok(Some("int"))
+        }
+        (words, 0) if !words.is_empty() && words.iter().all(|w| INTEGER_WORDS.contains(w)) => {
+            Ok(Some("int"))
+        }
+        _ => unsupported(),
+    }
+}
@@ -0,0 +1,101 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::bindgen::generate;
+
+#[test]
+fn test_simple_prototypes() {
+    let header = "
+    int abs(int n);
+    void exit(int status);
+    int puts(const char *s);
+    unsigned long strlen(const char* str);
+    bool is_ready(void);
+    ";
+    assert_eq!(
+        generate(header),
+        [
+            "extern \"C\" fn abs(n: int): int",
+            "extern \"C\" fn exit(status: int)",
+            "extern \"C\" fn puts(s: string): int",
+            "extern \"C\" fn strlen(str: string): int",
+            "extern \"C\" fn is_ready(): bool",
+            "",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn test_unnamed_and_renamed_parameters() {
+    let header = "int add(int, long);\nint contains(const char *in, int match);";
+    assert_eq!(
+        generate(header),
+        "extern \"C\" fn add(arg0: int, arg1: int): int\nextern \"C\" fn contains(in_: string, match_: int): int\n"
+    );
+}
+
+#[test]
+fn test_ignores_comments_preprocessor_and_definitions() {
+    let header = "
+    #ifndef MATH_H
+    #define MAX(a, b) \\
+        ((a) > (b) ? (a) : (b))
+    /* Returns the larger number */
+    int max(int a, int b); // inline comment
+    struct point { int x; int y; };
+    typedef int (*callback)(int);
+    static inline int twice(int x) { return x * 2; }
+    extern int counter;
+    #endif
+    ";
+    assert_eq!(
+        generate(header),
+        "extern \"C\" fn max(a: int, b: int): int\n"
+    );
+}
+
+#[test]
+fn test_extern_c_blocks_and_attributes() {
+    let header = "
+    extern \"C\" {
+        extern int atoi(const char *nptr) __attribute__((pure)) __THROW;
+        extern long int atol(const char *__nptr) __THROW __attribute_pure__ __nonnull ((1)) __wur;
+    }
+    ";
+    assert_eq!(
+        generate(header),
+        "extern \"C\" fn atoi(nptr: string): int\nextern \"C\" fn atol(__nptr: string): int\n"
+    );
+}
+
+#[test]
+fn test_unsupported_declarations_are_skipped() {
+    let header = "
+    double sqrt(double x);
+    int printf(const char *format, ...);
+    void *malloc(size_t size);
+    ";
+    assert_eq!(
+        generate(header),
+        [
+            "// Skipped `double sqrt(double x)`: floating point types are not supported",
+            "// Skipped `int printf(const char *format, ...)`: variadic functions are not supported",
+            "// Skipped `void *malloc(size_t size)`: type 'void *' is not supported",
+            "",
+        ]
+        .join("\n")
+    );
+}
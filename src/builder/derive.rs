@@ -0,0 +1,604 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::{DERIVABLE, DERIVE, DERIVE_EQ, DERIVE_JSON, NO_MANGLE};
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Struct of the stdlib that represents JSON values
+const JSON_STRUCT: &str = "Json";
+
+/// Generates the functions of every struct that is annotated with `@derive`:
+///
+/// - `json`: `<Struct>_to_json(value: Struct): Json` converts the struct to a JSON object, and
+///   `<Struct>_from_json(value: Json): Struct!` converts a JSON object to the struct
+/// - `eq`: `<Struct>_eq(a: Struct, b: Struct): bool` compares the fields of two structs,
+///   which `==` and `!=` are lowered to
+/// - `show`: `<Struct>_to_string(value: Struct): string` describes the struct and its fields,
+///   which the struct is converted with when it is printed
+///
+/// The functions are declared in the module of the struct, and are public if the struct is.
+/// Struct names are global, so the functions keep their names in every backend.
+///
+/// `unavailable` describes why the `json` module of the stdlib can not be used, if it can not.
+pub(super) fn derive_functions(
+    modules: &mut [Module],
+    unavailable: Option<&str>,
+) -> Result<(), String> {
+    // Derive -> structs that derive it
+    let mut derived: HashMap<&str, HashSet<String>> = HashMap::new();
+    for def in modules.iter().flat_map(|m| &m.structs) {
+        for attribute in def.attributes.iter().filter(|attr| attr.name == DERIVE) {
+            if attribute.positional().is_none() {
+                return Err(format!(
+                    "@derive on struct '{}' needs to name what to derive, e.g. @derive({})",
+                    def.name, DERIVE_EQ
+                ));
+            }
+            for derive in attribute.positionals() {
+                let derive = DERIVABLE
+                    .iter()
+                    .copied()
+                    .find(|d| *d == derive)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown derive '{}' on struct '{}', expected one of '{}'",
+                            derive,
+                            def.name,
+                            DERIVABLE.join("', '")
+                        )
+                    })?;
+                if let (DERIVE_JSON, Some(reason)) = (derive, unavailable) {
+                    return Err(format!(
+                        "@derive(json) on struct '{}' needs the json module of the standard library, which is not available {}",
+                        def.name, reason
+                    ));
+                }
+                derived.entry(derive).or_default().insert(def.name.clone());
+            }
+        }
+    }
+
+    for module in modules.iter_mut() {
+        let mut functions = Vec::new();
+        for def in &module.structs {
+            for derive in DERIVABLE {
+                let Some(structs) = derived.get(derive).filter(|s| s.contains(&def.name)) else {
+                    continue;
+                };
+                for field in &def.fields {
+                    check_field_type(def, field, derive, field.ty.as_ref(), structs)?;
+                }
+                let deriver = Deriver { def };
+                let generated = match derive {
+                    DERIVE_JSON => vec![deriver.serializer(), deriver.deserializer()],
+                    DERIVE_EQ => vec![deriver.equality()],
+                    _ => vec![deriver.description()],
+                };
+                functions.extend(generated.into_iter().map(|func| (derive, func)));
+            }
+        }
+        for (derive, mut func) in functions {
+            if module.func.iter().any(|f| f.name == func.name) {
+                return Err(format!(
+                    "Function '{}' is already declared, but is generated by @derive({})",
+                    func.name, derive
+                ));
+            }
+            func.attributes.push(Attribute {
+                name: NO_MANGLE.to_owned(),
+                arguments: Vec::new(),
+            });
+            module.func.push(func);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a field has a type that the functions of a derive can handle
+fn check_field_type(
+    def: &StructDef,
+    field: &Variable,
+    derive: &str,
+    ty: Option<&Type>,
+    derived: &HashSet<String>,
+) -> Result<(), String> {
+    let unsupported = match derive {
+        DERIVE_JSON => "can not be converted to JSON",
+        DERIVE_EQ => "can not be compared",
+        _ => "can not be converted to a string",
+    };
+    match ty {
+        Some(Type::Int | Type::Bool | Type::Str) => Ok(()),
+        Some(Type::Struct(name)) if derive == DERIVE_JSON && name == JSON_STRUCT => Ok(()),
+        Some(Type::Struct(name)) if derived.contains(name) => Ok(()),
+        Some(Type::Struct(name)) => Err(format!(
+            "Field '{}' of struct '{}' has type '{}', which does not derive {}. Annotate struct '{}' with @derive({})",
+            field.name, def.name, name, derive, name, derive
+        )),
+        Some(Type::Array(elem, _)) => check_field_type(def, field, derive, Some(elem), derived),
+        Some(other) => Err(format!(
+            "Field '{}' of struct '{}' has type '{}', which {}",
+            field.name, def.name, other, unsupported
+        )),
+        None => Err(format!(
+            "Field '{}' of struct '{}' needs a type, since it is derived with @derive({})",
+            field.name, def.name, derive
+        )),
+    }
+}
+
+fn var(name: &str) -> Expression {
+    Expression::Variable(name.to_owned())
+}
+
+fn call(name: &str, args: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(name.to_owned(), args)
+}
+
+fn field(object: Expression, name: &str) -> Expression {
+    Expression::FieldAccess(Box::new(object), Box::new(var(name)))
+}
+
+fn method(object: &str, name: &str, args: Vec<Expression>) -> Expression {
+    Expression::FieldAccess(Box::new(var(object)), Box::new(call(name, args)))
+}
+
+fn concat(lhs: Expression, rhs: Expression) -> Expression {
+    Expression::BinOp(Box::new(lhs), BinOp::Addition, Box::new(rhs))
+}
+
+/// `name += value`
+fn append(name: &str, value: Expression) -> Statement {
+    Statement::Exp(Expression::BinOp(
+        Box::new(var(name)),
+        BinOp::AddAssign,
+        Box::new(value),
+    ))
+}
+
+fn parameter(name: &str, ty: Type) -> Variable {
+    Variable {
+        name: name.to_owned(),
+        ty: Some(ty),
+        pos: None,
+        reference: false,
+    }
+}
+
+fn declare(name: &str, ty: Type, value: Expression) -> Statement {
+    Statement::Declare(parameter(name, ty), Some(value))
+}
+
+fn block(statements: Vec<Statement>) -> Box<Statement> {
+    Box::new(Statement::Block(statements, Vec::new()))
+}
+
+fn return_error(message: Expression) -> Statement {
+    Statement::Return(Some(call("error", vec![message])))
+}
+
+fn json_type() -> Type {
+    Type::Struct(JSON_STRUCT.to_owned())
+}
+
+/// Appends a loop over the elements of the array in the variable `array` to `body`.
+/// The statements of the loop are built by `element` from the index of the element.
+fn for_each(
+    body: &mut Vec<Statement>,
+    array: &str,
+    index: &str,
+    element: impl FnOnce(Expression) -> Vec<Statement>,
+) {
+    body.push(declare(index, Type::Int, Expression::Int(0)));
+    let mut statements = element(var(index));
+    statements.push(Statement::Exp(Expression::BinOp(
+        Box::new(var(index)),
+        BinOp::AddAssign,
+        Box::new(Expression::Int(1)),
+    )));
+    body.push(Statement::While(
+        Expression::BinOp(
+            Box::new(var(index)),
+            BinOp::LessThan,
+            Box::new(method(array, "len", Vec::new())),
+        ),
+        block(statements),
+    ));
+}
+
+/// Kind of a JSON value that is converted to a value of the type, with an article
+fn json_kind(ty: &Type) -> (&'static str, &'static str) {
+    match ty {
+        Type::Int => ("number", "a"),
+        Type::Bool => ("bool", "a"),
+        Type::Str => ("string", "a"),
+        Type::Array(..) => ("array", "an"),
+        _ => ("object", "an"),
+    }
+}
+
+struct Deriver<'a> {
+    def: &'a StructDef,
+}
+
+impl Deriver<'_> {
+    /// `<Struct>_to_json(value: Struct): Json`
+    fn serializer(&self) -> Function {
+        let mut body = vec![
+            declare(
+                "_keys",
+                Type::Array(Box::new(Type::Str), None),
+                Expression::Array(0, Vec::new()),
+            ),
+            declare(
+                "_values",
+                Type::Array(Box::new(json_type()), None),
+                Expression::Array(0, Vec::new()),
+            ),
+        ];
+        for f in &self.def.fields {
+            let ty = f.ty.as_ref().expect("Field types are checked");
+            let local = format!("_{}", f.name);
+            let value = self.value_to_json(&mut body, field(var("value"), &f.name), ty, &local);
+            body.push(Statement::Exp(method(
+                "_keys",
+                "push",
+                vec![Expression::Str(f.name.clone())],
+            )));
+            body.push(Statement::Exp(method("_values", "push", vec![value])));
+        }
+        body.push(Statement::Return(Some(call(
+            "json_object",
+            vec![var("_keys"), var("_values")],
+        ))));
+
+        Function {
+            name: format!("{}_to_json", self.def.name),
+            arguments: vec![parameter("value", Type::Struct(self.def.name.clone()))],
+            body: Statement::Block(body, Vec::new()),
+            ret_type: Some(json_type()),
+            attributes: Vec::new(),
+            public: self.def.public,
+            is_async: false,
+        }
+    }
+
+    /// Returns an expression that converts `value` to JSON. Arrays are converted by a loop
+    /// that is appended to `body`, with variables that are prefixed by `local`.
+    fn value_to_json(
+        &self,
+        body: &mut Vec<Statement>,
+        value: Expression,
+        ty: &Type,
+        local: &str,
+    ) -> Expression {
+        match ty {
+            Type::Int => call("json_number", vec![value]),
+            Type::Bool => call("json_bool", vec![value]),
+            Type::Str => call("json_string", vec![value]),
+            Type::Struct(name) if name == JSON_STRUCT => value,
+            Type::Struct(name) => call(&format!("{}_to_json", name), vec![value]),
+            Type::Array(elem, _) => {
+                // Only variables can be indexed
+                let items = format!("{}_json", local);
+                body.push(declare(local, ty.clone(), value));
+                body.push(declare(
+                    &items,
+                    Type::Array(Box::new(json_type()), None),
+                    Expression::Array(0, Vec::new()),
+                ));
+                for_each(body, local, &format!("{}_i", local), |index| {
+                    let mut statements = Vec::new();
+                    let element = Expression::ArrayAccess(local.to_owned(), Box::new(index));
+                    let item = format!("{}_item", local);
+                    let json = self.value_to_json(&mut statements, element, elem, &item);
+                    statements.push(Statement::Exp(method(&items, "push", vec![json])));
+                    statements
+                });
+                call("json_array", vec![var(&items)])
+            }
+            _ => unreachable!("Field types are checked"),
+        }
+    }
+
+    /// `<Struct>_from_json(value: Json): Struct!`
+    fn deserializer(&self) -> Function {
+        let name = &self.def.name;
+        let mut body = vec![self.check_kind(
+            "value",
+            &Type::Struct(name.clone()),
+            &format!("struct '{}'", name),
+        )];
+        let mut fields = HashMap::new();
+        for f in &self.def.fields {
+            let ty = f.ty.as_ref().expect("Field types are checked");
+            let local = format!("_{}", f.name);
+            let json = format!("{}_json", local);
+            let context = format!("field '{}' of struct '{}'", f.name, name);
+            let lookup = |key: &str| {
+                call(
+                    "json_get",
+                    vec![var("value"), Expression::Str(key.to_owned())],
+                )
+            };
+            let has = call(
+                "json_has",
+                vec![var("value"), Expression::Str(f.name.clone())],
+            );
+
+            match self.def.defaults.get(&f.name) {
+                // Fields with a default value may be omitted
+                Some(default) => {
+                    body.push(declare(&local, ty.clone(), default.clone()));
+                    let value = format!("{}_value", local);
+                    let mut statements = vec![declare(&json, json_type(), lookup(&f.name))];
+                    self.value_from_json(&mut statements, &json, ty, &value, &context);
+                    statements.push(Statement::Assign(
+                        Box::new(var(&local)),
+                        Box::new(var(&value)),
+                    ));
+                    body.push(Statement::If(has, block(statements), None));
+                }
+                None => {
+                    let missing = Expression::BinOp(
+                        Box::new(has),
+                        BinOp::Equal,
+                        Box::new(Expression::Bool(false)),
+                    );
+                    body.push(Statement::If(
+                        missing,
+                        block(vec![return_error(Expression::Str(format!(
+                            "Missing {}",
+                            context
+                        )))]),
+                        None,
+                    ));
+                    body.push(declare(&json, json_type(), lookup(&f.name)));
+                    self.value_from_json(&mut body, &json, ty, &local, &context);
+                }
+            }
+            fields.insert(f.name.clone(), Box::new(var(&local)));
+        }
+        body.push(Statement::Return(Some(Expression::StructInitialization(
+            name.clone(),
+            fields,
+        ))));
+
+        Function {
+            name: format!("{}_from_json", name),
+            arguments: vec![parameter("value", json_type())],
+            body: Statement::Block(body, Vec::new()),
+            ret_type: Some(Type::Result(Box::new(Type::Struct(name.clone())))),
+            attributes: Vec::new(),
+            public: self.def.public,
+            is_async: false,
+        }
+    }
+
+    /// Appends statements to `body` that declare `local` with the value of the JSON value
+    /// in the variable `json`, and return an error if it has the wrong kind.
+    /// `context` describes the value in error messages.
+    fn value_from_json(
+        &self,
+        body: &mut Vec<Statement>,
+        json: &str,
+        ty: &Type,
+        local: &str,
+        context: &str,
+    ) {
+        let value = match ty {
+            Type::Struct(name) if name == JSON_STRUCT => var(json),
+            // The conversion of the struct checks the kind itself
+            Type::Struct(name) => Expression::Try(Box::new(call(
+                &format!("{}_from_json", name),
+                vec![var(json)],
+            ))),
+            Type::Int | Type::Bool | Type::Str => {
+                body.push(self.check_kind(json, ty, context));
+                let member = match ty {
+                    Type::Int => "number",
+                    Type::Bool => "boolean",
+                    _ => "text",
+                };
+                field(var(json), member)
+            }
+            Type::Array(elem, _) => {
+                body.push(self.check_kind(json, ty, context));
+                // Only variables can be indexed
+                let items = format!("{}_items", local);
+                body.push(declare(
+                    &items,
+                    Type::Array(Box::new(json_type()), None),
+                    field(var(json), "items"),
+                ));
+                body.push(declare(local, ty.clone(), Expression::Array(0, Vec::new())));
+                for_each(body, &items, &format!("{}_i", local), |index| {
+                    let item = format!("{}_item", local);
+                    let value = format!("{}_value", local);
+                    let mut statements = vec![declare(
+                        &item,
+                        json_type(),
+                        Expression::ArrayAccess(items.clone(), Box::new(index)),
+                    )];
+                    let context = format!("an element of {}", context);
+                    self.value_from_json(&mut statements, &item, elem, &value, &context);
+                    statements.push(Statement::Exp(method(local, "push", vec![var(&value)])));
+                    statements
+                });
+                return;
+            }
+            _ => unreachable!("Field types are checked"),
+        };
+        body.push(declare(local, ty.clone(), value));
+    }
+
+    /// Returns a statement that returns an error if the JSON value in the variable `json`
+    /// is not of the kind that is converted to the type
+    fn check_kind(&self, json: &str, ty: &Type, context: &str) -> Statement {
+        let (kind, article) = json_kind(ty);
+        let actual = || field(var(json), "kind");
+        Statement::If(
+            Expression::BinOp(
+                Box::new(actual()),
+                BinOp::NotEqual,
+                Box::new(Expression::Str(kind.to_owned())),
+            ),
+            block(vec![return_error(Expression::BinOp(
+                Box::new(Expression::Str(format!(
+                    "Expected {} to be {} {}, found ",
+                    context, article, kind
+                ))),
+                BinOp::Addition,
+                Box::new(actual()),
+            ))]),
+            None,
+        )
+    }
+
+    /// `<Struct>_eq(a: Struct, b: Struct): bool`
+    fn equality(&self) -> Function {
+        let unequal = |a: Expression, b: Expression| {
+            Statement::If(
+                Expression::BinOp(Box::new(a), BinOp::NotEqual, Box::new(b)),
+                block(vec![Statement::Return(Some(Expression::Bool(false)))]),
+                None,
+            )
+        };
+        let mut body = Vec::new();
+        for f in &self.def.fields {
+            let ty = f.ty.as_ref().expect("Field types are checked");
+            let (a, b) = (field(var("a"), &f.name), field(var("b"), &f.name));
+            match ty {
+                // Arrays are equal if they have equal elements
+                Type::Array(..) => {
+                    // Only variables can be indexed
+                    let (local_a, local_b) = (format!("_{}_a", f.name), format!("_{}_b", f.name));
+                    body.push(declare(&local_a, ty.clone(), a));
+                    body.push(declare(&local_b, ty.clone(), b));
+                    body.push(unequal(
+                        method(&local_a, "len", Vec::new()),
+                        method(&local_b, "len", Vec::new()),
+                    ));
+                    for_each(&mut body, &local_a, &format!("_{}_i", f.name), |index| {
+                        let element = |array: &str| {
+                            Expression::ArrayAccess(array.to_owned(), Box::new(index.clone()))
+                        };
+                        vec![unequal(element(&local_a), element(&local_b))]
+                    });
+                }
+                // Comparisons of structs that derive eq are lowered to their function as well
+                _ => body.push(unequal(a, b)),
+            }
+        }
+        body.push(Statement::Return(Some(Expression::Bool(true))));
+
+        let ty = Type::Struct(self.def.name.clone());
+        Function {
+            name: format!("{}_eq", self.def.name),
+            arguments: vec![parameter("a", ty.clone()), parameter("b", ty)],
+            body: Statement::Block(body, Vec::new()),
+            ret_type: Some(Type::Bool),
+            attributes: Vec::new(),
+            public: self.def.public,
+            is_async: false,
+        }
+    }
+
+    /// `<Struct>_to_string(value: Struct): string`, which describes the struct
+    /// like `Point { x: 1, y: 2 }`
+    fn description(&self) -> Function {
+        let name = &self.def.name;
+        let mut body = vec![declare(
+            "_out",
+            Type::Str,
+            Expression::Str(format!("{} {{", name)),
+        )];
+        for (i, f) in self.def.fields.iter().enumerate() {
+            let ty = f.ty.as_ref().expect("Field types are checked");
+            let local = format!("_{}", f.name);
+            let value = self.value_to_string(&mut body, field(var("value"), &f.name), ty, &local);
+            let separator = if i == 0 { " " } else { ", " };
+            body.push(append(
+                "_out",
+                concat(Expression::Str(format!("{}{}: ", separator, f.name)), value),
+            ));
+        }
+        let end = if self.def.fields.is_empty() {
+            "}"
+        } else {
+            " }"
+        };
+        body.push(append("_out", Expression::Str(end.to_owned())));
+        body.push(Statement::Return(Some(var("_out"))));
+
+        Function {
+            name: format!("{}_to_string", name),
+            arguments: vec![parameter("value", Type::Struct(name.clone()))],
+            body: Statement::Block(body, Vec::new()),
+            ret_type: Some(Type::Str),
+            attributes: Vec::new(),
+            public: self.def.public,
+            is_async: false,
+        }
+    }
+
+    /// Returns an expression that describes `value` as a string. Arrays are described by
+    /// a loop that is appended to `body`, with variables that are prefixed by `local`.
+    fn value_to_string(
+        &self,
+        body: &mut Vec<Statement>,
+        value: Expression,
+        ty: &Type,
+        local: &str,
+    ) -> Expression {
+        match ty {
+            Type::Int => call("_int_to_str", vec![value]),
+            Type::Bool => call("_bool_to_str", vec![value]),
+            Type::Str => {
+                let quote = || Expression::Str("\"".to_owned());
+                concat(concat(quote(), value), quote())
+            }
+            Type::Struct(name) => call(&format!("{}_to_string", name), vec![value]),
+            Type::Array(elem, _) => {
+                // Only variables can be indexed
+                let out = format!("{}_str", local);
+                body.push(declare(local, ty.clone(), value));
+                body.push(declare(&out, Type::Str, Expression::Str("[".to_owned())));
+                let index = format!("{}_i", local);
+                for_each(body, local, &index, |i| {
+                    let mut statements = vec![Statement::If(
+                        Expression::BinOp(
+                            Box::new(var(&index)),
+                            BinOp::GreaterThan,
+                            Box::new(Expression::Int(0)),
+                        ),
+                        block(vec![append(&out, Expression::Str(", ".to_owned()))]),
+                        None,
+                    )];
+                    let element = Expression::ArrayAccess(local.to_owned(), Box::new(i));
+                    let item = format!("{}_item", local);
+                    let value = self.value_to_string(&mut statements, element, elem, &item);
+                    statements.push(append(&out, value));
+                    statements
+                });
+                body.push(append(&out, Expression::Str("]".to_owned())));
+                var(&out)
+            }
+            _ => unreachable!("Field types are checked"),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::{Expression, MatchArm, Module, Statement};
+
+/// Function the parser lowers `include_str!("file")` to: (file, location of the call)
+const INCLUDE_STR: &str = "_include_str";
+
+/// Replaces the uses of `include_str!` in a module with string literals of the embedded files.
+/// `read` returns the contents of a file, given its path as written and the location of the call.
+pub(super) fn embed_files(
+    module: &mut Module,
+    read: &mut impl FnMut(&str, &str) -> Result<String, String>,
+) -> Result<(), String> {
+    for global in &mut module.globals {
+        embed_expression(&mut global.value, read)?;
+    }
+    for func in &mut module.func {
+        embed_statement(&mut func.body, read)?;
+    }
+    for def in &mut module.structs {
+        for value in def.defaults.values_mut() {
+            embed_expression(value, read)?;
+        }
+        for method in &mut def.methods {
+            embed_statement(&mut method.body, read)?;
+        }
+    }
+    Ok(())
+}
+
+fn embed_statement(
+    statement: &mut Statement,
+    read: &mut impl FnMut(&str, &str) -> Result<String, String>,
+) -> Result<(), String> {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .try_for_each(|s| embed_statement(s, read)),
+        Statement::Declare(_, Some(expr))
+        | Statement::Static(_, expr)
+        | Statement::Destructure(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => embed_expression(expr, read),
+        Statement::Assign(lhs, rhs) => {
+            embed_expression(lhs, read)?;
+            embed_expression(rhs, read)
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            embed_expression(cond, read)?;
+            embed_statement(if_branch, read)?;
+            match else_branch {
+                Some(else_branch) => embed_statement(else_branch, read),
+                None => Ok(()),
+            }
+        }
+        Statement::While(cond, body)
+        | Statement::For(_, cond, body)
+        | Statement::ForPair(_, _, cond, body) => {
+            embed_expression(cond, read)?;
+            embed_statement(body, read)
+        }
+        Statement::Unsafe(body) => embed_statement(body, read),
+        Statement::Match(subject, arms) => {
+            embed_expression(subject, read)?;
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        embed_expression(expr, read)?;
+                        embed_statement(s, read)?;
+                    }
+                    MatchArm::Else(s) => embed_statement(s, read)?,
+                }
+            }
+            Ok(())
+        }
+        Statement::Declare(_, None)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => Ok(()),
+    }
+}
+
+fn embed_expression(
+    expr: &mut Expression,
+    read: &mut impl FnMut(&str, &str) -> Result<String, String>,
+) -> Result<(), String> {
+    match expr {
+        Expression::FunctionCall(name, args) if name == INCLUDE_STR => {
+            if let [Expression::Str(file), Expression::Str(location)] = args.as_slice() {
+                *expr = Expression::Str(read(file, location)?);
+            }
+            Ok(())
+        }
+        Expression::FunctionCall(_, elements) | Expression::Array(_, elements) => elements
+            .iter_mut()
+            .try_for_each(|e| embed_expression(e, read)),
+        Expression::ArrayAccess(_, index)
+        | Expression::Try(index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index)
+        | Expression::Load(_, index) => embed_expression(index, read),
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            embed_expression(lhs, read)?;
+            embed_expression(rhs, read)
+        }
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .try_for_each(|e| embed_expression(e, read)),
+        Expression::StructUpdate(_, base, fields) => {
+            embed_expression(base, read)?;
+            fields
+                .values_mut()
+                .try_for_each(|e| embed_expression(e, read))
+        }
+        Expression::FieldAccess(obj, field) => {
+            embed_expression(obj, read)?;
+            match &mut **field {
+                Expression::FunctionCall(_, args) => {
+                    args.iter_mut().try_for_each(|e| embed_expression(e, read))
+                }
+                _ => Ok(()),
+            }
+        }
+        Expression::Map(entries) => entries.iter_mut().try_for_each(|(key, value)| {
+            embed_expression(key, read)?;
+            embed_expression(value, read)
+        }),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_)
+        | Expression::SizeOf(_) => Ok(()),
+    }
+}
@@ -0,0 +1,244 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::NO_MANGLE;
+use crate::ast::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Renames the functions of each module to module-qualified symbols, e.g. `lib.math.add`
+/// for the function `add` of `lib/math.sb`, so that functions of different modules
+/// with the same name do not collide once the modules are merged.
+///
+/// `roots` are the directories of the entrypoint and of packages, together with the name that
+/// prefixes the symbols of their modules. Functions of the `entry` module, `main` and functions
+/// annotated with `@no_mangle` keep their name. Calls are resolved to the function of the calling module if it declares one,
+/// and to the public function of another module otherwise.
+pub(super) fn mangle_symbols(
+    modules: &mut [Module],
+    entry: &str,
+    roots: &[(String, PathBuf)],
+) -> Result<(), String> {
+    let symbols: Vec<HashMap<String, String>> = modules
+        .iter()
+        .map(|module| {
+            let qualifier = qualifier(Path::new(&module.path), roots);
+            module
+                .func
+                .iter()
+                .map(|func| {
+                    // Functions that collide with the ones of other modules are already qualified
+                    let symbol = if module.path == entry
+                        || func.name.contains('.')
+                        || func.name == "main"
+                        || func.has_attribute(NO_MANGLE)
+                    {
+                        func.name.clone()
+                    } else {
+                        format!("{}.{}", qualifier, func.name)
+                    };
+                    (func.name.clone(), symbol)
+                })
+                .collect()
+        })
+        .collect();
+
+    // Name -> symbol of public functions, `None` if several modules declare the name
+    let mut public: HashMap<&str, Option<&str>> = HashMap::new();
+    for (module, symbols) in modules.iter().zip(&symbols) {
+        for func in module.func.iter().filter(|f| f.public) {
+            let symbol = symbols[&func.name].as_str();
+            public
+                .entry(&func.name)
+                .and_modify(|existing| *existing = None)
+                .or_insert(Some(symbol));
+        }
+    }
+
+    let mut renames: Vec<HashMap<String, Option<String>>> = Vec::new();
+    for own in &symbols {
+        let mut module_renames: HashMap<String, Option<String>> = public
+            .iter()
+            .map(|(name, symbol)| (name.to_string(), symbol.map(str::to_owned)))
+            .collect();
+        for (name, symbol) in own {
+            module_renames.insert(name.clone(), Some(symbol.clone()));
+        }
+        renames.push(module_renames);
+    }
+
+    for ((module, symbols), renames) in modules.iter_mut().zip(&symbols).zip(&renames) {
+        let mut ambiguous = None;
+        let mut rename = |name: &mut String| match renames.get(name.as_str()) {
+            Some(Some(symbol)) => *name = symbol.clone(),
+            Some(None) => {
+                ambiguous.get_or_insert_with(|| name.clone());
+            }
+            None => {}
+        };
+        for global in &mut module.globals {
+            rename_calls_expression(&mut global.value, &mut rename);
+        }
+        let methods = module.structs.iter_mut().flat_map(|def| &mut def.methods);
+        for func in module.func.iter_mut().chain(methods) {
+            rename_calls(&mut func.body, &mut rename);
+        }
+        if let Some(name) = ambiguous {
+            return Err(format!(
+                "Function '{}' called in module '{}' is declared by more than one module",
+                name, module.path
+            ));
+        }
+        for func in &mut module.func {
+            func.name = symbols[&func.name].clone();
+        }
+    }
+    Ok(())
+}
+
+/// Returns the prefix of the symbols of a module, which is its path relative
+/// to the closest root, without the extension and the `module` file name of directories
+pub(super) fn qualifier(path: &Path, roots: &[(String, PathBuf)]) -> String {
+    let (prefix, relative) = roots
+        .iter()
+        .filter_map(|(name, dir)| Some((name, path.strip_prefix(dir).ok()?)))
+        .min_by_key(|(_, relative)| relative.components().count())
+        .map(|(name, relative)| (name.as_str(), relative))
+        .unwrap_or(("", path));
+
+    let mut components: Vec<String> = relative
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if components.len() > 1 && components.last().map(String::as_str) == Some("module") {
+        components.pop();
+    }
+    if !prefix.is_empty() {
+        components.insert(0, prefix.to_owned());
+    }
+    components
+        .iter()
+        .map(|component| {
+            component
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+pub(super) fn rename_calls(statement: &mut Statement, rename: &mut impl FnMut(&mut String)) {
+    match statement {
+        Statement::Block(statements, _) => {
+            statements.iter_mut().for_each(|s| rename_calls(s, rename))
+        }
+        Statement::Declare(_, Some(expr))
+        | Statement::Static(_, expr)
+        | Statement::Destructure(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => rename_calls_expression(expr, rename),
+        Statement::Assign(lhs, rhs) => {
+            rename_calls_expression(lhs, rename);
+            rename_calls_expression(rhs, rename);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            rename_calls_expression(cond, rename);
+            rename_calls(if_branch, rename);
+            if let Some(else_branch) = else_branch {
+                rename_calls(else_branch, rename);
+            }
+        }
+        Statement::While(cond, body)
+        | Statement::For(_, cond, body)
+        | Statement::ForPair(_, _, cond, body) => {
+            rename_calls_expression(cond, rename);
+            rename_calls(body, rename);
+        }
+        Statement::Unsafe(body) => rename_calls(body, rename),
+        Statement::Match(subject, arms) => {
+            rename_calls_expression(subject, rename);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        rename_calls_expression(expr, rename);
+                        rename_calls(s, rename);
+                    }
+                    MatchArm::Else(s) => rename_calls(s, rename),
+                }
+            }
+        }
+        Statement::Declare(_, None)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+}
+
+pub(super) fn rename_calls_expression(expr: &mut Expression, rename: &mut impl FnMut(&mut String)) {
+    match expr {
+        Expression::FunctionCall(name, args) => {
+            rename(name);
+            args.iter_mut()
+                .for_each(|e| rename_calls_expression(e, rename));
+        }
+        Expression::ArrayAccess(_, index)
+        | Expression::Try(index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index)
+        | Expression::Load(_, index) => rename_calls_expression(index, rename),
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            rename_calls_expression(lhs, rename);
+            rename_calls_expression(rhs, rename);
+        }
+        Expression::Array(_, elements) => elements
+            .iter_mut()
+            .for_each(|e| rename_calls_expression(e, rename)),
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|e| rename_calls_expression(e, rename)),
+        Expression::StructUpdate(_, base, fields) => {
+            rename_calls_expression(base, rename);
+            fields
+                .values_mut()
+                .for_each(|e| rename_calls_expression(e, rename))
+        }
+        // Method names belong to the struct of the object
+        Expression::FieldAccess(obj, field) => {
+            rename_calls_expression(obj, rename);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut()
+                    .for_each(|e| rename_calls_expression(e, rename));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                rename_calls_expression(key, rename);
+                rename_calls_expression(value, rename);
+            }
+        }
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_)
+        | Expression::SizeOf(_) => {}
+    }
+}
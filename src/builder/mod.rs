@@ -13,118 +13,546 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use crate::ast::Module;
-use crate::generator::{self, Generator, Target};
-use crate::lexer;
+use crate::ast::attributes;
+use crate::ast::types::Type;
+use crate::ast::{BinOp, Expression, Function, Global, MatchArm, Module, Statement, Variable};
+use crate::cache::{self, Cache};
+use crate::diagnostics::{Diagnostic, MessageFormat, Severity};
+use crate::generator::js::{JsRuntime, ModuleFormat};
+use crate::generator::{self, Arch, Generator, Target};
+use crate::lexer::{self, Position};
+use crate::optimizer;
+use crate::package::Packages;
 use crate::parser;
+use crate::semantic;
+use crate::timings::{Timings, TimingsFormat};
+use crate::util::string_util::highlight_position_in_file;
+use crate::warnings::{self, WarningOptions};
 use crate::Lib;
-use crate::PathBuf;
-use std::env;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+mod derive;
+mod embed;
+mod mangle;
+mod namespace;
+
+/// Settings that influence how a program is built
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Level of optimizations applied before code generation
+    pub opt_level: u8,
+    /// Lints that are reported while building
+    pub warnings: WarningOptions,
+    /// Print information about each compilation step
+    pub verbose: bool,
+    /// Replace the main function with one that runs all `@test` functions
+    pub test: bool,
+    /// Replace the main function with one that measures all `@bench` functions
+    pub bench: bool,
+    /// Instrument native code with runtime checks, like a limit of the recursion depth
+    pub debug: bool,
+    /// Panic if the result of an integer operation overflows
+    pub overflow_checks: bool,
+    /// Omit runtime checks, like bounds checks of array accesses
+    pub release: bool,
+    /// Dependencies that can be imported by their name
+    pub packages: Packages,
+    /// Leave out the prelude of the standard library, except for the builtins the compiler relies on
+    pub no_prelude: bool,
+    /// Module of the project that every module can use without importing it
+    pub prelude: Option<PathBuf>,
+    /// Directory parsed modules and generated output are cached in. Caching is disabled if not set
+    pub cache: Option<PathBuf>,
+    /// How diagnostics are printed
+    pub message_format: MessageFormat,
+    /// How the output of the JavaScript backend is packaged
+    pub module_format: ModuleFormat,
+    /// Environment the output of the JavaScript backend runs in
+    pub js_runtime: JsRuntime,
+    /// Architecture the output of the native backends is compiled for. Defaults to the host's
+    pub arch: Option<Arch>,
+    /// Generate TypeScript declarations of the JavaScript output
+    pub declarations: bool,
+    /// Keep functions and structs the program does not use
+    pub keep_all: bool,
+    /// Measure the time spent in each phase of the compiler, reported in the given format
+    pub timings: Option<TimingsFormat>,
+}
+
+/// Returns the path of a module imported by the given file
+pub fn resolve_import(importer: &Path, import: &str, packages: &Packages) -> PathBuf {
+    let mut components = Path::new(import).components();
+    let package = components
+        .next()
+        .and_then(|first| packages.get(&*first.as_os_str().to_string_lossy()));
+
+    let mut import_path = match package {
+        // Importing a package by its name refers to its entrypoint,
+        // other modules are resolved relative to the entrypoint
+        Some(entry) if components.as_path().as_os_str().is_empty() => return entry.clone(),
+        Some(entry) => entry
+            .parent()
+            .expect("Entrypoint does not have a parent")
+            .join(components.as_path()),
+        // Modules are resolved relative to the importing file
+        None => importer
+            .parent()
+            .expect("File does not have a parent")
+            .join(PathBuf::from(import)),
+    };
+
+    if import_path.is_dir() {
+        import_path = import_path.join("module.sb");
+    } else if !import_path.ends_with(".sb") {
+        import_path.set_extension("sb");
+    }
+
+    normalize(&import_path)
+}
+
+/// Removes `.` and `..` components from a path without accessing the file system,
+/// so that every module is identified by a single path
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
 
 pub struct Builder {
     in_file: PathBuf,
+    /// Sources of modules that are not read from disk
+    files: HashMap<PathBuf, String>,
     modules: Vec<Module>,
+    /// File path -> source mappings, used to render diagnostics
+    sources: HashMap<String, String>,
+    /// File path -> contents of the files embedded with `include_str!`
+    embedded: HashMap<String, String>,
+    options: BuildOptions,
+    cache: Option<Cache>,
+    /// Warnings reported while building
+    diagnostics: Vec<Diagnostic>,
+    /// TypeScript declarations of the generated JavaScript, if they were requested
+    declarations: Option<String>,
+    /// Paths of the modules of the standard library
+    stdlib: HashSet<String>,
+    timings: Timings,
 }
 
 impl Builder {
-    pub fn new(entrypoint: PathBuf) -> Self {
+    pub fn new(entrypoint: PathBuf, options: BuildOptions) -> Self {
+        let timings = match options.timings {
+            Some(_) => Timings::new(),
+            None => Timings::disabled(),
+        };
         Self {
             in_file: entrypoint,
+            files: HashMap::new(),
             modules: Vec::new(),
+            sources: HashMap::new(),
+            embedded: HashMap::new(),
+            cache: options.cache.as_deref().map(Cache::new),
+            options,
+            diagnostics: Vec::new(),
+            declarations: None,
+            stdlib: HashSet::new(),
+            timings,
         }
     }
 
-    fn get_base_path(&self) -> Result<PathBuf, String> {
-        Ok(self
-            .in_file
-            .parent()
-            .ok_or("File does not have a parent")?
-            .to_path_buf())
+    /// Uses the given source instead of reading the entrypoint from disk.
+    /// Imports are still resolved relative to the path of the entrypoint.
+    pub fn with_source(self, source: String) -> Self {
+        let path = self.in_file.clone();
+        self.with_file(path, source)
     }
 
-    pub fn build(&mut self, target: &Target) -> Result<(), String> {
-        let in_file = self.in_file.clone();
-        // Resolve path deltas between working directory and entrypoint
-        let base_directory = self.get_base_path()?;
+    /// Uses the given source for the module at the given path instead of reading it from disk.
+    /// This allows building programs without access to a file system.
+    pub fn with_file(mut self, path: PathBuf, source: String) -> Self {
+        self.files.insert(path, source);
+        self
+    }
+
+    /// Returns the TypeScript declarations of the last generated JavaScript output,
+    /// if `declarations` is set in the build options
+    pub fn declarations(&self) -> Option<&str> {
+        self.declarations.as_deref()
+    }
 
-        // During building, we change the environment directory.
-        // After we're done, we have to set it back to the initial directory.
-        let initial_directory = env::current_dir().expect("Current directory does not exist");
-        if let Ok(resolved_delta) = in_file.strip_prefix(&base_directory) {
-            // TODO: This error could probably be handled better
-            let _ = env::set_current_dir(base_directory);
-            self.in_file = resolved_delta.to_path_buf();
+    /// Time spent in the phases of the compiler so far, if `timings` is set in the build options
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Files embedded with `include_str!` by the last build
+    pub fn embedded_files(&self) -> Vec<PathBuf> {
+        self.embedded.keys().map(PathBuf::from).collect()
+    }
+
+    /// Warnings reported by the last build, including denied ones
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Renders a diagnostic with the source of the file it refers to
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        diagnostic.render(self.source_of(diagnostic))
+    }
+
+    /// Serializes a diagnostic into JSON, see [`Diagnostic::to_json`]
+    pub fn render_json(&self, diagnostic: &Diagnostic) -> String {
+        diagnostic.to_json(self.source_of(diagnostic))
+    }
+
+    fn source_of(&self, diagnostic: &Diagnostic) -> Option<&str> {
+        diagnostic
+            .file
+            .as_ref()
+            .and_then(|file| self.sources.get(file))
+            .map(String::as_str)
+    }
+
+    fn log(&self, message: String) {
+        if self.options.verbose {
+            eprintln!("{}", message);
+        }
+    }
+
+    pub fn build(&mut self, target: &Target) -> Result<(), String> {
+        let entry = self
+            .build_module(self.in_file.clone(), &mut Vec::new())?
+            .path;
+        // The prelude of the project is imported by every module, unless a module already imports it
+        if let Some(prelude) = self.options.prelude.clone() {
+            if !self.modules.iter().any(|m| Path::new(&m.path) == prelude) {
+                self.build_module(prelude, &mut Vec::new())?;
+            }
         }
-        self.build_module(self.in_file.clone(), &mut Vec::new())?;
+        // Only user code is checked, so this has to happen before the stdlib is added
+        let started = self.timings.start();
+        self.check_warnings()?;
+        self.timings.stop("Warnings", None, started);
+        // The json module of the stdlib is only part of the prelude of the JS and QBE backends
+        let json_unavailable = if self.options.no_prelude {
+            Some("with --no-prelude")
+        } else if *target == Target::C {
+            Some("for the C backend")
+        } else {
+            None
+        };
+        let modules = &mut self.modules;
+        self.timings.time("Deriving", || {
+            derive::derive_functions(modules, json_unavailable)
+        })?;
+        // Symbols of QBE are qualified with dots, which mangling keeps
+        let separator = if *target == Target::Qbe { "." } else { "_" };
+        let roots = self.symbol_roots();
+        let packages = &self.options.packages;
+        let modules = &mut self.modules;
+        self.timings.time("Name resolution", || {
+            namespace::resolve_namespaces(modules, &entry, &roots, packages, separator)
+        })?;
+        let modules = &self.modules;
+        self.timings
+            .time("Visibility", || semantic::check_visibility(modules))?;
+        // Functions of the stdlib are not mangled, so that code generated by the compiler can call them
+        if *target == Target::Qbe {
+            let modules = &mut self.modules;
+            self.timings.time("Mangling", || {
+                mangle::mangle_symbols(modules, &entry, &roots)
+            })?;
+        }
+        match target {
+            // The native runtime implements the platform dependent builtins of the stdlib
+            Target::JS | Target::Qbe | Target::C => self.build_stdlib(target),
+            _ => Ok(()),
+        }
+    }
 
-        // Append standard library
-        if matches!(target, Target::JS) {
-            self.build_stdlib()?;
+    /// Returns the directories of the entrypoint and of packages, with the names
+    /// that prefix the symbols of their modules
+    fn symbol_roots(&self) -> Vec<(String, PathBuf)> {
+        let entry_dir = if self.in_file.is_dir() {
+            self.in_file.clone()
+        } else {
+            self.in_file.parent().unwrap_or(Path::new("")).to_path_buf()
+        };
+        let mut roots = vec![(String::new(), entry_dir)];
+        for (name, entry) in self.options.packages.iter() {
+            if let Some(dir) = entry.parent() {
+                roots.push((name.clone(), dir.to_path_buf()));
+            }
         }
+        roots
+    }
+
+    /// Collects the warnings of all modules
+    fn check_warnings(&mut self) -> Result<(), String> {
+        self.log(format!("Checking {} modules", self.modules.len()));
+        // Code of packages is not checked, only the one of the project itself
+        let package_dirs: Vec<&Path> = self
+            .options
+            .packages
+            .values()
+            .filter_map(|entry| entry.parent())
+            .collect();
+        self.diagnostics = warnings::check(
+            &self.modules,
+            &self.options.warnings,
+            &self.options.packages,
+        )
+        .into_iter()
+        .filter(|d| match &d.file {
+            Some(file) => !package_dirs
+                .iter()
+                .any(|dir| Path::new(file).starts_with(dir)),
+            None => true,
+        })
+        .collect();
 
-        // Change back to the initial directory
-        env::set_current_dir(initial_directory).expect("Could not set current directory");
+        let errors = self
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            return Err(format!(
+                "Aborting due to {} denied warning{}",
+                errors,
+                if errors == 1 { "" } else { "s" }
+            ));
+        }
         Ok(())
     }
 
+    /// Builds the module at the given path, after the modules it imports.
+    /// `importers` are the modules whose imports lead to this one, with the position of the import.
     fn build_module(
         &mut self,
         file_path: PathBuf,
-        seen: &mut Vec<String>,
+        importers: &mut Vec<(String, Position)>,
     ) -> Result<Module, String> {
-        // TODO: This method can probably cleaned up quite a bit
-
         // In case the module is a directory, we have to append the filename of the entrypoint
         let resolved_file_path = if file_path.is_dir() {
             file_path.join("module.sb")
         } else {
             file_path
         };
-        let mut file = File::open(&resolved_file_path)
-            .map_err(|_| format!("Could not open file: {}", resolved_file_path.display()))?;
-        let mut contents = String::new();
-
-        file.read_to_string(&mut contents)
-            .expect("Could not read file");
-        let tokens = lexer::tokenize(&contents)?;
-        let module = parser::parse(
-            tokens,
-            Some(contents),
-            resolved_file_path.display().to_string(),
-        )?;
-        for import in &module.imports {
-            // Prevent circular imports
-            if seen.contains(import) {
-                continue;
+        let contents = match self.files.get(&resolved_file_path) {
+            Some(source) => source.clone(),
+            None => {
+                let mut file = File::open(&resolved_file_path).map_err(|_| {
+                    format!("Could not open file: {}", resolved_file_path.display())
+                })?;
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).map_err(|e| {
+                    format!(
+                        "Could not read file {}: {}",
+                        resolved_file_path.display(),
+                        e
+                    )
+                })?;
+                // Source files are UTF-8, which makes every string literal valid UTF-8
+                String::from_utf8(bytes).map_err(|e| {
+                    let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+                    let line = valid.iter().filter(|&&b| b == b'\n').count() + 1;
+                    format!(
+                        "{}:{}: File is not valid UTF-8",
+                        resolved_file_path.display(),
+                        line
+                    )
+                })?
+            }
+        };
+        let path = resolved_file_path.display().to_string();
+        let mut module = self.parse(&path, &contents)?;
+        // Embedded files are read on every build, since cached modules only depend on their source
+        if contents.contains("include_str!") {
+            embed::embed_files(&mut module, &mut |file, location| {
+                self.read_embedded(&resolved_file_path, file, location)
+            })?;
+        }
+        self.sources.insert(path.clone(), contents);
+
+        let mut imports: Vec<(&String, &Position)> = module.imports.iter().collect();
+        imports.sort_by_key(|(_, pos)| pos.raw);
+        for (import, pos) in imports {
+            let import_path = resolve_import(&resolved_file_path, import, &self.options.packages);
+            let import_path = if import_path.is_dir() {
+                import_path.join("module.sb")
             } else {
-                seen.push(import.to_string());
+                import_path
+            };
+            let target = import_path.display().to_string();
+            importers.push((path.clone(), *pos));
+            if let Some(start) = importers
+                .iter()
+                .position(|(importer, _)| *importer == target)
+            {
+                return Err(self.circular_import(&importers[start..]));
+            }
+            // Modules that are imported more than once are only built once
+            if !self.modules.iter().any(|m| m.path == target) {
+                self.build_module(import_path, importers)?;
             }
-            // Build module relative to the current file
-            let mut import_path = resolved_file_path
+            importers.pop();
+        }
+        self.modules.push(module.clone());
+        Ok(module)
+    }
+
+    /// Reads a file embedded with `include_str!`, which is resolved relative to the embedding module
+    fn read_embedded(
+        &mut self,
+        module: &Path,
+        file: &str,
+        location: &str,
+    ) -> Result<String, String> {
+        let path = normalize(
+            &module
                 .parent()
-                .unwrap()
-                .join(PathBuf::from(import));
+                .expect("File does not have a parent")
+                .join(file),
+        );
+        let contents = match self.files.get(&path) {
+            Some(source) => source.clone(),
+            None => {
+                let bytes = std::fs::read(&path).map_err(|e| {
+                    format!(
+                        "{}: Could not embed file {}: {}",
+                        location,
+                        path.display(),
+                        e
+                    )
+                })?;
+                String::from_utf8(bytes).map_err(|_| {
+                    format!(
+                        "{}: Could not embed file {}, since it is not valid UTF-8",
+                        location,
+                        path.display()
+                    )
+                })?
+            }
+        };
+        self.embedded
+            .insert(path.display().to_string(), contents.clone());
+        Ok(contents)
+    }
 
-            if import_path.is_dir() {
-                import_path = import_path.join("module.sb");
-            } else if !import_path.ends_with(".sb") {
-                import_path.set_extension("sb");
+    /// Describes a cycle of imports, where each module imports the next one
+    /// and the last one imports the first one
+    fn circular_import(&self, cycle: &[(String, Position)]) -> String {
+        let mut paths: Vec<&str> = cycle.iter().map(|(path, _)| path.as_str()).collect();
+        paths.push(&cycle[0].0);
+        let mut message = format!("Circular import: {}", paths.join(" -> "));
+        for (path, pos) in cycle {
+            message += &format!("\n --> {}:{}:{}", path, pos.line, pos.offset);
+            if let Some(source) = self.sources.get(path) {
+                message += "\n";
+                message += &highlight_position_in_file(source.clone(), *pos);
             }
+        }
+        message
+    }
 
-            self.build_module(import_path, seen)?;
+    /// Parses a module, unless it is found in the cache
+    fn parse(&mut self, path: &str, contents: &str) -> Result<Module, String> {
+        let started = self.timings.start();
+        if let Some(module) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.load_module(path, contents))
+        {
+            self.log(format!("Using cached {}", path));
+            self.timings.stop("Reading cache", Some(path), started);
+            return Ok(module);
+        }
+
+        self.log(format!("Parsing {}", path));
+        let tokens = lexer::tokenize(contents)?;
+        self.timings.stop("Lexing", Some(path), started);
+        let started = self.timings.start();
+        let module = parser::parse(tokens, Some(contents.to_string()), path.to_string())?;
+        self.timings.stop("Parsing", Some(path), started);
+        if let Some(cache) = &self.cache {
+            cache.store_module(contents, &module);
         }
-        self.modules.push(module.clone());
         Ok(module)
     }
 
-    pub(crate) fn generate(
+    /// Identifies the generated output by everything it depends on
+    fn output_key(&self, target: &Target) -> u64 {
+        let mut inputs = vec![
+            target.to_string(),
+            self.options.opt_level.to_string(),
+            self.options.test.to_string(),
+            self.options.bench.to_string(),
+            self.options.debug.to_string(),
+            self.options.overflow_checks.to_string(),
+            self.options.release.to_string(),
+            format!("{:?}", self.options.module_format),
+            self.options.js_runtime.to_string(),
+            format!("{:?}", self.options.arch),
+            self.options.keep_all.to_string(),
+        ];
+        for module in &self.modules {
+            inputs.push(module.path.clone());
+            inputs.push(self.sources.get(&module.path).cloned().unwrap_or_default());
+        }
+        let mut embedded: Vec<(&String, &String)> = self.embedded.iter().collect();
+        embedded.sort();
+        for (path, contents) in embedded {
+            inputs.push(path.clone());
+            inputs.push(contents.clone());
+        }
+        cache::hash(&inputs)
+    }
+
+    pub fn generate(
         &mut self,
         target: &Target,
         buffer: &mut Box<impl Write>,
     ) -> Result<(), String> {
+        let key = self.output_key(target);
+        let started = self.timings.start();
+        // Only the output is cached, not the declarations
+        let cached = if self.options.declarations {
+            None
+        } else {
+            self.cache.as_ref().and_then(|cache| cache.load_output(key))
+        };
+        if let Some(output) = cached {
+            self.log("Using cached output".into());
+            self.timings.stop("Reading cache", None, started);
+            buffer.write_all(&output).expect("write failed");
+            return buffer.flush().map_err(|_| "Could not flush file".into());
+        }
+
+        let stdlib_functions: HashSet<String> = self
+            .modules
+            .iter()
+            .filter(|module| self.stdlib.contains(&module.path))
+            .flat_map(|module| module.func.iter().map(|func| func.name.clone()))
+            .collect();
+
+        let started = self.timings.start();
         let mut mod_iter = self.modules.iter();
 
         // TODO: We shouldn't clone here
@@ -132,9 +560,68 @@ impl Builder {
         for module in mod_iter {
             condensed.merge_with(module.clone());
         }
+        self.timings.stop("Merging modules", None, started);
+
+        let started = self.timings.start();
+        attributes::check(&condensed)?;
+        select_target_code(&mut condensed, target)?;
+        check_inline_code(&condensed, target)?;
+        check_unsafe_code(&condensed, target)?;
+        check_js_imports(&mut condensed, target)?;
+        check_native_builtins(&condensed, target, &stdlib_functions)?;
+        if self.options.test {
+            generate_test_harness(&mut condensed)?;
+        }
+        if self.options.bench {
+            generate_bench_harness(&mut condensed)?;
+        }
+        // JavaScript engines already turn stack overflows into exceptions
+        if self.options.debug && *target == Target::Qbe {
+            limit_recursion_depth(&mut condensed);
+        }
+        self.timings.stop("Lowering", None, started);
+
+        let started = self.timings.start();
+        semantic::analyze(&mut condensed);
+        semantic::check(&mut condensed)?;
+        self.timings.stop("Semantic analysis", None, started);
+        if self.options.declarations && *target == Target::JS {
+            let format = self.options.module_format;
+            self.declarations = Some(self.timings.time("Declarations", || {
+                generator::typescript::generate_declarations(&condensed, format)
+            }));
+        }
+        self.timings.time("Compile-time evaluation", || {
+            optimizer::evaluate_comptime(&mut condensed)
+        })?;
+        let started = self.timings.start();
+        if self.options.overflow_checks {
+            semantic::lower_overflow_checks(&mut condensed);
+        }
+        // Arrays of the C backend do not store their length
+        if !self.options.release && matches!(target, Target::JS | Target::Qbe) {
+            semantic::lower_index_checks(&mut condensed);
+        }
+        self.timings.stop("Runtime checks", None, started);
+        if self.options.opt_level > 0 {
+            self.log(format!("Optimizing with level {}", self.options.opt_level));
+        }
+        let mut condensed =
+            optimizer::optimize_timed(condensed, self.options.opt_level, &mut self.timings);
+        if !self.options.keep_all {
+            self.timings.time("Unused declarations", || {
+                optimizer::remove_unused_declarations(&mut condensed, &stdlib_functions)
+            });
+        }
 
+        self.log(format!("Generating {} code", target));
+        let started = self.timings.start();
         let output = match target {
-            Target::JS => generator::js::JsGenerator::generate(condensed)?,
+            Target::JS => generator::js::JsGenerator::generate_module(
+                condensed,
+                self.options.module_format,
+                self.options.js_runtime,
+            )?,
             Target::C => generator::c::CGenerator::generate(condensed)?,
             Target::Llvm => {
                 #[cfg(not(feature = "llvm"))]
@@ -143,28 +630,552 @@ impl Builder {
                 #[cfg(feature = "llvm")]
                 generator::llvm::LLVMGenerator::generate(condensed)?
             }
-            Target::Qbe => generator::qbe::QbeGenerator::generate(condensed)?,
-            Target::X86 => generator::x86::X86Generator::generate(condensed)?,
+            Target::Qbe => generator::qbe::QbeGenerator::generate_for(
+                condensed,
+                self.options.arch.unwrap_or_else(Arch::host),
+                self.options.opt_level,
+            )?,
+            Target::X86 => match self.options.arch {
+                Some(arch) if arch != Arch::Amd64 => {
+                    return Err(format!(
+                        "The x86 target can not compile for {}, only for amd64",
+                        arch
+                    ))
+                }
+                _ => generator::x86::X86Generator::generate(condensed)?,
+            },
         };
+        self.timings
+            .stop(&format!("Code generation ({})", target), None, started);
 
+        if let Some(cache) = &self.cache {
+            cache.store_output(key, output.as_bytes());
+        }
         buffer.write_all(output.as_bytes()).expect("write failed");
         buffer.flush().map_err(|_| "Could not flush file".into())
     }
 
-    fn build_stdlib(&mut self) -> Result<(), String> {
+    /// Modules of the program that have been built, without the standard library
+    pub fn program_modules(&self) -> Vec<&Module> {
+        self.modules
+            .iter()
+            .filter(|module| !self.stdlib.contains(&module.path))
+            .collect()
+    }
+
+    /// Parses the modules of the stdlib that the target can compile. All modules except for the
+    /// builtins form the prelude, which is available to every module without importing it.
+    /// The C backend can not compile the string operations of the prelude yet, so it only gets the builtins.
+    fn build_stdlib(&mut self, target: &Target) -> Result<(), String> {
         let assets = Lib::iter();
 
         for file in assets {
+            if file != BUILTIN_MODULE && (self.options.no_prelude || *target == Target::C) {
+                continue;
+            }
             let stdlib_raw =
                 Lib::get(&file).expect("Standard library not found. This should not occur.");
             let stblib_str =
                 std::str::from_utf8(&stdlib_raw).expect("Could not interpret standard library.");
-            let stdlib_tokens = lexer::tokenize(&stblib_str)?;
-            let module = parser::parse(stdlib_tokens, Some(stblib_str.into()), file.to_string())
-                .expect("Could not parse stdlib");
+            let module = self.parse(&file, stblib_str).map_err(|e| {
+                format!(
+                    "Could not parse module '{}' of the standard library: {}",
+                    file, e
+                )
+            })?;
+            self.stdlib.insert(module.path.clone());
             self.modules.push(module);
         }
 
         Ok(())
     }
 }
+
+/// Module of the stdlib with the builtins that are written in Antimony, shared by all backends
+const BUILTIN_MODULE: &str = "builtin.sb";
+
+/// Maximum number of nested function calls of debug builds
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Name of the global that counts nested function calls of debug builds
+const CALL_DEPTH_SYMBOL: &str = "_sb_call_depth";
+
+/// Counts the nested calls of all functions in a global, and panics once the depth
+/// exceeds [`MAX_CALL_DEPTH`], before the native stack could overflow
+fn limit_recursion_depth(module: &mut Module) {
+    let depth = || Box::new(Expression::Variable(CALL_DEPTH_SYMBOL.to_owned()));
+    for func in &mut module.func {
+        let prologue = vec![
+            Statement::Exp(Expression::BinOp(
+                depth(),
+                BinOp::AddAssign,
+                Box::new(Expression::Int(1)),
+            )),
+            Statement::If(
+                Expression::BinOp(
+                    depth(),
+                    BinOp::GreaterThan,
+                    Box::new(Expression::Int(MAX_CALL_DEPTH)),
+                ),
+                Box::new(Statement::Exp(Expression::FunctionCall(
+                    "_panic".to_owned(),
+                    vec![
+                        Expression::Str(format!(
+                            "maximum call depth of {} exceeded",
+                            MAX_CALL_DEPTH
+                        )),
+                        Expression::Str(format!("function '{}'", func.name)),
+                    ],
+                ))),
+                None,
+            ),
+            Statement::Defer(Expression::BinOp(
+                depth(),
+                BinOp::SubtractAssign,
+                Box::new(Expression::Int(1)),
+            )),
+        ];
+        if let Statement::Block(statements, _) = &mut func.body {
+            statements.splice(0..0, prologue);
+        }
+    }
+    module.globals.push(Global {
+        var: Variable {
+            name: CALL_DEPTH_SYMBOL.to_owned(),
+            ty: Some(Type::Int),
+            pos: None,
+            reference: false,
+        },
+        value: Expression::Int(0),
+        constant: false,
+        public: false,
+    });
+}
+
+/// Prefix of the conditions that are true when compiling to the named target, e.g. `if target_js {}`
+const TARGET_CONDITION_PREFIX: &str = "target_";
+
+/// Removes functions whose `@cfg` attribute does not match the target, and replaces
+/// `if target_<name>` statements with the branch that is taken for the target
+fn select_target_code(module: &mut Module, target: &Target) -> Result<(), String> {
+    let mut functions = Vec::with_capacity(module.func.len());
+    for func in module.func.drain(..) {
+        if is_configured(&func, target)? {
+            functions.push(func);
+        }
+    }
+    module.func = functions;
+    for def in &mut module.structs {
+        let mut methods = Vec::with_capacity(def.methods.len());
+        for method in def.methods.drain(..) {
+            if is_configured(&method, target)? {
+                methods.push(method);
+            }
+        }
+        def.methods = methods;
+    }
+
+    let methods = module.structs.iter_mut().flat_map(|def| &mut def.methods);
+    for func in module.func.iter_mut().chain(methods) {
+        select_target_branches(&mut func.body, target);
+    }
+    Ok(())
+}
+
+/// Returns true if the `@cfg` attributes of the function match the target
+fn is_configured(func: &Function, target: &Target) -> Result<bool, String> {
+    // The keys of the arguments are checked by `attributes::check`
+    for attribute in func
+        .attributes
+        .iter()
+        .filter(|attr| attr.name == attributes::CFG)
+    {
+        for (_, value) in &attribute.arguments {
+            let configured: Target = value.parse().map_err(|_| {
+                format!(
+                    "Unknown target '{}' in @cfg of function '{}'",
+                    value, func.name
+                )
+            })?;
+            if configured != *target {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Returns the target of a condition like `target_js`
+fn target_condition(name: &str) -> Option<Target> {
+    name.strip_prefix(TARGET_CONDITION_PREFIX)?.parse().ok()
+}
+
+fn select_target_branches(statement: &mut Statement, target: &Target) {
+    match statement {
+        Statement::If(Expression::Variable(name), if_branch, else_branch)
+            if target_condition(name).is_some() =>
+        {
+            let branch = if target_condition(name) == Some(*target) {
+                Some(std::mem::replace(
+                    &mut **if_branch,
+                    Statement::Block(Vec::new(), Vec::new()),
+                ))
+            } else {
+                else_branch.take().map(|branch| *branch)
+            };
+            *statement = branch.unwrap_or_else(|| Statement::Block(Vec::new(), Vec::new()));
+            select_target_branches(statement, target);
+        }
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .for_each(|s| select_target_branches(s, target)),
+        Statement::If(_, if_branch, else_branch) => {
+            select_target_branches(if_branch, target);
+            if let Some(else_branch) = else_branch {
+                select_target_branches(else_branch, target);
+            }
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(.., body)
+        | Statement::Unsafe(body) => select_target_branches(body, target),
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => select_target_branches(s, target),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rejects inline code that is written for another target than the one being compiled to
+fn check_inline_code(module: &Module, target: &Target) -> Result<(), String> {
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        if let Some(other) = foreign_inline_code(&func.body, target.name()) {
+            return Err(format!(
+                "Function '{}' contains a '{}!' block, which can not be compiled to {}",
+                func.name, other, target
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects `unsafe` blocks for JavaScript, which has no memory that raw pointers could address
+fn check_unsafe_code(module: &Module, target: &Target) -> Result<(), String> {
+    if *target != Target::JS {
+        return Ok(());
+    }
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        if func.body.has_unsafe_block() {
+            return Err(format!(
+                "Function '{}' contains an unsafe block, which can not be compiled to {}",
+                func.name, target
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Removes the bindings to JavaScript modules for other targets than JavaScript.
+/// Calling them is an error, unless the call is limited to JavaScript, e.g. by `if target_js`.
+fn check_js_imports(module: &mut Module, target: &Target) -> Result<(), String> {
+    if *target == Target::JS {
+        return Ok(());
+    }
+    let calls = optimizer::called_functions(module);
+    for func in &module.externs {
+        if let Some(js_module) = &func.module {
+            if calls.contains(&func.name) {
+                return Err(format!(
+                    "Function '{}' of JavaScript module '{}' is not available for target {}",
+                    func.name, js_module, target
+                ));
+            }
+        }
+    }
+    module.externs.retain(|func| func.module.is_none());
+    Ok(())
+}
+
+/// Builtins that only the native runtime implements, since JavaScript has no shared memory
+const NATIVE_BUILTINS: [&str; 11] = [
+    "_atomic_new",
+    "_atomic_load",
+    "_atomic_store",
+    "_atomic_add",
+    "_atomic_compare_exchange",
+    "_mutex_lock",
+    "_mutex_try_lock",
+    "_mutex_unlock",
+    "_rwlock_read",
+    "_rwlock_write",
+    "_rwlock_unlock",
+];
+
+/// Rejects the atomics and locks of `lib/sync.sb` for JavaScript. Functions of the standard
+/// library are only checked if the program calls them, since unused ones are removed.
+fn check_native_builtins(
+    module: &Module,
+    target: &Target,
+    stdlib: &HashSet<String>,
+) -> Result<(), String> {
+    if *target != Target::JS {
+        return Ok(());
+    }
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    let functions: Vec<&Function> = module.func.iter().chain(methods).collect();
+    let calls: HashMap<&str, Vec<String>> = functions
+        .iter()
+        .map(|func| (func.name.as_str(), optimizer::function_calls(func)))
+        .collect();
+    let mut visited = HashSet::new();
+    for func in functions.iter().filter(|func| !stdlib.contains(&func.name)) {
+        for call in &calls[func.name.as_str()] {
+            if calls_native_builtin(call, &calls, &mut visited) {
+                return Err(format!(
+                    "Function '{}' calls '{}', which needs the native runtime and is not available for target {}",
+                    func.name, call, target
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the function is a native builtin or calls one, directly or indirectly
+fn calls_native_builtin<'a>(
+    name: &'a str,
+    calls: &'a HashMap<&str, Vec<String>>,
+    visited: &mut HashSet<&'a str>,
+) -> bool {
+    if NATIVE_BUILTINS.contains(&name) {
+        return true;
+    }
+    // Functions that have been visited before do not call any
+    visited.insert(name)
+        && calls.get(name).is_some_and(|callees| {
+            callees
+                .iter()
+                .any(|callee| calls_native_builtin(callee, calls, visited))
+        })
+}
+
+/// Returns the target of the first inline code in the statement that is not written for `target`
+fn foreign_inline_code<'a>(statement: &'a Statement, target: &str) -> Option<&'a str> {
+    match statement {
+        Statement::Inline(other, _) if other != target => Some(other),
+        Statement::Block(statements, _) => statements
+            .iter()
+            .find_map(|s| foreign_inline_code(s, target)),
+        Statement::If(_, if_branch, else_branch) => {
+            foreign_inline_code(if_branch, target).or_else(|| {
+                else_branch
+                    .as_deref()
+                    .and_then(|s| foreign_inline_code(s, target))
+            })
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(.., body)
+        | Statement::Unsafe(body) => foreign_inline_code(body, target),
+        Statement::Match(_, arms) => arms.iter().find_map(|arm| match arm {
+            MatchArm::Case(_, s) | MatchArm::Else(s) => foreign_inline_code(s, target),
+        }),
+        _ => None,
+    }
+}
+
+/// Replaces the main function of the module with one that calls
+/// every function annotated with `@test`. Async tests are awaited.
+fn generate_test_harness(module: &mut Module) -> Result<(), String> {
+    let mut tests = Vec::new();
+    for func in module
+        .func
+        .iter()
+        .filter(|f| f.has_attribute(attributes::TEST))
+    {
+        if !func.arguments.is_empty() {
+            return Err(format!(
+                "Test function '{}' must not take any arguments",
+                func.name
+            ));
+        }
+        tests.push((func.name.clone(), func.is_async));
+    }
+    module.func.retain(|f| f.name != "main");
+
+    let call = |name: &str, message: String| {
+        Statement::Exp(Expression::FunctionCall(
+            name.to_owned(),
+            vec![Expression::Str(message)],
+        ))
+    };
+    let mut body = vec![call("println", format!("running {} tests", tests.len()))];
+    for (test, is_async) in &tests {
+        body.push(call("print", format!("test {} ... ", test)));
+        let test_call = Expression::FunctionCall(test.clone(), Vec::new());
+        body.push(Statement::Exp(if *is_async {
+            Expression::Await(Box::new(test_call))
+        } else {
+            test_call
+        }));
+        body.push(call("println", "ok".to_owned()));
+    }
+    body.push(call(
+        "println",
+        format!("\ntest result: ok. {} passed", tests.len()),
+    ));
+
+    module.func.push(Function {
+        name: "main".to_owned(),
+        arguments: Vec::new(),
+        body: Statement::Block(body, Vec::new()),
+        ret_type: None,
+        attributes: Vec::new(),
+        public: false,
+        is_async: tests.iter().any(|(_, is_async)| *is_async),
+    });
+    Ok(())
+}
+
+/// Microseconds a benchmark is run for before it is measured
+const BENCH_WARMUP_MICROS: usize = 20_000;
+
+/// Microseconds a benchmark is at least measured for
+const BENCH_MEASURE_MICROS: usize = 100_000;
+
+/// Replaces the main function with one that measures all `@bench` functions.
+/// A benchmark is run repeatedly to warm up, then its number of iterations is doubled
+/// until they take long enough to be measured. For each benchmark, a line of the form
+/// `bench <name> <microseconds> <iterations>` is printed.
+fn generate_bench_harness(module: &mut Module) -> Result<(), String> {
+    let mut benches = Vec::new();
+    for func in module
+        .func
+        .iter()
+        .filter(|f| f.has_attribute(attributes::BENCH))
+    {
+        if !func.arguments.is_empty() {
+            return Err(format!(
+                "Benchmark function '{}' must not take any arguments",
+                func.name
+            ));
+        }
+        if func.is_async {
+            return Err(format!(
+                "Benchmark function '{}' can not be async",
+                func.name
+            ));
+        }
+        benches.push(func.name.clone());
+    }
+    module.func.retain(|f| f.name != "main");
+
+    let var = |name: &str| Box::new(Expression::Variable(name.to_owned()));
+    let int = |value: usize| Box::new(Expression::Int(value));
+    let now = || {
+        Box::new(Expression::FunctionCall(
+            "_now_micros".to_owned(),
+            Vec::new(),
+        ))
+    };
+    let declare = |name: &str, value: Box<Expression>| {
+        Statement::Declare(
+            Variable {
+                name: name.to_owned(),
+                ty: Some(Type::Int),
+                pos: None,
+                reference: false,
+            },
+            Some(*value),
+        )
+    };
+    let since_start = || Box::new(Expression::BinOp(now(), BinOp::Subtraction, var("start")));
+
+    let mut main = Vec::new();
+    for (i, bench) in benches.iter().enumerate() {
+        let run = Statement::Exp(Expression::FunctionCall(bench.clone(), Vec::new()));
+        // Functions of imported modules are qualified with their module when compiling to QBE
+        let name = bench.rsplit('.').next().unwrap_or(bench);
+        let body = vec![
+            declare("start", now()),
+            Statement::While(
+                Expression::BinOp(since_start(), BinOp::LessThan, int(BENCH_WARMUP_MICROS)),
+                Box::new(Statement::Block(vec![run.clone()], Vec::new())),
+            ),
+            declare("iterations", int(1)),
+            declare("elapsed", int(0)),
+            Statement::While(
+                Expression::BinOp(var("elapsed"), BinOp::LessThan, int(BENCH_MEASURE_MICROS)),
+                Box::new(Statement::Block(
+                    vec![
+                        Statement::Exp(Expression::BinOp(
+                            var("iterations"),
+                            BinOp::MultiplyAssign,
+                            int(2),
+                        )),
+                        declare("i", int(0)),
+                        Statement::Assign(var("start"), now()),
+                        Statement::While(
+                            Expression::BinOp(var("i"), BinOp::LessThan, var("iterations")),
+                            Box::new(Statement::Block(
+                                vec![
+                                    run,
+                                    Statement::Exp(Expression::BinOp(
+                                        var("i"),
+                                        BinOp::AddAssign,
+                                        int(1),
+                                    )),
+                                ],
+                                Vec::new(),
+                            )),
+                        ),
+                        Statement::Assign(var("elapsed"), since_start()),
+                    ],
+                    Vec::new(),
+                )),
+            ),
+            Statement::Exp(Expression::FunctionCall(
+                "println".to_owned(),
+                vec![Expression::FunctionCall(
+                    "format".to_owned(),
+                    vec![
+                        Expression::Str(format!("bench {} %d %d", name)),
+                        *var("elapsed"),
+                        *var("iterations"),
+                    ],
+                )],
+            )),
+        ];
+        let runner = format!("_bench_{}", i);
+        main.push(Statement::Exp(Expression::FunctionCall(
+            runner.clone(),
+            Vec::new(),
+        )));
+        module.func.push(Function {
+            name: runner,
+            arguments: Vec::new(),
+            body: Statement::Block(body, Vec::new()),
+            ret_type: None,
+            attributes: Vec::new(),
+            public: false,
+            is_async: false,
+        });
+    }
+
+    module.func.push(Function {
+        name: "main".to_owned(),
+        arguments: Vec::new(),
+        body: Statement::Block(main, Vec::new()),
+        ret_type: None,
+        attributes: Vec::new(),
+        public: false,
+        is_async: false,
+    });
+    Ok(())
+}
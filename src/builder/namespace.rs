@@ -0,0 +1,231 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::mangle::{qualifier, rename_calls, rename_calls_expression};
+use super::resolve_import;
+use crate::ast::attributes::{EXPORT, NO_MANGLE};
+use crate::ast::*;
+use crate::lexer::Position;
+use crate::package::Packages;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Resolves calls through the alias of an import, e.g. `math::sqrt(x)`, to the function
+/// of the imported module, and calls of functions that are selected by an import,
+/// e.g. `sqrt` of `import { sqrt } from "math"`.
+///
+/// Functions that more than one module declares are renamed to symbols that are qualified
+/// by their module, e.g. `lib_math_sqrt` if `separator` is `_`, so that they do not collide
+/// once the modules are merged.
+/// Functions of the `entry` module, `main` and functions annotated with `@no_mangle` or `@export`
+/// keep their name. Unqualified calls of such a function are resolved to the function of the
+/// calling module if it declares one, and to the only public one otherwise.
+pub(super) fn resolve_namespaces(
+    modules: &mut [Module],
+    entry: &str,
+    roots: &[(String, PathBuf)],
+    packages: &Packages,
+    separator: &str,
+) -> Result<(), String> {
+    check_selections(modules, packages)?;
+
+    // Name -> paths of the modules that declare a function with the name
+    let mut declared: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for module in modules.iter() {
+        for func in &module.func {
+            declared.entry(&func.name).or_default().insert(&module.path);
+        }
+    }
+
+    // (module, name) -> symbol of each function
+    let mut symbols: HashMap<(String, String), String> = HashMap::new();
+    // (module, name) of functions that are declared with `pub`
+    let mut exported: HashSet<(String, String)> = HashSet::new();
+    // Name -> symbol of public functions, `None` if several modules declare the name
+    let mut public: HashMap<String, Option<String>> = HashMap::new();
+    // (module, name) of extern functions, which can not be renamed
+    let mut externs: HashSet<(String, String)> = HashSet::new();
+    for module in modules.iter() {
+        for func in &module.func {
+            let symbol = if declared[func.name.as_str()].len() == 1
+                || module.path == entry
+                || func.name == "main"
+                || func.has_attribute(NO_MANGLE)
+                || func.has_attribute(EXPORT)
+            {
+                func.name.clone()
+            } else {
+                let qualifier = qualifier(Path::new(&module.path), roots).replace('.', separator);
+                format!("{}{}{}", qualifier, separator, func.name)
+            };
+            if func.public {
+                exported.insert((module.path.clone(), func.name.clone()));
+                public
+                    .entry(func.name.clone())
+                    .and_modify(|existing| {
+                        if existing.as_deref() != Some(symbol.as_str()) {
+                            *existing = None
+                        }
+                    })
+                    .or_insert_with(|| Some(symbol.clone()));
+            }
+            symbols.insert((module.path.clone(), func.name.clone()), symbol);
+        }
+        for func in &module.externs {
+            externs.insert((module.path.clone(), func.name.clone()));
+        }
+    }
+    let colliding: HashSet<String> = declared
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(name, _)| name.to_owned())
+        .collect();
+
+    for module in modules.iter_mut() {
+        let path = module.path.clone();
+        let aliases: HashMap<&str, String> = module
+            .aliases
+            .iter()
+            .map(|(alias, import)| {
+                let target = resolve_import(Path::new(&path), import, packages);
+                (alias.as_str(), target.display().to_string())
+            })
+            .collect();
+
+        let selected: HashMap<&str, String> = module
+            .selected
+            .iter()
+            .map(|(name, (import, _))| {
+                let target = resolve_import(Path::new(&path), import, packages);
+                (name.as_str(), target.display().to_string())
+            })
+            .collect();
+
+        let mut error = None;
+        let mut resolve = |name: &mut String| {
+            let resolved = match name.split_once("::") {
+                // math::sqrt
+                Some((alias, func)) => match aliases.get(alias) {
+                    Some(target) => {
+                        let key = (target.clone(), func.to_owned());
+                        match symbols.get(&key) {
+                            Some(_) if *target != path && !exported.contains(&key) => {
+                                Err(format!(
+                                    "Function '{}' is private to module '{}' and can not be used by module '{}'. Declare it with `pub` to use it in other modules",
+                                    func, target, path
+                                ))
+                            }
+                            Some(symbol) => Ok(symbol.clone()),
+                            None if externs.contains(&key) => Ok(func.to_owned()),
+                            None => Err(format!(
+                                "Module '{}' does not declare a function '{}'",
+                                target, func
+                            )),
+                        }
+                    }
+                    None => Err(format!(
+                        "Unknown import alias '{}' in module '{}'",
+                        alias, path
+                    )),
+                },
+                None => match symbols.get(&(path.clone(), name.clone())).or_else(|| {
+                    let target = selected.get(name.as_str())?;
+                    symbols.get(&(target.clone(), name.clone()))
+                }) {
+                    Some(symbol) => Ok(symbol.clone()),
+                    None if colliding.contains(name.as_str()) => match public.get(name.as_str()) {
+                        Some(Some(symbol)) => Ok(symbol.clone()),
+                        _ => Err(format!(
+                            "Function '{}' called in module '{}' is declared by more than one module, call it through the alias of an import",
+                            name, path
+                        )),
+                    },
+                    None => return,
+                },
+            };
+            match resolved {
+                Ok(symbol) => *name = symbol,
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        };
+        for global in &mut module.globals {
+            rename_calls_expression(&mut global.value, &mut resolve);
+        }
+        let methods = module.structs.iter_mut().flat_map(|def| &mut def.methods);
+        for func in module.func.iter_mut().chain(methods) {
+            rename_calls(&mut func.body, &mut resolve);
+        }
+        if let Some(error) = error {
+            return Err(error);
+        }
+        for func in &mut module.func {
+            func.name = symbols[&(path.clone(), func.name.clone())].clone();
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the names selected by imports are declared by the imported modules,
+/// and that they are public
+fn check_selections(modules: &[Module], packages: &Packages) -> Result<(), String> {
+    for module in modules {
+        let mut selected: Vec<(&String, &(String, Position))> = module.selected.iter().collect();
+        selected.sort_by_key(|(_, (_, pos))| pos.raw);
+        for (name, (import, _)) in selected {
+            let target = resolve_import(Path::new(&module.path), import, packages)
+                .display()
+                .to_string();
+            let Some(imported) = modules.iter().find(|m| m.path == target) else {
+                continue;
+            };
+            let declaration = imported
+                .func
+                .iter()
+                .map(|f| ("Function", &f.name, f.public))
+                .chain(imported.externs.iter().map(|f| ("Function", &f.name, true)))
+                .chain(
+                    imported
+                        .structs
+                        .iter()
+                        .map(|d| ("Struct", &d.name, d.public)),
+                )
+                .chain(
+                    imported
+                        .globals
+                        .iter()
+                        .map(|g| ("Global variable", &g.var.name, g.public)),
+                )
+                .find(|(_, declared, _)| *declared == name);
+            match declaration {
+                Some((_, _, true)) => {}
+                Some((kind, _, false)) => {
+                    return Err(format!(
+                        "{} '{}' is private to module '{}' and can not be used by module '{}'. Declare it with `pub` to use it in other modules",
+                        kind, name, target, module.path
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "Module '{}' does not declare '{}', which is imported by module '{}'",
+                        target, name, module.path
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
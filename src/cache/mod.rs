@@ -0,0 +1,121 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::Module;
+use lazy_static::lazy_static;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Version of the format of cached entries. Has to be increased whenever the syntax tree
+/// or the generated output changes, so that entries of older compilers are not read.
+const FORMAT_VERSION: u32 = 2;
+
+lazy_static! {
+    /// Size and modification time of the running compiler, so that entries written by another
+    /// build of the same version, e.g. during development, are not used either.
+    /// Unknown if the compiler does not run from an executable, like in WebAssembly.
+    static ref EXECUTABLE: Option<(u64, u128)> = env::current_exe()
+        .and_then(fs::metadata)
+        .ok()
+        .and_then(|meta| {
+            let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+            Some((meta.len(), modified.as_nanos()))
+        });
+}
+
+#[cfg(test)]
+mod tests;
+
+/// On-disk storage for parsed modules, generated programs and the compiled runtime.
+/// Entries are keyed by a hash of their inputs and the compiler that wrote them,
+/// so they never have to be invalidated. Entries that can not be read or deserialized
+/// are ignored, like entries that do not exist.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Returns the module parsed from the given source, if it was cached before
+    pub fn load_module(&self, path: &str, source: &str) -> Option<Module> {
+        let raw = fs::read(self.module_path(path, source)).ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    pub fn store_module(&self, source: &str, module: &Module) {
+        if let Ok(raw) = serde_json::to_vec(module) {
+            self.write(&self.module_path(&module.path, source), &raw);
+        }
+    }
+
+    /// Returns the output generated for the given key, if it was cached before
+    pub fn load_output(&self, key: u64) -> Option<Vec<u8>> {
+        fs::read(self.output_path(key)).ok()
+    }
+
+    pub fn store_output(&self, key: u64, output: &[u8]) {
+        self.write(&self.output_path(key), output);
+    }
+
+    /// Returns the compiled runtime archive for the given key, if it was cached before
+    pub fn load_runtime(&self, key: u64) -> Option<Vec<u8>> {
+        fs::read(self.runtime_path(key)).ok()
+    }
+
+    pub fn store_runtime(&self, key: u64, archive: &[u8]) {
+        self.write(&self.runtime_path(key), archive);
+    }
+
+    fn module_path(&self, path: &str, source: &str) -> PathBuf {
+        let key = hash(&[path, source]);
+        self.dir.join("modules").join(format!("{:016x}.json", key))
+    }
+
+    fn output_path(&self, key: u64) -> PathBuf {
+        self.dir.join("output").join(format!("{:016x}", key))
+    }
+
+    fn runtime_path(&self, key: u64) -> PathBuf {
+        self.dir.join("runtime").join(format!("{:016x}.a", key))
+    }
+
+    /// Failing to write to the cache only makes the next build slower, so errors are ignored
+    fn write(&self, path: &Path, contents: &[u8]) {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(path, contents);
+    }
+}
+
+/// Hashes the given values together with the compiler version, the format of the entries
+/// and the executable of the compiler
+pub fn hash<T: Hash>(values: &[T]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    FORMAT_VERSION.hash(&mut hasher);
+    EXECUTABLE.hash(&mut hasher);
+    values.hash(&mut hasher);
+    hasher.finish()
+}
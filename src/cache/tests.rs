@@ -0,0 +1,95 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::Statement;
+use crate::cache::*;
+use crate::lexer::tokenize;
+use crate::parser::parse;
+use std::env;
+
+fn temp_cache(name: &str) -> (Cache, PathBuf) {
+    let dir = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    (Cache::new(&dir), dir)
+}
+
+#[test]
+fn test_cache_module() {
+    let (cache, dir) = temp_cache("sb_test_cache_module");
+    let raw = "fn main() {\n    let x = 1\n    println(x)\n}\n";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "main.sb".into()).unwrap();
+
+    assert!(cache.load_module("main.sb", raw).is_none());
+    cache.store_module(raw, &module);
+
+    let cached = cache.load_module("main.sb", raw).unwrap();
+    assert_eq!(cached.path, "main.sb");
+    assert_eq!(cached.func[0].body, module.func[0].body);
+    assert_eq!(cached.imports, module.imports);
+    match &cached.func[0].body {
+        Statement::Block(statements, _) => match &statements[0] {
+            Statement::Declare(var, _) => assert_eq!(var.pos.map(|pos| pos.line), Some(2)),
+            _ => panic!("Expected a declaration"),
+        },
+        _ => panic!("Function body should be a block"),
+    }
+
+    // Changing the source or the path invalidates the entry
+    assert!(cache.load_module("main.sb", "fn main() {}").is_none());
+    assert!(cache.load_module("other.sb", raw).is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cache_output() {
+    let (cache, dir) = temp_cache("sb_test_cache_output");
+    let key = hash(&["js", "main.sb"]);
+
+    assert!(cache.load_output(key).is_none());
+    cache.store_output(key, b"console.log(1)");
+    assert_eq!(cache.load_output(key), Some(b"console.log(1)".to_vec()));
+    assert!(cache.load_output(hash(&["qbe", "main.sb"])).is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_corrupt_entries_are_ignored() {
+    let (cache, dir) = temp_cache("sb_test_corrupt_entries_are_ignored");
+    let raw = "fn main() {}";
+    cache.write(&cache.module_path("main.sb", raw), b"not json");
+    assert!(cache.load_module("main.sb", raw).is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_entries_of_other_formats_are_ignored() {
+    let (cache, dir) = temp_cache("sb_test_entries_of_other_formats_are_ignored");
+    let raw = "fn main() {}";
+    // Valid JSON, but not a module
+    cache.write(&cache.module_path("main.sb", raw), b"{\"path\": 1}");
+    assert!(cache.load_module("main.sb", raw).is_none());
+
+    // The format version is part of every key
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    ["main.sb"].hash(&mut hasher);
+    assert_ne!(hash(&["main.sb"]), hasher.finish());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
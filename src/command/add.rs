@@ -0,0 +1,63 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::manifest::{self, Dependency, Manifest, Project, MANIFEST_FILE};
+use antimony::package;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Adds a dependency to the project in the current directory and fetches it
+pub fn add(name: &str, git: Option<String>, path: Option<PathBuf>) -> Result<(), String> {
+    if !manifest::is_valid_name(name) {
+        return Err(format!("Invalid package name '{}'", name));
+    }
+    let dependency = match (git, path) {
+        (Some(git), None) => Dependency {
+            path: None,
+            git: Some(git),
+        },
+        (None, Some(path)) => Dependency {
+            path: Some(path),
+            git: None,
+        },
+        _ => return Err("Either --git or --path has to be given".into()),
+    };
+
+    let cwd = env::current_dir().map_err(|e| format!("Could not get current directory: {}", e))?;
+    let project = Project::discover(&cwd)?
+        .ok_or_else(|| format!("Could not find {} in {}", MANIFEST_FILE, cwd.display()))?;
+    if project.manifest.dependencies.contains_key(name) {
+        return Err(format!("Dependency '{}' already exists", name));
+    }
+
+    let manifest_path = project.root.join(MANIFEST_FILE);
+    let raw = fs::read_to_string(&manifest_path)
+        .map_err(|e| format!("Could not read {}: {}", manifest_path.display(), e))?;
+    let updated = package::add_dependency(&raw, name, &dependency);
+
+    // The dependency is fetched first, so the manifest stays untouched if that fails
+    let project = Project {
+        manifest: Manifest::from_str(&updated)?,
+        ..project
+    };
+    package::resolve(&project, &package::cache_dir()?)?;
+    fs::write(&manifest_path, updated)
+        .map_err(|e| format!("Could not write {}: {}", manifest_path.display(), e))?;
+
+    println!("Added dependency '{}'", name);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::command::{build, run};
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::builder::BuildOptions;
+use antimony::generator::Target;
+use antimony::warnings::WarningOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Output;
+
+/// Time a benchmark took on one backend
+struct Measurement {
+    name: String,
+    nanos_per_iteration: f64,
+}
+
+/// Measures all functions of a program that are annotated with `@bench`,
+/// once compiled to JavaScript and once to a native executable, and compares them
+pub fn bench(in_file: &Path, mut options: BuildOptions) -> Result<(), String> {
+    options.bench = true;
+    // Benchmarks are run on this machine
+    options.arch = None;
+
+    let js = measure_js(in_file, options.clone())?;
+    // Warnings have already been reported by the JavaScript build
+    options.warnings = WarningOptions::from_flags(&[], &["all".to_owned()], false)?;
+    let native = measure_native(in_file, options)?;
+
+    println!("running {} benchmarks", js.len());
+    let width = js
+        .iter()
+        .map(|m| m.name.len())
+        .chain(["benchmark".len()])
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<width$}  {:>16}  {:>16}  {:>9}",
+        "benchmark", "js", "native", "js/native"
+    );
+    for m in &js {
+        let native = native.iter().find(|n| n.name == m.name);
+        let (time, ratio) = match native {
+            Some(n) => (
+                format_nanos(n.nanos_per_iteration),
+                format!("{:.2}x", m.nanos_per_iteration / n.nanos_per_iteration),
+            ),
+            None => ("-".to_owned(), "-".to_owned()),
+        };
+        println!(
+            "{:<width$}  {:>16}  {:>16}  {:>9}",
+            m.name,
+            format_nanos(m.nanos_per_iteration),
+            time,
+            ratio
+        );
+    }
+    Ok(())
+}
+
+fn measure_js(in_file: &Path, options: BuildOptions) -> Result<Vec<Measurement>, String> {
+    let runtime = options.js_runtime;
+    let mut buf = Box::new(Vec::new());
+    build::build_to_buffer(&Target::JS, in_file, &mut buf, options)?;
+    let output = run::execute(Target::JS, runtime, &buf, &[])?;
+    measurements(&Target::JS, output)
+}
+
+fn measure_native(in_file: &Path, options: BuildOptions) -> Result<Vec<Measurement>, String> {
+    let output = run::execute_native(in_file, &[], options)?;
+    measurements(&Target::Qbe, output)
+}
+
+/// Reads the lines of the form `bench <name> <microseconds> <iterations>`
+/// that the benchmark harness prints. Other output of the program is ignored
+fn measurements(target: &Target, output: Output) -> Result<Vec<Measurement>, String> {
+    if !output.status.success() {
+        std::io::stderr()
+            .write_all(&output.stderr)
+            .map_err(|e| format!("Could not write to stderr: {}", e))?;
+        return Err(format!("Benchmarks failed on the {} target", target));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let measurements = stdout
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.strip_prefix("bench ")?.split_whitespace();
+            let name = parts.next()?.to_owned();
+            let micros: f64 = parts.next()?.parse().ok()?;
+            let iterations: f64 = parts.next()?.parse().ok()?;
+            Some(Measurement {
+                name,
+                nanos_per_iteration: micros * 1000.0 / iterations,
+            })
+        })
+        .collect();
+    Ok(measurements)
+}
+
+fn format_nanos(nanos: f64) -> String {
+    format!("{:.0} ns/iter", nanos)
+}
@@ -0,0 +1,38 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::bindgen;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+/// Generates `extern` declarations for a C header
+pub fn bindgen(header: &Path, out_file: Option<&Path>) -> Result<(), String> {
+    let raw = fs::read_to_string(header)
+        .map_err(|e| format!("Could not read {}: {}", header.display(), e))?;
+    let generated = format!(
+        "// Generated by `sb bindgen` from {}\n\n{}",
+        header.display(),
+        bindgen::generate(&raw)
+    );
+
+    match out_file {
+        Some(out_file) => fs::write(out_file, generated)
+            .map_err(|e| format!("Could not write {}: {}", out_file.display(), e)),
+        None => stdout()
+            .write_all(generated.as_bytes())
+            .map_err(|e| format!("Could not write to stdout: {}", e)),
+    }
+}
@@ -13,35 +13,138 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use crate::builder;
-use crate::generator::Target;
+use antimony::builder::{self, BuildOptions};
+use antimony::cache::Cache;
+use antimony::diagnostics::{Diagnostic, MessageFormat};
+use antimony::driver::{self, LinkOptions};
+use antimony::generator::{Arch, Target};
+use antimony::timings::TimingsFormat;
 use std::fs::File;
 use std::io::stdout;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-pub fn build(target: &Target, in_file: &Path, out_file: &Path) -> Result<(), String> {
+/// Builds a program into the output file and returns the files embedded into it
+pub fn build(
+    target: &Target,
+    in_file: &Path,
+    out_file: &Path,
+    options: BuildOptions,
+    link: &LinkOptions,
+) -> Result<Vec<PathBuf>, String> {
+    let arch = options.arch.unwrap_or_else(Arch::host);
+    let cache = options.cache.as_deref().map(Cache::new);
     let mut buf = Box::new(Vec::new());
-    build_to_buffer(target, in_file, &mut buf)?;
+    let Built {
+        declarations,
+        embedded,
+    } = build_to_buffer(target, in_file, &mut buf, options)?;
 
     if out_file.to_str() == Some("-") {
+        if declarations.is_some() {
+            return Err("Declarations can not be written to stdout, use an output file".into());
+        }
         stdout()
             .write_all(&buf)
-            .map_err(|e| format!("Could not write to stdout: {}", e))
-    } else {
-        File::create(out_file)
-            .map_err(|e| format!("Could not create output file: {}", e))?
-            .write_all(&buf)
-            .map_err(|e| format!("Could not write to file: {}", e))
+            .map_err(|e| format!("Could not write to stdout: {}", e))?;
+        return Ok(embedded);
     }
+    if is_executable(target, out_file) {
+        driver::link(&buf, arch, out_file, link, cache.as_ref())?;
+        return Ok(embedded);
+    }
+    write_file(out_file, &buf)?;
+    if let Some(declarations) = declarations {
+        write_file(&declarations_file(out_file), declarations.as_bytes())?;
+    }
+    Ok(embedded)
+}
+
+/// QBE output is linked into an executable, unless it is written to a `.ssa` file
+fn is_executable(target: &Target, out_file: &Path) -> bool {
+    *target == Target::Qbe
+        && out_file.extension().and_then(|ext| ext.to_str()) != Some(target.extension())
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<(), String> {
+    File::create(path)
+        .map_err(|e| format!("Could not create output file: {}", e))?
+        .write_all(content)
+        .map_err(|e| format!("Could not write to file: {}", e))
+}
+
+/// Returns the path of the TypeScript declarations of a JavaScript file,
+/// e.g. `lib.d.ts` for `lib.js` and `lib.d.mts` for `lib.mjs`
+fn declarations_file(out_file: &Path) -> PathBuf {
+    let extension = match out_file.extension().and_then(|ext| ext.to_str()) {
+        Some("mjs") => "d.mts",
+        Some("cjs") => "d.cts",
+        _ => "d.ts",
+    };
+    out_file.with_extension(extension)
+}
+
+/// What a build produces besides its output
+pub struct Built {
+    /// TypeScript declarations of the output, if they were requested
+    pub declarations: Option<String>,
+    /// Files embedded with `include_str!`
+    pub embedded: Vec<PathBuf>,
 }
 
 pub fn build_to_buffer(
     target: &Target,
     in_file: &Path,
     buf: &mut Box<impl Write>,
-) -> Result<(), String> {
-    let mut b = builder::Builder::new(in_file.to_path_buf());
-    b.build(target)?;
-    b.generate(target, buf)
+    options: BuildOptions,
+) -> Result<Built, String> {
+    let format = options.message_format;
+    let timings = options.timings;
+    let mut b = builder::Builder::new(in_file.to_path_buf(), options);
+    let result = b.build(target);
+    report_diagnostics(&b, format);
+    result?;
+    b.generate(target, buf)?;
+    if let Some(timings) = timings {
+        report_timings(&b, timings)?;
+    }
+    Ok(Built {
+        declarations: b.declarations().map(str::to_owned),
+        embedded: b.embedded_files(),
+    })
+}
+
+/// Prints the warnings of a build to stderr
+pub fn report_diagnostics(b: &builder::Builder, format: MessageFormat) {
+    for diagnostic in b.diagnostics() {
+        match format {
+            MessageFormat::Human => eprintln!("{}\n", b.render(diagnostic)),
+            MessageFormat::Json => eprintln!("{}", b.render_json(diagnostic)),
+        }
+    }
+}
+
+/// File the timings of a build are written to in the Chrome trace event format
+const TRACE_FILE: &str = "timings.json";
+
+/// Prints the time spent in each phase of a build to stderr,
+/// or writes them to a trace file that can be opened in `chrome://tracing`
+pub fn report_timings(b: &builder::Builder, format: TimingsFormat) -> Result<(), String> {
+    let timings = b.timings().render(format);
+    match format {
+        TimingsFormat::Table => eprint!("{}", timings),
+        TimingsFormat::Json => {
+            write_file(Path::new(TRACE_FILE), timings.as_bytes())?;
+            eprintln!("Timings written to {}", TRACE_FILE);
+        }
+    }
+    Ok(())
+}
+
+/// Prints an error that aborted a command to stderr
+pub fn report_error(err: &str, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => eprintln!("Error: {}", err),
+        MessageFormat::Json => eprintln!("{}", Diagnostic::error(err.to_owned()).to_json(None)),
+    }
 }
@@ -0,0 +1,33 @@
+use crate::command::build::{report_diagnostics, report_timings};
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::builder::{self, BuildOptions};
+use antimony::generator::Target;
+use std::path::Path;
+
+/// Reports errors and warnings of a program without generating code
+pub fn check(target: &Target, in_file: &Path, options: BuildOptions) -> Result<(), String> {
+    let format = options.message_format;
+    let timings = options.timings;
+    let mut b = builder::Builder::new(in_file.to_path_buf(), options);
+    let result = b.build(target);
+    report_diagnostics(&b, format);
+    result?;
+    match timings {
+        Some(timings) => report_timings(&b, timings),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,36 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::manifest::Project;
+use antimony::package;
+use std::env;
+use std::fs;
+
+/// Removes build artifacts of the project in the current directory.
+/// Outside of a project, the global build cache is removed.
+pub fn clean() -> Result<(), String> {
+    let cwd = env::current_dir().map_err(|e| format!("Could not get current directory: {}", e))?;
+    let dir = match Project::discover(&cwd)? {
+        Some(project) => project.out_dir(),
+        None => package::cache_dir()?.join("cache"),
+    };
+
+    if dir.exists() {
+        fs::remove_dir_all(&dir)
+            .map_err(|e| format!("Could not remove {}: {}", dir.display(), e))?;
+        println!("Removed {}", dir.display());
+    }
+    Ok(())
+}
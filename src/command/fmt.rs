@@ -0,0 +1,73 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::formatter;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Formats the given files. Directories are searched for Antimony files recursively.
+/// If `check` is set, files are not modified, but an error is returned if
+/// any of them is not formatted.
+pub fn fmt(paths: &[PathBuf], check: bool) -> Result<(), String> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_sources(path, &mut files)?;
+    }
+
+    let mut unformatted = Vec::new();
+    for file in files {
+        let source = fs::read_to_string(&file)
+            .map_err(|e| format!("Could not read file {}: {}", file.display(), e))?;
+        let formatted =
+            formatter::format(&source).map_err(|e| format!("{}: {}", file.display(), e))?;
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            unformatted.push(file.display().to_string());
+        } else {
+            fs::write(&file, formatted)
+                .map_err(|e| format!("Could not write file {}: {}", file.display(), e))?;
+        }
+    }
+
+    if unformatted.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "The following files are not formatted:\n{}",
+            unformatted.join("\n")
+        ))
+    }
+}
+
+fn collect_sources(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let entries =
+        fs::read_dir(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|ext| ext == "sb") {
+            collect_sources(&entry, files)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::command::build::report_diagnostics;
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::builder::{self, BuildOptions};
+use antimony::generator::Target;
+use antimony::graph::{self, GraphFormat};
+use std::path::Path;
+
+/// Prints the call graph of a program, or the graph of its modules if `modules` is set
+pub fn graph(
+    in_file: &Path,
+    modules: bool,
+    format: GraphFormat,
+    options: BuildOptions,
+) -> Result<(), String> {
+    let message_format = options.message_format;
+    let packages = options.packages.clone();
+    // JavaScript is the only target whose functions are not renamed while building
+    let mut b = builder::Builder::new(in_file.to_path_buf(), options);
+    let result = b.build(&Target::JS);
+    report_diagnostics(&b, message_format);
+    result?;
+
+    let program = b.program_modules();
+    let graph = if modules {
+        graph::module_graph(&program, &packages)
+    } else {
+        graph::call_graph(&program)
+    };
+    println!("{}", graph.render(format).trim_end());
+    Ok(())
+}
@@ -13,5 +13,16 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+pub mod add;
+pub mod bench;
+pub mod bindgen;
 pub mod build;
+pub mod check;
+pub mod clean;
+pub mod fmt;
+pub mod graph;
+pub mod new;
+pub mod repl;
 pub mod run;
+pub mod test;
+pub mod watch;
@@ -0,0 +1,82 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::manifest::{self, MANIFEST_FILE};
+use std::fs;
+use std::path::Path;
+
+const MAIN: &str = "fn main() {
+    println(\"Hello, world!\")
+}
+";
+
+const GITIGNORE: &str = "/build
+";
+
+/// Creates a new project in a new directory
+pub fn new(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!("Destination {} already exists", path.display()));
+    }
+    fs::create_dir_all(path)
+        .map_err(|e| format!("Could not create directory {}: {}", path.display(), e))?;
+    init(path)
+}
+
+/// Creates a new project in an existing directory.
+/// Files that already exist are left untouched.
+pub fn init(path: &Path) -> Result<(), String> {
+    if path.join(MANIFEST_FILE).exists() {
+        return Err(format!(
+            "{} already exists in {}",
+            MANIFEST_FILE,
+            path.display()
+        ));
+    }
+
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("Could not resolve {}: {}", path.display(), e))?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !manifest::is_valid_name(&name) {
+        return Err(format!(
+            "Invalid package name '{}', only letters, digits, '-' and '_' are allowed",
+            name
+        ));
+    }
+
+    let manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nentry = \"src/main.sb\"\n\n[dependencies]\n",
+        name
+    );
+    write_new(&path.join(MANIFEST_FILE), &manifest)?;
+    fs::create_dir_all(path.join("src"))
+        .map_err(|e| format!("Could not create source directory: {}", e))?;
+    write_new(&path.join("src").join("main.sb"), MAIN)?;
+    write_new(&path.join(".gitignore"), GITIGNORE)?;
+
+    println!("Created package '{}'", name);
+    Ok(())
+}
+
+fn write_new(path: &Path, contents: &str) -> Result<(), String> {
+    if path.exists() {
+        return Ok(());
+    }
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
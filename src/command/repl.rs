@@ -0,0 +1,141 @@
+use crate::command::run;
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::builder::{BuildOptions, Builder};
+use antimony::generator::Target;
+use antimony::lexer::{tokenize, TokenKind};
+use antimony::warnings::WarningOptions;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// Name of the program compiled by the REPL. Imports are resolved relative to it.
+const REPL_FILE: &str = "repl.sb";
+
+/// Starts an interactive session. Every input is compiled into a program
+/// together with all previous inputs, which is then executed.
+pub fn repl(mut options: BuildOptions) -> Result<(), String> {
+    // Every declaration would be reported as unused
+    options.warnings = WarningOptions::from_flags(&[], &["all".into()], false)?;
+    // Every input would create a new cache entry
+    options.cache = None;
+
+    let stdin = io::stdin();
+    let mut session = Session::default();
+    let mut input = String::new();
+
+    loop {
+        print!("{}", if input.is_empty() { ">> " } else { ".. " });
+        io::stdout()
+            .flush()
+            .map_err(|e| format!("Could not write to stdout: {}", e))?;
+
+        let mut line = String::new();
+        let read = stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| format!("Could not read from stdin: {}", e))?;
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+
+        input.push_str(&line);
+        if !is_complete(&input) {
+            continue;
+        }
+
+        let chunk = std::mem::take(&mut input);
+        match chunk.trim() {
+            "" => continue,
+            ":quit" => return Ok(()),
+            chunk => match session.eval(chunk, &options) {
+                Ok(output) => print!("{}", output),
+                Err(err) => eprintln!("Error: {}", err),
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    /// Functions, structs and imports
+    definitions: Vec<String>,
+    /// Statements of the main function
+    statements: Vec<String>,
+    /// Length of the output that has already been shown
+    printed: usize,
+}
+
+impl Session {
+    /// Evaluates an input and returns the output it produced.
+    /// Inputs that fail to compile or run are discarded.
+    fn eval(&mut self, chunk: &str, options: &BuildOptions) -> Result<String, String> {
+        let mut definitions = self.definitions.clone();
+        let mut statements = self.statements.clone();
+        if ["fn ", "struct ", "import ", "const ", "@"]
+            .iter()
+            .any(|prefix| chunk.starts_with(prefix))
+        {
+            definitions.push(chunk.to_owned());
+        } else {
+            statements.push(chunk.to_owned());
+        }
+
+        let source = format!(
+            "{}\nfn main() {{\n{}\n}}\n",
+            definitions.join("\n"),
+            statements.join("\n")
+        );
+        let mut builder =
+            Builder::new(PathBuf::from(REPL_FILE), options.clone()).with_source(source);
+        let mut buf = Box::new(Vec::new());
+        builder.build(&Target::JS)?;
+        builder.generate(&Target::JS, &mut buf)?;
+
+        let output = run::execute(Target::JS, options.js_runtime, &buf, &[])?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+        }
+
+        // The whole program is executed again, so only the new part of the output is shown
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let new_output = stdout.get(self.printed..).unwrap_or_default().to_owned();
+
+        self.definitions = definitions;
+        self.statements = statements;
+        self.printed = stdout.len();
+        Ok(new_output)
+    }
+}
+
+/// Input is complete once all brackets are closed
+fn is_complete(input: &str) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        // Let the compiler report the error
+        Err(_) => return true,
+    };
+
+    let depth: i64 = tokens
+        .iter()
+        .map(|token| match token.kind {
+            TokenKind::CurlyBracesOpen | TokenKind::BraceOpen | TokenKind::SquareBraceOpen => 1,
+            TokenKind::CurlyBracesClose | TokenKind::BraceClose | TokenKind::SquareBraceClose => -1,
+            _ => 0,
+        })
+        .sum();
+    depth <= 0
+}
@@ -1,3 +1,4 @@
+use crate::command::build;
 /**
  * Copyright 2020 Garrit Franke
  *
@@ -13,44 +14,225 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use crate::command::build;
-use crate::generator::Target;
-use std::io::Read;
-use std::io::Write;
-use std::path::PathBuf;
+use antimony::builder::BuildOptions;
+use antimony::cache::Cache;
+use antimony::driver::{self, LinkOptions};
+use antimony::generator::js::JsRuntime;
+use antimony::generator::{Arch, Target};
+use antimony::warnings::WarningOptions;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
 use std::process::Command;
+use std::process::Output;
 use std::process::Stdio;
 
-pub fn run(target: Target, in_file: PathBuf) -> Result<(), String> {
+pub fn run(
+    target: Target,
+    in_file: PathBuf,
+    args: &[String],
+    options: BuildOptions,
+) -> Result<(), String> {
+    let runtime = options.js_runtime;
     let mut buf = Box::new(Vec::new());
-    build::build_to_buffer(&target, &in_file, &mut buf)?;
+    build::build_to_buffer(&target, &in_file, &mut buf, options)?;
+
+    let status = spawn(target, runtime, &buf, args, false)?
+        .wait()
+        .map_err(|e| format!("Could not wait for child process: {}", e));
+    let _ = fs::remove_file(program_file());
+
+    // Exit with the exit code of the program
+    match status?.code() {
+        Some(0) => Ok(()),
+        Some(code) => std::process::exit(code),
+        None => Err("Program was terminated by a signal".into()),
+    }
+}
+
+/// Compiles a program and starts it without waiting for it to finish.
+/// Returns the program and the files embedded into it.
+pub(crate) fn start(
+    target: Target,
+    in_file: &Path,
+    args: &[String],
+    options: BuildOptions,
+) -> Result<(Child, Vec<PathBuf>), String> {
+    let runtime = options.js_runtime;
+    let mut buf = Box::new(Vec::new());
+    let built = build::build_to_buffer(&target, in_file, &mut buf, options)?;
+    Ok((spawn(target, runtime, &buf, args, false)?, built.embedded))
+}
+
+/// Executes a compiled program with the given arguments and collects its output
+pub(crate) fn execute(
+    target: Target,
+    runtime: JsRuntime,
+    program: &[u8],
+    args: &[String],
+) -> Result<Output, String> {
+    let output = spawn(target, runtime, program, args, true)?
+        .wait_with_output()
+        .map_err(|e| format!("Could not read from child process: {}", e));
+    let _ = fs::remove_file(program_file());
+    output
+}
+
+/// Compiles a program to a native executable for this machine, runs it with the given arguments
+/// and collects its output
+pub(crate) fn execute_native(
+    in_file: &Path,
+    args: &[String],
+    options: BuildOptions,
+) -> Result<Output, String> {
+    let cache = options.cache.as_deref().map(Cache::new);
+    let mut buf = Box::new(Vec::new());
+    build::build_to_buffer(&Target::Qbe, in_file, &mut buf, options)?;
+
+    let executable = std::env::temp_dir().join(format!("antimony-{}", std::process::id()));
+    driver::link(
+        &buf,
+        Arch::host(),
+        &executable,
+        &LinkOptions::default(),
+        cache.as_ref(),
+    )?;
+    let output = Command::new(&executable)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Could not run {}: {}", executable.display(), e));
+    let _ = fs::remove_file(&executable);
+    output
+}
+
+/// Runs a program once compiled to JavaScript and once compiled to a native executable,
+/// and fails if the backends disagree on the output or the exit code of the program
+pub fn differential(
+    in_file: &Path,
+    args: &[String],
+    mut options: BuildOptions,
+) -> Result<(), String> {
+    // The native executable is run on this machine
+    options.arch = None;
+    let runtime = options.js_runtime;
+
+    let mut buf = Box::new(Vec::new());
+    build::build_to_buffer(&Target::JS, in_file, &mut buf, options.clone())?;
+    let js = execute(Target::JS, runtime, &buf, args)?;
+    // Warnings have already been reported by the JavaScript build
+    options.warnings = WarningOptions::from_flags(&[], &["all".to_owned()], false)?;
+    let native = execute_native(in_file, args, options)?;
+
+    let mismatches = compare_outputs(&js, &native);
+    if !mismatches.is_empty() {
+        for mismatch in mismatches {
+            eprintln!("{}", mismatch);
+        }
+        return Err("The js and native backends disagree".into());
+    }
+
+    io::stdout()
+        .write_all(&js.stdout)
+        .map_err(|e| format!("Could not write to stdout: {}", e))?;
+    match js.status.code() {
+        Some(0) => Ok(()),
+        Some(code) => std::process::exit(code),
+        None => Err("Program was terminated by a signal".into()),
+    }
+}
+
+/// Describes how the outputs of the JavaScript and the native program differ
+pub(crate) fn compare_outputs(js: &Output, native: &Output) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if js.status.code() != native.status.code() {
+        let code = |output: &Output| match output.status.code() {
+            Some(code) => code.to_string(),
+            None => "terminated by a signal".to_owned(),
+        };
+        mismatches.push(format!(
+            "exit code: js {}, native {}",
+            code(js),
+            code(native)
+        ));
+    }
+    let js_lines: Vec<&str> = std::str::from_utf8(&js.stdout)
+        .unwrap_or_default()
+        .lines()
+        .collect();
+    let native_lines: Vec<&str> = std::str::from_utf8(&native.stdout)
+        .unwrap_or_default()
+        .lines()
+        .collect();
+    let len = js_lines.len().max(native_lines.len());
+    if let Some(line) = (0..len).find(|&i| js_lines.get(i) != native_lines.get(i)) {
+        let show = |lines: &[&str]| match lines.get(line) {
+            Some(l) => format!("{:?}", l),
+            None => "end of output".to_owned(),
+        };
+        mismatches.push(format!(
+            "stdout differs at line {}:\n  js:     {}\n  native: {}",
+            line + 1,
+            show(&js_lines),
+            show(&native_lines)
+        ));
+    }
+    mismatches
+}
+
+/// Path of the file a compiled program is written to before it is started
+fn program_file() -> PathBuf {
+    std::env::temp_dir().join(format!("antimony-{}.js", std::process::id()))
+}
+
+/// Starts a compiled program. If `capture` is set, its output is piped instead of being printed
+/// and it does not read from stdin
+fn spawn(
+    target: Target,
+    runtime: JsRuntime,
+    program: &[u8],
+    args: &[String],
+    capture: bool,
+) -> Result<Child, String> {
+    let stdio = |piped: bool| {
+        if !capture {
+            Stdio::inherit()
+        } else if piped {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    };
 
     match target {
         Target::JS => {
-            let process = Command::new("node")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
+            // The program is loaded from a file instead of stdin, which belongs to the program
+            let file = program_file();
+            fs::write(&file, program)
+                .map_err(|e| format!("Could not write {}: {}", file.display(), e))?;
+
+            let mut command = match runtime {
+                JsRuntime::Node => Command::new("node"),
+                JsRuntime::Deno => {
+                    // Programs can read files and the environment, like they can in Node.js
+                    let mut command = Command::new("deno");
+                    command.args(["run", "--allow-all"]);
+                    command
+                }
+                JsRuntime::Browser => {
+                    return Err("Programs for the browser runtime can not be run".into())
+                }
+            };
+            command
+                .arg(&file)
+                .args(args)
+                .stdin(stdio(false))
+                .stdout(stdio(true))
+                .stderr(stdio(true))
                 .spawn()
-                .map_err(|e| format!("Could not spawn Node.js process: {}", e))?;
-
-            process
-                .stdin
-                .unwrap()
-                .write_all(&buf)
-                .map_err(|e| format!("Could not write to Node.js process: {}", e))?;
-
-            let mut s = Vec::new();
-            process
-                .stdout
-                .unwrap()
-                .read_to_end(&mut s)
-                .map_err(|e| format!("Could not read from child process: {}", e))?;
-            std::io::stdout()
-                .write_all(&s)
-                .map_err(|e| format!("Could not write to stdout: {}", e))?;
+                .map_err(|e| format!("Could not spawn {} process: {}", runtime, e))
         }
-        _ => todo!(),
+        other => Err(format!("Running the {} target is not supported", other)),
     }
-    Ok(())
 }
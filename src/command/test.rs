@@ -0,0 +1,50 @@
+use crate::command::{build, run};
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use antimony::builder::BuildOptions;
+use antimony::generator::Target;
+use std::io::Write;
+use std::path::Path;
+
+/// Runs all functions of a program that are annotated with `@test`
+pub fn test(in_file: &Path, mut options: BuildOptions) -> Result<(), String> {
+    options.test = true;
+    let runtime = options.js_runtime;
+
+    // Only the JS target can be executed
+    let mut buf = Box::new(Vec::new());
+    build::build_to_buffer(&Target::JS, in_file, &mut buf, options)?;
+    let output = run::execute(Target::JS, runtime, &buf, &[])?;
+
+    let mut stdout = output.stdout;
+    // A failing assertion ends the program while a test is running,
+    // before the test could report its result
+    if !output.status.success() && stdout.last() != Some(&b'\n') {
+        stdout.extend_from_slice(b"FAILED\n");
+    }
+    std::io::stdout()
+        .write_all(&stdout)
+        .map_err(|e| format!("Could not write to stdout: {}", e))?;
+    std::io::stderr()
+        .write_all(&output.stderr)
+        .map_err(|e| format!("Could not write to stderr: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err("Test failed".into())
+    }
+}
@@ -0,0 +1,134 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::command::build::report_error;
+use antimony::builder::BuildOptions;
+use antimony::diagnostics::MessageFormat;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Interval in which source files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Returns the directories that contain the sources of a program
+pub fn source_dirs(in_file: &Path, options: &BuildOptions) -> Vec<PathBuf> {
+    let mut dirs = vec![match in_file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }];
+    dirs.extend(
+        options
+            .packages
+            .values()
+            .filter_map(|entry| entry.parent())
+            .map(Path::to_path_buf),
+    );
+    dirs
+}
+
+/// Runs `action` every time a source file in one of the directories changes.
+/// If the action starts a program, the program is stopped before the action runs again.
+/// The action returns the files embedded into the program, which are watched as well.
+pub fn watch<F>(dirs: &[PathBuf], format: MessageFormat, mut action: F) -> Result<(), String>
+where
+    F: FnMut() -> Result<(Option<Child>, Vec<PathBuf>), String>,
+{
+    // Files embedded by the last successful build
+    let mut embedded = Vec::new();
+    loop {
+        let sources = snapshot(dirs, &[]);
+        let previous = modification_times(&embedded);
+
+        // Clear the screen and move the cursor to the top left corner
+        print!("\x1B[2J\x1B[1;1H");
+        let _ = io::stdout().flush();
+        eprintln!("Watching for changes...");
+        let mut child = match action() {
+            Ok((child, files)) => {
+                embedded = files;
+                child
+            }
+            Err(err) => {
+                report_error(&err, format);
+                None
+            }
+        };
+
+        // The build may embed other files than before, so only the files it embeds are
+        // compared. Files that were not embedded before are compared from now on.
+        let mut before = sources;
+        for (path, modified) in modification_times(&embedded) {
+            let modified = previous.get(&path).copied().unwrap_or(modified);
+            before.insert(path, modified);
+        }
+        while snapshot(dirs, &embedded) == before {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if let Some(child) = &mut child {
+            // The program may already have exited
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Modification times of all source files in the directories, and of the embedded files
+fn snapshot(dirs: &[PathBuf], embedded: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = modification_times(embedded);
+    for dir in dirs {
+        collect_modification_times(dir, &mut files);
+    }
+    files
+}
+
+fn modification_times(paths: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+            Some((path.clone(), modified))
+        })
+        .collect()
+}
+
+fn collect_modification_times(dir: &Path, files: &mut BTreeMap<PathBuf, SystemTime>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_modification_times(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "sb") {
+            if let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) {
+                files.insert(path, modified);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::lexer::Position;
+use crate::util::string_util::highlight_position_in_file;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(test)]
+mod tests;
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// How diagnostics are printed by the command line interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    /// Rendered for humans
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("Unknown message format: {}", s)),
+        }
+    }
+}
+
+/// A message about the program that is reported to the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the lint that emitted the diagnostic, if any
+    pub lint: Option<String>,
+    /// File the diagnostic refers to
+    pub file: Option<String>,
+    /// Position inside the file
+    pub pos: Option<Position>,
+    /// Suggestion on how to resolve the diagnostic
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            lint: None,
+            file: None,
+            pos: None,
+            help: None,
+        }
+    }
+
+    pub fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    pub fn with_lint(mut self, lint: &str) -> Self {
+        self.lint = Some(lint.to_owned());
+        self
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_owned());
+        self
+    }
+
+    pub fn with_pos(mut self, pos: Option<Position>) -> Self {
+        self.pos = pos;
+        self
+    }
+
+    /// Renders the diagnostic. If the source of the file is provided,
+    /// the position of the diagnostic is highlighted.
+    pub fn render(&self, source: Option<&str>) -> String {
+        let mut buf = match &self.lint {
+            Some(lint) => format!("{}[{}]: {}", self.severity, lint, self.message),
+            None => format!("{}: {}", self.severity, self.message),
+        };
+
+        match (&self.file, self.pos) {
+            (Some(file), Some(pos)) => {
+                buf.push_str(&format!("\n --> {}:{}:{}", file, pos.line, pos.offset))
+            }
+            (Some(file), None) => buf.push_str(&format!("\n --> {}", file)),
+            (None, Some(pos)) => buf.push_str(&format!("\n --> {}:{}", pos.line, pos.offset)),
+            (None, None) => {}
+        }
+
+        if let (Some(source), Some(pos)) = (source, self.pos) {
+            buf.push('\n');
+            buf.push_str(&highlight_position_in_file(source.to_owned(), pos));
+        }
+
+        if let Some(help) = &self.help {
+            buf.push_str(&format!("\n     = help: {}", help));
+        }
+
+        buf
+    }
+
+    /// Serializes the diagnostic into a single line of JSON, for editors and other tools.
+    /// Contains the rendered diagnostic as well.
+    pub fn to_json(&self, source: Option<&str>) -> String {
+        json!({
+            "severity": self.severity,
+            "code": self.lint,
+            "message": self.message,
+            "file": self.file,
+            "span": self.pos.map(|pos| json!({ "line": pos.line, "column": pos.offset })),
+            "help": self.help,
+            "rendered": self.render(source),
+        })
+        .to_string()
+    }
+}
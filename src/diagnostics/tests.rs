@@ -0,0 +1,56 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::diagnostics::{Diagnostic, MessageFormat};
+use crate::lexer::Position;
+
+#[test]
+fn test_to_json() {
+    let diagnostic = Diagnostic::warning("Variable 'x' is never used".into())
+        .with_lint("unused-variable")
+        .with_file("main.sb")
+        .with_pos(Some(Position {
+            line: 2,
+            offset: 9,
+            raw: 20,
+        }));
+    let json: serde_json::Value =
+        serde_json::from_str(&diagnostic.to_json(Some("fn main() {\n    let x = 1\n}"))).unwrap();
+    assert_eq!(json["severity"], "warning");
+    assert_eq!(json["code"], "unused-variable");
+    assert_eq!(json["file"], "main.sb");
+    assert_eq!(json["span"]["line"], 2);
+    assert_eq!(json["span"]["column"], 9);
+    assert!(json["help"].is_null());
+    assert_eq!(
+        json["rendered"].as_str().unwrap(),
+        diagnostic.render(Some("fn main() {\n    let x = 1\n}"))
+    );
+}
+
+#[test]
+fn test_to_json_is_single_line() {
+    let diagnostic = Diagnostic::error("Expected token\nfound end of file".into());
+    let json = diagnostic.to_json(None);
+    assert!(!json.contains('\n'));
+    assert!(json.contains("\"span\":null"));
+}
+
+#[test]
+fn test_parse_message_format() {
+    assert_eq!("human".parse(), Ok(MessageFormat::Human));
+    assert_eq!("json".parse(), Ok(MessageFormat::Json));
+    assert!("xml".parse::<MessageFormat>().is_err());
+}
@@ -0,0 +1,170 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::cache::{self, Cache};
+use crate::generator::Arch;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(test)]
+mod tests;
+
+/// Options of the step that links native code into an executable
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    /// Program used to link. Defaults to `cc`, or `<triple>-gcc` when cross-compiling
+    pub linker: Option<String>,
+    /// Directories searched for libraries, passed as `-L`
+    pub search_paths: Vec<PathBuf>,
+    /// Libraries linked into the executable, passed as `-l`
+    pub libraries: Vec<String>,
+}
+
+/// Compiles QBE IL to an executable, by running QBE, the assembler and the linker.
+/// The runtime is linked into every executable, and taken from the cache if possible.
+/// Intermediate files are written to a temporary directory, which is removed afterwards.
+pub fn link(
+    ir: &[u8],
+    arch: Arch,
+    out_file: &Path,
+    options: &LinkOptions,
+    cache: Option<&Cache>,
+) -> Result<(), String> {
+    let dir = std::env::temp_dir().join(format!("antimony-link-{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+
+    let result = write(&dir.join("main.ssa"), ir)
+        .and_then(|_| runtime_archive(&dir, arch, options, cache))
+        .and_then(|_| {
+            commands(&dir, arch, out_file, options)
+                .iter_mut()
+                .try_for_each(run)
+        });
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Writes the runtime archive `libantimony.a` to `dir`. The runtime is compiled from
+/// the C source embedded in the compiler, once for each architecture and compiler.
+fn runtime_archive(
+    dir: &Path,
+    arch: Arch,
+    options: &LinkOptions,
+    cache: Option<&Cache>,
+) -> Result<(), String> {
+    let source = crate::Builtins::get("runtime.c").expect("Could not locate runtime functions");
+    let archive = dir.join("libantimony.a");
+    let key = cache::hash(&[
+        std::str::from_utf8(source.as_ref()).expect("Unable to interpret runtime functions"),
+        arch.name(),
+        &compiler(arch, options),
+    ]);
+    if let Some(cached) = cache.and_then(|cache| cache.load_runtime(key)) {
+        return write(&archive, &cached);
+    }
+
+    write(&dir.join("runtime.c"), source.as_ref())?;
+    runtime_commands(dir, arch, options)
+        .iter_mut()
+        .try_for_each(run)?;
+    if let Some(cache) = cache {
+        let compiled = fs::read(&archive)
+            .map_err(|e| format!("Could not read {}: {}", archive.display(), e))?;
+        cache.store_runtime(key, &compiled);
+    }
+    Ok(())
+}
+
+/// Cross-compiling toolchains are prefixed with the target triple, e.g. `aarch64-linux-gnu-as`
+fn tool(arch: Arch, name: &str) -> String {
+    if arch != Arch::host() {
+        format!("{}-{}", arch.triple(), name)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// The linker is a C compiler driver, which also compiles the runtime
+fn compiler(arch: Arch, options: &LinkOptions) -> String {
+    match &options.linker {
+        Some(linker) => linker.clone(),
+        None if arch != Arch::host() => tool(arch, "gcc"),
+        None => "cc".into(),
+    }
+}
+
+/// Returns the commands that compile `runtime.c` in `dir` to `libantimony.a`
+fn runtime_commands(dir: &Path, arch: Arch, options: &LinkOptions) -> Vec<Command> {
+    let obj = dir.join("runtime.o");
+
+    let mut compiler = Command::new(compiler(arch, options));
+    compiler
+        .args(["-c", "-O2", "-o"])
+        .arg(&obj)
+        .arg(dir.join("runtime.c"));
+
+    let mut archiver = Command::new(tool(arch, "ar"));
+    archiver.arg("rcs").arg(dir.join("libantimony.a")).arg(&obj);
+
+    vec![compiler, archiver]
+}
+
+/// Returns the commands that turn `main.ssa` in `dir` into an executable
+fn commands(dir: &Path, arch: Arch, out_file: &Path, options: &LinkOptions) -> Vec<Command> {
+    let ssa = dir.join("main.ssa");
+    let asm = dir.join("main.s");
+    let obj = dir.join("main.o");
+
+    let mut qbe = Command::new("qbe");
+    qbe.args(["-t", arch.qbe_target(), "-o"])
+        .arg(&asm)
+        .arg(&ssa);
+
+    let mut assembler = Command::new(tool(arch, "as"));
+    assembler.arg(&asm).arg("-o").arg(&obj);
+
+    let mut linker = Command::new(compiler(arch, options));
+    linker.arg(&obj).arg(dir.join("libantimony.a"));
+    for path in &options.search_paths {
+        linker.arg("-L").arg(path);
+    }
+    linker.arg("-o").arg(out_file);
+    // Libraries come after the objects using them, so that the linker resolves their symbols
+    for library in &options.libraries {
+        linker.arg(format!("-l{}", library));
+    }
+
+    vec![qbe, assembler, linker]
+}
+
+fn write(path: &Path, content: &[u8]) -> Result<(), String> {
+    fs::write(path, content).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| format!("Could not run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed:\n{}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    Ok(())
+}
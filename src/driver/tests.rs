@@ -0,0 +1,106 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::{commands, runtime_commands, LinkOptions};
+use crate::generator::Arch;
+use std::path::Path;
+use std::process::Command;
+
+fn args(command: &Command) -> Vec<String> {
+    command
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn test_link_commands() {
+    let options = LinkOptions {
+        linker: None,
+        search_paths: vec!["lib".into()],
+        libraries: vec!["m".into()],
+    };
+    let dir = Path::new("tmp");
+    let commands = commands(dir, Arch::host(), Path::new("main"), &options);
+    assert_eq!(commands.len(), 3);
+
+    let (qbe, assembler, linker) = (&commands[0], &commands[1], &commands[2]);
+    assert_eq!(qbe.get_program(), "qbe");
+    assert_eq!(
+        args(qbe),
+        [
+            "-t",
+            Arch::host().qbe_target(),
+            "-o",
+            "tmp/main.s",
+            "tmp/main.ssa"
+        ]
+    );
+    assert_eq!(assembler.get_program(), "as");
+    assert_eq!(args(assembler), ["tmp/main.s", "-o", "tmp/main.o"]);
+    assert_eq!(linker.get_program(), "cc");
+    assert_eq!(
+        args(linker),
+        [
+            "tmp/main.o",
+            "tmp/libantimony.a",
+            "-L",
+            "lib",
+            "-o",
+            "main",
+            "-lm"
+        ]
+    );
+}
+
+#[test]
+fn test_runtime_commands() {
+    let commands = runtime_commands(Path::new("tmp"), Arch::host(), &LinkOptions::default());
+    assert_eq!(commands[0].get_program(), "cc");
+    assert_eq!(
+        args(&commands[0]),
+        ["-c", "-O2", "-o", "tmp/runtime.o", "tmp/runtime.c"]
+    );
+    assert_eq!(commands[1].get_program(), "ar");
+    assert_eq!(
+        args(&commands[1]),
+        ["rcs", "tmp/libantimony.a", "tmp/runtime.o"]
+    );
+}
+
+#[test]
+fn test_cross_link_commands() {
+    let arch = if Arch::host() == Arch::Riscv64 {
+        Arch::Arm64
+    } else {
+        Arch::Riscv64
+    };
+    let cross = commands(
+        Path::new("tmp"),
+        arch,
+        Path::new("main"),
+        &LinkOptions::default(),
+    );
+    let prefix = arch.triple();
+    assert_eq!(cross[1].get_program(), &*format!("{}-as", prefix));
+    assert_eq!(cross[2].get_program(), &*format!("{}-gcc", prefix));
+
+    let options = LinkOptions {
+        linker: Some("clang".into()),
+        ..LinkOptions::default()
+    };
+    let custom = commands(Path::new("tmp"), arch, Path::new("main"), &options);
+    assert_eq!(custom[2].get_program(), "clang");
+}
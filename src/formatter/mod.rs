@@ -0,0 +1,101 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::lexer::{tokenize, TokenKind};
+
+#[cfg(test)]
+mod tests;
+
+/// Whitespace of one level of indentation
+const INDENT: &str = "    ";
+
+/// Formats the source code of a module.
+///
+/// Indentation is derived from the nesting of brackets, trailing whitespace
+/// is removed and consecutive empty lines are collapsed into one.
+/// Everything else, including comments and the lines of inline code, is left untouched.
+pub fn format(source: &str) -> Result<String, String> {
+    let tokens = tokenize(source)?;
+    let lines = source.lines().count();
+
+    // Change of nesting caused by each line
+    let mut depth_change = vec![0_i64; lines + 2];
+    // Closing brackets at the start of each line, which dedent the line itself
+    let mut leading_closes = vec![0_i64; lines + 2];
+    let mut has_content = vec![false; lines + 2];
+    // Lines inside of inline code blocks
+    let mut verbatim = vec![false; lines + 2];
+
+    for token in tokens {
+        let line = token.pos.line;
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Tab | TokenKind::CarriageReturn => {}
+            TokenKind::CurlyBracesOpen | TokenKind::BraceOpen | TokenKind::SquareBraceOpen => {
+                depth_change[line] += 1;
+                has_content[line] = true;
+            }
+            TokenKind::CurlyBracesClose | TokenKind::BraceClose | TokenKind::SquareBraceClose => {
+                depth_change[line] -= 1;
+                if !has_content[line] {
+                    leading_closes[line] += 1;
+                }
+            }
+            TokenKind::Inline(..) => {
+                // The position of a token is where it ends
+                let start = line - token.raw.matches('\n').count();
+                depth_change[start] += 1;
+                has_content[start] = true;
+                if start < line {
+                    verbatim[start + 1..line].fill(true);
+                    depth_change[line] -= 1;
+                    let last_line = token.raw.rsplit('\n').next().unwrap_or_default();
+                    if last_line.trim() == "}" {
+                        leading_closes[line] += 1;
+                    }
+                }
+            }
+            _ => has_content[line] = true,
+        }
+    }
+
+    let mut formatted = String::new();
+    let mut depth = 0_i64;
+    let mut pending_empty_line = false;
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let trimmed = line.trim();
+
+        if verbatim[number] {
+            formatted.push_str(line.trim_end());
+            formatted.push('\n');
+        } else if trimmed.is_empty() {
+            // Empty lines at the start of the file are dropped
+            pending_empty_line = !formatted.is_empty();
+        } else {
+            if pending_empty_line {
+                formatted.push('\n');
+                pending_empty_line = false;
+            }
+            let indent = (depth - leading_closes[number]).max(0) as usize;
+            formatted.push_str(&INDENT.repeat(indent));
+            formatted.push_str(trimmed);
+            formatted.push('\n');
+        }
+
+        depth += depth_change[number];
+    }
+
+    Ok(formatted)
+}
@@ -0,0 +1,59 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::formatter::format;
+
+#[test]
+fn test_indentation() {
+    let raw = "fn main() {\nlet x = [\n1,\n2\n]\n  if x[0] == 1 {\n        println(x)\n}\n}\n";
+    let expected = "fn main() {\n    let x = [\n        1,\n        2\n    ]\n    if x[0] == 1 {\n        println(x)\n    }\n}\n";
+    assert_eq!(format(raw).unwrap(), expected);
+}
+
+#[test]
+fn test_closing_and_opening_on_same_line() {
+    let raw = "fn main() {\nif true {\nprintln(1)\n} else {\nprintln(2)\n}\n}\n";
+    let expected =
+        "fn main() {\n    if true {\n        println(1)\n    } else {\n        println(2)\n    }\n}\n";
+    assert_eq!(format(raw).unwrap(), expected);
+}
+
+#[test]
+fn test_brackets_in_strings_and_comments() {
+    let raw = "fn main() {\n// {\nprintln(\"}\")\n}\n";
+    let expected = "fn main() {\n    // {\n    println(\"}\")\n}\n";
+    assert_eq!(format(raw).unwrap(), expected);
+}
+
+#[test]
+fn test_empty_lines() {
+    let raw = "\n\nfn foo() {}   \n\n\n\nfn bar() {}\n\n";
+    let expected = "fn foo() {}\n\nfn bar() {}\n";
+    assert_eq!(format(raw).unwrap(), expected);
+}
+
+#[test]
+fn test_formatting_is_idempotent() {
+    let raw = "fn main() {\nlet x = 1\n    while x < 10 {\nx += 1\n        }\n}";
+    let formatted = format(raw).unwrap();
+    assert_eq!(format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn test_inline_code_is_left_untouched() {
+    let raw = "fn main() {\njs! {\n  if (x) {\n    f()\n  }\n}\nprintln(1)\n}\n";
+    let expected = "fn main() {\n    js! {\n  if (x) {\n    f()\n  }\n    }\n    println(1)\n}\n";
+    assert_eq!(format(raw).unwrap(), expected);
+}
@@ -29,11 +29,33 @@ impl Generator for CGenerator {
             crate::Builtins::get("builtin.c").expect("Could not locate builtin functions");
         code += std::str::from_utf8(raw_builtins.as_ref())
             .expect("Unable to interpret builtin functions");
+        let raw_runtime =
+            crate::Builtins::get("runtime.c").expect("Could not locate runtime functions");
+        code += std::str::from_utf8(raw_runtime.as_ref())
+            .expect("Unable to interpret runtime functions");
+
+        if !prog.globals.is_empty() {
+            todo!("global variables");
+        }
+        if let Some(func) = prog
+            .func
+            .iter()
+            .find(|func| func.arguments.iter().any(|arg| arg.reference))
+        {
+            return Err(format!(
+                "Function '{}' takes a struct by reference, which is not supported by the C backend",
+                func.name
+            ));
+        }
 
         let structs: String = prog.structs.into_iter().map(generate_struct).collect();
 
         code += &structs;
 
+        for func in &prog.externs {
+            code += &format!("{};\n", &generate_extern_signature(func));
+        }
+
         for func in &prog.func {
             code += &format!("{};\n", &generate_function_signature(func.clone()));
         }
@@ -73,6 +95,12 @@ pub(super) fn generate_type(t: Either<Variable, Option<Type>>) -> String {
             Type::Any => "void *".into(),
             Type::Bool => "bool".into(),
             Type::Struct(name) => format!("struct {}", name),
+            Type::Map(..) => "struct map *".into(),
+            Type::Chan(_) => "struct chan *".into(),
+            Type::Result(_) => "struct result *".into(),
+            Type::Ref(t) => format!("{} *", generate_type(Either::Right(Some(*t)))),
+            // Arithmetic on `char *` counts bytes, like the arithmetic on raw pointers
+            Type::Ptr => "char *".into(),
             Type::Array(t, capacity) => match name {
                 Some(n) => format!(
                     "{T} {N}[{C}]",
@@ -100,14 +128,22 @@ fn generate_function(func: Function) -> String {
 }
 
 fn generate_function_signature(func: Function) -> String {
-    let arguments: String = func
-        .arguments
+    generate_signature(&func.name, func.arguments, func.ret_type)
+}
+
+/// External functions are only declared, their definition is provided by the linker
+fn generate_extern_signature(func: &ExternFunction) -> String {
+    generate_signature(&func.name, func.arguments.clone(), func.ret_type.clone())
+}
+
+fn generate_signature(name: &str, arguments: Vec<Variable>, ret_type: Option<Type>) -> String {
+    let arguments: String = arguments
         .into_iter()
         .map(|var| format!("{} {}", generate_type(Either::Left(var.clone())), var.name))
         .collect::<Vec<String>>()
         .join(", ");
-    let t = generate_type(Either::Right(func.ret_type));
-    format!("{T} {N}({A})", T = t, N = func.name, A = arguments)
+    let t = generate_type(Either::Right(ret_type));
+    format!("{T} {N}({A})", T = t, N = name, A = arguments)
 }
 
 fn generate_block(block: Vec<Statement>, _scope: Vec<Variable>) -> String {
@@ -133,10 +169,18 @@ fn generate_statement(statement: Statement) -> String {
         Statement::Assign(name, state) => generate_assign(*name, *state),
         Statement::Block(statements, scope) => generate_block(statements, scope),
         Statement::While(expr, body) => generate_while_loop(expr, *body),
+        Statement::Unsafe(body) => generate_statement(*body),
         Statement::For(_ident, _expr, _body) => todo!(),
         Statement::Continue => todo!(),
         Statement::Break => todo!(),
         Statement::Match(_, _) => todo!(),
+        Statement::Defer(_) => todo!(),
+        Statement::Spawn(_) => todo!(),
+        Statement::Static(..) => unreachable!("Static variables are moved to the globals"),
+        Statement::Inline(..) => unreachable!("Inline code is rejected by the builder"),
+        Statement::Destructure(..) | Statement::ForPair(..) => {
+            unreachable!("Patterns are desugared by the semantic analysis")
+        }
     };
 
     format!("{}\n", state)
@@ -153,8 +197,27 @@ fn generate_expression(expr: Expression) -> String {
         Expression::ArrayAccess(name, expr) => generate_array_access(name, *expr),
         Expression::BinOp(left, op, right) => generate_bin_op(*left, op, *right),
         Expression::StructInitialization(_, fields) => generate_struct_initialization(fields),
+        Expression::StructUpdate(..) => todo!(),
         Expression::FieldAccess(expr, field) => generate_field_access(*expr, *field),
         Expression::Selff => todo!(),
+        Expression::Map(_) => todo!(),
+        Expression::Try(_) => unreachable!("'?' is lowered by the semantic analysis"),
+        Expression::SizeOf(_) => unreachable!("'size_of' is evaluated by the semantic analysis"),
+        // Async functions are called like any other function
+        Expression::Await(call) => generate_expression(*call),
+        Expression::Reference(target) => format!("&{}", generate_expression(*target)),
+        Expression::Dereference(operand) => format!("(*{})", generate_expression(*operand)),
+        Expression::Load(ty, address) => format!(
+            "(*({} *)({}))",
+            generate_type(Either::Right(Some(ty))),
+            generate_expression(*address)
+        ),
+        Expression::Store(ty, address, value) => format!(
+            "(*({} *)({}) = {})",
+            generate_type(Either::Right(Some(ty))),
+            generate_expression(*address),
+            generate_expression(*value)
+        ),
     }
 }
 
@@ -249,8 +312,19 @@ fn generate_function_call(func: String, args: Vec<Expression>) -> String {
             Expression::Array(_, _) => todo!(),
             Expression::BinOp(left, op, right) => generate_bin_op(*left, op, *right),
             Expression::StructInitialization(_, fields) => generate_struct_initialization(fields),
+            Expression::StructUpdate(..) => todo!(),
             Expression::FieldAccess(expr, field) => generate_field_access(*expr, *field),
             Expression::Selff => todo!(),
+            Expression::Map(_) => todo!(),
+            Expression::Try(_) => unreachable!("'?' is lowered by the semantic analysis"),
+            expr @ (Expression::Await(_)
+            | Expression::Reference(_)
+            | Expression::Dereference(_)
+            | Expression::Load(..)
+            | Expression::Store(..)) => generate_expression(expr),
+            Expression::SizeOf(_) => {
+                unreachable!("'size_of' is evaluated by the semantic analysis")
+            }
         })
         .collect::<Vec<String>>()
         .join(",");
@@ -286,12 +360,20 @@ fn generate_bin_op(left: Expression, op: BinOp, right: Expression) -> String {
     };
     format!(
         "{l} {op} {r}",
-        l = generate_expression(left),
+        l = generate_operand(left),
         op = op_str,
-        r = generate_expression(right)
+        r = generate_operand(right)
     )
 }
 
+/// Nested binary operations are parenthesized, so their precedence is retained
+fn generate_operand(expr: Expression) -> String {
+    match expr {
+        Expression::BinOp(..) => format!("({})", generate_expression(expr)),
+        other => generate_expression(other),
+    }
+}
+
 fn generate_struct_initialization(fields: HashMap<String, Box<Expression>>) -> String {
     let mut buf: String = String::from("{");
 
@@ -15,20 +15,174 @@
  */
 use crate::ast::*;
 use crate::generator::{Generator, GeneratorResult};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use types::Type;
 
 pub struct JsGenerator;
 
+/// How the generated code is packaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModuleFormat {
+    /// CommonJS module that assigns public functions to `module.exports`
+    #[default]
+    Cjs,
+    /// ECMAScript module with a named export of each public function
+    Esm,
+    /// Immediately invoked function expression for `<script>` tags,
+    /// which assigns public functions to the global object
+    Iife,
+}
+
+impl FromStr for ModuleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cjs" => Ok(ModuleFormat::Cjs),
+            "esm" => Ok(ModuleFormat::Esm),
+            "iife" => Ok(ModuleFormat::Iife),
+            _ => Err(format!(
+                "Unknown module format: {}, expected cjs, esm or iife",
+                s
+            )),
+        }
+    }
+}
+
+/// Environment the generated code runs in, which provides printing, file I/O and arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsRuntime {
+    #[default]
+    Node,
+    Deno,
+    /// Web browsers, which print to the console and have no files, arguments or environment
+    Browser,
+}
+
+impl FromStr for JsRuntime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => Ok(JsRuntime::Node),
+            "deno" => Ok(JsRuntime::Deno),
+            "browser" => Ok(JsRuntime::Browser),
+            _ => Err(format!(
+                "Unknown JavaScript runtime: {}, expected node, deno or browser",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for JsRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsRuntime::Node => write!(f, "node"),
+            JsRuntime::Deno => write!(f, "deno"),
+            JsRuntime::Browser => write!(f, "browser"),
+        }
+    }
+}
+
+impl JsRuntime {
+    /// Module format that is used if none is given
+    pub fn default_module_format(&self) -> ModuleFormat {
+        match self {
+            JsRuntime::Node => ModuleFormat::Cjs,
+            JsRuntime::Deno => ModuleFormat::Esm,
+            JsRuntime::Browser => ModuleFormat::Iife,
+        }
+    }
+
+    /// Code that calls the main function of the program. If the program spawns tasks,
+    /// the tasks that are still queued run once `main` has returned. An async `main`
+    /// has returned once its promise is resolved.
+    fn main_call(&self, returns_exit_code: bool, spawns: bool, is_async: bool) -> String {
+        let exit_code = match self {
+            JsRuntime::Node if returns_exit_code => Some("process.exitCode"),
+            JsRuntime::Deno if returns_exit_code => Some("Deno.exitCode"),
+            JsRuntime::Node | JsRuntime::Deno | JsRuntime::Browser => None,
+        };
+        let mut after = Vec::new();
+        if spawns {
+            after.push("_run_tasks();");
+        }
+        if *self == JsRuntime::Browser {
+            after.push("_flush();");
+        }
+
+        if !is_async {
+            let call = match exit_code {
+                Some(exit_code) => format!("{} = main();", exit_code),
+                None => "main();".to_owned(),
+            };
+            return std::iter::once(call.as_str())
+                .chain(after)
+                .collect::<Vec<&str>>()
+                .join("\n");
+        }
+        if exit_code.is_none() && after.is_empty() {
+            return "main();".to_owned();
+        }
+        let (param, assign) = match exit_code {
+            Some(exit_code) => ("code", Some(format!("{} = code;", exit_code))),
+            None => ("", None),
+        };
+        let body: String = assign
+            .iter()
+            .map(String::as_str)
+            .chain(after)
+            .map(|line| format!("  {}\n", line))
+            .collect();
+        format!("main().then(({}) => {{\n{}}});", param, body)
+    }
+}
+
 impl Generator for JsGenerator {
     fn generate(prog: Module) -> GeneratorResult<String> {
+        Self::generate_module(prog, ModuleFormat::default(), JsRuntime::default())
+    }
+}
+
+impl JsGenerator {
+    /// Generates the program as a module of the given format, running in the given runtime
+    pub fn generate_module(
+        prog: Module,
+        format: ModuleFormat,
+        runtime: JsRuntime,
+    ) -> GeneratorResult<String> {
+        // Only Node.js defines `module.exports`
+        if format == ModuleFormat::Cjs && runtime != JsRuntime::Node {
+            return Err(format!(
+                "The cjs module format is not supported by the {} runtime, use esm or iife",
+                runtime
+            ));
+        }
+
         let mut code = String::new();
 
+        // The builtins load Node.js modules with `require`, which does not exist in ES modules
+        if format == ModuleFormat::Esm && runtime == JsRuntime::Node {
+            code += "import { createRequire } from \"module\";\n";
+            code += "const require = createRequire(import.meta.url);\n";
+        }
+
+        let raw_runtime = crate::Builtins::get(&format!("js/{}.js", runtime))
+            .expect("Could not locate runtime functions");
+        let mut builtins = std::str::from_utf8(raw_runtime.as_ref())
+            .expect("Unable to interpret runtime functions")
+            .to_owned();
+
         let raw_builtins =
             crate::Builtins::get("builtin.js").expect("Could not locate builtin functions");
-        code += std::str::from_utf8(raw_builtins.as_ref())
+        builtins += std::str::from_utf8(raw_builtins.as_ref())
             .expect("Unable to interpret builtin functions");
 
+        // The program is generated first, so that only the builtins it uses are included
+        let mut program = String::new();
+
         let structs: String = prog
             .structs
             .clone()
@@ -36,18 +190,128 @@ impl Generator for JsGenerator {
             .map(generate_struct_definition)
             .collect();
 
-        code += &structs;
+        program += &structs;
+
+        let (imports, externs): (Vec<&ExternFunction>, Vec<&ExternFunction>) =
+            prog.externs.iter().partition(|func| func.module.is_some());
+        program += &generate_imports(&imports, format)?;
+        let externs: String = externs.into_iter().map(generate_extern_function).collect();
+        program += &externs;
+
+        // The return value of main is the exit code of the program.
+        // Modules without a main function are libraries.
+        let main = prog.func.iter().find(|func| func.name == "main");
+        let main_signature = main.map(|main| (main.ret_type.is_some(), main.is_async));
+
+        let exports: Vec<String> = prog
+            .func
+            .iter()
+            .filter(|func| func.public)
+            .map(|func| func.name.clone())
+            .collect();
 
         let funcs: String = prog.func.into_iter().map(generate_function).collect();
 
-        code += &funcs;
+        program += &funcs;
 
-        code += "main();";
+        let main_call = match main_signature {
+            Some((returns_exit_code, is_async)) => {
+                let spawns = builtin_names(&program).contains("_spawn");
+                runtime.main_call(returns_exit_code, spawns, is_async)
+            }
+            None => String::new(),
+        };
+
+        // Function declarations are hoisted, so initializers of globals can call them
+        let globals: String = prog.globals.into_iter().map(generate_global).collect();
+        program += &globals;
+
+        if !exports.is_empty() {
+            let exports = exports.join(", ");
+            program += &match format {
+                ModuleFormat::Cjs => format!("module.exports = {{ {} }};\n", exports),
+                ModuleFormat::Esm => format!("export {{ {} }};\n", exports),
+                ModuleFormat::Iife => format!("Object.assign(globalThis, {{ {} }});\n", exports),
+            };
+        }
+        program += &main_call;
+
+        code += &remove_unused_builtins(&builtins, &program);
+        code += &program;
+
+        if format == ModuleFormat::Iife {
+            code = format!("(function () {{\n{}\n}})();\n", code);
+        }
 
         Ok(code)
     }
 }
 
+/// Removes the builtin functions the program does not refer to, directly or through other
+/// builtins. Builtins are declared by top-level `function`s that end with a `}` line.
+/// Other declarations, like variables of a runtime, are always kept.
+fn remove_unused_builtins(builtins: &str, program: &str) -> String {
+    // (function name, code) of each declaration, including the comments in front of it
+    let mut declarations: Vec<(Option<&str>, String)> = Vec::new();
+    let mut current = String::new();
+    let mut function = None;
+    for line in builtins.lines() {
+        if current.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        current += line;
+        current += "\n";
+        if let Some(signature) = line.strip_prefix("function ") {
+            function = signature.split('(').next();
+        }
+        let complete = match function {
+            Some(_) => line == "}",
+            None => line.ends_with(';') || line.ends_with("*/"),
+        };
+        if complete {
+            declarations.push((function.take(), std::mem::take(&mut current)));
+        }
+    }
+
+    let mut used = builtin_names(program);
+    let mut included = vec![false; declarations.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, (name, code)) in declarations.iter().enumerate() {
+            if !included[i] && name.is_none_or(|name| used.contains(name)) {
+                included[i] = true;
+                used.extend(builtin_names(code));
+                changed = true;
+            }
+        }
+    }
+
+    declarations
+        .into_iter()
+        .zip(included)
+        .filter(|(_, included)| *included)
+        .map(|((_, code), _)| code + "\n")
+        .collect()
+}
+
+/// Returns the identifiers in the code that start with an underscore, like the names of builtins
+fn builtin_names(code: &str) -> HashSet<&str> {
+    code.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+        .filter(|word| word.starts_with('_'))
+        .collect()
+}
+
+fn generate_global(global: Global) -> String {
+    let keyword = if global.constant { "const" } else { "let" };
+    format!(
+        "{} {} = {};\n",
+        keyword,
+        global.var.name,
+        generate_expression(global.value)
+    )
+}
+
 fn generate_arguments(args: Vec<Variable>) -> String {
     args.into_iter()
         .map(|var| var.name)
@@ -55,17 +319,72 @@ fn generate_arguments(args: Vec<Variable>) -> String {
         .join(", ")
 }
 
+/// Objects are shared between the caller and the function, so structs that are
+/// passed by value are copied when the function is entered
+fn generate_argument_copies(arguments: &[Variable]) -> Option<String> {
+    let copies: String = arguments
+        .iter()
+        .filter(|arg| !arg.reference && matches!(arg.ty, Some(Type::Struct(_))))
+        .map(|arg| format!("{N} = _copy_struct({N});\n", N = arg.name))
+        .collect();
+    Some(copies).filter(|copies| !copies.is_empty())
+}
+
 fn generate_function(func: Function) -> String {
+    let copies = generate_argument_copies(&func.arguments);
     let arguments: String = generate_arguments(func.arguments);
 
-    let mut raw = format!("function {N}({A})", N = func.name, A = arguments);
+    let keyword = if func.is_async {
+        "async function"
+    } else {
+        "function"
+    };
+    let mut raw = format!("{K} {N}({A})", K = keyword, N = func.name, A = arguments);
 
-    raw += &generate_block(func.body, None);
+    raw += &generate_block(func.body, copies);
     raw += "\n";
     raw
 }
 
+/// External functions are looked up on the global object when they are called.
+/// A function declaration would replace the property of the global object, so a constant is used.
+fn generate_extern_function(func: &ExternFunction) -> String {
+    format!(
+        "const {N} = (...args) => globalThis.{N}(...args);\n",
+        N = func.name
+    )
+}
+
+/// Binds the imported functions of JavaScript modules, grouped by module
+fn generate_imports(imports: &[&ExternFunction], format: ModuleFormat) -> GeneratorResult<String> {
+    let mut modules: Vec<(&str, Vec<&str>)> = Vec::new();
+    for func in imports {
+        let module = func.module.as_deref().unwrap_or_default();
+        match modules.iter_mut().find(|(name, _)| *name == module) {
+            Some((_, names)) => names.push(&func.name),
+            None => modules.push((module, vec![&func.name])),
+        }
+    }
+
+    let mut code = String::new();
+    for (module, names) in modules {
+        let names = names.join(", ");
+        code += &match format {
+            ModuleFormat::Cjs => format!("const {{ {} }} = require(\"{}\");\n", names, module),
+            ModuleFormat::Esm => format!("import {{ {} }} from \"{}\";\n", names, module),
+            ModuleFormat::Iife => {
+                return Err(format!(
+                    "JavaScript module '{}' can not be imported by an IIFE, use the cjs or esm module format",
+                    module
+                ))
+            }
+        };
+    }
+    Ok(code)
+}
+
 fn generate_method(subject: String, func: Function) -> String {
+    let copies = generate_argument_copies(&func.arguments);
     let mut buf = format!(
         "{}.prototype.{} = function({})",
         subject,
@@ -73,7 +392,7 @@ fn generate_method(subject: String, func: Function) -> String {
         generate_arguments(func.arguments)
     );
 
-    buf += &generate_block(func.body, None);
+    buf += &generate_block(func.body, copies);
     buf += "\n";
 
     buf
@@ -115,8 +434,20 @@ fn generate_block(block: Statement, prepend: Option<String>) -> String {
         _ => panic!("Block body should be of type Statement::Block"),
     };
 
+    // Statements following a deferred expression are wrapped in a try-block,
+    // which evaluates the expression when it is left
+    let mut deferred = Vec::new();
     for statement in statements {
-        generated += &generate_statement(statement);
+        match statement {
+            Statement::Defer(expr) => {
+                generated += "try {\n";
+                deferred.push(expr);
+            }
+            statement => generated += &generate_statement(statement),
+        }
+    }
+    for expr in deferred.into_iter().rev() {
+        generated += &format!("}} finally {{\n{};\n}}\n", generate_expression(expr));
     }
 
     generated += "}\n";
@@ -139,6 +470,15 @@ fn generate_statement(statement: Statement) -> String {
         Statement::Continue => generate_continue(),
         Statement::Break => generate_break(),
         Statement::Match(subject, arms) => generate_match(subject, arms),
+        Statement::Spawn(call) => generate_spawn(call),
+        Statement::Defer(_) => unreachable!("Deferred expressions are generated by their block"),
+        Statement::Static(..) => unreachable!("Static variables are moved to the globals"),
+        Statement::Unsafe(_) => unreachable!("Unsafe blocks are rejected by the builder"),
+        // Code of other targets is rejected by the builder
+        Statement::Inline(_, code) => code.trim().to_owned(),
+        Statement::Destructure(..) | Statement::ForPair(..) => {
+            unreachable!("Patterns are desugared by the semantic analysis")
+        }
     };
 
     format!("{};\n", state)
@@ -158,7 +498,43 @@ fn generate_expression(expr: Expression) -> String {
         Expression::StructInitialization(name, fields) => {
             generate_struct_initialization(name, fields)
         }
+        Expression::StructUpdate(name, base, fields) => generate_struct_update(name, *base, fields),
         Expression::FieldAccess(expr, field) => generate_field_access(*expr, *field),
+        Expression::Map(entries) => generate_map(entries),
+        Expression::Try(_) => unreachable!("'?' is lowered by the semantic analysis"),
+        Expression::SizeOf(_) => unreachable!("'size_of' is evaluated by the semantic analysis"),
+        // The parentheses apply field accesses and operators to the awaited value
+        Expression::Await(call) => format!("(await {})", generate_expression(*call)),
+        Expression::Reference(target) => generate_reference(*target),
+        Expression::Dereference(operand) => format!("{}.value", generate_operand(*operand)),
+        Expression::Load(..) | Expression::Store(..) => {
+            unreachable!(
+                "Raw pointers can only be used in unsafe blocks, which are rejected by the builder"
+            )
+        }
+    }
+}
+
+/// References are objects whose `value` property reads and writes the referenced value
+fn generate_reference(target: Expression) -> String {
+    match target {
+        Expression::FieldAccess(obj, field) => match *field {
+            Expression::Variable(field) => {
+                format!("_ref({}, \"{}\")", generate_expression(*obj), field)
+            }
+            _ => unreachable!("Only fields can be referenced"),
+        },
+        Expression::ArrayAccess(name, index) => {
+            format!("_ref({}, {})", name, generate_expression(*index))
+        }
+        // Variables are captured by a closure, since they can not be referenced by a property
+        target => {
+            let var = generate_expression(target);
+            format!(
+                "{{ get value() {{ return {V}; }}, set value(v) {{ {V} = v; }} }}",
+                V = var
+            )
+        }
     }
 }
 
@@ -196,6 +572,16 @@ fn generate_for_loop(ident: Variable, expr: Expression, body: Statement) -> Stri
     out_str
 }
 
+/// Queues the function with the values of its arguments, see `_spawn` of the builtins
+fn generate_spawn(call: Expression) -> String {
+    match call {
+        Expression::FunctionCall(name, args) => {
+            format!("_spawn({}, {})", name, generate_array(args))
+        }
+        _ => unreachable!("Only function calls can be spawned"),
+    }
+}
+
 fn generate_break() -> String {
     "break;\n".into()
 }
@@ -205,6 +591,14 @@ fn generate_continue() -> String {
 }
 
 fn generate_match(subject: Expression, arms: Vec<MatchArm>) -> String {
+    // A `break` inside of a `switch` would leave the `switch` instead of the loop around it
+    let breaks = arms.iter().any(|arm| match arm {
+        MatchArm::Case(_, body) | MatchArm::Else(body) => body.breaks(),
+    });
+    if breaks {
+        return generate_match_chain(subject, arms);
+    }
+
     let mut out_str = format!("switch ({E}) {{\n", E = generate_expression(subject));
     for arm in arms {
         match arm {
@@ -225,6 +619,39 @@ fn generate_match(subject: Expression, arms: Vec<MatchArm>) -> String {
     out_str
 }
 
+/// Generates a `match` statement as a chain of `if` statements, which compare the subject
+/// against the cases in order
+fn generate_match_chain(subject: Expression, arms: Vec<MatchArm>) -> String {
+    let mut out_str = format!(
+        "{{\nconst match_subject = {};\n",
+        generate_expression(subject)
+    );
+    let mut first = true;
+    for arm in arms {
+        match arm {
+            MatchArm::Case(expr, statement) => {
+                if !first {
+                    out_str += " else ";
+                }
+                out_str += &format!(
+                    "if (match_subject === {}) {{\n{}\n}}",
+                    generate_expression(expr),
+                    generate_statement(statement)
+                );
+            }
+            MatchArm::Else(statement) => {
+                if !first {
+                    out_str += " else ";
+                }
+                out_str += &format!("{{\n{}\n}}", generate_statement(statement));
+            }
+        }
+        first = false;
+    }
+    out_str += "\n}";
+    out_str
+}
+
 fn generate_array(elements: Vec<Expression>) -> String {
     let mut out_str = String::from("[");
 
@@ -238,6 +665,21 @@ fn generate_array(elements: Vec<Expression>) -> String {
     out_str
 }
 
+fn generate_map(entries: Vec<(Expression, Expression)>) -> String {
+    let entries = entries
+        .into_iter()
+        .map(|(key, value)| {
+            format!(
+                "[{}, {}]",
+                generate_expression(key),
+                generate_expression(value)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("new Map([{}])", entries)
+}
+
 fn generate_array_access(name: String, expr: Expression) -> String {
     format!("{n}[{e}]", n = name, e = generate_expression(expr))
 }
@@ -287,7 +729,10 @@ fn generate_declare<V: AsRef<Variable>>(identifier: V, val: Option<Expression>)
             // But this works:
             // var x = [];
             // x[0] = 1;
+            // Arrays with a capacity already have elements that can be indexed
+            Some(Type::Array(_, Some(len))) => format!("var {} = new Array({})", ident.name, len),
             Some(Type::Array(_, _)) => format!("var {} = []", ident.name),
+            Some(Type::Map(_, _)) => format!("var {} = new Map()", ident.name),
             _ => format!("var {}", ident.name),
         },
     }
@@ -309,7 +754,20 @@ fn generate_function_call(func: String, args: Vec<Expression>) -> String {
             Expression::StructInitialization(name, fields) => {
                 generate_struct_initialization(name, fields)
             }
+            Expression::StructUpdate(name, base, fields) => {
+                generate_struct_update(name, *base, fields)
+            }
             Expression::FieldAccess(expr, field) => generate_field_access(*expr, *field),
+            Expression::Map(entries) => generate_map(entries),
+            Expression::Try(_) => unreachable!("'?' is lowered by the semantic analysis"),
+            expr @ (Expression::Await(_)
+            | Expression::Reference(_)
+            | Expression::Dereference(_)
+            | Expression::Load(..)
+            | Expression::Store(..)) => generate_expression(expr),
+            Expression::SizeOf(_) => {
+                unreachable!("'size_of' is evaluated by the semantic analysis")
+            }
         })
         .collect::<Vec<String>>()
         .join(",");
@@ -345,12 +803,20 @@ fn generate_bin_op(left: Expression, op: BinOp, right: Expression) -> String {
     };
     format!(
         "{l} {op} {r}",
-        l = generate_expression(left),
+        l = generate_operand(left),
         op = op_str,
-        r = generate_expression(right)
+        r = generate_operand(right)
     )
 }
 
+/// Nested binary operations are parenthesized, so their precedence is retained
+fn generate_operand(expr: Expression) -> String {
+    match expr {
+        Expression::BinOp(..) => format!("({})", generate_expression(expr)),
+        other => generate_expression(other),
+    }
+}
+
 fn generate_struct_initialization(
     name: String,
     fields: HashMap<String, Box<Expression>>,
@@ -365,6 +831,22 @@ fn generate_struct_initialization(
     out_str
 }
 
+/// The constructor of a struct copies the omitted fields from the spread base object
+fn generate_struct_update(
+    name: String,
+    base: Expression,
+    fields: HashMap<String, Box<Expression>>,
+) -> String {
+    let mut out_str = format!("new {}({{...{},", name, generate_expression(base));
+    for (key, value) in fields {
+        out_str += &format!("{}: {},", key, generate_expression(*value));
+    }
+
+    out_str += "})";
+
+    out_str
+}
+
 fn generate_field_access(expr: Expression, field: Expression) -> String {
     format!(
         "{}.{}",
@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 use crate::ast::*;
+use std::fmt;
 use std::path;
 use std::str::FromStr;
 
@@ -21,12 +22,15 @@ pub mod c;
 pub mod js;
 #[cfg(feature = "llvm")]
 pub mod llvm;
+mod peephole;
 pub mod qbe;
+mod temporaries;
 #[cfg(test)]
 mod tests;
+pub mod typescript;
 pub mod x86;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Target {
     C,
     JS,
@@ -36,34 +40,141 @@ pub enum Target {
 }
 
 impl Target {
+    /// All targets, in the order they are presented to the user
+    pub const ALL: [Target; 5] = [
+        Target::JS,
+        Target::C,
+        Target::Qbe,
+        Target::Llvm,
+        Target::X86,
+    ];
+
     /// Constructs target based on provided output filename, returns
     /// None if target can't be detected
     pub fn from_extension(file: &path::Path) -> Option<Self> {
         let ext = file.extension()?;
+        // Extensions of JavaScript modules
+        if ext == "mjs" || ext == "cjs" {
+            return Some(Target::JS);
+        }
 
-        match &*ext.to_string_lossy() {
-            "c" => Some(Self::C),
-            "js" => Some(Self::JS),
-            "ssa" => Some(Self::Qbe),
-            "s" => Some(Self::X86),
-            _ => None,
+        Self::ALL
+            .iter()
+            .find(|target| target.extension() == ext.to_string_lossy())
+            .copied()
+    }
+
+    /// File extension of generated output
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::C => "c",
+            Self::JS => "js",
+            Self::Llvm => "ll",
+            Self::Qbe => "ssa",
+            Self::X86 => "s",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::C => "c",
+            Self::JS => "js",
+            Self::Llvm => "llvm",
+            Self::Qbe => "qbe",
+            Self::X86 => "x86",
         }
     }
 }
 
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl FromStr for Target {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.to_lowercase();
 
-        match s.as_str() {
-            "c" => Ok(Target::C),
-            "js" => Ok(Target::JS),
-            "llvm" => Ok(Target::Llvm),
-            "qbe" => Ok(Target::Qbe),
-            "x86" => Ok(Target::X86),
-            _ => Err(format!("no target {} found", s)),
+        Self::ALL
+            .iter()
+            .find(|target| target.name() == s)
+            .copied()
+            .ok_or_else(|| format!("no target {} found", s))
+    }
+}
+
+/// Processor architecture native code is compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    Amd64,
+    Arm64,
+    Riscv64,
+}
+
+impl Arch {
+    /// Architecture of the machine the compiler runs on. Other hosts default to amd64
+    pub fn host() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            Arch::Arm64
+        } else if cfg!(target_arch = "riscv64") {
+            Arch::Riscv64
+        } else {
+            Arch::Amd64
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::Arm64 => "arm64",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// Target passed to `qbe -t`. The calling convention of Apple platforms differs,
+    /// so programs compiled on macOS use the Apple variants
+    pub fn qbe_target(&self) -> &'static str {
+        let apple = cfg!(target_os = "macos");
+        match self {
+            Arch::Amd64 if apple => "amd64_apple",
+            Arch::Amd64 => "amd64_sysv",
+            Arch::Arm64 if apple => "arm64_apple",
+            Arch::Arm64 => "arm64",
+            Arch::Riscv64 => "rv64",
+        }
+    }
+
+    /// Target triple of the assembler and linker, e.g. `aarch64-linux-gnu`
+    pub fn triple(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "x86_64-linux-gnu",
+            Arch::Arm64 => "aarch64-linux-gnu",
+            Arch::Riscv64 => "riscv64-linux-gnu",
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amd64" => Ok(Arch::Amd64),
+            "arm64" => Ok(Arch::Arm64),
+            "riscv64" => Ok(Arch::Riscv64),
+            _ => Err(format!(
+                "Unknown architecture: {}, expected amd64, arm64 or riscv64",
+                s
+            )),
         }
     }
 }
@@ -86,7 +197,7 @@ pub fn string_syntax(raw: String) -> String {
                 '\u{000C}' => "\\f".to_string(),
                 '\u{0008}' => "\\b".to_string(),
                 '\\' => "\\\\".to_string(),
-                '"' => "\"".to_string(),
+                '"' => "\\\"".to_string(),
                 other => other.to_string(),
             })
             .collect::<String>(),
@@ -0,0 +1,244 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::qbe::ir::{QbeFunction, QbeInstr, QbeStatement, QbeValue};
+use std::collections::{HashMap, HashSet};
+
+/// Cleans up the instructions of a generated QBE function. The passes are repeated
+/// until none of them changes the function anymore.
+///
+/// Functions that contain `qbe!` blocks are left untouched, since the passes do not
+/// know which temporaries and labels the embedded code refers to. So are functions
+/// with phis, whose labels would have to follow the blocks that are merged or removed.
+pub(super) fn optimize(func: &mut QbeFunction) {
+    let skip = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .any(|stmt| {
+            matches!(
+                stmt,
+                QbeStatement::Raw(_) | QbeStatement::Assign(_, _, QbeInstr::Phi(_))
+            )
+        });
+    if skip {
+        return;
+    }
+
+    loop {
+        let mut changed = remove_self_copies(func);
+        changed |= remove_dead_temporaries(func);
+        changed |= thread_jumps(func);
+        changed |= remove_empty_blocks(func);
+        changed |= remove_unreachable_blocks(func);
+        changed |= remove_fallthrough_jumps(func);
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Removes copies of a temporary to itself, like `%a =w copy %a`
+fn remove_self_copies(func: &mut QbeFunction) -> bool {
+    let mut changed = false;
+    for block in &mut func.blocks {
+        block.instructions.retain(|stmt| {
+            let redundant = matches!(
+                stmt,
+                QbeStatement::Assign(QbeValue::Temporary(dest), _, QbeInstr::Copy(QbeValue::Temporary(src)))
+                    if dest == src
+            );
+            changed |= redundant;
+            !redundant
+        });
+    }
+    changed
+}
+
+/// Removes instructions without side effects whose result is never read.
+/// Calls whose result is never read are kept without assigning it.
+fn remove_dead_temporaries(func: &mut QbeFunction) -> bool {
+    let mut used: HashSet<String> = HashSet::new();
+    for stmt in func.blocks.iter().flat_map(|block| &block.instructions) {
+        let instr = match stmt {
+            QbeStatement::Assign(_, _, instr) | QbeStatement::Volatile(instr) => instr,
+            QbeStatement::Raw(_) => continue,
+        };
+        for value in instr.operands() {
+            if let QbeValue::Temporary(name) = value {
+                used.insert(name.clone());
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let instructions = std::mem::take(&mut block.instructions);
+        for stmt in instructions {
+            let dead = match &stmt {
+                QbeStatement::Assign(QbeValue::Temporary(dest), _, _) => !used.contains(dest),
+                _ => false,
+            };
+            match stmt {
+                QbeStatement::Assign(_, _, instr) if dead && instr.is_pure() => changed = true,
+                // Calls are kept for their side effects, without the result
+                QbeStatement::Assign(_, _, instr @ QbeInstr::Call(..)) if dead => {
+                    block.instructions.push(QbeStatement::Volatile(instr));
+                    changed = true;
+                }
+                stmt => block.instructions.push(stmt),
+            }
+        }
+    }
+    changed
+}
+
+/// Redirects jumps to blocks that only jump somewhere else to the final destination
+fn thread_jumps(func: &mut QbeFunction) -> bool {
+    let forwards: HashMap<String, String> = func
+        .blocks
+        .iter()
+        .filter_map(|block| match block.instructions.as_slice() {
+            [QbeStatement::Volatile(QbeInstr::Jmp(target))] if *target != block.label => {
+                Some((block.label.clone(), target.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    let resolve = |label: &str| {
+        let mut label = label;
+        let mut seen = HashSet::new();
+        while let Some(target) = forwards.get(label) {
+            // A cycle of jumps never ends, so it is kept as it is
+            if !seen.insert(label) {
+                break;
+            }
+            label = target;
+        }
+        label.to_owned()
+    };
+    redirect(func, resolve)
+}
+
+/// Removes blocks without instructions, which fall through to the next block.
+/// The first block is kept, since it is the entry of the function, and so is the last one.
+fn remove_empty_blocks(func: &mut QbeFunction) -> bool {
+    let mut forwards: HashMap<String, String> = HashMap::new();
+    let len = func.blocks.len();
+    for i in (1..len.saturating_sub(1)).rev() {
+        if func.blocks[i].instructions.is_empty() {
+            let next = &func.blocks[i + 1].label;
+            let target = forwards.get(next).unwrap_or(next).clone();
+            forwards.insert(func.blocks[i].label.clone(), target);
+        }
+    }
+    if forwards.is_empty() {
+        return false;
+    }
+
+    redirect(func, |label| {
+        forwards
+            .get(label)
+            .cloned()
+            .unwrap_or_else(|| label.to_owned())
+    });
+    func.blocks
+        .retain(|block| !forwards.contains_key(&block.label));
+    true
+}
+
+/// Removes blocks that can not be reached from the entry of the function
+fn remove_unreachable_blocks(func: &mut QbeFunction) -> bool {
+    let index: HashMap<&str, usize> = func
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.label.as_str(), i))
+        .collect();
+
+    let mut reachable = vec![false; func.blocks.len()];
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if i >= func.blocks.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        let block = &func.blocks[i];
+        for stmt in &block.instructions {
+            if let QbeStatement::Volatile(instr) = stmt {
+                pending.extend(instr.targets().iter().filter_map(|l| index.get(l.as_str())));
+            }
+        }
+        if !block.jumps() {
+            pending.push(i + 1);
+        }
+    }
+
+    if reachable.iter().all(|r| *r) {
+        return false;
+    }
+    let mut reachable = reachable.into_iter();
+    func.blocks.retain(|_| reachable.next().unwrap_or(true));
+    true
+}
+
+/// Removes jumps at the end of a block to the block that directly follows it
+fn remove_fallthrough_jumps(func: &mut QbeFunction) -> bool {
+    let mut changed = false;
+    for i in 0..func.blocks.len().saturating_sub(1) {
+        let next = func.blocks[i + 1].label.clone();
+        let block = &mut func.blocks[i];
+        if let Some(QbeStatement::Volatile(QbeInstr::Jmp(target))) = block.instructions.last() {
+            if *target == next {
+                block.instructions.pop();
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Replaces the targets of all jumps. Returns true if any target changed.
+fn redirect(func: &mut QbeFunction, resolve: impl Fn(&str) -> String) -> bool {
+    let update = |label: &mut String| {
+        let target = resolve(label);
+        let changed = target != *label;
+        *label = target;
+        changed
+    };
+    let mut changed = false;
+    for block in &mut func.blocks {
+        for stmt in &mut block.instructions {
+            let collapsed = match stmt {
+                QbeStatement::Volatile(QbeInstr::Jmp(label)) => {
+                    changed |= update(label);
+                    None
+                }
+                QbeStatement::Volatile(QbeInstr::Jnz(_, if_nonzero, if_zero)) => {
+                    changed |= update(if_nonzero);
+                    changed |= update(if_zero);
+                    // Both branches lead to the same block
+                    Some(if_zero.clone()).filter(|label| label == if_nonzero)
+                }
+                _ => None,
+            };
+            if let Some(label) = collapsed {
+                *stmt = QbeStatement::Volatile(QbeInstr::Jmp(label));
+                changed = true;
+            }
+        }
+    }
+    changed
+}
@@ -0,0 +1,403 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::ir::*;
+use super::{bit_instruction, symbol, GeneratorResult, QbeGenerator, VEC_SLOT_SIZE};
+use crate::mir::{self, BinaryOp, BlockId, CompareOp, Inst, Terminator, Ty, Value};
+use std::collections::HashMap;
+
+/// Type of a value in QBE. Booleans are passed around as words, arrays as pointers to
+/// vectors of the runtime.
+fn qbe_type(ty: Ty) -> QbeType {
+    match ty {
+        Ty::Int | Ty::Bool => QbeType::Word,
+        Ty::Str | Ty::Array => QbeType::Long,
+    }
+}
+
+fn label(block: BlockId) -> String {
+    match block.0 {
+        0 => "start".to_owned(),
+        n => format!("block.{}", n),
+    }
+}
+
+/// Switches with at least this many cases are lowered like a jump table, if their cases are dense
+const MIN_TABLE_CASES: usize = 4;
+
+/// Blocks that a switch is lowered to. The first block continues the block of the switch.
+struct SwitchLowering {
+    blocks: Vec<QbeBlock>,
+    /// Target of every jump to a block of the IR, with the label of the block that jumps
+    edges: Vec<(BlockId, String)>,
+}
+
+impl SwitchLowering {
+    /// Jumps from one of the blocks to a block of the IR
+    fn target(&mut self, from: usize, block: BlockId) -> String {
+        self.edges.push((block, self.blocks[from].label.clone()));
+        label(block)
+    }
+
+    fn add_block(&mut self) -> usize {
+        let label = format!("{}.case.{}", self.blocks[0].label, self.blocks.len());
+        self.blocks.push(QbeBlock {
+            label,
+            instructions: Vec::new(),
+        });
+        self.blocks.len() - 1
+    }
+}
+
+impl QbeGenerator {
+    /// Generates a function from its SSA form. Constants and strings are used directly
+    /// as operands, every other value of the IR becomes a temporary.
+    pub(super) fn generate_from_mir(
+        &mut self,
+        func: &mir::Function,
+        exported: bool,
+    ) -> GeneratorResult<QbeFunction> {
+        let mut values: HashMap<Value, QbeValue> = HashMap::new();
+        let mut arguments = Vec::new();
+        for arg in &func.arguments {
+            let tmp = self.new_temporary();
+            values.insert(*arg, tmp.clone());
+            arguments.push((qbe_type(func.ty(*arg)), tmp));
+        }
+        let mut qfunc =
+            QbeFunction::new(symbol(&func.name), arguments, func.ret_type.map(qbe_type));
+        qfunc.exported = exported;
+
+        // Phis may refer to values of blocks that follow them, so all values are named upfront
+        let mut switches: HashMap<BlockId, SwitchLowering> = HashMap::new();
+        for (i, block) in func.blocks.iter().enumerate() {
+            for phi in &block.phis {
+                values.insert(phi.result, self.new_temporary());
+            }
+            for inst in &block.instructions {
+                if let Some(result) = inst.result {
+                    let value = match &inst.inst {
+                        Inst::Int(value) => QbeValue::Const(*value),
+                        Inst::Bool(value) => QbeValue::Const(*value as u64),
+                        Inst::Str(string) => self.generate_string(string)?.1,
+                        _ => self.new_temporary(),
+                    };
+                    values.insert(result, value);
+                }
+            }
+            if let Terminator::Switch(value, cases, default) = &block.terminator {
+                let id = BlockId(i as u32);
+                let lowering = self.lower_switch(id, &values[value], cases, *default);
+                switches.insert(id, lowering);
+            }
+        }
+
+        for (i, block) in func.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            qfunc.add_block(label(id));
+            for phi in &block.phis {
+                let mut incoming: Vec<(String, QbeValue)> = Vec::new();
+                for (pred, value) in &phi.incoming {
+                    // A switch may reach the block from several of the blocks it is lowered to
+                    let labels = match switches.get(pred) {
+                        Some(lowering) => lowering
+                            .edges
+                            .iter()
+                            .filter(|(target, _)| *target == id)
+                            .map(|(_, from)| from.clone())
+                            .collect(),
+                        None => vec![label(*pred)],
+                    };
+                    for from in labels {
+                        if incoming.iter().all(|(other, _)| *other != from) {
+                            incoming.push((from, values[value].clone()));
+                        }
+                    }
+                }
+                qfunc.assign_instr(
+                    values[&phi.result].clone(),
+                    qbe_type(func.ty(phi.result)),
+                    QbeInstr::Phi(incoming),
+                );
+            }
+            for inst in &block.instructions {
+                self.generate_mir_instruction(&mut qfunc, func, inst, &values);
+            }
+            qfunc.add_instr(match &block.terminator {
+                Terminator::Jump(target) => QbeInstr::Jmp(label(*target)),
+                Terminator::Branch(cond, if_true, if_false) => {
+                    QbeInstr::Jnz(values[cond].clone(), label(*if_true), label(*if_false))
+                }
+                Terminator::Return(value) => QbeInstr::Ret(value.map(|v| values[&v].clone())),
+                Terminator::Unreachable => QbeInstr::Hlt,
+                Terminator::Switch(..) => {
+                    let mut blocks = switches
+                        .remove(&id)
+                        .expect("switches are lowered upfront")
+                        .blocks
+                        .into_iter();
+                    let head = blocks.next().expect("switch has a first block");
+                    for stmt in head.instructions {
+                        qfunc.blocks.last_mut().unwrap().instructions.push(stmt);
+                    }
+                    qfunc.blocks.extend(blocks);
+                    continue;
+                }
+            });
+        }
+
+        Ok(qfunc)
+    }
+
+    /// Lowers a switch on an integer. Dense cases are looked up like in a jump table: a single
+    /// comparison sends values outside the range of the cases to the default block, and the
+    /// others find their case by halving the range, instead of comparing against every case.
+    /// QBE has no indirect jumps, so the table is made of branches. Sparse cases are
+    /// compared one after another.
+    fn lower_switch(
+        &mut self,
+        block: BlockId,
+        value: &QbeValue,
+        cases: &[(u64, BlockId)],
+        default: BlockId,
+    ) -> SwitchLowering {
+        let mut lowering = SwitchLowering {
+            blocks: vec![QbeBlock {
+                label: label(block),
+                instructions: Vec::new(),
+            }],
+            edges: Vec::new(),
+        };
+        // The first of several cases with the same value is taken
+        let mut sorted = cases.to_vec();
+        sorted.sort_by_key(|(case, _)| *case);
+        sorted.dedup_by_key(|(case, _)| *case);
+
+        let (min, max) = match (sorted.first(), sorted.last()) {
+            (Some((min, _)), Some((max, _))) => (*min, *max),
+            _ => {
+                let target = lowering.target(0, default);
+                lowering.blocks[0]
+                    .instructions
+                    .push(QbeStatement::Volatile(QbeInstr::Jmp(target)));
+                return lowering;
+            }
+        };
+        let span = (max - min).saturating_add(1);
+        if sorted.len() >= MIN_TABLE_CASES && span <= 2 * sorted.len() as u64 {
+            let table: Vec<BlockId> = (min..=max)
+                .map(|case| {
+                    sorted
+                        .iter()
+                        .find(|(other, _)| *other == case)
+                        .map_or(default, |(_, target)| *target)
+                })
+                .collect();
+            let index = self.new_temporary();
+            let outside = self.new_temporary();
+            let head = &mut lowering.blocks[0].instructions;
+            head.push(QbeStatement::Assign(
+                index.clone(),
+                QbeType::Word,
+                QbeInstr::Sub(value.clone(), QbeValue::Const(min)),
+            ));
+            head.push(QbeStatement::Assign(
+                outside.clone(),
+                QbeType::Word,
+                QbeInstr::Cmp(
+                    QbeType::Word,
+                    QbeCmp::Ugt,
+                    index.clone(),
+                    QbeValue::Const(span - 1),
+                ),
+            ));
+            let if_outside = lowering.target(0, default);
+            let if_inside = self.lower_table(&mut lowering, 0, &index, &table, 0);
+            lowering.blocks[0]
+                .instructions
+                .push(QbeStatement::Volatile(QbeInstr::Jnz(
+                    outside, if_outside, if_inside,
+                )));
+        } else {
+            let mut current = 0;
+            for (i, (case, target)) in sorted.iter().enumerate() {
+                let matches = self.new_temporary();
+                let if_matches = lowering.target(current, *target);
+                let next = if i + 1 < sorted.len() {
+                    let next = lowering.add_block();
+                    lowering.blocks[next].label.clone()
+                } else {
+                    lowering.target(current, default)
+                };
+                let instructions = &mut lowering.blocks[current].instructions;
+                instructions.push(QbeStatement::Assign(
+                    matches.clone(),
+                    QbeType::Word,
+                    QbeInstr::Cmp(
+                        QbeType::Word,
+                        QbeCmp::Eq,
+                        value.clone(),
+                        QbeValue::Const(*case),
+                    ),
+                ));
+                instructions.push(QbeStatement::Volatile(QbeInstr::Jnz(
+                    matches, if_matches, next,
+                )));
+                current = lowering.blocks.len() - 1;
+            }
+        }
+        lowering
+    }
+
+    /// Returns the label to jump to for an index within the table. If the table has
+    /// different targets, the block with the given index compares the index against
+    /// the middle of the table and continues in the half that contains it.
+    fn lower_table(
+        &mut self,
+        lowering: &mut SwitchLowering,
+        from: usize,
+        index: &QbeValue,
+        table: &[BlockId],
+        offset: u64,
+    ) -> String {
+        if table.iter().all(|target| *target == table[0]) {
+            return lowering.target(from, table[0]);
+        }
+        let block = lowering.add_block();
+        let middle = table.len() / 2;
+        let below = self.new_temporary();
+        let if_below = self.lower_table(lowering, block, index, &table[..middle], offset);
+        let if_above = self.lower_table(
+            lowering,
+            block,
+            index,
+            &table[middle..],
+            offset + middle as u64,
+        );
+        let instructions = &mut lowering.blocks[block].instructions;
+        instructions.push(QbeStatement::Assign(
+            below.clone(),
+            QbeType::Word,
+            QbeInstr::Cmp(
+                QbeType::Word,
+                QbeCmp::Ult,
+                index.clone(),
+                QbeValue::Const(offset + middle as u64),
+            ),
+        ));
+        instructions.push(QbeStatement::Volatile(QbeInstr::Jnz(
+            below, if_below, if_above,
+        )));
+        lowering.blocks[block].label.clone()
+    }
+
+    fn generate_mir_instruction(
+        &mut self,
+        qfunc: &mut QbeFunction,
+        func: &mir::Function,
+        inst: &mir::Instruction,
+        values: &HashMap<Value, QbeValue>,
+    ) {
+        let value = |v: &Value| values[v].clone();
+        let typed = |v: &Value| (qbe_type(func.ty(*v)), values[v].clone());
+        let instr = match &inst.inst {
+            // Constants are used as operands directly
+            Inst::Int(_) | Inst::Bool(_) | Inst::Str(_) => return,
+            Inst::Binary(BinaryOp::Add, lhs, rhs) if func.ty(*lhs) == Ty::Str => {
+                QbeInstr::Call("_str_concat".into(), vec![typed(lhs), typed(rhs)])
+            }
+            Inst::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (value(lhs), value(rhs));
+                match op {
+                    BinaryOp::Add => QbeInstr::Add(lhs, rhs),
+                    BinaryOp::Sub => QbeInstr::Sub(lhs, rhs),
+                    BinaryOp::Mul => QbeInstr::Mul(lhs, rhs),
+                    BinaryOp::Div => QbeInstr::Div(lhs, rhs),
+                    BinaryOp::Rem => QbeInstr::Rem(lhs, rhs),
+                    BinaryOp::Shl => QbeInstr::Shl(lhs, rhs),
+                }
+            }
+            Inst::Compare(op, lhs, rhs) if func.ty(*lhs) == Ty::Str => {
+                let eq = QbeInstr::Call("_str_eq".into(), vec![typed(lhs), typed(rhs)]);
+                if *op == CompareOp::Eq {
+                    eq
+                } else {
+                    let tmp = self.new_temporary();
+                    qfunc.assign_instr(tmp.clone(), QbeType::Word, eq);
+                    QbeInstr::Cmp(QbeType::Word, QbeCmp::Eq, tmp, QbeValue::Const(0))
+                }
+            }
+            Inst::Compare(op, lhs, rhs) => {
+                let cmp = match op {
+                    CompareOp::Lt => QbeCmp::Slt,
+                    CompareOp::Le => QbeCmp::Sle,
+                    CompareOp::Gt => QbeCmp::Sgt,
+                    CompareOp::Ge => QbeCmp::Sge,
+                    CompareOp::Eq => QbeCmp::Eq,
+                    CompareOp::Ne => QbeCmp::Ne,
+                };
+                QbeInstr::Cmp(QbeType::Word, cmp, value(lhs), value(rhs))
+            }
+            Inst::Call(name, args) => {
+                let args: Vec<_> = args.iter().map(typed).collect();
+                bit_instruction(name, &args).unwrap_or_else(|| QbeInstr::Call(symbol(name), args))
+            }
+            Inst::Length(array) => QbeInstr::Call("_vec_len".into(), vec![typed(array)]),
+            Inst::Load(array, index) => {
+                let ptr = self.element_ptr(qfunc, value(array), value(index));
+                let ty = qbe_type(func.ty(inst.result.expect("loads have a result")));
+                QbeInstr::Load(ty, ptr)
+            }
+            Inst::Store(array, index, element) => {
+                let ptr = self.element_ptr(qfunc, value(array), value(index));
+                QbeInstr::Store(qbe_type(func.ty(*element)), ptr, value(element))
+            }
+        };
+        match inst.result {
+            Some(result) => qfunc.assign_instr(value(&result), qbe_type(func.ty(result)), instr),
+            None => qfunc.add_instr(instr),
+        }
+    }
+
+    /// Returns a pointer to the element of an array. The elements of a vector of the runtime
+    /// are stored in 8 byte slots, so the index is shifted instead of multiplied.
+    fn element_ptr(
+        &mut self,
+        qfunc: &mut QbeFunction,
+        array: QbeValue,
+        index: QbeValue,
+    ) -> QbeValue {
+        let data = self.new_temporary();
+        qfunc.assign_instr(
+            data.clone(),
+            QbeType::Long,
+            QbeInstr::Load(QbeType::Long, array),
+        );
+        let extended = self.new_temporary();
+        qfunc.assign_instr(extended.clone(), QbeType::Long, QbeInstr::Extsw(index));
+        let offset = self.new_temporary();
+        qfunc.assign_instr(
+            offset.clone(),
+            QbeType::Long,
+            QbeInstr::Shl(
+                extended,
+                QbeValue::Const(VEC_SLOT_SIZE.trailing_zeros() as u64),
+            ),
+        );
+        let ptr = self.new_temporary();
+        qfunc.assign_instr(ptr.clone(), QbeType::Long, QbeInstr::Add(data, offset));
+        ptr
+    }
+}
@@ -0,0 +1,933 @@
+/**
+ * Copyright 2021 Alexey Yerin
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::collections::HashSet;
+use std::fmt;
+
+/// QBE comparision
+#[derive(Debug)]
+pub enum QbeCmp {
+    /// Returns 1 if first value is less than second, respecting signedness
+    Slt,
+    /// Returns 1 if first value is less than or equal to second, respecting signedness
+    Sle,
+    /// Returns 1 if first value is greater than second, respecting signedness
+    Sgt,
+    /// Returns 1 if first value is greater than or equal to second, respecting signedness
+    Sge,
+    /// Returns 1 if first value is less than second, treating both as unsigned
+    Ult,
+    /// Returns 1 if first value is less than or equal to second, treating both as unsigned
+    Ule,
+    /// Returns 1 if first value is greater than second, treating both as unsigned
+    Ugt,
+    /// Returns 1 if first value is greater than or equal to second, treating both as unsigned
+    Uge,
+    /// Returns 1 if values are equal
+    Eq,
+    /// Returns 1 if values are not equal
+    Ne,
+    /// Returns 1 if first floating point value is less than second
+    Lt,
+    /// Returns 1 if first floating point value is less than or equal to second
+    Le,
+    /// Returns 1 if first floating point value is greater than second
+    Gt,
+    /// Returns 1 if first floating point value is greater than or equal to second
+    Ge,
+    /// Returns 1 if neither floating point value is NaN
+    O,
+    /// Returns 1 if at least one floating point value is NaN
+    Uo,
+}
+
+impl QbeCmp {
+    /// Returns true if the comparison can only be applied to floating point values
+    pub fn is_float(&self) -> bool {
+        matches!(
+            self,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge | Self::O | Self::Uo
+        )
+    }
+
+    /// Returns true if the comparison can only be applied to integers
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Self::Slt
+                | Self::Sle
+                | Self::Sgt
+                | Self::Sge
+                | Self::Ult
+                | Self::Ule
+                | Self::Ugt
+                | Self::Uge
+        )
+    }
+}
+
+impl fmt::Display for QbeCmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Slt => "slt",
+            Self::Sle => "sle",
+            Self::Sgt => "sgt",
+            Self::Sge => "sge",
+            Self::Ult => "ult",
+            Self::Ule => "ule",
+            Self::Ugt => "ugt",
+            Self::Uge => "uge",
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Lt => "lt",
+            Self::Le => "le",
+            Self::Gt => "gt",
+            Self::Ge => "ge",
+            Self::O => "o",
+            Self::Uo => "uo",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// QBE instruction
+#[derive(Debug)]
+pub enum QbeInstr {
+    /// Adds values of two temporaries together
+    Add(QbeValue, QbeValue),
+    /// Subtracts the second value from the first one
+    Sub(QbeValue, QbeValue),
+    /// Multiplies values of two temporaries
+    Mul(QbeValue, QbeValue),
+    /// Divides the first value by the second one
+    Div(QbeValue, QbeValue),
+    /// Divides the first value by the second one, treating both as unsigned
+    Udiv(QbeValue, QbeValue),
+    /// Returns a remainder from division
+    Rem(QbeValue, QbeValue),
+    /// Returns a remainder from division, treating both values as unsigned
+    Urem(QbeValue, QbeValue),
+    /// Negates a value
+    Neg(QbeValue),
+    /// Performs a comparion between values
+    Cmp(QbeType, QbeCmp, QbeValue, QbeValue),
+    /// Performs a bitwise AND on values
+    And(QbeValue, QbeValue),
+    /// Performs a bitwise OR on values
+    Or(QbeValue, QbeValue),
+    /// Performs a bitwise XOR on values
+    Xor(QbeValue, QbeValue),
+    /// Shifts the first value right by the second one, copying the sign bit
+    Sar(QbeValue, QbeValue),
+    /// Shifts the first value right by the second one, filling with zeros
+    Shr(QbeValue, QbeValue),
+    /// Shifts the first value left by the second one
+    Shl(QbeValue, QbeValue),
+    /// Copies either a temporary or a literal value
+    Copy(QbeValue),
+    /// Reinterprets the bits of a value as another type of the same size
+    Cast(QbeValue),
+    /// Return from a function, optionally with a value
+    Ret(Option<QbeValue>),
+    /// Jumps to first label if a value is nonzero or to the second one otherwise
+    Jnz(QbeValue, String, String),
+    /// Unconditionally jumps to a label
+    Jmp(String),
+    /// Terminates the program
+    Hlt,
+    /// Selects the value of the block that was executed before the current one.
+    /// Has to precede all other instructions of a block.
+    Phi(Vec<(String, QbeValue)>),
+    /// Calls a function
+    Call(String, Vec<(QbeType, QbeValue)>),
+    /// Allocates a 4-byte aligned area on the stack
+    Alloc4(u64),
+    /// Allocates a 8-byte aligned area on the stack
+    Alloc8(u64),
+    /// Allocates a 16-byte aligned area on the stack
+    Alloc16(u64),
+    /// Stores a value into memory pointed to by destination.
+    /// `(type, destination, value)`
+    Store(QbeType, QbeValue, QbeValue),
+    /// Loads a value from memory pointed to by source. Bytes and halfwords are zero-extended.
+    /// `(type, source)`
+    Load(QbeType, QbeValue),
+    /// Loads a value from memory pointed to by source. Bytes, halfwords and words are sign-extended.
+    /// `(type, source)`
+    LoadSigned(QbeType, QbeValue),
+    /// Copies a number of bytes from the memory pointed to by source to the destination.
+    /// `(source, destination, size)`
+    Blit(QbeValue, QbeValue, u64),
+    /// Sign-extends a word to a long
+    Extsw(QbeValue),
+    /// Zero-extends a word to a long
+    Extuw(QbeValue),
+    /// Sign-extends a halfword
+    Extsh(QbeValue),
+    /// Zero-extends a halfword
+    Extuh(QbeValue),
+    /// Sign-extends a byte
+    Extsb(QbeValue),
+    /// Zero-extends a byte
+    Extub(QbeValue),
+    /// Extends a single to a double
+    Exts(QbeValue),
+    /// Truncates a double to a single
+    Truncd(QbeValue),
+    /// Converts a single to a signed integer
+    Stosi(QbeValue),
+    /// Converts a single to an unsigned integer
+    Stoui(QbeValue),
+    /// Converts a double to a signed integer
+    Dtosi(QbeValue),
+    /// Converts a double to an unsigned integer
+    Dtoui(QbeValue),
+    /// Converts a signed word to a floating point value
+    Swtof(QbeValue),
+    /// Converts an unsigned word to a floating point value
+    Uwtof(QbeValue),
+    /// Converts a signed long to a floating point value
+    Sltof(QbeValue),
+    /// Converts an unsigned long to a floating point value
+    Ultof(QbeValue),
+}
+
+impl QbeInstr {
+    /// Returns true if the instruction ends a block
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Self::Ret(_) | Self::Jmp(_) | Self::Jnz(..) | Self::Hlt
+        )
+    }
+
+    /// Labels the instruction may jump to. The labels of phis name predecessors, not targets.
+    pub fn targets(&self) -> Vec<&String> {
+        match self {
+            Self::Jmp(label) => vec![label],
+            Self::Jnz(_, if_nonzero, if_zero) => vec![if_nonzero, if_zero],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Values the instruction reads
+    pub fn operands(&self) -> Vec<&QbeValue> {
+        match self {
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Udiv(lhs, rhs)
+            | Self::Rem(lhs, rhs)
+            | Self::Urem(lhs, rhs)
+            | Self::Cmp(_, _, lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Sar(lhs, rhs)
+            | Self::Shr(lhs, rhs)
+            | Self::Shl(lhs, rhs)
+            | Self::Store(_, lhs, rhs)
+            | Self::Blit(lhs, rhs, _) => vec![lhs, rhs],
+            Self::Neg(value)
+            | Self::Copy(value)
+            | Self::Cast(value)
+            | Self::Jnz(value, _, _)
+            | Self::Load(_, value)
+            | Self::LoadSigned(_, value)
+            | Self::Extsw(value)
+            | Self::Extuw(value)
+            | Self::Extsh(value)
+            | Self::Extuh(value)
+            | Self::Extsb(value)
+            | Self::Extub(value)
+            | Self::Exts(value)
+            | Self::Truncd(value)
+            | Self::Stosi(value)
+            | Self::Stoui(value)
+            | Self::Dtosi(value)
+            | Self::Dtoui(value)
+            | Self::Swtof(value)
+            | Self::Uwtof(value)
+            | Self::Sltof(value)
+            | Self::Ultof(value) => vec![value],
+            Self::Ret(value) => value.iter().collect(),
+            Self::Phi(incoming) => incoming.iter().map(|(_, value)| value).collect(),
+            Self::Call(_, args) => args.iter().map(|(_, value)| value).collect(),
+            Self::Jmp(_) | Self::Hlt | Self::Alloc4(_) | Self::Alloc8(_) | Self::Alloc16(_) => {
+                Vec::new()
+            }
+        }
+    }
+
+    /// Mutable references to the values the instruction reads
+    pub fn operands_mut(&mut self) -> Vec<&mut QbeValue> {
+        match self {
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Udiv(lhs, rhs)
+            | Self::Rem(lhs, rhs)
+            | Self::Urem(lhs, rhs)
+            | Self::Cmp(_, _, lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Sar(lhs, rhs)
+            | Self::Shr(lhs, rhs)
+            | Self::Shl(lhs, rhs)
+            | Self::Store(_, lhs, rhs)
+            | Self::Blit(lhs, rhs, _) => vec![lhs, rhs],
+            Self::Neg(value)
+            | Self::Copy(value)
+            | Self::Cast(value)
+            | Self::Jnz(value, _, _)
+            | Self::Load(_, value)
+            | Self::LoadSigned(_, value)
+            | Self::Extsw(value)
+            | Self::Extuw(value)
+            | Self::Extsh(value)
+            | Self::Extuh(value)
+            | Self::Extsb(value)
+            | Self::Extub(value)
+            | Self::Exts(value)
+            | Self::Truncd(value)
+            | Self::Stosi(value)
+            | Self::Stoui(value)
+            | Self::Dtosi(value)
+            | Self::Dtoui(value)
+            | Self::Swtof(value)
+            | Self::Uwtof(value)
+            | Self::Sltof(value)
+            | Self::Ultof(value) => vec![value],
+            Self::Ret(value) => value.iter_mut().collect(),
+            Self::Phi(incoming) => incoming.iter_mut().map(|(_, value)| value).collect(),
+            Self::Call(_, args) => args.iter_mut().map(|(_, value)| value).collect(),
+            Self::Jmp(_) | Self::Hlt | Self::Alloc4(_) | Self::Alloc8(_) | Self::Alloc16(_) => {
+                Vec::new()
+            }
+        }
+    }
+
+    /// Returns true if the instruction only computes its result. Divisions are not pure,
+    /// because they trap if the divisor is zero.
+    pub fn is_pure(&self) -> bool {
+        !matches!(
+            self,
+            Self::Call(..)
+                | Self::Div(..)
+                | Self::Udiv(..)
+                | Self::Rem(..)
+                | Self::Urem(..)
+                | Self::Store(..)
+                | Self::Blit(..)
+                | Self::Ret(_)
+                | Self::Jmp(_)
+                | Self::Jnz(..)
+                | Self::Hlt
+        )
+    }
+}
+
+impl fmt::Display for QbeInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add(lhs, rhs) => write!(f, "add {}, {}", lhs, rhs),
+            Self::Sub(lhs, rhs) => write!(f, "sub {}, {}", lhs, rhs),
+            Self::Mul(lhs, rhs) => write!(f, "mul {}, {}", lhs, rhs),
+            Self::Div(lhs, rhs) => write!(f, "div {}, {}", lhs, rhs),
+            Self::Udiv(lhs, rhs) => write!(f, "udiv {}, {}", lhs, rhs),
+            Self::Rem(lhs, rhs) => write!(f, "rem {}, {}", lhs, rhs),
+            Self::Urem(lhs, rhs) => write!(f, "urem {}, {}", lhs, rhs),
+            Self::Neg(val) => write!(f, "neg {}", val),
+            Self::Cmp(ty, cmp, lhs, rhs) => {
+                assert!(
+                    !matches!(ty, QbeType::Aggregate(_)),
+                    "Cannot compare aggregate types"
+                );
+
+                write!(f, "c{}{} {}, {}", cmp, ty, lhs, rhs)
+            }
+            Self::And(lhs, rhs) => write!(f, "and {}, {}", lhs, rhs),
+            Self::Or(lhs, rhs) => write!(f, "or {}, {}", lhs, rhs),
+            Self::Xor(lhs, rhs) => write!(f, "xor {}, {}", lhs, rhs),
+            Self::Sar(lhs, rhs) => write!(f, "sar {}, {}", lhs, rhs),
+            Self::Shr(lhs, rhs) => write!(f, "shr {}, {}", lhs, rhs),
+            Self::Shl(lhs, rhs) => write!(f, "shl {}, {}", lhs, rhs),
+            Self::Copy(val) => write!(f, "copy {}", val),
+            Self::Cast(val) => write!(f, "cast {}", val),
+            Self::Ret(val) => match val {
+                Some(val) => write!(f, "ret {}", val),
+                None => write!(f, "ret"),
+            },
+            Self::Jnz(val, if_nonzero, if_zero) => {
+                write!(f, "jnz {}, @{}, @{}", val, if_nonzero, if_zero)
+            }
+            Self::Jmp(label) => write!(f, "jmp @{}", label),
+            Self::Hlt => write!(f, "hlt"),
+            Self::Phi(incoming) => write!(
+                f,
+                "phi {}",
+                incoming
+                    .iter()
+                    .map(|(label, value)| format!("@{} {}", label, value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::Call(name, args) => {
+                write!(
+                    f,
+                    "call ${}({})",
+                    name,
+                    args.iter()
+                        .map(|(ty, temp)| format!("{} {}", ty, temp))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                )
+            }
+            Self::Alloc4(size) => write!(f, "alloc4 {}", size),
+            Self::Alloc8(size) => write!(f, "alloc8 {}", size),
+            Self::Alloc16(size) => write!(f, "alloc16 {}", size),
+            Self::Store(ty, dest, value) => {
+                if matches!(ty, QbeType::Aggregate(_)) {
+                    unimplemented!("Store to an aggregate type");
+                }
+
+                write!(f, "store{} {}, {}", ty, value, dest)
+            }
+            Self::Load(ty, src) | Self::LoadSigned(ty, src) => {
+                if matches!(ty, QbeType::Aggregate(_)) {
+                    unimplemented!("Load aggregate type");
+                }
+
+                let sign = if matches!(self, Self::LoadSigned(..)) {
+                    "s"
+                } else {
+                    "u"
+                };
+                match ty {
+                    QbeType::Byte | QbeType::Halfword => write!(f, "load{}{} {}", sign, ty, src),
+                    QbeType::Word if sign == "s" => write!(f, "loadsw {}", src),
+                    _ => write!(f, "load{} {}", ty, src),
+                }
+            }
+            Self::Blit(src, dest, size) => write!(f, "blit {}, {}, {}", src, dest, size),
+            Self::Extsw(val) => write!(f, "extsw {}", val),
+            Self::Extuw(val) => write!(f, "extuw {}", val),
+            Self::Extsh(val) => write!(f, "extsh {}", val),
+            Self::Extuh(val) => write!(f, "extuh {}", val),
+            Self::Extsb(val) => write!(f, "extsb {}", val),
+            Self::Extub(val) => write!(f, "extub {}", val),
+            Self::Exts(val) => write!(f, "exts {}", val),
+            Self::Truncd(val) => write!(f, "truncd {}", val),
+            Self::Stosi(val) => write!(f, "stosi {}", val),
+            Self::Stoui(val) => write!(f, "stoui {}", val),
+            Self::Dtosi(val) => write!(f, "dtosi {}", val),
+            Self::Dtoui(val) => write!(f, "dtoui {}", val),
+            Self::Swtof(val) => write!(f, "swtof {}", val),
+            Self::Uwtof(val) => write!(f, "uwtof {}", val),
+            Self::Sltof(val) => write!(f, "sltof {}", val),
+            Self::Ultof(val) => write!(f, "ultof {}", val),
+        }
+    }
+}
+
+/// QBE type
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[allow(dead_code)]
+pub enum QbeType {
+    // Base types
+    Word,
+    Long,
+    Single,
+    Double,
+
+    // Extended types
+    Byte,
+    Halfword,
+
+    /// Aggregate type with a specified name
+    Aggregate(String),
+}
+
+impl QbeType {
+    /// Returns a C ABI type. Extended types are converted to closest base
+    /// types
+    pub fn into_abi(self) -> Self {
+        match self {
+            Self::Byte | Self::Halfword => Self::Word,
+            other => other,
+        }
+    }
+
+    /// Returns the closest base type
+    pub fn into_base(self) -> Self {
+        match self {
+            Self::Byte | Self::Halfword => Self::Word,
+            Self::Aggregate(_) => Self::Long,
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for QbeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Word => write!(f, "w"),
+            Self::Long => write!(f, "l"),
+            Self::Single => write!(f, "s"),
+            Self::Double => write!(f, "d"),
+
+            Self::Byte => write!(f, "b"),
+            Self::Halfword => write!(f, "h"),
+
+            Self::Aggregate(name) => write!(f, ":{}", name),
+        }
+    }
+}
+
+/// QBE value that is accepted by instructions
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum QbeValue {
+    /// `%`-temporary
+    Temporary(String),
+    /// `$`-global
+    Global(String),
+    /// Constant
+    Const(u64),
+}
+
+impl fmt::Display for QbeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Temporary(name) => write!(f, "%{}", name),
+            Self::Global(name) => write!(f, "${}", name),
+            Self::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// QBE data definition
+#[derive(Debug)]
+pub struct QbeDataDef {
+    pub exported: bool,
+    pub name: String,
+    pub align: Option<u64>,
+
+    pub items: Vec<(QbeType, QbeDataItem)>,
+}
+
+impl QbeDataDef {
+    /// Creates a data definition that is local to the compilation unit
+    pub fn new(name: impl Into<String>, items: Vec<(QbeType, QbeDataItem)>) -> Self {
+        Self {
+            exported: false,
+            name: name.into(),
+            align: None,
+            items,
+        }
+    }
+
+    /// Makes the data available to other compilation units
+    pub fn export(mut self) -> Self {
+        self.exported = true;
+        self
+    }
+
+    /// Aligns the data to a number of bytes
+    pub fn align(mut self, align: u64) -> Self {
+        self.align = Some(align);
+        self
+    }
+}
+
+impl fmt::Display for QbeDataDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exported {
+            write!(f, "export ")?;
+        }
+
+        write!(f, "data ${} = ", self.name)?;
+
+        if let Some(align) = self.align {
+            write!(f, "align {} ", align)?;
+        }
+        write!(
+            f,
+            "{{ {} }}",
+            self.items
+                .iter()
+                .map(|(ty, item)| format!("{} {}", ty, item))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+/// Data definition item
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum QbeDataItem {
+    /// Symbol and offset
+    Symbol(String, Option<u64>),
+    /// String
+    Str(String),
+    /// Constant
+    Const(u64),
+    /// Number of zero bytes
+    Zero(u64),
+}
+
+impl fmt::Display for QbeDataItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Symbol(name, offset) => match offset {
+                Some(off) => write!(f, "${} +{}", name, off),
+                None => write!(f, "${}", name),
+            },
+            Self::Str(string) => write!(f, "\"{}\"", string),
+            Self::Const(val) => write!(f, "{}", val),
+            Self::Zero(size) => write!(f, "z {}", size),
+        }
+    }
+}
+
+/// QBE aggregate type definition
+#[derive(Debug)]
+pub struct QbeTypeDef {
+    pub name: String,
+    pub align: Option<u64>,
+    // TODO: Opaque types?
+    pub items: Vec<(QbeType, usize)>,
+}
+
+impl QbeTypeDef {
+    /// Creates an aggregate type without fields
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            align: None,
+            items: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for QbeTypeDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "type :{} = ", self.name)?;
+        if let Some(align) = self.align {
+            write!(f, "align {} ", align)?;
+        }
+
+        write!(
+            f,
+            "{{ {} }}",
+            self.items
+                .iter()
+                .map(|(ty, count)| if *count > 1 {
+                    format!("{} {}", ty, count)
+                } else {
+                    format!("{}", ty)
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+        )
+    }
+}
+
+/// An IR statement
+#[derive(Debug)]
+pub enum QbeStatement {
+    Assign(QbeValue, QbeType, QbeInstr),
+    Volatile(QbeInstr),
+    /// Line of a `qbe!` block, which is passed through verbatim
+    Raw(String),
+}
+
+impl fmt::Display for QbeStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Assign(temp, ty, instr) => {
+                assert!(matches!(temp, QbeValue::Temporary(_)));
+                write!(f, "{} ={} {}", temp, ty, instr)
+            }
+            Self::Volatile(instr) => write!(f, "{}", instr),
+            Self::Raw(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+/// Function block with a label
+#[derive(Debug)]
+pub struct QbeBlock {
+    /// Label before the block
+    pub label: String,
+
+    /// A list of instructions in the block
+    pub instructions: Vec<QbeStatement>,
+}
+
+impl QbeBlock {
+    /// Adds a new instruction to the block
+    pub fn add_instr(&mut self, instr: QbeInstr) {
+        self.instructions.push(QbeStatement::Volatile(instr));
+    }
+
+    /// Adds a new instruction assigned to a temporary
+    pub fn assign_instr(&mut self, temp: QbeValue, ty: QbeType, instr: QbeInstr) {
+        self.instructions
+            .push(QbeStatement::Assign(temp, ty.into_base(), instr));
+    }
+
+    /// Returns true if the block's last instruction is a jump
+    pub fn jumps(&self) -> bool {
+        let last = self.instructions.last();
+
+        if let Some(QbeStatement::Volatile(instr)) = last {
+            instr.is_jump()
+        } else {
+            false
+        }
+    }
+}
+
+impl fmt::Display for QbeBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "@{}", self.label)?;
+
+        write!(
+            f,
+            "{}",
+            self.instructions
+                .iter()
+                .map(|instr| format!("\t{}", instr))
+                .collect::<Vec<String>>()
+                .join("\n")
+        )
+    }
+}
+
+/// QBE function
+#[derive(Debug)]
+pub struct QbeFunction {
+    /// Should the function be available to outside users
+    pub exported: bool,
+
+    /// Function name
+    pub name: String,
+
+    /// Function arguments
+    pub arguments: Vec<(QbeType, QbeValue)>,
+
+    /// Return type
+    pub return_ty: Option<QbeType>,
+
+    /// Labelled blocks
+    pub blocks: Vec<QbeBlock>,
+}
+
+impl QbeFunction {
+    /// Creates a function without blocks that is local to the compilation unit
+    pub fn new(
+        name: impl Into<String>,
+        arguments: Vec<(QbeType, QbeValue)>,
+        return_ty: Option<QbeType>,
+    ) -> Self {
+        Self {
+            exported: false,
+            name: name.into(),
+            arguments,
+            return_ty,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Makes the function available to other compilation units
+    pub fn export(mut self) -> Self {
+        self.exported = true;
+        self
+    }
+
+    /// Adds a new empty block with a specified label
+    pub fn add_block(&mut self, label: String) {
+        self.blocks.push(QbeBlock {
+            label,
+            instructions: Vec::new(),
+        });
+    }
+
+    pub fn last_block(&mut self) -> &QbeBlock {
+        self.blocks
+            .last()
+            .expect("Function must have at least one block")
+    }
+
+    /// Adds a new instruction to the last block
+    pub fn add_instr(&mut self, instr: QbeInstr) {
+        self.blocks
+            .last_mut()
+            .expect("Last block must be present")
+            .add_instr(instr);
+    }
+
+    /// Adds a line of code to the last block
+    pub fn add_raw(&mut self, line: String) {
+        self.blocks
+            .last_mut()
+            .expect("Last block must be present")
+            .instructions
+            .push(QbeStatement::Raw(line));
+    }
+
+    /// Adds a new instruction assigned to a temporary
+    pub fn assign_instr(&mut self, temp: QbeValue, ty: QbeType, instr: QbeInstr) {
+        self.blocks
+            .last_mut()
+            .expect("Last block must be present")
+            .assign_instr(temp, ty, instr);
+    }
+
+    /// Checks that the function is well-formed, so that QBE accepts it. Returns a
+    /// description of the first problem that is found.
+    ///
+    /// Functions that contain `qbe!` blocks are not checked, since their lines are not parsed.
+    pub fn validate(&self) -> Result<(), String> {
+        let statements = self.blocks.iter().flat_map(|block| &block.instructions);
+        if statements
+            .clone()
+            .any(|stmt| matches!(stmt, QbeStatement::Raw(_)))
+        {
+            return Ok(());
+        }
+
+        let first = self
+            .blocks
+            .first()
+            .ok_or_else(|| "Function has no blocks".to_owned())?;
+        let mut labels = HashSet::new();
+        for block in &self.blocks {
+            if !labels.insert(block.label.as_str()) {
+                return Err(format!("Label '@{}' is defined twice", block.label));
+            }
+        }
+
+        let mut defined: HashSet<&str> = HashSet::new();
+        for (_, arg) in &self.arguments {
+            if let QbeValue::Temporary(name) = arg {
+                defined.insert(name);
+            }
+        }
+        for stmt in statements.clone() {
+            if let QbeStatement::Assign(QbeValue::Temporary(name), _, _) = stmt {
+                defined.insert(name);
+            }
+        }
+
+        for block in &self.blocks {
+            let mut leading_phis = true;
+            for (i, stmt) in block.instructions.iter().enumerate() {
+                let instr = match stmt {
+                    QbeStatement::Assign(temp, ty, instr) => {
+                        if !matches!(temp, QbeValue::Temporary(_)) {
+                            return Err(format!("Can not assign to '{}'", temp));
+                        }
+                        if let QbeType::Aggregate(_) = ty {
+                            return Err(format!("Temporary '{}' has an aggregate type", temp));
+                        }
+                        instr
+                    }
+                    QbeStatement::Volatile(instr) => instr,
+                    QbeStatement::Raw(_) => continue,
+                };
+
+                let is_phi = matches!(instr, QbeInstr::Phi(_));
+                if is_phi && !leading_phis {
+                    return Err(format!(
+                        "Phi in block '@{}' follows other instructions",
+                        block.label
+                    ));
+                }
+                leading_phis &= is_phi;
+                if instr.is_jump() && i + 1 != block.instructions.len() {
+                    return Err(format!(
+                        "Jump in block '@{}' is followed by other instructions",
+                        block.label
+                    ));
+                }
+                if let QbeInstr::Cmp(ty, ..) | QbeInstr::Load(ty, _) | QbeInstr::Store(ty, ..) =
+                    instr
+                {
+                    if let QbeType::Aggregate(_) = ty {
+                        return Err(format!("'{}' can not operate on aggregate types", instr));
+                    }
+                }
+                if let QbeInstr::Jmp(_) | QbeInstr::Jnz(..) = instr {
+                    for target in instr.targets() {
+                        if !labels.contains(target.as_str()) {
+                            return Err(format!("Jump to undefined label '@{}'", target));
+                        }
+                        if *target == first.label {
+                            return Err(format!("Jump to the start block '@{}'", target));
+                        }
+                    }
+                }
+                for value in instr.operands() {
+                    if let QbeValue::Temporary(name) = value {
+                        if !defined.contains(name.as_str()) {
+                            return Err(format!("Temporary '%{}' is never defined", name));
+                        }
+                    }
+                }
+            }
+        }
+
+        if !self.blocks.last().is_some_and(|block| block.jumps()) {
+            return Err("Last block does not end with a jump".to_owned());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for QbeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exported {
+            write!(f, "export ")?;
+        }
+        write!(f, "function")?;
+        if let Some(ty) = &self.return_ty {
+            write!(f, " {}", ty)?;
+        }
+
+        writeln!(
+            f,
+            " ${name}({args}) {{",
+            name = self.name,
+            args = self
+                .arguments
+                .iter()
+                .map(|(ty, temp)| format!("{} {}", ty, temp))
+                .collect::<Vec<String>>()
+                .join(", "),
+        )?;
+
+        for blk in self.blocks.iter() {
+            writeln!(f, "{}", blk)?;
+        }
+
+        write!(f, "}}")
+    }
+}
@@ -0,0 +1,1877 @@
+/**
+ * Copyright 2021 Alexey Yerin
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::{peephole, temporaries, Arch, Generator, GeneratorResult};
+use crate::ast::types::Type;
+use crate::ast::*;
+use crate::layout::Layouts;
+use crate::mir;
+use crate::optimizer;
+use crate::semantic::types::TypeEnv;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+
+mod from_mir;
+pub mod ir;
+mod references;
+use ir::*;
+
+/// Size of an element of a vector in the runtime
+const VEC_SLOT_SIZE: u64 = 8;
+
+/// Symbol of the program's `main` function. The exported `main` symbol is
+/// a generated entry point that initializes the runtime before calling it.
+const MAIN_SYMBOL: &str = "_sb_main";
+
+/// Symbol of the function that evaluates the initializers of global variables
+/// that are not known at compile time
+const INIT_GLOBALS_SYMBOL: &str = "_sb_init_globals";
+
+pub struct QbeGenerator {
+    /// Counters for unique names within the function that is currently generated
+    names: NameCounters,
+    /// Block-scoped variable -> temporary mappings
+    scopes: Vec<HashMap<String, (QbeType, QbeValue)>>,
+    /// Global variable -> type mappings. Globals are stored in `$global.<name>`.
+    globals: HashMap<String, QbeType>,
+    /// Structure -> (type, meta data, size) mappings
+    struct_map: HashMap<String, (QbeType, StructMeta, u64)>,
+    /// Sizes and field offsets of the structures
+    layouts: Layouts,
+    /// Label prefix of loop scopes and the number of blocks that enclose them
+    loop_labels: Vec<(String, usize)>,
+    /// Deferred expressions of the blocks that are currently generated
+    defers: Vec<Vec<Expression>>,
+    /// Data defintions collected during generation
+    datadefs: Vec<QbeDataDef>,
+    /// Type defintions collected during generation
+    typedefs: Vec<QbeTypeDef>,
+    /// Functions that run spawned calls, collected during generation
+    tasks: Vec<QbeFunction>,
+    /// Name and arguments of the function that is currently generated,
+    /// used to lower self-recursive tail calls
+    current_func: Option<(String, Vec<(QbeType, QbeValue)>)>,
+    /// Types of the expressions in the current function
+    types: TypeEnv,
+    /// Optimization level the functions of the IR are optimized with
+    opt_level: u8,
+    /// Function -> (arrays that do not escape the function) mappings.
+    /// These arrays are allocated on the stack of the function.
+    stack_arrays: HashMap<String, HashSet<String>>,
+    /// Function -> (whether each argument is passed by reference) mappings.
+    /// Structures that are passed by reference are passed as a pointer instead of a copy.
+    references: HashMap<String, Vec<bool>>,
+    /// Variables of the current function whose address is taken. Unless they are structures,
+    /// which are always stored in memory, their temporary holds the address of a stack slot.
+    addressed: HashSet<String>,
+}
+
+/// Mapping of field -> (type, offset)
+type StructMeta = HashMap<String, (QbeType, u64)>;
+
+/// Counters for unique names within a function. They start over in every function,
+/// so that the names of a function do not change when other functions change or move.
+#[derive(Default)]
+struct NameCounters {
+    /// Name of the function, which prefixes the data definitions of its strings
+    function: String,
+    temporaries: u32,
+    /// `if` statements, in the order they appear in the function
+    conditions: u32,
+    /// Loops, in the order they appear in the function
+    loops: u32,
+    /// `match` statements, in the order they appear in the function
+    matches: u32,
+    /// `&&` and `||` operators, in the order they appear in the function
+    logical: u32,
+    /// `spawn` statements, in the order they appear in the function
+    spawns: u32,
+    strings: u32,
+}
+
+impl NameCounters {
+    fn new(function: &str) -> Self {
+        NameCounters {
+            function: function.to_owned(),
+            ..NameCounters::default()
+        }
+    }
+}
+
+impl Generator for QbeGenerator {
+    fn generate(prog: Module) -> GeneratorResult<String> {
+        Self::generate_for(prog, Arch::host(), 0)
+    }
+}
+
+impl QbeGenerator {
+    /// Generates the program for the given architecture, which determines the layout of structures.
+    /// From optimization level 1, functions are optimized in the mid-level IR, and the instructions
+    /// of the other functions are cleaned up by a peephole pass. Temporaries whose value is no
+    /// longer needed are then reused for later values.
+    pub fn generate_for(prog: Module, arch: Arch, opt_level: u8) -> GeneratorResult<String> {
+        let mut generator = QbeGenerator {
+            names: NameCounters::default(),
+            scopes: Vec::new(),
+            globals: HashMap::new(),
+            struct_map: HashMap::new(),
+            loop_labels: Vec::new(),
+            defers: Vec::new(),
+            datadefs: Vec::new(),
+            typedefs: Vec::new(),
+            tasks: Vec::new(),
+            current_func: None,
+            types: TypeEnv::new(&prog),
+            opt_level,
+            stack_arrays: optimizer::stack_arrays(&prog),
+            references: prog
+                .func
+                .iter()
+                .map(|func| {
+                    let references = func.arguments.iter().map(|arg| arg.reference);
+                    (func.name.clone(), references.collect())
+                })
+                .collect(),
+            addressed: HashSet::new(),
+            layouts: Layouts::for_arch(&prog.structs, arch),
+        };
+
+        let raw_builtins =
+            crate::Builtins::get("builtin.ssa").expect("Could not locate builtin functions");
+        let mut buf = std::str::from_utf8(raw_builtins.as_ref())
+            .expect("Unable to interpret builtin functions")
+            .to_owned();
+
+        for def in embedding_order(&prog.structs) {
+            let structure = generator.generate_struct(def)?;
+
+            #[cfg(debug_assertions)]
+            {
+                // Just in case it incorrectly calculates offsets
+                let (_, meta, size) = generator.struct_map.get(&def.name).unwrap();
+                buf.push_str(&format!("# size: {}\n", size));
+                buf.push_str(&format!("# meta: {:?}\n", meta));
+            }
+            buf.push_str(&format!("{}\n", structure));
+        }
+
+        let init_globals = generator.generate_globals(&prog.globals)?;
+        if let Some(init_globals) = &init_globals {
+            validate(init_globals)?;
+            buf.push_str(&format!("{}\n", init_globals));
+        }
+
+        for func in &prog.func {
+            let mut func = generator.generate_function(func)?;
+            if opt_level > 0 {
+                peephole::optimize(&mut func);
+                temporaries::reuse_temporaries(&mut func);
+            }
+            validate(&func)?;
+            buf.push_str(&format!("{}\n", func));
+        }
+
+        for task in &generator.tasks {
+            validate(task)?;
+            buf.push_str(&format!("{}\n", task));
+        }
+
+        if let Some(main) = prog.func.iter().find(|func| func.name == "main") {
+            let entry_point = generate_entry_point(
+                main.ret_type.is_some(),
+                init_globals.is_some(),
+                !generator.tasks.is_empty(),
+            );
+            buf.push_str(&format!("{}\n", entry_point));
+        }
+
+        for def in &generator.typedefs {
+            buf.push_str(&format!("{}\n", def));
+        }
+
+        for def in &generator.datadefs {
+            buf.push_str(&format!("{}\n", def));
+        }
+
+        Ok(buf)
+    }
+}
+
+/// Returns the symbol of a function
+fn symbol(name: &str) -> String {
+    match name {
+        "main" => MAIN_SYMBOL.to_owned(),
+        _ => name.to_owned(),
+    }
+}
+
+/// Returns the instruction that a call of a bitwise builtin like `_bit_and` is lowered to,
+/// instead of calling the runtime. QBE masks the amount of a shift of a word to 5 bits.
+fn bit_instruction(name: &str, args: &[(QbeType, QbeValue)]) -> Option<QbeInstr> {
+    let (lhs, rhs) = match args {
+        [(_, lhs), (_, rhs)] => (lhs.clone(), rhs.clone()),
+        _ => return None,
+    };
+    match name {
+        "_bit_and" => Some(QbeInstr::And(lhs, rhs)),
+        "_bit_or" => Some(QbeInstr::Or(lhs, rhs)),
+        "_bit_xor" => Some(QbeInstr::Xor(lhs, rhs)),
+        "_shift_left" => Some(QbeInstr::Shl(lhs, rhs)),
+        "_shift_right" => Some(QbeInstr::Shr(lhs, rhs)),
+        _ => None,
+    }
+}
+
+/// Returns the symbol of the memory that holds a global variable
+fn global_symbol(name: &str) -> QbeValue {
+    QbeValue::Global(format!("global.{}", name))
+}
+
+/// Orders structures so that each one follows the structures of its fields,
+/// since the type of a field has to be known when its structure is generated
+fn embedding_order(structs: &[StructDef]) -> Vec<&StructDef> {
+    fn visit<'a>(
+        def: &'a StructDef,
+        by_name: &HashMap<&str, &'a StructDef>,
+        visited: &mut HashSet<&'a str>,
+        ordered: &mut Vec<&'a StructDef>,
+    ) {
+        if !visited.insert(&def.name) {
+            return;
+        }
+        for field in &def.fields {
+            if let Some(Type::Struct(name)) = &field.ty {
+                if let Some(embedded) = by_name.get(name.as_str()) {
+                    visit(embedded, by_name, visited, ordered);
+                }
+            }
+        }
+        ordered.push(def);
+    }
+
+    let by_name: HashMap<&str, &StructDef> =
+        structs.iter().map(|def| (def.name.as_str(), def)).collect();
+    let mut visited = HashSet::new();
+    let mut ordered = Vec::new();
+    for def in structs {
+        visit(def, &by_name, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+/// Rejects functions that QBE would not accept, which indicates a bug in the generator
+fn validate(func: &QbeFunction) -> GeneratorResult<()> {
+    func.validate()
+        .map_err(|err| format!("Invalid QBE IR in function '{}': {}", func.name, err))
+}
+
+/// Generates the `main` function called by the C runtime, which passes
+/// the command line arguments to the runtime and calls the program's `main` function.
+/// If `returns_code` is set, the return value of the program's `main` is the exit code.
+/// If `init_globals` is set, global variables are initialized before `main` is called.
+/// If `run_tasks` is set, the spawned tasks that are still queued run after `main` has returned.
+fn generate_entry_point(returns_code: bool, init_globals: bool, run_tasks: bool) -> QbeFunction {
+    let argc = QbeValue::Temporary("argc".into());
+    let argv = QbeValue::Temporary("argv".into());
+
+    let mut func = QbeFunction::new(
+        "main",
+        vec![(QbeType::Word, argc.clone()), (QbeType::Long, argv.clone())],
+        Some(QbeType::Word),
+    )
+    .export();
+    func.add_block("start".into());
+    func.add_instr(QbeInstr::Call(
+        "_init_args".into(),
+        vec![(QbeType::Word, argc), (QbeType::Long, argv)],
+    ));
+    if init_globals {
+        func.add_instr(QbeInstr::Call(INIT_GLOBALS_SYMBOL.into(), Vec::new()));
+    }
+    let call = QbeInstr::Call(MAIN_SYMBOL.into(), Vec::new());
+    let code = if returns_code {
+        let code = QbeValue::Temporary("code".into());
+        func.assign_instr(code.clone(), QbeType::Word, call);
+        code
+    } else {
+        func.add_instr(call);
+        QbeValue::Const(0)
+    };
+    if run_tasks {
+        func.add_instr(QbeInstr::Call("_run_tasks".into(), Vec::new()));
+    }
+    func.add_instr(QbeInstr::Ret(Some(code)));
+    func
+}
+
+impl QbeGenerator {
+    /// Returns an aggregate type for a structure (note: has side effects)
+    fn generate_struct(&mut self, def: &StructDef) -> GeneratorResult<QbeTypeDef> {
+        let mut typedef = QbeTypeDef::new(format!("struct.{}", self.struct_map.len() + 1));
+        let layout = self.layouts.of_struct(&def.name)?;
+        let mut meta: StructMeta = StructMeta::new();
+
+        for field in &def.fields {
+            let ty = self.get_type(
+                field
+                    .ty
+                    .as_ref()
+                    .ok_or_else(|| "Structure field must have a type".to_owned())?
+                    .to_owned(),
+            )?;
+
+            // QBE lays out the fields of aggregates like C, which matches the offsets
+            let offset = layout.offset(&field.name).unwrap_or_default();
+            meta.insert(field.name.clone(), (ty.clone(), offset));
+            typedef.items.push((ty, 1));
+        }
+        self.struct_map.insert(
+            def.name.clone(),
+            (
+                QbeType::Aggregate(typedef.name.clone()),
+                meta,
+                layout.layout.size,
+            ),
+        );
+
+        Ok(typedef)
+    }
+
+    /// Generates a data definition for every global variable. Integer and boolean
+    /// literals are stored directly, other initializers are evaluated by a function
+    /// that is returned if there are any.
+    fn generate_globals(&mut self, globals: &[Global]) -> GeneratorResult<Option<QbeFunction>> {
+        let mut func = QbeFunction::new(INIT_GLOBALS_SYMBOL, Vec::new(), None);
+        func.add_block("start".into());
+        self.scopes.push(HashMap::new());
+        self.names = NameCounters::new("global");
+
+        for global in globals {
+            let var = &global.var;
+            let ty = self.get_type(
+                var.ty
+                    .as_ref()
+                    .ok_or_else(|| format!("Missing type for global '{}'", &var.name))?
+                    .to_owned(),
+            )?;
+            let item = match &global.value {
+                Expression::Int(value) => QbeDataItem::Const(*value as u64),
+                Expression::Bool(value) => QbeDataItem::Const(*value as u64),
+                value => {
+                    if let Some((_, result)) =
+                        self.generate_initial_value(&mut func, var, Some(value))?
+                    {
+                        func.add_instr(QbeInstr::Store(
+                            ty.clone().into_base(),
+                            global_symbol(&var.name),
+                            result,
+                        ));
+                    }
+                    QbeDataItem::Const(0)
+                }
+            };
+            self.datadefs.push(QbeDataDef::new(
+                format!("global.{}", var.name),
+                vec![(ty.clone().into_base(), item)],
+            ));
+            self.globals.insert(var.name.clone(), ty);
+            self.types.declare_global(&var.name, var.ty.clone());
+        }
+        self.scopes.pop();
+
+        if func
+            .blocks
+            .iter()
+            .all(|block| block.instructions.is_empty())
+        {
+            return Ok(None);
+        }
+        func.add_instr(QbeInstr::Ret(None));
+        Ok(Some(func))
+    }
+
+    fn generate_function(&mut self, func: &Function) -> GeneratorResult<QbeFunction> {
+        self.names = NameCounters::new(&func.name);
+        // Functions are generated from their SSA form, unless they use features the IR does not support
+        if let Ok(mut ssa) = mir::lower_function(func, &mut self.types) {
+            mir::optimize(&mut ssa, self.opt_level);
+            return self.generate_from_mir(&ssa, func.public);
+        }
+
+        // Function argument scope
+        self.scopes.push(HashMap::new());
+        self.types.enter_function(func);
+        self.addressed = references::addressed_variables(&func.body);
+
+        let mut arguments: Vec<(QbeType, QbeValue)> = Vec::new();
+        let mut bindings: Vec<(QbeType, QbeValue)> = Vec::new();
+        // Arguments whose address is taken are copied to a stack slot: (type, slot, argument)
+        let mut spills: Vec<(QbeType, QbeValue, QbeValue)> = Vec::new();
+        for arg in &func.arguments {
+            let ty = self.get_type(
+                arg.ty
+                    .as_ref()
+                    .ok_or("Function arguments must have a type")?
+                    .to_owned(),
+            )?;
+            let tmp = if self.in_slot(&arg.name, &ty) {
+                let slot = self.new_var(&ty, &arg.name);
+                let tmp = self.new_temporary();
+                spills.push((ty.clone(), slot, tmp.clone()));
+                tmp
+            } else {
+                self.new_var(&ty, &arg.name)
+            };
+
+            bindings.push((ty.clone(), tmp.clone()));
+            if arg.reference {
+                arguments.push((QbeType::Long, tmp));
+            } else {
+                arguments.push((ty.into_abi(), tmp));
+            }
+        }
+        self.current_func = Some((func.name.clone(), bindings));
+
+        let return_ty = if let Some(ty) = &func.ret_type {
+            Some(self.get_type(ty.to_owned())?.into_abi())
+        } else {
+            None
+        };
+
+        let mut qfunc = QbeFunction::new(symbol(&func.name), arguments, return_ty);
+        qfunc.exported = func.public;
+
+        qfunc.add_block("start".to_owned());
+        for (ty, slot, arg) in spills {
+            self.allocate_slot(&mut qfunc, slot.clone())?;
+            qfunc.add_instr(QbeInstr::Store(ty.into_base(), slot, arg));
+        }
+        if func.body.has_tail_call(&func.name, func.arguments.len()) {
+            // Tail calls jump back to this block, since the start block
+            // can not be a jump target
+            qfunc.add_instr(QbeInstr::Jmp("body".to_owned()));
+            qfunc.add_block("body".to_owned());
+        }
+
+        self.generate_statement(&mut qfunc, &func.body)?;
+
+        // A trailing jump can only be a tail call, which never falls through
+        let returns = qfunc.last_block().instructions.last().is_some_and(|i| {
+            matches!(
+                i,
+                QbeStatement::Volatile(QbeInstr::Ret(_)) | QbeStatement::Volatile(QbeInstr::Jmp(_))
+            )
+        });
+        // Automatically add return in void functions unless it already returns,
+        // non-void functions raise an error
+        if !returns {
+            if func.ret_type.is_none() {
+                qfunc.add_instr(QbeInstr::Ret(None));
+            } else {
+                return Err(format!(
+                    "Function '{}' does not return in all code paths",
+                    &func.name
+                ));
+            }
+        }
+
+        self.scopes.pop();
+        self.current_func = None;
+        self.addressed.clear();
+
+        Ok(qfunc)
+    }
+
+    /// Generates a statement
+    fn generate_statement(
+        &mut self,
+        func: &mut QbeFunction,
+        stmt: &Statement,
+    ) -> GeneratorResult<()> {
+        match stmt {
+            Statement::Block(statements, _) => {
+                self.scopes.push(HashMap::new());
+                self.defers.push(Vec::new());
+                for stmt in statements.iter() {
+                    self.generate_statement(func, stmt)?;
+                }
+                // Blocks that end with a jump have already evaluated their deferred expressions
+                if !func.blocks.last().is_some_and(|b| b.jumps()) {
+                    self.generate_defers(func, 1)?;
+                }
+                self.defers.pop();
+                self.scopes.pop();
+            }
+            Statement::Declare(var, expr) => {
+                let ty = self.get_type(
+                    var.ty
+                        .as_ref()
+                        .ok_or_else(|| format!("Missing type for variable '{}'", &var.name))?
+                        .to_owned(),
+                )?;
+                // The initializer may refer to a variable that is shadowed by this one
+                let init = self.generate_initial_value(func, var, expr.as_ref())?;
+                let tmp = self.new_var(&ty, &var.name);
+                self.types.declare(&var.name, var.ty.clone());
+
+                if self.in_slot(&var.name, &ty) {
+                    self.allocate_slot(func, tmp.clone())?;
+                    if let Some((_, result)) = init {
+                        func.add_instr(QbeInstr::Store(ty.into_base(), tmp, result));
+                    }
+                } else if let Some((ty, result)) = init {
+                    func.assign_instr(tmp, ty, QbeInstr::Copy(result));
+                }
+            }
+            Statement::Assign(lhs, rhs) => {
+                let (_, rhs) = self.generate_expression(func, rhs)?;
+                // TODO: type check
+                self.generate_assignment(func, lhs, rhs)?;
+            }
+            Statement::Return(val) => match val {
+                Some(Expression::FunctionCall(name, args))
+                    if self.is_tail_call(name, args) && self.defers.iter().all(Vec::is_empty) =>
+                {
+                    self.generate_tail_call(func, args)?;
+                }
+                Some(expr) => {
+                    // The returned value is evaluated before the deferred expressions
+                    let (_, result) = self.generate_expression(func, expr)?;
+                    self.generate_defers(func, self.defers.len())?;
+                    // TODO: Cast to function return type
+                    func.add_instr(QbeInstr::Ret(Some(result)));
+                }
+                None => {
+                    self.generate_defers(func, self.defers.len())?;
+                    func.add_instr(QbeInstr::Ret(None));
+                }
+            },
+            Statement::If(cond, if_clause, else_clause) => {
+                self.generate_if(func, cond, if_clause, else_clause)?;
+            }
+            Statement::While(cond, body) => {
+                self.generate_while(func, cond, body)?;
+            }
+            Statement::Unsafe(body) => self.generate_statement(func, body)?,
+            Statement::Match(subject, arms) => {
+                self.generate_match(func, subject, arms)?;
+            }
+            Statement::Break => {
+                if let Some((label, depth)) = self.loop_labels.last().cloned() {
+                    self.generate_defers(func, self.defers.len() - depth)?;
+                    func.add_instr(QbeInstr::Jmp(format!("{}.end", label)));
+                } else {
+                    return Err("break used outside of a loop".to_owned());
+                }
+            }
+            Statement::Continue => {
+                if let Some((label, depth)) = self.loop_labels.last().cloned() {
+                    self.generate_defers(func, self.defers.len() - depth)?;
+                    func.add_instr(QbeInstr::Jmp(format!("{}.cond", label)));
+                } else {
+                    return Err("continue used outside of a loop".to_owned());
+                }
+            }
+            Statement::Exp(expr) => {
+                self.generate_expression(func, expr)?;
+            }
+            Statement::Defer(expr) => {
+                if let Some(defers) = self.defers.last_mut() {
+                    defers.push(expr.clone());
+                }
+            }
+            Statement::Spawn(call) => self.generate_spawn(func, call)?,
+            Statement::Inline(_, code) => self.generate_inline_code(func, code),
+            _ => todo!("statement: {:?}", stmt),
+        }
+        Ok(())
+    }
+
+    /// Queues a call with the values of its arguments. The values are copied to memory, which
+    /// is passed to a function that is generated for the call and calls it with the values.
+    fn generate_spawn(&mut self, func: &mut QbeFunction, call: &Expression) -> GeneratorResult<()> {
+        let (name, args) = match call {
+            Expression::FunctionCall(name, args) => (name, args),
+            _ => return Err("Only function calls can be spawned".to_owned()),
+        };
+        let mut values: Vec<(QbeType, QbeValue, u64)> = Vec::new();
+        let mut size = 0;
+        for arg in args {
+            let (ty, value) = self.generate_expression(func, arg)?;
+            // Every value starts at a multiple of 8 bytes
+            let offset = size;
+            size += self.size_of(&ty)?.div_ceil(8) * 8;
+            values.push((ty, value, offset));
+        }
+
+        let env = self.new_temporary();
+        if size > 0 {
+            func.assign_instr(
+                env.clone(),
+                QbeType::Long,
+                QbeInstr::Call(
+                    "_gc_alloc".into(),
+                    vec![(QbeType::Long, QbeValue::Const(size))],
+                ),
+            );
+        } else {
+            func.assign_instr(
+                env.clone(),
+                QbeType::Long,
+                QbeInstr::Copy(QbeValue::Const(0)),
+            );
+        }
+
+        self.names.spawns += 1;
+        let symbol_name = format!("_sb_task.{}.{}", self.names.function, self.names.spawns);
+        let task_env = QbeValue::Temporary("env".into());
+        let mut task = QbeFunction::new(
+            symbol_name.clone(),
+            vec![(QbeType::Long, task_env.clone())],
+            None,
+        );
+        task.add_block("start".into());
+        let mut task_args = Vec::new();
+        for (i, (ty, value, offset)) in values.into_iter().enumerate() {
+            let ptr = self.new_temporary();
+            func.assign_instr(
+                ptr.clone(),
+                QbeType::Long,
+                QbeInstr::Add(env.clone(), QbeValue::Const(offset)),
+            );
+            self.store_value(func, ty.clone(), ptr, value)?;
+
+            let task_ptr = QbeValue::Temporary(format!("ptr.{}", i + 1));
+            task.assign_instr(
+                task_ptr.clone(),
+                QbeType::Long,
+                QbeInstr::Add(task_env.clone(), QbeValue::Const(offset)),
+            );
+            // The value of a structure is its address
+            let arg = if matches!(ty, QbeType::Aggregate(_)) {
+                task_ptr
+            } else {
+                let arg = QbeValue::Temporary(format!("arg.{}", i + 1));
+                task.assign_instr(
+                    arg.clone(),
+                    ty.clone(),
+                    QbeInstr::Load(ty.clone(), task_ptr),
+                );
+                arg
+            };
+            task_args.push((ty, arg));
+        }
+        task.add_instr(QbeInstr::Call(symbol(name), task_args));
+        task.add_instr(QbeInstr::Ret(None));
+        self.tasks.push(task);
+
+        func.add_instr(QbeInstr::Call(
+            "_spawn".into(),
+            vec![
+                (QbeType::Long, QbeValue::Global(symbol_name)),
+                (QbeType::Long, env),
+            ],
+        ));
+        Ok(())
+    }
+
+    /// Returns the number of bytes a value of the type takes in memory.
+    /// Values other than structures fit into 8 bytes.
+    fn size_of(&self, ty: &QbeType) -> GeneratorResult<u64> {
+        match ty {
+            QbeType::Aggregate(_) => self
+                .struct_map
+                .values()
+                .find(|(sty, ..)| sty == ty)
+                .map(|(.., size)| *size)
+                .ok_or_else(|| format!("Unknown aggregate type {}", ty)),
+            _ => Ok(8),
+        }
+    }
+
+    /// Generates the deferred expressions of the innermost `blocks` blocks,
+    /// starting with the most recently deferred one
+    fn generate_defers(&mut self, func: &mut QbeFunction, blocks: usize) -> GeneratorResult<()> {
+        let start = self.defers.len() - blocks;
+        let deferred: Vec<Expression> = self.defers[start..]
+            .iter()
+            .rev()
+            .flat_map(|defers| defers.iter().rev().cloned())
+            .collect();
+        for expr in deferred.iter() {
+            self.generate_expression(func, expr)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if a call in tail position can be lowered to a jump
+    fn is_tail_call(&self, name: &str, args: &[Expression]) -> bool {
+        matches!(
+            &self.current_func,
+            Some((current, arguments)) if current == name && arguments.len() == args.len()
+        ) && self.addressed.is_empty()
+    }
+
+    /// Generates a self-recursive tail call by rebinding the arguments and
+    /// jumping back to the function body
+    fn generate_tail_call(
+        &mut self,
+        func: &mut QbeFunction,
+        args: &[Expression],
+    ) -> GeneratorResult<()> {
+        // All arguments are evaluated before any of them is rebound, since
+        // they may refer to each other (e.g. `return foo(b, a)`)
+        let mut values: Vec<QbeValue> = Vec::new();
+        for arg in args.iter() {
+            let (ty, result) = self.generate_expression(func, arg)?;
+            let tmp = self.new_temporary();
+            func.assign_instr(tmp.clone(), ty, QbeInstr::Copy(result));
+            values.push(tmp);
+        }
+
+        let (_, arguments) = self
+            .current_func
+            .as_ref()
+            .expect("tail call outside of a function");
+        for ((ty, arg), value) in arguments.iter().zip(values) {
+            func.assign_instr(arg.clone(), ty.clone(), QbeInstr::Copy(value));
+        }
+
+        func.add_instr(QbeInstr::Jmp("body".to_owned()));
+
+        Ok(())
+    }
+
+    /// Generates an expression
+    fn generate_expression(
+        &mut self,
+        func: &mut QbeFunction,
+        expr: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        match expr {
+            Expression::Int(literal) => {
+                let tmp = self.new_temporary();
+                func.assign_instr(
+                    tmp.clone(),
+                    QbeType::Word,
+                    QbeInstr::Copy(QbeValue::Const(*literal as u64)),
+                );
+
+                Ok((QbeType::Word, tmp))
+            }
+            Expression::Str(string) => self.generate_string(string),
+            Expression::Bool(literal) => {
+                let tmp = self.new_temporary();
+                func.assign_instr(
+                    tmp.clone(),
+                    QbeType::Word,
+                    QbeInstr::Copy(QbeValue::Const(if *literal { 1 } else { 0 })),
+                );
+
+                Ok((QbeType::Word, tmp))
+            }
+            Expression::Array(len, items) => self.generate_array(func, *len, items, false),
+            Expression::FunctionCall(name, args) => {
+                let mut new_args: Vec<(QbeType, QbeValue)> = Vec::new();
+                for arg in args.iter() {
+                    new_args.push(self.generate_expression(func, arg)?);
+                }
+                // Elements of vectors, keys and values of maps, as well as
+                // values of results are passed to the runtime as longs
+                let boxed = match name.as_str() {
+                    "_result_ok" => Some(0),
+                    "_vec_push" | "_chan_send" => Some(1),
+                    _ if name.starts_with("_map_") => Some(1),
+                    _ => None,
+                };
+                if let Some(instr) = bit_instruction(name, &new_args) {
+                    let tmp = self.new_temporary();
+                    func.assign_instr(tmp.clone(), QbeType::Word, instr);
+                    return Ok((QbeType::Word, tmp));
+                }
+                if let Some(skip) = boxed {
+                    for (ty, value) in new_args.iter_mut().skip(skip) {
+                        *value = self.extend_to_long(func, ty.clone(), value.clone());
+                        *ty = QbeType::Long;
+                    }
+                }
+                // Structures are addressed by pointers, which are passed on as they are
+                if let Some(references) = self.references.get(name) {
+                    for ((ty, _), reference) in new_args.iter_mut().zip(references) {
+                        if *reference {
+                            *ty = QbeType::Long;
+                        }
+                    }
+                }
+
+                let ty = match self.types.expression_type(expr) {
+                    Some(ty) => self.get_type(ty)?.into_abi(),
+                    // TODO: get that type properly
+                    None => QbeType::Word,
+                };
+
+                let tmp = self.new_temporary();
+                func.assign_instr(
+                    tmp.clone(),
+                    ty.clone(),
+                    QbeInstr::Call(symbol(name), new_args),
+                );
+
+                Ok((ty, tmp))
+            }
+            Expression::Variable(name) => self.load_var(func, name),
+            // Async functions are called like any other function
+            Expression::Await(call) => self.generate_expression(func, call),
+            Expression::Reference(target) => self.generate_reference(func, target),
+            Expression::Dereference(target) => {
+                let ty = self.referenced_type(target)?;
+                let (_, ptr) = self.generate_expression(func, target)?;
+                let value = self.load_value(func, ty.clone(), ptr);
+                Ok((ty.into_abi(), value))
+            }
+            Expression::Load(ty, address) => {
+                let ty = self.get_type(ty.clone())?;
+                let (_, ptr) = self.generate_expression(func, address)?;
+                let value = self.load_value(func, ty.clone(), ptr);
+                Ok((ty.into_abi(), value))
+            }
+            Expression::Store(ty, address, value) => {
+                let ty = self.get_type(ty.clone())?;
+                let (_, ptr) = self.generate_expression(func, address)?;
+                let (_, value) = self.generate_expression(func, value)?;
+                self.store_value(func, ty, ptr, value)?;
+                Ok((QbeType::Word, QbeValue::Const(0)))
+            }
+            Expression::BinOp(lhs, op, rhs) => self.generate_binop(func, lhs, op, rhs),
+            Expression::StructInitialization(name, fields) => {
+                self.generate_struct_init(func, name, None, fields)
+            }
+            Expression::StructUpdate(name, base, fields) => {
+                self.generate_struct_init(func, name, Some(base), fields)
+            }
+            Expression::FieldAccess(obj, field) => self.generate_field_access(func, obj, field),
+            Expression::Map(entries) => self.generate_map(func, entries, None),
+            Expression::ArrayAccess(name, index) => {
+                let (ty, ptr) = self.generate_element_ptr(func, name, index)?;
+                let tmp = self.new_temporary();
+                func.assign_instr(tmp.clone(), ty.clone(), QbeInstr::Load(ty.clone(), ptr));
+                Ok((ty, tmp))
+            }
+            _ => todo!("expression: {:?}", expr),
+        }
+    }
+
+    /// Generates the initial value of a variable, if it has one
+    fn generate_initial_value(
+        &mut self,
+        func: &mut QbeFunction,
+        var: &Variable,
+        expr: Option<&Expression>,
+    ) -> GeneratorResult<Option<(QbeType, QbeValue)>> {
+        let on_stack = self.is_stack_array(&var.name);
+        let init = match (expr, &var.ty) {
+            (Some(Expression::Array(len, items)), _) if on_stack => {
+                Some(self.generate_array(func, *len, items, true)?)
+            }
+            // Empty maps take the type of their keys from the declaration
+            (Some(Expression::Map(entries)), Some(Type::Map(key, _))) => {
+                Some(self.generate_map(func, entries, Some(key))?)
+            }
+            (None, Some(Type::Map(key, _))) => Some(self.generate_map(func, &[], Some(key))?),
+            (Some(expr), _) => Some(self.generate_expression(func, expr)?),
+            // Uninitialized arrays are filled with zeroes
+            (None, Some(Type::Array(_, Some(len)))) => {
+                Some(self.generate_array(func, *len, &[], on_stack)?)
+            }
+            (None, _) => None,
+        };
+        Ok(init)
+    }
+
+    /// Adds the lines of a `qbe!` block to the function. Variables of the
+    /// function can be referred to as `%name`.
+    fn generate_inline_code(&self, func: &mut QbeFunction, code: &str) {
+        lazy_static! {
+            static ref VARIABLE: Regex = Regex::new(r"%([\pL_][\pL\p{Nd}_]*)").unwrap();
+        }
+        for line in code.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let line = VARIABLE.replace_all(line, |captures: &Captures| {
+                match self.get_var(&captures[1]) {
+                    Ok((_, tmp)) => tmp.to_string(),
+                    Err(_) => captures[0].to_owned(),
+                }
+            });
+            func.add_raw(line.into_owned());
+        }
+    }
+
+    /// Generates an `if` statement
+    fn generate_if(
+        &mut self,
+        func: &mut QbeFunction,
+        cond: &Expression,
+        if_clause: &Statement,
+        else_clause: &Option<Box<Statement>>,
+    ) -> GeneratorResult<()> {
+        let (_, result) = self.generate_expression(func, cond)?;
+
+        self.names.conditions += 1;
+        let if_label = format!("cond.{}.if", self.names.conditions);
+        let else_label = format!("cond.{}.else", self.names.conditions);
+        let end_label = format!("cond.{}.end", self.names.conditions);
+
+        func.add_instr(QbeInstr::Jnz(
+            result,
+            if_label.clone(),
+            if else_clause.is_some() {
+                else_label.clone()
+            } else {
+                end_label.clone()
+            },
+        ));
+
+        func.add_block(if_label);
+        self.generate_statement(func, if_clause)?;
+
+        if let Some(else_clause) = else_clause {
+            // Jump over to the end to prevent fallthrough into else
+            // clause, unless the last block already jumps
+            if !func.blocks.last().is_some_and(|b| b.jumps()) {
+                func.add_instr(QbeInstr::Jmp(end_label.clone()));
+            }
+
+            func.add_block(else_label);
+            self.generate_statement(func, else_clause)?;
+        }
+
+        func.add_block(end_label);
+
+        Ok(())
+    }
+
+    /// Generates a `match` statement, which compares the subject against the cases in order.
+    /// Functions that are generated from the mid-level IR lower their `match` statements
+    /// on integers to a switch instead.
+    fn generate_match(
+        &mut self,
+        func: &mut QbeFunction,
+        subject: &Expression,
+        arms: &[MatchArm],
+    ) -> GeneratorResult<()> {
+        let is_str = self.types.expression_type(subject) == Some(Type::Str);
+        let (ty, subject) = self.generate_expression(func, subject)?;
+
+        self.names.matches += 1;
+        let prefix = format!("match.{}", self.names.matches);
+        let end_label = format!("{}.end", prefix);
+
+        let mut bodies = Vec::new();
+        let mut else_body = None;
+        for (i, arm) in arms.iter().enumerate() {
+            let (case, body) = match arm {
+                MatchArm::Case(case, body) => (case, body),
+                MatchArm::Else(body) => {
+                    else_body = Some(body);
+                    continue;
+                }
+            };
+            let (_, case) = self.generate_expression(func, case)?;
+            let matches = self.new_temporary();
+            let compare = if is_str {
+                QbeInstr::Call(
+                    "_str_eq".into(),
+                    vec![(QbeType::Long, subject.clone()), (QbeType::Long, case)],
+                )
+            } else {
+                QbeInstr::Cmp(ty.clone(), QbeCmp::Eq, subject.clone(), case)
+            };
+            func.assign_instr(matches.clone(), QbeType::Word, compare);
+
+            let case_label = format!("{}.case.{}", prefix, i + 1);
+            let next_label = format!("{}.next.{}", prefix, i + 1);
+            func.add_instr(QbeInstr::Jnz(
+                matches,
+                case_label.clone(),
+                next_label.clone(),
+            ));
+            func.add_block(next_label);
+            bodies.push((case_label, body));
+        }
+
+        // None of the cases matched
+        let mut reaches_end = else_body.is_none();
+        match else_body {
+            Some(body) => {
+                let else_label = format!("{}.else", prefix);
+                func.add_instr(QbeInstr::Jmp(else_label.clone()));
+                bodies.push((else_label, body));
+            }
+            None => func.add_instr(QbeInstr::Jmp(end_label.clone())),
+        }
+
+        for (label, body) in bodies {
+            func.add_block(label);
+            self.generate_statement(func, body)?;
+            if !func.blocks.last().is_some_and(|b| b.jumps()) {
+                func.add_instr(QbeInstr::Jmp(end_label.clone()));
+                reaches_end = true;
+            }
+        }
+        // If every arm returns, the function ends with the last arm
+        if reaches_end {
+            func.add_block(end_label);
+        }
+
+        Ok(())
+    }
+
+    /// Generates a `while` statement
+    fn generate_while(
+        &mut self,
+        func: &mut QbeFunction,
+        cond: &Expression,
+        body: &Statement,
+    ) -> GeneratorResult<()> {
+        self.names.loops += 1;
+        let cond_label = format!("loop.{}.cond", self.names.loops);
+        let body_label = format!("loop.{}.body", self.names.loops);
+        let end_label = format!("loop.{}.end", self.names.loops);
+
+        self.loop_labels
+            .push((format!("loop.{}", self.names.loops), self.defers.len()));
+
+        func.add_block(cond_label.clone());
+
+        let (_, result) = self.generate_expression(func, cond)?;
+        func.add_instr(QbeInstr::Jnz(result, body_label.clone(), end_label.clone()));
+
+        func.add_block(body_label);
+        self.generate_statement(func, body)?;
+
+        if !func.blocks.last().is_some_and(|b| b.jumps()) {
+            func.add_instr(QbeInstr::Jmp(cond_label));
+        }
+
+        func.add_block(end_label);
+
+        self.loop_labels.pop();
+
+        Ok(())
+    }
+
+    /// Generates a string
+    fn generate_string(&mut self, string: &str) -> GeneratorResult<(QbeType, QbeValue)> {
+        self.names.strings += 1;
+        let name = format!("string.{}.{}", self.names.function, self.names.strings);
+
+        let mut items: Vec<(QbeType, QbeDataItem)> = Vec::new();
+        let mut buf = String::new();
+        for ch in string.chars() {
+            // Quotes and backslashes would be read as escapes by QBE and the assembler
+            if ch.is_ascii() && !ch.is_ascii_control() && ch != '"' && ch != '\\' {
+                buf.push(ch)
+            } else {
+                if !buf.is_empty() {
+                    items.push((QbeType::Byte, QbeDataItem::Str(buf.clone())));
+                    buf.clear();
+                }
+
+                let mut buf = [0; 4];
+                let len = ch.encode_utf8(&mut buf).len();
+
+                for b in buf.iter().take(len) {
+                    items.push((QbeType::Byte, QbeDataItem::Const(*b as u64)));
+                }
+                continue;
+            }
+        }
+        if !buf.is_empty() {
+            items.push((QbeType::Byte, QbeDataItem::Str(buf)));
+        }
+        // NUL terminator
+        items.push((QbeType::Byte, QbeDataItem::Const(0)));
+
+        self.datadefs.push(QbeDataDef::new(name.clone(), items));
+
+        Ok((QbeType::Long, QbeValue::Global(name)))
+    }
+
+    /// Returns the result of a binary operation (e.g. `+` or `*=`).
+    fn generate_binop(
+        &mut self,
+        func: &mut QbeFunction,
+        lhs: &Expression,
+        op: &BinOp,
+        rhs: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let is_str = |expr| self.types.expression_type(expr) == Some(Type::Str);
+        if is_str(lhs) || is_str(rhs) {
+            return self.generate_string_binop(func, lhs, op, rhs);
+        }
+
+        if matches!(op, BinOp::And | BinOp::Or) {
+            return self.generate_short_circuit(func, lhs, op, rhs);
+        }
+
+        let is_ptr = |expr| self.types.expression_type(expr) == Some(Type::Ptr);
+        if is_ptr(lhs) || is_ptr(rhs) {
+            return self.generate_pointer_binop(func, lhs, op, rhs);
+        }
+
+        let (_, lhs_val) = self.generate_expression(func, lhs)?;
+        let (_, rhs_val) = self.generate_expression(func, rhs)?;
+        let tmp = self.new_temporary();
+
+        // TODO: take the biggest
+        let ty = QbeType::Word;
+
+        func.assign_instr(
+            tmp.clone(),
+            ty.clone(),
+            match op {
+                BinOp::Addition | BinOp::AddAssign => QbeInstr::Add(lhs_val, rhs_val),
+                BinOp::Subtraction | BinOp::SubtractAssign => QbeInstr::Sub(lhs_val, rhs_val),
+                BinOp::Multiplication | BinOp::MultiplyAssign => QbeInstr::Mul(lhs_val, rhs_val),
+                BinOp::Division | BinOp::DivideAssign => QbeInstr::Div(lhs_val, rhs_val),
+                BinOp::Modulus => QbeInstr::Rem(lhs_val, rhs_val),
+
+                // Others should be comparisons
+                cmp => QbeInstr::Cmp(
+                    ty.clone(),
+                    match cmp {
+                        BinOp::LessThan => QbeCmp::Slt,
+                        BinOp::LessThanOrEqual => QbeCmp::Sle,
+                        BinOp::GreaterThan => QbeCmp::Sgt,
+                        BinOp::GreaterThanOrEqual => QbeCmp::Sge,
+                        BinOp::Equal => QbeCmp::Eq,
+                        BinOp::NotEqual => QbeCmp::Ne,
+                        _ => unreachable!(),
+                    },
+                    lhs_val,
+                    rhs_val,
+                ),
+            },
+        );
+
+        // *Assign BinOps work just like normal ones except that here the
+        // result is assigned to the left hand side. This essentially makes
+        // `a += 1` the same as `a = a + 1`.
+        match op {
+            BinOp::AddAssign
+            | BinOp::SubtractAssign
+            | BinOp::MultiplyAssign
+            | BinOp::DivideAssign => {
+                self.generate_assignment(func, lhs, tmp.clone())?;
+            }
+            _ => {}
+        };
+
+        Ok((ty, tmp))
+    }
+
+    /// Returns the result of `&&` or `||`. The right operand is only evaluated
+    /// if the left operand does not decide the result.
+    fn generate_short_circuit(
+        &mut self,
+        func: &mut QbeFunction,
+        lhs: &Expression,
+        op: &BinOp,
+        rhs: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let (_, lhs_val) = self.generate_expression(func, lhs)?;
+        let tmp = self.new_temporary();
+        func.assign_instr(tmp.clone(), QbeType::Word, QbeInstr::Copy(lhs_val));
+
+        self.names.logical += 1;
+        let rhs_label = format!("logic.{}.rhs", self.names.logical);
+        let end_label = format!("logic.{}.end", self.names.logical);
+        func.add_instr(match op {
+            BinOp::And => QbeInstr::Jnz(tmp.clone(), rhs_label.clone(), end_label.clone()),
+            _ => QbeInstr::Jnz(tmp.clone(), end_label.clone(), rhs_label.clone()),
+        });
+
+        func.add_block(rhs_label);
+        let (_, rhs_val) = self.generate_expression(func, rhs)?;
+        func.assign_instr(tmp.clone(), QbeType::Word, QbeInstr::Copy(rhs_val));
+        func.add_block(end_label);
+
+        Ok((QbeType::Word, tmp))
+    }
+
+    /// Returns the result of a binary operation on raw pointers. Offsets are
+    /// counted in bytes and extended to longs before they are added.
+    fn generate_pointer_binop(
+        &mut self,
+        func: &mut QbeFunction,
+        lhs: &Expression,
+        op: &BinOp,
+        rhs: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let (lhs_ty, lhs_val) = self.generate_expression(func, lhs)?;
+        let lhs_val = self.extend_to_long(func, lhs_ty, lhs_val);
+        let (rhs_ty, rhs_val) = self.generate_expression(func, rhs)?;
+        let rhs_val = self.extend_to_long(func, rhs_ty, rhs_val);
+        let tmp = self.new_temporary();
+
+        let (ty, instr) = match op {
+            BinOp::Addition | BinOp::AddAssign => (QbeType::Long, QbeInstr::Add(lhs_val, rhs_val)),
+            BinOp::Subtraction | BinOp::SubtractAssign => {
+                (QbeType::Long, QbeInstr::Sub(lhs_val, rhs_val))
+            }
+            BinOp::Equal => (
+                QbeType::Word,
+                QbeInstr::Cmp(QbeType::Long, QbeCmp::Eq, lhs_val, rhs_val),
+            ),
+            BinOp::NotEqual => (
+                QbeType::Word,
+                QbeInstr::Cmp(QbeType::Long, QbeCmp::Ne, lhs_val, rhs_val),
+            ),
+            _ => return Err(format!("Operator '{}' can not be applied to pointers", op)),
+        };
+        func.assign_instr(tmp.clone(), ty.clone(), instr);
+
+        if matches!(op, BinOp::AddAssign | BinOp::SubtractAssign) {
+            self.generate_assignment(func, lhs, tmp.clone())?;
+        }
+        Ok((ty, tmp))
+    }
+
+    /// Returns the result of a binary operation on strings, which is
+    /// implemented by the builtin functions
+    fn generate_string_binop(
+        &mut self,
+        func: &mut QbeFunction,
+        lhs: &Expression,
+        op: &BinOp,
+        rhs: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let lhs_val = self.generate_expression(func, lhs)?;
+        let rhs_val = self.generate_expression(func, rhs)?;
+        let args = vec![lhs_val, rhs_val];
+        let tmp = self.new_temporary();
+
+        match op {
+            BinOp::Addition | BinOp::AddAssign => {
+                func.assign_instr(
+                    tmp.clone(),
+                    QbeType::Long,
+                    QbeInstr::Call("_str_concat".into(), args),
+                );
+                if *op == BinOp::AddAssign {
+                    self.generate_assignment(func, lhs, tmp.clone())?;
+                }
+                Ok((QbeType::Long, tmp))
+            }
+            BinOp::Equal | BinOp::NotEqual => {
+                func.assign_instr(
+                    tmp.clone(),
+                    QbeType::Word,
+                    QbeInstr::Call("_str_eq".into(), args),
+                );
+                if *op == BinOp::Equal {
+                    return Ok((QbeType::Word, tmp));
+                }
+
+                let not_equal = self.new_temporary();
+                func.assign_instr(
+                    not_equal.clone(),
+                    QbeType::Word,
+                    QbeInstr::Cmp(QbeType::Word, QbeCmp::Eq, tmp, QbeValue::Const(0)),
+                );
+                Ok((QbeType::Word, not_equal))
+            }
+            _ => Err(format!("Operator '{}' can not be applied to strings", op)),
+        }
+    }
+
+    /// Generates an assignment to either a variable, field access or array
+    /// access
+    fn generate_assignment(
+        &mut self,
+        func: &mut QbeFunction,
+        lhs: &Expression,
+        rhs: QbeValue,
+    ) -> GeneratorResult<()> {
+        match lhs {
+            Expression::Variable(name) if self.globals.contains_key(name) => {
+                let ty = self.globals[name].clone().into_base();
+                func.add_instr(QbeInstr::Store(ty, global_symbol(name), rhs));
+            }
+            Expression::Variable(name) => {
+                let (vty, tmp) = self.get_var(name)?.to_owned();
+                if self.in_slot(name, &vty) {
+                    func.add_instr(QbeInstr::Store(vty.into_base(), tmp, rhs));
+                    return Ok(());
+                }
+                func.assign_instr(tmp, vty, QbeInstr::Copy(rhs));
+            }
+            Expression::FieldAccess(obj, field) => {
+                let (src, ty, offset) = self.resolve_field_access(func, obj, field)?;
+
+                let field_ptr = self.new_temporary();
+                func.assign_instr(
+                    field_ptr.clone(),
+                    QbeType::Long,
+                    QbeInstr::Add(src, QbeValue::Const(offset)),
+                );
+
+                self.store_value(func, ty, field_ptr, rhs)?;
+            }
+            Expression::ArrayAccess(name, index) => {
+                let (ty, ptr) = self.generate_element_ptr(func, name, index)?;
+                func.add_instr(QbeInstr::Store(ty, ptr, rhs));
+            }
+            Expression::Dereference(target) => {
+                let ty = self.referenced_type(target)?;
+                let (_, ptr) = self.generate_expression(func, target)?;
+                self.store_value(func, ty, ptr, rhs)?;
+            }
+            _ => return Err("Left side of an assignment must be either a variable, field access or array access".to_owned()),
+        }
+
+        Ok(())
+    }
+
+    /// Generates the initialization of a struct. Fields that are not initialized
+    /// explicitly are copied from `update`, if given.
+    fn generate_struct_init(
+        &mut self,
+        func: &mut QbeFunction,
+        name: &str,
+        update: Option<&Expression>,
+        fields: &HashMap<String, Box<Expression>>,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let (ty, meta, size) = self
+            .struct_map
+            .get(name)
+            .ok_or_else(|| format!("Initialization of undeclared struct '{}'", name))?
+            .to_owned();
+        let src = match update {
+            Some(expr) => Some(self.generate_expression(func, expr)?.1),
+            None => None,
+        };
+        let base = self.new_temporary();
+
+        func.assign_instr(
+            base.clone(),
+            QbeType::Long,
+            // XXX: Always align to 8 bytes?
+            QbeInstr::Alloc8(size),
+        );
+
+        for (name, expr) in fields {
+            let (field_ty, offset) = meta
+                .get(name)
+                .ok_or_else(|| format!("Unknown field '{}'", name))?;
+
+            let (_, expr_tmp) = self.generate_expression(func, expr)?;
+
+            let field_tmp = self.new_temporary();
+            func.assign_instr(
+                field_tmp.clone(),
+                QbeType::Long,
+                QbeInstr::Add(base.clone(), QbeValue::Const(*offset)),
+            );
+
+            // Fields are stored with their own size, which may be smaller than the value
+            self.store_value(func, field_ty.clone(), field_tmp, expr_tmp)?;
+        }
+
+        if let Some(src) = src {
+            let mut copied: Vec<&(QbeType, u64)> = meta
+                .iter()
+                .filter(|(name, _)| !fields.contains_key(*name))
+                .map(|(_, field)| field)
+                .collect();
+            copied.sort_by_key(|(_, offset)| *offset);
+            for (field_ty, offset) in copied {
+                let src_ptr = self.new_temporary();
+                func.assign_instr(
+                    src_ptr.clone(),
+                    QbeType::Long,
+                    QbeInstr::Add(src.clone(), QbeValue::Const(*offset)),
+                );
+                let value = self.load_value(func, field_ty.clone(), src_ptr);
+                let dest_ptr = self.new_temporary();
+                func.assign_instr(
+                    dest_ptr.clone(),
+                    QbeType::Long,
+                    QbeInstr::Add(base.clone(), QbeValue::Const(*offset)),
+                );
+                self.store_value(func, field_ty.clone(), dest_ptr, value)?;
+            }
+        }
+
+        Ok((ty, base))
+    }
+
+    /// Retrieves the result of struct field access
+    fn generate_field_access(
+        &mut self,
+        func: &mut QbeFunction,
+        obj: &Expression,
+        field: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let (src, ty, offset) = self.resolve_field_access(func, obj, field)?;
+
+        let field_ptr = self.new_temporary();
+        func.assign_instr(
+            field_ptr.clone(),
+            QbeType::Long,
+            QbeInstr::Add(src, QbeValue::Const(offset)),
+        );
+
+        let value = self.load_value(func, ty.clone(), field_ptr);
+        Ok((ty, value))
+    }
+
+    /// Stores a value of the type at the address. Structures are contained by
+    /// value, so they are copied to the address.
+    fn store_value(
+        &self,
+        func: &mut QbeFunction,
+        ty: QbeType,
+        dest: QbeValue,
+        value: QbeValue,
+    ) -> GeneratorResult<()> {
+        if !matches!(ty, QbeType::Aggregate(_)) {
+            func.add_instr(QbeInstr::Store(ty, dest, value));
+            return Ok(());
+        }
+        let (.., size) = self
+            .struct_map
+            .values()
+            .find(|(sty, ..)| *sty == ty)
+            .ok_or_else(|| format!("Unknown aggregate type {}", ty))?;
+        func.add_instr(QbeInstr::Call(
+            "memcpy".into(),
+            vec![
+                (QbeType::Long, dest),
+                (QbeType::Long, value),
+                (QbeType::Long, QbeValue::Const(*size)),
+            ],
+        ));
+        Ok(())
+    }
+
+    /// Loads a value of the type from the address. The value of a structure
+    /// is its address.
+    fn load_value(&mut self, func: &mut QbeFunction, ty: QbeType, src: QbeValue) -> QbeValue {
+        if matches!(ty, QbeType::Aggregate(_)) {
+            return src;
+        }
+        let tmp = self.new_temporary();
+        func.assign_instr(tmp.clone(), ty.clone(), QbeInstr::Load(ty, src));
+        tmp
+    }
+
+    /// Retrieves `(source, offset)` from field access expression
+    fn resolve_field_access(
+        &mut self,
+        func: &mut QbeFunction,
+        obj: &Expression,
+        field: &Expression,
+    ) -> GeneratorResult<(QbeValue, QbeType, u64)> {
+        let (ty, src) = match obj {
+            Expression::Variable(var) => self.load_var(func, var)?,
+            // Structures in fields and behind references evaluate to their address
+            Expression::FieldAccess(..) | Expression::Dereference(_) => {
+                self.generate_expression(func, obj)?
+            }
+            Expression::Selff => unimplemented!("methods"),
+            other => {
+                return Err(format!(
+                    "Invalid field access type: expected variable, field access or 'self', got {:?}",
+                    other,
+                ));
+            }
+        };
+        let field = match field {
+            Expression::Variable(v) => v,
+            Expression::FunctionCall(..) => unimplemented!("methods"),
+            // Parser should ensure this won't happen
+            _ => unreachable!(),
+        };
+
+        // XXX: this is very hacky and inefficient
+        let (name, meta) = self
+            .struct_map
+            .iter()
+            .filter_map(
+                |(name, (sty, meta, _))| {
+                    if ty == *sty {
+                        Some((name, meta))
+                    } else {
+                        None
+                    }
+                },
+            )
+            .next()
+            .unwrap();
+
+        let (ty, offset) = meta
+            .get(field)
+            .ok_or_else(|| format!("No field '{}' on struct {}", field, name))?
+            .to_owned();
+
+        Ok((src, ty, offset))
+    }
+
+    /// Allocates a vector of the runtime with `len` elements on the stack
+    fn generate_stack_vec(
+        &mut self,
+        func: &mut QbeFunction,
+        vec: &QbeValue,
+        len: usize,
+        zeroed: bool,
+    ) {
+        let cap = len.max(1) as u64;
+        func.assign_instr(
+            vec.clone(),
+            QbeType::Long,
+            QbeInstr::Alloc8(3 * VEC_SLOT_SIZE),
+        );
+        let data = self.new_temporary();
+        func.assign_instr(
+            data.clone(),
+            QbeType::Long,
+            QbeInstr::Alloc8(cap * VEC_SLOT_SIZE),
+        );
+        // Unlike the heap, the stack is not zeroed
+        if zeroed {
+            func.add_instr(QbeInstr::Call(
+                "memset".into(),
+                vec![
+                    (QbeType::Long, data.clone()),
+                    (QbeType::Word, QbeValue::Const(0)),
+                    (QbeType::Long, QbeValue::Const(cap * VEC_SLOT_SIZE)),
+                ],
+            ));
+        }
+
+        func.add_instr(QbeInstr::Store(QbeType::Long, vec.clone(), data));
+        for (offset, value) in [(1, len as u64), (2, cap)] {
+            let field = self.new_temporary();
+            func.assign_instr(
+                field.clone(),
+                QbeType::Long,
+                QbeInstr::Add(vec.clone(), QbeValue::Const(offset * VEC_SLOT_SIZE)),
+            );
+            func.add_instr(QbeInstr::Store(
+                QbeType::Long,
+                field,
+                QbeValue::Const(value),
+            ));
+        }
+    }
+
+    /// Returns true if the variable is an array of the current function that is allocated on the stack
+    fn is_stack_array(&self, name: &str) -> bool {
+        self.current_func
+            .as_ref()
+            .and_then(|(func, _)| self.stack_arrays.get(func))
+            .is_some_and(|arrays| arrays.contains(name))
+    }
+
+    /// Generates an array literal. Arrays `on_stack` are allocated in the stack frame of the
+    /// current function, their elements are moved to the heap once the array grows.
+    fn generate_array(
+        &mut self,
+        func: &mut QbeFunction,
+        len: usize,
+        items: &[Expression],
+        on_stack: bool,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let mut first_type: Option<QbeType> = None;
+        let mut results: Vec<QbeValue> = Vec::new();
+
+        for item in items.iter() {
+            let (ty, result) = self.generate_expression(func, item)?;
+            results.push(result);
+
+            if let Some(first_type) = first_type.clone() {
+                if ty != first_type {
+                    return Err(format!(
+                        "Inconsistent array types {:?} and {:?} (possibly more)",
+                        first_type, ty
+                    ));
+                }
+            } else {
+                first_type = Some(ty);
+            }
+        }
+
+        // Arrays are vectors of the runtime with the following in-memory representation:
+        // {
+        //    data (pointer to 8 byte slots),
+        //    length (long),
+        //    capacity (long),
+        // }
+        let tmp = self.new_temporary();
+        if on_stack {
+            self.generate_stack_vec(func, &tmp, len, items.is_empty());
+        } else {
+            func.assign_instr(
+                tmp.clone(),
+                QbeType::Long,
+                QbeInstr::Call(
+                    "_vec_new".into(),
+                    vec![(QbeType::Long, QbeValue::Const(len as u64))],
+                ),
+            );
+        }
+
+        if let Some(ty) = first_type {
+            let data = self.new_temporary();
+            func.assign_instr(
+                data.clone(),
+                QbeType::Long,
+                QbeInstr::Load(QbeType::Long, tmp.clone()),
+            );
+            for (i, value) in results.into_iter().enumerate() {
+                let value_ptr = self.new_temporary();
+                func.assign_instr(
+                    value_ptr.clone(),
+                    QbeType::Long,
+                    QbeInstr::Add(data.clone(), QbeValue::Const(i as u64 * VEC_SLOT_SIZE)),
+                );
+                func.add_instr(QbeInstr::Store(ty.clone().into_base(), value_ptr, value));
+            }
+        }
+
+        Ok((QbeType::Long, tmp))
+    }
+
+    /// Generates a map literal. The type of the keys is inferred from the first entry,
+    /// unless it is given.
+    fn generate_map(
+        &mut self,
+        func: &mut QbeFunction,
+        entries: &[(Expression, Expression)],
+        key_type: Option<&Type>,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let key_type = key_type
+            .cloned()
+            .or_else(|| {
+                let (key, _) = entries.first()?;
+                self.types.expression_type(key)
+            })
+            .ok_or("Cannot infer the type of the keys of an empty map")?;
+
+        // Strings are hashed by their contents, other keys by their value
+        let tmp = self.new_temporary();
+        func.assign_instr(
+            tmp.clone(),
+            QbeType::Long,
+            QbeInstr::Call(
+                "_map_new".into(),
+                vec![(
+                    QbeType::Word,
+                    QbeValue::Const((key_type == Type::Str) as u64),
+                )],
+            ),
+        );
+
+        for (key, value) in entries {
+            let mut args = vec![(QbeType::Long, tmp.clone())];
+            for expr in [key, value] {
+                let (ty, value) = self.generate_expression(func, expr)?;
+                args.push((QbeType::Long, self.extend_to_long(func, ty, value)));
+            }
+            func.add_instr(QbeInstr::Call("_map_insert".into(), args));
+        }
+
+        Ok((QbeType::Long, tmp))
+    }
+
+    /// Returns the type of the elements of an array and a pointer to the element at the given index
+    fn generate_element_ptr(
+        &mut self,
+        func: &mut QbeFunction,
+        name: &str,
+        index: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let ty = match self
+            .types
+            .expression_type(&Expression::Variable(name.to_owned()))
+        {
+            Some(Type::Array(ty, _)) => self.get_type(*ty)?.into_base(),
+            _ => return Err(format!("Variable '{}' is not an array", name)),
+        };
+        let (_, vec) = self.load_var(func, name)?;
+        let (index_ty, index) = self.generate_expression(func, index)?;
+        let index = self.extend_to_long(func, index_ty, index);
+
+        let data = self.new_temporary();
+        func.assign_instr(
+            data.clone(),
+            QbeType::Long,
+            QbeInstr::Load(QbeType::Long, vec),
+        );
+        let offset = self.new_temporary();
+        func.assign_instr(
+            offset.clone(),
+            QbeType::Long,
+            QbeInstr::Mul(index, QbeValue::Const(VEC_SLOT_SIZE)),
+        );
+        let ptr = self.new_temporary();
+        func.assign_instr(ptr.clone(), QbeType::Long, QbeInstr::Add(data, offset));
+
+        Ok((ty, ptr))
+    }
+
+    /// Sign-extends a word to a long, other values are returned unchanged
+    fn extend_to_long(&mut self, func: &mut QbeFunction, ty: QbeType, value: QbeValue) -> QbeValue {
+        if ty.into_base() != QbeType::Word {
+            return value;
+        }
+        let tmp = self.new_temporary();
+        func.assign_instr(tmp.clone(), QbeType::Long, QbeInstr::Extsw(value));
+        tmp
+    }
+
+    /// Returns a new temporary that is unique within the current function
+    fn new_temporary(&mut self) -> QbeValue {
+        self.names.temporaries += 1;
+        QbeValue::Temporary(format!("tmp.{}", self.names.temporaries))
+    }
+
+    /// Returns a new temporary bound to a variable. A variable that has
+    /// already been declared is shadowed until the end of the current scope.
+    fn new_var(&mut self, ty: &QbeType, name: &str) -> QbeValue {
+        let tmp = self.new_temporary();
+
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("expected last scope to be present");
+        scope.insert(name.to_owned(), (ty.to_owned(), tmp.to_owned()));
+
+        tmp
+    }
+
+    /// Returns a temporary accociated to a variable
+    fn get_var(&self, name: &str) -> GeneratorResult<&(QbeType, QbeValue)> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|s| s.get(name))
+            .next()
+            .ok_or_else(|| format!("Undefined variable '{}'", name))
+    }
+
+    /// Returns the value of a local or global variable. Globals are loaded from memory.
+    fn load_var(
+        &mut self,
+        func: &mut QbeFunction,
+        name: &str,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let ty = match self.globals.get(name) {
+            Some(ty) => ty.clone(),
+            None => {
+                let (ty, tmp) = self.get_var(name)?.to_owned();
+                if !self.in_slot(name, &ty) {
+                    return Ok((ty, tmp));
+                }
+                let value = self.load_value(func, ty.clone().into_base(), tmp);
+                return Ok((ty.into_abi(), value));
+            }
+        };
+        let tmp = self.new_temporary();
+        func.assign_instr(
+            tmp.clone(),
+            ty.clone().into_base(),
+            QbeInstr::Load(ty.clone().into_base(), global_symbol(name)),
+        );
+        Ok((ty.into_abi(), tmp))
+    }
+
+    /// Returns true if a variable is kept in a stack slot, because its address is taken.
+    /// Structures are stored in memory anyway, so their temporary already is an address.
+    fn in_slot(&self, name: &str, ty: &QbeType) -> bool {
+        self.addressed.contains(name) && !matches!(ty, QbeType::Aggregate(_))
+    }
+
+    /// Allocates the stack slot of a variable at the start of the function, so that
+    /// a slot that is declared in a loop is not allocated again. Slots hold values
+    /// other than structures, which fit into 8 bytes.
+    fn allocate_slot(&self, func: &mut QbeFunction, slot: QbeValue) -> GeneratorResult<()> {
+        let start = func.blocks.first_mut().ok_or("Function without a block")?;
+        start.instructions.insert(
+            0,
+            QbeStatement::Assign(slot, QbeType::Long, QbeInstr::Alloc8(8)),
+        );
+        Ok(())
+    }
+
+    /// Returns the address of a variable, field or element of an array
+    fn generate_reference(
+        &mut self,
+        func: &mut QbeFunction,
+        target: &Expression,
+    ) -> GeneratorResult<(QbeType, QbeValue)> {
+        let ptr = match target {
+            Expression::Variable(name) if self.globals.contains_key(name) => global_symbol(name),
+            // Slots and structures are addresses already
+            Expression::Variable(name) => self.get_var(name)?.1.to_owned(),
+            Expression::FieldAccess(obj, field) => {
+                let (src, _, offset) = self.resolve_field_access(func, obj, field)?;
+                let ptr = self.new_temporary();
+                func.assign_instr(
+                    ptr.clone(),
+                    QbeType::Long,
+                    QbeInstr::Add(src, QbeValue::Const(offset)),
+                );
+                ptr
+            }
+            Expression::ArrayAccess(name, index) => self.generate_element_ptr(func, name, index)?.1,
+            other => return Err(format!("Can not take a reference to {:?}", other)),
+        };
+        Ok((QbeType::Long, ptr))
+    }
+
+    /// Returns the type of the value a reference points to
+    fn referenced_type(&self, reference: &Expression) -> GeneratorResult<QbeType> {
+        match self.types.expression_type(reference) {
+            Some(Type::Ref(inner)) => self.get_type(*inner),
+            _ => Err("Only references can be dereferenced".to_owned()),
+        }
+    }
+
+    /// Returns a QBE type for the given AST type
+    fn get_type(&self, ty: Type) -> GeneratorResult<QbeType> {
+        match ty {
+            Type::Any => Err("'any' type is not supported".into()),
+            Type::Int => Ok(QbeType::Word),
+            Type::Bool => Ok(QbeType::Byte),
+            Type::Str => Ok(QbeType::Long),
+            Type::Struct(name) => {
+                let (ty, ..) = self
+                    .struct_map
+                    .get(&name)
+                    .ok_or_else(|| format!("Use of undeclared struct '{}'", name))?
+                    .to_owned();
+                Ok(ty)
+            }
+            Type::Array(..)
+            | Type::Map(..)
+            | Type::Chan(_)
+            | Type::Result(_)
+            | Type::Ref(_)
+            | Type::Ptr => Ok(QbeType::Long),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Returns the names of the variables in a function body whose address is taken with `&`.
+/// These variables are kept in a stack slot instead of a temporary.
+pub(super) fn addressed_variables(body: &Statement) -> HashSet<String> {
+    let mut names = HashSet::new();
+    statement(body, &mut names);
+    names
+}
+
+fn statement(stmt: &Statement, names: &mut HashSet<String>) {
+    match stmt {
+        Statement::Block(statements, _) => statements.iter().for_each(|s| statement(s, names)),
+        Statement::Declare(_, value) => value.iter().for_each(|v| expression(v, names)),
+        Statement::Static(_, value)
+        | Statement::Destructure(_, value)
+        | Statement::Exp(value)
+        | Statement::Defer(value)
+        | Statement::Spawn(value)
+        | Statement::Return(Some(value)) => expression(value, names),
+        Statement::Assign(lhs, rhs) => {
+            expression(lhs, names);
+            expression(rhs, names);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            expression(cond, names);
+            statement(if_branch, names);
+            else_branch.iter().for_each(|s| statement(s, names));
+        }
+        Statement::Unsafe(body) => statement(body, names),
+        Statement::While(value, body)
+        | Statement::For(_, value, body)
+        | Statement::ForPair(_, _, value, body) => {
+            expression(value, names);
+            statement(body, names);
+        }
+        Statement::Match(subject, arms) => {
+            expression(subject, names);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(value, body) => {
+                        expression(value, names);
+                        statement(body, names);
+                    }
+                    MatchArm::Else(body) => statement(body, names),
+                }
+            }
+        }
+        Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+}
+
+fn expression(expr: &Expression, names: &mut HashSet<String>) {
+    match expr {
+        Expression::Reference(target) => match &**target {
+            Expression::Variable(name) => {
+                names.insert(name.clone());
+            }
+            other => expression(other, names),
+        },
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::SizeOf(_)
+        | Expression::Variable(_) => {}
+        Expression::ArrayAccess(_, value)
+        | Expression::Try(value)
+        | Expression::Await(value)
+        | Expression::Dereference(value)
+        | Expression::Load(_, value) => expression(value, names),
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+            args.iter().for_each(|arg| expression(arg, names))
+        }
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => {
+            expression(lhs, names);
+            expression(rhs, names);
+        }
+        Expression::StructInitialization(_, fields) => {
+            fields.values().for_each(|field| expression(field, names))
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            expression(base, names);
+            fields.values().for_each(|field| expression(field, names))
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                expression(key, names);
+                expression(value, names);
+            }
+        }
+    }
+}
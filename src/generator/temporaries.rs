@@ -0,0 +1,219 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::qbe::ir::{QbeFunction, QbeInstr, QbeStatement, QbeType, QbeValue};
+use std::collections::{HashMap, HashSet};
+
+/// Reuses the temporaries of a function once their value is no longer needed, so QBE has
+/// fewer of them to allocate registers for. Two temporaries share a name if neither of them
+/// is live where the other one is assigned, and they are assigned values of the same type.
+///
+/// Only temporaries that are assigned exactly once are renamed. Arguments and temporaries
+/// that phis read or assign keep their names. Functions that contain `qbe!` blocks are left
+/// untouched. Returns true if any temporary was renamed.
+pub(super) fn reuse_temporaries(func: &mut QbeFunction) -> bool {
+    let statements = func.blocks.iter().flat_map(|block| &block.instructions);
+    if statements
+        .clone()
+        .any(|stmt| matches!(stmt, QbeStatement::Raw(_)))
+    {
+        return false;
+    }
+
+    // Temporaries that can be renamed, in the order they are assigned
+    let mut fixed: HashSet<&str> = HashSet::new();
+    for (_, arg) in &func.arguments {
+        if let QbeValue::Temporary(name) = arg {
+            fixed.insert(name);
+        }
+    }
+    let mut assigned: Vec<(&str, &QbeType)> = Vec::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    for stmt in statements {
+        if let QbeStatement::Assign(QbeValue::Temporary(name), ty, instr) = stmt {
+            if let QbeInstr::Phi(incoming) = instr {
+                fixed.insert(name);
+                fixed.extend(incoming.iter().filter_map(|(_, value)| match value {
+                    QbeValue::Temporary(name) => Some(name.as_str()),
+                    _ => None,
+                }));
+            }
+            if !seen.insert(name) {
+                fixed.insert(name);
+            }
+            assigned.push((name, ty));
+        }
+    }
+    let candidates: Vec<(&str, &QbeType)> = assigned
+        .into_iter()
+        .filter(|(name, _)| !fixed.contains(name))
+        .collect();
+    if candidates.len() < 2 {
+        return false;
+    }
+    let index: HashMap<&str, usize> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, i))
+        .collect();
+
+    let interference = interference(func, &index);
+
+    // Greedy coloring in the order of assignment, the name of a color is the name of its first temporary
+    let mut colors: Vec<usize> = Vec::with_capacity(candidates.len());
+    let mut names: Vec<(&str, &QbeType)> = Vec::new();
+    for (i, (name, ty)) in candidates.iter().enumerate() {
+        let taken: HashSet<usize> = interference[i]
+            .iter()
+            .filter(|other| **other < i)
+            .map(|other| colors[*other])
+            .collect();
+        let color = (0..names.len())
+            .find(|color| names[*color].1 == *ty && !taken.contains(color))
+            .unwrap_or_else(|| {
+                names.push((name, ty));
+                names.len() - 1
+            });
+        colors.push(color);
+    }
+
+    let renames: HashMap<String, String> = candidates
+        .iter()
+        .zip(&colors)
+        .filter(|((name, _), color)| *name != names[**color].0)
+        .map(|((name, _), color)| (name.to_string(), names[*color].0.to_owned()))
+        .collect();
+    if renames.is_empty() {
+        return false;
+    }
+
+    let rename = |value: &mut QbeValue| {
+        if let QbeValue::Temporary(name) = value {
+            if let Some(new_name) = renames.get(name) {
+                *name = new_name.clone();
+            }
+        }
+    };
+    for block in &mut func.blocks {
+        for stmt in &mut block.instructions {
+            match stmt {
+                QbeStatement::Assign(temp, _, instr) => {
+                    rename(temp);
+                    instr.operands_mut().into_iter().for_each(rename);
+                }
+                QbeStatement::Volatile(instr) => {
+                    instr.operands_mut().into_iter().for_each(rename);
+                }
+                QbeStatement::Raw(_) => {}
+            }
+        }
+        // A copy between two temporaries that now share a name does nothing
+        block.instructions.retain(|stmt| {
+            !matches!(
+                stmt,
+                QbeStatement::Assign(QbeValue::Temporary(dest), _, QbeInstr::Copy(QbeValue::Temporary(src)))
+                    if dest == src
+            )
+        });
+    }
+    true
+}
+
+/// Finds the pairs of temporaries that are live at the same time. For every temporary,
+/// returns the indices of the temporaries it interferes with.
+fn interference(func: &QbeFunction, index: &HashMap<&str, usize>) -> Vec<HashSet<usize>> {
+    let temporary = |value: &QbeValue| match value {
+        QbeValue::Temporary(name) => index.get(name.as_str()).copied(),
+        _ => None,
+    };
+    let labels: HashMap<&str, usize> = func
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block.label.as_str(), i))
+        .collect();
+
+    // Temporaries a block reads before assigning them, and the ones it assigns
+    let mut uses = vec![HashSet::new(); func.blocks.len()];
+    let mut defs = vec![HashSet::new(); func.blocks.len()];
+    let mut successors = vec![Vec::new(); func.blocks.len()];
+    for (i, block) in func.blocks.iter().enumerate() {
+        for stmt in &block.instructions {
+            let (dest, instr) = match stmt {
+                QbeStatement::Assign(temp, _, instr) => (temporary(temp), instr),
+                QbeStatement::Volatile(instr) => (None, instr),
+                QbeStatement::Raw(_) => continue,
+            };
+            for value in instr.operands().into_iter().filter_map(temporary) {
+                if !defs[i].contains(&value) {
+                    uses[i].insert(value);
+                }
+            }
+            defs[i].extend(dest);
+            successors[i].extend(
+                instr
+                    .targets()
+                    .iter()
+                    .filter_map(|l| labels.get(l.as_str())),
+            );
+        }
+        if !block.jumps() && i + 1 < func.blocks.len() {
+            successors[i].push(i + 1);
+        }
+    }
+
+    let mut live_in: Vec<HashSet<usize>> = uses.clone();
+    let mut live_out: Vec<HashSet<usize>> = vec![HashSet::new(); func.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..func.blocks.len()).rev() {
+            let out: HashSet<usize> = successors[i]
+                .iter()
+                .flat_map(|succ| live_in[*succ].iter().copied())
+                .collect();
+            let new_in: HashSet<usize> = out
+                .iter()
+                .filter(|value| !defs[i].contains(*value))
+                .chain(&uses[i])
+                .copied()
+                .collect();
+            changed |= new_in.len() != live_in[i].len() || out.len() != live_out[i].len();
+            live_in[i] = new_in;
+            live_out[i] = out;
+        }
+    }
+
+    let mut interference = vec![HashSet::new(); index.len()];
+    for (i, block) in func.blocks.iter().enumerate() {
+        let mut live = live_out[i].clone();
+        for stmt in block.instructions.iter().rev() {
+            let (dest, instr) = match stmt {
+                QbeStatement::Assign(temp, _, instr) => (temporary(temp), instr),
+                QbeStatement::Volatile(instr) => (None, instr),
+                QbeStatement::Raw(_) => continue,
+            };
+            if let Some(dest) = dest {
+                live.remove(&dest);
+                for other in &live {
+                    interference[dest].insert(*other);
+                    interference[*other].insert(dest);
+                }
+            }
+            live.extend(instr.operands().into_iter().filter_map(temporary));
+        }
+    }
+    interference
+}
@@ -13,7 +13,26 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use crate::generator::peephole;
+use crate::generator::qbe::ir::*;
 use crate::generator::qbe::*;
+use crate::generator::temporaries;
+use crate::generator::Arch;
+use crate::lexer::tokenize;
+use crate::parser::parse;
+use crate::semantic;
+
+fn generate_raw(raw: &str) -> String {
+    generate_optimized(raw, 0)
+}
+
+fn generate_optimized(raw: &str, opt_level: u8) -> String {
+    let tokens = tokenize(raw).unwrap();
+    let mut module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    semantic::analyze(&mut module);
+    semantic::check(&mut module).unwrap();
+    QbeGenerator::generate_for(module, Arch::host(), opt_level).unwrap()
+}
 
 #[test]
 fn qbe_value() {
@@ -139,3 +158,842 @@ fn type_into_base() {
     assert_eq!(QbeType::Halfword.into_base(), QbeType::Word);
     assert_eq!(QbeType::Aggregate("foo".into()).into_base(), QbeType::Long);
 }
+
+#[test]
+fn tail_call() {
+    let raw = "
+    fn sum(n: int, acc: int): int {
+        if n == 0 {
+            return acc
+        }
+        return sum(n - 1, acc + n)
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(!generated.contains("call $sum"));
+    // The loop that replaces the call merges the arguments with the values of the call
+    assert!(generated.contains("phi @start %tmp.1, @block.3 %tmp.6"));
+    assert!(generated.contains("jmp @block.1"));
+}
+
+#[test]
+fn no_tail_call_for_non_tail_recursion() {
+    let raw = "
+    fn fib(n: int): int {
+        if n < 2 {
+            return n
+        }
+        return fib(n - 1) + fib(n - 2)
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("call $fib"));
+    assert!(!generated.contains("@body"));
+}
+
+#[test]
+fn shadowing() {
+    let raw = "
+    fn main() {
+        let x: int = 1
+        let x: int = x + 1
+        if true {
+            let x: int = 3
+            println(x)
+        }
+        println(x)
+    }
+    ";
+    let generated = generate_raw(raw);
+    let lines: Vec<&str> = generated.lines().collect();
+    // Each declaration is bound to a new value, the initializer
+    // refers to the previous one
+    assert!(lines.contains(&"\t%tmp.1 =w add 1, 1"));
+    assert!(lines.contains(&"\tcall $println(w 3)"));
+    assert!(lines.contains(&"\tcall $println(w %tmp.1)"));
+}
+
+#[test]
+fn names_are_scoped_to_functions() {
+    let first = "
+    fn greet(loud: bool) {
+        if loud {
+            println(\"hi\")
+        }
+    }
+    ";
+    let second = "
+    fn count(n: int) {
+        while n > 0 {
+            n -= 1
+        }
+    }
+    ";
+    let body = |generated: &str| {
+        let start = generated.find("function $greet(").unwrap();
+        let end = start + generated[start..].find("\n}").unwrap();
+        generated[start..end].to_owned()
+    };
+    // Adding a function before another one does not rename its temporaries and labels
+    let alone = generate_raw(first);
+    let after = generate_raw(&format!("{}{}", second, first));
+    assert_eq!(body(&alone), body(&after));
+    assert!(body(&alone).contains("jnz %tmp.1, @block.1, @block.2"));
+    assert!(alone.contains("data $string.greet.1 = { b \"hi\", b 0 }"));
+}
+
+#[test]
+fn extern_function() {
+    let raw = "
+    extern \"C\" fn getenv(name: string): string
+
+    fn main() {
+        let home: string = getenv(\"HOME\")
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("=l call $getenv(l "));
+    assert!(!generated.contains("function l $getenv"));
+}
+
+#[test]
+fn string_operators() {
+    let raw = "
+    fn main() {
+        let a: string = \"foo\"
+        let b: string = a + \"bar\"
+        let equal: bool = a == b
+        let different: bool = a != b
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("function l $_str_concat(l %a, l %b)"));
+    assert!(generated.contains("=l call $_str_concat(l "));
+    assert_eq!(generated.matches("=w call $_str_eq(l ").count(), 2);
+    assert!(generated.contains("=w ceqw "));
+}
+
+#[test]
+fn string_methods() {
+    let raw = "
+    fn main() {
+        let s: string = \" foo \"
+        let t = s.trim()
+        let n = t.len()
+        let found = t.contains(\"o\")
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("=l call $_str_trim(l "));
+    assert!(generated.contains("=w call $_str_len(l "));
+    assert!(generated.contains("=w call $_str_contains(l "));
+}
+
+#[test]
+fn bitwise_builtins_are_lowered_to_instructions() {
+    // The first function is lowered through the SSA form, the second one uses an array
+    let raw = "
+    fn mix(a: int, b: int): int {
+        return _bit_xor(_shift_left(a, 5), _shift_right(b, 2))
+    }
+
+    fn masks(): int[] {
+        let n = 6
+        return [_bit_and(n, 3), _bit_or(n, 1), _count_ones(n)]
+    }
+    ";
+    let generated = generate_raw(raw);
+    for instr in ["xor", "shl", "shr", "and", "or"] {
+        assert!(generated.contains(&format!("=w {} ", instr)), "{}", instr);
+    }
+    assert!(!generated.contains("call $_bit_"));
+    assert!(generated.contains("=w call $_count_ones(w "));
+}
+
+#[test]
+fn vectors() {
+    let raw = "
+    fn keep(arr: int[]): int[] {
+        return arr
+    }
+
+    fn main() {
+        let v = [1, 2]
+        v.push(3)
+        v[0] = v[2]
+        let uninitialized: int[4]
+        keep(v)
+        keep(uninitialized)
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("=l call $_vec_new(l 2)"));
+    assert!(generated.contains("=l call $_vec_new(l 4)"));
+    assert!(generated.contains("=l extsw "));
+    assert!(generated.contains("call $_vec_push(l "));
+    assert!(generated.contains("=w loadw "));
+    assert_eq!(generated.matches("=l mul ").count(), 2);
+}
+
+#[test]
+fn loops_over_arrays_are_optimized() {
+    let raw = "
+    fn scale(xs: int[], factor: int): int {
+        let total = 0
+        for x in xs {
+            total += x * 8 + factor * 3
+        }
+        return total
+    }
+
+    fn main() {}
+    ";
+    let output = generate_optimized(raw, 2);
+    // The length of the array and `factor * 3` are computed in front of the loop
+    assert!(output.contains(
+        "@start\n\t%tmp.3 =w call $_vec_len(l %tmp.1)\n\t%tmp.4 =w shl %tmp.2, 1\n\t%tmp.4 =w add %tmp.4, %tmp.2\n\tjmp @block.1"
+    ));
+    // Elements are found by shifting the index, and `x * 8` is a shift as well
+    assert!(output.contains("=l shl %tmp.15, 3\n"));
+    assert!(output.contains("=w shl %tmp.8, 3\n"));
+    assert!(!output.contains(" mul "));
+}
+
+#[test]
+fn maps() {
+    let raw = "
+    fn main() {
+        let ages = { \"alice\": 31 }
+        let squares: map<int, int> = {}
+        squares.insert(2, 4)
+        let four: int = squares.get(2)
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("=l call $_map_new(w 1)"));
+    assert!(generated.contains("=l call $_map_new(w 0)"));
+    assert_eq!(generated.matches("call $_map_insert(l ").count(), 2);
+    assert!(generated.contains("=w call $_map_get(l "));
+}
+
+#[test]
+fn entry_point() {
+    let raw = "
+    fn main() {
+        let args: string[] = _args()
+        let home: string = _env(\"HOME\")
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("function $_sb_main() {"));
+    assert!(generated.contains("export function w $main(w %argc, l %argv) {"));
+    assert!(generated.contains("\tcall $_init_args(w %argc, l %argv)"));
+    assert!(generated.contains("\tcall $_sb_main()"));
+    assert!(generated.contains("=l call $_args()"));
+    assert!(generated.contains("=l call $_env(l "));
+}
+
+#[test]
+fn exit_code() {
+    let raw = "
+    fn main(): int {
+        return 3
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("function w $_sb_main() {"));
+    assert!(generated.contains("\t%code =w call $_sb_main()"));
+    assert!(generated.contains("\tret %code"));
+}
+
+#[test]
+fn result() {
+    let raw = "
+    fn parse(s: string): int! {
+        return 1
+    }
+
+    fn twice(s: string): int! {
+        return parse(s)? * 2
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("function l $parse(l %tmp.1) {"));
+    assert!(generated.contains("call $_result_ok(l "));
+    assert!(generated.contains("=w call $_result_is_error(l "));
+    assert!(generated.contains("=w call $_result_value(l "));
+}
+
+#[test]
+fn defer() {
+    let raw = "
+    fn cleanup(n: int) {}
+
+    fn main(): int {
+        defer cleanup(1)
+        defer cleanup(2)
+        return 3
+    }
+    ";
+    let generated = generate_raw(raw);
+    let body = generated.split("function w $_sb_main() {").nth(1).unwrap();
+    let first = body.find("call $cleanup(w %tmp.").unwrap();
+    let ret = body.find("\tret %").unwrap();
+    assert!(first < ret);
+    // Both expressions are evaluated before the return, the last one first
+    assert_eq!(body[..ret].matches("call $cleanup").count(), 2);
+    assert!(body.find("copy 2").unwrap() < body.find("copy 1").unwrap());
+}
+
+#[test]
+fn struct_update() {
+    let raw = "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    fn main() {
+        let p = new Point {
+            x: 1
+            y: 2
+        }
+        let q = new Point {
+            ..p
+            x: 3
+        }
+    }
+    ";
+    let generated = generate_raw(raw);
+    // Only the omitted field is copied
+    assert_eq!(generated.matches("loadw").count(), 1);
+    assert_eq!(generated.matches("storew").count(), 4);
+}
+
+#[test]
+fn nested_structs_are_stored_by_value() {
+    let raw = "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    struct Line {
+        visible: bool
+        start: Point
+    }
+
+    fn main() {
+        let p = new Point {
+            x: 1
+            y: 2
+        }
+        let line = new Line {
+            visible: true
+            start: p
+        }
+        println(line.start.y)
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("type :struct.2 = { b, :struct.1 }"));
+    assert!(generated.contains("alloc8 12"));
+    // The point is copied behind the padding after `visible`
+    assert!(generated.contains("storeb "));
+    assert!(generated.contains(", l 8)"));
+    assert!(generated.contains(", 4\n\tcall $memcpy("));
+}
+
+#[test]
+fn structs_follow_the_structs_of_their_fields() {
+    let raw = "
+    struct Line {
+        start: Point
+    }
+
+    struct Point {
+        x: int
+    }
+
+    fn main() {
+        let line = new Line {
+            start: new Point { x: 1 }
+        }
+        println(line.start.x)
+    }
+    ";
+    let generated = generate_raw(raw);
+    let point = generated.find("type :struct.1 = { w }").unwrap();
+    let line = generated.find("type :struct.2 = { :struct.1 }").unwrap();
+    assert!(point < line);
+}
+
+#[test]
+fn globals() {
+    let raw = "
+    const LIMIT = 3
+    let count = 0
+    let name = \"antimony\"
+
+    fn main() {
+        count += LIMIT
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("data $global.LIMIT = { w 3 }"));
+    assert!(generated.contains("data $global.name = { l 0 }"));
+    // Initializers that are not literals are evaluated before main is called
+    assert!(generated.contains("storel $string."));
+    let entry = generated.split("export function w $main(").nth(1).unwrap();
+    assert!(
+        entry.find("call $_sb_init_globals()").unwrap() < entry.find("call $_sb_main()").unwrap()
+    );
+    assert!(generated.contains("loadw $global.LIMIT"));
+    assert!(generated.contains(", $global.count"));
+}
+
+#[test]
+fn inferred_return_type() {
+    let raw = "
+    fn answer() {
+        return 42
+    }
+
+    fn main() {
+        let x = answer()
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("function w $answer()"));
+}
+
+#[test]
+fn non_escaping_arrays_are_allocated_on_the_stack() {
+    let raw = "
+    fn sum(arr: int[]): int {
+        return arr[0] + arr[1]
+    }
+
+    fn main() {
+        let local = [1, 2, 3]
+        println(sum(local))
+    }
+
+    fn make(): int[] {
+        let arr = [1, 2, 3]
+        return arr
+    }
+    ";
+    let generated = generate_raw(raw);
+    let main = &generated[generated.find("function $_sb_main").unwrap()..];
+    let main = &main[..main.find('}').unwrap()];
+    assert!(main.contains("alloc8 24"));
+    assert!(!main.contains("_vec_new"));
+
+    let make = &generated[generated.find("function l $make").unwrap()..];
+    assert!(make.contains("_vec_new"));
+}
+
+#[test]
+fn inline_code() {
+    let raw = "
+    fn main() {
+        let x = 1
+        qbe! {
+            %x =w add %x, 41
+            call $println(w %x, w %unknown)
+        }
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("\t%tmp.2 =w add %tmp.2, 41\n\tcall $println(w %tmp.2, w %unknown)"));
+}
+
+#[test]
+fn only_public_functions_are_exported() {
+    let raw = "
+    pub fn api(): int {
+        return helper()
+    }
+
+    fn helper(): int {
+        return 1
+    }
+    ";
+    let generated = generate_raw(raw);
+    assert!(generated.contains("export function w $api() {"));
+    assert!(generated.contains("\nfunction w $helper() {"));
+}
+
+fn function_with_blocks(blocks: Vec<(&str, Vec<QbeStatement>)>) -> QbeFunction {
+    let mut func = QbeFunction::new(
+        "f",
+        vec![(QbeType::Word, QbeValue::Temporary("a".into()))],
+        Some(QbeType::Word),
+    );
+    func.blocks = blocks
+        .into_iter()
+        .map(|(label, instructions)| QbeBlock {
+            label: label.into(),
+            instructions,
+        })
+        .collect();
+    func
+}
+
+fn tmp(name: &str) -> QbeValue {
+    QbeValue::Temporary(name.into())
+}
+
+#[test]
+fn peephole_removes_unused_instructions() {
+    let mut func = function_with_blocks(vec![(
+        "start",
+        vec![
+            QbeStatement::Assign(tmp("a"), QbeType::Word, QbeInstr::Copy(tmp("a"))),
+            QbeStatement::Assign(
+                tmp("b"),
+                QbeType::Word,
+                QbeInstr::Add(tmp("a"), QbeValue::Const(1)),
+            ),
+            QbeStatement::Assign(tmp("c"), QbeType::Word, QbeInstr::Copy(tmp("b"))),
+            QbeStatement::Assign(tmp("d"), QbeType::Word, QbeInstr::Div(tmp("a"), tmp("a"))),
+            QbeStatement::Assign(tmp("e"), QbeType::Word, QbeInstr::Call("g".into(), vec![])),
+            QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("a")))),
+        ],
+    )]);
+    peephole::optimize(&mut func);
+    assert_eq!(
+        func.to_string(),
+        "function w $f(w %a) {\n@start\n\t%d =w div %a, %a\n\tcall $g()\n\tret %a\n}"
+    );
+}
+
+#[test]
+fn peephole_simplifies_jumps() {
+    let jmp = |label: &str| QbeStatement::Volatile(QbeInstr::Jmp(label.into()));
+    let mut func = function_with_blocks(vec![
+        (
+            "start",
+            vec![QbeStatement::Volatile(QbeInstr::Jnz(
+                tmp("a"),
+                "empty".into(),
+                "forward".into(),
+            ))],
+        ),
+        ("empty", vec![]),
+        ("forward", vec![jmp("end")]),
+        ("unreachable", vec![jmp("start")]),
+        (
+            "end",
+            vec![QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("a"))))],
+        ),
+    ]);
+    peephole::optimize(&mut func);
+    // Both branches end up at the same block, which directly follows
+    assert_eq!(
+        func.to_string(),
+        "function w $f(w %a) {\n@start\n\n@end\n\tret %a\n}"
+    );
+}
+
+#[test]
+fn temporaries_are_reused_once_dead() {
+    let assign = |name, instr| QbeStatement::Assign(tmp(name), QbeType::Word, instr);
+    let mut func = function_with_blocks(vec![(
+        "start",
+        vec![
+            assign("b", QbeInstr::Add(tmp("a"), QbeValue::Const(1))),
+            assign("c", QbeInstr::Mul(tmp("b"), tmp("b"))),
+            assign("d", QbeInstr::Sub(tmp("c"), tmp("a"))),
+            assign("e", QbeInstr::Copy(tmp("d"))),
+            QbeStatement::Assign(tmp("f"), QbeType::Long, QbeInstr::Extsw(tmp("e"))),
+            QbeStatement::Volatile(QbeInstr::Call("g".into(), vec![(QbeType::Long, tmp("f"))])),
+            QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("e")))),
+        ],
+    )]);
+    assert!(temporaries::reuse_temporaries(&mut func));
+    // Arguments keep their names, and so do temporaries of another type
+    assert_eq!(
+        func.to_string(),
+        "function w $f(w %a) {\n@start\n\t%b =w add %a, 1\n\t%b =w mul %b, %b\n\t%b =w sub %b, %a\n\t%f =l extsw %b\n\tcall $g(l %f)\n\tret %b\n}"
+    );
+}
+
+#[test]
+fn temporaries_live_across_loops_are_kept() {
+    let assign = |name, instr| QbeStatement::Assign(tmp(name), QbeType::Word, instr);
+    let mut func = function_with_blocks(vec![
+        (
+            "start",
+            vec![assign("step", QbeInstr::Add(tmp("a"), QbeValue::Const(1)))],
+        ),
+        (
+            "loop",
+            vec![
+                assign(
+                    "i",
+                    QbeInstr::Phi(vec![
+                        ("start".into(), QbeValue::Const(0)),
+                        ("loop".into(), tmp("next")),
+                    ]),
+                ),
+                assign("next", QbeInstr::Add(tmp("i"), tmp("step"))),
+                assign(
+                    "done",
+                    QbeInstr::Cmp(QbeType::Word, QbeCmp::Sge, tmp("next"), tmp("a")),
+                ),
+                QbeStatement::Volatile(QbeInstr::Jnz(tmp("done"), "end".into(), "loop".into())),
+            ],
+        ),
+        (
+            "end",
+            vec![
+                assign("result", QbeInstr::Mul(tmp("next"), QbeValue::Const(2))),
+                QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("result")))),
+            ],
+        ),
+    ]);
+    assert!(temporaries::reuse_temporaries(&mut func));
+    // `step` is read by every iteration, so `done` can not reuse it
+    let generated = func.to_string();
+    assert!(generated.contains("\t%done =w csgew %next, %a"));
+    assert!(generated.contains("\t%step =w mul %next, 2\n\tret %step"));
+}
+
+#[test]
+fn ir_prints_instructions() {
+    let cases = [
+        (
+            QbeInstr::Cmp(QbeType::Long, QbeCmp::Ult, tmp("a"), tmp("b")),
+            "cultl %a, %b",
+        ),
+        (
+            QbeInstr::Cmp(QbeType::Double, QbeCmp::Uo, tmp("a"), tmp("b")),
+            "cuod %a, %b",
+        ),
+        (QbeInstr::Load(QbeType::Byte, tmp("p")), "loadub %p"),
+        (
+            QbeInstr::LoadSigned(QbeType::Halfword, tmp("p")),
+            "loadsh %p",
+        ),
+        (QbeInstr::LoadSigned(QbeType::Word, tmp("p")), "loadsw %p"),
+        (QbeInstr::Load(QbeType::Long, tmp("p")), "loadl %p"),
+        (QbeInstr::Shl(tmp("a"), QbeValue::Const(2)), "shl %a, 2"),
+        (QbeInstr::Sltof(tmp("a")), "sltof %a"),
+        (
+            QbeInstr::Phi(vec![
+                ("then".into(), tmp("a")),
+                ("else".into(), QbeValue::Const(0)),
+            ]),
+            "phi @then %a, @else 0",
+        ),
+        (
+            QbeInstr::Blit(tmp("src"), tmp("dest"), 16),
+            "blit %src, %dest, 16",
+        ),
+        (QbeInstr::Hlt, "hlt"),
+    ];
+    for (instr, expected) in cases.iter() {
+        assert_eq!(instr.to_string(), *expected);
+    }
+
+    let data = QbeDataDef::new("buf", vec![(QbeType::Byte, QbeDataItem::Zero(8))])
+        .export()
+        .align(8);
+    assert_eq!(data.to_string(), "export data $buf = align 8 { b z 8 }");
+}
+
+#[test]
+fn ir_validates_functions() {
+    let ret = || QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("a"))));
+    let jmp = |label: &str| QbeStatement::Volatile(QbeInstr::Jmp(label.into()));
+    let error = |blocks| function_with_blocks(blocks).validate().unwrap_err();
+
+    assert!(function_with_blocks(vec![("start", vec![ret()])])
+        .validate()
+        .is_ok());
+    assert_eq!(error(vec![]), "Function has no blocks");
+    assert_eq!(
+        error(vec![("start", vec![jmp("end")]), ("start", vec![ret()])]),
+        "Label '@start' is defined twice"
+    );
+    assert_eq!(
+        error(vec![("start", vec![jmp("end")])]),
+        "Jump to undefined label '@end'"
+    );
+    assert_eq!(
+        error(vec![("start", vec![]), ("loop", vec![jmp("start")])]),
+        "Jump to the start block '@start'"
+    );
+    assert_eq!(
+        error(vec![("start", vec![ret(), ret()])]),
+        "Jump in block '@start' is followed by other instructions"
+    );
+    assert_eq!(
+        error(vec![(
+            "start",
+            vec![
+                QbeStatement::Assign(tmp("b"), QbeType::Word, QbeInstr::Copy(tmp("a"))),
+                QbeStatement::Assign(
+                    tmp("c"),
+                    QbeType::Word,
+                    QbeInstr::Phi(vec![("start".into(), tmp("a"))])
+                ),
+                ret(),
+            ]
+        )]),
+        "Phi in block '@start' follows other instructions"
+    );
+    assert_eq!(
+        error(vec![(
+            "start",
+            vec![QbeStatement::Volatile(QbeInstr::Ret(Some(tmp("b"))))]
+        )]),
+        "Temporary '%b' is never defined"
+    );
+    assert_eq!(
+        error(vec![(
+            "start",
+            vec![QbeStatement::Volatile(QbeInstr::Call("g".into(), vec![]))]
+        )]),
+        "Last block does not end with a jump"
+    );
+}
+
+#[test]
+fn dense_matches_are_lowered_to_a_branch_tree() {
+    let raw = "
+    fn days(m: int): int {
+        match m {
+            1 => return 31
+            2 => return 28
+            3 => return 31
+            4 => return 30
+            5 => return 31
+            else => return 0
+        }
+    }
+
+    fn main() {}
+    ";
+    let output = generate_raw(raw);
+    // The cases are shifted to start at zero, and values outside of them go to the else arm
+    assert!(output.contains("%tmp.2 =w sub %tmp.1, 1\n\t%tmp.3 =w cugtw %tmp.2, 4\n\tjnz %tmp.3, @block.6, @start.case.1"));
+    assert!(
+        output.contains("%tmp.4 =w cultw %tmp.2, 2\n\tjnz %tmp.4, @start.case.2, @start.case.3")
+    );
+}
+
+#[test]
+fn sparse_matches_are_lowered_to_comparisons() {
+    let raw = "
+    fn sparse(m: int): int {
+        match m {
+            1 => return 1
+            100 => return 2
+            else => return 0
+        }
+    }
+
+    fn main() {}
+    ";
+    let output = generate_raw(raw);
+    assert!(output.contains(
+        "@start\n\t%tmp.2 =w ceqw %tmp.1, 1\n\tjnz %tmp.2, @block.1, @start.case.1\n@start.case.1\n\t%tmp.3 =w ceqw %tmp.1, 100\n\tjnz %tmp.3, @block.2, @block.3"
+    ));
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    // Structs are not supported by the IR, so the function is generated from the AST
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn touch(v: bool): bool {
+        return v
+    }
+
+    fn any(p: Point): bool {
+        return p.x > 0 || touch(false)
+    }
+
+    fn main() {}
+    ";
+    let output = generate_raw(raw);
+    assert!(output.contains("\tjnz %tmp.6, @logic.1.end, @logic.1.rhs\n@logic.1.rhs\n"));
+    assert!(output
+        .contains("call $touch(w %tmp.7)\n\t%tmp.6 =w copy %tmp.8\n@logic.1.end\n\tret %tmp.6"));
+}
+
+#[test]
+fn structs_passed_by_reference() {
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn copied(p: Point) {}
+
+    fn shared(ref p: Point) {}
+
+    fn main() {
+        let p = new Point { x: 1 }
+        copied(p)
+        shared(p)
+    }
+    ";
+    let output = generate_raw(raw);
+    // Copies are passed as aggregates, references as pointers
+    assert!(output.contains("function $copied(:struct.1 %tmp.1)"));
+    assert!(output.contains("function $shared(l %tmp.1)"));
+    assert!(output.contains("call $copied(:struct.1 %tmp."));
+    assert!(output.contains("call $shared(l %tmp."));
+}
+
+#[test]
+fn referenced_variables_are_kept_in_stack_slots() {
+    let raw = "
+    fn set(out: &int, value: int) {
+        *out = value
+    }
+
+    fn main() {
+        let n = 1
+        set(&n, 2)
+        let m = n
+    }
+    ";
+    let output = generate_raw(raw);
+    assert!(
+        output.contains("function $set(l %tmp.1, w %tmp.2) {\n@start\n\tstorew %tmp.2, %tmp.1\n")
+    );
+    // The variable is stored in a slot, whose address is passed to the function
+    assert!(output.contains("%tmp.2 =l alloc8 8"));
+    assert!(output.contains("storew %tmp.1, %tmp.2"));
+    assert!(output.contains("call $set(l %tmp.2, w %tmp."));
+    assert!(output.contains("=w loadw %tmp.2"));
+}
+
+#[test]
+fn unsafe_blocks_access_raw_memory() {
+    let raw = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(16)
+        unsafe {
+            store<int>(p + 8, 42)
+            let n = load<int>(p + 8)
+        }
+    }
+    ";
+    let output = generate_raw(raw);
+    // Offsets are extended to longs before they are added to the pointer
+    assert!(output.contains("=l extsw"));
+    assert!(output.contains("=l add %tmp."));
+    assert!(output.contains("storew %tmp."));
+    assert!(output.contains("=w loadw %tmp."));
+}
@@ -0,0 +1,153 @@
+/**
+ * Copyright 2020 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::ast::*;
+use crate::generator::js::ModuleFormat;
+use std::collections::HashSet;
+
+/// Declaration of the JavaScript representation of results, see `_result_ok` of the builtins
+const RESULT_DECLARATION: &str =
+    "type Result<T> = { ok: true; value: T } | { ok: false; error: string };\n";
+
+/// Declaration of the JavaScript representation of channels, see `_chan_new` of the builtins
+const CHANNEL_DECLARATION: &str = "type Channel<T> = { values: T[]; closed: boolean };\n";
+
+/// Generates TypeScript declarations of the public functions of a program, and interfaces
+/// of the structs they use. Has to run after the types of the program are inferred.
+///
+/// Declarations of ES and CommonJS modules are exported, declarations of IIFEs are global.
+pub fn generate_declarations(prog: &Module, format: ModuleFormat) -> String {
+    let export = match format {
+        ModuleFormat::Cjs | ModuleFormat::Esm => "export ",
+        ModuleFormat::Iife => "",
+    };
+
+    let functions: Vec<&Function> = prog.func.iter().filter(|f| f.public).collect();
+    let mut used = HashSet::new();
+    for func in &functions {
+        for ty in func.arguments.iter().filter_map(|arg| arg.ty.as_ref()) {
+            collect_structs(prog, ty, &mut used);
+        }
+        if let Some(ty) = &func.ret_type {
+            collect_structs(prog, ty, &mut used);
+        }
+    }
+    for def in prog.structs.iter().filter(|def| def.public) {
+        collect_structs(prog, &Type::Struct(def.name.clone()), &mut used);
+    }
+
+    let mut out = String::new();
+    for def in prog.structs.iter().filter(|def| used.contains(&def.name)) {
+        out += &format!("{}interface {} {{\n", export, def.name);
+        for field in &def.fields {
+            out += &format!("    {}: {};\n", field.name, optional_type(&field.ty));
+        }
+        for method in &def.methods {
+            out += &format!("    {};\n", signature(method));
+        }
+        out += "}\n";
+    }
+    for func in functions {
+        out += &format!("{}declare function {};\n", export, signature(func));
+    }
+
+    if out.contains("Result<") {
+        out = format!("{}{}", RESULT_DECLARATION, out);
+    }
+    if out.contains("Channel<") {
+        out = format!("{}{}", CHANNEL_DECLARATION, out);
+    }
+    out
+}
+
+/// Returns e.g. `add(x: number, y: number): number`
+fn signature(func: &Function) -> String {
+    let arguments = func
+        .arguments
+        .iter()
+        .map(|arg| format!("{}: {}", arg.name, optional_type(&arg.ty)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut ret = match &func.ret_type {
+        Some(ty) => typescript_type(ty),
+        None => "void".to_owned(),
+    };
+    if func.is_async {
+        ret = format!("Promise<{}>", ret);
+    }
+    format!("{}({}): {}", func.name, arguments, ret)
+}
+
+fn optional_type(ty: &Option<Type>) -> String {
+    ty.as_ref()
+        .map(typescript_type)
+        .unwrap_or_else(|| "any".to_owned())
+}
+
+/// Returns the TypeScript type of the JavaScript representation of a type
+fn typescript_type(ty: &Type) -> String {
+    match ty {
+        Type::Any => "any".to_owned(),
+        Type::Int | Type::Ptr => "number".to_owned(),
+        Type::Str => "string".to_owned(),
+        Type::Bool => "boolean".to_owned(),
+        Type::Array(inner, _) => format!("{}[]", typescript_type(inner)),
+        Type::Map(key, value) => {
+            format!("Map<{}, {}>", typescript_type(key), typescript_type(value))
+        }
+        Type::Struct(name) => name.clone(),
+        Type::Result(inner) => format!("Result<{}>", typescript_type(inner)),
+        Type::Ref(inner) => format!("{{ value: {} }}", typescript_type(inner)),
+        Type::Chan(inner) => format!("Channel<{}>", typescript_type(inner)),
+    }
+}
+
+/// Adds the names of the structs a type refers to, including the types of their fields
+fn collect_structs(prog: &Module, ty: &Type, used: &mut HashSet<String>) {
+    match ty {
+        Type::Struct(name) => {
+            if !used.insert(name.clone()) {
+                return;
+            }
+            if let Some(def) = prog.structs.iter().find(|def| &def.name == name) {
+                let methods = def.methods.iter().flat_map(|method| {
+                    method
+                        .arguments
+                        .iter()
+                        .filter_map(|arg| arg.ty.as_ref())
+                        .chain(&method.ret_type)
+                });
+                let types: Vec<&Type> = def
+                    .fields
+                    .iter()
+                    .filter_map(|field| field.ty.as_ref())
+                    .chain(methods)
+                    .collect();
+                for ty in types {
+                    collect_structs(prog, ty, used);
+                }
+            }
+        }
+        Type::Array(inner, _) | Type::Result(inner) | Type::Ref(inner) | Type::Chan(inner) => {
+            collect_structs(prog, inner, used)
+        }
+        Type::Map(key, value) => {
+            collect_structs(prog, key, used);
+            collect_structs(prog, value, used);
+        }
+        Type::Any | Type::Int | Type::Str | Type::Bool | Type::Ptr => {}
+    }
+}
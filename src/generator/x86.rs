@@ -57,10 +57,13 @@ impl X86Generator {
         let mut asm = Assembly::new();
         let Module {
             func,
+            externs: _,
             globals,
             structs: _,
             path: _,
             imports: _,
+            aliases: _,
+            selected: _,
         } = prog;
 
         asm.add(".intel_syntax noprefix");
@@ -71,7 +74,7 @@ impl X86Generator {
         }
         asm.add(".data");
         for g in globals {
-            asm.add(format!("_{0}: .word 0", g));
+            asm.add(format!("_{0}: .word 0", g.var.name));
         }
 
         asm
@@ -0,0 +1,188 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::{Function, Module};
+use crate::builder::resolve_import;
+use crate::optimizer;
+use crate::package::Packages;
+use serde_json::json;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(test)]
+mod tests;
+
+/// How a graph is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT, e.g. for `dot -Tsvg`
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!("Unknown graph format: {}, expected dot or json", s)),
+        }
+    }
+}
+
+/// Directed graph of the functions or modules of a program
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    /// (from, to) pairs of node ids
+    pub edges: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Node {
+    /// Unique name of the node, e.g. `lib/math.sb:add`
+    pub id: String,
+    pub label: String,
+    /// Path of the module a function belongs to. Nodes of modules do not belong to one
+    pub module: Option<String>,
+}
+
+/// Returns the graph of the functions and methods of the modules and the calls between them.
+/// A call refers to the function of the calling module if it has one, otherwise to the function
+/// of another module. Calls of functions that are not part of the modules, like builtins, are left out.
+pub fn call_graph(modules: &[&Module]) -> Graph {
+    let mut graph = Graph::default();
+    let mut callers: Vec<(String, &Module, &Function)> = Vec::new();
+    for module in modules {
+        for func in &module.func {
+            graph.nodes.push(Node {
+                id: format!("{}:{}", module.path, func.name),
+                label: func.name.clone(),
+                module: Some(module.path.clone()),
+            });
+            callers.push((format!("{}:{}", module.path, func.name), module, func));
+        }
+        for def in &module.structs {
+            for method in &def.methods {
+                let label = format!("{}.{}", def.name, method.name);
+                graph.nodes.push(Node {
+                    id: format!("{}:{}", module.path, label),
+                    label: label.clone(),
+                    module: Some(module.path.clone()),
+                });
+                callers.push((format!("{}:{}", module.path, label), module, method));
+            }
+        }
+    }
+
+    for (caller, module, func) in callers {
+        let mut calls = optimizer::function_calls(func);
+        calls.sort();
+        calls.dedup();
+        for call in calls {
+            let callee = if module.func.iter().any(|f| f.name == call) {
+                Some(module)
+            } else {
+                modules
+                    .iter()
+                    .find(|other| other.func.iter().any(|f| f.name == call))
+                    .copied()
+            };
+            if let Some(callee) = callee {
+                graph
+                    .edges
+                    .push((caller.clone(), format!("{}:{}", callee.path, call)));
+            }
+        }
+    }
+    graph
+}
+
+/// Returns the graph of the modules and their imports
+pub fn module_graph(modules: &[&Module], packages: &Packages) -> Graph {
+    let mut graph = Graph::default();
+    for module in modules {
+        graph.nodes.push(Node {
+            id: module.path.clone(),
+            label: module.path.clone(),
+            module: None,
+        });
+        let mut imports: Vec<&String> = module.imports.keys().collect();
+        imports.sort();
+        for import in imports {
+            let path = resolve_import(Path::new(&module.path), import, packages);
+            graph
+                .edges
+                .push((module.path.clone(), path.display().to_string()));
+        }
+    }
+    graph
+}
+
+impl Graph {
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Renders the graph in DOT. Functions of the same module are grouped in a cluster
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n");
+        let mut modules: Vec<Option<&String>> = Vec::new();
+        for node in &self.nodes {
+            if !modules.contains(&node.module.as_ref()) {
+                modules.push(node.module.as_ref());
+            }
+        }
+        for (i, module) in modules.into_iter().enumerate() {
+            let nodes = self.nodes.iter().filter(|n| n.module.as_ref() == module);
+            let Some(module) = module else {
+                for node in nodes {
+                    dot += &format!("    {:?};\n", node.id);
+                }
+                continue;
+            };
+            dot += &format!("    subgraph cluster_{} {{\n", i);
+            dot += &format!("        label = {:?};\n", module);
+            for node in nodes {
+                dot += &format!("        {:?} [label = {:?}];\n", node.id, node.label);
+            }
+            dot += "    }\n";
+        }
+        for (from, to) in &self.edges {
+            dot += &format!("    {:?} -> {:?};\n", from, to);
+        }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|n| json!({ "id": n.id, "label": n.label, "module": n.module }))
+            .collect();
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(from, to)| json!({ "from": from, "to": to }))
+            .collect();
+        json!({ "nodes": nodes, "edges": edges }).to_string()
+    }
+}
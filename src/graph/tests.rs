@@ -0,0 +1,99 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::{call_graph, module_graph, GraphFormat};
+use crate::ast::Module;
+use crate::lexer::tokenize;
+use crate::package::Packages;
+use crate::parser::parse;
+
+fn parse_module(raw: &str, path: &str) -> Module {
+    let tokens = tokenize(raw).unwrap();
+    parse(tokens, Some(raw.to_string()), path.into()).unwrap()
+}
+
+fn edge(from: &str, to: &str) -> (String, String) {
+    (from.to_string(), to.to_string())
+}
+
+#[test]
+fn test_call_graph() {
+    let main = parse_module(
+        "
+        fn main() {
+            greet()
+            add(1, 2)
+            println(\"done\")
+        }
+
+        fn greet() {
+            println(\"Hello\")
+        }
+        ",
+        "src/main.sb",
+    );
+    let math = parse_module(
+        "
+        pub fn add(x: int, y: int): int {
+            return x + y
+        }
+        ",
+        "src/math.sb",
+    );
+
+    let graph = call_graph(&[&main, &math]);
+    let ids: Vec<&str> = graph.nodes.iter().map(|n| n.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        ["src/main.sb:main", "src/main.sb:greet", "src/math.sb:add"]
+    );
+    // The call of println refers to a builtin and is left out
+    assert_eq!(
+        graph.edges,
+        [
+            edge("src/main.sb:main", "src/math.sb:add"),
+            edge("src/main.sb:main", "src/main.sb:greet"),
+        ]
+    );
+}
+
+#[test]
+fn test_module_graph() {
+    let main = parse_module("import \"math\"\n\nfn main() {}", "src/main.sb");
+    let math = parse_module("fn add() {}", "src/math.sb");
+
+    let graph = module_graph(&[&main, &math], &Packages::new());
+    let ids: Vec<&str> = graph.nodes.iter().map(|n| n.id.as_str()).collect();
+    assert_eq!(ids, ["src/main.sb", "src/math.sb"]);
+    assert_eq!(graph.edges, [edge("src/main.sb", "src/math.sb")]);
+}
+
+#[test]
+fn test_render_graph() {
+    let main = parse_module("fn main() {\n    helper()\n}\n\nfn helper() {}", "main.sb");
+    let graph = call_graph(&[&main]);
+
+    let dot = graph.render(GraphFormat::Dot);
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.contains("label = \"main.sb\";"));
+    assert!(dot.contains("\"main.sb:helper\" [label = \"helper\"];"));
+    assert!(dot.contains("\"main.sb:main\" -> \"main.sb:helper\";"));
+
+    let json: serde_json::Value = serde_json::from_str(&graph.render(GraphFormat::Json)).unwrap();
+    assert_eq!(json["nodes"][1]["label"], "helper");
+    assert_eq!(json["nodes"][1]["module"], "main.sb");
+    assert_eq!(json["edges"][0]["from"], "main.sb:main");
+    assert_eq!(json["edges"][0]["to"], "main.sb:helper");
+}
@@ -0,0 +1,169 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::ast::StructDef;
+use crate::generator::Arch;
+use std::collections::HashMap;
+
+#[cfg(test)]
+mod tests;
+
+/// Size of pointers of the 64 bit architectures the native backends compile to.
+/// Since every architecture has the same pointer size, `sizeof` does not depend on `--arch`
+pub const POINTER_SIZE: u64 = 8;
+
+/// Returns the size of pointers on the architecture
+pub fn pointer_size(arch: Arch) -> u64 {
+    match arch {
+        Arch::Amd64 | Arch::Arm64 | Arch::Riscv64 => POINTER_SIZE,
+    }
+}
+
+/// Size and alignment of values of a type in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u64,
+    pub align: u64,
+}
+
+impl Layout {
+    const fn new(size: u64, align: u64) -> Self {
+        Layout { size, align }
+    }
+}
+
+/// Layout of a structure and the offsets of its fields in declaration order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructLayout {
+    pub layout: Layout,
+    pub fields: Vec<(String, u64)>,
+}
+
+impl StructLayout {
+    /// Returns the offset of a field from the start of the structure
+    pub fn offset(&self, field: &str) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// Computes the layouts of the types of a module, the way a C compiler lays
+/// out the equivalent types on the target architecture:
+///
+/// - `int` is a 32 bit integer and `bool` is a single byte
+/// - Strings, arrays, maps and results are pointers to memory of the runtime
+/// - Structures contain their fields by value, in declaration order. Every field
+///   is aligned to its own alignment and the size is padded to the largest one
+pub struct Layouts {
+    /// Structure -> (field, type) mappings in declaration order
+    structs: HashMap<String, Vec<(String, Option<Type>)>>,
+    pointer_size: u64,
+}
+
+impl Layouts {
+    /// Computes layouts for the architecture of the host
+    pub fn new(structs: &[StructDef]) -> Self {
+        Self::for_arch(structs, Arch::host())
+    }
+
+    pub fn for_arch(structs: &[StructDef], arch: Arch) -> Self {
+        let structs = structs
+            .iter()
+            .map(|def| {
+                let fields = def
+                    .fields
+                    .iter()
+                    .map(|field| (field.name.clone(), field.ty.clone()))
+                    .collect();
+                (def.name.clone(), fields)
+            })
+            .collect();
+        Layouts {
+            structs,
+            pointer_size: pointer_size(arch),
+        }
+    }
+
+    /// Returns the layout of values of the type
+    pub fn of(&self, ty: &Type) -> Result<Layout, String> {
+        self.layout(ty, &mut Vec::new())
+    }
+
+    /// Returns the layout of a structure, including the offsets of its fields
+    pub fn of_struct(&self, name: &str) -> Result<StructLayout, String> {
+        self.struct_layout(name, &mut Vec::new())
+    }
+
+    /// `visiting` holds the structures whose layout is being computed, which
+    /// can not contain themselves by value
+    fn layout(&self, ty: &Type, visiting: &mut Vec<String>) -> Result<Layout, String> {
+        match ty {
+            Type::Int => Ok(Layout::new(4, 4)),
+            Type::Bool => Ok(Layout::new(1, 1)),
+            Type::Str
+            | Type::Array(..)
+            | Type::Map(..)
+            | Type::Chan(_)
+            | Type::Result(_)
+            | Type::Ref(_)
+            | Type::Ptr => Ok(Layout::new(self.pointer_size, self.pointer_size)),
+            Type::Struct(name) => Ok(self.struct_layout(name, visiting)?.layout),
+            Type::Any => Err("Values of type 'any' have no known size".into()),
+        }
+    }
+
+    fn struct_layout(
+        &self,
+        name: &str,
+        visiting: &mut Vec<String>,
+    ) -> Result<StructLayout, String> {
+        let fields = self
+            .structs
+            .get(name)
+            .ok_or_else(|| format!("Use of undeclared struct '{}'", name))?;
+        if visiting.iter().any(|visited| visited == name) {
+            return Err(format!("Struct '{}' contains itself", name));
+        }
+        visiting.push(name.to_owned());
+
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut size = 0;
+        let mut align = 1;
+        for (field, ty) in fields {
+            let ty = ty
+                .as_ref()
+                .ok_or_else(|| format!("Field '{}' of struct '{}' has no type", field, name))?;
+            let layout = self.layout(ty, visiting)?;
+            size = align_to(size, layout.align);
+            offsets.push((field.clone(), size));
+            size += layout.size;
+            align = align.max(layout.align);
+        }
+
+        visiting.pop();
+        Ok(StructLayout {
+            layout: Layout::new(align_to(size, align), align),
+            fields: offsets,
+        })
+    }
+}
+
+/// Rounds the offset up to the next multiple of the alignment
+pub fn align_to(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
@@ -0,0 +1,129 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::generator::Arch;
+use crate::layout::{Layout, Layouts};
+use crate::lexer::tokenize;
+use crate::parser::parse;
+
+// The expected layouts are those of the equivalent C types, as reported by
+// `sizeof`, `_Alignof` and `offsetof` of GCC and Clang on x86_64 and aarch64.
+
+fn layouts_of(raw: &str) -> Layouts {
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    Layouts::new(&module.structs)
+}
+
+#[test]
+fn test_primitive_layouts() {
+    let layouts = layouts_of("");
+    // int
+    assert_eq!(layouts.of(&Type::Int), Ok(Layout { size: 4, align: 4 }));
+    // bool
+    assert_eq!(layouts.of(&Type::Bool), Ok(Layout { size: 1, align: 1 }));
+    // char *
+    assert_eq!(layouts.of(&Type::Str), Ok(Layout { size: 8, align: 8 }));
+    // struct vec *
+    let array = Type::Array(Box::new(Type::Int), Some(4));
+    assert_eq!(layouts.of(&array), Ok(Layout { size: 8, align: 8 }));
+    assert!(layouts.of(&Type::Any).is_err());
+}
+
+#[test]
+fn test_pointer_layouts_of_architectures() {
+    for arch in ["amd64", "arm64", "riscv64"] {
+        let layouts = Layouts::for_arch(&[], arch.parse::<Arch>().unwrap());
+        assert_eq!(layouts.of(&Type::Str), Ok(Layout { size: 8, align: 8 }));
+    }
+    assert_eq!(
+        "x86".parse::<Arch>(),
+        Err("Unknown architecture: x86, expected amd64, arm64 or riscv64".into())
+    );
+}
+
+#[test]
+fn test_struct_fields_are_aligned() {
+    // struct Mixed { bool flag; int count; char *name; bool done; };
+    let layouts = layouts_of(
+        "
+    struct Mixed {
+        flag: bool
+        count: int
+        name: string
+        done: bool
+    }
+    ",
+    );
+    let mixed = layouts.of_struct("Mixed").unwrap();
+    assert_eq!(mixed.layout, Layout { size: 24, align: 8 });
+    assert_eq!(mixed.offset("flag"), Some(0));
+    assert_eq!(mixed.offset("count"), Some(4));
+    assert_eq!(mixed.offset("name"), Some(8));
+    assert_eq!(mixed.offset("done"), Some(16));
+}
+
+#[test]
+fn test_nested_structs_are_stored_by_value() {
+    // struct Point { int x; int y; };
+    // struct Line { struct Point start; bool visible; struct Point end; };
+    let layouts = layouts_of(
+        "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    struct Line {
+        start: Point
+        visible: bool
+        end: Point
+    }
+    ",
+    );
+    let point = layouts.of_struct("Point").unwrap();
+    assert_eq!(point.layout, Layout { size: 8, align: 4 });
+    assert_eq!(point.fields, vec![("x".into(), 0), ("y".into(), 4)]);
+
+    let line = layouts.of_struct("Line").unwrap();
+    assert_eq!(line.layout, Layout { size: 20, align: 4 });
+    assert_eq!(line.offset("visible"), Some(8));
+    assert_eq!(line.offset("end"), Some(12));
+}
+
+#[test]
+fn test_empty_struct() {
+    // struct Empty {}; (GNU C)
+    let layouts = layouts_of("struct Empty {}");
+    let empty = layouts.of(&Type::Struct("Empty".into()));
+    assert_eq!(empty, Ok(Layout { size: 0, align: 1 }));
+}
+
+#[test]
+fn test_struct_containing_itself_has_no_layout() {
+    let layouts = layouts_of(
+        "
+    struct Node {
+        value: int
+        next: Node
+    }
+    ",
+    );
+    assert_eq!(
+        layouts.of_struct("Node"),
+        Err("Struct 'Node' contains itself".into())
+    );
+}
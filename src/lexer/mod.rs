@@ -19,11 +19,15 @@ use self::TokenKind::*;
 use cursor::Cursor;
 use lazy_static::lazy_static;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 mod tests;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Names of the targets that code can be inlined for, e.g. `js! { ... }`
+pub const INLINE_TARGETS: [&str; 2] = ["js", "qbe"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub struct Token {
     pub kind: TokenKind,
     pub len: usize,
@@ -42,7 +46,7 @@ impl Token {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub struct Position {
     pub line: usize,
     pub offset: usize,
@@ -50,7 +54,7 @@ pub struct Position {
 }
 
 /// Enum representing common lexeme types.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum TokenKind {
     /// Any whitespace characters sequence.
     Whitespace,
@@ -72,12 +76,20 @@ pub enum TokenKind {
     Percent,
     /// ":"
     Colon,
+    /// "::"
+    DoubleColon,
     /// ";"
     SemiColon,
     /// "."
     Dot,
     /// "!"
     Exclamation,
+    /// "&"
+    Ampersand,
+    /// "?"
+    QuestionMark,
+    /// "@"
+    At,
     /// ","
     Comma,
     /// "="
@@ -124,19 +136,22 @@ pub enum TokenKind {
     Tab,
     /// "\n"
     CarriageReturn,
+    /// `js! { ... }`, code of a backend: (target, code between the braces)
+    Inline(String, String),
     /// Unknown token, not expected by the lexer, e.g. "№"
     Unknown,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum Value {
     Int,
     Str(String),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub enum Keyword {
     Let,
+    Const,
     If,
     Else,
     Return,
@@ -145,17 +160,28 @@ pub enum Keyword {
     In,
     Break,
     Continue,
+    Defer,
+    Spawn,
+    Static,
+    Async,
+    Await,
     Function,
     Boolean,
     Struct,
     New,
     Match,
     Import,
+    Extern,
+    Pub,
+    Ref,
+    Unsafe,
+    Macro,
     Selff, // "self"
     Unknown,
 }
 
 /// Creates an iterator that produces tokens from the input string.
+/// Malformed input is reported as an error, the lexer never panics.
 pub fn tokenize(mut input: &str) -> Result<Vec<Token>, String> {
     let mut pos = Position {
         raw: usize::MAX,
@@ -175,7 +201,6 @@ pub fn tokenize(mut input: &str) -> Result<Vec<Token>, String> {
 
 /// Parses the first token from the provided input string.
 pub fn first_token(input: &str, pos: &mut Position) -> Result<Token, String> {
-    debug_assert!(!input.is_empty());
     Cursor::new(input, pos).advance_token()
 }
 
@@ -231,12 +256,16 @@ impl Cursor<'_> {
         let original_chars = self.chars();
         // FIXME: Identical value, since it will be used twice and is not clonable later
         let original_chars2 = self.chars();
-        let first_char = self.bump().unwrap();
+        let first_char = self
+            .bump()
+            .ok_or_else(|| self.make_error_msg("Unexpected end of file".into()))?;
         let token_kind = match first_char {
             c if is_whitespace(c) => self.whitespace(),
             '0'..='9' => self.number(),
             '"' | '\'' => self.string(first_char)?,
             '.' => Dot,
+            '@' => At,
+            '?' => QuestionMark,
             '+' => match self.first() {
                 '=' => {
                     self.bump();
@@ -281,7 +310,13 @@ impl Cursor<'_> {
                 }
                 _ => Assign,
             },
-            ':' => Colon,
+            ':' => match self.first() {
+                ':' => {
+                    self.bump();
+                    DoubleColon
+                }
+                _ => Colon,
+            },
             ';' => SemiColon,
             ',' => Comma,
             '<' => match self.first() {
@@ -303,7 +338,7 @@ impl Cursor<'_> {
                     self.bump();
                     And
                 }
-                _ => Unknown,
+                _ => Ampersand,
             },
             '|' => match self.first() {
                 '|' => {
@@ -330,7 +365,14 @@ impl Cursor<'_> {
                 if kind == Keyword::Unknown {
                     let mut ch: String = original_chars.collect();
                     ch.truncate(self.len_consumed());
-                    TokenKind::Identifier(ch)
+                    if INLINE_TARGETS.contains(&ch.as_str())
+                        && self.first() == '!'
+                        && self.second() != '='
+                    {
+                        self.inline_code(ch)?
+                    } else {
+                        TokenKind::Identifier(ch)
+                    }
                 } else {
                     TokenKind::Keyword(kind)
                 }
@@ -410,26 +452,34 @@ impl Cursor<'_> {
             c if c == "fn" => Keyword::Function,
             c if c == "true" || c == "false" => Keyword::Boolean,
             c if c == "let" => Keyword::Let,
+            c if c == "const" => Keyword::Const,
             c if c == "return" => Keyword::Return,
             c if c == "while" => Keyword::While,
             c if c == "for" => Keyword::For,
             c if c == "in" => Keyword::In,
             c if c == "break" => Keyword::Break,
             c if c == "continue" => Keyword::Continue,
+            c if c == "defer" => Keyword::Defer,
+            c if c == "spawn" => Keyword::Spawn,
+            c if c == "static" => Keyword::Static,
+            c if c == "async" => Keyword::Async,
+            c if c == "await" => Keyword::Await,
             c if c == "struct" => Keyword::Struct,
             c if c == "new" => Keyword::New,
             c if c == "match" => Keyword::Match,
             c if c == "import" => Keyword::Import,
+            c if c == "extern" => Keyword::Extern,
+            c if c == "pub" => Keyword::Pub,
+            c if c == "ref" => Keyword::Ref,
+            c if c == "unsafe" => Keyword::Unsafe,
+            c if c == "macro" => Keyword::Macro,
             c if c == "self" => Keyword::Selff,
             _ => Keyword::Unknown,
         }
     }
 
     fn comment(&mut self) -> TokenKind {
-        // FIXME: Might lead to a bug, if End of file is encountered
-        while self.first() != '\n' {
-            self.bump();
-        }
+        self.eat_while(|c| c != '\n');
 
         TokenKind::Comment
     }
@@ -525,6 +575,7 @@ impl Cursor<'_> {
         loop {
             match self.first() {
                 '\n' => return Err(self.make_error_msg("String does not end on same line".into())),
+                _ if self.is_eof() => return Err(self.make_error_msg("Unterminated string".into())),
                 '\\' => {
                     self.bump();
                     buf.push(self.eat_escape()?)
@@ -543,6 +594,33 @@ impl Cursor<'_> {
         Ok(buf)
     }
 
+    /// Eats the block of an inline code token, after the name of the target.
+    /// The code ends at the brace that closes the block, so braces inside of it have to be balanced.
+    fn inline_code(&mut self, target: String) -> Result<TokenKind, String> {
+        self.bump();
+        self.eat_while(is_whitespace);
+        if self.first() != '{' {
+            return Err(self.make_error_msg(format!("Expected '{{' after '{}!'", target)));
+        }
+        self.bump();
+
+        let mut code = String::new();
+        let mut depth = 0;
+        loop {
+            let c = self
+                .bump()
+                .ok_or_else(|| self.make_error_msg(format!("Unterminated '{}!' block", target)))?;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            code.push(c);
+        }
+        Ok(TokenKind::Inline(target, code))
+    }
+
     fn make_error_msg(&self, msg: String) -> String {
         let pos = self.pos();
         format!("{}:{}: {}", pos.line, pos.offset, msg)
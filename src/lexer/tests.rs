@@ -342,3 +342,63 @@ fn fib() {}
         }
     );
 }
+
+#[test]
+fn test_inline_code() {
+    let tokens = tokenize("js! { if (x) { y() } }").unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(
+        tokens[0].kind,
+        TokenKind::Inline("js".into(), " if (x) { y() } ".into())
+    );
+
+    // Only `!` directly after the name of a target starts a block
+    let kinds: Vec<TokenKind> = tokenize("js != qbe")
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .filter(|kind| *kind != TokenKind::Whitespace)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Identifier("js".into()),
+            TokenKind::NotEqual,
+            TokenKind::Identifier("qbe".into())
+        ]
+    );
+
+    assert!(tokenize("qbe! { ret").is_err());
+}
+
+#[test]
+fn test_qualified_names() {
+    let kinds: Vec<TokenKind> = tokenize("math::sqrt(x: int)")
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            TokenKind::Identifier("math".into()),
+            TokenKind::DoubleColon,
+            TokenKind::Identifier("sqrt".into()),
+            TokenKind::BraceOpen,
+            TokenKind::Identifier("x".into()),
+            TokenKind::Colon,
+            TokenKind::Whitespace,
+            TokenKind::Identifier("int".into()),
+            TokenKind::BraceClose,
+        ]
+    );
+}
+
+#[test]
+fn test_unterminated_input() {
+    assert!(tokenize("let x = \"abc").is_err());
+    assert!(tokenize("let x = 'abc\\").is_err());
+
+    let tokens = tokenize("let x = 1 // comment").unwrap();
+    assert_eq!(tokens.last().unwrap().kind, TokenKind::Comment);
+}
@@ -0,0 +1,108 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+extern crate lazy_static;
+extern crate regex;
+extern crate rust_embed;
+
+use rust_embed::RustEmbed;
+use std::path::PathBuf;
+
+pub mod ast;
+pub mod bindgen;
+pub mod builder;
+pub mod cache;
+pub mod diagnostics;
+pub mod driver;
+pub mod formatter;
+pub mod generator;
+pub mod graph;
+pub mod layout;
+pub mod lexer;
+pub mod manifest;
+pub mod mir;
+pub mod optimizer;
+pub mod package;
+pub mod parser;
+pub mod semantic;
+#[cfg(test)]
+mod tests;
+pub mod timings;
+pub mod util;
+pub mod warnings;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use builder::BuildOptions;
+pub use diagnostics::{Diagnostic, Severity};
+pub use generator::Target;
+
+#[derive(RustEmbed)]
+#[folder = "lib/"]
+pub struct Lib;
+
+#[derive(RustEmbed)]
+#[folder = "builtin/"]
+pub struct Builtins;
+
+/// Name the source is compiled as. Imports are resolved relative to the working directory.
+pub(crate) const SOURCE_NAME: &str = "main.sb";
+
+/// Compiles a program with the default options.
+/// On failure, all diagnostics are returned, with the error that stopped the build last.
+///
+/// ```
+/// use antimony::Target;
+///
+/// let js = antimony::compile("fn main() { println(\"Hello\") }", Target::JS).unwrap();
+/// assert!(js.contains("function main()"));
+/// ```
+pub fn compile(source: &str, target: Target) -> Result<String, Vec<Diagnostic>> {
+    compile_with_options(source, target, BuildOptions::default())
+}
+
+/// Compiles a program with the given options. Warnings are discarded if the build succeeds.
+pub fn compile_with_options(
+    source: &str,
+    target: Target,
+    options: BuildOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    let mut b =
+        builder::Builder::new(PathBuf::from(SOURCE_NAME), options).with_source(source.to_owned());
+    let mut buffer = Box::new(Vec::new());
+    match b
+        .build(&target)
+        .and_then(|_| b.generate(&target, &mut buffer))
+    {
+        Ok(()) => Ok(String::from_utf8_lossy(&buffer).into_owned()),
+        Err(message) => {
+            let mut diagnostics = b.diagnostics().to_vec();
+            diagnostics.push(Diagnostic::error(message));
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Returns the errors and warnings of a program without generating code
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let mut b = builder::Builder::new(PathBuf::from(SOURCE_NAME), BuildOptions::default())
+        .with_source(source.to_owned());
+    let result = b.build(&Target::JS);
+    let mut diagnostics = b.diagnostics().to_vec();
+    if let Err(message) = result {
+        diagnostics.push(Diagnostic::error(message));
+    }
+    diagnostics
+}
@@ -1,5 +1,3 @@
-extern crate lazy_static;
-extern crate regex;
 /**
  * Copyright 2020 Garrit Franke
  *
@@ -15,44 +13,142 @@ extern crate regex;
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-extern crate rust_embed;
+extern crate antimony;
 extern crate structopt;
 
-use generator::Target;
+use antimony::builder::BuildOptions;
+use antimony::diagnostics::MessageFormat;
+use antimony::driver::LinkOptions;
+use antimony::generator::js::{JsRuntime, ModuleFormat};
+use antimony::generator::{Arch, Target};
+use antimony::graph::GraphFormat;
+use antimony::manifest::{self, Project};
+use antimony::package::{self, Packages};
+use antimony::timings::TimingsFormat;
+use antimony::warnings::WarningOptions;
+use std::env;
+use std::fs;
 use std::path::PathBuf;
 use std::process;
 use structopt::StructOpt;
 
-mod ast;
-mod builder;
 mod command;
-mod generator;
-mod lexer;
-mod parser;
-#[cfg(test)]
-mod tests;
-mod util;
-use rust_embed::RustEmbed;
-
-#[derive(RustEmbed)]
-#[folder = "lib/"]
-pub struct Lib;
-
-#[derive(RustEmbed)]
-#[folder = "builtin/"]
-pub struct Builtins;
 
 #[derive(StructOpt, Debug)]
 enum Command {
+    /// Compile a program
     #[structopt()]
     Build {
-        in_file: PathBuf,
-        /// Write output to a file. Use '-' to print to stdout
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+        /// Write output to a file. Use '-' to print to stdout.
+        /// Defaults to the input file with the extension of the target,
+        /// or to the build directory of the project
         #[structopt(short, long)]
-        out_file: PathBuf,
+        out_file: Option<PathBuf>,
+        /// Build again whenever a source file changes
+        #[structopt(short, long)]
+        watch: bool,
+        /// Program used to link executables. Defaults to cc
+        #[structopt(long)]
+        linker: Option<String>,
+        /// Add a directory to the library search path of the linker
+        #[structopt(short = "L", number_of_values = 1)]
+        search_paths: Vec<PathBuf>,
+        /// Link a library into the executable
+        #[structopt(short = "l", number_of_values = 1)]
+        libraries: Vec<String>,
     },
+    /// Compile and run a program
     #[structopt()]
-    Run { in_file: PathBuf },
+    Run {
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+        /// Restart the program whenever a source file changes
+        #[structopt(short, long)]
+        watch: bool,
+        /// Run the program compiled to JavaScript and to a native executable,
+        /// and fail if their output or exit code differ
+        #[structopt(long, conflicts_with = "watch")]
+        differential: bool,
+        /// Arguments passed to the program
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    /// Report errors and warnings without generating code
+    #[structopt()]
+    Check {
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+    },
+    /// Format source files in place. Directories are formatted recursively
+    #[structopt()]
+    Fmt {
+        #[structopt(required = true)]
+        files: Vec<PathBuf>,
+        /// Fail if a file is not formatted, without changing it
+        #[structopt(long)]
+        check: bool,
+    },
+    /// Run all functions annotated with @test
+    #[structopt()]
+    Test {
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+    },
+    /// Measure all functions annotated with @bench, compiled to JavaScript and native code
+    #[structopt()]
+    Bench {
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+    },
+    /// Start an interactive session
+    #[structopt()]
+    Repl,
+    /// Create a new project in a new directory
+    #[structopt()]
+    New { path: PathBuf },
+    /// Remove the build directory of the project in the current directory,
+    /// or the global build cache outside of projects
+    #[structopt()]
+    Clean,
+    /// Generate extern declarations for the function prototypes of a C header
+    #[structopt()]
+    Bindgen {
+        header: PathBuf,
+        /// Write the declarations to a file instead of stdout
+        #[structopt(short, long)]
+        out_file: Option<PathBuf>,
+    },
+    /// Print the call graph or the module graph of a program
+    #[structopt()]
+    Graph {
+        /// Defaults to the entrypoint of the project in the current directory
+        in_file: Option<PathBuf>,
+        /// Print the imports between modules instead of the calls between functions
+        #[structopt(long)]
+        modules: bool,
+        /// Output format. Options: dot, json
+        #[structopt(long, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// Add a dependency to the project in the current directory
+    #[structopt()]
+    Add {
+        name: String,
+        /// Fetch the package from a git repository
+        #[structopt(long)]
+        git: Option<String>,
+        /// Use the package in a local directory
+        #[structopt(long, conflicts_with = "git")]
+        path: Option<PathBuf>,
+    },
+    /// Create a new project in an existing directory
+    #[structopt()]
+    Init {
+        #[structopt(default_value = ".")]
+        path: PathBuf,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -60,37 +156,294 @@ struct Opt {
     #[structopt(subcommand)]
     command: Command,
 
-    /// Target language. Options: c, js, llvm, x86
-    #[structopt(long, short, parse(try_from_str))]
+    /// Target language. Options: c, js, llvm, qbe, x86
+    #[structopt(long, short, global = true, parse(try_from_str))]
     target: Option<Target>,
+
+    /// Optimization level. Options: 0, 1, 2
+    #[structopt(short = "O", long = "opt-level", global = true, default_value = "0")]
+    opt_level: u8,
+
+    /// Report the given lint as a warning. Use 'all' to enable every lint
+    #[structopt(short = "W", long = "warn", global = true, number_of_values = 1)]
+    warn: Vec<String>,
+
+    /// Silence the given lint. Use 'all' to silence every lint
+    #[structopt(short = "A", long = "allow", global = true, number_of_values = 1)]
+    allow: Vec<String>,
+
+    /// Fail the build if any warning is emitted
+    #[structopt(long = "deny-warnings", global = true)]
+    deny_warnings: bool,
+
+    /// Reject values of type 'any' and functions without a fully typed signature
+    #[structopt(long, global = true)]
+    strict: bool,
+
+    /// Print information about each compilation step
+    #[structopt(short, long, global = true)]
+    verbose: bool,
+
+    /// Instrument native code with runtime checks, like a limit of the recursion depth
+    #[structopt(long, global = true)]
+    debug: bool,
+
+    /// Panic if the result of an integer operation overflows
+    #[structopt(long = "overflow-checks", global = true)]
+    overflow_checks: bool,
+
+    /// Omit runtime checks, like bounds checks of array accesses
+    #[structopt(long, global = true)]
+    release: bool,
+
+    /// Leave out the prelude of the standard library, like println
+    #[structopt(long = "no-prelude", global = true)]
+    no_prelude: bool,
+
+    /// Neither read from nor write to the build cache
+    #[structopt(long = "no-cache", global = true)]
+    no_cache: bool,
+
+    /// Format of errors and warnings. Options: human, json
+    #[structopt(long = "message-format", global = true, default_value = "human")]
+    message_format: MessageFormat,
+
+    /// How JavaScript output is packaged. Options: cjs, esm, iife. Defaults to the format of the runtime
+    #[structopt(long = "module-format", global = true)]
+    module_format: Option<ModuleFormat>,
+
+    /// Environment JavaScript output runs in. Options: node, deno, browser
+    #[structopt(long = "js-runtime", global = true, default_value = "node")]
+    js_runtime: JsRuntime,
+
+    /// Architecture of native output. Options: amd64, arm64, riscv64. Defaults to the host's
+    #[structopt(long, global = true)]
+    arch: Option<Arch>,
+
+    /// Write TypeScript declarations of JavaScript output next to the output file
+    #[structopt(long, global = true)]
+    declarations: bool,
+
+    /// Keep functions and structs the program does not use in the output
+    #[structopt(long = "keep-all", global = true)]
+    keep_all: bool,
+
+    /// Report the time spent in each phase of the compiler. Options: table, json
+    #[structopt(long, global = true, require_equals = true, min_values = 0)]
+    timings: Option<Option<TimingsFormat>>,
 }
 
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("Error: {}", err);
+    let opts = Opt::from_args();
+    let format = opts.message_format;
+    if let Err(err) = run(opts) {
+        command::build::report_error(&err, format);
         process::exit(1);
     }
 }
 
-fn run() -> Result<(), String> {
-    let opts = Opt::from_args();
+fn run(opts: Opt) -> Result<(), String> {
+    let mut warnings = WarningOptions::from_flags(&opts.warn, &opts.allow, opts.deny_warnings)?;
+    warnings.strict = opts.strict;
+    let mut options = BuildOptions {
+        opt_level: opts.opt_level,
+        warnings,
+        verbose: opts.verbose,
+        debug: opts.debug,
+        overflow_checks: opts.overflow_checks,
+        release: opts.release,
+        test: false,
+        bench: false,
+        packages: Packages::new(),
+        no_prelude: opts.no_prelude,
+        prelude: None,
+        // Builds outside of projects use a global cache
+        cache: if opts.no_cache {
+            None
+        } else {
+            package::cache_dir().ok().map(|dir| dir.join("cache"))
+        },
+        message_format: opts.message_format,
+        module_format: opts
+            .module_format
+            .unwrap_or_else(|| opts.js_runtime.default_module_format()),
+        js_runtime: opts.js_runtime,
+        arch: opts.arch,
+        declarations: opts.declarations,
+        keep_all: opts.keep_all,
+        timings: opts.timings.map(Option::unwrap_or_default),
+    };
 
     match opts.command {
-        Command::Build { in_file, out_file } => {
-            let target = match opts.target {
-                Some(t) => t,
-                None => Target::from_extension(&out_file).ok_or_else(|| {
-                    format!(
-                        "Cannot detect target from output file {}, use --target option to set it explicitly",
-                        &out_file.to_string_lossy(),
-                    )
-                })?,
+        Command::Build {
+            in_file,
+            out_file,
+            watch,
+            linker,
+            search_paths,
+            libraries,
+        } => {
+            let link = LinkOptions {
+                linker,
+                search_paths,
+                libraries,
+            };
+            let (in_file, project) = resolve_input(in_file, &mut options)?;
+            let target = match (opts.target, &out_file) {
+                (Some(t), _) => t,
+                (None, Some(out_file)) if out_file.to_str() != Some("-") => {
+                    Target::from_extension(out_file).ok_or_else(|| {
+                        format!(
+                            "Cannot detect target from output file {}, use --target option to set it explicitly",
+                            &out_file.to_string_lossy(),
+                        )
+                    })?
+                }
+                (None, _) => project_target(&project)?.unwrap_or(Target::JS),
+            };
+            let out_file = match (out_file, &project) {
+                (Some(out_file), _) => out_file,
+                (None, Some(project)) => {
+                    let out_dir = project.out_dir();
+                    fs::create_dir_all(&out_dir)
+                        .map_err(|e| format!("Could not create build directory: {}", e))?;
+                    out_dir
+                        .join(&project.manifest.package.name)
+                        .with_extension(target.extension())
+                }
+                (None, None) => in_file.with_extension(target.extension()),
             };
 
-            command::build::build(&target, &in_file, &out_file)?
+            if watch {
+                let dirs = command::watch::source_dirs(&in_file, &options);
+                command::watch::watch(&dirs, options.message_format, || {
+                    let embedded = command::build::build(
+                        &target,
+                        &in_file,
+                        &out_file,
+                        options.clone(),
+                        &link,
+                    )?;
+                    eprintln!("Wrote {}", out_file.display());
+                    Ok((None, embedded))
+                })?
+            } else {
+                command::build::build(&target, &in_file, &out_file, options, &link)?;
+            }
+        }
+        Command::Run {
+            in_file,
+            watch,
+            differential,
+            args,
+        } => {
+            let (in_file, project) = resolve_input(in_file, &mut options)?;
+            let target = opts.target.or(project_target(&project)?);
+            let target = target.unwrap_or(Target::JS);
+            if differential {
+                command::run::differential(&in_file, &args, options)?
+            } else if watch {
+                let dirs = command::watch::source_dirs(&in_file, &options);
+                command::watch::watch(&dirs, options.message_format, || {
+                    command::run::start(target, &in_file, &args, options.clone())
+                        .map(|(child, embedded)| (Some(child), embedded))
+                })?
+            } else {
+                command::run::run(target, in_file, &args, options)?
+            }
+        }
+        Command::Check { in_file } => {
+            let (in_file, project) = resolve_input(in_file, &mut options)?;
+            let target = opts.target.or(project_target(&project)?);
+            command::check::check(&target.unwrap_or(Target::JS), &in_file, options)?
+        }
+        Command::Fmt { files, check } => command::fmt::fmt(&files, check)?,
+        Command::Test { in_file } => {
+            let (in_file, _) = resolve_input(in_file, &mut options)?;
+            command::test::test(&in_file, options)?
+        }
+        Command::Bench { in_file } => {
+            let (in_file, _) = resolve_input(in_file, &mut options)?;
+            command::bench::bench(&in_file, options)?
+        }
+        Command::Repl => command::repl::repl(options)?,
+        Command::New { path } => command::new::new(&path)?,
+        Command::Init { path } => command::new::init(&path)?,
+        Command::Graph {
+            in_file,
+            modules,
+            format,
+        } => {
+            let (in_file, _) = resolve_input(in_file, &mut options)?;
+            command::graph::graph(&in_file, modules, format, options)?
+        }
+        Command::Add { name, git, path } => command::add::add(&name, git, path)?,
+        Command::Clean => command::clean::clean()?,
+        Command::Bindgen { header, out_file } => {
+            command::bindgen::bindgen(&header, out_file.as_deref())?
         }
-        Command::Run { in_file } => command::run::run(opts.target.unwrap_or(Target::JS), in_file)?,
     };
 
     Ok(())
 }
+
+/// Returns the file a command should operate on. If no file is given,
+/// the entrypoint of the project in the current directory is used.
+/// The dependencies of the project the file belongs to are added to the options.
+fn resolve_input(
+    in_file: Option<PathBuf>,
+    options: &mut BuildOptions,
+) -> Result<(PathBuf, Option<Project>), String> {
+    let cwd = env::current_dir().map_err(|e| format!("Could not get current directory: {}", e))?;
+
+    if let Some(in_file) = in_file {
+        let path = cwd.join(&in_file);
+        if let Some(project) = Project::discover(path.parent().unwrap_or(&cwd))? {
+            use_project(&project, options)?;
+        }
+        return Ok((in_file, None));
+    }
+
+    let project = Project::discover(&cwd)?.ok_or_else(|| {
+        format!(
+            "No input file given and no {} found in {} or any parent directory",
+            manifest::MANIFEST_FILE,
+            cwd.display()
+        )
+    })?;
+    if options.verbose {
+        let package = &project.manifest.package;
+        eprintln!(
+            "Using package {} v{} ({})",
+            package.name,
+            package.version,
+            project.root.display()
+        );
+    }
+    use_project(&project, options)?;
+    // Diagnostics refer to files relative to the current directory
+    let entry = project.entry();
+    let entry = entry.strip_prefix(&cwd).unwrap_or(&entry).to_path_buf();
+    Ok((entry, Some(project)))
+}
+
+/// Builds with the dependencies, the prelude and the cache of the given project
+fn use_project(project: &Project, options: &mut BuildOptions) -> Result<(), String> {
+    options.packages = package::resolve(project, &package::cache_dir()?)?;
+    // Like the entrypoint, the prelude is relative to the current directory
+    let cwd = env::current_dir().map_err(|e| format!("Could not get current directory: {}", e))?;
+    options.prelude = project
+        .prelude()
+        .map(|prelude| prelude.strip_prefix(&cwd).unwrap_or(&prelude).to_path_buf());
+    if options.cache.is_some() {
+        options.cache = Some(project.out_dir().join("cache"));
+    }
+    Ok(())
+}
+
+fn project_target(project: &Option<Project>) -> Result<Option<Target>, String> {
+    match project {
+        Some(project) => project.manifest.target(),
+        None => Ok(None),
+    }
+}
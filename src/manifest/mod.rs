@@ -0,0 +1,172 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::generator::Target;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(test)]
+mod tests;
+
+/// File name of the project manifest
+pub const MANIFEST_FILE: &str = "sb.toml";
+
+/// Contents of a `sb.toml` file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: Package,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Entrypoint of the program, relative to the manifest
+    #[serde(default = "default_entry")]
+    pub entry: PathBuf,
+    /// Target that is used if none is given on the command line
+    pub target: Option<String>,
+    /// Module that every module of the project can use without importing it, relative to the manifest
+    pub prelude: Option<PathBuf>,
+}
+
+/// Source of a package this project depends on
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    pub path: Option<PathBuf>,
+    pub git: Option<String>,
+}
+
+fn default_entry() -> PathBuf {
+    PathBuf::from("src").join("main.sb")
+}
+
+impl FromStr for Manifest {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, String> {
+        let manifest: Manifest = toml::from_str(raw).map_err(|e| e.to_string())?;
+
+        if !is_valid_name(&manifest.package.name) {
+            return Err(format!(
+                "Invalid package name '{}', only letters, digits, '-' and '_' are allowed",
+                manifest.package.name
+            ));
+        }
+        manifest.target()?;
+        for (name, dependency) in &manifest.dependencies {
+            // Names of git dependencies become part of the directory they are fetched into
+            if !is_valid_name(name) {
+                return Err(format!(
+                    "Invalid dependency name '{}', only letters, digits, '-' and '_' are allowed",
+                    name
+                ));
+            }
+            if dependency.path.is_some() == dependency.git.is_some() {
+                return Err(format!(
+                    "Dependency '{}' needs either a 'path' or a 'git' source",
+                    name
+                ));
+            }
+            // The url is passed to git, which would read it as an option
+            if let Some(url) = dependency.git.as_ref().filter(|url| url.starts_with('-')) {
+                return Err(format!(
+                    "Dependency '{}' has an invalid git url '{}'",
+                    name, url
+                ));
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl Manifest {
+    pub fn target(&self) -> Result<Option<Target>, String> {
+        self.package
+            .target
+            .as_ref()
+            .map(|target| Target::from_str(target))
+            .transpose()
+    }
+}
+
+/// Returns true if the name can be used for a package
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A directory containing a manifest
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    /// Reads the manifest located in the given directory
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join(MANIFEST_FILE);
+        let raw = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let manifest =
+            Manifest::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            manifest,
+        })
+    }
+
+    /// Searches the given directory and its parents for a manifest
+    pub fn discover(dir: &Path) -> Result<Option<Self>, String> {
+        for ancestor in dir.ancestors() {
+            if ancestor.join(MANIFEST_FILE).is_file() {
+                return Self::load(ancestor).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Path of the entrypoint of the program
+    pub fn entry(&self) -> PathBuf {
+        self.root.join(&self.manifest.package.entry)
+    }
+
+    /// Path of the prelude of the project, if it has one
+    pub fn prelude(&self) -> Option<PathBuf> {
+        self.manifest
+            .package
+            .prelude
+            .as_ref()
+            .map(|prelude| self.root.join(prelude))
+    }
+
+    /// Directory build artifacts are written to
+    pub fn out_dir(&self) -> PathBuf {
+        self.root.join("build")
+    }
+}
@@ -0,0 +1,108 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::generator::Target;
+use crate::manifest::*;
+use std::env;
+
+#[test]
+fn test_minimal_manifest() {
+    let raw = "
+    [package]
+    name = \"hello\"
+    version = \"0.1.0\"
+    ";
+    let manifest = Manifest::from_str(raw).unwrap();
+    assert_eq!(manifest.package.name, "hello");
+    assert_eq!(manifest.package.entry, PathBuf::from("src/main.sb"));
+    assert_eq!(manifest.target(), Ok(None));
+    assert_eq!(manifest.package.prelude, None);
+    assert!(manifest.dependencies.is_empty());
+}
+
+#[test]
+fn test_full_manifest() {
+    let raw = "
+    [package]
+    name = \"hello\"
+    version = \"0.1.0\"
+    entry = \"lib/app.sb\"
+    target = \"qbe\"
+    prelude = \"lib/prelude.sb\"
+
+    [dependencies]
+    math = { path = \"../math\" }
+    json = { git = \"https://example.com/json.git\" }
+    ";
+    let manifest = Manifest::from_str(raw).unwrap();
+    assert_eq!(manifest.package.entry, PathBuf::from("lib/app.sb"));
+    assert_eq!(manifest.target(), Ok(Some(Target::Qbe)));
+    assert_eq!(
+        manifest.package.prelude,
+        Some(PathBuf::from("lib/prelude.sb"))
+    );
+    assert_eq!(
+        manifest.dependencies["math"].path,
+        Some(PathBuf::from("../math"))
+    );
+    assert_eq!(
+        manifest.dependencies["json"].git,
+        Some("https://example.com/json.git".into())
+    );
+}
+
+#[test]
+fn test_invalid_manifests() {
+    let missing_version = "[package]\nname = \"hello\"";
+    assert!(Manifest::from_str(missing_version).is_err());
+
+    let invalid_name = "[package]\nname = \"hello world\"\nversion = \"0.1.0\"";
+    assert!(Manifest::from_str(invalid_name).is_err());
+
+    let invalid_target = "[package]\nname = \"hello\"\nversion = \"0.1.0\"\ntarget = \"foo\"";
+    assert!(Manifest::from_str(invalid_target).is_err());
+
+    let missing_source =
+        "[package]\nname = \"hello\"\nversion = \"0.1.0\"\n[dependencies]\nfoo = {}";
+    assert!(Manifest::from_str(missing_source).is_err());
+
+    let option_as_url = "[package]\nname = \"hello\"\nversion = \"0.1.0\"\n[dependencies]\nfoo = { git = \"--upload-pack=touch pwned\" }";
+    assert!(Manifest::from_str(option_as_url)
+        .unwrap_err()
+        .contains("invalid git url"));
+
+    let path_as_name = "[package]\nname = \"hello\"\nversion = \"0.1.0\"\n[dependencies]\n\"../../../tmp/x\" = { git = \"https://example.com/x.git\" }";
+    assert!(Manifest::from_str(path_as_name)
+        .unwrap_err()
+        .contains("Invalid dependency name '../../../tmp/x'"));
+}
+
+#[test]
+fn test_discover_project() {
+    let root = env::temp_dir().join("sb_test_discover_project");
+    let nested = root.join("src").join("nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(
+        root.join(MANIFEST_FILE),
+        "[package]\nname = \"hello\"\nversion = \"0.1.0\"",
+    )
+    .unwrap();
+
+    let project = Project::discover(&nested).unwrap().unwrap();
+    assert_eq!(project.root, root);
+    assert_eq!(project.entry(), root.join("src").join("main.sb"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,223 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::{BlockId, Function};
+
+/// Edges between the blocks of a function
+pub struct Cfg {
+    preds: Vec<Vec<BlockId>>,
+    succs: Vec<Vec<BlockId>>,
+    /// Reachable blocks in reverse postorder, starting with the entry
+    order: Vec<BlockId>,
+    reachable: Vec<bool>,
+}
+
+impl Cfg {
+    pub fn new(func: &Function) -> Self {
+        let mut preds = vec![Vec::new(); func.blocks.len()];
+        let mut succs = vec![Vec::new(); func.blocks.len()];
+        for (i, block) in func.blocks.iter().enumerate() {
+            for succ in block.terminator.successors() {
+                // A branch to the same block on both sides is a single edge
+                if !succs[i].contains(&succ) {
+                    succs[i].push(succ);
+                    preds[succ.0 as usize].push(BlockId(i as u32));
+                }
+            }
+        }
+
+        // Depth-first search from the entry, a block is finished once all its successors are
+        let mut reachable = vec![false; func.blocks.len()];
+        let mut order = Vec::new();
+        let mut stack = Vec::new();
+        if !func.blocks.is_empty() {
+            reachable[0] = true;
+            stack.push((BlockId(0), 0));
+        }
+        while let Some((block, next)) = stack.pop() {
+            match succs[block.0 as usize].get(next) {
+                Some(succ) => {
+                    stack.push((block, next + 1));
+                    if !reachable[succ.0 as usize] {
+                        reachable[succ.0 as usize] = true;
+                        stack.push((*succ, 0));
+                    }
+                }
+                None => order.push(block),
+            }
+        }
+        order.reverse();
+
+        Cfg {
+            preds,
+            succs,
+            order,
+            reachable,
+        }
+    }
+
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.preds[block.0 as usize]
+    }
+
+    pub fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.succs[block.0 as usize]
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.reachable[block.0 as usize]
+    }
+
+    /// Reachable blocks ordered so that every block comes before its successors,
+    /// except for the successors that close a loop
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.order
+    }
+}
+
+/// Immediate dominators of the reachable blocks of a function. A block dominates another one
+/// if every path from the entry to the other block passes through it.
+pub struct Dominators {
+    idom: Vec<Option<BlockId>>,
+    /// Position of every block in reverse postorder
+    position: Vec<usize>,
+}
+
+impl Dominators {
+    /// Computes the dominators with the iterative algorithm by Cooper, Harvey and Kennedy
+    pub fn new(cfg: &Cfg) -> Self {
+        let mut position = vec![usize::MAX; cfg.succs.len()];
+        for (i, block) in cfg.order.iter().enumerate() {
+            position[block.0 as usize] = i;
+        }
+        let mut idom: Vec<Option<BlockId>> = vec![None; cfg.succs.len()];
+        if let Some(entry) = cfg.order.first() {
+            idom[entry.0 as usize] = Some(*entry);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in cfg.order.iter().skip(1) {
+                let mut new_idom: Option<BlockId> = None;
+                for pred in cfg.predecessors(*block) {
+                    if idom[pred.0 as usize].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => *pred,
+                        Some(other) => intersect(&idom, &position, *pred, other),
+                    });
+                }
+                if new_idom != idom[block.0 as usize] {
+                    idom[block.0 as usize] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idom, position }
+    }
+
+    /// Immediate dominator of a block, `None` for the entry and unreachable blocks
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0 as usize].filter(|idom| *idom != block)
+    }
+
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if self.position[b.0 as usize] == usize::MAX {
+            return false;
+        }
+        let mut block = b;
+        loop {
+            if block == a {
+                return true;
+            }
+            match self.idom(block) {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Walks up the dominator tree from both blocks until they meet
+fn intersect(
+    idom: &[Option<BlockId>],
+    position: &[usize],
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while position[a.0 as usize] > position[b.0 as usize] {
+            a = idom[a.0 as usize].expect("processed blocks have a dominator");
+        }
+        while position[b.0 as usize] > position[a.0 as usize] {
+            b = idom[b.0 as usize].expect("processed blocks have a dominator");
+        }
+    }
+    a
+}
+
+/// Natural loop, formed by the edges back to a header that dominates their source
+pub struct Loop {
+    pub header: BlockId,
+    /// Blocks of the loop including the header, in reverse postorder
+    pub body: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.body.contains(&block)
+    }
+}
+
+/// Finds the loops of a function, inner loops before the loops that contain them.
+/// Loops with the same header are treated as one loop.
+pub fn loops(cfg: &Cfg, doms: &Dominators) -> Vec<Loop> {
+    let mut loops: Vec<Loop> = Vec::new();
+    for header in cfg.reverse_postorder() {
+        let latches: Vec<BlockId> = cfg
+            .predecessors(*header)
+            .iter()
+            .copied()
+            .filter(|pred| doms.dominates(*header, *pred))
+            .collect();
+        if latches.is_empty() {
+            continue;
+        }
+
+        // Every block that reaches a latch without passing through the header is in the loop
+        let mut body = vec![*header];
+        let mut pending = latches;
+        while let Some(block) = pending.pop() {
+            if body.contains(&block) {
+                continue;
+            }
+            body.push(block);
+            pending.extend(
+                cfg.predecessors(block)
+                    .iter()
+                    .filter(|pred| cfg.is_reachable(**pred)),
+            );
+        }
+        body.sort_by_key(|block| doms.position[block.0 as usize]);
+        loops.push(Loop {
+            header: *header,
+            body,
+        });
+    }
+    loops.sort_by_key(|l| l.body.len());
+    loops
+}
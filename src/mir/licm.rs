@@ -0,0 +1,182 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Loop-invariant code motion: instructions inside a loop whose operands do not change
+// while the loop runs are computed once, before the loop is entered
+use super::cfg::{self, Cfg, Dominators};
+use super::{BinaryOp, Block, BlockId, Function, Inst, Phi, Terminator, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Builtins that never change the length of an array, so loops that call them can still
+/// read the length of an array once
+const NON_RESIZING_BUILTINS: [&str; 4] = [
+    "_check_index",
+    "_add_checked",
+    "_sub_checked",
+    "_mul_checked",
+];
+
+/// Moves loop-invariant instructions into the preheader of their loop.
+/// Inner loops are handled first, so an instruction can move out of several loops.
+pub fn hoist_loop_invariants(func: &mut Function) -> bool {
+    let mut changed = insert_preheaders(func);
+    let cfg = Cfg::new(func);
+    let doms = Dominators::new(&cfg);
+    let constants: HashMap<Value, u64> = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|inst| match (inst.result, &inst.inst) {
+            (Some(result), Inst::Int(value)) => Some((result, *value)),
+            _ => None,
+        })
+        .collect();
+
+    for l in cfg::loops(&cfg, &doms) {
+        let preheader = match preheader(&cfg, &l) {
+            Some(preheader) => preheader,
+            None => continue,
+        };
+        let mut variant: HashSet<Value> = HashSet::new();
+        let mut resizes_arrays = false;
+        for block in &l.body {
+            let block = &func.blocks[block.0 as usize];
+            variant.extend(block.phis.iter().map(|phi| phi.result));
+            variant.extend(block.instructions.iter().filter_map(|inst| inst.result));
+            // Any other function may push to or pop from the arrays it is passed
+            resizes_arrays |= block.instructions.iter().any(|inst| {
+                matches!(&inst.inst, Inst::Call(name, _) if !NON_RESIZING_BUILTINS.contains(&name.as_str()))
+            });
+        }
+
+        let mut hoisted = Vec::new();
+        for block in &l.body {
+            let instructions = std::mem::take(&mut func.blocks[block.0 as usize].instructions);
+            for inst in instructions {
+                let invariant = is_hoistable(&inst.inst, &constants, resizes_arrays)
+                    && inst.inst.operands().iter().all(|v| !variant.contains(v));
+                if invariant {
+                    if let Some(result) = inst.result {
+                        variant.remove(&result);
+                    }
+                    hoisted.push(inst);
+                } else {
+                    func.blocks[block.0 as usize].instructions.push(inst);
+                }
+            }
+        }
+        changed |= !hoisted.is_empty();
+        func.blocks[preheader.0 as usize]
+            .instructions
+            .extend(hoisted);
+    }
+    changed
+}
+
+/// Instructions without side effects that can not fail, so computing them on a path
+/// that would not have computed them does not change the behavior of the function.
+/// Elements of arrays are not moved, since the index may be out of bounds on such a path.
+fn is_hoistable(inst: &Inst, constants: &HashMap<Value, u64>, resizes_arrays: bool) -> bool {
+    match inst {
+        Inst::Int(_) | Inst::Bool(_) | Inst::Str(_) | Inst::Compare(..) => true,
+        // Division by zero traps
+        Inst::Binary(BinaryOp::Div | BinaryOp::Rem, _, divisor) => {
+            constants.get(divisor).is_some_and(|value| *value != 0)
+        }
+        Inst::Binary(..) => true,
+        Inst::Length(_) => !resizes_arrays,
+        Inst::Call(..) | Inst::Load(..) | Inst::Store(..) => false,
+    }
+}
+
+/// Block outside of the loop that only jumps to its header, if the header has one
+fn preheader(cfg: &Cfg, l: &cfg::Loop) -> Option<BlockId> {
+    match outside_predecessors(cfg, l).as_slice() {
+        [pred] if cfg.successors(*pred) == [l.header] => Some(*pred),
+        _ => None,
+    }
+}
+
+fn outside_predecessors(cfg: &Cfg, l: &cfg::Loop) -> Vec<BlockId> {
+    cfg.predecessors(l.header)
+        .iter()
+        .copied()
+        .filter(|pred| cfg.is_reachable(*pred) && !l.contains(*pred))
+        .collect()
+}
+
+/// Gives every loop a block that is the only way to enter it from outside.
+/// The new blocks are added after the existing ones.
+fn insert_preheaders(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let cfg = Cfg::new(func);
+        let doms = Dominators::new(&cfg);
+        let missing = cfg::loops(&cfg, &doms)
+            .into_iter()
+            .find(|l| preheader(&cfg, l).is_none());
+        let l = match missing {
+            Some(l) => l,
+            None => return changed,
+        };
+
+        let outside = outside_predecessors(&cfg, &l);
+        let preheader = BlockId(func.blocks.len() as u32);
+        let mut phis = Vec::new();
+        for phi in &mut func.blocks[l.header.0 as usize].phis {
+            let (entering, looping): (Vec<_>, Vec<_>) = phi
+                .incoming
+                .drain(..)
+                .partition(|(pred, _)| outside.contains(pred));
+            phi.incoming = looping;
+            phis.push((phi.result, entering));
+        }
+        // The values entering the loop are merged in the preheader if it has several predecessors
+        let mut preheader_phis = Vec::new();
+        for (result, entering) in phis {
+            let value = match entering.as_slice() {
+                [(_, value)] => *value,
+                _ => {
+                    let value = func.new_value(func.ty(result));
+                    preheader_phis.push(Phi {
+                        result: value,
+                        incoming: entering,
+                    });
+                    value
+                }
+            };
+            let header = &mut func.blocks[l.header.0 as usize];
+            let phi = header
+                .phis
+                .iter_mut()
+                .find(|phi| phi.result == result)
+                .expect("phi was just visited");
+            phi.incoming.push((preheader, value));
+        }
+        func.blocks.push(Block {
+            phis: preheader_phis,
+            instructions: Vec::new(),
+            terminator: Terminator::Jump(l.header),
+        });
+        for pred in outside {
+            for target in func.blocks[pred.0 as usize].terminator.successors_mut() {
+                if *target == l.header {
+                    *target = preheader;
+                }
+            }
+        }
+        changed = true;
+    }
+}
@@ -0,0 +1,780 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::simplify;
+use super::*;
+use crate::ast::types::Type;
+use crate::ast::{self, BinOp, Expression, MatchArm, Statement};
+use crate::semantic::types::TypeEnv;
+use std::collections::HashMap;
+
+/// Lowers a function into SSA form, following "Simple and Efficient Construction of
+/// Static Single Assignment Form" by Braun et al.: the current value of each variable is
+/// looked up per block, and phis are inserted where the definitions of predecessors meet.
+///
+/// Returns an error if the function uses a feature the IR does not support yet, like
+/// structures, array literals or global variables. Backends generate those functions from the AST.
+pub fn lower_function(func: &ast::Function, types: &mut TypeEnv) -> Result<Function, String> {
+    types.enter_function(func);
+    let mut builder = Builder {
+        func: Function {
+            name: func.name.clone(),
+            arguments: Vec::new(),
+            ret_type: func.ret_type.as_ref().map(to_ty).transpose()?,
+            blocks: Vec::new(),
+            types: Vec::new(),
+        },
+        types,
+        current: None,
+        definitions: HashMap::new(),
+        preds: Vec::new(),
+        sealed: Vec::new(),
+        incomplete: HashMap::new(),
+        locals: HashMap::new(),
+        loops: Vec::new(),
+        tail_call: None,
+    };
+
+    let entry = builder.new_block();
+    builder.seal(entry)?;
+    builder.current = Some(entry);
+    let mut arguments = Vec::new();
+    for arg in &func.arguments {
+        let ty = to_ty(
+            arg.ty
+                .as_ref()
+                .ok_or("Function arguments must have a type")?,
+        )?;
+        let value = builder.new_value(ty);
+        builder.write_variable(&arg.name, entry, value);
+        builder.locals.insert(arg.name.clone(), ty);
+        arguments.push((arg.name.clone(), value));
+    }
+    builder.func.arguments = arguments.iter().map(|(_, value)| *value).collect();
+
+    if func.body.has_tail_call(&func.name, func.arguments.len()) {
+        // Tail calls jump back to this block, since the entry block can not be a jump target
+        let body = builder.new_block();
+        builder.jump(body);
+        builder.current = Some(body);
+        let names = arguments.into_iter().map(|(name, _)| name).collect();
+        builder.tail_call = Some((func.name.clone(), names, body));
+    }
+
+    builder.statement(&func.body)?;
+    if let Some((_, _, body)) = builder.tail_call.clone() {
+        builder.seal(body)?;
+    }
+    if builder.current.is_some() {
+        // Falling off the end of a function with a return type is only fine if the
+        // control flow graph shows that the end can not be reached
+        let end = match builder.func.ret_type {
+            Some(_) => Terminator::Unreachable,
+            None => Terminator::Return(None),
+        };
+        builder.terminate(end);
+    }
+
+    let mut func = builder.func;
+    simplify::fold_constant_branches(&mut func);
+    simplify::remove_unreachable_blocks(&mut func);
+    simplify::remove_trivial_phis(&mut func);
+    let falls_through = func
+        .blocks
+        .iter()
+        .any(|block| block.terminator == Terminator::Unreachable);
+    if falls_through {
+        return Err(format!(
+            "Function '{}' does not return in all code paths",
+            func.name
+        ));
+    }
+    func.verify()?;
+    Ok(func)
+}
+
+/// Returns the IR type of a variable, if the IR supports it
+fn to_ty(ty: &Type) -> Result<Ty, String> {
+    match ty {
+        Type::Int => Ok(Ty::Int),
+        Type::Bool => Ok(Ty::Bool),
+        Type::Str => Ok(Ty::Str),
+        Type::Array(..) => Ok(Ty::Array),
+        other => Err(format!("Type '{}' is not supported by the IR", other)),
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, String> {
+    Err(format!("{} are not supported by the IR", what))
+}
+
+struct Builder<'a> {
+    func: Function,
+    types: &'a mut TypeEnv,
+    /// Block that instructions are appended to, or `None` after a terminator
+    current: Option<BlockId>,
+    /// Variable -> value mappings at the end of each block
+    definitions: HashMap<BlockId, HashMap<String, Value>>,
+    preds: Vec<Vec<BlockId>>,
+    /// Blocks whose predecessors are all known
+    sealed: Vec<bool>,
+    /// Phis of blocks that are not sealed yet, whose operands are added once they are
+    incomplete: HashMap<BlockId, Vec<(String, Value)>>,
+    /// Types of the arguments and the variables declared so far
+    locals: HashMap<String, Ty>,
+    /// Condition and exit block of the enclosing loops
+    loops: Vec<(BlockId, BlockId)>,
+    /// Name and argument names of the function and the block that
+    /// self-recursive tail calls jump to
+    tail_call: Option<(String, Vec<String>, BlockId)>,
+}
+
+impl<'a> Builder<'a> {
+    fn new_block(&mut self) -> BlockId {
+        self.func.blocks.push(Block {
+            phis: Vec::new(),
+            instructions: Vec::new(),
+            terminator: Terminator::Unreachable,
+        });
+        self.preds.push(Vec::new());
+        self.sealed.push(false);
+        BlockId(self.func.blocks.len() as u32 - 1)
+    }
+
+    fn new_value(&mut self, ty: Ty) -> Value {
+        self.func.new_value(ty)
+    }
+
+    fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.func.blocks[id.0 as usize]
+    }
+
+    /// Appends an instruction to the current block
+    fn emit(&mut self, inst: Inst, ty: Option<Ty>) -> Option<Value> {
+        let block = self
+            .current
+            .expect("instructions are only emitted into reachable blocks");
+        let result = ty.map(|ty| self.new_value(ty));
+        self.block_mut(block)
+            .instructions
+            .push(Instruction { result, inst });
+        result
+    }
+
+    fn emit_value(&mut self, inst: Inst, ty: Ty) -> Value {
+        self.emit(inst, Some(ty)).expect("instruction has a result")
+    }
+
+    /// Ends the current block
+    fn terminate(&mut self, terminator: Terminator) {
+        if let Some(block) = self.current.take() {
+            for succ in terminator.successors() {
+                self.preds[succ.0 as usize].push(block);
+            }
+            self.block_mut(block).terminator = terminator;
+        }
+    }
+
+    fn jump(&mut self, target: BlockId) {
+        self.terminate(Terminator::Jump(target));
+    }
+
+    /// Continues in the given block, unless no other block jumps to it
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = if self.preds[block.0 as usize].is_empty() {
+            None
+        } else {
+            Some(block)
+        };
+    }
+
+    fn write_variable(&mut self, name: &str, block: BlockId, value: Value) {
+        self.definitions
+            .entry(block)
+            .or_default()
+            .insert(name.to_owned(), value);
+    }
+
+    fn read_variable(&mut self, name: &str, block: BlockId, ty: Ty) -> Result<Value, String> {
+        if let Some(value) = self.definitions.get(&block).and_then(|defs| defs.get(name)) {
+            return Ok(*value);
+        }
+
+        let value = if !self.sealed[block.0 as usize] {
+            // Further predecessors may be added, the phi is completed when the block is sealed
+            let phi = self.new_phi(block, ty);
+            self.incomplete
+                .entry(block)
+                .or_default()
+                .push((name.to_owned(), phi));
+            phi
+        } else {
+            match self.preds[block.0 as usize].as_slice() {
+                [] => return Err(format!("Variable '{}' is used before it is defined", name)),
+                [pred] => {
+                    let pred = *pred;
+                    self.read_variable(name, pred, ty)?
+                }
+                _ => {
+                    // The phi breaks cycles of the lookup through loops
+                    let phi = self.new_phi(block, ty);
+                    self.write_variable(name, block, phi);
+                    self.add_phi_operands(name, block, phi, ty)?;
+                    phi
+                }
+            }
+        };
+        self.write_variable(name, block, value);
+        Ok(value)
+    }
+
+    fn new_phi(&mut self, block: BlockId, ty: Ty) -> Value {
+        let result = self.new_value(ty);
+        self.block_mut(block).phis.push(Phi {
+            result,
+            incoming: Vec::new(),
+        });
+        result
+    }
+
+    fn add_phi_operands(
+        &mut self,
+        name: &str,
+        block: BlockId,
+        phi: Value,
+        ty: Ty,
+    ) -> Result<(), String> {
+        for pred in self.preds[block.0 as usize].clone() {
+            let value = self.read_variable(name, pred, ty)?;
+            if let Some(phi) = self
+                .block_mut(block)
+                .phis
+                .iter_mut()
+                .find(|p| p.result == phi)
+            {
+                phi.incoming.push((pred, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks that all predecessors of the block are known
+    fn seal(&mut self, block: BlockId) -> Result<(), String> {
+        for (name, phi) in self.incomplete.remove(&block).unwrap_or_default() {
+            let ty = self.func.ty(phi);
+            self.add_phi_operands(&name, block, phi, ty)?;
+        }
+        self.sealed[block.0 as usize] = true;
+        Ok(())
+    }
+
+    /// Returns the type of a local variable. Names that are not declared in the
+    /// function refer to global variables, which are not supported.
+    fn local_type(&self, name: &str) -> Result<Ty, String> {
+        match self.locals.get(name) {
+            Some(ty) => Ok(*ty),
+            None => unsupported("Global variables"),
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Result<Value, String> {
+        let ty = self.local_type(name)?;
+        let block = self
+            .current
+            .expect("variables are only read in reachable blocks");
+        self.read_variable(name, block, ty)
+    }
+
+    fn assign(&mut self, name: &str, value: Value) {
+        if let Some(block) = self.current {
+            self.write_variable(name, block, value);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), String> {
+        // Statements after a `return`, `break` or `continue` are never executed
+        if self.current.is_none() {
+            return Ok(());
+        }
+        match stmt {
+            Statement::Block(statements, _) => {
+                for stmt in statements {
+                    self.statement(stmt)?;
+                }
+            }
+            Statement::Declare(var, init) => {
+                // The type of the elements of an array is needed to access them
+                let declared = var.ty.clone().or_else(|| {
+                    init.as_ref()
+                        .and_then(|init| self.types.expression_type(init))
+                });
+                let value = init
+                    .as_ref()
+                    .map(|init| self.expression(init))
+                    .transpose()?;
+                // The type of a variable may not have been inferred yet
+                let ty = match (&var.ty, value) {
+                    (Some(ty), _) => to_ty(ty)?,
+                    (None, Some(value)) => self.func.ty(value),
+                    (None, None) => {
+                        return Err(format!("Missing type for variable '{}'", var.name))
+                    }
+                };
+                let value = match value {
+                    Some(value) => value,
+                    // Variables without an initializer start out with the zero value of their type
+                    None => match ty {
+                        Ty::Int => self.emit_value(Inst::Int(0), ty),
+                        Ty::Bool => self.emit_value(Inst::Bool(false), ty),
+                        Ty::Str => self.emit_value(Inst::Str(String::new()), ty),
+                        Ty::Array => return unsupported("Arrays without an initial value"),
+                    },
+                };
+                self.types.declare(&var.name, declared);
+                self.locals.insert(var.name.clone(), ty);
+                self.assign(&var.name, value);
+            }
+            Statement::Assign(lhs, rhs) => match &**lhs {
+                Expression::Variable(name) => {
+                    self.local_type(name)?;
+                    let value = self.expression(rhs)?;
+                    self.assign(name, value);
+                }
+                Expression::ArrayAccess(name, index) => {
+                    let value = self.expression(rhs)?;
+                    let (array, index, ty) = self.element(name, index)?;
+                    if self.func.ty(value) != ty {
+                        return Err(format!(
+                            "Element of type '{}' assigned a value of type '{}'",
+                            ty,
+                            self.func.ty(value)
+                        ));
+                    }
+                    self.emit(Inst::Store(array, index, value), None);
+                }
+                _ => return unsupported("Assignments to fields"),
+            },
+            Statement::Return(Some(Expression::FunctionCall(name, args)))
+                if self.tail_call.as_ref().is_some_and(|(func, params, _)| {
+                    func == name && params.len() == args.len()
+                }) =>
+            {
+                // All arguments are evaluated before any of them is rebound
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.expression(arg)?);
+                }
+                let (_, params, body) = self.tail_call.clone().expect("tail call target");
+                for (param, value) in params.iter().zip(values) {
+                    self.assign(param, value);
+                }
+                self.jump(body);
+            }
+            Statement::Return(value) => {
+                let value = value
+                    .as_ref()
+                    .map(|value| self.expression(value))
+                    .transpose()?;
+                self.terminate(Terminator::Return(value));
+            }
+            Statement::If(cond, if_clause, else_clause) => {
+                let cond = self.expression(cond)?;
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let end = match else_clause {
+                    Some(_) => self.new_block(),
+                    None => else_block,
+                };
+                self.terminate(Terminator::Branch(cond, then_block, else_block));
+                self.seal(then_block)?;
+
+                self.switch_to(then_block);
+                self.statement(if_clause)?;
+                self.jump(end);
+                if let Some(else_clause) = else_clause {
+                    self.seal(else_block)?;
+                    self.switch_to(else_block);
+                    self.statement(else_clause)?;
+                    self.jump(end);
+                }
+                self.seal(end)?;
+                self.switch_to(end);
+            }
+            Statement::While(cond, body) => {
+                let header = self.new_block();
+                self.jump(header);
+                self.switch_to(header);
+                let cond = self.expression(cond)?;
+                let body_block = self.new_block();
+                let end = self.new_block();
+                self.terminate(Terminator::Branch(cond, body_block, end));
+                self.seal(body_block)?;
+
+                self.loops.push((header, end));
+                self.switch_to(body_block);
+                self.statement(body)?;
+                self.jump(header);
+                self.loops.pop();
+
+                self.seal(header)?;
+                self.seal(end)?;
+                self.switch_to(end);
+            }
+            Statement::For(var, iterable, body) => self.for_loop(var, iterable, body)?,
+            Statement::Match(subject, arms) => self.match_statement(subject, arms)?,
+            Statement::Break => {
+                let (_, end) = *self.loops.last().ok_or("break used outside of a loop")?;
+                self.jump(end);
+            }
+            Statement::Continue => {
+                let (header, _) = *self.loops.last().ok_or("continue used outside of a loop")?;
+                self.jump(header);
+            }
+            Statement::Exp(expr) => {
+                self.expression_or_void(expr)?;
+            }
+            Statement::Static(..) => return unsupported("Static variables"),
+            Statement::Destructure(..) => return unsupported("Destructuring assignments"),
+            Statement::ForPair(..) => return unsupported("Loops over pairs"),
+            Statement::Defer(_) => return unsupported("Deferred expressions"),
+            Statement::Spawn(_) => return unsupported("Spawned calls"),
+            Statement::Inline(..) => return unsupported("Inline code blocks"),
+            Statement::Unsafe(_) => return unsupported("Unsafe blocks"),
+        }
+        Ok(())
+    }
+
+    /// Loops over the elements of an array by their index, which is counted at the start of
+    /// an iteration, so `continue` can not skip it:
+    ///
+    /// ```text
+    /// header:
+    ///     index = phi
+    ///     branch index < length(array), body, end
+    /// body:
+    ///     element = load array, index
+    ///     index = index + 1
+    ///     <body>
+    ///     jump header
+    /// ```
+    fn for_loop(
+        &mut self,
+        var: &ast::Variable,
+        iterable: &Expression,
+        body: &Statement,
+    ) -> Result<(), String> {
+        let element_type = match self.types.expression_type(iterable) {
+            Some(Type::Array(ty, _)) => *ty,
+            _ => return unsupported("Loops over values other than arrays"),
+        };
+        let ty = to_ty(var.ty.as_ref().unwrap_or(&element_type))?;
+        let array = self.expression(iterable)?;
+        // Names of variables can not contain dots, so the index can not collide with them
+        let counter = format!("for.{}.index", self.func.blocks.len());
+        let zero = self.emit_value(Inst::Int(0), Ty::Int);
+        self.assign(&counter, zero);
+
+        let header = self.new_block();
+        self.jump(header);
+        self.switch_to(header);
+        let index = self.read_variable(&counter, header, Ty::Int)?;
+        let length = self.emit_value(Inst::Length(array), Ty::Int);
+        let cond = self.emit_value(Inst::Compare(CompareOp::Lt, index, length), Ty::Bool);
+        let body_block = self.new_block();
+        let end = self.new_block();
+        self.terminate(Terminator::Branch(cond, body_block, end));
+        self.seal(body_block)?;
+
+        self.loops.push((header, end));
+        self.switch_to(body_block);
+        let element = self.emit_value(Inst::Load(array, index), ty);
+        let one = self.emit_value(Inst::Int(1), Ty::Int);
+        let next = self.emit_value(Inst::Binary(BinaryOp::Add, index, one), Ty::Int);
+        self.assign(&counter, next);
+        self.types
+            .declare(&var.name, Some(var.ty.clone().unwrap_or(element_type)));
+        self.locals.insert(var.name.clone(), ty);
+        self.assign(&var.name, element);
+        self.statement(body)?;
+        self.jump(header);
+        self.loops.pop();
+
+        self.seal(header)?;
+        self.seal(end)?;
+        self.switch_to(end);
+        Ok(())
+    }
+
+    /// Returns the array, the index and the type of the element of an access to an array
+    fn element(&mut self, name: &str, index: &Expression) -> Result<(Value, Value, Ty), String> {
+        let array = self.variable(name)?;
+        let ty = match self
+            .types
+            .expression_type(&Expression::Variable(name.to_owned()))
+        {
+            Some(Type::Array(ty, _)) => to_ty(&ty)?,
+            _ => return Err(format!("Variable '{}' is not an array", name)),
+        };
+        let index = self.expression(index)?;
+        if self.func.ty(index) != Ty::Int {
+            return Err("Arrays must be indexed by integers".to_owned());
+        }
+        Ok((array, index, ty))
+    }
+
+    /// Integer literals as cases become a single switch, other cases are compared in order
+    fn match_statement(&mut self, subject: &Expression, arms: &[MatchArm]) -> Result<(), String> {
+        let subject = self.expression(subject)?;
+        let end = self.new_block();
+        let literals: Option<Vec<u64>> = arms
+            .iter()
+            .filter_map(|arm| match arm {
+                MatchArm::Case(case, _) => Some(case),
+                MatchArm::Else(_) => None,
+            })
+            .map(|case| match case {
+                Expression::Int(value) => Some(*value as u64),
+                _ => None,
+            })
+            .collect();
+
+        let mut bodies = Vec::new();
+        let mut default = end;
+        match literals {
+            Some(literals) if self.func.ty(subject) == Ty::Int => {
+                let mut cases = literals.into_iter();
+                let mut targets = Vec::new();
+                for arm in arms {
+                    let block = self.new_block();
+                    match arm {
+                        MatchArm::Case(_, body) => {
+                            let case = cases.next().expect("every case is a literal");
+                            targets.push((case, block));
+                            bodies.push((block, body));
+                        }
+                        MatchArm::Else(body) => {
+                            default = block;
+                            bodies.push((block, body));
+                        }
+                    }
+                }
+                self.terminate(Terminator::Switch(subject, targets, default));
+            }
+            _ => {
+                let mut else_body = None;
+                // Without an `else` arm, the last case of an exhaustive match is taken
+                // if none of the others matched
+                let unconditional = match arms.last() {
+                    Some(MatchArm::Case(_, body)) if MatchArm::is_exhaustive(arms) => Some(body),
+                    _ => None,
+                };
+                let compared = match unconditional {
+                    Some(_) => &arms[..arms.len() - 1],
+                    None => arms,
+                };
+                for arm in compared {
+                    match arm {
+                        MatchArm::Case(case, body) => {
+                            let case = self.expression(case)?;
+                            if self.func.ty(case) != self.func.ty(subject) {
+                                return Err(
+                                    "Cases of a match statement must have the type of its subject"
+                                        .to_owned(),
+                                );
+                            }
+                            let matches = self
+                                .emit_value(Inst::Compare(CompareOp::Eq, subject, case), Ty::Bool);
+                            let block = self.new_block();
+                            let next = self.new_block();
+                            self.terminate(Terminator::Branch(matches, block, next));
+                            self.seal(next)?;
+                            self.switch_to(next);
+                            bodies.push((block, body));
+                        }
+                        MatchArm::Else(body) => else_body = Some(body),
+                    }
+                }
+                // The `else` arm is taken once every case was compared
+                match else_body.or(unconditional) {
+                    Some(body) => {
+                        let block = self.new_block();
+                        self.jump(block);
+                        bodies.push((block, body));
+                    }
+                    None => self.jump(end),
+                }
+            }
+        }
+
+        for (block, body) in bodies {
+            self.seal(block)?;
+            self.switch_to(block);
+            self.statement(body)?;
+            self.jump(end);
+        }
+        self.seal(end)?;
+        self.switch_to(end);
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<Value, String> {
+        self.expression_or_void(expr)?
+            .ok_or_else(|| "Function without a return value used as a value".to_owned())
+    }
+
+    /// Lowers an expression, which has no value if it calls a function without a return value
+    fn expression_or_void(&mut self, expr: &Expression) -> Result<Option<Value>, String> {
+        let value = match expr {
+            Expression::Int(value) => self.emit_value(Inst::Int(*value as u64), Ty::Int),
+            Expression::Bool(value) => self.emit_value(Inst::Bool(*value), Ty::Bool),
+            Expression::Str(value) => self.emit_value(Inst::Str(value.clone()), Ty::Str),
+            Expression::Variable(name) => self.variable(name)?,
+            // Async functions are called like any other function
+            Expression::Await(call) => return self.expression_or_void(call),
+            // The length of an array is read directly, which lets it move out of loops
+            Expression::FunctionCall(name, args) if name == "_vec_len" && args.len() == 1 => {
+                let array = self.expression(&args[0])?;
+                if self.func.ty(array) != Ty::Array {
+                    return Err("Only arrays have a length".to_owned());
+                }
+                self.emit_value(Inst::Length(array), Ty::Int)
+            }
+            Expression::FunctionCall(name, args) => {
+                let ty = self
+                    .types
+                    .expression_type(expr)
+                    .as_ref()
+                    .map(to_ty)
+                    .transpose()?;
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.expression(arg)?);
+                }
+                return Ok(self.emit(Inst::Call(name.clone(), values), ty));
+            }
+            Expression::BinOp(lhs, op, rhs) => self.binop(lhs, op, rhs)?,
+            Expression::ArrayAccess(name, index) => {
+                let (array, index, ty) = self.element(name, index)?;
+                self.emit_value(Inst::Load(array, index), ty)
+            }
+            Expression::Array(..) => return unsupported("Array literals"),
+            Expression::StructInitialization(..)
+            | Expression::StructUpdate(..)
+            | Expression::FieldAccess(..)
+            | Expression::Selff => return unsupported("Structures"),
+            Expression::Map(_) => return unsupported("Maps"),
+            Expression::Reference(_) | Expression::Dereference(_) => {
+                return unsupported("References")
+            }
+            Expression::Load(..) | Expression::Store(..) => return unsupported("Raw pointers"),
+            Expression::Try(_) => return unsupported("Results"),
+            Expression::SizeOf(_) => return unsupported("Sizes of types"),
+        };
+        Ok(Some(value))
+    }
+
+    fn binop(&mut self, lhs: &Expression, op: &BinOp, rhs: &Expression) -> Result<Value, String> {
+        let (op, assign) = match op {
+            BinOp::And => return self.short_circuit(lhs, rhs, false),
+            BinOp::Or => return self.short_circuit(lhs, rhs, true),
+            BinOp::AddAssign => (BinOp::Addition, true),
+            BinOp::SubtractAssign => (BinOp::Subtraction, true),
+            BinOp::MultiplyAssign => (BinOp::Multiplication, true),
+            BinOp::DivideAssign => (BinOp::Division, true),
+            op => (op.clone(), false),
+        };
+        let lhs_value = self.expression(lhs)?;
+        let rhs_value = self.expression(rhs)?;
+        let ty = self.func.ty(lhs_value);
+        if ty != self.func.ty(rhs_value) {
+            return Err(format!("Operator '{}' applied to different types", op));
+        }
+        if ty == Ty::Array {
+            return unsupported("Operations on arrays");
+        }
+
+        let arithmetic = |op| Inst::Binary(op, lhs_value, rhs_value);
+        let compare = |op| Inst::Compare(op, lhs_value, rhs_value);
+        let (inst, result_ty) = match (op, ty) {
+            (BinOp::Addition, Ty::Int) | (BinOp::Addition, Ty::Str) => {
+                (arithmetic(BinaryOp::Add), ty)
+            }
+            (BinOp::Subtraction, Ty::Int) => (arithmetic(BinaryOp::Sub), ty),
+            (BinOp::Multiplication, Ty::Int) => (arithmetic(BinaryOp::Mul), ty),
+            (BinOp::Division, Ty::Int) => (arithmetic(BinaryOp::Div), ty),
+            (BinOp::Modulus, Ty::Int) => (arithmetic(BinaryOp::Rem), ty),
+            (BinOp::Equal, _) => (compare(CompareOp::Eq), Ty::Bool),
+            (BinOp::NotEqual, _) => (compare(CompareOp::Ne), Ty::Bool),
+            (BinOp::LessThan, Ty::Int) => (compare(CompareOp::Lt), Ty::Bool),
+            (BinOp::LessThanOrEqual, Ty::Int) => (compare(CompareOp::Le), Ty::Bool),
+            (BinOp::GreaterThan, Ty::Int) => (compare(CompareOp::Gt), Ty::Bool),
+            (BinOp::GreaterThanOrEqual, Ty::Int) => (compare(CompareOp::Ge), Ty::Bool),
+            (op, ty) => {
+                return Err(format!(
+                    "Operator '{}' can not be applied to values of type '{}'",
+                    op, ty
+                ))
+            }
+        };
+        let result = self.emit_value(inst, result_ty);
+        if assign {
+            match lhs {
+                Expression::Variable(name) => self.assign(name, result),
+                _ => return unsupported("Assignments to fields and elements"),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Evaluates the right side of `&&` and `||` only if the left side does not decide the result
+    fn short_circuit(
+        &mut self,
+        lhs: &Expression,
+        rhs: &Expression,
+        is_or: bool,
+    ) -> Result<Value, String> {
+        let lhs = self.expression(lhs)?;
+        // The value of the operation if the right side is skipped
+        let decided = self.emit_value(Inst::Bool(is_or), Ty::Bool);
+        let lhs_block = self
+            .current
+            .expect("expressions are evaluated in reachable blocks");
+        let rhs_block = self.new_block();
+        let end = self.new_block();
+        let (if_true, if_false) = if is_or {
+            (end, rhs_block)
+        } else {
+            (rhs_block, end)
+        };
+        self.terminate(Terminator::Branch(lhs, if_true, if_false));
+        self.seal(rhs_block)?;
+
+        self.switch_to(rhs_block);
+        let rhs = self.expression(rhs)?;
+        let rhs_block_end = self
+            .current
+            .expect("expressions are evaluated in reachable blocks");
+        self.jump(end);
+        self.seal(end)?;
+        self.switch_to(end);
+
+        let result = self.new_value(Ty::Bool);
+        self.block_mut(end).phis.push(Phi {
+            result,
+            incoming: vec![(lhs_block, decided), (rhs_block_end, rhs)],
+        });
+        Ok(result)
+    }
+}
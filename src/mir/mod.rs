@@ -0,0 +1,493 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Mid-level IR of the compiler. Functions are lowered from the AST into a control flow
+// graph of basic blocks, whose values are in SSA form: every value is defined exactly once,
+// and values that depend on the path taken through the graph are merged by phi nodes.
+//
+// The IR is an optional fast path of the QBE backend: functions that only use the features
+// the IR supports are generated from it and optimized on it, all others are generated from
+// the AST, like the functions of the other backends. Lowering a function reports the first
+// unsupported feature it finds, like structures, array literals or global variables.
+use std::collections::HashSet;
+use std::fmt;
+
+pub mod cfg;
+mod licm;
+mod lower;
+mod simplify;
+mod strength;
+#[cfg(test)]
+mod tests;
+
+use cfg::Cfg;
+pub use lower::lower_function;
+
+/// Runs the optimization passes enabled by the given level on the function.
+///
+/// Level 0 leaves the function as it was lowered.
+/// Level 1 merges chains of blocks that follow each other unconditionally.
+/// Level 2 additionally replaces multiplications by constants with shifts and additions,
+/// and moves computations that do not change inside a loop in front of it.
+pub fn optimize(func: &mut Function, level: u8) {
+    if level == 0 {
+        return;
+    }
+    if level >= 2 {
+        strength::reduce_multiplications(func);
+        licm::hoist_loop_invariants(func);
+    }
+    simplify::merge_blocks(func);
+}
+
+/// SSA value, defined by a function argument, an instruction or a phi
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub u32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// Index of a basic block in its function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+/// Type of a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Bool,
+    Str,
+    /// Array, whose elements have the type of the values they are loaded into
+    Array,
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ty::Int => "int",
+            Ty::Bool => "bool",
+            Ty::Str => "string",
+            Ty::Array => "array",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Arithmetic operation. Addition of strings concatenates them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// Shift to the left, which is only introduced by optimizations
+    Shl,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Rem => "rem",
+            BinaryOp::Shl => "shl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Comparison of two values. Strings can only be compared for equality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompareOp::Lt => "lt",
+            CompareOp::Le => "le",
+            CompareOp::Gt => "gt",
+            CompareOp::Ge => "ge",
+            CompareOp::Eq => "eq",
+            CompareOp::Ne => "ne",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Operation of an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Int(u64),
+    Bool(bool),
+    Str(String),
+    Binary(BinaryOp, Value, Value),
+    Compare(CompareOp, Value, Value),
+    /// Call of a function or a builtin by name
+    Call(String, Vec<Value>),
+    /// Number of elements of an array
+    Length(Value),
+    /// Element of an array at an index, which has to be within the bounds of the array
+    Load(Value, Value),
+    /// Replaces the element of an array at an index, which has to be within the bounds of the array
+    Store(Value, Value, Value),
+}
+
+impl Inst {
+    /// Values the instruction reads
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Inst::Int(_) | Inst::Bool(_) | Inst::Str(_) => Vec::new(),
+            Inst::Binary(_, lhs, rhs) | Inst::Compare(_, lhs, rhs) | Inst::Load(lhs, rhs) => {
+                vec![*lhs, *rhs]
+            }
+            Inst::Call(_, args) => args.clone(),
+            Inst::Length(array) => vec![*array],
+            Inst::Store(array, index, value) => vec![*array, *index, *value],
+        }
+    }
+
+    /// Mutable references to the values the instruction reads
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Inst::Int(_) | Inst::Bool(_) | Inst::Str(_) => Vec::new(),
+            Inst::Binary(_, lhs, rhs) | Inst::Compare(_, lhs, rhs) | Inst::Load(lhs, rhs) => {
+                vec![lhs, rhs]
+            }
+            Inst::Call(_, args) => args.iter_mut().collect(),
+            Inst::Length(array) => vec![array],
+            Inst::Store(array, index, value) => vec![array, index, value],
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::Int(value) => write!(f, "int {}", value),
+            Inst::Bool(value) => write!(f, "bool {}", value),
+            Inst::Str(value) => write!(f, "string {:?}", value),
+            Inst::Binary(op, lhs, rhs) => write!(f, "{} {}, {}", op, lhs, rhs),
+            Inst::Compare(op, lhs, rhs) => write!(f, "{} {}, {}", op, lhs, rhs),
+            Inst::Call(name, args) => write!(
+                f,
+                "call {}({})",
+                name,
+                args.iter()
+                    .map(|arg| arg.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Inst::Length(array) => write!(f, "length {}", array),
+            Inst::Load(array, index) => write!(f, "load {}, {}", array, index),
+            Inst::Store(array, index, value) => {
+                write!(f, "store {}, {}, {}", array, index, value)
+            }
+        }
+    }
+}
+
+/// Instruction with the value it defines. Calls of functions without a return value define none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub result: Option<Value>,
+    pub inst: Inst,
+}
+
+/// Selects the value of the predecessor that control came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phi {
+    pub result: Value,
+    pub incoming: Vec<(BlockId, Value)>,
+}
+
+/// Last instruction of a block, which transfers control
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Jumps to the first block if the value is true, and to the second one otherwise
+    Branch(Value, BlockId, BlockId),
+    /// Jumps to the block of the case that equals the integer, or to the last block if none does
+    Switch(Value, Vec<(u64, BlockId)>, BlockId),
+    Return(Option<Value>),
+    /// Control never reaches the end of the block
+    Unreachable,
+}
+
+impl Terminator {
+    /// Blocks control may continue in
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, if_true, if_false) => vec![*if_true, *if_false],
+            Terminator::Switch(_, cases, default) => cases
+                .iter()
+                .map(|(_, target)| *target)
+                .chain([*default])
+                .collect(),
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    /// Mutable references to the blocks control may continue in
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, if_true, if_false) => vec![if_true, if_false],
+            Terminator::Switch(_, cases, default) => cases
+                .iter_mut()
+                .map(|(_, target)| target)
+                .chain([default])
+                .collect(),
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    /// Values the terminator reads
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::Branch(cond, ..) | Terminator::Switch(cond, ..) => vec![*cond],
+            Terminator::Return(value) => value.iter().copied().collect(),
+            Terminator::Jump(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump {}", target),
+            Terminator::Branch(cond, if_true, if_false) => {
+                write!(f, "branch {}, {}, {}", cond, if_true, if_false)
+            }
+            Terminator::Switch(value, cases, default) => {
+                let cases: Vec<String> = cases
+                    .iter()
+                    .map(|(case, target)| format!("{}: {}", case, target))
+                    .collect();
+                write!(f, "switch {}, [{}], {}", value, cases.join(", "), default)
+            }
+            Terminator::Return(Some(value)) => write!(f, "return {}", value),
+            Terminator::Return(None) => write!(f, "return"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+/// Basic block. Phis are evaluated first, then the instructions in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+/// Function in SSA form. The first block is the entry of the function, no block jumps to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub arguments: Vec<Value>,
+    pub ret_type: Option<Ty>,
+    pub blocks: Vec<Block>,
+    /// Type of every value, indexed by the number of the value
+    pub types: Vec<Ty>,
+}
+
+impl Function {
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn ty(&self, value: Value) -> Ty {
+        self.types[value.0 as usize]
+    }
+
+    /// Allocates a value of the given type, which still has to be defined
+    pub fn new_value(&mut self, ty: Ty) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() as u32 - 1)
+    }
+
+    /// Checks the structural invariants of the IR: every value is defined once and before
+    /// it is used in its block, jumps target existing blocks other than the entry, and the
+    /// phis of a block have one incoming value per predecessor.
+    ///
+    /// Values used in other blocks are not checked to be dominated by their definition.
+    pub fn verify(&self) -> Result<(), String> {
+        if self.blocks.is_empty() {
+            return Err(format!("Function '{}' has no blocks", self.name));
+        }
+        let mut defined: HashSet<Value> = self.arguments.iter().copied().collect();
+        if defined.len() != self.arguments.len() {
+            return Err("Arguments must be distinct values".to_owned());
+        }
+        let mut define = |value: Value| {
+            if value.0 as usize >= self.types.len() {
+                Err(format!("{} has no type", value))
+            } else if !defined.insert(value) {
+                Err(format!("{} is defined twice", value))
+            } else {
+                Ok(())
+            }
+        };
+        for block in &self.blocks {
+            for phi in &block.phis {
+                define(phi.result)?;
+            }
+            for inst in &block.instructions {
+                if let Some(result) = inst.result {
+                    define(result)?;
+                }
+            }
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.terminator.successors() {
+                if succ.0 as usize >= self.blocks.len() {
+                    return Err(format!(
+                        "{} jumps to undefined block {}",
+                        BlockId(i as u32),
+                        succ
+                    ));
+                }
+            }
+        }
+        let cfg = Cfg::new(self);
+        if !cfg.predecessors(BlockId(0)).is_empty() {
+            return Err("The entry block can not be jumped to".to_owned());
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            let mut local: HashSet<Value> = self.arguments.iter().copied().collect();
+            for phi in &block.phis {
+                let mut sources: Vec<BlockId> = phi.incoming.iter().map(|(b, _)| *b).collect();
+                let mut expected = cfg.predecessors(id).to_vec();
+                sources.sort();
+                expected.sort();
+                if sources != expected {
+                    return Err(format!(
+                        "Phi {} in {} does not match the predecessors of the block",
+                        phi.result, id
+                    ));
+                }
+                if let Some((_, value)) = phi.incoming.iter().find(|(_, v)| !defined.contains(v)) {
+                    return Err(format!("{} is used but never defined", value));
+                }
+                local.insert(phi.result);
+            }
+            for inst in &block.instructions {
+                check_uses(&inst.inst.operands(), &defined, &local, &self.blocks, id)?;
+                local.extend(inst.result);
+            }
+            check_uses(
+                &block.terminator.operands(),
+                &defined,
+                &local,
+                &self.blocks,
+                id,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Values that are defined in the same block have to be defined before they are used
+fn check_uses(
+    uses: &[Value],
+    defined: &HashSet<Value>,
+    local: &HashSet<Value>,
+    blocks: &[Block],
+    block: BlockId,
+) -> Result<(), String> {
+    let own = &blocks[block.0 as usize];
+    for value in uses {
+        if !defined.contains(value) {
+            return Err(format!("{} is used but never defined", value));
+        }
+        let defined_here = own.phis.iter().any(|phi| phi.result == *value)
+            || own
+                .instructions
+                .iter()
+                .any(|inst| inst.result == Some(*value));
+        if defined_here && !local.contains(value) {
+            return Err(format!(
+                "{} is used before its definition in {}",
+                value, block
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fn {}({})",
+            self.name,
+            self.arguments
+                .iter()
+                .map(|arg| format!("{}: {}", arg, self.ty(*arg)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )?;
+        if let Some(ty) = self.ret_type {
+            write!(f, ": {}", ty)?;
+        }
+        writeln!(f, " {{")?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i as u32))?;
+            for phi in &block.phis {
+                writeln!(
+                    f,
+                    "    {} = phi {} {}",
+                    phi.result,
+                    self.ty(phi.result),
+                    phi.incoming
+                        .iter()
+                        .map(|(block, value)| format!("[{}: {}]", block, value))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )?;
+            }
+            for inst in &block.instructions {
+                match inst.result {
+                    Some(result) => writeln!(f, "    {} = {}", result, inst.inst)?,
+                    None => writeln!(f, "    {}", inst.inst)?,
+                }
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        write!(f, "}}")
+    }
+}
@@ -0,0 +1,222 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Passes that simplify the control flow graph of a function, without changing what it computes
+use super::cfg::Cfg;
+use super::{BlockId, Function, Inst, Terminator, Value};
+use std::collections::HashMap;
+
+/// Replaces branches on constant conditions and switches on constant integers with jumps.
+/// The phis of the blocks that are no longer jumped to forget the value of the jumping block.
+pub fn fold_constant_branches(func: &mut Function) -> bool {
+    let constants: HashMap<Value, u64> = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|inst| match (inst.result, &inst.inst) {
+            (Some(result), Inst::Bool(value)) => Some((result, *value as u64)),
+            (Some(result), Inst::Int(value)) => Some((result, *value)),
+            _ => None,
+        })
+        .collect();
+
+    let mut changed = false;
+    for i in 0..func.blocks.len() {
+        let target = match &func.blocks[i].terminator {
+            Terminator::Branch(cond, if_true, if_false) => match constants.get(cond) {
+                Some(0) => *if_false,
+                Some(_) => *if_true,
+                None => continue,
+            },
+            Terminator::Switch(value, cases, default) => match constants.get(value) {
+                Some(value) => cases
+                    .iter()
+                    .find(|(case, _)| case == value)
+                    .map_or(*default, |(_, target)| *target),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let block = BlockId(i as u32);
+        for skipped in func.blocks[i].terminator.successors() {
+            if skipped != target {
+                for phi in &mut func.blocks[skipped.0 as usize].phis {
+                    phi.incoming.retain(|(pred, _)| *pred != block);
+                }
+            }
+        }
+        func.blocks[i].terminator = Terminator::Jump(target);
+        changed = true;
+    }
+    changed
+}
+
+/// Removes blocks that can not be reached from the entry and renumbers the others
+pub fn remove_unreachable_blocks(func: &mut Function) -> bool {
+    let cfg = Cfg::new(func);
+    if cfg.reverse_postorder().len() == func.blocks.len() {
+        return false;
+    }
+
+    let mut ids = HashMap::new();
+    let blocks = std::mem::take(&mut func.blocks);
+    for (i, block) in blocks.into_iter().enumerate() {
+        if cfg.is_reachable(BlockId(i as u32)) {
+            ids.insert(BlockId(i as u32), BlockId(func.blocks.len() as u32));
+            func.blocks.push(block);
+        }
+    }
+    for block in &mut func.blocks {
+        for phi in &mut block.phis {
+            phi.incoming.retain(|(pred, _)| ids.contains_key(pred));
+        }
+    }
+    rename_blocks(func, |id| ids[&id]);
+    true
+}
+
+/// Merges blocks into their only predecessor, if the predecessor jumps to no other block
+pub fn merge_blocks(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let cfg = Cfg::new(func);
+        let chain = (0..func.blocks.len()).find_map(|i| {
+            let block = BlockId(i as u32);
+            match func.blocks[i].terminator {
+                Terminator::Jump(next)
+                    if next != block && next.0 != 0 && cfg.predecessors(next) == [block] =>
+                {
+                    Some((block, next))
+                }
+                _ => None,
+            }
+        });
+        let (block, next) = match chain {
+            Some(chain) => chain,
+            None => break,
+        };
+
+        // The phis of a block with a single predecessor can only select one value
+        let merged = std::mem::replace(
+            &mut func.blocks[next.0 as usize],
+            super::Block {
+                phis: Vec::new(),
+                instructions: Vec::new(),
+                terminator: Terminator::Unreachable,
+            },
+        );
+        let replacements: HashMap<Value, Value> = merged
+            .phis
+            .iter()
+            .map(|phi| (phi.result, phi.incoming[0].1))
+            .collect();
+        let target = &mut func.blocks[block.0 as usize];
+        target.instructions.extend(merged.instructions);
+        target.terminator = merged.terminator;
+        replace_uses(func, &replacements);
+        // The successors of the merged block are now entered from the block it was merged into
+        for succ in func.blocks[block.0 as usize].terminator.successors() {
+            for phi in &mut func.blocks[succ.0 as usize].phis {
+                for (pred, _) in &mut phi.incoming {
+                    if *pred == next {
+                        *pred = block;
+                    }
+                }
+            }
+        }
+        remove_unreachable_blocks(func);
+        changed = true;
+    }
+    changed
+}
+
+/// Removes phis that select the same value on every path, or only themselves,
+/// and replaces their uses with that value
+pub fn remove_trivial_phis(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut replacements: HashMap<Value, Value> = HashMap::new();
+        for block in &func.blocks {
+            for phi in &block.phis {
+                let mut sources = phi
+                    .incoming
+                    .iter()
+                    .map(|(_, value)| *value)
+                    .filter(|value| *value != phi.result);
+                if let Some(first) = sources.next() {
+                    if sources.all(|value| value == first) {
+                        replacements.insert(phi.result, first);
+                    }
+                }
+            }
+        }
+        if replacements.is_empty() {
+            return changed;
+        }
+
+        for block in &mut func.blocks {
+            block
+                .phis
+                .retain(|phi| !replacements.contains_key(&phi.result));
+        }
+        replace_uses(func, &replacements);
+        changed = true;
+    }
+}
+
+/// Replaces every use of a value with its replacement
+pub(super) fn replace_uses(func: &mut Function, replacements: &HashMap<Value, Value>) {
+    if replacements.is_empty() {
+        return;
+    }
+    let resolve = |mut value: Value| {
+        while let Some(replacement) = replacements.get(&value) {
+            value = *replacement;
+        }
+        value
+    };
+    for block in &mut func.blocks {
+        for phi in &mut block.phis {
+            phi.incoming
+                .iter_mut()
+                .for_each(|(_, value)| *value = resolve(*value));
+        }
+        for inst in &mut block.instructions {
+            for value in inst.inst.operands_mut() {
+                *value = resolve(*value);
+            }
+        }
+        match &mut block.terminator {
+            Terminator::Branch(value, ..)
+            | Terminator::Switch(value, ..)
+            | Terminator::Return(Some(value)) => *value = resolve(*value),
+            _ => {}
+        }
+    }
+}
+
+/// Renames the blocks that are jumped to and that phis refer to
+fn rename_blocks(func: &mut Function, rename: impl Fn(BlockId) -> BlockId) {
+    for block in &mut func.blocks {
+        for phi in &mut block.phis {
+            phi.incoming
+                .iter_mut()
+                .for_each(|(pred, _)| *pred = rename(*pred));
+        }
+        for target in block.terminator.successors_mut() {
+            *target = rename(*target);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Strength reduction: multiplications by constants are replaced with cheaper shifts and additions
+use super::simplify::replace_uses;
+use super::{BinaryOp, Function, Inst, Instruction, Ty, Value};
+use std::collections::HashMap;
+
+/// Replaces multiplications of integers by a constant that is a power of two, or one more
+/// or less than a power of two, with shifts and additions. Multiplications by zero and one
+/// are removed. Integers of the IR are 32 bits wide in native code, so the shift amount
+/// has to stay below 32.
+pub fn reduce_multiplications(func: &mut Function) -> bool {
+    let constants: HashMap<Value, u64> = func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|inst| match (inst.result, &inst.inst) {
+            (Some(result), Inst::Int(value)) => Some((result, *value)),
+            _ => None,
+        })
+        .collect();
+
+    let mut changed = false;
+    let mut replacements = HashMap::new();
+    for i in 0..func.blocks.len() {
+        let instructions = std::mem::take(&mut func.blocks[i].instructions);
+        let mut reduced = Vec::with_capacity(instructions.len());
+        for inst in instructions {
+            let (result, value, factor) = match (inst.result, &inst.inst) {
+                (Some(result), Inst::Binary(BinaryOp::Mul, lhs, rhs))
+                    if func.ty(result) == Ty::Int =>
+                {
+                    match (constants.get(lhs), constants.get(rhs)) {
+                        (_, Some(factor)) => (result, *lhs, *factor),
+                        (Some(factor), None) => (result, *rhs, *factor),
+                        (None, None) => {
+                            reduced.push(inst);
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    reduced.push(inst);
+                    continue;
+                }
+            };
+
+            let define = |inst| Instruction {
+                result: Some(result),
+                inst,
+            };
+            match factor {
+                0 => reduced.push(define(Inst::Int(0))),
+                1 => {
+                    replacements.insert(result, value);
+                }
+                _ => match decompose(factor) {
+                    Some((amount, rest)) => {
+                        let amount_value = func.new_value(Ty::Int);
+                        reduced.push(Instruction {
+                            result: Some(amount_value),
+                            inst: Inst::Int(amount as u64),
+                        });
+                        let shift = Inst::Binary(BinaryOp::Shl, value, amount_value);
+                        match rest {
+                            None => reduced.push(define(shift)),
+                            Some(op) => {
+                                let shifted = func.new_value(Ty::Int);
+                                reduced.push(Instruction {
+                                    result: Some(shifted),
+                                    inst: shift,
+                                });
+                                reduced.push(define(Inst::Binary(op, shifted, value)));
+                            }
+                        }
+                    }
+                    None => {
+                        reduced.push(inst);
+                        continue;
+                    }
+                },
+            }
+            changed = true;
+        }
+        func.blocks[i].instructions = reduced;
+    }
+
+    replace_uses(func, &replacements);
+    changed
+}
+
+/// Splits a factor into a shift to the left, followed by adding or subtracting the
+/// shifted value once more
+fn decompose(factor: u64) -> Option<(u32, Option<BinaryOp>)> {
+    let candidates = [
+        (Some(factor), None),
+        (factor.checked_sub(1), Some(BinaryOp::Add)),
+        (factor.checked_add(1), Some(BinaryOp::Sub)),
+    ];
+    candidates.iter().find_map(|&(shifted, op)| {
+        let shifted = shifted.filter(|shifted| shifted.is_power_of_two())?;
+        let amount = shifted.trailing_zeros();
+        (1..32).contains(&amount).then_some((amount, op))
+    })
+}
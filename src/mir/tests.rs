@@ -0,0 +1,648 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::*;
+use crate::lexer::tokenize;
+use crate::parser::parse;
+use crate::semantic;
+use crate::semantic::types::TypeEnv;
+use cfg::Dominators;
+use std::collections::HashMap;
+
+fn lower_raw(raw: &str, name: &str) -> Result<Function, String> {
+    let tokens = tokenize(raw).unwrap();
+    let mut module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    semantic::analyze(&mut module);
+    semantic::check(&mut module).unwrap();
+    let mut types = TypeEnv::new(&module);
+    let func = module.func.iter().find(|f| f.name == name).unwrap();
+    lower_function(func, &mut types)
+}
+
+/// Runs a function that only does arithmetic on integers
+fn eval(func: &Function, args: &[u64]) -> u64 {
+    eval_with_arrays(func, args, &mut [])
+}
+
+/// Runs a function that does arithmetic on integers and arrays of them.
+/// Arrays are passed as their index in `arrays`.
+fn eval_with_arrays(func: &Function, args: &[u64], arrays: &mut [Vec<u64>]) -> u64 {
+    let mut values: HashMap<Value, u64> = func
+        .arguments
+        .iter()
+        .copied()
+        .zip(args.iter().copied())
+        .collect();
+    let mut prev = BlockId(0);
+    let mut current = BlockId(0);
+    loop {
+        let block = func.block(current);
+        let incoming: Vec<(Value, u64)> = block
+            .phis
+            .iter()
+            .map(|phi| {
+                let (_, value) = phi.incoming.iter().find(|(b, _)| *b == prev).unwrap();
+                (phi.result, values[value])
+            })
+            .collect();
+        values.extend(incoming);
+        for inst in &block.instructions {
+            let value = match &inst.inst {
+                Inst::Int(value) => *value,
+                Inst::Bool(value) => *value as u64,
+                Inst::Binary(op, lhs, rhs) => {
+                    let (lhs, rhs) = (values[lhs], values[rhs]);
+                    match op {
+                        BinaryOp::Add => lhs.wrapping_add(rhs),
+                        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                        BinaryOp::Div => lhs / rhs,
+                        BinaryOp::Rem => lhs % rhs,
+                        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    }
+                }
+                Inst::Compare(op, lhs, rhs) => {
+                    let (lhs, rhs) = (values[lhs], values[rhs]);
+                    let result = match op {
+                        CompareOp::Lt => lhs < rhs,
+                        CompareOp::Le => lhs <= rhs,
+                        CompareOp::Gt => lhs > rhs,
+                        CompareOp::Ge => lhs >= rhs,
+                        CompareOp::Eq => lhs == rhs,
+                        CompareOp::Ne => lhs != rhs,
+                    };
+                    result as u64
+                }
+                Inst::Length(array) => arrays[values[array] as usize].len() as u64,
+                Inst::Load(array, index) => arrays[values[array] as usize][values[index] as usize],
+                Inst::Store(array, index, value) => {
+                    arrays[values[array] as usize][values[index] as usize] = values[value];
+                    continue;
+                }
+                other => panic!("Can not evaluate {}", other),
+            };
+            values.insert(inst.result.unwrap(), value);
+        }
+        prev = current;
+        current = match &block.terminator {
+            Terminator::Jump(target) => *target,
+            Terminator::Branch(cond, if_true, if_false) => {
+                if values[cond] != 0 {
+                    *if_true
+                } else {
+                    *if_false
+                }
+            }
+            Terminator::Switch(value, cases, default) => cases
+                .iter()
+                .find(|(case, _)| *case == values[value])
+                .map_or(*default, |(_, target)| *target),
+            Terminator::Return(value) => return value.map(|v| values[&v]).unwrap_or(0),
+            Terminator::Unreachable => panic!("Reached unreachable code"),
+        };
+    }
+}
+
+#[test]
+fn test_lower_straight_line_code() {
+    let raw = "
+    fn double(x: int): int {
+        let y = x + x
+        return y
+    }
+    ";
+    let func = lower_raw(raw, "double").unwrap();
+    assert_eq!(
+        func.to_string(),
+        "fn double(v0: int): int {\nb0:\n    v1 = add v0, v0\n    return v1\n}"
+    );
+}
+
+#[test]
+fn test_lower_merges_values_with_phis() {
+    let raw = "
+    fn max(a: int, b: int): int {
+        let result = a
+        if b > a {
+            result = b
+        }
+        return result
+    }
+    ";
+    let func = lower_raw(raw, "max").unwrap();
+    assert_eq!(
+        func.to_string(),
+        "fn max(v0: int, v1: int): int {
+b0:
+    v2 = gt v1, v0
+    branch v2, b1, b2
+b1:
+    jump b2
+b2:
+    v3 = phi int [b0: v0], [b1: v1]
+    return v3
+}"
+    );
+    assert_eq!(eval(&func, &[3, 7]), 7);
+    assert_eq!(eval(&func, &[7, 3]), 7);
+}
+
+#[test]
+fn test_lower_loops() {
+    let raw = "
+    fn sum(n: int): int {
+        let total = 0
+        let i = 0
+        while i < n {
+            i += 1
+            if i == 3 {
+                continue
+            }
+            total += i
+        }
+        return total
+    }
+    ";
+    let func = lower_raw(raw, "sum").unwrap();
+    func.verify().unwrap();
+    // The loop header merges the values of both variables
+    assert_eq!(func.blocks[1].phis.len(), 2);
+    assert_eq!(eval(&func, &[5]), 1 + 2 + 4 + 5);
+    assert_eq!(eval(&func, &[0]), 0);
+}
+
+#[test]
+fn test_lower_short_circuit() {
+    let raw = "
+    fn both(a: int, b: int): bool {
+        return a > 0 && b / a > 1
+    }
+    ";
+    let func = lower_raw(raw, "both").unwrap();
+    // The division by zero is skipped
+    assert_eq!(eval(&func, &[0, 4]), 0);
+    assert_eq!(eval(&func, &[2, 4]), 1);
+    assert_eq!(eval(&func, &[2, 2]), 0);
+}
+
+#[test]
+fn test_lower_tail_calls_to_loops() {
+    let raw = "
+    fn gcd(a: int, b: int): int {
+        if b == 0 {
+            return a
+        }
+        return gcd(b, a % b)
+    }
+    ";
+    let func = lower_raw(raw, "gcd").unwrap();
+    assert!(func
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .all(|inst| !matches!(inst.inst, Inst::Call(..))));
+    assert_eq!(eval(&func, &[48, 18]), 6);
+}
+
+#[test]
+fn test_lower_skips_unreachable_code() {
+    let raw = "
+    fn first(): int {
+        while true {
+            return 1
+            println(\"never\")
+        }
+        return 2
+    }
+    ";
+    let func = lower_raw(raw, "first").unwrap();
+    assert!(!func.to_string().contains("never"));
+    assert_eq!(eval(&func, &[]), 1);
+}
+
+#[test]
+fn test_lower_match_statements() {
+    let raw = "
+    fn classify(n: int): int {
+        let result = 0
+        match n {
+            1 => result = 10
+            5 => result = 50
+            else => result = 99
+        }
+        return result
+    }
+    ";
+    let func = lower_raw(raw, "classify").unwrap();
+    let printed = func.to_string();
+    assert!(
+        printed.contains("switch v0, [1: b2, 5: b3], b4"),
+        "{}",
+        printed
+    );
+    assert_eq!(eval(&func, &[1]), 10);
+    assert_eq!(eval(&func, &[5]), 50);
+    assert_eq!(eval(&func, &[7]), 99);
+
+    // Without an `else` arm, the last case of an exhaustive match is not compared
+    let raw = "
+    fn invert(b: bool): bool {
+        match b {
+            true => return false
+            false => return true
+        }
+    }
+    ";
+    let func = lower_raw(raw, "invert").unwrap();
+    assert_eq!(eval(&func, &[1]), 0);
+    assert_eq!(eval(&func, &[0]), 1);
+
+    let raw = "
+    fn pick(): int {
+        match 5 {
+            1 => return 10
+            5 => return 50
+            else => return 99
+        }
+    }
+    ";
+    let func = lower_raw(raw, "pick").unwrap();
+    assert!(!func.to_string().contains("switch"));
+    assert_eq!(eval(&func, &[]), 50);
+}
+
+#[test]
+fn test_lower_rejects_unsupported_features() {
+    let raw = "
+    fn main() {
+        let xs = [1, 2, 3]
+        println(xs[0])
+    }
+    ";
+    assert_eq!(
+        lower_raw(raw, "main").unwrap_err(),
+        "Array literals are not supported by the IR"
+    );
+}
+
+#[test]
+fn test_lower_arrays() {
+    let raw = "
+    fn sum(xs: int[]): int {
+        let total = 0
+        for x in xs {
+            total += x
+        }
+        return total
+    }
+
+    fn double(xs: int[]) {
+        let i = 0
+        while i < len(xs) {
+            xs[i] = xs[i] * 2
+            i += 1
+        }
+    }
+    ";
+    let sum = lower_raw(raw, "sum").unwrap();
+    assert_eq!(
+        sum.to_string(),
+        "fn sum(v0: array): int {
+b0:
+    v1 = int 0
+    v2 = int 0
+    jump b1
+b1:
+    v3 = phi int [b0: v2], [b2: v8]
+    v9 = phi int [b0: v1], [b2: v10]
+    v4 = length v0
+    v5 = lt v3, v4
+    branch v5, b2, b3
+b2:
+    v6 = load v0, v3
+    v7 = int 1
+    v8 = add v3, v7
+    v10 = add v9, v6
+    jump b1
+b3:
+    return v9
+}"
+    );
+    let mut arrays = [vec![1, 2, 3]];
+    assert_eq!(eval_with_arrays(&sum, &[0], &mut arrays), 6);
+
+    let double = lower_raw(raw, "double").unwrap();
+    eval_with_arrays(&double, &[0], &mut arrays);
+    assert_eq!(arrays[0], [2, 4, 6]);
+}
+
+#[test]
+fn test_verify_rejects_invalid_functions() {
+    let mut func = lower_raw("fn one(): int { return 1 }", "one").unwrap();
+    func.blocks[0].terminator = Terminator::Return(Some(Value(7)));
+    assert_eq!(func.verify().unwrap_err(), "v7 is used but never defined");
+
+    func.blocks[0].terminator = Terminator::Jump(BlockId(0));
+    assert_eq!(
+        func.verify().unwrap_err(),
+        "The entry block can not be jumped to"
+    );
+}
+
+#[test]
+fn test_lower_folds_constant_branches() {
+    let raw = "
+    fn pick(n: int): int {
+        let fast = true
+        if fast {
+            n = n * 2
+        } else {
+            n = n + 1
+        }
+        return n
+    }
+    ";
+    let mut func = lower_raw(raw, "pick").unwrap();
+    assert_eq!(
+        func.to_string(),
+        "fn pick(v0: int): int {
+b0:
+    v1 = bool true
+    jump b1
+b1:
+    v2 = int 2
+    v3 = mul v0, v2
+    jump b2
+b2:
+    return v3
+}"
+    );
+
+    optimize(&mut func, 1);
+    assert_eq!(
+        func.to_string(),
+        "fn pick(v0: int): int {
+b0:
+    v1 = bool true
+    v2 = int 2
+    v3 = mul v0, v2
+    return v3
+}"
+    );
+    assert_eq!(eval(&func, &[4]), 8);
+}
+
+#[test]
+fn test_cfg_edges() {
+    let raw = "
+    fn count(n: int): int {
+        let i = 0
+        while i < n {
+            i += 1
+        }
+        return i
+    }
+    ";
+    let func = lower_raw(raw, "count").unwrap();
+    let cfg = Cfg::new(&func);
+    assert_eq!(cfg.predecessors(BlockId(1)), &[BlockId(0), BlockId(2)]);
+    assert_eq!(cfg.successors(BlockId(1)), &[BlockId(2), BlockId(3)]);
+    assert_eq!(
+        cfg.reverse_postorder(),
+        &[BlockId(0), BlockId(1), BlockId(3), BlockId(2)]
+    );
+    assert!(cfg.is_reachable(BlockId(3)));
+}
+
+#[test]
+fn test_cfg_dominators_and_loops() {
+    let raw = "
+    fn count(n: int): int {
+        let i = 0
+        while i < n {
+            if i > 3 {
+                i += 2
+            }
+            i += 1
+        }
+        return i
+    }
+    ";
+    let func = lower_raw(raw, "count").unwrap();
+    let cfg = Cfg::new(&func);
+    let doms = Dominators::new(&cfg);
+    assert_eq!(doms.idom(BlockId(0)), None);
+    assert_eq!(doms.idom(BlockId(1)), Some(BlockId(0)));
+    assert_eq!(doms.idom(BlockId(3)), Some(BlockId(1)));
+    assert!(doms.dominates(BlockId(1), BlockId(4)));
+    assert!(!doms.dominates(BlockId(4), BlockId(1)));
+
+    let loops = cfg::loops(&cfg, &doms);
+    assert_eq!(loops.len(), 1);
+    assert_eq!(loops[0].header, BlockId(1));
+    assert!(loops[0].contains(BlockId(2)));
+    assert!(!loops[0].contains(BlockId(3)));
+}
+
+#[test]
+fn test_optimize_reduces_multiplications() {
+    let raw = "
+    fn scale(x: int): int {
+        return x * 1 + 9 * x + x * 10
+    }
+    ";
+    let mut func = lower_raw(raw, "scale").unwrap();
+    optimize(&mut func, 2);
+    assert_eq!(
+        func.to_string(),
+        "fn scale(v0: int): int {
+b0:
+    v1 = int 1
+    v3 = int 9
+    v9 = int 3
+    v10 = shl v0, v9
+    v4 = add v10, v0
+    v5 = add v0, v4
+    v6 = int 10
+    v7 = mul v0, v6
+    v8 = add v5, v7
+    return v8
+}"
+    );
+    assert_eq!(eval(&func, &[3]), 60);
+}
+
+#[test]
+fn test_optimize_hoists_loop_invariants() {
+    let raw = "
+    fn sum(n: int, m: int): int {
+        let total = 0
+        let i = 0
+        while i < n {
+            let j = 0
+            while j < n {
+                total += m * 8 + i * 3 + j
+                j += 1
+            }
+            i += 1
+        }
+        return total
+    }
+    ";
+    let lowered = lower_raw(raw, "sum").unwrap();
+    let mut func = lowered.clone();
+    optimize(&mut func, 2);
+    func.verify().unwrap();
+    for args in [[0, 5], [3, 2], [5, 7]] {
+        assert_eq!(eval(&func, &args), eval(&lowered, &args));
+    }
+
+    let cfg = Cfg::new(&func);
+    let doms = Dominators::new(&cfg);
+    let loops = cfg::loops(&cfg, &doms);
+    let (inner, outer) = (&loops[0], &loops[1]);
+    let shifts = |l: &cfg::Loop| {
+        l.body
+            .iter()
+            .flat_map(|block| &func.block(*block).instructions)
+            .filter(|inst| matches!(inst.inst, Inst::Binary(BinaryOp::Shl, ..)))
+            .count()
+    };
+    // `m * 8` moves out of both loops, and `i * 3` out of the inner one
+    assert_eq!(shifts(inner), 0);
+    assert_eq!(shifts(outer), 1);
+}
+
+#[test]
+fn test_optimize_array_loops() {
+    let raw = "
+    fn scale(xs: int[], factor: int): int {
+        let total = 0
+        for x in xs {
+            total += x * 8 + factor * 3
+        }
+        return total
+    }
+
+    fn scale_and_print(xs: int[]) {
+        for x in xs {
+            println(x * 4)
+        }
+    }
+    ";
+    let lowered = lower_raw(raw, "scale").unwrap();
+    let mut func = lowered.clone();
+    optimize(&mut func, 2);
+    func.verify().unwrap();
+    let mut arrays = [vec![], vec![1, 2, 3]];
+    for args in [[0, 5], [1, 2]] {
+        assert_eq!(
+            eval_with_arrays(&func, &args, &mut arrays),
+            eval_with_arrays(&lowered, &args, &mut arrays)
+        );
+    }
+
+    let count = |func: &Function, l: &cfg::Loop, matches: fn(&Inst) -> bool| {
+        l.body
+            .iter()
+            .flat_map(|block| &func.block(*block).instructions)
+            .filter(|inst| matches(&inst.inst))
+            .count()
+    };
+    let is_length = |inst: &Inst| matches!(inst, Inst::Length(_));
+    let is_mul = |inst: &Inst| matches!(inst, Inst::Binary(BinaryOp::Mul, ..));
+    let is_shift = |inst: &Inst| matches!(inst, Inst::Binary(BinaryOp::Shl, ..));
+    let cfg = Cfg::new(&func);
+    let loops = cfg::loops(&cfg, &Dominators::new(&cfg));
+    // The length of the array and `factor * 3` are computed once, `x * 8` is a shift
+    assert_eq!(count(&func, &loops[0], is_length), 0);
+    assert_eq!(count(&func, &loops[0], is_mul), 0);
+    assert_eq!(count(&func, &loops[0], is_shift), 1);
+
+    // Functions that are called in the loop could change the length of the array
+    let mut func = lower_raw(raw, "scale_and_print").unwrap();
+    optimize(&mut func, 2);
+    let cfg = Cfg::new(&func);
+    let loops = cfg::loops(&cfg, &Dominators::new(&cfg));
+    assert_eq!(count(&func, &loops[0], is_length), 1);
+}
+
+#[test]
+fn test_optimize_inserts_preheaders() {
+    // The loop in b1 is entered from both b0 and b2
+    let block = |phis, instructions, terminator| Block {
+        phis,
+        instructions,
+        terminator,
+    };
+    let inst = |result, inst| Instruction {
+        result: Some(Value(result)),
+        inst,
+    };
+    let mut func = Function {
+        name: "entered_twice".into(),
+        arguments: vec![Value(0), Value(1)],
+        ret_type: Some(Ty::Int),
+        blocks: vec![
+            block(
+                Vec::new(),
+                vec![inst(2, Inst::Int(0)), inst(3, Inst::Int(7))],
+                Terminator::Branch(Value(1), BlockId(1), BlockId(2)),
+            ),
+            block(
+                vec![Phi {
+                    result: Value(4),
+                    incoming: vec![
+                        (BlockId(0), Value(2)),
+                        (BlockId(2), Value(3)),
+                        (BlockId(1), Value(6)),
+                    ],
+                }],
+                vec![
+                    inst(5, Inst::Binary(BinaryOp::Add, Value(0), Value(3))),
+                    inst(6, Inst::Binary(BinaryOp::Add, Value(4), Value(5))),
+                    inst(7, Inst::Compare(CompareOp::Lt, Value(6), Value(0))),
+                ],
+                Terminator::Branch(Value(7), BlockId(1), BlockId(3)),
+            ),
+            block(Vec::new(), Vec::new(), Terminator::Jump(BlockId(1))),
+            block(Vec::new(), Vec::new(), Terminator::Return(Some(Value(6)))),
+        ],
+        types: vec![
+            Ty::Int,
+            Ty::Bool,
+            Ty::Int,
+            Ty::Int,
+            Ty::Int,
+            Ty::Int,
+            Ty::Int,
+            Ty::Bool,
+        ],
+    };
+    func.verify().unwrap();
+    let original = func.clone();
+
+    optimize(&mut func, 2);
+    func.verify().unwrap();
+    let header = func
+        .blocks
+        .iter()
+        .find(|block| !block.phis.is_empty() && block.phis[0].incoming.len() == 2)
+        .unwrap();
+    assert_eq!(header.instructions.len(), 2);
+    for args in [[20, 0], [20, 1], [0, 1]] {
+        assert_eq!(eval(&func, &args), eval(&original, &args));
+    }
+}
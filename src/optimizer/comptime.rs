@@ -0,0 +1,554 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::COMPTIME;
+use crate::ast::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Number of statements and calls a call may evaluate, so that loops that do not end are reported
+const MAX_STEPS: usize = 1_000_000;
+/// Number of calls that may be nested, so that unbounded recursion does not overflow the stack
+const MAX_DEPTH: usize = 200;
+
+/// Replaces calls of functions annotated with `@comptime` whose arguments are constant
+/// by the value they return. `semantic::check` ensures that these functions only compute
+/// with integers, booleans, strings and arrays. Integers are 32 bits wide, like in native code.
+pub(super) fn evaluate_calls(module: &mut Module) -> Result<(), String> {
+    let functions: HashMap<String, Function> = module
+        .func
+        .iter()
+        .filter(|func| func.has_attribute(COMPTIME))
+        .map(|func| (func.name.clone(), func.clone()))
+        .collect();
+    if functions.is_empty() {
+        return Ok(());
+    }
+
+    let methods = module.structs.iter_mut().flat_map(|def| &mut def.methods);
+    for func in module.func.iter_mut().chain(methods) {
+        replace_in_statement(&mut func.body, &functions)?;
+    }
+    for global in &mut module.globals {
+        replace_in_expression(&mut global.value, &functions)?;
+    }
+    Ok(())
+}
+
+fn replace_in_statement(
+    statement: &mut Statement,
+    functions: &HashMap<String, Function>,
+) -> Result<(), String> {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .try_for_each(|s| replace_in_statement(s, functions)),
+        Statement::Declare(_, Some(expr))
+        | Statement::Static(_, expr)
+        | Statement::Destructure(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => replace_in_expression(expr, functions),
+        Statement::Assign(lhs, rhs) => {
+            replace_in_expression(lhs, functions)?;
+            replace_in_expression(rhs, functions)
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            replace_in_expression(cond, functions)?;
+            replace_in_statement(if_branch, functions)?;
+            match else_branch {
+                Some(else_branch) => replace_in_statement(else_branch, functions),
+                None => Ok(()),
+            }
+        }
+        Statement::While(expr, body)
+        | Statement::For(_, expr, body)
+        | Statement::ForPair(_, _, expr, body) => {
+            replace_in_expression(expr, functions)?;
+            replace_in_statement(body, functions)
+        }
+        Statement::Match(subject, arms) => {
+            replace_in_expression(subject, functions)?;
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        replace_in_expression(expr, functions)?;
+                        replace_in_statement(s, functions)?;
+                    }
+                    MatchArm::Else(s) => replace_in_statement(s, functions)?,
+                }
+            }
+            Ok(())
+        }
+        Statement::Unsafe(body) => replace_in_statement(body, functions),
+        _ => Ok(()),
+    }
+}
+
+fn replace_in_expression(
+    expr: &mut Expression,
+    functions: &HashMap<String, Function>,
+) -> Result<(), String> {
+    match expr {
+        Expression::FunctionCall(name, args) => {
+            for arg in args.iter_mut() {
+                replace_in_expression(arg, functions)?;
+            }
+            if let Some(value) = evaluate_call(name, args, functions)? {
+                *expr = value.into_expression();
+            }
+            Ok(())
+        }
+        Expression::Array(_, elements) => elements
+            .iter_mut()
+            .try_for_each(|e| replace_in_expression(e, functions)),
+        Expression::ArrayAccess(_, operand)
+        | Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => replace_in_expression(operand, functions),
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => {
+            replace_in_expression(lhs, functions)?;
+            replace_in_expression(rhs, functions)
+        }
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .try_for_each(|e| replace_in_expression(e, functions)),
+        Expression::StructUpdate(_, base, fields) => {
+            replace_in_expression(base, functions)?;
+            fields
+                .values_mut()
+                .try_for_each(|e| replace_in_expression(e, functions))
+        }
+        Expression::Map(entries) => entries.iter_mut().try_for_each(|(key, value)| {
+            replace_in_expression(key, functions)?;
+            replace_in_expression(value, functions)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the value of a call of a `@comptime` function,
+/// or `None` if it is not one or its arguments are only known at runtime
+fn evaluate_call(
+    name: &str,
+    args: &[Expression],
+    functions: &HashMap<String, Function>,
+) -> Result<Option<Value>, String> {
+    if !functions.contains_key(name) {
+        return Ok(None);
+    }
+    let mut evaluator = Evaluator {
+        functions,
+        steps: 0,
+        depth: 0,
+    };
+    let mut frame = HashMap::new();
+    let result = args
+        .iter()
+        .map(|arg| evaluator.expression(arg, &mut frame))
+        .collect::<Result<Vec<Value>, Failure>>()
+        .and_then(|args| evaluator.call(name, args));
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(Failure::Unknown) => Ok(None),
+        Err(Failure::Error(reason)) => Err(format!(
+            "Could not evaluate the call of '{}' at compile time: {}",
+            name, reason
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    /// Arrays are shared by their copies, like at runtime
+    Array(Rc<RefCell<Vec<Value>>>),
+}
+
+impl Value {
+    fn into_expression(self) -> Expression {
+        match self {
+            Value::Int(n) if n < 0 => Expression::BinOp(
+                Box::new(Expression::Int(0)),
+                BinOp::Subtraction,
+                Box::new(Expression::Int(n.unsigned_abs() as usize)),
+            ),
+            Value::Int(n) => Expression::Int(n as usize),
+            Value::Bool(b) => Expression::Bool(b),
+            Value::Str(s) => Expression::Str(s),
+            Value::Array(elements) => {
+                let elements: Vec<Expression> = elements
+                    .borrow()
+                    .iter()
+                    .map(|e| e.clone().into_expression())
+                    .collect();
+                Expression::Array(elements.len(), elements)
+            }
+        }
+    }
+
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => l == r,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Str(l), Value::Str(r)) => l == r,
+            (Value::Array(l), Value::Array(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+/// Reasons why an expression can not be evaluated
+enum Failure {
+    /// The expression depends on a value that is only known at runtime,
+    /// so it is left to be evaluated when the program runs
+    Unknown,
+    /// Evaluating the expression failed, which would also fail at runtime
+    Error(String),
+}
+
+impl From<String> for Failure {
+    fn from(reason: String) -> Self {
+        Failure::Error(reason)
+    }
+}
+
+/// How the execution of a statement continues
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// Values of the variables of a call. Variables are unique within a function, so
+/// the variables of nested blocks do not have to be kept apart.
+type Frame = HashMap<String, Value>;
+
+struct Evaluator<'a> {
+    functions: &'a HashMap<String, Function>,
+    steps: usize,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn step(&mut self) -> Result<(), Failure> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(format!("exceeded the limit of {} steps", MAX_STEPS).into());
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Failure> {
+        let func = match self.functions.get(name) {
+            Some(func) => func,
+            None => return Err(Failure::Unknown),
+        };
+        self.step()?;
+        if self.depth == MAX_DEPTH {
+            return Err(format!("exceeded the limit of {} nested calls", MAX_DEPTH).into());
+        }
+        let mut frame: Frame = func
+            .arguments
+            .iter()
+            .map(|arg| arg.name.clone())
+            .zip(args)
+            .collect();
+        self.depth += 1;
+        let flow = self.statement(&func.body, &mut frame);
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            _ => Err(format!("function '{}' did not return a value", name).into()),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, frame: &mut Frame) -> Result<Flow, Failure> {
+        self.step()?;
+        match statement {
+            Statement::Block(statements, _) => {
+                for statement in statements {
+                    match self.statement(statement, frame)? {
+                        Flow::Next => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Statement::Declare(var, Some(expr)) => {
+                let value = self.expression(expr, frame)?;
+                frame.insert(var.name.clone(), value);
+            }
+            Statement::Declare(_, None) => {}
+            Statement::Assign(lhs, rhs) => {
+                let value = self.expression(rhs, frame)?;
+                self.assign(lhs, value, frame)?;
+            }
+            Statement::Return(Some(expr)) => {
+                return Ok(Flow::Return(self.expression(expr, frame)?))
+            }
+            Statement::If(cond, if_branch, else_branch) => {
+                if self.condition(cond, frame)? {
+                    return self.statement(if_branch, frame);
+                } else if let Some(else_branch) = else_branch {
+                    return self.statement(else_branch, frame);
+                }
+            }
+            Statement::While(cond, body) => {
+                while self.condition(cond, frame)? {
+                    match self.statement(body, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            Statement::For(var, iterable, body) => {
+                for (_, element) in self.elements(iterable, frame)? {
+                    frame.insert(var.name.clone(), element);
+                    match self.statement(body, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            Statement::ForPair(index, var, iterable, body) => {
+                for (i, element) in self.elements(iterable, frame)? {
+                    frame.insert(index.name.clone(), Value::Int(i as i64));
+                    frame.insert(var.name.clone(), element);
+                    match self.statement(body, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            Statement::Match(subject, arms) => {
+                let subject = self.expression(subject, frame)?;
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(case, s) => {
+                            if self.expression(case, frame)?.equals(&subject) {
+                                return self.statement(s, frame);
+                            }
+                        }
+                        MatchArm::Else(s) => return self.statement(s, frame),
+                    }
+                }
+            }
+            Statement::Break => return Ok(Flow::Break),
+            Statement::Continue => return Ok(Flow::Continue),
+            Statement::Exp(expr) => {
+                self.expression(expr, frame)?;
+            }
+            _ => return Err(Failure::Unknown),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn condition(&mut self, cond: &Expression, frame: &mut Frame) -> Result<bool, Failure> {
+        match self.expression(cond, frame)? {
+            Value::Bool(b) => Ok(b),
+            _ => Err(Failure::Unknown),
+        }
+    }
+
+    /// Returns the indices and elements of an array, or the characters of a string
+    fn elements(
+        &mut self,
+        iterable: &Expression,
+        frame: &mut Frame,
+    ) -> Result<Vec<(usize, Value)>, Failure> {
+        let elements = match self.expression(iterable, frame)? {
+            Value::Array(elements) => elements.borrow().clone(),
+            Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+            _ => return Err(Failure::Unknown),
+        };
+        Ok(elements.into_iter().enumerate().collect())
+    }
+
+    fn assign(&mut self, lhs: &Expression, value: Value, frame: &mut Frame) -> Result<(), Failure> {
+        match lhs {
+            Expression::Variable(name) => {
+                frame.insert(name.clone(), value);
+                Ok(())
+            }
+            Expression::ArrayAccess(name, index) => {
+                let index = self.expression(index, frame)?;
+                let elements = array(frame.get(name))?;
+                let i = check_index(&elements.borrow(), &index)?;
+                elements.borrow_mut()[i] = value;
+                Ok(())
+            }
+            _ => Err(Failure::Unknown),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression, frame: &mut Frame) -> Result<Value, Failure> {
+        match expr {
+            Expression::Int(n) => Ok(Value::Int(*n as i64)),
+            Expression::Bool(b) => Ok(Value::Bool(*b)),
+            Expression::Str(s) => Ok(Value::Str(s.clone())),
+            Expression::Variable(name) => frame.get(name).cloned().ok_or(Failure::Unknown),
+            Expression::Array(_, elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.expression(e, frame))
+                    .collect::<Result<Vec<Value>, Failure>>()?;
+                Ok(Value::Array(Rc::new(RefCell::new(elements))))
+            }
+            Expression::ArrayAccess(name, index) => {
+                let index = self.expression(index, frame)?;
+                let elements = array(frame.get(name))?;
+                let elements = elements.borrow();
+                let i = check_index(&elements, &index)?;
+                Ok(elements[i].clone())
+            }
+            Expression::BinOp(lhs, op, rhs) => self.bin_op(lhs, op, rhs, frame),
+            Expression::FunctionCall(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expression(arg, frame))
+                    .collect::<Result<Vec<Value>, Failure>>()?;
+                builtin(name, &args).unwrap_or_else(|| self.call(name, args))
+            }
+            _ => Err(Failure::Unknown),
+        }
+    }
+
+    fn bin_op(
+        &mut self,
+        lhs: &Expression,
+        op: &BinOp,
+        rhs: &Expression,
+        frame: &mut Frame,
+    ) -> Result<Value, Failure> {
+        let l = self.expression(lhs, frame)?;
+        // Logical operators only evaluate their right operand if it decides the result
+        match (op, &l) {
+            (BinOp::And, Value::Bool(false)) | (BinOp::Or, Value::Bool(true)) => return Ok(l),
+            (BinOp::And | BinOp::Or, _) => return self.expression(rhs, frame),
+            _ => {}
+        }
+        let r = self.expression(rhs, frame)?;
+        let assigned = match op {
+            BinOp::AddAssign => BinOp::Addition,
+            BinOp::SubtractAssign => BinOp::Subtraction,
+            BinOp::MultiplyAssign => BinOp::Multiplication,
+            BinOp::DivideAssign => BinOp::Division,
+            op => return apply(op, &l, &r),
+        };
+        let value = apply(&assigned, &l, &r)?;
+        self.assign(lhs, value.clone(), frame)?;
+        Ok(value)
+    }
+}
+
+/// Evaluates the builtins that the methods of arrays and strings are lowered to
+fn builtin(name: &str, args: &[Value]) -> Option<Result<Value, Failure>> {
+    let result = match (name, args) {
+        ("_str_len", [Value::Str(s)]) => Ok(Value::Int(s.len() as i64)),
+        ("_vec_len", [Value::Array(elements)]) => Ok(Value::Int(elements.borrow().len() as i64)),
+        ("_vec_push", [Value::Array(elements), value]) => {
+            elements.borrow_mut().push(value.clone());
+            // The value is not used, since `push` returns nothing
+            Ok(Value::Bool(false))
+        }
+        ("_vec_pop", [Value::Array(elements)]) => elements
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| "Cannot pop from an empty array".to_string().into()),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn apply(op: &BinOp, l: &Value, r: &Value) -> Result<Value, Failure> {
+    let value = match (l, r) {
+        (Value::Int(l), Value::Int(r)) => {
+            let (l, r) = (*l, *r);
+            let result = match op {
+                BinOp::Addition => l + r,
+                BinOp::Subtraction => l - r,
+                BinOp::Multiplication => l * r,
+                BinOp::Division if r == 0 => {
+                    return Err("attempt to divide by zero".to_string().into())
+                }
+                BinOp::Division => l / r,
+                BinOp::Modulus if r == 0 => {
+                    return Err("attempt to calculate the remainder with a divisor of zero"
+                        .to_string()
+                        .into())
+                }
+                BinOp::Modulus => l % r,
+                BinOp::LessThan => return Ok(Value::Bool(l < r)),
+                BinOp::LessThanOrEqual => return Ok(Value::Bool(l <= r)),
+                BinOp::GreaterThan => return Ok(Value::Bool(l > r)),
+                BinOp::GreaterThanOrEqual => return Ok(Value::Bool(l >= r)),
+                BinOp::Equal => return Ok(Value::Bool(l == r)),
+                BinOp::NotEqual => return Ok(Value::Bool(l != r)),
+                _ => return Err(Failure::Unknown),
+            };
+            if result < i32::MIN as i64 || result > i32::MAX as i64 {
+                let operation = match op {
+                    BinOp::Addition => "add",
+                    BinOp::Subtraction => "subtract",
+                    BinOp::Multiplication => "multiply",
+                    _ => "divide",
+                };
+                return Err(format!("attempt to {} with overflow", operation).into());
+            }
+            Value::Int(result)
+        }
+        (Value::Str(l), Value::Str(r)) if *op == BinOp::Addition => {
+            Value::Str(format!("{}{}", l, r))
+        }
+        _ => match op {
+            BinOp::Equal => Value::Bool(l.equals(r)),
+            BinOp::NotEqual => Value::Bool(!l.equals(r)),
+            _ => return Err(Failure::Unknown),
+        },
+    };
+    Ok(value)
+}
+
+fn array(value: Option<&Value>) -> Result<Rc<RefCell<Vec<Value>>>, Failure> {
+    match value {
+        Some(Value::Array(elements)) => Ok(elements.clone()),
+        _ => Err(Failure::Unknown),
+    }
+}
+
+fn check_index(elements: &[Value], index: &Value) -> Result<usize, Failure> {
+    match index {
+        Value::Int(i) if *i >= 0 && (*i as usize) < elements.len() => Ok(*i as usize),
+        Value::Int(i) => Err(format!(
+            "index out of bounds: the length is {} but the index is {}",
+            elements.len(),
+            i
+        )
+        .into()),
+        _ => Err(Failure::Unknown),
+    }
+}
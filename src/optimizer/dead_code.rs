@@ -0,0 +1,590 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::EXPORT;
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Removes statements that follow a `return`, `break` or `continue` in the same block
+pub(super) fn remove_unreachable(statement: &mut Statement) {
+    match statement {
+        Statement::Block(statements, scope) => {
+            if let Some(pos) = statements.iter().position(|s| {
+                matches!(
+                    s,
+                    Statement::Return(_) | Statement::Break | Statement::Continue
+                )
+            }) {
+                statements.truncate(pos + 1);
+            }
+            statements.iter_mut().for_each(remove_unreachable);
+
+            // Keep track of variables that are still declared in this block
+            scope.retain(|var| {
+                statements
+                    .iter()
+                    .any(|s| matches!(s, Statement::Declare(v, _) if v.name == var.name))
+            });
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            remove_unreachable(if_branch);
+            if let Some(else_branch) = else_branch {
+                remove_unreachable(else_branch);
+            }
+        }
+        Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+            remove_unreachable(body)
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => remove_unreachable(s),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Removes declarations of local variables that are never used.
+/// If the initializer of such a variable has side effects, it is kept as a plain expression.
+pub(super) fn remove_unused_variables(func: &mut Function) {
+    let mut used = HashSet::new();
+    collect_used_names(&func.body, &mut used);
+    remove_declarations(&mut func.body, &used);
+}
+
+/// Removes functions that can not be reached from `main`, public functions
+/// or functions annotated with `@export`, since those are exported from the program.
+/// Modules without a `main` function are left untouched.
+pub(super) fn remove_uncalled_functions(module: &mut Module) {
+    if !module.func.iter().any(|f| f.name == "main") {
+        return;
+    }
+
+    let mut roots: Vec<String> = vec!["main".to_string()];
+    for func in module
+        .func
+        .iter()
+        .filter(|f| f.public || f.has_attribute(EXPORT))
+    {
+        roots.push(func.name.clone());
+    }
+
+    let reachable = reachable_functions(module, roots);
+    module.func.retain(|f| reachable.contains(&f.name));
+}
+
+/// Removes the functions and structs the program does not use. If the module has a `main`
+/// function, declarations have to be reachable from `main`, public functions or functions
+/// annotated with `@export`. Otherwise the module is a library, and only the functions of
+/// the standard library that its own functions do not call are removed.
+pub(super) fn remove_unused_declarations(module: &mut Module, stdlib: &HashSet<String>) {
+    let has_main = module.func.iter().any(|f| f.name == "main");
+    let mut calls: Vec<String> = module
+        .func
+        .iter()
+        .filter(|f| {
+            if has_main {
+                f.name == "main" || f.public || f.has_attribute(EXPORT)
+            } else {
+                !stdlib.contains(&f.name)
+            }
+        })
+        .map(|f| f.name.clone())
+        .collect();
+    let mut uses: Vec<String> = Vec::new();
+    for global in &module.globals {
+        collect_calls_expr(&global.value, &mut calls);
+        collect_struct_uses_expr(&global.value, &mut uses);
+        if let Some(ty) = &global.var.ty {
+            collect_type_structs(ty, &mut uses);
+        }
+    }
+
+    // Methods of used structs can call functions, and functions can use structs
+    let mut functions: HashSet<String> = HashSet::new();
+    let mut structs: HashSet<String> = HashSet::new();
+    loop {
+        if let Some(name) = calls.pop() {
+            if !functions.insert(name.clone()) {
+                continue;
+            }
+            if let Some(func) = module.func.iter().find(|f| f.name == name) {
+                collect_calls(&func.body, &mut calls);
+                collect_function_structs(func, &mut uses);
+            }
+        } else if let Some(name) = uses.pop() {
+            if !structs.insert(name.clone()) {
+                continue;
+            }
+            if let Some(def) = module.structs.iter().find(|def| def.name == name) {
+                for field in &def.fields {
+                    if let Some(ty) = &field.ty {
+                        collect_type_structs(ty, &mut uses);
+                    }
+                }
+                for method in &def.methods {
+                    collect_calls(&method.body, &mut calls);
+                    collect_function_structs(method, &mut uses);
+                }
+            }
+        } else {
+            break;
+        }
+    }
+
+    module.func.retain(|f| functions.contains(&f.name));
+    module.structs.retain(|def| structs.contains(&def.name));
+}
+
+/// Returns the functions that are called, directly or indirectly, by the given functions.
+/// Methods and initializers of globals are always kept, so every function they call is reachable
+fn reachable_functions(module: &Module, mut queue: Vec<String>) -> HashSet<String> {
+    let mut reachable: HashSet<String> = HashSet::new();
+    for global in &module.globals {
+        collect_calls_expr(&global.value, &mut queue);
+    }
+    for def in &module.structs {
+        for method in &def.methods {
+            collect_calls(&method.body, &mut queue);
+        }
+    }
+
+    while let Some(name) = queue.pop() {
+        if !reachable.insert(name.clone()) {
+            continue;
+        }
+        if let Some(func) = module.func.iter().find(|f| f.name == name) {
+            collect_calls(&func.body, &mut queue);
+        }
+    }
+    reachable
+}
+
+fn remove_declarations(statement: &mut Statement, used: &HashSet<String>) {
+    match statement {
+        Statement::Block(statements, scope) => {
+            let mut retained = Vec::new();
+            for mut statement in statements.drain(..) {
+                if let Statement::Declare(var, expr) = &statement {
+                    if !used.contains(&var.name) {
+                        match expr {
+                            Some(expr) if has_side_effects(expr) => {
+                                retained.push(Statement::Exp(expr.clone()))
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                }
+                remove_declarations(&mut statement, used);
+                retained.push(statement);
+            }
+            *statements = retained;
+            scope.retain(|var| used.contains(&var.name));
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            remove_declarations(if_branch, used);
+            if let Some(else_branch) = else_branch {
+                remove_declarations(else_branch, used);
+            }
+        }
+        Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+            remove_declarations(body, used)
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => remove_declarations(s, used),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_side_effects(expr: &Expression) -> bool {
+    match expr {
+        Expression::FunctionCall(..) => true,
+        Expression::BinOp(lhs, op, rhs) => {
+            matches!(
+                op,
+                BinOp::AddAssign
+                    | BinOp::SubtractAssign
+                    | BinOp::MultiplyAssign
+                    | BinOp::DivideAssign
+            ) || has_side_effects(lhs)
+                || has_side_effects(rhs)
+        }
+        Expression::Array(_, elements) => elements.iter().any(has_side_effects),
+        Expression::ArrayAccess(_, index) => has_side_effects(index),
+        Expression::StructInitialization(_, fields) => fields.values().any(|e| has_side_effects(e)),
+        Expression::StructUpdate(_, base, fields) => {
+            has_side_effects(base) || fields.values().any(|e| has_side_effects(e))
+        }
+        Expression::FieldAccess(obj, field) => has_side_effects(obj) || has_side_effects(field),
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| has_side_effects(key) || has_side_effects(value)),
+        Expression::Store(..) => true,
+        Expression::Load(_, operand) | Expression::Dereference(operand) => {
+            has_side_effects(operand)
+        }
+        _ => false,
+    }
+}
+
+/// Collects the names of all variables that are used in a statement, apart from their declaration
+fn collect_used_names(statement: &Statement, used: &mut HashSet<String>) {
+    match statement {
+        Statement::Block(statements, _) => {
+            statements.iter().for_each(|s| collect_used_names(s, used))
+        }
+        Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => collect_used_names_expr(expr, used),
+        Statement::Assign(lhs, rhs) => {
+            collect_used_names_expr(lhs, used);
+            collect_used_names_expr(rhs, used);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            collect_used_names_expr(cond, used);
+            collect_used_names(if_branch, used);
+            if let Some(else_branch) = else_branch {
+                collect_used_names(else_branch, used);
+            }
+        }
+        Statement::While(cond, body) => {
+            collect_used_names_expr(cond, used);
+            collect_used_names(body, used);
+        }
+        Statement::For(var, expr, body) => {
+            // The loop variable is always considered used
+            used.insert(var.name.clone());
+            collect_used_names_expr(expr, used);
+            collect_used_names(body, used);
+        }
+        Statement::Match(subject, arms) => {
+            collect_used_names_expr(subject, used);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        collect_used_names_expr(expr, used);
+                        collect_used_names(s, used);
+                    }
+                    MatchArm::Else(s) => collect_used_names(s, used),
+                }
+            }
+        }
+        Statement::Unsafe(body) => collect_used_names(body, used),
+        Statement::Inline(_, code) => used.extend(inline_identifiers(code).map(String::from)),
+        _ => {}
+    }
+}
+
+fn collect_used_names_expr(expr: &Expression, used: &mut HashSet<String>) {
+    match expr {
+        Expression::Variable(name) => {
+            used.insert(name.clone());
+        }
+        Expression::ArrayAccess(name, index) => {
+            used.insert(name.clone());
+            collect_used_names_expr(index, used);
+        }
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            collect_used_names_expr(lhs, used);
+            collect_used_names_expr(rhs, used);
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => collect_used_names_expr(operand, used),
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => elements
+            .iter()
+            .for_each(|e| collect_used_names_expr(e, used)),
+        Expression::StructInitialization(_, fields) => fields
+            .values()
+            .for_each(|e| collect_used_names_expr(e, used)),
+        Expression::StructUpdate(_, base, fields) => {
+            collect_used_names_expr(base, used);
+            fields
+                .values()
+                .for_each(|e| collect_used_names_expr(e, used))
+        }
+        // Field names are not variables, only the accessed object is
+        Expression::FieldAccess(obj, field) => {
+            collect_used_names_expr(obj, used);
+            if let Expression::FunctionCall(_, args) = &**field {
+                args.iter().for_each(|e| collect_used_names_expr(e, used));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                collect_used_names_expr(key, used);
+                collect_used_names_expr(value, used);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects the names of all functions called in a statement
+pub(super) fn collect_calls(statement: &Statement, calls: &mut Vec<String>) {
+    match statement {
+        Statement::Block(statements, _) => statements.iter().for_each(|s| collect_calls(s, calls)),
+        Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => collect_calls_expr(expr, calls),
+        Statement::Assign(lhs, rhs) => {
+            collect_calls_expr(lhs, calls);
+            collect_calls_expr(rhs, calls);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            collect_calls_expr(cond, calls);
+            collect_calls(if_branch, calls);
+            if let Some(else_branch) = else_branch {
+                collect_calls(else_branch, calls);
+            }
+        }
+        Statement::While(cond, body) | Statement::For(_, cond, body) => {
+            collect_calls_expr(cond, calls);
+            collect_calls(body, calls);
+        }
+        Statement::Match(subject, arms) => {
+            collect_calls_expr(subject, calls);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        collect_calls_expr(expr, calls);
+                        collect_calls(s, calls);
+                    }
+                    MatchArm::Else(s) => collect_calls(s, calls),
+                }
+            }
+        }
+        Statement::ForPair(_, _, iterable, body) => {
+            collect_calls_expr(iterable, calls);
+            collect_calls(body, calls);
+        }
+        Statement::Static(_, expr) | Statement::Destructure(_, expr) => {
+            collect_calls_expr(expr, calls)
+        }
+        Statement::Unsafe(body) => collect_calls(body, calls),
+        Statement::Inline(_, code) => calls.extend(inline_identifiers(code).map(String::from)),
+        _ => {}
+    }
+}
+
+fn collect_calls_expr(expr: &Expression, calls: &mut Vec<String>) {
+    match expr {
+        Expression::FunctionCall(name, args) => {
+            calls.push(name.clone());
+            args.iter().for_each(|e| collect_calls_expr(e, calls));
+        }
+        Expression::ArrayAccess(_, index) => collect_calls_expr(index, calls),
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => {
+            collect_calls_expr(lhs, calls);
+            collect_calls_expr(rhs, calls);
+        }
+        Expression::Array(_, elements) => {
+            elements.iter().for_each(|e| collect_calls_expr(e, calls))
+        }
+        Expression::StructInitialization(_, fields) => {
+            fields.values().for_each(|e| collect_calls_expr(e, calls))
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            collect_calls_expr(base, calls);
+            fields.values().for_each(|e| collect_calls_expr(e, calls))
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                collect_calls_expr(key, calls);
+                collect_calls_expr(value, calls);
+            }
+        }
+        Expression::Try(inner)
+        | Expression::Await(inner)
+        | Expression::Reference(inner)
+        | Expression::Dereference(inner)
+        | Expression::Load(_, inner) => collect_calls_expr(inner, calls),
+        _ => {}
+    }
+}
+
+/// Collects the names of the structs used by the signature and body of a function
+fn collect_function_structs(func: &Function, structs: &mut Vec<String>) {
+    for ty in func.arguments.iter().filter_map(|arg| arg.ty.as_ref()) {
+        collect_type_structs(ty, structs);
+    }
+    if let Some(ty) = &func.ret_type {
+        collect_type_structs(ty, structs);
+    }
+    collect_struct_uses(&func.body, structs);
+}
+
+fn collect_type_structs(ty: &Type, structs: &mut Vec<String>) {
+    match ty {
+        Type::Struct(name) => structs.push(name.clone()),
+        Type::Array(inner, _) | Type::Result(inner) | Type::Ref(inner) | Type::Chan(inner) => {
+            collect_type_structs(inner, structs)
+        }
+        Type::Map(key, value) => {
+            collect_type_structs(key, structs);
+            collect_type_structs(value, structs);
+        }
+        Type::Any | Type::Int | Type::Str | Type::Bool | Type::Ptr => {}
+    }
+}
+
+fn collect_variable_structs(var: &Variable, structs: &mut Vec<String>) {
+    if let Some(ty) = &var.ty {
+        collect_type_structs(ty, structs);
+    }
+}
+
+/// Collects the names of the structs that are initialized in a statement,
+/// or that are the type of one of its variables
+fn collect_struct_uses(statement: &Statement, structs: &mut Vec<String>) {
+    match statement {
+        Statement::Block(statements, vars) => {
+            vars.iter()
+                .for_each(|var| collect_variable_structs(var, structs));
+            statements
+                .iter()
+                .for_each(|s| collect_struct_uses(s, structs));
+        }
+        Statement::Declare(var, expr) => {
+            collect_variable_structs(var, structs);
+            if let Some(expr) = expr {
+                collect_struct_uses_expr(expr, structs);
+            }
+        }
+        Statement::Static(var, expr) => {
+            collect_variable_structs(var, structs);
+            collect_struct_uses_expr(expr, structs);
+        }
+        Statement::Destructure(fields, expr) => {
+            for (_, var) in fields {
+                collect_variable_structs(var, structs);
+            }
+            collect_struct_uses_expr(expr, structs);
+        }
+        Statement::Assign(lhs, rhs) => {
+            collect_struct_uses_expr(lhs, structs);
+            collect_struct_uses_expr(rhs, structs);
+        }
+        Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => collect_struct_uses_expr(expr, structs),
+        Statement::If(cond, if_branch, else_branch) => {
+            collect_struct_uses_expr(cond, structs);
+            collect_struct_uses(if_branch, structs);
+            if let Some(else_branch) = else_branch {
+                collect_struct_uses(else_branch, structs);
+            }
+        }
+        Statement::While(cond, body) => {
+            collect_struct_uses_expr(cond, structs);
+            collect_struct_uses(body, structs);
+        }
+        Statement::For(var, iterable, body) => {
+            collect_variable_structs(var, structs);
+            collect_struct_uses_expr(iterable, structs);
+            collect_struct_uses(body, structs);
+        }
+        Statement::ForPair(key, value, iterable, body) => {
+            collect_variable_structs(key, structs);
+            collect_variable_structs(value, structs);
+            collect_struct_uses_expr(iterable, structs);
+            collect_struct_uses(body, structs);
+        }
+        Statement::Match(subject, arms) => {
+            collect_struct_uses_expr(subject, structs);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        collect_struct_uses_expr(expr, structs);
+                        collect_struct_uses(s, structs);
+                    }
+                    MatchArm::Else(s) => collect_struct_uses(s, structs),
+                }
+            }
+        }
+        Statement::Unsafe(body) => collect_struct_uses(body, structs),
+        Statement::Inline(_, code) => structs.extend(inline_identifiers(code).map(String::from)),
+        Statement::Return(None) | Statement::Break | Statement::Continue => {}
+    }
+}
+
+fn collect_struct_uses_expr(expr: &Expression, structs: &mut Vec<String>) {
+    match expr {
+        Expression::StructInitialization(name, fields) => {
+            structs.push(name.clone());
+            fields
+                .values()
+                .for_each(|e| collect_struct_uses_expr(e, structs));
+        }
+        Expression::StructUpdate(name, base, fields) => {
+            structs.push(name.clone());
+            collect_struct_uses_expr(base, structs);
+            fields
+                .values()
+                .for_each(|e| collect_struct_uses_expr(e, structs));
+        }
+        Expression::SizeOf(ty) => collect_type_structs(ty, structs),
+        Expression::Load(ty, address) => {
+            collect_type_structs(ty, structs);
+            collect_struct_uses_expr(address, structs);
+        }
+        Expression::Store(ty, address, value) => {
+            collect_type_structs(ty, structs);
+            collect_struct_uses_expr(address, structs);
+            collect_struct_uses_expr(value, structs);
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => args
+            .iter()
+            .for_each(|e| collect_struct_uses_expr(e, structs)),
+        Expression::ArrayAccess(_, index)
+        | Expression::Try(index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index) => collect_struct_uses_expr(index, structs),
+        Expression::BinOp(lhs, _, rhs) | Expression::FieldAccess(lhs, rhs) => {
+            collect_struct_uses_expr(lhs, structs);
+            collect_struct_uses_expr(rhs, structs);
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                collect_struct_uses_expr(key, structs);
+                collect_struct_uses_expr(value, structs);
+            }
+        }
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_) => {}
+    }
+}
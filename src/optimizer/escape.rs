@@ -0,0 +1,318 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Builtin functions that do not keep a reference to the array passed as their first argument
+const NON_RETAINING_BUILTINS: [&str; 6] = [
+    "_vec_push",
+    "_vec_pop",
+    "_vec_len",
+    "_vec_slice",
+    "_vec_concat",
+    "_check_index",
+];
+
+/// Arrays with more elements are always allocated on the heap, so that the stack stays small
+const MAX_STACK_ARRAY_LEN: usize = 64;
+
+/// Number of rounds of the analysis after which every array is left on the heap
+const MAX_ITERATIONS: usize = 100;
+
+/// Function -> (local variables that escape the function) mappings.
+/// Arguments that escape are stored or returned by the function.
+type Escapes = HashMap<String, HashSet<String>>;
+
+/// Returns the arrays of each function that never escape it, and are declared
+/// outside of loops, so that they are allocated once per call.
+///
+/// Whether the arguments of a function escape depends on the functions it passes them to,
+/// so the escaping variables of all functions are collected until they do not change anymore.
+pub(super) fn stack_arrays(module: &Module) -> HashMap<String, HashSet<String>> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for func in &module.func {
+        *counts.entry(func.name.as_str()).or_default() += 1;
+    }
+    // Calls of a name that is defined more than once are treated like calls of unknown functions
+    let functions: HashMap<&str, &Function> = module
+        .func
+        .iter()
+        .filter(|func| counts[func.name.as_str()] == 1)
+        .map(|func| (func.name.as_str(), func))
+        .collect();
+
+    // The escaping variables of each function, by its index
+    let mut escaping_of: Vec<HashSet<String>> = vec![HashSet::new(); module.func.len()];
+    let mut escapes: Escapes = HashMap::new();
+    let mut converged = false;
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (index, func) in module.func.iter().enumerate() {
+            let mut escaping = HashSet::new();
+            Analysis {
+                functions: &functions,
+                escapes: &escapes,
+                escaping: &mut escaping,
+            }
+            .statement(&func.body);
+            if escaping_of[index] != escaping {
+                if functions.contains_key(func.name.as_str()) {
+                    escapes.insert(func.name.clone(), escaping.clone());
+                }
+                escaping_of[index] = escaping;
+                changed = true;
+            }
+        }
+        if !changed {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        return HashMap::new();
+    }
+
+    let mut arrays: HashMap<String, HashSet<String>> = HashMap::new();
+    for (func, escaping) in module.func.iter().zip(&escaping_of) {
+        let mut candidates = HashSet::new();
+        // Self-recursive tail calls jump back to the start of the function like a loop
+        if !returns_self_call(&func.name, &func.body) {
+            collect_arrays(&func.body, &mut candidates);
+            candidates.retain(|name| !escaping.contains(name));
+        }
+        // Functions with the same name only keep the arrays that none of them lets escape
+        match arrays.get_mut(&func.name) {
+            Some(existing) => existing.retain(|name| candidates.contains(name)),
+            None => {
+                arrays.insert(func.name.clone(), candidates);
+            }
+        }
+    }
+    arrays
+}
+
+struct Analysis<'a> {
+    functions: &'a HashMap<&'a str, &'a Function>,
+    /// Escaping variables of the functions that have been analyzed so far
+    escapes: &'a Escapes,
+    /// Variables of the current function that escape it
+    escaping: &'a mut HashSet<String>,
+}
+
+impl Analysis<'_> {
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(statements, _) => statements.iter().for_each(|s| self.statement(s)),
+            Statement::Declare(_, Some(expr))
+            | Statement::Destructure(_, expr)
+            | Statement::Static(_, expr)
+            | Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::Defer(expr) => self.expression(expr),
+            // The spawned call runs after the function may have returned, so it keeps its arguments
+            Statement::Spawn(Expression::FunctionCall(_, args)) => {
+                args.iter().for_each(|arg| self.expression(arg))
+            }
+            Statement::Spawn(expr) => self.expression(expr),
+            Statement::Assign(lhs, rhs) => {
+                match &**lhs {
+                    // Reassigning a variable does not leak its previous value
+                    Expression::Variable(_) => {}
+                    Expression::ArrayAccess(_, index) => self.expression(index),
+                    other => self.expression(other),
+                }
+                self.expression(rhs);
+            }
+            Statement::If(cond, if_branch, else_branch) => {
+                self.expression(cond);
+                self.statement(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Statement::While(cond, body) => {
+                self.expression(cond);
+                self.statement(body);
+            }
+            Statement::Unsafe(body) => self.statement(body),
+            // Iterating over an array does not leak it
+            Statement::For(_, expr, body) | Statement::ForPair(_, _, expr, body) => {
+                if !matches!(expr, Expression::Variable(_)) {
+                    self.expression(expr);
+                }
+                self.statement(body);
+            }
+            Statement::Match(subject, arms) => {
+                self.expression(subject);
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(expr, body) => {
+                            self.expression(expr);
+                            self.statement(body);
+                        }
+                        MatchArm::Else(body) => self.statement(body),
+                    }
+                }
+            }
+            Statement::Declare(_, None)
+            | Statement::Return(None)
+            | Statement::Break
+            | Statement::Continue => {}
+            // Inline code may keep references to any array it mentions
+            Statement::Inline(_, code) => self
+                .escaping
+                .extend(inline_identifiers(code).map(String::from)),
+        }
+    }
+
+    /// Marks the variables used by an expression as escaping, unless they are only
+    /// indexed or passed to functions that do not keep them
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Variable(name) => {
+                self.escaping.insert(name.clone());
+            }
+            Expression::ArrayAccess(_, index) => self.expression(index),
+            // A reference to an element may outlive the array
+            Expression::Reference(inner) => match &**inner {
+                Expression::ArrayAccess(name, index) => {
+                    self.escaping.insert(name.clone());
+                    self.expression(index);
+                }
+                other => self.expression(other),
+            },
+            Expression::Dereference(inner) | Expression::Load(_, inner) => self.expression(inner),
+            Expression::FunctionCall(name, args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    match arg {
+                        Expression::Variable(_) if self.is_retained(name, i) => {
+                            self.expression(arg)
+                        }
+                        Expression::Variable(_) => {}
+                        other => self.expression(other),
+                    }
+                }
+            }
+            Expression::Array(_, elements) => elements.iter().for_each(|e| self.expression(e)),
+            Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::StructInitialization(_, fields) => {
+                fields.values().for_each(|e| self.expression(e))
+            }
+            Expression::StructUpdate(_, base, fields) => {
+                self.expression(base);
+                fields.values().for_each(|e| self.expression(e))
+            }
+            Expression::FieldAccess(obj, field) => {
+                self.expression(obj);
+                if let Expression::FunctionCall(_, args) = &**field {
+                    args.iter().for_each(|e| self.expression(e));
+                }
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expression::Try(inner) | Expression::Await(inner) => self.expression(inner),
+            Expression::Int(_)
+            | Expression::Str(_)
+            | Expression::Bool(_)
+            | Expression::Selff
+            | Expression::SizeOf(_) => {}
+        }
+    }
+
+    /// Returns true if the function may keep a reference to its argument at the given position.
+    /// Unknown functions, like external ones, are assumed to keep it.
+    fn is_retained(&self, name: &str, position: usize) -> bool {
+        if NON_RETAINING_BUILTINS.contains(&name) {
+            // Only the array itself is not kept, e.g. pushed values are
+            return position > 0 && name != "_vec_concat";
+        }
+        match self.functions.get(name) {
+            // Functions that have not been analyzed yet keep nothing until proven otherwise
+            Some(callee) => match callee.arguments.get(position) {
+                Some(arg) => self
+                    .escapes
+                    .get(name)
+                    .is_some_and(|escaping| escaping.contains(&arg.name)),
+                None => true,
+            },
+            None => true,
+        }
+    }
+}
+
+/// Collects the arrays that are declared outside of loops with a known, small capacity
+fn collect_arrays(statement: &Statement, arrays: &mut HashSet<String>) {
+    match statement {
+        Statement::Block(statements, _) => {
+            statements.iter().for_each(|s| collect_arrays(s, arrays))
+        }
+        Statement::Declare(var, Some(Expression::Array(len, _)))
+        | Statement::Declare(
+            var @ Variable {
+                ty: Some(Type::Array(_, Some(len))),
+                ..
+            },
+            None,
+        ) if *len <= MAX_STACK_ARRAY_LEN => {
+            arrays.insert(var.name.clone());
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            collect_arrays(if_branch, arrays);
+            if let Some(else_branch) = else_branch {
+                collect_arrays(else_branch, arrays);
+            }
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, body) | MatchArm::Else(body) => collect_arrays(body, arrays),
+                }
+            }
+        }
+        Statement::Unsafe(body) => collect_arrays(body, arrays),
+        _ => {}
+    }
+}
+
+/// Returns true if the function returns the result of a call of itself
+fn returns_self_call(name: &str, statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(Some(Expression::FunctionCall(callee, _))) => callee == name,
+        Statement::Block(statements, _) => statements.iter().any(|s| returns_self_call(name, s)),
+        Statement::If(_, if_branch, else_branch) => {
+            returns_self_call(name, if_branch)
+                || else_branch
+                    .as_deref()
+                    .is_some_and(|s| returns_self_call(name, s))
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(_, _, _, body)
+        | Statement::Unsafe(body) => returns_self_call(name, body),
+        Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+            MatchArm::Case(_, body) | MatchArm::Else(body) => returns_self_call(name, body),
+        }),
+        _ => false,
+    }
+}
@@ -0,0 +1,415 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::*;
+
+/// Largest integer that can be represented by every backend.
+/// QBE stores integers in 32 bit words.
+const MAX_FOLDED_INT: usize = i32::MAX as usize;
+
+/// Replaces reads of variables that are bound to a literal and never
+/// reassigned with the literal itself.
+///
+/// Only declarations in the outermost block of the function are considered,
+/// since they are visible for the rest of the function body.
+pub(super) fn propagate_constants(func: &mut Function) {
+    let constants: Vec<(usize, String, Expression)> = match &func.body {
+        Statement::Block(statements, _) => statements
+            .iter()
+            .enumerate()
+            .filter_map(|(i, statement)| match statement {
+                Statement::Declare(var, Some(expr)) if is_literal(expr) => {
+                    Some((i, var.name.clone(), expr.clone()))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => return,
+    };
+
+    for (i, name, literal) in constants {
+        if func.arguments.iter().any(|arg| arg.name == name)
+            || count_declarations(&func.body, &name) != 1
+            || is_written(&func.body, &name)
+        {
+            continue;
+        }
+
+        if let Statement::Block(statements, _) = &mut func.body {
+            for statement in statements.iter_mut().skip(i + 1) {
+                substitute_statement(statement, &name, &literal);
+            }
+        }
+    }
+}
+
+/// Folds binary operations on literals inside a function
+pub(super) fn fold_function(func: &mut Function) {
+    fold_statement(&mut func.body);
+}
+
+fn is_literal(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Int(_) | Expression::Bool(_) | Expression::Str(_)
+    )
+}
+
+fn is_assign_op(op: &BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::AddAssign | BinOp::SubtractAssign | BinOp::MultiplyAssign | BinOp::DivideAssign
+    )
+}
+
+/// Counts how often a variable with the given name is declared in a statement
+fn count_declarations(statement: &Statement, name: &str) -> usize {
+    match statement {
+        Statement::Block(statements, _) => {
+            statements.iter().map(|s| count_declarations(s, name)).sum()
+        }
+        Statement::Declare(var, _) => (var.name == name) as usize,
+        Statement::If(_, if_branch, else_branch) => {
+            count_declarations(if_branch, name)
+                + else_branch
+                    .as_ref()
+                    .map_or(0, |s| count_declarations(s, name))
+        }
+        Statement::While(_, body) | Statement::Unsafe(body) => count_declarations(body, name),
+        Statement::For(var, _, body) => {
+            (var.name == name) as usize + count_declarations(body, name)
+        }
+        Statement::Match(_, arms) => arms
+            .iter()
+            .map(|arm| match arm {
+                MatchArm::Case(_, s) | MatchArm::Else(s) => count_declarations(s, name),
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Returns true if the variable is the target of an assignment
+fn is_written(statement: &Statement, name: &str) -> bool {
+    match statement {
+        Statement::Block(statements, _) => statements.iter().any(|s| is_written(s, name)),
+        Statement::Declare(_, Some(expr)) | Statement::Return(Some(expr)) => {
+            is_written_expr(expr, name)
+        }
+        Statement::Assign(lhs, rhs) => {
+            matches!(&**lhs, Expression::Variable(v) if v == name) || is_written_expr(rhs, name)
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            is_written_expr(cond, name)
+                || is_written(if_branch, name)
+                || else_branch.as_ref().is_some_and(|s| is_written(s, name))
+        }
+        Statement::While(cond, body) => is_written_expr(cond, name) || is_written(body, name),
+        Statement::For(_, expr, body) => is_written_expr(expr, name) || is_written(body, name),
+        Statement::Match(subject, arms) => {
+            is_written_expr(subject, name)
+                || arms.iter().any(|arm| match arm {
+                    MatchArm::Case(expr, s) => is_written_expr(expr, name) || is_written(s, name),
+                    MatchArm::Else(s) => is_written(s, name),
+                })
+        }
+        Statement::Exp(expr) | Statement::Defer(expr) | Statement::Spawn(expr) => {
+            is_written_expr(expr, name)
+        }
+        Statement::Unsafe(body) => is_written(body, name),
+        // Inline code may assign to the variables it refers to
+        Statement::Inline(_, code) => inline_identifiers(code).any(|word| word == name),
+        _ => false,
+    }
+}
+
+fn is_written_expr(expr: &Expression, name: &str) -> bool {
+    match expr {
+        Expression::BinOp(lhs, op, rhs) => {
+            (is_assign_op(op) && matches!(&**lhs, Expression::Variable(v) if v == name))
+                || is_written_expr(lhs, name)
+                || is_written_expr(rhs, name)
+        }
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => {
+            elements.iter().any(|e| is_written_expr(e, name))
+        }
+        Expression::ArrayAccess(_, index) => is_written_expr(index, name),
+        Expression::StructInitialization(_, fields) => {
+            fields.values().any(|e| is_written_expr(e, name))
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            is_written_expr(base, name) || fields.values().any(|e| is_written_expr(e, name))
+        }
+        Expression::FieldAccess(obj, field) => {
+            is_written_expr(obj, name) || is_written_expr(field, name)
+        }
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| is_written_expr(key, name) || is_written_expr(value, name)),
+        // The variable may be written through a reference to it
+        Expression::Reference(target) => {
+            matches!(&**target, Expression::Variable(v) if v == name)
+                || is_written_expr(target, name)
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => is_written_expr(operand, name),
+        Expression::Store(_, address, value) => {
+            is_written_expr(address, name) || is_written_expr(value, name)
+        }
+        _ => false,
+    }
+}
+
+fn substitute_statement(statement: &mut Statement, name: &str, literal: &Expression) {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .for_each(|s| substitute_statement(s, name, literal)),
+        Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => substitute_expression(expr, name, literal),
+        Statement::Assign(lhs, rhs) => {
+            // The assignee itself is never substituted, but it might contain reads
+            match &mut **lhs {
+                Expression::FieldAccess(obj, _) => substitute_expression(obj, name, literal),
+                Expression::ArrayAccess(_, index) => substitute_expression(index, name, literal),
+                _ => {}
+            }
+            substitute_expression(rhs, name, literal);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            substitute_expression(cond, name, literal);
+            substitute_statement(if_branch, name, literal);
+            if let Some(else_branch) = else_branch {
+                substitute_statement(else_branch, name, literal);
+            }
+        }
+        Statement::While(cond, body) => {
+            substitute_expression(cond, name, literal);
+            substitute_statement(body, name, literal);
+        }
+        Statement::For(_, expr, body) => {
+            substitute_expression(expr, name, literal);
+            substitute_statement(body, name, literal);
+        }
+        Statement::Match(subject, arms) => {
+            substitute_expression(subject, name, literal);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        substitute_expression(expr, name, literal);
+                        substitute_statement(s, name, literal);
+                    }
+                    MatchArm::Else(s) => substitute_statement(s, name, literal),
+                }
+            }
+        }
+        Statement::Unsafe(body) => substitute_statement(body, name, literal),
+        _ => {}
+    }
+}
+
+fn substitute_expression(expr: &mut Expression, name: &str, literal: &Expression) {
+    match expr {
+        Expression::Variable(v) if v == name => *expr = literal.clone(),
+        Expression::BinOp(lhs, _, rhs) => {
+            substitute_expression(lhs, name, literal);
+            substitute_expression(rhs, name, literal);
+        }
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => elements
+            .iter_mut()
+            .for_each(|e| substitute_expression(e, name, literal)),
+        Expression::ArrayAccess(_, index) => substitute_expression(index, name, literal),
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|e| substitute_expression(e, name, literal)),
+        Expression::StructUpdate(_, base, fields) => {
+            substitute_expression(base, name, literal);
+            fields
+                .values_mut()
+                .for_each(|e| substitute_expression(e, name, literal))
+        }
+        // The right hand side of a field access is the name of the field,
+        // or a method call
+        Expression::FieldAccess(obj, field) => {
+            substitute_expression(obj, name, literal);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut()
+                    .for_each(|e| substitute_expression(e, name, literal));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                substitute_expression(key, name, literal);
+                substitute_expression(value, name, literal);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => substitute_expression(operand, name, literal),
+        Expression::Store(_, address, value) => {
+            substitute_expression(address, name, literal);
+            substitute_expression(value, name, literal);
+        }
+        _ => {}
+    }
+}
+
+fn fold_statement(statement: &mut Statement) {
+    match statement {
+        Statement::Block(statements, _) => statements.iter_mut().for_each(fold_statement),
+        Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => fold_expression(expr),
+        Statement::Assign(_, rhs) => fold_expression(rhs),
+        Statement::If(cond, if_branch, else_branch) => {
+            fold_expression(cond);
+            fold_statement(if_branch);
+            if let Some(else_branch) = else_branch {
+                fold_statement(else_branch);
+            }
+
+            // Replace conditionals with a constant condition by the taken branch
+            let taken = match cond {
+                Expression::Bool(true) => Some(*if_branch.clone()),
+                Expression::Bool(false) => Some(match else_branch {
+                    Some(else_branch) => *else_branch.clone(),
+                    None => Statement::Block(Vec::new(), Vec::new()),
+                }),
+                _ => None,
+            };
+            if let Some(taken) = taken {
+                *statement = taken;
+            }
+        }
+        Statement::While(cond, body) => {
+            fold_expression(cond);
+            fold_statement(body);
+            if matches!(cond, Expression::Bool(false)) {
+                *statement = Statement::Block(Vec::new(), Vec::new());
+            }
+        }
+        Statement::For(_, expr, body) => {
+            fold_expression(expr);
+            fold_statement(body);
+        }
+        Statement::Match(subject, arms) => {
+            fold_expression(subject);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => fold_statement(s),
+                }
+            }
+        }
+        Statement::Unsafe(body) => fold_statement(body),
+        _ => {}
+    }
+}
+
+/// Folds binary operations with literal operands. Operands are folded first, so a chain
+/// like `10 - 5 - 3` is folded in the order the parser grouped it by precedence.
+fn fold_expression(expr: &mut Expression) {
+    match expr {
+        Expression::BinOp(lhs, op, rhs) => {
+            fold_expression(lhs);
+            fold_expression(rhs);
+            if let Some(folded) = fold_bin_op(lhs, op, rhs) {
+                *expr = folded;
+            }
+        }
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => {
+            elements.iter_mut().for_each(fold_expression)
+        }
+        Expression::ArrayAccess(_, index) => fold_expression(index),
+        Expression::StructInitialization(_, fields) => {
+            fields.values_mut().for_each(|e| fold_expression(e))
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            fold_expression(base);
+            fields.values_mut().for_each(|e| fold_expression(e))
+        }
+        Expression::FieldAccess(obj, field) => {
+            fold_expression(obj);
+            fold_expression(field);
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                fold_expression(key);
+                fold_expression(value);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => fold_expression(operand),
+        Expression::Store(_, address, value) => {
+            fold_expression(address);
+            fold_expression(value);
+        }
+        _ => {}
+    }
+}
+
+fn fold_bin_op(lhs: &Expression, op: &BinOp, rhs: &Expression) -> Option<Expression> {
+    let folded = match (lhs, rhs) {
+        (Expression::Int(l), Expression::Int(r)) => {
+            let (l, r) = (*l, *r);
+            match op {
+                BinOp::Addition => Expression::Int(l.checked_add(r)?),
+                BinOp::Subtraction => Expression::Int(l.checked_sub(r)?),
+                BinOp::Multiplication => Expression::Int(l.checked_mul(r)?),
+                // Division is only folded if it is exact, since the JS backend
+                // does not truncate the result
+                BinOp::Division if r != 0 && l % r == 0 => Expression::Int(l / r),
+                BinOp::Modulus if r != 0 => Expression::Int(l % r),
+                BinOp::LessThan => Expression::Bool(l < r),
+                BinOp::LessThanOrEqual => Expression::Bool(l <= r),
+                BinOp::GreaterThan => Expression::Bool(l > r),
+                BinOp::GreaterThanOrEqual => Expression::Bool(l >= r),
+                BinOp::Equal => Expression::Bool(l == r),
+                BinOp::NotEqual => Expression::Bool(l != r),
+                _ => return None,
+            }
+        }
+        (Expression::Bool(l), Expression::Bool(r)) => match op {
+            BinOp::And => Expression::Bool(*l && *r),
+            BinOp::Or => Expression::Bool(*l || *r),
+            BinOp::Equal => Expression::Bool(l == r),
+            BinOp::NotEqual => Expression::Bool(l != r),
+            _ => return None,
+        },
+        (Expression::Str(l), Expression::Str(r)) => match op {
+            BinOp::Addition => Expression::Str(format!("{}{}", l, r)),
+            BinOp::Equal => Expression::Bool(l == r),
+            BinOp::NotEqual => Expression::Bool(l != r),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    match folded {
+        Expression::Int(val) if val > MAX_FOLDED_INT => None,
+        other => Some(other),
+    }
+}
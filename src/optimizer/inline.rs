@@ -0,0 +1,499 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::dead_code::collect_calls;
+use crate::ast::attributes::{INLINE, NOINLINE};
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Maximum number of AST nodes of a function that is inlined without `@inline`
+const INLINE_THRESHOLD: usize = 20;
+
+/// Replaces calls to small, non-recursive functions with their bodies.
+///
+/// Only calls that make up a whole statement (e.g. `foo()`, `let x = foo()`,
+/// `x = foo()` or `return foo()`) are inlined, so the evaluation order of the
+/// surrounding code is preserved. Arguments are bound to fresh local variables
+/// and every local of the inlined function is renamed to avoid collisions.
+pub(super) fn inline_functions(module: &mut Module) {
+    let candidates: HashMap<String, Function> = module
+        .func
+        .iter()
+        .filter(|func| is_inlinable(func, module))
+        .map(|func| (func.name.clone(), func.clone()))
+        .collect();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    let mut counter = 0;
+    for func in &mut module.func {
+        inline_statement(&mut func.body, &candidates, &mut counter);
+    }
+    for def in &mut module.structs {
+        for method in &mut def.methods {
+            inline_statement(&mut method.body, &candidates, &mut counter);
+        }
+    }
+}
+
+fn is_inlinable(func: &Function, module: &Module) -> bool {
+    // Calls of async functions are awaited, which only applies to calls
+    if func.has_attribute(NOINLINE) || func.name == "main" || func.is_async {
+        return false;
+    }
+    if !func.has_attribute(INLINE) && count_nodes(&func.body) > INLINE_THRESHOLD {
+        return false;
+    }
+
+    let statements = match &func.body {
+        Statement::Block(statements, _) => statements,
+        _ => return false,
+    };
+
+    // Arguments are bound to variables, which would share structs with the caller instead of copying them
+    let copies_struct = |arg: &Variable| !arg.reference && matches!(arg.ty, Some(Type::Struct(_)));
+    if func.arguments.iter().any(copies_struct) {
+        return false;
+    }
+
+    // Deferred expressions of the body would run when the block of the caller is left
+    if statements.iter().any(|s| matches!(s, Statement::Defer(_))) {
+        return false;
+    }
+
+    // A return in the middle of the body would return from the caller
+    let (last, rest) = match statements.split_last() {
+        Some(split) => split,
+        None => return true,
+    };
+    if rest.iter().any(contains_return) || matches!(last, Statement::Return(None)) {
+        return false;
+    }
+    if !matches!(last, Statement::Return(_)) && contains_return(last) {
+        return false;
+    }
+    // Inline code refers to the locals by their names, which are changed by inlining
+    if contains_inline_code(&func.body) {
+        return false;
+    }
+
+    !is_recursive(&func.name, module)
+}
+
+/// Returns true if the function can reach itself through the call graph
+fn is_recursive(name: &str, module: &Module) -> bool {
+    let mut seen = HashSet::new();
+    let mut queue = Vec::new();
+    if let Some(func) = module.func.iter().find(|f| f.name == name) {
+        collect_calls(&func.body, &mut queue);
+    }
+
+    while let Some(callee) = queue.pop() {
+        if callee == name {
+            return true;
+        }
+        if !seen.insert(callee.clone()) {
+            continue;
+        }
+        if let Some(func) = module.func.iter().find(|f| f.name == callee) {
+            collect_calls(&func.body, &mut queue);
+        }
+    }
+
+    false
+}
+
+fn inline_statement(
+    statement: &mut Statement,
+    candidates: &HashMap<String, Function>,
+    counter: &mut usize,
+) {
+    match statement {
+        Statement::Block(statements, _) => {
+            let mut inlined = Vec::new();
+            for mut statement in statements.drain(..) {
+                match expand_call(&statement, candidates, counter) {
+                    Some(expanded) => inlined.extend(expanded),
+                    None => {
+                        inline_statement(&mut statement, candidates, counter);
+                        inlined.push(statement);
+                    }
+                }
+            }
+            *statements = inlined;
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            inline_statement(if_branch, candidates, counter);
+            if let Some(else_branch) = else_branch {
+                inline_statement(else_branch, candidates, counter);
+            }
+        }
+        Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+            inline_statement(body, candidates, counter)
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => {
+                        inline_statement(s, candidates, counter)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Expands a statement consisting of a call to an inlinable function
+fn expand_call(
+    statement: &Statement,
+    candidates: &HashMap<String, Function>,
+    counter: &mut usize,
+) -> Option<Vec<Statement>> {
+    let call = match statement {
+        Statement::Exp(expr)
+        | Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr)) => expr,
+        Statement::Assign(_, expr) => expr,
+        _ => return None,
+    };
+    let (name, args) = match call {
+        Expression::FunctionCall(name, args) => (name, args),
+        _ => return None,
+    };
+    let callee = candidates.get(name)?;
+    if callee.arguments.len() != args.len() {
+        return None;
+    }
+
+    *counter += 1;
+    let mut renames = HashMap::new();
+    for param in &callee.arguments {
+        renames.insert(param.name.clone(), inlined_name(*counter, &param.name));
+    }
+    collect_declarations(&callee.body, *counter, &mut renames);
+
+    // Bind arguments to fresh variables
+    let mut expanded: Vec<Statement> = callee
+        .arguments
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| {
+            Statement::Declare(
+                Variable {
+                    name: renames[&param.name].clone(),
+                    ty: param.ty.clone(),
+                    pos: None,
+                    reference: false,
+                },
+                Some(arg.clone()),
+            )
+        })
+        .collect();
+
+    let mut body = callee.body.clone();
+    rename_statement(&mut body, &renames);
+    let mut statements = match body {
+        Statement::Block(statements, _) => statements,
+        _ => return None,
+    };
+
+    let result = match statements.last() {
+        Some(Statement::Return(Some(_))) => match statements.pop() {
+            Some(Statement::Return(value)) => value,
+            _ => unreachable!(),
+        },
+        _ => None,
+    };
+    expanded.append(&mut statements);
+
+    match (statement, result) {
+        (Statement::Exp(_), Some(result)) => expanded.push(Statement::Exp(result)),
+        (Statement::Exp(_), None) => {}
+        (Statement::Declare(var, _), result) => {
+            expanded.push(Statement::Declare(var.clone(), result))
+        }
+        (Statement::Assign(lhs, _), Some(result)) => {
+            expanded.push(Statement::Assign(lhs.clone(), Box::new(result)))
+        }
+        (Statement::Return(_), Some(result)) => expanded.push(Statement::Return(Some(result))),
+        // Using the result of a function that does not return anything
+        _ => return None,
+    }
+
+    Some(expanded)
+}
+
+fn inlined_name(counter: usize, name: &str) -> String {
+    format!("_inl{}_{}", counter, name)
+}
+
+fn collect_declarations(
+    statement: &Statement,
+    counter: usize,
+    renames: &mut HashMap<String, String>,
+) {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter()
+            .for_each(|s| collect_declarations(s, counter, renames)),
+        Statement::Declare(var, _) => {
+            renames.insert(var.name.clone(), inlined_name(counter, &var.name));
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            collect_declarations(if_branch, counter, renames);
+            if let Some(else_branch) = else_branch {
+                collect_declarations(else_branch, counter, renames);
+            }
+        }
+        Statement::While(_, body) | Statement::Unsafe(body) => {
+            collect_declarations(body, counter, renames)
+        }
+        Statement::For(var, _, body) => {
+            renames.insert(var.name.clone(), inlined_name(counter, &var.name));
+            collect_declarations(body, counter, renames);
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, s) | MatchArm::Else(s) => {
+                        collect_declarations(s, counter, renames)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn rename_variable(var: &mut Variable, renames: &HashMap<String, String>) {
+    if let Some(renamed) = renames.get(&var.name) {
+        var.name = renamed.clone();
+    }
+}
+
+fn rename_statement(statement: &mut Statement, renames: &HashMap<String, String>) {
+    match statement {
+        Statement::Block(statements, scope) => {
+            statements
+                .iter_mut()
+                .for_each(|s| rename_statement(s, renames));
+            scope.iter_mut().for_each(|v| rename_variable(v, renames));
+        }
+        Statement::Declare(var, expr) => {
+            rename_variable(var, renames);
+            if let Some(expr) = expr {
+                rename_expression(expr, renames);
+            }
+        }
+        Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => rename_expression(expr, renames),
+        Statement::Assign(lhs, rhs) => {
+            rename_expression(lhs, renames);
+            rename_expression(rhs, renames);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            rename_expression(cond, renames);
+            rename_statement(if_branch, renames);
+            if let Some(else_branch) = else_branch {
+                rename_statement(else_branch, renames);
+            }
+        }
+        Statement::While(cond, body) => {
+            rename_expression(cond, renames);
+            rename_statement(body, renames);
+        }
+        Statement::For(var, expr, body) => {
+            rename_variable(var, renames);
+            rename_expression(expr, renames);
+            rename_statement(body, renames);
+        }
+        Statement::Match(subject, arms) => {
+            rename_expression(subject, renames);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        rename_expression(expr, renames);
+                        rename_statement(s, renames);
+                    }
+                    MatchArm::Else(s) => rename_statement(s, renames),
+                }
+            }
+        }
+        Statement::Unsafe(body) => rename_statement(body, renames),
+        _ => {}
+    }
+}
+
+fn rename_expression(expr: &mut Expression, renames: &HashMap<String, String>) {
+    match expr {
+        Expression::Variable(name) => {
+            if let Some(renamed) = renames.get(name) {
+                *name = renamed.clone();
+            }
+        }
+        Expression::ArrayAccess(name, index) => {
+            if let Some(renamed) = renames.get(name) {
+                *name = renamed.clone();
+            }
+            rename_expression(index, renames);
+        }
+        Expression::BinOp(lhs, _, rhs) => {
+            rename_expression(lhs, renames);
+            rename_expression(rhs, renames);
+        }
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => elements
+            .iter_mut()
+            .for_each(|e| rename_expression(e, renames)),
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|e| rename_expression(e, renames)),
+        Expression::StructUpdate(_, base, fields) => {
+            rename_expression(base, renames);
+            fields
+                .values_mut()
+                .for_each(|e| rename_expression(e, renames))
+        }
+        Expression::FieldAccess(obj, field) => {
+            rename_expression(obj, renames);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut().for_each(|e| rename_expression(e, renames));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                rename_expression(key, renames);
+                rename_expression(value, renames);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => rename_expression(operand, renames),
+        Expression::Store(_, address, value) => {
+            rename_expression(address, renames);
+            rename_expression(value, renames);
+        }
+        _ => {}
+    }
+}
+
+fn contains_return(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) => true,
+        Statement::Block(statements, _) => statements.iter().any(contains_return),
+        Statement::If(_, if_branch, else_branch) => {
+            contains_return(if_branch) || else_branch.as_deref().is_some_and(contains_return)
+        }
+        Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+            contains_return(body)
+        }
+        Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+            MatchArm::Case(_, s) | MatchArm::Else(s) => contains_return(s),
+        }),
+        _ => false,
+    }
+}
+
+fn contains_inline_code(statement: &Statement) -> bool {
+    match statement {
+        Statement::Inline(..) => true,
+        Statement::Block(statements, _) => statements.iter().any(contains_inline_code),
+        Statement::If(_, if_branch, else_branch) => {
+            contains_inline_code(if_branch)
+                || else_branch.as_deref().is_some_and(contains_inline_code)
+        }
+        Statement::While(_, body) | Statement::For(_, _, body) | Statement::Unsafe(body) => {
+            contains_inline_code(body)
+        }
+        Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+            MatchArm::Case(_, s) | MatchArm::Else(s) => contains_inline_code(s),
+        }),
+        _ => false,
+    }
+}
+
+/// Approximates the size of a function body by counting its statements and expressions
+fn count_nodes(statement: &Statement) -> usize {
+    1 + match statement {
+        Statement::Block(statements, _) => statements.iter().map(count_nodes).sum(),
+        Statement::Declare(_, Some(expr))
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => count_expression_nodes(expr),
+        Statement::Assign(lhs, rhs) => count_expression_nodes(lhs) + count_expression_nodes(rhs),
+        Statement::If(cond, if_branch, else_branch) => {
+            count_expression_nodes(cond)
+                + count_nodes(if_branch)
+                + else_branch.as_deref().map_or(0, count_nodes)
+        }
+        Statement::While(cond, body) | Statement::For(_, cond, body) => {
+            count_expression_nodes(cond) + count_nodes(body)
+        }
+        Statement::Match(subject, arms) => {
+            count_expression_nodes(subject)
+                + arms
+                    .iter()
+                    .map(|arm| match arm {
+                        MatchArm::Case(expr, s) => count_expression_nodes(expr) + count_nodes(s),
+                        MatchArm::Else(s) => count_nodes(s),
+                    })
+                    .sum::<usize>()
+        }
+        Statement::Unsafe(body) => count_nodes(body),
+        _ => 0,
+    }
+}
+
+fn count_expression_nodes(expr: &Expression) -> usize {
+    1 + match expr {
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => {
+            count_expression_nodes(lhs) + count_expression_nodes(rhs)
+        }
+        Expression::ArrayAccess(_, index)
+        | Expression::Try(index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index)
+        | Expression::Load(_, index) => count_expression_nodes(index),
+        Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => {
+            elements.iter().map(count_expression_nodes).sum()
+        }
+        Expression::StructInitialization(_, fields) => {
+            fields.values().map(|e| count_expression_nodes(e)).sum()
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            count_expression_nodes(base)
+                + fields
+                    .values()
+                    .map(|e| count_expression_nodes(e))
+                    .sum::<usize>()
+        }
+        Expression::Map(entries) => entries
+            .iter()
+            .map(|(key, value)| count_expression_nodes(key) + count_expression_nodes(value))
+            .sum(),
+        _ => 0,
+    }
+}
@@ -0,0 +1,119 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::{Function, Module, Statement};
+use crate::timings::Timings;
+use std::collections::{HashMap, HashSet};
+
+mod comptime;
+mod dead_code;
+mod escape;
+mod fold;
+mod inline;
+#[cfg(test)]
+mod tests;
+
+/// Runs all optimization passes enabled by the given level on the module.
+///
+/// Level 0 leaves the module untouched.
+/// Level 1 propagates and folds constants and eliminates dead code.
+/// Level 2 additionally inlines small functions.
+pub fn optimize(module: Module, level: u8) -> Module {
+    optimize_timed(module, level, &mut Timings::disabled())
+}
+
+/// Like [`optimize`], but measures the time of each pass
+pub fn optimize_timed(mut module: Module, level: u8, timings: &mut Timings) -> Module {
+    if level == 0 {
+        return module;
+    }
+
+    if level >= 2 {
+        timings.time("Inlining", || inline::inline_functions(&mut module));
+    }
+
+    // Each pass only changes the function it is applied to
+    timings.time("Constant propagation", || {
+        for_each_function(&mut module, fold::propagate_constants)
+    });
+    timings.time("Constant folding", || {
+        for_each_function(&mut module, fold::fold_function)
+    });
+    timings.time("Unreachable code", || {
+        for_each_function(&mut module, |func| {
+            dead_code::remove_unreachable(&mut func.body)
+        })
+    });
+    timings.time("Unused variables", || {
+        for_each_function(&mut module, dead_code::remove_unused_variables)
+    });
+    timings.time("Uncalled functions", || {
+        dead_code::remove_uncalled_functions(&mut module)
+    });
+
+    module
+}
+
+/// Applies a pass to every function and method of the module
+fn for_each_function(module: &mut Module, mut pass: impl FnMut(&mut Function)) {
+    for func in &mut module.func {
+        pass(func);
+    }
+    for def in &mut module.structs {
+        for method in &mut def.methods {
+            pass(method);
+        }
+    }
+}
+
+/// Removes the functions and structs that the program does not use, which includes the unused
+/// functions of the standard library. Unlike [`optimize`], this also applies to libraries
+/// without a `main` function and to structs.
+pub fn remove_unused_declarations(module: &mut Module, stdlib: &HashSet<String>) {
+    dead_code::remove_unused_declarations(module, stdlib)
+}
+
+/// Replaces calls of functions annotated with `@comptime` whose arguments are constant by
+/// their result. Unlike [`optimize`], this applies to every optimization level, and fails if
+/// a call can not be evaluated, e.g. because it divides by zero or does not finish in time.
+pub fn evaluate_comptime(module: &mut Module) -> Result<(), String> {
+    comptime::evaluate_calls(module)
+}
+
+/// Returns the names of the functions called by a function, in the order of the calls
+pub fn function_calls(func: &Function) -> Vec<String> {
+    let mut calls = Vec::new();
+    dead_code::collect_calls(&func.body, &mut calls);
+    calls
+}
+
+/// Returns the names of all functions called by the functions, methods and globals of the module
+pub fn called_functions(module: &Module) -> HashSet<String> {
+    let mut calls = Vec::new();
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        dead_code::collect_calls(&func.body, &mut calls);
+    }
+    for global in &module.globals {
+        dead_code::collect_calls(&Statement::Exp(global.value.clone()), &mut calls);
+    }
+    calls.into_iter().collect()
+}
+
+/// Returns the arrays of each function that can be allocated on the stack,
+/// because no reference to them outlives the call of the function
+pub fn stack_arrays(module: &Module) -> HashMap<String, HashSet<String>> {
+    escape::stack_arrays(module)
+}
@@ -0,0 +1,356 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::*;
+use crate::lexer::tokenize;
+use crate::optimizer::{optimize, stack_arrays};
+use crate::parser::parse;
+
+fn optimize_raw(raw: &str) -> Module {
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    optimize(module, 1)
+}
+
+fn body_of<'a>(module: &'a Module, name: &str) -> &'a Vec<Statement> {
+    let func = module.func.iter().find(|f| f.name == name).unwrap();
+    match &func.body {
+        Statement::Block(statements, _) => statements,
+        _ => panic!("Function body should be a block"),
+    }
+}
+
+#[test]
+fn test_fold_constants() {
+    let raw = "
+    fn main() {
+        println(2 * 21)
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert_eq!(
+        body_of(&module, "main")[0],
+        Statement::Exp(Expression::FunctionCall(
+            "println".into(),
+            vec![Expression::Int(42)]
+        ))
+    );
+}
+
+#[test]
+fn test_fold_chains() {
+    let raw = "
+    fn main() {
+        println(10 - 5 - 3)
+        println(2 + 3 * 4)
+    }
+    ";
+    let module = optimize_raw(raw);
+    let printed: Vec<&Expression> = body_of(&module, "main")
+        .iter()
+        .map(|statement| match statement {
+            Statement::Exp(Expression::FunctionCall(_, args)) => &args[0],
+            other => panic!("Unexpected statement {:?}", other),
+        })
+        .collect();
+    assert_eq!(printed, [&Expression::Int(2), &Expression::Int(14)]);
+}
+
+#[test]
+fn test_fold_inexact_division() {
+    let raw = "
+    fn main() {
+        println(5 / 2)
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert!(matches!(
+        &body_of(&module, "main")[0],
+        Statement::Exp(Expression::FunctionCall(_, args)) if matches!(args[0], Expression::BinOp(..))
+    ));
+}
+
+#[test]
+fn test_propagate_constants() {
+    let raw = "
+    fn main() {
+        let x = 20
+        println(x + 1)
+    }
+    ";
+    let module = optimize_raw(raw);
+    let body = body_of(&module, "main");
+    assert_eq!(body.len(), 1);
+    assert_eq!(
+        body[0],
+        Statement::Exp(Expression::FunctionCall(
+            "println".into(),
+            vec![Expression::Int(21)]
+        ))
+    );
+}
+
+#[test]
+fn test_no_propagation_of_reassigned_variables() {
+    let raw = "
+    fn main() {
+        let x = 20
+        x = 5
+        println(x)
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert_eq!(body_of(&module, "main").len(), 3);
+}
+
+#[test]
+fn test_remove_unreachable_statements() {
+    let raw = "
+    fn main() {
+        return 1
+        println(1)
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert_eq!(
+        body_of(&module, "main"),
+        &vec![Statement::Return(Some(Expression::Int(1)))]
+    );
+}
+
+#[test]
+fn test_constant_condition() {
+    let raw = "
+    fn main() {
+        if 1 > 2 {
+            println(1)
+        } else {
+            println(2)
+        }
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert!(matches!(
+        &body_of(&module, "main")[0],
+        Statement::Block(statements, _) if statements.len() == 1
+    ));
+}
+
+#[test]
+fn test_keep_side_effects_of_unused_variables() {
+    let raw = "
+    fn main() {
+        let x = foo()
+    }
+
+    fn foo(): int {
+        return 1
+    }
+    ";
+    let module = optimize_raw(raw);
+    assert_eq!(
+        body_of(&module, "main"),
+        &vec![Statement::Exp(Expression::FunctionCall(
+            "foo".into(),
+            Vec::new()
+        ))]
+    );
+}
+
+#[test]
+fn test_remove_uncalled_functions() {
+    let raw = "
+    fn main() {
+        foo()
+    }
+
+    fn foo() {}
+
+    fn bar() {}
+    ";
+    let module = optimize_raw(raw);
+    let names: Vec<String> = module.func.iter().map(|f| f.name.clone()).collect();
+    assert_eq!(names, vec!["main".to_string(), "foo".to_string()]);
+}
+
+#[test]
+fn test_keep_functions_without_main() {
+    let raw = "
+    fn foo() {}
+
+    fn bar() {}
+    ";
+    let module = optimize_raw(raw);
+    assert_eq!(module.func.len(), 2);
+}
+
+fn optimize_raw_level(raw: &str, level: u8) -> Module {
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    optimize(module, level)
+}
+
+#[test]
+fn test_inline_small_function() {
+    let raw = "
+    fn main() {
+        let y = add_one(foo())
+        println(y)
+    }
+
+    fn add_one(x: int): int {
+        return x + 1
+    }
+    ";
+    let module = optimize_raw_level(raw, 2);
+    assert!(module.func.iter().all(|f| f.name != "add_one"));
+    let body = body_of(&module, "main");
+    assert_eq!(
+        body[0],
+        Statement::Declare(
+            Variable {
+                name: "_inl1_x".into(),
+                ty: Some(crate::ast::types::Type::Int),
+                pos: None,
+                reference: false,
+            },
+            Some(Expression::FunctionCall("foo".into(), Vec::new()))
+        )
+    );
+    assert_eq!(
+        body[1],
+        Statement::Declare(
+            Variable {
+                name: "y".into(),
+                ty: Some(crate::ast::types::Type::Int),
+                pos: None,
+                reference: false,
+            },
+            Some(Expression::BinOp(
+                Box::new(Expression::Variable("_inl1_x".into())),
+                BinOp::Addition,
+                Box::new(Expression::Int(1))
+            ))
+        )
+    );
+}
+
+#[test]
+fn test_no_inline_of_recursive_function() {
+    let raw = "
+    fn main() {
+        println(fib(5))
+    }
+
+    fn fib(n: int): int {
+        if n < 2 {
+            return n
+        }
+        return fib(n - 1) + fib(n - 2)
+    }
+    ";
+    let module = optimize_raw_level(raw, 2);
+    assert!(module.func.iter().any(|f| f.name == "fib"));
+}
+
+#[test]
+fn test_noinline_attribute() {
+    let raw = "
+    fn main() {
+        foo()
+    }
+
+    @noinline
+    fn foo() {
+        println(1)
+    }
+    ";
+    let module = optimize_raw_level(raw, 2);
+    assert!(module.func.iter().any(|f| f.name == "foo"));
+}
+
+#[test]
+fn test_inline_attribute_ignores_threshold() {
+    let raw = "
+    fn main() {
+        foo()
+    }
+
+    @inline
+    fn foo() {
+        println(1)
+        println(2)
+        println(3)
+        println(4)
+        println(5)
+        println(6)
+        println(7)
+        println(8)
+    }
+    ";
+    let module = optimize_raw_level(raw, 2);
+    assert!(module.func.iter().all(|f| f.name != "foo"));
+    assert_eq!(body_of(&module, "main").len(), 8);
+}
+
+#[test]
+fn test_stack_arrays_exclude_escaping_arrays() {
+    let raw = "
+    fn keep(arr: int[]): int[] {
+        return arr
+    }
+
+    fn first(arr: int[]): int {
+        return arr[0]
+    }
+
+    fn main() {
+        let local = [1, 2]
+        let stored = [3, 4]
+        first(local)
+        let kept = keep(stored)
+        println(kept)
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let arrays = &stack_arrays(&module)["main"];
+    assert!(arrays.contains("local"));
+    assert!(!arrays.contains("stored"));
+}
+
+#[test]
+fn test_stack_arrays_of_functions_with_the_same_name() {
+    let raw = "
+    fn foo(x: int[]) {
+        println(x[0])
+    }
+
+    fn main() {
+        let a = [1, 2]
+        foo(a)
+    }
+
+    fn main() {
+        let b = [3, 4]
+        println(b[1])
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    // Terminates, and only keeps arrays that neither of the functions lets escape
+    let arrays = &stack_arrays(&module)["main"];
+    assert!(arrays.is_empty());
+}
@@ -0,0 +1,328 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::manifest::{Dependency, Project};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(test)]
+mod tests;
+
+/// Dependency name -> path of the entrypoint of the package
+pub type Packages = HashMap<String, PathBuf>;
+
+/// File name of the lockfile, located next to the manifest
+pub const LOCK_FILE: &str = "sb.lock";
+
+/// Returns the directory fetched packages are stored in.
+/// Defaults to `~/.antimony`, but can be changed with the `ANTIMONY_HOME` environment variable.
+pub fn cache_dir() -> Result<PathBuf, String> {
+    if let Some(dir) = env::var_os("ANTIMONY_HOME") {
+        return Ok(PathBuf::from(dir));
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".antimony"))
+        .ok_or_else(|| "Could not determine home directory, set ANTIMONY_HOME instead".into())
+}
+
+/// Exact versions of all git dependencies, so builds are reproducible
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub git: String,
+    /// Commit that is checked out
+    pub rev: String,
+}
+
+impl Lockfile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let lockfile: Self =
+            toml::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // Both are passed to git, which would read them as options
+        for package in &lockfile.packages {
+            if package.git.starts_with('-') || package.rev.starts_with('-') {
+                return Err(format!(
+                    "{}: Package '{}' has an invalid git url or revision",
+                    path.display(),
+                    package.name
+                ));
+            }
+        }
+        Ok(lockfile)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let raw = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(
+            path,
+            format!(
+                "# This file is generated by sb. Do not edit it manually.\n\n{}",
+                raw
+            ),
+        )
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    fn find(&self, name: &str, git: &str) -> Option<&LockedPackage> {
+        self.packages
+            .iter()
+            .find(|package| package.name == name && package.git == git)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Path(PathBuf),
+    Git(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Git(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Resolves all dependencies of a project, including the dependencies of its dependencies.
+/// Git dependencies are fetched into the cache and pinned in the lockfile of the project.
+pub fn resolve(project: &Project, cache: &Path) -> Result<Packages, String> {
+    let lock_path = project.root.join(LOCK_FILE);
+    let mut resolver = Resolver {
+        cache: cache.to_path_buf(),
+        previous: Lockfile::load(&lock_path)?,
+        locked: Lockfile::default(),
+        packages: BTreeMap::new(),
+    };
+    resolver.resolve_project(project)?;
+
+    let mut locked = resolver.locked;
+    locked.packages.sort_by(|a, b| a.name.cmp(&b.name));
+    if locked != resolver.previous && (lock_path.exists() || !locked.packages.is_empty()) {
+        locked.save(&lock_path)?;
+    }
+
+    Ok(resolver
+        .packages
+        .into_iter()
+        .map(|(name, (_, entry))| (name, entry))
+        .collect())
+}
+
+struct Resolver {
+    cache: PathBuf,
+    /// Lockfile of the previous resolution
+    previous: Lockfile,
+    locked: Lockfile,
+    /// Name -> source and entrypoint of all resolved packages
+    packages: BTreeMap<String, (Source, PathBuf)>,
+}
+
+impl Resolver {
+    fn resolve_project(&mut self, project: &Project) -> Result<(), String> {
+        for (name, dependency) in &project.manifest.dependencies {
+            let source = match dependency {
+                Dependency {
+                    path: Some(path), ..
+                } => {
+                    let path = project.root.join(path);
+                    Source::Path(path.canonicalize().map_err(|_| {
+                        format!("Could not find dependency '{}' at {}", name, path.display())
+                    })?)
+                }
+                Dependency { git: Some(url), .. } => Source::Git(url.clone()),
+                _ => return Err(format!("Dependency '{}' has no source", name)),
+            };
+
+            if let Some((existing, _)) = self.packages.get(name) {
+                if *existing != source {
+                    return Err(format!(
+                        "Package '{}' is required from two different sources: {} and {}",
+                        name, existing, source
+                    ));
+                }
+                continue;
+            }
+
+            let root = match &source {
+                Source::Path(path) => path.clone(),
+                Source::Git(url) => self.fetch(name, url)?,
+            };
+            let package = Project::load(&root)?;
+            if package.manifest.package.name != *name {
+                return Err(format!(
+                    "Dependency '{}' refers to package '{}' ({})",
+                    name, package.manifest.package.name, source
+                ));
+            }
+
+            self.packages
+                .insert(name.clone(), (source, package.entry()));
+            self.resolve_project(&package)?;
+        }
+        Ok(())
+    }
+
+    /// Checks out a git dependency in the cache and returns its directory.
+    /// If the dependency is locked, the locked commit is used.
+    fn fetch(&mut self, name: &str, url: &str) -> Result<PathBuf, String> {
+        let dir = checkout_dir(&self.cache, name, url);
+
+        let fresh = !dir.exists();
+        if fresh {
+            eprintln!("Fetching {}", url);
+            fs::create_dir_all(self.cache.join("git"))
+                .map_err(|e| format!("Could not create package cache: {}", e))?;
+            git(
+                None,
+                &["clone", "--quiet", "--", url, &dir.to_string_lossy()],
+            )?;
+        }
+
+        // `checkout` can not tell options from revisions, so the revision is resolved to
+        // the hash of its commit first
+        let checkout = |rev: &str| {
+            let commit = format!("{}^{{commit}}", rev);
+            let hash = git(
+                Some(&dir),
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    "--end-of-options",
+                    &commit,
+                ],
+            )?;
+            git(Some(&dir), &["checkout", "--quiet", "--detach", &hash])
+        };
+        match self.previous.find(name, url) {
+            // The locked commit may not have been fetched yet
+            Some(locked) if checkout(&locked.rev).is_err() => {
+                eprintln!("Fetching {}", url);
+                git(Some(&dir), &["fetch", "--quiet", "origin"])?;
+                checkout(&locked.rev)?;
+            }
+            Some(_) => {}
+            // Dependencies that are not locked yet use the latest commit of the default branch
+            None if !fresh => {
+                eprintln!("Fetching {}", url);
+                git(Some(&dir), &["fetch", "--quiet", "origin"])?;
+                checkout("origin/HEAD")?;
+            }
+            None => {}
+        }
+
+        let rev = git(Some(&dir), &["rev-parse", "HEAD"])?;
+        self.locked.packages.push(LockedPackage {
+            name: name.to_string(),
+            git: url.to_string(),
+            rev,
+        });
+        Ok(dir)
+    }
+}
+
+/// Directory in the cache a git dependency is checked out into. Both the name and the url
+/// only contribute letters and digits, so the directory can not be outside of the cache.
+fn checkout_dir(cache: &Path, name: &str, url: &str) -> PathBuf {
+    let sanitize = |s: &str| -> String {
+        s.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    };
+    cache
+        .join("git")
+        .join(format!("{}-{}", sanitize(name), sanitize(url)))
+}
+
+/// Runs git and returns its trimmed output
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<String, String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|e| format!("Could not run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Adds a dependency to the given manifest source, keeping its formatting
+pub fn add_dependency(manifest: &str, name: &str, dependency: &Dependency) -> String {
+    let source = match dependency {
+        Dependency {
+            path: Some(path), ..
+        } => format!(
+            "path = {}",
+            toml::Value::String(path.to_string_lossy().to_string())
+        ),
+        Dependency { git: Some(url), .. } => {
+            format!("git = {}", toml::Value::String(url.clone()))
+        }
+        _ => String::new(),
+    };
+    let entry = format!("{} = {{ {} }}\n", name, source);
+
+    let mut lines: Vec<&str> = manifest.lines().collect();
+    match lines
+        .iter()
+        .position(|line| line.trim() == "[dependencies]")
+    {
+        Some(header) => {
+            // Insert after the last entry of the section
+            let mut end = header + 1;
+            while end < lines.len() && !lines[end].trim_start().starts_with('[') {
+                end += 1;
+            }
+            while end > header + 1 && lines[end - 1].trim().is_empty() {
+                end -= 1;
+            }
+            lines.insert(end, entry.trim_end());
+            lines.join("\n") + "\n"
+        }
+        None => format!(
+            "{}\n[dependencies]\n{}",
+            manifest.trim_end_matches('\n').to_string() + "\n",
+            entry
+        ),
+    }
+}
@@ -0,0 +1,191 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::builder::resolve_import;
+use crate::manifest::{Dependency, Project};
+use crate::package::*;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Creates a package with the given dependencies in a temporary directory
+fn create_package(root: &Path, name: &str, dependencies: &str) -> PathBuf {
+    let dir = root.join(name);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(
+        dir.join("sb.toml"),
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}",
+            name, dependencies
+        ),
+    )
+    .unwrap();
+    fs::write(dir.join("src").join("main.sb"), "fn foo() {}\n").unwrap();
+    dir
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+#[test]
+fn test_resolve_import_of_package() {
+    let mut packages = Packages::new();
+    packages.insert("math".into(), PathBuf::from("/deps/math/src/main.sb"));
+    let importer = Path::new("src/main.sb");
+
+    assert_eq!(
+        resolve_import(importer, "math", &packages),
+        PathBuf::from("/deps/math/src/main.sb")
+    );
+    assert_eq!(
+        resolve_import(importer, "math/vector", &packages),
+        PathBuf::from("/deps/math/src/vector.sb")
+    );
+    assert_eq!(
+        resolve_import(importer, "foo", &packages),
+        PathBuf::from("src/foo.sb")
+    );
+}
+
+#[test]
+fn test_resolve_path_dependencies() {
+    let root = temp_dir("sb_test_resolve_path_dependencies");
+    create_package(&root, "b", "");
+    create_package(&root, "a", "b = { path = \"../b\" }\n");
+    let app = create_package(&root, "app", "a = { path = \"../a\" }\n");
+
+    let packages = resolve(&Project::load(&app).unwrap(), &root.join("cache")).unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages["a"], root.join("a").join("src").join("main.sb"));
+    assert_eq!(packages["b"], root.join("b").join("src").join("main.sb"));
+    // Path dependencies are not locked
+    assert!(!app.join(LOCK_FILE).exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_conflicting_sources() {
+    let root = temp_dir("sb_test_conflicting_sources");
+    create_package(&root, "b", "");
+    fs::create_dir_all(root.join("other")).unwrap();
+    create_package(&root.join("other"), "b", "");
+    create_package(&root, "a", "b = { path = \"../other/b\" }\n");
+    let app = create_package(
+        &root,
+        "app",
+        "a = { path = \"../a\" }\nb = { path = \"../b\" }\n",
+    );
+
+    let result = resolve(&Project::load(&app).unwrap(), &root.join("cache"));
+    assert!(result
+        .unwrap_err()
+        .contains("is required from two different sources"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_git_dependency_is_locked() {
+    let root = temp_dir("sb_test_git_dependency_is_locked");
+    let repo = create_package(&root, "lib", "");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+    };
+    git(&["init", "--quiet"]);
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "Initial commit"]);
+
+    let url = repo.display().to_string();
+    let app = create_package(&root, "app", &format!("lib = {{ git = \"{}\" }}\n", url));
+    let cache = root.join("cache");
+    let packages = resolve(&Project::load(&app).unwrap(), &cache).unwrap();
+    assert!(packages["lib"].starts_with(cache.join("git")));
+
+    let lockfile = Lockfile::load(&app.join(LOCK_FILE)).unwrap();
+    assert_eq!(lockfile.packages.len(), 1);
+    let rev = lockfile.packages[0].rev.clone();
+
+    // New commits are not used while the dependency is locked
+    fs::write(repo.join("src").join("main.sb"), "fn bar() {}\n").unwrap();
+    git(&["commit", "--quiet", "-am", "Update"]);
+    resolve(&Project::load(&app).unwrap(), &cache).unwrap();
+    let lockfile = Lockfile::load(&app.join(LOCK_FILE)).unwrap();
+    assert_eq!(lockfile.packages[0].rev, rev);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_lockfile_rejects_options() {
+    let root = temp_dir("sb_test_lockfile_rejects_options");
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join(LOCK_FILE);
+    fs::write(
+        &path,
+        "[[package]]\nname = \"lib\"\ngit = \"https://example.com/lib.git\"\nrev = \"--output=pwned\"\n",
+    )
+    .unwrap();
+    assert!(Lockfile::load(&path)
+        .unwrap_err()
+        .contains("Package 'lib' has an invalid git url or revision"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_checkout_dir_stays_in_cache() {
+    let cache = Path::new("cache");
+    assert_eq!(
+        checkout_dir(cache, "../../../tmp/x", "https://example.com/x.git"),
+        cache
+            .join("git")
+            .join("_________tmp_x-https___example_com_x_git")
+    );
+}
+
+#[test]
+fn test_add_dependency() {
+    let dependency = Dependency {
+        path: Some(PathBuf::from("../math")),
+        git: None,
+    };
+
+    let manifest =
+        "[package]\nname = \"app\"\n\n[dependencies]\nfoo = { path = \"foo\" }\n\n[other]\n";
+    assert_eq!(
+        add_dependency(manifest, "math", &dependency),
+        "[package]\nname = \"app\"\n\n[dependencies]\nfoo = { path = \"foo\" }\nmath = { path = \"../math\" }\n\n[other]\n"
+    );
+
+    let manifest = "[package]\nname = \"app\"\n";
+    assert_eq!(
+        add_dependency(manifest, "math", &dependency),
+        "[package]\nname = \"app\"\n\n[dependencies]\nmath = { path = \"../math\" }\n"
+    );
+}
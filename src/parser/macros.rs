@@ -0,0 +1,397 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::parser::Parser;
+use crate::ast::*;
+use crate::lexer::{Keyword, Position, Token, TokenKind};
+use std::collections::HashMap;
+
+/// Maximum number of macro expansions that can be nested in each other
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Definition of a macro, e.g. `macro log(value) { println("[log] " + value) }`.
+/// The body is kept as tokens and parsed again for every expansion,
+/// so it can use macros that are defined after it.
+#[derive(Debug, Clone)]
+pub(super) struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    pos: Position,
+}
+
+impl Parser {
+    /// Parses the definition of a macro, which can be used by the rest of the module
+    pub(super) fn parse_macro_definition(&mut self) -> Result<(), String> {
+        self.match_keyword(Keyword::Macro)?;
+        let pos = self.peek()?.pos;
+        let name = self.match_identifier()?;
+        if self.macros.contains_key(&name) {
+            return Err(self.macro_error(
+                &name,
+                pos,
+                format!("Macro '{}' is already defined", name),
+            ));
+        }
+
+        self.match_token(TokenKind::BraceOpen)?;
+        let mut parameters = Vec::new();
+        while self.peek_token(TokenKind::BraceClose).is_err() {
+            if !parameters.is_empty() {
+                self.match_token(TokenKind::Comma)?;
+            }
+            let param_pos = self.peek()?.pos;
+            let param = self.match_identifier()?;
+            if parameters.contains(&param) {
+                return Err(self.make_error_msg(
+                    param_pos,
+                    format!("Macro '{}' has more than one parameter '{}'", name, param),
+                ));
+            }
+            parameters.push(param);
+        }
+        self.match_token(TokenKind::BraceClose)?;
+
+        // The tokens up to the matching closing brace
+        let mut body = vec![self.match_token(TokenKind::CurlyBracesOpen)?];
+        let mut depth = 1;
+        while depth > 0 {
+            let token = self.next()?;
+            match token.kind {
+                TokenKind::CurlyBracesOpen => depth += 1,
+                TokenKind::CurlyBracesClose => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(
+            name,
+            Macro {
+                parameters,
+                body,
+                pos,
+            },
+        );
+        Ok(())
+    }
+
+    /// Expands a macro that is used as a statement, e.g. `log!(x)`, into a block
+    pub(super) fn expand_macro_statement(
+        &mut self,
+        name: String,
+        pos: Position,
+    ) -> Result<Statement, String> {
+        let (statements, scope) = self.expand_macro(&name, pos)?;
+        Ok(Statement::Block(statements, scope))
+    }
+
+    /// Expands a macro that is used as an expression, e.g. `square!(x)`.
+    /// The body of the macro has to consist of a single expression.
+    pub(super) fn expand_macro_expression(
+        &mut self,
+        name: String,
+        pos: Position,
+    ) -> Result<Expression, String> {
+        let (mut statements, _) = self.expand_macro(&name, pos)?;
+        match (statements.pop(), statements.is_empty()) {
+            (Some(Statement::Exp(expr)), true) => Ok(expr),
+            _ => Err(self.macro_error(
+                &name,
+                pos,
+                format!(
+                    "Macro '{}' expands to statements, so it can not be used as an expression",
+                    name
+                ),
+            )),
+        }
+    }
+
+    /// Parses the arguments of a macro and returns the statements and scoped variables of its body
+    fn expand_macro(
+        &mut self,
+        name: &str,
+        pos: Position,
+    ) -> Result<(Vec<Statement>, Vec<Variable>), String> {
+        self.match_token(TokenKind::Exclamation)?;
+        let args = self.parse_macro_arguments()?;
+        let def = match self.macros.get(name) {
+            Some(def) => def.clone(),
+            None => {
+                return Err(self.make_error_msg(
+                    pos,
+                    format!(
+                        "Macro '{}' is not defined. Macros have to be defined before they are used",
+                        name
+                    ),
+                ))
+            }
+        };
+        if args.len() != def.parameters.len() {
+            return Err(self.macro_error(
+                name,
+                pos,
+                format!(
+                    "Macro '{}' expects {} argument{}, found {}",
+                    name,
+                    def.parameters.len(),
+                    if def.parameters.len() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+            ));
+        }
+        if self.depth >= MAX_EXPANSION_DEPTH {
+            return Err(self.macro_error(
+                name,
+                pos,
+                format!(
+                    "Expansions of macro '{}' are nested more than {} times, does it expand to itself?",
+                    name, MAX_EXPANSION_DEPTH
+                ),
+            ));
+        }
+
+        let id = self.expansions;
+        let mut parser = Parser::new(def.body, self.raw.clone(), self.path.clone());
+        parser.macros = self.macros.clone();
+        parser.expansions = self.expansions + 1;
+        parser.depth = self.depth + 1;
+        let body = parser.parse_block();
+        self.expansions = parser.expansions;
+        let mut body = match body {
+            Ok(body) => body,
+            // Errors in the body point at the definition, so the outermost expansion adds
+            // where the macro has been used
+            Err(error) if self.depth == 0 => {
+                return Err(format!(
+                    "{}\n{}",
+                    error,
+                    self.make_error_msg(pos, format!("In the expansion of macro '{}'", name))
+                ))
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut expansion = Expansion {
+            name,
+            id,
+            arguments: def.parameters.into_iter().zip(args).collect(),
+            renames: HashMap::new(),
+        };
+        if let Err(msg) = expansion.statement(&mut body) {
+            return Err(self.macro_error(name, pos, msg));
+        }
+        match body {
+            Statement::Block(statements, scope) => Ok((statements, scope)),
+            statement => Ok((vec![statement], Vec::new())),
+        }
+    }
+
+    fn parse_macro_arguments(&mut self) -> Result<Vec<Expression>, String> {
+        self.match_token(TokenKind::BraceOpen)?;
+        let mut args = Vec::new();
+        while self.peek_token(TokenKind::BraceClose).is_err() {
+            if !args.is_empty() {
+                self.match_token(TokenKind::Comma)?;
+            }
+            args.push(self.parse_expression()?);
+        }
+        self.match_token(TokenKind::BraceClose)?;
+        Ok(args)
+    }
+
+    /// Reports an error at the use of a macro, followed by a note pointing at its definition
+    fn macro_error(&mut self, name: &str, pos: Position, msg: String) -> String {
+        let error = self.make_error_msg(pos, msg);
+        match self.macros.get(name).map(|def| def.pos) {
+            Some(definition) => format!(
+                "{}\n{}",
+                error,
+                self.make_error_msg(definition, format!("Macro '{}' is defined here", name))
+            ),
+            None => error,
+        }
+    }
+}
+
+/// Substitutes the arguments of an expansion for the parameters of a macro.
+/// Variables declared by the macro get unique names, so they can neither hide
+/// nor be hidden by the variables at the site of the expansion.
+struct Expansion<'a> {
+    name: &'a str,
+    id: usize,
+    arguments: HashMap<String, Expression>,
+    renames: HashMap<String, String>,
+}
+
+impl Expansion<'_> {
+    fn declare(&mut self, var: &mut Variable) -> Result<(), String> {
+        if self.arguments.contains_key(&var.name) {
+            return Err(format!(
+                "Macro '{}' declares a variable with the name of its parameter '{}'",
+                self.name, var.name
+            ));
+        }
+        let renamed = format!("_macro{}_{}", self.id, var.name);
+        self.renames.insert(var.name.clone(), renamed.clone());
+        var.name = renamed;
+        Ok(())
+    }
+
+    fn rename(&self, name: &mut String) {
+        if let Some(renamed) = self.renames.get(name) {
+            *name = renamed.clone();
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Statement) -> Result<(), String> {
+        match statement {
+            Statement::Block(statements, scope) => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                scope.iter_mut().for_each(|var| self.rename(&mut var.name));
+                Ok(())
+            }
+            Statement::Declare(var, value) => {
+                if let Some(value) = value {
+                    self.expression(value)?;
+                }
+                self.declare(var)
+            }
+            Statement::Static(var, value) => {
+                self.expression(value)?;
+                self.declare(var)
+            }
+            Statement::Destructure(fields, value) => {
+                self.expression(value)?;
+                fields.iter_mut().try_for_each(|(_, var)| self.declare(var))
+            }
+            Statement::Assign(lhs, rhs) => {
+                self.expression(lhs)?;
+                self.expression(rhs)
+            }
+            Statement::Return(None) | Statement::Break | Statement::Continue => Ok(()),
+            Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::Defer(expr)
+            | Statement::Spawn(expr) => self.expression(expr),
+            Statement::If(cond, if_branch, else_branch) => {
+                self.expression(cond)?;
+                self.statement(if_branch)?;
+                match else_branch {
+                    Some(else_branch) => self.statement(else_branch),
+                    None => Ok(()),
+                }
+            }
+            Statement::While(cond, body) => {
+                self.expression(cond)?;
+                self.statement(body)
+            }
+            Statement::For(var, iterable, body) => {
+                self.expression(iterable)?;
+                self.declare(var)?;
+                self.statement(body)
+            }
+            Statement::ForPair(key, value, iterable, body) => {
+                self.expression(iterable)?;
+                self.declare(key)?;
+                self.declare(value)?;
+                self.statement(body)
+            }
+            Statement::Match(subject, arms) => {
+                self.expression(subject)?;
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(case, body) => {
+                            self.expression(case)?;
+                            self.statement(body)?;
+                        }
+                        MatchArm::Else(body) => self.statement(body)?,
+                    }
+                }
+                Ok(())
+            }
+            Statement::Unsafe(body) => self.statement(body),
+            // Inline code is passed through verbatim
+            Statement::Inline(..) => Ok(()),
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) -> Result<(), String> {
+        match expr {
+            Expression::Variable(name) => {
+                match self.arguments.get(name.as_str()).cloned() {
+                    Some(arg) => *expr = arg,
+                    None => self.rename(name),
+                }
+                Ok(())
+            }
+            Expression::ArrayAccess(name, index) => {
+                self.expression(index)?;
+                match self.arguments.get(name.as_str()) {
+                    Some(Expression::Variable(arg)) => *name = arg.clone(),
+                    Some(_) => {
+                        return Err(format!(
+                            "Parameter '{}' of macro '{}' is indexed, so its argument has to be a variable",
+                            name, self.name
+                        ))
+                    }
+                    None => self.rename(name),
+                }
+                Ok(())
+            }
+            Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+                self.expression(lhs)?;
+                self.expression(rhs)
+            }
+            Expression::Array(_, elements) | Expression::FunctionCall(_, elements) => elements
+                .iter_mut()
+                .try_for_each(|element| self.expression(element)),
+            Expression::StructInitialization(_, fields) => fields
+                .values_mut()
+                .try_for_each(|value| self.expression(value)),
+            Expression::StructUpdate(_, base, fields) => {
+                self.expression(base)?;
+                fields
+                    .values_mut()
+                    .try_for_each(|value| self.expression(value))
+            }
+            Expression::FieldAccess(obj, field) => {
+                self.expression(obj)?;
+                // Only the arguments of a method call, field names are left alone
+                match &mut **field {
+                    Expression::FunctionCall(_, args) => {
+                        args.iter_mut().try_for_each(|arg| self.expression(arg))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Expression::Map(entries) => entries.iter_mut().try_for_each(|(key, value)| {
+                self.expression(key)?;
+                self.expression(value)
+            }),
+            Expression::Try(operand)
+            | Expression::Await(operand)
+            | Expression::Reference(operand)
+            | Expression::Dereference(operand)
+            | Expression::Load(_, operand) => self.expression(operand),
+            Expression::Int(_)
+            | Expression::Str(_)
+            | Expression::Bool(_)
+            | Expression::Selff
+            | Expression::SizeOf(_) => Ok(()),
+        }
+    }
+}
@@ -13,7 +13,7 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-mod infer;
+mod macros;
 // TODO: Resolve this lint by renaming the module
 #[allow(clippy::module_inception)]
 mod parser;
@@ -23,6 +23,7 @@ use crate::lexer::Token;
 #[cfg(test)]
 mod tests;
 
+/// Parses the tokens of a module. Malformed input is reported as an error, the parser never panics.
 pub fn parse(tokens: Vec<Token>, raw: Option<String>, path: String) -> Result<Module, String> {
     let mut parser = parser::Parser::new(tokens, raw, path);
     parser.parse()
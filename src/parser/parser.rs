@@ -13,12 +13,14 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use super::macros::Macro;
 use crate::ast::*;
 use crate::lexer::Keyword;
 use crate::lexer::Position;
 use crate::lexer::{Token, TokenKind};
-use crate::parser::infer::infer;
+use crate::semantic::infer::infer_variables;
 use crate::util::string_util::highlight_position_in_file;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::iter::Peekable;
 use std::vec::IntoIter;
@@ -29,7 +31,13 @@ pub struct Parser {
     peeked: Vec<Token>,
     current: Option<Token>,
     prev: Option<Token>,
-    raw: Option<String>,
+    pub(super) raw: Option<String>,
+    /// Macros that have been defined so far, by name
+    pub(super) macros: HashMap<String, Macro>,
+    /// Number of macro expansions, used to give the locals of each expansion unique names
+    pub(super) expansions: usize,
+    /// Number of macro expansions the parsed tokens are nested in
+    pub(super) depth: usize,
 }
 
 impl Parser {
@@ -45,13 +53,16 @@ impl Parser {
             current: None,
             prev: None,
             raw,
+            macros: HashMap::new(),
+            expansions: 0,
+            depth: 0,
         }
     }
 
     pub fn parse(&mut self) -> Result<Module, String> {
         let mut program = self.parse_module()?;
         // infer types
-        infer(&mut program);
+        infer_variables(&mut program);
 
         Ok(program)
     }
@@ -69,9 +80,17 @@ impl Parser {
     }
 
     pub(super) fn peek(&mut self) -> Result<Token, String> {
-        let token = self.next()?;
-        self.push(token.to_owned());
-        Ok(token)
+        if self.peeked.is_empty() {
+            let token = self.tokens.next().ok_or("Expected token")?;
+            self.push(token);
+        }
+        Ok(self.peeked.last().cloned().expect("Expected peeked token"))
+    }
+
+    /// Returns true if the next token is on a later line than the last consumed token
+    pub(super) fn starts_line(&mut self) -> Result<bool, String> {
+        let line = self.current.as_ref().map(|token| token.pos.line);
+        Ok(Some(self.peek()?.pos.line) > line)
     }
 
     pub(super) fn push(&mut self, token: Token) {
@@ -2,7 +2,7 @@ use super::parser::Parser;
 use crate::ast::types::Type;
 use crate::ast::*;
 use crate::lexer::Keyword;
-use crate::lexer::{TokenKind, Value};
+use crate::lexer::{Position, TokenKind, Value};
 use std::collections::HashMap;
 /**
  * Copyright 2020 Garrit Franke
@@ -19,25 +19,104 @@ use std::collections::HashMap;
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use std::collections::HashSet;
 use std::convert::TryFrom;
 
 impl Parser {
     pub fn parse_module(&mut self) -> Result<Module, String> {
         let mut functions = Vec::new();
+        let mut externs = Vec::new();
         let mut structs = Vec::new();
-        let mut imports = HashSet::new();
-        let globals = Vec::new();
+        let mut imports = HashMap::new();
+        let mut aliases = HashMap::new();
+        let mut selected = HashMap::new();
+        let mut globals = Vec::new();
 
         while self.has_more() {
             let next = self.peek()?;
             match next.kind {
-                TokenKind::Keyword(Keyword::Function) => functions.push(self.parse_function()?),
+                TokenKind::Keyword(Keyword::Extern) => externs.push(self.parse_extern_function()?),
+                TokenKind::Keyword(Keyword::Macro) => self.parse_macro_definition()?,
                 TokenKind::Keyword(Keyword::Import) => {
-                    imports.insert(self.parse_import()?);
+                    let pos = next.pos;
+                    self.match_keyword(Keyword::Import)?;
+                    match self.peek()?.kind {
+                        TokenKind::Identifier(id) if id == "js" => {
+                            externs.append(&mut self.parse_js_import()?)
+                        }
+                        // import { foo, bar } from "module"
+                        TokenKind::CurlyBracesOpen => {
+                            let (import, names) = self.parse_selective_import()?;
+                            imports.entry(import.clone()).or_insert(pos);
+                            for (name, name_pos) in names {
+                                if selected.contains_key(&name) {
+                                    return Err(self.make_error_msg(
+                                        name_pos,
+                                        format!("'{}' is imported more than once", name),
+                                    ));
+                                }
+                                selected.insert(name, (import.clone(), name_pos));
+                            }
+                        }
+                        _ => {
+                            let import = self.parse_import()?;
+                            imports.entry(import.clone()).or_insert(pos);
+                            // import "lib/math" as math
+                            if matches!(self.peek().map(|t| t.kind), Ok(TokenKind::Identifier(id)) if id == "as")
+                            {
+                                self.match_identifier()?;
+                                let alias = self.peek()?;
+                                let name = self.match_identifier()?;
+                                if aliases.insert(name.clone(), import).is_some() {
+                                    return Err(self.make_error_msg(
+                                        alias.pos,
+                                        format!(
+                                            "Alias '{}' is already used by another import",
+                                            name
+                                        ),
+                                    ));
+                                }
+                            }
+                        }
+                    }
                 }
-                TokenKind::Keyword(Keyword::Struct) => {
-                    structs.push(self.parse_struct_definition()?)
+                TokenKind::At
+                | TokenKind::Keyword(Keyword::Pub)
+                | TokenKind::Keyword(Keyword::Async)
+                | TokenKind::Keyword(Keyword::Function)
+                | TokenKind::Keyword(Keyword::Struct)
+                | TokenKind::Keyword(Keyword::Let)
+                | TokenKind::Keyword(Keyword::Const) => {
+                    let attributes = self.parse_attributes()?;
+                    let public = self.peek_token(TokenKind::Keyword(Keyword::Pub)).is_ok();
+                    if public {
+                        self.match_keyword(Keyword::Pub)?;
+                    }
+                    let next = self.peek()?;
+                    match next.kind {
+                        TokenKind::Keyword(Keyword::Struct) => {
+                            let mut def = self.parse_struct_definition()?;
+                            def.attributes = attributes;
+                            def.public = public;
+                            structs.push(def);
+                        }
+                        TokenKind::Keyword(Keyword::Let) | TokenKind::Keyword(Keyword::Const) => {
+                            if !attributes.is_empty() {
+                                return Err(self.make_error_msg(
+                                    next.pos,
+                                    "Global variables can not have attributes".into(),
+                                ));
+                            }
+                            let mut global = self.parse_global()?;
+                            global.public = public;
+                            globals.push(global);
+                        }
+                        _ => {
+                            let mut func = self.parse_function()?;
+                            func.attributes = attributes;
+                            func.public = public;
+                            functions.push(func);
+                        }
+                    }
                 }
                 _ => return Err(format!("Unexpected token: {}", next.raw)),
             }
@@ -47,27 +126,80 @@ impl Parser {
 
         Ok(Module {
             func: functions,
+            externs,
             structs,
             globals,
             path: self.path.clone(),
             imports,
+            aliases,
+            selected,
+        })
+    }
+
+    /// Parses a module-level `let` or `const` declaration, which has to be initialized
+    fn parse_global(&mut self) -> Result<Global, String> {
+        let constant = self.peek()?.kind == TokenKind::Keyword(Keyword::Const);
+        let (var, value) = self.parse_initialized_variable("Global")?;
+        Ok(Global {
+            var,
+            value,
+            constant,
+            public: false,
         })
     }
 
+    /// Parses the keyword, variable and initializer of a declaration
+    /// that has to be initialized, e.g. `const LIMIT: int = 10`
+    fn parse_initialized_variable(&mut self, kind: &str) -> Result<(Variable, Expression), String> {
+        let next = self.next()?;
+        let pos = Some(self.peek()?.pos);
+        let name = self.match_identifier()?;
+        let ty = match self.peek()?.kind {
+            TokenKind::Colon => Some(self.parse_type()?),
+            _ => None,
+        };
+        if self.peek_token(TokenKind::Assign).is_err() {
+            return Err(self.make_error_msg(
+                next.pos,
+                format!("{} variable '{}' must be initialized", kind, name),
+            ));
+        }
+        self.match_token(TokenKind::Assign)?;
+        let value = self.parse_expression()?;
+        Ok((
+            Variable {
+                name,
+                ty,
+                pos,
+                reference: false,
+            },
+            value,
+        ))
+    }
+
     fn parse_struct_definition(&mut self) -> Result<StructDef, String> {
         self.match_keyword(Keyword::Struct)?;
         let name = self.match_identifier()?;
 
         self.match_token(TokenKind::CurlyBracesOpen)?;
         let mut fields = Vec::new();
+        let mut defaults = HashMap::new();
         let mut methods = Vec::new();
         while self.peek_token(TokenKind::CurlyBracesClose).is_err() {
             let next = self.peek()?;
             match next.kind {
-                TokenKind::Keyword(Keyword::Function) => {
+                TokenKind::Keyword(Keyword::Function) | TokenKind::At => {
                     methods.push(self.parse_function()?);
                 }
-                TokenKind::Identifier(_) => fields.push(self.parse_typed_variable()?),
+                TokenKind::Identifier(_) => {
+                    let field = self.parse_typed_variable()?;
+                    // x: int = 0
+                    if self.peek_token(TokenKind::Assign).is_ok() {
+                        self.match_token(TokenKind::Assign)?;
+                        defaults.insert(field.name.clone(), self.parse_expression()?);
+                    }
+                    fields.push(field);
+                }
                 _ => {
                     return Err(
                         self.make_error_msg(next.pos, "Expected struct field or method".into())
@@ -79,7 +211,10 @@ impl Parser {
         Ok(StructDef {
             name,
             fields,
+            defaults,
             methods,
+            attributes: Vec::new(),
+            public: false,
         })
     }
 
@@ -87,33 +222,46 @@ impl Parser {
         let mut args = Vec::new();
 
         // If there is an argument
-        if let TokenKind::Identifier(_) = self.peek()?.kind {
+        if let TokenKind::Identifier(_) | TokenKind::Keyword(Keyword::Ref) = self.peek()?.kind {
             // Parse first argument
-            args.push(self.parse_typed_variable()?);
+            args.push(self.parse_argument()?);
             // Then continue to parse arguments
             // as long as a comma token is found
             while self.peek_token(TokenKind::Comma).is_ok() {
                 self.match_token(TokenKind::Comma)?;
-                args.push(self.parse_typed_variable()?);
+                args.push(self.parse_argument()?);
             }
         }
 
         Ok(args)
     }
 
+    /// Parses an argument of a function, which may be marked with `ref`
+    fn parse_argument(&mut self) -> Result<Variable, String> {
+        let reference = self.peek_token(TokenKind::Keyword(Keyword::Ref)).is_ok();
+        if reference {
+            self.match_keyword(Keyword::Ref)?;
+        }
+        let mut var = self.parse_typed_variable()?;
+        var.reference = reference;
+        Ok(var)
+    }
+
     fn parse_typed_variable(&mut self) -> Result<Variable, String> {
         let next = self.next()?;
         if let TokenKind::Identifier(name) = next.kind {
             return Ok(Variable {
                 name,
                 ty: Some(self.parse_type()?),
+                pos: Some(next.pos),
+                reference: false,
             });
         }
 
         Err(format!("Argument could not be parsed: {}", next.raw))
     }
 
-    fn parse_block(&mut self) -> Result<Statement, String> {
+    pub(super) fn parse_block(&mut self) -> Result<Statement, String> {
         self.match_token(TokenKind::CurlyBracesOpen)?;
 
         let mut statements = vec![];
@@ -142,6 +290,89 @@ impl Parser {
     /// If a function is parsed, the `fn` keyword is matched.
     /// If a method is parsed, `fn` will be omitted
     fn parse_function(&mut self) -> Result<Function, String> {
+        let attributes = self.parse_attributes()?;
+        let (name, arguments, ret_type, is_async) = self.parse_function_signature()?;
+        let body = self.parse_block()?;
+
+        Ok(Function {
+            name,
+            arguments,
+            body,
+            ret_type,
+            attributes,
+            public: false,
+            is_async,
+        })
+    }
+
+    /// Parses the declaration of a function that is implemented elsewhere,
+    /// e.g. `extern "C" fn puts(s: string): int`. The ABI is optional and defaults to "C".
+    fn parse_extern_function(&mut self) -> Result<ExternFunction, String> {
+        self.match_keyword(Keyword::Extern)?;
+        let abi = match self.peek()?.kind {
+            TokenKind::Literal(Value::Str(abi)) => {
+                let token = self.next()?;
+                if abi != "C" {
+                    return Err(self.make_error_msg(
+                        token.pos,
+                        format!("Unsupported ABI \"{}\", only \"C\" is supported", abi),
+                    ));
+                }
+                abi
+            }
+            _ => "C".into(),
+        };
+        let pos = self.peek()?.pos;
+        let (name, arguments, ret_type, is_async) = self.parse_function_signature()?;
+        if is_async {
+            return Err(self.make_error_msg(
+                pos,
+                "Only functions of JavaScript modules can be declared async".into(),
+            ));
+        }
+
+        Ok(ExternFunction {
+            name,
+            abi,
+            arguments,
+            ret_type,
+            module: None,
+            is_async,
+        })
+    }
+
+    /// Parses the bindings to a JavaScript module following `import`,
+    /// e.g. `js "fs" { fn existsSync(path: string): bool }`
+    fn parse_js_import(&mut self) -> Result<Vec<ExternFunction>, String> {
+        self.match_identifier()?;
+        let module = self.parse_string()?;
+        self.match_token(TokenKind::CurlyBracesOpen)?;
+
+        let mut bindings = Vec::new();
+        while self.peek_token(TokenKind::CurlyBracesClose).is_err() {
+            let (name, arguments, ret_type, is_async) = self.parse_function_signature()?;
+            bindings.push(ExternFunction {
+                name,
+                abi: "js".into(),
+                arguments,
+                ret_type,
+                module: Some(module.clone()),
+                is_async,
+            });
+        }
+        self.match_token(TokenKind::CurlyBracesClose)?;
+
+        Ok(bindings)
+    }
+
+    /// Parses `fn name(arguments): type`, which may start with `async`
+    fn parse_function_signature(
+        &mut self,
+    ) -> Result<(String, Vec<Variable>, Option<Type>, bool), String> {
+        let is_async = self.peek_token(TokenKind::Keyword(Keyword::Async)).is_ok();
+        if is_async {
+            self.match_keyword(Keyword::Async)?;
+        }
         self.match_keyword(Keyword::Function)?;
         let name = self.match_identifier()?;
 
@@ -159,21 +390,111 @@ impl Parser {
             _ => None,
         };
 
-        let body = self.parse_block()?;
+        Ok((name, arguments, ty, is_async))
+    }
 
-        Ok(Function {
-            name,
-            arguments,
-            body,
-            ret_type: ty,
-        })
+    /// Parses a possibly empty list of attributes, e.g. `@inline`
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, String> {
+        let mut attributes = Vec::new();
+        while self.peek_token(TokenKind::At).is_ok() {
+            self.match_token(TokenKind::At)?;
+            let name = self.match_identifier()?;
+            let arguments = if self.peek_token(TokenKind::BraceOpen).is_ok() {
+                self.parse_attribute_arguments()?
+            } else {
+                Vec::new()
+            };
+            attributes.push(Attribute { name, arguments });
+        }
+
+        Ok(attributes)
+    }
+
+    /// Parses the arguments of an attribute, e.g. `(target = "js")`, `("use y")` or `(json)`.
+    /// An identifier without a value is a positional argument.
+    fn parse_attribute_arguments(&mut self) -> Result<Vec<(Option<String>, String)>, String> {
+        self.match_token(TokenKind::BraceOpen)?;
+        let mut arguments = Vec::new();
+        while self.peek_token(TokenKind::BraceClose).is_err() {
+            let key = match self.peek()?.kind {
+                TokenKind::Identifier(_) => Some(self.match_identifier()?),
+                _ => None,
+            };
+            match key {
+                Some(key) if self.peek_token(TokenKind::Assign).is_err() => {
+                    arguments.push((None, key))
+                }
+                key => {
+                    if key.is_some() {
+                        self.match_token(TokenKind::Assign)?;
+                    }
+                    let token = self.next()?;
+                    match token.kind {
+                        TokenKind::Literal(Value::Str(value)) => arguments.push((key, value)),
+                        other => {
+                            return Err(self.make_error_msg(
+                                token.pos,
+                                format!("Expected string, got {:?}", other),
+                            ))
+                        }
+                    }
+                }
+            }
+            if self.peek_token(TokenKind::Comma).is_ok() {
+                self.match_token(TokenKind::Comma)?;
+            }
+        }
+        self.match_token(TokenKind::BraceClose)?;
+
+        Ok(arguments)
+    }
+
+    /// Parses the name following the alias of an import, e.g. `sqrt` of `math::sqrt(x)`.
+    /// Only functions can be accessed through an alias, so the name has to be called.
+    fn parse_qualified_name(&mut self, alias: String) -> Result<String, String> {
+        self.match_token(TokenKind::DoubleColon)?;
+        let pos = self.peek()?.pos;
+        let name = self.match_identifier()?;
+        if self.peek_token(TokenKind::BraceOpen).is_err() {
+            return Err(
+                self.make_error_msg(pos, format!("Expected a call of '{}::{}'", alias, name))
+            );
+        }
+        Ok(format!("{}::{}", alias, name))
+    }
+
+    /// Parses the names and the path of a selective import following `import`,
+    /// e.g. `{ foo, bar } from "module"`
+    fn parse_selective_import(&mut self) -> Result<(String, Vec<(String, Position)>), String> {
+        self.match_token(TokenKind::CurlyBracesOpen)?;
+        let mut names = Vec::new();
+        while self.peek_token(TokenKind::CurlyBracesClose).is_err() {
+            let pos = self.peek()?.pos;
+            names.push((self.match_identifier()?, pos));
+            if self.peek_token(TokenKind::Comma).is_ok() {
+                self.match_token(TokenKind::Comma)?;
+            }
+        }
+        self.match_token(TokenKind::CurlyBracesClose)?;
+
+        let from = self.next()?;
+        if !matches!(&from.kind, TokenKind::Identifier(id) if id == "from") {
+            return Err(
+                self.make_error_msg(from.pos, format!("Expected 'from', found {:?}", from.kind))
+            );
+        }
+        Ok((self.parse_import()?, names))
     }
 
+    /// Parses the path of an import following `import`, e.g. `"lib/math"`
     fn parse_import(&mut self) -> Result<String, String> {
-        self.match_keyword(Keyword::Import)?;
+        self.parse_string()
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
         let token = self.next()?;
-        let path = match token.kind {
-            TokenKind::Literal(Value::Str(path)) => path,
+        let value = match token.kind {
+            TokenKind::Literal(Value::Str(value)) => value,
             other => {
                 return Err(
                     self.make_error_msg(token.pos, format!("Expected string, got {:?}", other))
@@ -181,17 +502,44 @@ impl Parser {
             }
         };
 
-        Ok(path)
+        Ok(value)
     }
 
     fn parse_type(&mut self) -> Result<Type, String> {
         self.match_token(TokenKind::Colon)?;
+        self.parse_type_name()
+    }
+
+    fn parse_type_name(&mut self) -> Result<Type, String> {
         let next = self.peek()?;
         let typ = match next.kind {
+            // &int
+            TokenKind::Ampersand => {
+                self.next()?;
+                return Ok(Type::Ref(Box::new(self.parse_type_name()?)));
+            }
+            // map<string, int>
+            TokenKind::Identifier(name) if name == "map" => {
+                self.next()?;
+                self.match_token(TokenKind::LessThan)?;
+                let key = self.parse_type_name()?;
+                self.match_token(TokenKind::Comma)?;
+                let value = self.parse_type_name()?;
+                self.match_token(TokenKind::GreaterThan)?;
+                Ok(Type::Map(Box::new(key), Box::new(value)))
+            }
+            // chan<int>
+            TokenKind::Identifier(name) if name == "chan" => {
+                self.next()?;
+                self.match_token(TokenKind::LessThan)?;
+                let value = self.parse_type_name()?;
+                self.match_token(TokenKind::GreaterThan)?;
+                Ok(Type::Chan(Box::new(value)))
+            }
             TokenKind::Identifier(_) => Type::try_from(self.next()?.raw),
             _ => Err("Expected type".into()),
         }?;
-        if self.peek_token(TokenKind::SquareBraceOpen).is_ok() {
+        let typ = if self.peek_token(TokenKind::SquareBraceOpen).is_ok() {
             self.match_token(TokenKind::SquareBraceOpen)?;
             let capacity = match self.peek_token(TokenKind::Literal(Value::Int)) {
                 Ok(val) => {
@@ -201,7 +549,14 @@ impl Parser {
                 Err(_) => None,
             };
             self.match_token(TokenKind::SquareBraceClose)?;
-            Ok(Type::Array(Box::new(typ), capacity))
+            Type::Array(Box::new(typ), capacity)
+        } else {
+            typ
+        };
+        // int!
+        if self.peek_token(TokenKind::Exclamation).is_ok() {
+            self.match_token(TokenKind::Exclamation)?;
+            Ok(Type::Result(Box::new(typ)))
         } else {
             Ok(typ)
         }
@@ -211,28 +566,66 @@ impl Parser {
         let token = self.peek()?;
         match &token.kind {
             TokenKind::CurlyBracesOpen => self.parse_block(),
-            TokenKind::BraceOpen | TokenKind::Keyword(Keyword::Selff) => {
-                Ok(Statement::Exp(self.parse_expression()?))
+            TokenKind::BraceOpen
+            | TokenKind::Keyword(Keyword::Selff)
+            | TokenKind::Keyword(Keyword::Await) => {
+                let expr = self.parse_expression()?;
+                // (*point).x = 1
+                if self.peek_token(TokenKind::Assign).is_ok() {
+                    return self.parse_assignent(Some(expr));
+                }
+                Ok(Statement::Exp(expr))
             }
             TokenKind::Keyword(Keyword::Let) => self.parse_declare(),
             TokenKind::Keyword(Keyword::Return) => self.parse_return(),
             TokenKind::Keyword(Keyword::If) => self.parse_conditional_statement(),
             TokenKind::Keyword(Keyword::While) => self.parse_while_loop(),
+            TokenKind::Keyword(Keyword::Unsafe) => self.parse_unsafe(),
             TokenKind::Keyword(Keyword::Break) => self.parse_break(),
             TokenKind::Keyword(Keyword::Continue) => self.parse_continue(),
+            TokenKind::Keyword(Keyword::Defer) => self.parse_defer(),
+            TokenKind::Keyword(Keyword::Spawn) => self.parse_spawn(),
+            TokenKind::Keyword(Keyword::Static) => self.parse_static(),
             TokenKind::Keyword(Keyword::For) => self.parse_for_loop(),
             TokenKind::Keyword(Keyword::Match) => self.parse_match_statement(),
+            TokenKind::Inline(target, code) => {
+                self.next()?;
+                Ok(Statement::Inline(target.clone(), code.clone()))
+            }
             TokenKind::Identifier(_) => {
                 let ident = self.match_identifier()?;
-                let expr = if self.peek_token(TokenKind::Dot).is_ok() {
-                    self.parse_field_access(Expression::Variable(ident.clone()))?
-                } else {
-                    Expression::Variable(ident.clone())
-                };
+                // log!(x)
+                if self.peek_token(TokenKind::Exclamation).is_ok() {
+                    return self.expand_macro_statement(ident, token.pos);
+                }
+                // math::sqrt(x)
+                if self.peek_token(TokenKind::DoubleColon).is_ok() {
+                    let name = self.parse_qualified_name(ident)?;
+                    let call = self.parse_function_call(Some(name))?;
+                    let state = self.parse_postfix(call)?;
+                    if BinOp::try_from(self.peek()?.kind).is_ok() {
+                        return Ok(Statement::Exp(self.parse_bin_op(Some(state))?));
+                    }
+                    return Ok(Statement::Exp(state));
+                }
+                // store<int>(address, 1)
+                if self.peek_token(TokenKind::LessThan).is_ok() && is_generic_builtin(&ident) {
+                    let call = self.parse_generic_builtin(&ident)?;
+                    let state = self.parse_postfix(call)?;
+                    if BinOp::try_from(self.peek()?.kind).is_ok() {
+                        return Ok(Statement::Exp(self.parse_bin_op(Some(state))?));
+                    }
+                    return Ok(Statement::Exp(state));
+                }
+                let expr = self.parse_postfix(Expression::Variable(ident.clone()))?;
 
                 // TODO: Use match statement
                 if self.peek_token(TokenKind::BraceOpen).is_ok() {
-                    let state = self.parse_function_call(Some(ident))?;
+                    let call = self.parse_function_call(Some(ident))?;
+                    let state = self.parse_postfix(call)?;
+                    if BinOp::try_from(self.peek()?.kind).is_ok() {
+                        return Ok(Statement::Exp(self.parse_bin_op(Some(state))?));
+                    }
                     Ok(Statement::Exp(state))
                 } else if self.peek_token(TokenKind::Assign).is_ok() {
                     let state = self.parse_assignent(Some(expr))?;
@@ -247,18 +640,24 @@ impl Parser {
                     }
                 } else if BinOp::try_from(self.peek()?.kind).is_ok() {
                     // Parse Binary operation
-                    let expr = Expression::Variable(ident);
                     let state = Statement::Exp(self.parse_bin_op(Some(expr))?);
                     Ok(state)
-                } else if self.peek_token(TokenKind::Dot).is_ok() {
-                    Ok(Statement::Exp(
-                        self.parse_field_access(Expression::Variable(ident))?,
-                    ))
                 } else {
                     Ok(Statement::Exp(expr))
                 }
             }
             TokenKind::Literal(_) => Ok(Statement::Exp(self.parse_expression()?)),
+            // *foo = 1
+            TokenKind::Star => {
+                let expr = self.parse_operand()?;
+                if self.peek_token(TokenKind::Assign).is_ok() {
+                    self.parse_assignent(Some(expr))
+                } else if BinOp::try_from(self.peek()?.kind).is_ok() {
+                    Ok(Statement::Exp(self.parse_bin_op(Some(expr))?))
+                } else {
+                    Ok(Statement::Exp(expr))
+                }
+            }
             TokenKind::Keyword(Keyword::Struct) => {
                 Err("Struct definitions inside functions are not allowed".to_string())
             }
@@ -275,6 +674,7 @@ impl Parser {
             None => self.next()?.raw,
         };
 
+        let pos = self.peek()?.pos;
         self.match_token(TokenKind::BraceOpen)?;
 
         let mut args = Vec::new();
@@ -290,9 +690,12 @@ impl Parser {
                 TokenKind::Identifier(_) | TokenKind::Literal(_) => {
                     args.push(self.parse_expression()?)
                 }
-                TokenKind::Keyword(Keyword::Boolean) | TokenKind::Keyword(Keyword::New) => {
-                    args.push(self.parse_expression()?)
-                }
+                TokenKind::Keyword(Keyword::Boolean)
+                | TokenKind::Keyword(Keyword::New)
+                | TokenKind::Keyword(Keyword::Await)
+                | TokenKind::CurlyBracesOpen
+                | TokenKind::Ampersand
+                | TokenKind::Star => args.push(self.parse_expression()?),
                 TokenKind::SquareBraceOpen => {
                     // TODO: Expression parsing currently uses `next` instead of `peek`.
                     // We have to eat that token here until that is resolved
@@ -306,11 +709,60 @@ impl Parser {
         }
 
         self.match_token(TokenKind::BraceClose)?;
-        let expr = Expression::FunctionCall(name, args);
-        match self.peek()?.kind {
-            TokenKind::Dot => self.parse_field_access(expr),
-            _ => Ok(expr),
+        match name.as_str() {
+            "assert" | "panic" => self.lower_located_call(&name, args, pos),
+            _ => Ok(Expression::FunctionCall(name, args)),
+        }
+    }
+
+    /// Lowers calls of `assert` and `panic` to their builtin functions,
+    /// passing the location of the call so it can be reported on failure
+    fn lower_located_call(
+        &mut self,
+        name: &str,
+        mut args: Vec<Expression>,
+        pos: Position,
+    ) -> Result<Expression, String> {
+        let (builtin, arity, expected) = match name {
+            "assert" => ("_assert", 1..=2, "1 or 2 arguments"),
+            _ => ("_panic", 1..=1, "1 argument"),
+        };
+        if !arity.contains(&args.len()) {
+            return Err(self.make_error_msg(
+                pos,
+                format!("'{}' expects {}, found {}", name, expected, args.len()),
+            ));
+        }
+        if name == "assert" && args.len() == 1 {
+            args.push(Expression::Str(String::new()));
         }
+        args.push(Expression::Str(format!("{}:{}", self.path, pos.line)));
+        Ok(Expression::FunctionCall(builtin.to_owned(), args))
+    }
+
+    /// Parses `include_str!("file")`, which the builder replaces with the contents of the file.
+    /// Like for `assert`, the location of the call is passed along, so that a file that
+    /// can not be read is reported where it is embedded.
+    fn parse_include_str(&mut self, pos: Position) -> Result<Expression, String> {
+        self.match_token(TokenKind::Exclamation)?;
+        self.match_token(TokenKind::BraceOpen)?;
+        let path = match self.next()?.kind {
+            TokenKind::Literal(Value::Str(path)) => path,
+            _ => {
+                return Err(self.make_error_msg(
+                    pos,
+                    "'include_str!' expects the path of a file as a string literal".into(),
+                ))
+            }
+        };
+        self.match_token(TokenKind::BraceClose)?;
+        Ok(Expression::FunctionCall(
+            "_include_str".into(),
+            vec![
+                Expression::Str(path),
+                Expression::Str(format!("{}:{}", self.path, pos.line)),
+            ],
+        ))
     }
 
     fn parse_return(&mut self) -> Result<Statement, String> {
@@ -322,7 +774,26 @@ impl Parser {
         }
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, String> {
+    pub(super) fn parse_expression(&mut self) -> Result<Expression, String> {
+        let expr = self.parse_operand()?;
+        if BinOp::try_from(self.peek()?.kind).is_ok() {
+            // 1 + 2
+            self.parse_bin_op(Some(expr))
+        } else {
+            // Nope, the expression was fully parsed
+            Ok(expr)
+        }
+    }
+
+    /// Parses an expression that is not a binary operation, but can be an operand of one
+    fn parse_operand(&mut self) -> Result<Expression, String> {
+        let expr = self.parse_primary()?;
+        // Check if the parsed expression continues
+        self.parse_postfix(expr)
+    }
+
+    /// Parses an operand without the field accesses and `?` operators following it
+    fn parse_primary(&mut self) -> Result<Expression, String> {
         let token = self.next()?;
 
         let expr = match token.kind {
@@ -366,32 +837,89 @@ impl Parser {
                 match &next.kind {
                     // foo()
                     TokenKind::BraceOpen => self.parse_function_call(Some(val))?,
+                    // include_str!("data.txt")
+                    TokenKind::Exclamation if val == "include_str" => {
+                        self.parse_include_str(token.pos)?
+                    }
+                    // square!(x)
+                    TokenKind::Exclamation => self.expand_macro_expression(val, token.pos)?,
+                    // math::sqrt(x)
+                    TokenKind::DoubleColon => {
+                        let name = self.parse_qualified_name(val)?;
+                        self.parse_function_call(Some(name))?
+                    }
                     // arr[0]
                     TokenKind::SquareBraceOpen => self.parse_array_access(Some(val))?,
+                    // size_of<int>(), load<int>(address)
+                    TokenKind::LessThan if is_generic_builtin(&val) => {
+                        self.parse_generic_builtin(&val)?
+                    }
                     // some_var
                     _ => Expression::Variable(val),
                 }
             }
             // [1, 2, 3]
             TokenKind::SquareBraceOpen => self.parse_array()?,
+            // { "a": 1 }
+            TokenKind::CurlyBracesOpen => self.parse_map()?,
             // new Foo {}
             TokenKind::Keyword(Keyword::New) => self.parse_struct_initialization()?,
+            // &foo
+            TokenKind::Ampersand => Expression::Reference(Box::new(self.parse_operand()?)),
+            // *foo
+            TokenKind::Star => Expression::Dereference(Box::new(self.parse_operand()?)),
+            // await foo(), the operators following the call apply to the awaited value
+            TokenKind::Keyword(Keyword::Await) => match self.parse_primary()? {
+                call @ Expression::FunctionCall(..) => Expression::Await(Box::new(call)),
+                _ => {
+                    return Err(self.make_error_msg(
+                        token.pos,
+                        "Expected a function call after 'await'".into(),
+                    ))
+                }
+            },
             other => return Err(format!("Expected Expression, found {:?}", other)),
         };
+        Ok(expr)
+    }
 
-        // Check if the parsed expression continues
-        if self.peek_token(TokenKind::Dot).is_ok() {
-            // foo.bar
-            self.parse_field_access(expr)
-        } else if BinOp::try_from(self.peek()?.kind).is_ok() {
-            // 1 + 2
-            self.parse_bin_op(Some(expr))
-        } else {
-            // Nope, the expression was fully parsed
-            Ok(expr)
+    /// Parses the field accesses and `?` operators following an expression
+    fn parse_postfix(&mut self, mut expr: Expression) -> Result<Expression, String> {
+        loop {
+            if self.peek_token(TokenKind::Dot).is_ok() {
+                // foo.bar
+                expr = self.parse_field_access(expr)?;
+            } else if self.peek_token(TokenKind::QuestionMark).is_ok() {
+                // foo()?
+                self.match_token(TokenKind::QuestionMark)?;
+                expr = Expression::Try(Box::new(expr));
+            } else {
+                return Ok(expr);
+            }
         }
     }
 
+    /// Parses a call of `size_of`, `load` or `store` with its type argument,
+    /// after the name has been consumed
+    fn parse_generic_builtin(&mut self, name: &str) -> Result<Expression, String> {
+        self.match_token(TokenKind::LessThan)?;
+        let ty = self.parse_type_name()?;
+        self.match_token(TokenKind::GreaterThan)?;
+        self.match_token(TokenKind::BraceOpen)?;
+        let expr = match name {
+            "load" => Expression::Load(ty, Box::new(self.parse_expression()?)),
+            "store" => {
+                let address = self.parse_expression()?;
+                self.match_token(TokenKind::Comma)?;
+                let value = self.parse_expression()?;
+                Expression::Store(ty, Box::new(address), Box::new(value))
+            }
+            _ => Expression::SizeOf(ty),
+        };
+        self.match_token(TokenKind::BraceClose)?;
+        Ok(expr)
+    }
+
     fn parse_field_access(&mut self, lhs: Expression) -> Result<Expression, String> {
         self.match_token(TokenKind::Dot)?;
 
@@ -407,8 +935,6 @@ impl Parser {
         let expr = Expression::FieldAccess(Box::new(lhs), Box::new(field));
         if self.peek_token(TokenKind::Dot).is_ok() {
             self.parse_field_access(expr)
-        } else if BinOp::try_from(self.peek()?.kind).is_ok() {
-            self.parse_bin_op(Some(expr))
         } else {
             Ok(expr)
         }
@@ -418,10 +944,21 @@ impl Parser {
     fn parse_struct_initialization(&mut self) -> Result<Expression, String> {
         let name = self.match_identifier()?;
         self.match_token(TokenKind::CurlyBracesOpen)?;
+        // ..base
+        let base = if self.peek_token(TokenKind::Dot).is_ok() {
+            self.match_token(TokenKind::Dot)?;
+            self.match_token(TokenKind::Dot)?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
         let fields = self.parse_struct_fields()?;
         self.match_token(TokenKind::CurlyBracesClose)?;
 
-        Ok(Expression::StructInitialization(name, fields))
+        Ok(match base {
+            Some(base) => Expression::StructUpdate(name, Box::new(base), fields),
+            None => Expression::StructInitialization(name, fields),
+        })
     }
 
     fn parse_struct_fields(&mut self) -> Result<HashMap<String, Box<Expression>>, String> {
@@ -446,6 +983,10 @@ impl Parser {
     fn parse_struct_field(&mut self) -> Result<(String, Box<Expression>), String> {
         let next = self.next()?;
         if let TokenKind::Identifier(name) = next.kind {
+            // A field without a value takes the variable of the same name
+            if self.peek_token(TokenKind::Colon).is_err() {
+                return Ok((name.clone(), Box::new(Expression::Variable(name))));
+            }
             self.match_token(TokenKind::Colon)?;
             return Ok((name, Box::new(self.parse_expression()?)));
         }
@@ -484,6 +1025,24 @@ impl Parser {
         Ok(Expression::Array(length, elements))
     }
 
+    /// Parses the entries of a map literal. The opening brace has already been consumed.
+    fn parse_map(&mut self) -> Result<Expression, String> {
+        let mut entries = Vec::new();
+        while self.peek_token(TokenKind::CurlyBracesClose).is_err() {
+            let key = self.parse_expression()?;
+            self.match_token(TokenKind::Colon)?;
+            let value = self.parse_expression()?;
+            entries.push((key, value));
+            if self.peek_token(TokenKind::CurlyBracesClose).is_ok() {
+                break;
+            }
+            self.match_token(TokenKind::Comma)?;
+        }
+        self.match_token(TokenKind::CurlyBracesClose)?;
+
+        Ok(Expression::Map(entries))
+    }
+
     fn parse_array_access(&mut self, arr_name: Option<String>) -> Result<Expression, String> {
         let name = match arr_name {
             Some(name) => name,
@@ -505,6 +1064,13 @@ impl Parser {
         Ok(Statement::While(expr, Box::new(body)))
     }
 
+    /// Parses `unsafe { ... }`
+    fn parse_unsafe(&mut self) -> Result<Statement, String> {
+        self.match_keyword(Keyword::Unsafe)?;
+        let body = self.parse_block()?;
+        Ok(Statement::Unsafe(Box::new(body)))
+    }
+
     fn parse_break(&mut self) -> Result<Statement, String> {
         self.match_keyword(Keyword::Break)?;
         Ok(Statement::Break)
@@ -515,9 +1081,44 @@ impl Parser {
         Ok(Statement::Continue)
     }
 
+    fn parse_defer(&mut self) -> Result<Statement, String> {
+        self.match_keyword(Keyword::Defer)?;
+        Ok(Statement::Defer(self.parse_expression()?))
+    }
+
+    /// Parses `spawn worker(jobs)`, which has to call a function
+    fn parse_spawn(&mut self) -> Result<Statement, String> {
+        let pos = self.peek()?.pos;
+        self.match_keyword(Keyword::Spawn)?;
+        match self.parse_expression()? {
+            call @ Expression::FunctionCall(..) => Ok(Statement::Spawn(call)),
+            _ => Err(self.make_error_msg(pos, "Expected a function call after 'spawn'".into())),
+        }
+    }
+
+    /// Parses `static count = 0`
+    fn parse_static(&mut self) -> Result<Statement, String> {
+        let (var, value) = self.parse_initialized_variable("Static")?;
+        Ok(Statement::Static(var, value))
+    }
+
     fn parse_for_loop(&mut self) -> Result<Statement, String> {
         self.match_keyword(Keyword::For)?;
 
+        // for (k, v) in pairs
+        if self.peek_token(TokenKind::BraceOpen).is_ok() {
+            self.match_token(TokenKind::BraceOpen)?;
+            let key = self.parse_binding()?;
+            self.match_token(TokenKind::Comma)?;
+            let value = self.parse_binding()?;
+            self.match_token(TokenKind::BraceClose)?;
+            self.match_keyword(Keyword::In)?;
+            let expr = self.parse_expression()?;
+            let body = self.parse_block()?;
+            return Ok(Statement::ForPair(key, value, expr, Box::new(body)));
+        }
+
+        let pos = self.peek()?.pos;
         let ident = self.match_identifier()?;
         let ident_ty = match self.peek()?.kind {
             TokenKind::Colon => Some(self.parse_type()?),
@@ -532,6 +1133,8 @@ impl Parser {
             Variable {
                 name: ident,
                 ty: ident_ty,
+                pos: Some(pos),
+                reference: false,
             },
             expr,
             Box::new(body),
@@ -641,17 +1244,28 @@ impl Parser {
             }
         };
 
-        let op = self.match_operator()?;
+        // Operands and operators are collected first, so that the operators
+        // can be applied in the order of their precedence
+        let mut operands = vec![left];
+        let mut operators = Vec::new();
+        while BinOp::try_from(self.peek()?.kind).is_ok() {
+            // A `*` at the start of a line dereferences the operand of the next statement
+            if self.peek_token(TokenKind::Star).is_ok() && self.starts_line()? {
+                break;
+            }
+            operators.push(self.match_operator()?);
+            operands.push(self.parse_operand()?);
+        }
 
-        Ok(Expression::BinOp(
-            Box::from(left),
-            op,
-            Box::from(self.parse_expression()?),
-        ))
+        Ok(build_bin_op(operands, operators))
     }
 
     fn parse_declare(&mut self) -> Result<Statement, String> {
         self.match_keyword(Keyword::Let)?;
+        if self.peek_token(TokenKind::CurlyBracesOpen).is_ok() {
+            return self.parse_destructure();
+        }
+        let pos = Some(self.peek()?.pos);
         let name = self.match_identifier()?;
         let ty = match self.peek()?.kind {
             TokenKind::Colon => Some(self.parse_type()?),
@@ -662,12 +1276,58 @@ impl Parser {
             TokenKind::Assign => {
                 self.match_token(TokenKind::Assign)?;
                 let expr = self.parse_expression()?;
-                Ok(Statement::Declare(Variable { name, ty }, Some(expr)))
+                Ok(Statement::Declare(
+                    Variable {
+                        name,
+                        ty,
+                        pos,
+                        reference: false,
+                    },
+                    Some(expr),
+                ))
             }
-            _ => Ok(Statement::Declare(Variable { name, ty }, None)),
+            _ => Ok(Statement::Declare(
+                Variable {
+                    name,
+                    ty,
+                    pos,
+                    reference: false,
+                },
+                None,
+            )),
         }
     }
 
+    /// Parses the pattern and value of `let { x, y } = point`
+    fn parse_destructure(&mut self) -> Result<Statement, String> {
+        self.match_token(TokenKind::CurlyBracesOpen)?;
+        let mut fields = Vec::new();
+        loop {
+            let var = self.parse_binding()?;
+            fields.push((var.name.clone(), var));
+            if self.peek_token(TokenKind::Comma).is_err() {
+                break;
+            }
+            self.match_token(TokenKind::Comma)?;
+        }
+        self.match_token(TokenKind::CurlyBracesClose)?;
+        self.match_token(TokenKind::Assign)?;
+        let expr = self.parse_expression()?;
+        Ok(Statement::Destructure(fields, expr))
+    }
+
+    /// Parses the name of a variable that is bound by a pattern
+    fn parse_binding(&mut self) -> Result<Variable, String> {
+        let pos = Some(self.peek()?.pos);
+        let name = self.match_identifier()?;
+        Ok(Variable {
+            name,
+            ty: None,
+            pos,
+            reference: false,
+        })
+    }
+
     fn parse_assignent(&mut self, name: Option<Expression>) -> Result<Statement, String> {
         let name = match name {
             Some(name) => name,
@@ -681,3 +1341,33 @@ impl Parser {
         Ok(Statement::Assign(Box::new(name), Box::new(expr)))
     }
 }
+
+/// Builds the tree of a chain of binary operations. `operands` has to contain one more item than `operators`.
+/// Operators of the same precedence are left associative, except for assignments like `+=`.
+fn build_bin_op(mut operands: Vec<Expression>, mut operators: Vec<BinOp>) -> Expression {
+    let lowest = match operators.iter().map(BinOp::precedence).min() {
+        Some(lowest) => lowest,
+        None => return operands.pop().expect("Expected operand"),
+    };
+    // The operator with the lowest precedence is applied last, so it becomes the root
+    let split = if lowest == BinOp::AddAssign.precedence() {
+        operators.iter().position(|op| op.precedence() == lowest)
+    } else {
+        operators.iter().rposition(|op| op.precedence() == lowest)
+    }
+    .unwrap();
+
+    let rhs_operands = operands.split_off(split + 1);
+    let rhs_operators = operators.split_off(split + 1);
+    let op = operators.pop().unwrap();
+    Expression::BinOp(
+        Box::new(build_bin_op(operands, operators)),
+        op,
+        Box::new(build_bin_op(rhs_operands, rhs_operators)),
+    )
+}
+
+/// Returns true if the builtin function takes a type argument, like `size_of<int>()`
+fn is_generic_builtin(name: &str) -> bool {
+    matches!(name, "size_of" | "load" | "store")
+}
@@ -14,6 +14,7 @@ use crate::ast::types::Type;
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use crate::ast::{BinOp, Expression, Statement};
 use crate::lexer::*;
 use crate::parser::parse;
 
@@ -841,3 +842,873 @@ fn test_array_capacity() {
     let tree = parse(tokens, Some(raw.to_string()), "".into());
     assert!(tree.is_ok());
 }
+
+#[test]
+fn test_extern_function() {
+    let raw = "
+    extern \"C\" fn puts(s: string): int
+    extern fn exit(code: int)
+
+    fn main() {
+        puts(\"Hello\")
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert_eq!(module.externs.len(), 2);
+    assert_eq!(module.externs[0].name, "puts");
+    assert_eq!(module.externs[0].abi, "C");
+    assert_eq!(module.externs[0].ret_type, Some(Type::Int));
+    assert_eq!(module.externs[1].abi, "C");
+    assert_eq!(module.externs[1].ret_type, None);
+    assert_eq!(module.func.len(), 1);
+}
+
+#[test]
+fn test_reference_arguments() {
+    let raw = "
+    fn rename(ref user: User, name: string) {
+        user.name = name
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let arguments = &module.func[0].arguments;
+    assert!(arguments[0].reference);
+    assert_eq!(arguments[0].ty, Some(Type::Struct("User".into())));
+    assert!(!arguments[1].reference);
+}
+
+#[test]
+fn test_references() {
+    let raw = "
+    fn set(out: &int, value: int) {
+        *out = value
+    }
+
+    fn main() {
+        let n = 1
+        let r = &n
+        *r += n * 2
+        set(&n, *r)
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let arguments = &module.func[0].arguments;
+    assert_eq!(arguments[0].ty, Some(Type::Ref(Box::new(Type::Int))));
+
+    let statements = match &module.func[1].body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, found {:?}", other),
+    };
+    let n = || Box::new(Expression::Variable("n".into()));
+    let r = || Box::new(Expression::Variable("r".into()));
+    match &statements[1] {
+        Statement::Declare(var, Some(value)) => {
+            assert_eq!(var.name, "r");
+            assert_eq!(*value, Expression::Reference(n()));
+        }
+        other => panic!("Expected declaration, found {:?}", other),
+    }
+    // A `*` at the start of a line dereferences, instead of multiplying the previous line
+    assert_eq!(
+        statements[2],
+        Statement::Exp(Expression::BinOp(
+            Box::new(Expression::Dereference(r())),
+            BinOp::AddAssign,
+            Box::new(Expression::BinOp(
+                n(),
+                BinOp::Multiplication,
+                Box::new(Expression::Int(2))
+            )),
+        ))
+    );
+    assert_eq!(
+        statements[3],
+        Statement::Exp(Expression::FunctionCall(
+            "set".into(),
+            vec![Expression::Reference(n()), Expression::Dereference(r())]
+        ))
+    );
+}
+
+#[test]
+fn test_unsafe_block() {
+    let raw = "
+    fn main() {
+        let p = alloc(8)
+        unsafe {
+            store<int>(p + 4, 1)
+            let n = load<int>(p + 4) + 1
+        }
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let statements = match &module.func[0].body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, found {:?}", other),
+    };
+    let body = match &statements[1] {
+        Statement::Unsafe(body) => match &**body {
+            Statement::Block(statements, _) => statements,
+            other => panic!("Expected block, found {:?}", other),
+        },
+        other => panic!("Expected unsafe block, found {:?}", other),
+    };
+    let address = || {
+        Box::new(Expression::BinOp(
+            Box::new(Expression::Variable("p".into())),
+            BinOp::Addition,
+            Box::new(Expression::Int(4)),
+        ))
+    };
+    assert_eq!(
+        body[0],
+        Statement::Exp(Expression::Store(
+            Type::Int,
+            address(),
+            Box::new(Expression::Int(1))
+        ))
+    );
+    match &body[1] {
+        Statement::Declare(_, Some(value)) => assert_eq!(
+            *value,
+            Expression::BinOp(
+                Box::new(Expression::Load(Type::Int, address())),
+                BinOp::Addition,
+                Box::new(Expression::Int(1))
+            )
+        ),
+        other => panic!("Expected declaration, found {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_js_import() {
+    let raw = "
+    import js \"fs\" {
+        fn existsSync(path: string): bool
+        fn unlinkSync(path: string)
+    }
+    import \"lib/math\"
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert_eq!(module.externs.len(), 2);
+    assert_eq!(module.externs[0].name, "existsSync");
+    assert_eq!(module.externs[0].abi, "js");
+    assert_eq!(module.externs[0].module, Some("fs".into()));
+    assert_eq!(module.externs[1].ret_type, None);
+    assert!(module.imports.contains_key("lib/math"));
+}
+
+#[test]
+fn test_parse_async() {
+    let raw = "
+    import js \"fs/promises\" {
+        async fn readFile(path: string, encoding: string): string
+    }
+
+    pub async fn size(path: string): int {
+        return await readFile(path, \"utf8\").len()
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert!(module.externs[0].is_async);
+    let func = &module.func[0];
+    assert!(func.is_async && func.public);
+    match &func.body {
+        Statement::Block(statements, _) => assert_eq!(
+            statements[..],
+            // The method is called on the awaited value
+            [Statement::Return(Some(Expression::FieldAccess(
+                Box::new(Expression::Await(Box::new(Expression::FunctionCall(
+                    "readFile".into(),
+                    vec![
+                        Expression::Variable("path".into()),
+                        Expression::Str("utf8".into())
+                    ]
+                )))),
+                Box::new(Expression::FunctionCall("len".into(), Vec::new()))
+            )))]
+        ),
+        other => panic!("Expected block, got {:?}", other),
+    }
+
+    let raw = "async fn main() {\n    let x = await value\n}";
+    let err = parse(tokenize(raw).unwrap(), Some(raw.to_string()), "".into()).unwrap_err();
+    assert!(
+        err.contains("Expected a function call after 'await'"),
+        "{}",
+        err
+    );
+
+    let raw = "extern async fn puts(s: string): int";
+    let err = parse(tokenize(raw).unwrap(), Some(raw.to_string()), "".into()).unwrap_err();
+    assert!(
+        err.contains("Only functions of JavaScript modules can be declared async"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_parse_import_alias() {
+    let raw = "
+    import \"lib/math\" as math
+    import \"lib/io\"
+
+    fn main() {
+        math::print(math::sqrt(4) + 1)
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert!(module.imports.contains_key("lib/math"));
+    assert!(module.imports.contains_key("lib/io"));
+    assert_eq!(module.aliases.len(), 1);
+    assert_eq!(module.aliases["math"], "lib/math");
+    match &module.func[0].body {
+        Statement::Block(statements, _) => match &statements[0] {
+            Statement::Exp(Expression::FunctionCall(name, args)) => {
+                assert_eq!(name, "math::print");
+                assert_eq!(
+                    args[0],
+                    bin_op(
+                        Expression::FunctionCall("math::sqrt".into(), vec![Expression::Int(4)]),
+                        BinOp::Addition,
+                        Expression::Int(1)
+                    )
+                );
+            }
+            other => panic!("Expected function call, got {:?}", other),
+        },
+        other => panic!("Expected block, got {:?}", other),
+    }
+
+    for raw in [
+        "import \"a\" as m\nimport \"b\" as m",
+        "import \"a\" as m\nfn main() { let x = m::pi }",
+    ] {
+        let tokens = tokenize(raw).unwrap();
+        assert!(parse(tokens, Some(raw.to_string()), "".into()).is_err());
+    }
+}
+
+#[test]
+fn test_parse_selective_import() {
+    let raw = "
+    import { sqrt, Vector, } from \"lib/math\"
+    import { println } from \"lib/io\"
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert!(module.imports.contains_key("lib/math"));
+    assert!(module.imports.contains_key("lib/io"));
+    assert_eq!(module.selected.len(), 3);
+    assert_eq!(module.selected["sqrt"].0, "lib/math");
+    assert_eq!(module.selected["Vector"].0, "lib/math");
+    assert_eq!(module.selected["println"].0, "lib/io");
+    assert_eq!(module.selected["println"].1.line, 3);
+
+    for raw in [
+        "import { sqrt } \"lib/math\"",
+        "import { sqrt, sqrt } from \"lib/math\"",
+        "import { sqrt } from \"a\"\nimport { sqrt } from \"b\"",
+    ] {
+        let tokens = tokenize(raw).unwrap();
+        assert!(parse(tokens, Some(raw.to_string()), "".into()).is_err());
+    }
+}
+
+#[test]
+fn test_extern_function_with_unsupported_abi() {
+    let raw = "extern \"stdcall\" fn puts(s: string): int";
+    let tokens = tokenize(raw).unwrap();
+    let tree = parse(tokens, Some(raw.to_string()), "".into());
+    assert!(tree.is_err());
+}
+
+/// Parses `expr` as the returned expression of a function
+fn parse_return_expression(expr: &str) -> Expression {
+    let raw = format!("fn main() {{ return {} }}", expr);
+    let tokens = tokenize(&raw).unwrap();
+    let module = parse(tokens, Some(raw.clone()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => match &statements[0] {
+            Statement::Return(Some(expr)) => expr.clone(),
+            other => panic!("Expected return statement, got {:?}", other),
+        },
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+fn bin_op(lhs: Expression, op: BinOp, rhs: Expression) -> Expression {
+    Expression::BinOp(Box::new(lhs), op, Box::new(rhs))
+}
+
+#[test]
+fn test_operator_precedence() {
+    use Expression::{Int, Variable};
+
+    assert_eq!(
+        parse_return_expression("1 + 2 * 3"),
+        bin_op(
+            Int(1),
+            BinOp::Addition,
+            bin_op(Int(2), BinOp::Multiplication, Int(3))
+        )
+    );
+    assert_eq!(
+        parse_return_expression("10 - 4 - 3"),
+        bin_op(
+            bin_op(Int(10), BinOp::Subtraction, Int(4)),
+            BinOp::Subtraction,
+            Int(3)
+        )
+    );
+    assert_eq!(
+        parse_return_expression("2 * (3 + 4)"),
+        bin_op(
+            Int(2),
+            BinOp::Multiplication,
+            bin_op(Int(3), BinOp::Addition, Int(4))
+        )
+    );
+    assert_eq!(
+        parse_return_expression("a == 1 && b < 2 || c"),
+        bin_op(
+            bin_op(
+                bin_op(Variable("a".into()), BinOp::Equal, Int(1)),
+                BinOp::And,
+                bin_op(Variable("b".into()), BinOp::LessThan, Int(2))
+            ),
+            BinOp::Or,
+            Variable("c".into())
+        )
+    );
+}
+
+#[test]
+fn test_infer_string_concatenation() {
+    let raw = "
+    fn main() {
+        let s = \"foo\" + \"bar\"
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => match &statements[0] {
+            Statement::Declare(var, _) => assert_eq!(var.ty, Some(Type::Str)),
+            other => panic!("Expected declaration, got {:?}", other),
+        },
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_infer_nested_declarations() {
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn origin(): Point {
+        return new Point { x: 0 }
+    }
+
+    fn main() {
+        let names = [\"a\", \"b\"]
+        if true {
+            let name = names[0]
+            let p = origin()
+            let x = p.x
+            let len = name.len()
+        }
+        for name in names {
+            let copy = name
+        }
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let statements = match &module.func[1].body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, got {:?}", other),
+    };
+    let types_of = |statement: &Statement| -> Vec<Option<Type>> {
+        match statement {
+            Statement::Block(statements, _) => statements
+                .iter()
+                .map(|s| match s {
+                    Statement::Declare(var, _) => var.ty.clone(),
+                    other => panic!("Expected declaration, got {:?}", other),
+                })
+                .collect(),
+            other => panic!("Expected block, got {:?}", other),
+        }
+    };
+    match &statements[1] {
+        Statement::If(_, body, _) => assert_eq!(
+            types_of(body),
+            [
+                Some(Type::Str),
+                Some(Type::Struct("Point".into())),
+                Some(Type::Int),
+                Some(Type::Int)
+            ]
+        ),
+        other => panic!("Expected if statement, got {:?}", other),
+    }
+    match &statements[2] {
+        Statement::For(var, _, body) => {
+            assert_eq!(var.ty, Some(Type::Str));
+            assert_eq!(types_of(body), [Some(Type::Str)]);
+        }
+        other => panic!("Expected for loop, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_infer_respects_scopes() {
+    let raw = "
+    fn main() {
+        let x = 1
+        if true {
+            let x = \"shadowed\"
+        }
+        let y = x
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => match &statements[2] {
+            Statement::Declare(var, _) => assert_eq!(var.ty, Some(Type::Int)),
+            other => panic!("Expected declaration, got {:?}", other),
+        },
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_method_chains_are_left_associative() {
+    let call = |name: &str| Box::new(Expression::FunctionCall(name.into(), Vec::new()));
+    assert_eq!(
+        parse_return_expression("s.trim().len()"),
+        Expression::FieldAccess(
+            Box::new(Expression::FieldAccess(
+                Box::new(Expression::Variable("s".into())),
+                call("trim")
+            )),
+            call("len")
+        )
+    );
+    assert_eq!(
+        parse_return_expression("foo().bar()"),
+        Expression::FieldAccess(call("foo"), call("bar"))
+    );
+}
+
+#[test]
+fn test_map_literals_and_types() {
+    let raw = "
+    fn main(counts: map<string, int[]>) {
+        let empty: map<int, bool> = {}
+        let ages = {
+            \"alice\": 31,
+            \"bob\": 27,
+        }
+        println({ 1: 2 })
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let func = &module.func[0];
+    assert_eq!(
+        func.arguments[0].ty,
+        Some(Type::Map(
+            Box::new(Type::Str),
+            Box::new(Type::Array(Box::new(Type::Int), None))
+        ))
+    );
+    let statements = match &func.body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, got {:?}", other),
+    };
+    match &statements[0] {
+        Statement::Declare(var, Some(Expression::Map(entries))) => {
+            assert_eq!(
+                var.ty,
+                Some(Type::Map(Box::new(Type::Int), Box::new(Type::Bool)))
+            );
+            assert!(entries.is_empty());
+        }
+        other => panic!("Expected map literal, got {:?}", other),
+    }
+    match &statements[1] {
+        Statement::Declare(_, Some(Expression::Map(entries))) => assert_eq!(
+            entries,
+            &vec![
+                (Expression::Str("alice".into()), Expression::Int(31)),
+                (Expression::Str("bob".into()), Expression::Int(27)),
+            ]
+        ),
+        other => panic!("Expected map literal, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_assert_and_panic_are_lowered_with_location() {
+    let raw = "fn main() {\n    assert(true)\n    panic(\"oops\")\n}";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "main.sb".into()).unwrap();
+    let str = |s: &str| Expression::Str(s.into());
+    match &module.func[0].body {
+        Statement::Block(statements, _) => assert_eq!(
+            statements[..],
+            [
+                Statement::Exp(Expression::FunctionCall(
+                    "_assert".into(),
+                    vec![Expression::Bool(true), str(""), str("main.sb:2")]
+                )),
+                Statement::Exp(Expression::FunctionCall(
+                    "_panic".into(),
+                    vec![str("oops"), str("main.sb:3")]
+                )),
+            ]
+        ),
+        other => panic!("Expected block, got {:?}", other),
+    }
+
+    let raw = "fn main() {\n    panic()\n}";
+    let tokens = tokenize(raw).unwrap();
+    let error = parse(tokens, Some(raw.to_string()), "main.sb".into()).unwrap_err();
+    assert!(error.contains("'panic' expects 1 argument, found 0"));
+}
+
+#[test]
+fn test_result_types_and_try_operator() {
+    let raw = "fn parse(s: string): int! { return 1 }";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    assert_eq!(
+        module.func[0].ret_type,
+        Some(Type::Result(Box::new(Type::Int)))
+    );
+
+    let call = |name: &str| Box::new(Expression::FunctionCall(name.into(), Vec::new()));
+    assert_eq!(
+        parse_return_expression("foo()? + 1"),
+        bin_op(
+            Expression::Try(call("foo")),
+            BinOp::Addition,
+            Expression::Int(1)
+        )
+    );
+    assert_eq!(
+        parse_return_expression("foo()?.len()"),
+        Expression::FieldAccess(Box::new(Expression::Try(call("foo"))), call("len"))
+    );
+}
+
+#[test]
+fn test_defer() {
+    let raw = "fn main() {\n    defer close(file)\n}";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => assert_eq!(
+            statements[..],
+            [Statement::Defer(Expression::FunctionCall(
+                "close".into(),
+                vec![Expression::Variable("file".into())]
+            ))]
+        ),
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_spawn() {
+    let raw = "fn main() {\n    let ch: chan<int> = channel()\n    spawn produce(ch, 3)\n}";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => {
+            match &statements[0] {
+                Statement::Declare(var, _) => {
+                    assert_eq!(var.ty, Some(Type::Chan(Box::new(Type::Int))))
+                }
+                other => panic!("Expected declaration, got {:?}", other),
+            }
+            assert_eq!(
+                statements[1],
+                Statement::Spawn(Expression::FunctionCall(
+                    "produce".into(),
+                    vec![Expression::Variable("ch".into()), Expression::Int(3)]
+                ))
+            )
+        }
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_spawn_requires_call() {
+    let raw = "fn main() {\n    spawn worker\n}";
+    let tokens = tokenize(raw).unwrap();
+    let err = parse(tokens, Some(raw.to_string()), "".into()).unwrap_err();
+    assert!(
+        err.contains("Expected a function call after 'spawn'"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_struct_defaults_and_field_shorthand() {
+    let raw = "
+    struct Config {
+        name: string
+        retries: int = 3
+    }
+
+    fn main() {
+        let config = new Config {
+            name
+        }
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let def = &module.structs[0];
+    assert_eq!(def.fields.len(), 2);
+    assert_eq!(def.defaults.len(), 1);
+    assert_eq!(def.defaults["retries"], Expression::Int(3));
+
+    match &module.func[0].body {
+        Statement::Block(statements, _) => match &statements[0] {
+            Statement::Declare(_, Some(Expression::StructInitialization(_, fields))) => {
+                assert_eq!(*fields["name"], Expression::Variable("name".into()))
+            }
+            other => panic!("Expected struct initialization, got {:?}", other),
+        },
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_struct_update() {
+    match parse_return_expression("new Point { ..origin x: 1 }") {
+        Expression::StructUpdate(name, base, fields) => {
+            assert_eq!(name, "Point");
+            assert_eq!(*base, Expression::Variable("origin".into()));
+            assert_eq!(*fields["x"], Expression::Int(1));
+            assert_eq!(fields.len(), 1);
+        }
+        other => panic!("Expected struct update, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_destructuring_patterns() {
+    let raw = "
+    fn main() {
+        let { x, y } = point
+        for (key, value) in pairs {
+        }
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let statements = match &module.func[0].body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, got {:?}", other),
+    };
+    match &statements[0] {
+        Statement::Destructure(fields, Expression::Variable(value)) => {
+            assert_eq!(value, "point");
+            let names: Vec<_> = fields.iter().map(|(field, _)| field.as_str()).collect();
+            assert_eq!(names, ["x", "y"]);
+        }
+        other => panic!("Expected destructuring, got {:?}", other),
+    }
+    match &statements[1] {
+        Statement::ForPair(key, value, Expression::Variable(pairs), _) => {
+            assert_eq!(key.name, "key");
+            assert_eq!(value.name, "value");
+            assert_eq!(pairs, "pairs");
+        }
+        other => panic!("Expected for loop over pairs, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_globals() {
+    let raw = "
+    const LIMIT: int = 10
+    let count = 0
+
+    fn main() {}
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let globals: Vec<_> = module
+        .globals
+        .iter()
+        .map(|g| (g.var.name.as_str(), g.var.ty.clone(), g.constant))
+        .collect();
+    assert_eq!(
+        globals,
+        [
+            ("LIMIT", Some(Type::Int), true),
+            ("count", Some(Type::Int), false)
+        ]
+    );
+
+    let raw = "let count";
+    let tokens = tokenize(raw).unwrap();
+    assert!(parse(tokens, Some(raw.to_string()), "".into()).is_err());
+}
+
+#[test]
+fn test_static_variables() {
+    let raw = "
+    fn next_id(): int {
+        static id: int = 0
+        id += 1
+        return id
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    match &module.func[0].body {
+        Statement::Block(statements, _) => assert!(matches!(
+            &statements[0],
+            Statement::Static(var, Expression::Int(0)) if var.name == "id" && var.ty == Some(Type::Int)
+        )),
+        other => panic!("Expected block, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_truncated_input_does_not_panic() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "sb") {
+            continue;
+        }
+        let raw = std::fs::read_to_string(&path).unwrap();
+        for (i, _) in raw.char_indices() {
+            let input = &raw[..i];
+            if let Ok(tokens) = tokenize(input) {
+                let _ = parse(tokens, Some(input.to_string()), "".into());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_macro_expansion() {
+    let raw = "
+    macro swap(a, b) {
+        let tmp = a
+        a = b
+        b = tmp
+    }
+
+    macro square(x) {
+        x * x
+    }
+
+    fn main() {
+        let tmp = 1
+        let y = 2
+        swap!(tmp, y)
+        let z = square!(y + 1)
+    }
+    ";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    let statements = match &module.func[0].body {
+        Statement::Block(statements, _) => statements,
+        other => panic!("Expected block, got {:?}", other),
+    };
+    // The variable declared by the macro is renamed, so the argument still refers to the caller's
+    match &statements[2] {
+        Statement::Block(expanded, scope) => {
+            assert!(matches!(
+                &expanded[0],
+                Statement::Declare(var, Some(Expression::Variable(value)))
+                    if var.name == "_macro0_tmp" && value == "tmp"
+            ));
+            assert!(matches!(
+                &expanded[2],
+                Statement::Assign(lhs, rhs)
+                    if **lhs == Expression::Variable("y".into())
+                        && **rhs == Expression::Variable("_macro0_tmp".into())
+            ));
+            assert_eq!(scope[0].name, "_macro0_tmp");
+        }
+        other => panic!("Expected block, got {:?}", other),
+    }
+    match &statements[3] {
+        Statement::Declare(_, Some(Expression::BinOp(lhs, BinOp::Multiplication, rhs))) => {
+            assert!(matches!(&**lhs, Expression::BinOp(_, BinOp::Addition, _)));
+            assert_eq!(lhs, rhs);
+        }
+        other => panic!("Expected multiplication, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_macro_errors() {
+    let error = |body: &str| {
+        let raw = format!("macro log(value) {{\n    println(value)\n}}\n{}", body);
+        let tokens = tokenize(&raw).unwrap();
+        parse(tokens, Some(raw.clone()), "".into()).unwrap_err()
+    };
+
+    // Errors point at the use and the definition of the macro
+    let message = error("fn main() { log!(1, 2) }");
+    assert!(message.starts_with("4:15: Macro 'log' expects 1 argument, found 2"));
+    assert!(message.contains("1:8: Macro 'log' is defined here"));
+
+    assert!(error("fn main() { missing!(1) }").contains("Macro 'missing' is not defined"));
+    assert!(error("macro log() {}").contains("Macro 'log' is already defined"));
+    assert!(
+        error("macro two(x) { log!(x) log!(x) }\nfn main() { let x = two!(1) }")
+            .contains("Macro 'two' expands to statements, so it can not be used as an expression")
+    );
+    assert!(
+        error("macro first(a) { a[0] }\nfn main() { first!(1 + 1) }")
+            .contains("Parameter 'a' of macro 'first' is indexed")
+    );
+
+    // Recursive expansions stop at the depth limit
+    let message = error("macro again(x) { again!(x) }\nfn main() { again!(1) }");
+    assert!(message.contains("Expansions of macro 'again' are nested more than 32 times"));
+    assert!(message.contains("5:17: In the expansion of macro 'again'"));
+}
+
+#[test]
+fn test_include_str() {
+    let raw = "const DATA = include_str!(\"data.txt\")\nfn main() {}";
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "main.sb".into()).unwrap();
+    assert!(matches!(
+        &module.globals[0].value,
+        Expression::FunctionCall(name, args) if name == "_include_str"
+            && args == &[Expression::Str("data.txt".into()), Expression::Str("main.sb:1".into())]
+    ));
+
+    let raw = "fn main() { let path = \"data.txt\"\n let data = include_str!(path) }";
+    let tokens = tokenize(raw).unwrap();
+    let error = parse(tokens, Some(raw.to_string()), "".into()).unwrap_err();
+    assert!(error.contains("'include_str!' expects the path of a file as a string literal"));
+}
@@ -0,0 +1,230 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::returns::diverges;
+use super::types::collect_variables;
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Checks that local variables are assigned on every path before they are read,
+/// e.g. a variable that is declared without a value and only assigned in one branch
+/// of an `if` statement.
+/// Has to run after the scope resolution, since it relies on variables being unique within a function.
+pub(super) fn check(module: &Module) -> Result<(), String> {
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        let mut declared = HashSet::new();
+        collect_declarations(&func.body, &mut declared);
+        let checker = Checker {
+            function: &func.name,
+            declared,
+        };
+        let mut assigned: HashSet<String> =
+            func.arguments.iter().map(|arg| arg.name.clone()).collect();
+        checker.statement(&func.body, &mut assigned)?;
+    }
+    Ok(())
+}
+
+struct Checker<'a> {
+    function: &'a str,
+    /// Local variables of the function. Other names refer to globals.
+    declared: HashSet<String>,
+}
+
+impl Checker<'_> {
+    /// Checks a statement and adds the variables it definitely assigns to `assigned`
+    fn statement(
+        &self,
+        statement: &Statement,
+        assigned: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        match statement {
+            Statement::Block(statements, _) => {
+                for statement in statements {
+                    self.statement(statement, assigned)?;
+                    // The rest of the block is unreachable
+                    if diverges(statement) {
+                        break;
+                    }
+                }
+            }
+            Statement::Declare(var, value) => {
+                if let Some(value) = value {
+                    self.expression(value, assigned)?;
+                    assigned.insert(var.name.clone());
+                } else if let Some(Type::Array(_, Some(_))) = var.ty {
+                    // Arrays with a capacity are allocated by their declaration
+                    assigned.insert(var.name.clone());
+                }
+            }
+            Statement::Destructure(fields, value) => {
+                self.expression(value, assigned)?;
+                assigned.extend(fields.iter().map(|(_, var)| var.name.clone()));
+            }
+            Statement::Static(_, value) => self.expression(value, assigned)?,
+            Statement::Assign(lhs, rhs) => {
+                self.expression(rhs, assigned)?;
+                match &**lhs {
+                    Expression::Variable(name) => {
+                        assigned.insert(name.clone());
+                    }
+                    // Assigning to an element or field reads the array or struct
+                    other => self.expression(other, assigned)?,
+                }
+            }
+            Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::Defer(expr)
+            | Statement::Spawn(expr) => self.expression(expr, assigned)?,
+            Statement::If(cond, if_branch, else_branch) => {
+                self.expression(cond, assigned)?;
+                let mut branches = vec![if_branch.as_ref()];
+                branches.extend(else_branch.as_deref());
+                self.branches(&branches, else_branch.is_some(), assigned)?;
+            }
+            Statement::Match(subject, arms) => {
+                self.expression(subject, assigned)?;
+                let mut exhaustive = false;
+                let mut branches = Vec::new();
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(expr, body) => {
+                            self.expression(expr, assigned)?;
+                            branches.push(body);
+                        }
+                        MatchArm::Else(body) => {
+                            exhaustive = true;
+                            branches.push(body);
+                        }
+                    }
+                }
+                self.branches(&branches, exhaustive, assigned)?;
+            }
+            // The body of a loop may not be executed at all
+            Statement::While(cond, body) => {
+                self.expression(cond, assigned)?;
+                self.statement(body, &mut assigned.clone())?;
+            }
+            Statement::Unsafe(body) => self.statement(body, assigned)?,
+            Statement::For(var, expr, body) => {
+                self.expression(expr, assigned)?;
+                let mut inner = assigned.clone();
+                inner.insert(var.name.clone());
+                self.statement(body, &mut inner)?;
+            }
+            Statement::ForPair(key, value, expr, body) => {
+                self.expression(expr, assigned)?;
+                let mut inner = assigned.clone();
+                inner.insert(key.name.clone());
+                inner.insert(value.name.clone());
+                self.statement(body, &mut inner)?;
+            }
+            Statement::Return(None)
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Inline(..) => {}
+        }
+        Ok(())
+    }
+
+    /// Checks alternative branches. A variable is assigned afterwards if every branch
+    /// that completes assigns it. Unless the branches are exhaustive, none of them may run.
+    fn branches(
+        &self,
+        branches: &[&Statement],
+        exhaustive: bool,
+        assigned: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        let mut merged: Option<HashSet<String>> = if exhaustive {
+            None
+        } else {
+            Some(assigned.clone())
+        };
+        for branch in branches {
+            let mut inner = assigned.clone();
+            self.statement(branch, &mut inner)?;
+            if diverges(branch) {
+                continue;
+            }
+            merged = Some(match merged {
+                Some(merged) => merged.intersection(&inner).cloned().collect(),
+                None => inner,
+            });
+        }
+        // If every branch diverges, the following code is unreachable
+        if let Some(merged) = merged {
+            *assigned = merged;
+        }
+        Ok(())
+    }
+
+    fn expression(&self, expr: &Expression, assigned: &HashSet<String>) -> Result<(), String> {
+        let mut used = Vec::new();
+        collect_variables(expr, &mut used);
+        match used
+            .iter()
+            .find(|name| self.declared.contains(*name) && !assigned.contains(*name))
+        {
+            Some(name) => Err(format!(
+                "Variable '{}' in function '{}' may be used before it is assigned",
+                name, self.function
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Collects the names of all local variables declared in a statement
+fn collect_declarations(statement: &Statement, names: &mut HashSet<String>) {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter()
+            .for_each(|s| collect_declarations(s, names)),
+        Statement::Declare(var, _) => {
+            names.insert(var.name.clone());
+        }
+        Statement::Destructure(fields, _) => {
+            names.extend(fields.iter().map(|(_, var)| var.name.clone()));
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            collect_declarations(if_branch, names);
+            if let Some(else_branch) = else_branch {
+                collect_declarations(else_branch, names);
+            }
+        }
+        Statement::While(_, body) | Statement::Unsafe(body) => collect_declarations(body, names),
+        Statement::For(var, _, body) => {
+            names.insert(var.name.clone());
+            collect_declarations(body, names);
+        }
+        Statement::ForPair(key, value, _, body) => {
+            names.insert(key.name.clone());
+            names.insert(value.name.clone());
+            collect_declarations(body, names);
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, body) | MatchArm::Else(body) => {
+                        collect_declarations(body, names)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,147 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::UNSAFE_INDEX;
+use crate::ast::*;
+
+/// Wraps the indices of all array accesses in calls of `_check_index(array, index, location)`,
+/// which panics if the index is out of bounds and returns it otherwise
+pub(super) fn lower_index_checks(module: &mut Module) {
+    for global in &mut module.globals {
+        let location = format!("global '{}'", global.var.name);
+        lower_expression(&mut global.value, &location);
+    }
+
+    let methods = module
+        .structs
+        .iter_mut()
+        .flat_map(|def| def.methods.iter_mut());
+    for func in module.func.iter_mut().chain(methods) {
+        if func.has_attribute(UNSAFE_INDEX) {
+            continue;
+        }
+        let location = format!("function '{}'", func.name);
+        lower_statement(&mut func.body, &location);
+    }
+}
+
+fn lower_statement(statement: &mut Statement, location: &str) {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .for_each(|s| lower_statement(s, location)),
+        Statement::Declare(_, Some(expr))
+        | Statement::Destructure(_, expr)
+        | Statement::Static(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => lower_expression(expr, location),
+        Statement::Assign(lhs, rhs) => {
+            lower_expression(lhs, location);
+            lower_expression(rhs, location);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            lower_expression(cond, location);
+            lower_statement(if_branch, location);
+            if let Some(else_branch) = else_branch {
+                lower_statement(else_branch, location);
+            }
+        }
+        Statement::While(cond, body) => {
+            lower_expression(cond, location);
+            lower_statement(body, location);
+        }
+        Statement::Unsafe(body) => lower_statement(body, location),
+        Statement::For(_, expr, body) | Statement::ForPair(_, _, expr, body) => {
+            lower_expression(expr, location);
+            lower_statement(body, location);
+        }
+        Statement::Match(subject, arms) => {
+            lower_expression(subject, location);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, body) => {
+                        lower_expression(expr, location);
+                        lower_statement(body, location);
+                    }
+                    MatchArm::Else(body) => lower_statement(body, location),
+                }
+            }
+        }
+        Statement::Declare(_, None)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+}
+
+fn lower_expression(expr: &mut Expression, location: &str) {
+    match expr {
+        Expression::ArrayAccess(name, index) => {
+            lower_expression(index, location);
+            let unchecked = std::mem::replace(&mut **index, Expression::Int(0));
+            **index = Expression::FunctionCall(
+                "_check_index".to_owned(),
+                vec![
+                    Expression::Variable(name.clone()),
+                    unchecked,
+                    Expression::Str(location.to_owned()),
+                ],
+            );
+        }
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            lower_expression(lhs, location);
+            lower_expression(rhs, location);
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => args
+            .iter_mut()
+            .for_each(|arg| lower_expression(arg, location)),
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|field| lower_expression(field, location)),
+        Expression::StructUpdate(_, base, fields) => {
+            lower_expression(base, location);
+            fields
+                .values_mut()
+                .for_each(|field| lower_expression(field, location));
+        }
+        Expression::FieldAccess(obj, field) => {
+            lower_expression(obj, location);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut()
+                    .for_each(|arg| lower_expression(arg, location));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                lower_expression(key, location);
+                lower_expression(value, location);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => lower_expression(operand, location),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_)
+        | Expression::SizeOf(_) => {}
+    }
+}
@@ -0,0 +1,80 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Checks that no two cases of a `match` statement have the same value, and that every
+/// `match` statement has an `else` arm, unless its cases cover every possible value.
+pub(super) fn check(module: &Module) -> Result<(), String> {
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        check_statement(&func.body)
+            .map_err(|err| format!("{} in function '{}'", err, func.name))?;
+    }
+    Ok(())
+}
+
+fn check_statement(statement: &Statement) -> Result<(), String> {
+    match statement {
+        Statement::Block(statements, _) => statements.iter().try_for_each(check_statement),
+        Statement::If(_, if_branch, else_branch) => {
+            check_statement(if_branch)?;
+            else_branch.as_deref().map_or(Ok(()), check_statement)
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(_, _, _, body)
+        | Statement::Unsafe(body) => check_statement(body),
+        Statement::Match(_, arms) => {
+            let mut seen = HashSet::new();
+            for arm in arms {
+                let body = match arm {
+                    MatchArm::Case(case, body) => {
+                        if let Some(value) = literal(case) {
+                            if !seen.insert(value.clone()) {
+                                return Err(format!(
+                                    "Duplicate case {} in `match` statement",
+                                    value
+                                ));
+                            }
+                        }
+                        body
+                    }
+                    MatchArm::Else(body) => body,
+                };
+                check_statement(body)?;
+            }
+            if !MatchArm::is_exhaustive(arms) {
+                return Err(
+                    "`match` statement needs an `else` arm, since its cases do not cover every value"
+                        .to_owned(),
+                );
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Source representation of a case whose value is known at compile time
+fn literal(case: &Expression) -> Option<String> {
+    match case {
+        Expression::Int(value) => Some(value.to_string()),
+        Expression::Bool(value) => Some(value.to_string()),
+        Expression::Str(value) => Some(format!("\"{}\"", value)),
+        _ => None,
+    }
+}
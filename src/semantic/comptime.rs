@@ -0,0 +1,190 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::COMPTIME;
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Methods of arrays and strings that can be called at compile time
+const COMPTIME_METHODS: [&str; 3] = ["len", "push", "pop"];
+
+/// Reason for rejecting values of other types
+const VALUES: &str =
+    "since only integers, booleans, strings and arrays of them are computed at compile time";
+
+/// Checks that functions annotated with `@comptime` can be evaluated by the compiler.
+/// They may only compute with integers, booleans, strings and arrays of them, and only
+/// call other `@comptime` functions, so that their result only depends on their arguments.
+/// Has to run before methods are lowered to builtins, so that errors name the method.
+pub(super) fn check(module: &Module) -> Result<(), String> {
+    let comptime: HashSet<&str> = module
+        .func
+        .iter()
+        .filter(|func| func.has_attribute(COMPTIME))
+        .map(|func| func.name.as_str())
+        .collect();
+    let globals: HashSet<&str> = module.globals.iter().map(|g| g.var.name.as_str()).collect();
+
+    for func in module
+        .func
+        .iter()
+        .filter(|func| func.has_attribute(COMPTIME))
+    {
+        let env = Env {
+            func: &func.name,
+            comptime: &comptime,
+            globals: &globals,
+        };
+        if func.is_async {
+            return Err(env.error("can not be async"));
+        }
+        for arg in &func.arguments {
+            if arg.reference || !arg.ty.as_ref().is_some_and(is_comptime_type) {
+                return Err(env.error(&format!("can not take argument '{}', {}", arg.name, VALUES)));
+            }
+        }
+        match &func.ret_type {
+            Some(ty) if is_comptime_type(ty) => {}
+            Some(_) => return Err(env.error(&format!("can not return this type, {}", VALUES))),
+            None => return Err(env.error("has to return a value")),
+        }
+        env.check_statement(&func.body)?;
+    }
+    Ok(())
+}
+
+fn is_comptime_type(ty: &Type) -> bool {
+    match ty {
+        Type::Int | Type::Bool | Type::Str => true,
+        Type::Array(elem, _) => is_comptime_type(elem),
+        _ => false,
+    }
+}
+
+struct Env<'a> {
+    func: &'a str,
+    comptime: &'a HashSet<&'a str>,
+    globals: &'a HashSet<&'a str>,
+}
+
+impl Env<'_> {
+    fn error(&self, reason: &str) -> String {
+        format!("@comptime function '{}' {}", self.func, reason)
+    }
+
+    fn check_statement(&self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Block(statements, _) => {
+                statements.iter().try_for_each(|s| self.check_statement(s))
+            }
+            Statement::Declare(_, None) | Statement::Return(None) => Ok(()),
+            Statement::Break | Statement::Continue => Ok(()),
+            Statement::Declare(_, Some(expr))
+            | Statement::Return(Some(expr))
+            | Statement::Exp(expr) => self.check_expression(expr),
+            Statement::Assign(lhs, rhs) => {
+                self.check_expression(lhs)?;
+                self.check_expression(rhs)
+            }
+            Statement::If(cond, if_branch, else_branch) => {
+                self.check_expression(cond)?;
+                self.check_statement(if_branch)?;
+                match else_branch {
+                    Some(else_branch) => self.check_statement(else_branch),
+                    None => Ok(()),
+                }
+            }
+            Statement::While(cond, body) => {
+                self.check_expression(cond)?;
+                self.check_statement(body)
+            }
+            Statement::For(_, iterable, body) | Statement::ForPair(_, _, iterable, body) => {
+                self.check_expression(iterable)?;
+                self.check_statement(body)
+            }
+            Statement::Match(subject, arms) => {
+                self.check_expression(subject)?;
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(case, s) => {
+                            self.check_expression(case)?;
+                            self.check_statement(s)?;
+                        }
+                        MatchArm::Else(s) => self.check_statement(s)?,
+                    }
+                }
+                Ok(())
+            }
+            Statement::Static(..) => Err(self.error("can not declare static variables")),
+            Statement::Destructure(..) => {
+                Err(self.error(&format!("can not destructure, {}", VALUES)))
+            }
+            Statement::Defer(_) => Err(self.error("can not use 'defer'")),
+            Statement::Spawn(_) => Err(self.error("can not spawn tasks")),
+            Statement::Inline(target, _) => {
+                Err(self.error(&format!("can not contain a '{}!' block", target)))
+            }
+            Statement::Unsafe(_) => Err(self.error("can not contain unsafe blocks")),
+        }
+    }
+
+    fn check_expression(&self, expr: &Expression) -> Result<(), String> {
+        match expr {
+            Expression::Int(_) | Expression::Bool(_) | Expression::Str(_) => Ok(()),
+            Expression::Variable(name) | Expression::ArrayAccess(name, _)
+                if self.globals.contains(name.as_str()) =>
+            {
+                Err(self.error(&format!(
+                    "can not use global '{}', since its value is only known at runtime",
+                    name
+                )))
+            }
+            Expression::Variable(_) => Ok(()),
+            Expression::ArrayAccess(_, index) => self.check_expression(index),
+            Expression::Array(_, elements) => {
+                elements.iter().try_for_each(|e| self.check_expression(e))
+            }
+            Expression::BinOp(lhs, _, rhs) => {
+                self.check_expression(lhs)?;
+                self.check_expression(rhs)
+            }
+            Expression::FunctionCall(name, args) => {
+                if !self.comptime.contains(name.as_str()) {
+                    return Err(self.error(&format!(
+                        "can not call '{}', which is not annotated with @comptime",
+                        name
+                    )));
+                }
+                args.iter().try_for_each(|arg| self.check_expression(arg))
+            }
+            Expression::FieldAccess(obj, method) => match &**method {
+                Expression::FunctionCall(name, args)
+                    if COMPTIME_METHODS.contains(&name.as_str()) =>
+                {
+                    self.check_expression(obj)?;
+                    args.iter().try_for_each(|arg| self.check_expression(arg))
+                }
+                Expression::FunctionCall(name, _) => Err(self.error(&format!(
+                    "can not call method '{}', only '{}' can be called at compile time",
+                    name,
+                    COMPTIME_METHODS.join("', '")
+                ))),
+                _ => Err(self.error(&format!("can not access fields, {}", VALUES))),
+            },
+            _ => Err(self.error(&format!("can not use this expression, {}", VALUES))),
+        }
+    }
+}
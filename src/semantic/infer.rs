@@ -0,0 +1,236 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::{is_compatible, TypeEnv};
+use crate::ast::types::Type;
+use crate::ast::*;
+
+/// Infers the types of variables from their initializers, so that lints can rely on them.
+///
+/// Only the functions of the module are known when a single module is inferred.
+/// Variables that are initialized by functions of other modules are inferred by [`infer_types`].
+pub fn infer_variables(module: &mut Module) {
+    let mut env = TypeEnv::new(module);
+    infer_globals(&mut env, module);
+    for (_, func) in functions_of(module) {
+        infer_function(&mut env, func);
+    }
+}
+
+/// Infers the types of variables, and the return types of functions that do not declare one.
+/// The return type is the type of the values returned by the function. Returned values
+/// of different types are reported.
+///
+/// Return types depend on the return types of called functions, so the functions
+/// are inferred repeatedly until no return type changes.
+pub fn infer_types(module: &mut Module) -> Result<(), String> {
+    let mut env = TypeEnv::new(module);
+    infer_globals(&mut env, module);
+
+    let mut functions = functions_of(module);
+    let undeclared: Vec<bool> = functions
+        .iter()
+        .map(|(_, func)| func.ret_type.is_none())
+        .collect();
+    // Every round that does not end the inference infers at least one more return type
+    for _ in 0..=functions.len() {
+        let mut changed = false;
+        for ((key, func), undeclared) in functions.iter_mut().zip(&undeclared) {
+            let returns = infer_function(&mut env, func);
+            if !undeclared {
+                continue;
+            }
+            let ty = return_type(&func.name, &returns)?;
+            if ty != func.ret_type {
+                func.ret_type = ty.clone();
+                env.declare_return_type(key, ty);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the functions and methods of a module. Methods are named `<struct>.<method>`.
+fn functions_of(module: &mut Module) -> Vec<(String, &mut Function)> {
+    let mut functions: Vec<(String, &mut Function)> = module
+        .func
+        .iter_mut()
+        .map(|func| (func.name.clone(), func))
+        .collect();
+    for def in &mut module.structs {
+        for method in &mut def.methods {
+            functions.push((format!("{}.{}", def.name, method.name), method));
+        }
+    }
+    functions
+}
+
+fn infer_globals(env: &mut TypeEnv, module: &mut Module) {
+    for global in &mut module.globals {
+        if global.var.ty.is_none() {
+            global.var.ty = env.expression_type(&global.value);
+        }
+        env.declare_global(&global.var.name, global.var.ty.clone());
+    }
+}
+
+/// Infers the types of the variables of a function and returns the types of the returned values
+fn infer_function(env: &mut TypeEnv, func: &mut Function) -> Vec<Option<Type>> {
+    env.enter_function(func);
+    let mut inference = Inference {
+        env,
+        returns: Vec::new(),
+    };
+    inference.statement(&mut func.body, &mut Vec::new());
+    inference.returns
+}
+
+/// Returns the common type of the returned values. Values of an unknown type are ignored.
+fn return_type(name: &str, returns: &[Option<Type>]) -> Result<Option<Type>, String> {
+    let mut inferred: Option<Type> = None;
+    for ty in returns.iter().flatten() {
+        // The capacity of returned arrays may differ
+        let ty = match ty {
+            Type::Array(elem, _) => Type::Array(elem.clone(), None),
+            ty => ty.clone(),
+        };
+        match &inferred {
+            None => inferred = Some(ty),
+            Some(previous) if !is_compatible(previous, &ty) => {
+                return Err(format!(
+                    "Function '{}' returns both {} and {}",
+                    name, previous, ty
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(inferred)
+}
+
+struct Inference<'a> {
+    env: &'a mut TypeEnv,
+    /// Types of the values returned by the current function
+    returns: Vec<Option<Type>>,
+}
+
+impl Inference<'_> {
+    /// Infers the types of the variables declared by a statement.
+    /// The previous types of the declared variables are added to `shadowed`,
+    /// so that they can be restored when the enclosing scope ends.
+    fn statement(&mut self, statement: &mut Statement, shadowed: &mut Vec<(String, Option<Type>)>) {
+        match statement {
+            Statement::Block(statements, _) => {
+                let mut scope = Vec::new();
+                for statement in statements {
+                    self.statement(statement, &mut scope);
+                }
+                self.restore(scope);
+            }
+            Statement::Declare(var, expr) => {
+                if var.ty.is_none() {
+                    var.ty = expr.as_ref().and_then(|e| self.env.expression_type(e));
+                }
+                self.declare(var, shadowed);
+            }
+            Statement::Static(var, expr) => {
+                if var.ty.is_none() {
+                    var.ty = self.env.expression_type(expr);
+                }
+                self.declare(var, shadowed);
+            }
+            Statement::Destructure(fields, expr) => {
+                let name = match self.env.expression_type(expr) {
+                    Some(Type::Struct(name)) => Some(name),
+                    _ => None,
+                };
+                for (field, var) in fields {
+                    if var.ty.is_none() {
+                        var.ty = name
+                            .as_ref()
+                            .and_then(|name| self.env.field_type(name, field))
+                            .cloned();
+                    }
+                    self.declare(var, shadowed);
+                }
+            }
+            Statement::For(var, expr, body) => {
+                if var.ty.is_none() {
+                    if let Some(Type::Array(ty, _)) = self.env.expression_type(expr) {
+                        var.ty = Some(*ty);
+                    }
+                }
+                let mut scope = Vec::new();
+                self.declare(var, &mut scope);
+                self.statement(body, &mut scope);
+                self.restore(scope);
+            }
+            Statement::ForPair(key, value, _, body) => {
+                let mut scope = Vec::new();
+                self.declare(key, &mut scope);
+                self.declare(value, &mut scope);
+                self.statement(body, &mut scope);
+                self.restore(scope);
+            }
+            Statement::If(_, if_branch, else_branch) => {
+                self.statement(if_branch, shadowed);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch, shadowed);
+                }
+            }
+            Statement::While(_, body) | Statement::Unsafe(body) => self.statement(body, shadowed),
+            Statement::Match(_, arms) => {
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(_, body) | MatchArm::Else(body) => {
+                            self.statement(body, shadowed)
+                        }
+                    }
+                }
+            }
+            Statement::Return(Some(expr)) => {
+                let ty = self.env.expression_type(expr);
+                self.returns.push(ty);
+            }
+            Statement::Assign(..)
+            | Statement::Return(None)
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Inline(..)
+            | Statement::Exp(_)
+            | Statement::Defer(_)
+            | Statement::Spawn(_) => {}
+        }
+    }
+
+    fn declare(&mut self, var: &Variable, shadowed: &mut Vec<(String, Option<Type>)>) {
+        let previous = self
+            .env
+            .expression_type(&Expression::Variable(var.name.clone()));
+        shadowed.push((var.name.clone(), previous));
+        self.env.declare(&var.name, var.ty.clone());
+    }
+
+    /// Restores the types of variables that were shadowed in a scope that ends
+    fn restore(&mut self, shadowed: Vec<(String, Option<Type>)>) {
+        for (name, ty) in shadowed.into_iter().rev() {
+            self.env.declare(&name, ty);
+        }
+    }
+}
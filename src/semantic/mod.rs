@@ -0,0 +1,90 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::*;
+
+mod assignment;
+mod bounds;
+mod cases;
+mod comptime;
+pub mod infer;
+mod overflow;
+mod pattern;
+mod regex;
+mod result;
+pub mod returns;
+mod scope;
+mod statics;
+#[cfg(test)]
+mod tests;
+pub mod types;
+mod visibility;
+
+/// Normalizes the module after parsing, so that later stages can make
+/// simplifying assumptions about it.
+///
+/// Variables that shadow an earlier declaration are renamed, which means
+/// that every variable is unique within its function and does not collide
+/// with a global variable.
+pub fn analyze(module: &mut Module) {
+    let globals: Vec<String> = module.globals.iter().map(|g| g.var.name.clone()).collect();
+    for func in &mut module.func {
+        let owner = func.name.clone();
+        scope::resolve_shadowing(func, &owner, &globals);
+    }
+
+    for def in &mut module.structs {
+        for method in &mut def.methods {
+            let owner = statics::method_owner(&def.name, &method.name);
+            scope::resolve_shadowing(method, &owner, &globals);
+        }
+    }
+}
+
+/// Checks that declarations of other modules are only used if they are declared with `pub`.
+/// Has to run on the modules of the program before they are merged.
+pub fn check_visibility(modules: &[Module]) -> Result<(), String> {
+    visibility::check(modules)
+}
+
+/// Checks that the operators and string methods of the module are applied to operands
+/// of a matching type, and lowers string methods to calls of builtin functions.
+/// `static` variables are moved to the globals of the module, and missing return types
+/// are inferred. Functions with a declared return type have to return on every path,
+/// and variables have to be assigned on every path before they are read. The cases of
+/// `match` statements have to be distinct and cover every value of the subject.
+/// Functions annotated with `@comptime` may only use what the compiler can evaluate.
+/// Has to run after [`analyze`], since it relies on variables being unique within a function.
+pub fn check(module: &mut Module) -> Result<(), String> {
+    statics::hoist_statics(module)?;
+    returns::check(module)?;
+    assignment::check(module)?;
+    infer::infer_types(module)?;
+    comptime::check(module)?;
+    types::check(module)?;
+    cases::check(module)
+}
+
+/// Replaces integer arithmetic with checked operations that panic if the result overflows.
+/// Has to run after [`check`], since it relies on the inferred types of variables.
+pub fn lower_overflow_checks(module: &mut Module) {
+    overflow::lower_arithmetic(module)
+}
+
+/// Inserts bounds checks into array accesses, except in functions annotated with `@unsafe_index`.
+/// Accesses with an index that is out of bounds panic instead of reading arbitrary memory.
+pub fn lower_index_checks(module: &mut Module) {
+    bounds::lower_index_checks(module)
+}
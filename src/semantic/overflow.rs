@@ -0,0 +1,243 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::TypeEnv;
+use crate::ast::types::Type;
+use crate::ast::*;
+
+/// Lowers additions, subtractions and multiplications of integers to calls of builtin
+/// functions, e.g. `a + b` to `_add_checked(a, b, location)`
+pub(super) fn lower_arithmetic(module: &mut Module) {
+    let mut env = TypeEnv::new(module);
+    for global in &mut module.globals {
+        let location = format!("global '{}'", global.var.name);
+        lower_expression(&env, &mut global.value, &location);
+        env.declare_global(&global.var.name, global.var.ty.clone());
+    }
+
+    let methods = module
+        .structs
+        .iter_mut()
+        .flat_map(|def| def.methods.iter_mut());
+    for func in module.func.iter_mut().chain(methods) {
+        env.enter_function(func);
+        declare_variables(&mut env, &func.body);
+        let location = format!("function '{}'", func.name);
+        lower_statement(&env, &mut func.body, &location);
+    }
+}
+
+/// Returns the builtin function that checks an operation for overflows
+fn checked_function(op: &BinOp) -> Option<&'static str> {
+    match op {
+        BinOp::Addition | BinOp::AddAssign => Some("_add_checked"),
+        BinOp::Subtraction | BinOp::SubtractAssign => Some("_sub_checked"),
+        BinOp::Multiplication | BinOp::MultiplyAssign => Some("_mul_checked"),
+        _ => None,
+    }
+}
+
+/// Declares all variables of a function. Variables are unique within a function,
+/// so their scopes do not have to be tracked.
+fn declare_variables(env: &mut TypeEnv, statement: &Statement) {
+    match statement {
+        Statement::Block(statements, _) => {
+            statements.iter().for_each(|s| declare_variables(env, s))
+        }
+        Statement::Declare(var, _) => env.declare(&var.name, var.ty.clone()),
+        Statement::Destructure(fields, _) => fields
+            .iter()
+            .for_each(|(_, var)| env.declare(&var.name, var.ty.clone())),
+        Statement::If(_, if_branch, else_branch) => {
+            declare_variables(env, if_branch);
+            if let Some(else_branch) = else_branch {
+                declare_variables(env, else_branch);
+            }
+        }
+        Statement::While(_, body) | Statement::Unsafe(body) => declare_variables(env, body),
+        Statement::For(var, _, body) => {
+            env.declare(&var.name, var.ty.clone());
+            declare_variables(env, body);
+        }
+        Statement::ForPair(key, value, _, body) => {
+            env.declare(&key.name, key.ty.clone());
+            env.declare(&value.name, value.ty.clone());
+            declare_variables(env, body);
+        }
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, body) | MatchArm::Else(body) => declare_variables(env, body),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn lower_statement(env: &TypeEnv, statement: &mut Statement, location: &str) {
+    match statement {
+        Statement::Block(statements, _) => statements
+            .iter_mut()
+            .for_each(|s| lower_statement(env, s, location)),
+        // `x += y` is lowered to `x = _add_checked(x, y)`, so the target is evaluated twice
+        Statement::Exp(Expression::BinOp(lhs, op, rhs))
+            if checked_function(op).is_some() && is_assign(op) && is_plain_place(lhs) =>
+        {
+            lower_expression(env, rhs, location);
+            if is_int(env, lhs) && is_int(env, rhs) {
+                let call = checked_call(checked_function(op).unwrap(), lhs, rhs, location);
+                *statement = Statement::Assign(lhs.clone(), Box::new(call));
+            }
+        }
+        Statement::Declare(_, Some(expr))
+        | Statement::Destructure(_, expr)
+        | Statement::Static(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => lower_expression(env, expr, location),
+        Statement::Assign(lhs, rhs) => {
+            lower_expression(env, lhs, location);
+            lower_expression(env, rhs, location);
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            lower_expression(env, cond, location);
+            lower_statement(env, if_branch, location);
+            if let Some(else_branch) = else_branch {
+                lower_statement(env, else_branch, location);
+            }
+        }
+        Statement::While(cond, body) => {
+            lower_expression(env, cond, location);
+            lower_statement(env, body, location);
+        }
+        Statement::Unsafe(body) => lower_statement(env, body, location),
+        Statement::For(_, expr, body) | Statement::ForPair(_, _, expr, body) => {
+            lower_expression(env, expr, location);
+            lower_statement(env, body, location);
+        }
+        Statement::Match(subject, arms) => {
+            lower_expression(env, subject, location);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, body) => {
+                        lower_expression(env, expr, location);
+                        lower_statement(env, body, location);
+                    }
+                    MatchArm::Else(body) => lower_statement(env, body, location),
+                }
+            }
+        }
+        Statement::Declare(_, None)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+}
+
+fn lower_expression(env: &TypeEnv, expr: &mut Expression, location: &str) {
+    match expr {
+        Expression::BinOp(lhs, op, rhs) => {
+            lower_expression(env, lhs, location);
+            lower_expression(env, rhs, location);
+            if let Some(function) = checked_function(op) {
+                if !is_assign(op) && is_int(env, lhs) && is_int(env, rhs) {
+                    *expr = checked_call(function, lhs, rhs, location);
+                }
+            }
+        }
+        Expression::ArrayAccess(_, index) | Expression::Load(_, index) => {
+            lower_expression(env, index, location)
+        }
+        Expression::Store(_, address, value) => {
+            lower_expression(env, address, location);
+            lower_expression(env, value, location);
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => args
+            .iter_mut()
+            .for_each(|arg| lower_expression(env, arg, location)),
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|field| lower_expression(env, field, location)),
+        Expression::StructUpdate(_, base, fields) => {
+            lower_expression(env, base, location);
+            fields
+                .values_mut()
+                .for_each(|field| lower_expression(env, field, location));
+        }
+        Expression::FieldAccess(obj, field) => {
+            lower_expression(env, obj, location);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut()
+                    .for_each(|arg| lower_expression(env, arg, location));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                lower_expression(env, key, location);
+                lower_expression(env, value, location);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand) => lower_expression(env, operand, location),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_)
+        | Expression::SizeOf(_) => {}
+    }
+}
+
+fn checked_call(function: &str, lhs: &Expression, rhs: &Expression, location: &str) -> Expression {
+    Expression::FunctionCall(
+        function.to_owned(),
+        vec![
+            lhs.clone(),
+            rhs.clone(),
+            Expression::Str(location.to_owned()),
+        ],
+    )
+}
+
+fn is_assign(op: &BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::AddAssign | BinOp::SubtractAssign | BinOp::MultiplyAssign
+    )
+}
+
+fn is_int(env: &TypeEnv, expr: &Expression) -> bool {
+    env.expression_type(expr) == Some(Type::Int)
+}
+
+/// Returns true if evaluating the target of an assignment twice has no side effects
+fn is_plain_place(expr: &Expression) -> bool {
+    match expr {
+        Expression::Variable(_) => true,
+        Expression::ArrayAccess(_, index) => {
+            matches!(**index, Expression::Int(_) | Expression::Variable(_))
+        }
+        Expression::FieldAccess(obj, field) => {
+            matches!(**field, Expression::Variable(_))
+                && matches!(**obj, Expression::Variable(_) | Expression::Selff)
+        }
+        _ => false,
+    }
+}
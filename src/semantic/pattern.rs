@@ -0,0 +1,235 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::TypeEnv;
+use crate::ast::types::Type;
+use crate::ast::*;
+
+/// Replaces a statement that binds variables with a pattern by plain declarations and loops,
+/// a loop over a struct by a loop over the indices of its elements, and a loop over a
+/// string by a loop over its characters.
+/// Other statements are returned unchanged.
+pub(super) fn desugar_pattern(
+    env: &mut TypeEnv,
+    statement: Statement,
+) -> Result<Vec<Statement>, String> {
+    match statement {
+        Statement::Destructure(fields, expr) => {
+            let name = match env.expression_type(&expr) {
+                Some(Type::Struct(name)) => name,
+                Some(Type::Any) | None => {
+                    return Err("The type of a destructured value must be known".into())
+                }
+                Some(ty) => return Err(format!("Only structs can be destructured, found {}", ty)),
+            };
+            if let Some((field, _)) = fields
+                .iter()
+                .find(|(field, _)| env.field_type(&name, field).is_none())
+            {
+                return Err(format!("Struct '{}' has no field '{}'", name, field));
+            }
+
+            let (mut statements, value) = bind(env, expr);
+            for (field, var) in fields {
+                let access = Expression::FieldAccess(
+                    Box::new(value.clone()),
+                    Box::new(Expression::Variable(field)),
+                );
+                statements.push(Statement::Declare(var, Some(access)));
+            }
+            Ok(statements)
+        }
+        Statement::ForPair(key, value, expr, mut body) => match env.expression_type(&expr) {
+            // for key in map.keys() { let value = map.get(key) }
+            Some(Type::Map(..)) => {
+                let (mut statements, map) = bind(env, expr);
+                let method = |name: &str, args| {
+                    Expression::FieldAccess(
+                        Box::new(map.clone()),
+                        Box::new(Expression::FunctionCall(name.into(), args)),
+                    )
+                };
+                let get = method("get", vec![Expression::Variable(key.name.clone())]);
+                prepend(&mut body, vec![Statement::Declare(value, Some(get))]);
+                statements.push(Statement::For(key, method("keys", Vec::new()), body));
+                Ok(statements)
+            }
+            Some(Type::Struct(name)) => iterate(env, &name, Some(key), value, expr, body),
+            // The index is counted at the start of an iteration, so `continue` can not skip it
+            Some(Type::Array(..)) => {
+                let counter = Variable {
+                    name: env.temporary("index"),
+                    ty: Some(Type::Int),
+                    pos: None,
+                    reference: false,
+                };
+                let count = Expression::Variable(counter.name.clone());
+                prepend(
+                    &mut body,
+                    vec![
+                        Statement::Declare(key, Some(count.clone())),
+                        Statement::Assign(
+                            Box::new(count.clone()),
+                            Box::new(Expression::BinOp(
+                                Box::new(count),
+                                BinOp::Addition,
+                                Box::new(Expression::Int(1)),
+                            )),
+                        ),
+                    ],
+                );
+                Ok(vec![
+                    Statement::Declare(counter, Some(Expression::Int(0))),
+                    Statement::For(value, expr, body),
+                ])
+            }
+            Some(Type::Any) | None => {
+                Err("The type of a value that is iterated in pairs must be known".into())
+            }
+            Some(ty) => Err(format!(
+                "Only maps, arrays and structs can be iterated in pairs, found {}",
+                ty
+            )),
+        },
+        Statement::For(var, expr, body) => match env.expression_type(&expr) {
+            Some(Type::Struct(name)) => iterate(env, &name, None, var, expr, body),
+            // Strings are iterated by their characters, not by their bytes
+            Some(Type::Str) => Ok(iterate_chars(env, None, var, expr, body)),
+            _ => Ok(vec![Statement::For(var, expr, body)]),
+        },
+        statement => Ok(vec![statement]),
+    }
+}
+
+/// Loops over the elements of a struct that declares the functions of the iteration protocol
+fn iterate(
+    env: &mut TypeEnv,
+    name: &str,
+    key: Option<Variable>,
+    mut element: Variable,
+    expr: Expression,
+    body: Box<Statement>,
+) -> Result<Vec<Statement>, String> {
+    match (
+        env.protocol_function(name, PROTOCOL_LEN),
+        env.protocol_function(name, PROTOCOL_GET),
+    ) {
+        (Some((len, _)), Some((get, ty))) => {
+            element.ty = element.ty.or(ty);
+            let get = |value, index| Expression::FunctionCall(get, vec![value, index]);
+            Ok(index_loop(env, key, element, expr, &len, get, body))
+        }
+        _ => Err(format!(
+            "Struct '{0}' can not be iterated. Declare the functions '{0}_{1}(value: {0}): int' and '{0}_{2}(value: {0}, index: int)'",
+            name, PROTOCOL_LEN, PROTOCOL_GET
+        )),
+    }
+}
+
+/// Loops over the characters of a string, which are collected into an array first
+fn iterate_chars(
+    env: &mut TypeEnv,
+    key: Option<Variable>,
+    mut element: Variable,
+    expr: Expression,
+    body: Box<Statement>,
+) -> Vec<Statement> {
+    element.ty = Some(Type::Str);
+    let chars = Expression::FunctionCall("_str_chars".into(), vec![expr]);
+    let get = |value, index| match value {
+        Expression::Variable(name) => Expression::ArrayAccess(name, Box::new(index)),
+        _ => unreachable!("Characters are bound to a variable"),
+    };
+    index_loop(env, key, element, chars, "_vec_len", get, body)
+}
+
+/// Loops over the indices of a value, which supports loops in backends that only implement `while`:
+///
+/// ```text
+/// let value = <expr>
+/// let index = 0
+/// while index < len(value) {
+///     let key = index
+///     let element = get(value, index)
+///     index = index + 1
+///     <body>
+/// }
+/// ```
+fn index_loop(
+    env: &mut TypeEnv,
+    key: Option<Variable>,
+    element: Variable,
+    expr: Expression,
+    len: &str,
+    get: impl FnOnce(Expression, Expression) -> Expression,
+    mut body: Box<Statement>,
+) -> Vec<Statement> {
+    let (mut statements, value) = bind(env, expr);
+    let counter = Variable {
+        name: env.temporary("index"),
+        ty: Some(Type::Int),
+        pos: None,
+        reference: false,
+    };
+    let index = Expression::Variable(counter.name.clone());
+    let mut prologue: Vec<Statement> = key
+        .map(|key| Statement::Declare(key, Some(index.clone())))
+        .into_iter()
+        .collect();
+    prologue.push(Statement::Declare(
+        element,
+        Some(get(value.clone(), index.clone())),
+    ));
+    prologue.push(Statement::Assign(
+        Box::new(index.clone()),
+        Box::new(Expression::BinOp(
+            Box::new(index.clone()),
+            BinOp::Addition,
+            Box::new(Expression::Int(1)),
+        )),
+    ));
+    prepend(&mut body, prologue);
+
+    let condition = Expression::BinOp(
+        Box::new(index),
+        BinOp::LessThan,
+        Box::new(Expression::FunctionCall(len.into(), vec![value])),
+    );
+    statements.push(Statement::Declare(counter, Some(Expression::Int(0))));
+    statements.push(Statement::While(condition, body));
+    statements
+}
+
+/// Returns the statements that evaluate an expression once and an expression referring to its value
+fn bind(env: &mut TypeEnv, expr: Expression) -> (Vec<Statement>, Expression) {
+    if let Expression::Variable(_) = expr {
+        return (Vec::new(), expr);
+    }
+    let var = Variable {
+        name: env.temporary("value"),
+        ty: None,
+        pos: None,
+        reference: false,
+    };
+    let value = Expression::Variable(var.name.clone());
+    (vec![Statement::Declare(var, Some(expr))], value)
+}
+
+fn prepend(body: &mut Statement, mut statements: Vec<Statement>) {
+    if let Statement::Block(body, _) = body {
+        statements.append(body);
+        *body = statements;
+    }
+}
@@ -0,0 +1,176 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Functions of `lib/regex.sb` that take a pattern as their first argument
+const PATTERN_FUNCTIONS: [&str; 5] = [
+    "regex_matches",
+    "regex_find",
+    "regex_find_all",
+    "regex_replace",
+    "_regex_find",
+];
+
+/// Characters that have to be escaped to match themselves
+const SYNTAX_CHARACTERS: &str = "^$\\.*+?()[]{}|/";
+
+pub(super) fn takes_pattern(function: &str) -> bool {
+    PATTERN_FUNCTIONS.contains(&function)
+}
+
+/// Checks the syntax of a regular expression. The syntax is the subset of JavaScript's
+/// that the engine of the native runtime implements, so that a pattern that passes
+/// matches the same text in every backend.
+pub(super) fn check_pattern(pattern: &str) -> Result<(), String> {
+    let mut chars = pattern.chars().peekable();
+    check_alternatives(&mut chars)?;
+    match chars.next() {
+        Some(')') => Err("Unmatched ')'".into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_alternatives(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    check_sequence(chars)?;
+    while chars.next_if_eq(&'|').is_some() {
+        check_sequence(chars)?;
+    }
+    Ok(())
+}
+
+fn check_sequence(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    while let Some(&c) = chars.peek() {
+        if c == '|' || c == ')' {
+            break;
+        }
+        let quantifiable = check_atom(chars)?;
+        if check_quantifier(chars)? {
+            if !quantifiable {
+                return Err("Nothing to repeat".into());
+            }
+            chars.next_if_eq(&'?');
+        }
+    }
+    Ok(())
+}
+
+/// Checks an atom and returns whether it can be repeated, which anchors can not
+fn check_atom(chars: &mut Peekable<Chars>) -> Result<bool, String> {
+    match chars.next() {
+        Some('^' | '$') => return Ok(false),
+        Some('*' | '+' | '?' | '{') => return Err("Nothing to repeat".into()),
+        Some(']' | '}') => return Err("Lone quantifier brackets".into()),
+        Some('[') => check_class(chars)?,
+        Some('(') => {
+            if chars.next_if_eq(&'?').is_some() && chars.next() != Some(':') {
+                return Err("Invalid group".into());
+            }
+            check_alternatives(chars)?;
+            if chars.next() != Some(')') {
+                return Err("Unterminated group".into());
+            }
+        }
+        Some('\\') => {
+            check_escape(chars, false)?;
+        }
+        _ => {}
+    }
+    Ok(true)
+}
+
+/// Checks the character after a backslash. Returns the escaped character,
+/// or `None` for class escapes like `\d`
+fn check_escape(chars: &mut Peekable<Chars>, in_class: bool) -> Result<Option<char>, String> {
+    match chars.next() {
+        None => Err("\\ at end of pattern".into()),
+        Some('d' | 'D' | 'w' | 'W' | 's' | 'S') => Ok(None),
+        Some('n') => Ok(Some('\n')),
+        Some('r') => Ok(Some('\r')),
+        Some('t') => Ok(Some('\t')),
+        Some('f') => Ok(Some('\x0c')),
+        Some('v') => Ok(Some('\x0b')),
+        Some(c) if SYNTAX_CHARACTERS.contains(c) || (in_class && c == '-') => Ok(Some(c)),
+        Some(_) => Err("Invalid escape".into()),
+    }
+}
+
+fn check_class(chars: &mut Peekable<Chars>) -> Result<(), String> {
+    chars.next_if_eq(&'^');
+    loop {
+        let lo = match chars.next() {
+            None => return Err("Unterminated character class".into()),
+            Some(']') => return Ok(()),
+            Some('\\') => check_escape(chars, true)?,
+            c => c,
+        };
+        let mut rest = chars.clone();
+        if rest.next() != Some('-') || matches!(rest.peek(), None | Some(']')) {
+            continue;
+        }
+        chars.next();
+        let hi = match chars.next() {
+            Some('\\') => check_escape(chars, true)?,
+            c => c,
+        };
+        match (lo, hi) {
+            (Some(lo), Some(hi)) if lo > hi => {
+                return Err("Range out of order in character class".into())
+            }
+            (Some(_), Some(_)) => {}
+            _ => return Err("Invalid character class".into()),
+        }
+    }
+}
+
+/// Checks a quantifier like `*` or `{2,5}` and returns whether there is one
+fn check_quantifier(chars: &mut Peekable<Chars>) -> Result<bool, String> {
+    match chars.peek() {
+        Some('*' | '+' | '?') => {
+            chars.next();
+            Ok(true)
+        }
+        Some('{') => {
+            chars.next();
+            let min = check_number(chars)?.ok_or("Lone quantifier brackets")?;
+            let max = match chars.next_if_eq(&',') {
+                Some(_) => check_number(chars)?,
+                None => Some(min),
+            };
+            if chars.next() != Some('}') {
+                return Err("Incomplete quantifier".into());
+            }
+            if max.is_some_and(|max| max < min) {
+                return Err("numbers out of order in {} quantifier".into());
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn check_number(chars: &mut Peekable<Chars>) -> Result<Option<u32>, String> {
+    let mut number = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        let n = number.unwrap_or(0) * 10 + digit;
+        if n > 100_000 {
+            return Err("Quantifier is too large".into());
+        }
+        number = Some(n);
+    }
+    Ok(number)
+}
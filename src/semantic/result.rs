@@ -0,0 +1,216 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::TypeEnv;
+use crate::ast::types::Type;
+use crate::ast::*;
+
+/// Moves the operands of the `?` operators of a statement into temporaries and returns
+/// the statements that have to run before it. If a temporary holds an error, the function
+/// returns it. Otherwise the operator is replaced by the value of the temporary.
+/// Operands are evaluated before the rest of the statement.
+pub(super) fn hoist_try(
+    env: &mut TypeEnv,
+    statement: &mut Statement,
+) -> Result<Vec<Statement>, String> {
+    let mut hoisted = Vec::new();
+    match statement {
+        Statement::Declare(_, Some(expr))
+        | Statement::Destructure(_, expr)
+        | Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        // The arguments of a spawned call are evaluated when it is spawned
+        | Statement::Spawn(expr)
+        | Statement::If(expr, ..)
+        | Statement::For(_, expr, _)
+        | Statement::ForPair(_, _, expr, _)
+        | Statement::Match(expr, _) => hoist_expression(env, expr, &mut hoisted),
+        Statement::Assign(lhs, rhs) => {
+            hoist_expression(env, lhs, &mut hoisted);
+            hoist_expression(env, rhs, &mut hoisted);
+        }
+        // Deferred expressions are evaluated when the block is left
+        Statement::Defer(expr) => {
+            hoist_expression(env, &mut expr.clone(), &mut hoisted);
+            if !hoisted.is_empty() {
+                return Err("The '?' operator can not be used in a deferred expression".into());
+            }
+        }
+        // The condition is evaluated on every iteration, so it can not be hoisted
+        Statement::While(cond, _) => {
+            hoist_expression(env, &mut cond.clone(), &mut hoisted);
+            if !hoisted.is_empty() {
+                return Err("The '?' operator can not be used in the condition of a loop".into());
+            }
+        }
+        Statement::Declare(_, None)
+        | Statement::Static(..)
+        | Statement::Block(..)
+        | Statement::Unsafe(_)
+        | Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+
+    if !hoisted.is_empty() && !matches!(env.return_type(), Some(Type::Result(_))) {
+        return Err("The '?' operator can only be used in functions that return a result".into());
+    }
+    Ok(hoisted)
+}
+
+fn hoist_expression(env: &mut TypeEnv, expr: &mut Expression, hoisted: &mut Vec<Statement>) {
+    match expr {
+        Expression::Try(operand) => {
+            hoist_expression(env, operand, hoisted);
+            let name = env.temporary("result");
+            let var = Expression::Variable(name.clone());
+            hoisted.push(Statement::Declare(
+                Variable {
+                    name,
+                    ty: None,
+                    pos: None,
+                    reference: false,
+                },
+                Some(*operand.clone()),
+            ));
+            hoisted.push(Statement::If(
+                Expression::FunctionCall("_result_is_error".into(), vec![var.clone()]),
+                Box::new(Statement::Block(
+                    vec![Statement::Return(Some(var.clone()))],
+                    Vec::new(),
+                )),
+                None,
+            ));
+            *expr = Expression::FunctionCall("_result_value".into(), vec![var]);
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => args
+            .iter_mut()
+            .for_each(|arg| hoist_expression(env, arg, hoisted)),
+        Expression::ArrayAccess(_, index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index)
+        | Expression::Load(_, index) => hoist_expression(env, index, hoisted),
+        // The right operand may not be evaluated, so its operators are hoisted into a branch
+        Expression::BinOp(lhs, op @ (BinOp::And | BinOp::Or), rhs) if contains_try(rhs) => {
+            hoist_expression(env, lhs, hoisted);
+            let name = env.temporary("condition");
+            hoisted.extend(desugar_short_circuit(&name, *lhs.clone(), op, *rhs.clone()));
+            *expr = Expression::Variable(name);
+        }
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            hoist_expression(env, lhs, hoisted);
+            hoist_expression(env, rhs, hoisted);
+        }
+        Expression::StructInitialization(_, fields) => fields
+            .values_mut()
+            .for_each(|field| hoist_expression(env, field, hoisted)),
+        Expression::StructUpdate(_, base, fields) => {
+            hoist_expression(env, base, hoisted);
+            fields
+                .values_mut()
+                .for_each(|field| hoist_expression(env, field, hoisted))
+        }
+        Expression::FieldAccess(obj, field) => {
+            hoist_expression(env, obj, hoisted);
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                args.iter_mut()
+                    .for_each(|arg| hoist_expression(env, arg, hoisted));
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                hoist_expression(env, key, hoisted);
+                hoist_expression(env, value, hoisted);
+            }
+        }
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Variable(_)
+        | Expression::Selff
+        | Expression::SizeOf(_) => {}
+    }
+}
+
+/// Lowers `lhs && rhs` or `lhs || rhs` to a temporary that holds the value of `lhs`, and a
+/// branch that only evaluates `rhs` if `lhs` does not decide the result. After the returned
+/// statements, the temporary holds the result of the operation.
+fn desugar_short_circuit(
+    name: &str,
+    lhs: Expression,
+    op: &BinOp,
+    rhs: Expression,
+) -> Vec<Statement> {
+    let var = Expression::Variable(name.to_owned());
+    let condition = match op {
+        BinOp::And => var.clone(),
+        _ => Expression::BinOp(
+            Box::new(var.clone()),
+            BinOp::Equal,
+            Box::new(Expression::Bool(false)),
+        ),
+    };
+    vec![
+        Statement::Declare(
+            Variable {
+                name: name.to_owned(),
+                ty: Some(Type::Bool),
+                pos: None,
+                reference: false,
+            },
+            Some(lhs),
+        ),
+        Statement::If(
+            condition,
+            Box::new(Statement::Block(
+                vec![Statement::Assign(Box::new(var), Box::new(rhs))],
+                Vec::new(),
+            )),
+            None,
+        ),
+    ]
+}
+
+fn contains_try(expr: &Expression) -> bool {
+    match expr {
+        Expression::Try(_) => true,
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+            args.iter().any(contains_try)
+        }
+        Expression::ArrayAccess(_, index)
+        | Expression::Await(index)
+        | Expression::Reference(index)
+        | Expression::Dereference(index)
+        | Expression::Load(_, index) => contains_try(index),
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => contains_try(lhs) || contains_try(rhs),
+        Expression::StructInitialization(_, fields) => fields.values().any(|f| contains_try(f)),
+        Expression::StructUpdate(_, base, fields) => {
+            contains_try(base) || fields.values().any(|f| contains_try(f))
+        }
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| contains_try(key) || contains_try(value)),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Variable(_)
+        | Expression::Selff
+        | Expression::SizeOf(_) => false,
+    }
+}
@@ -0,0 +1,123 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::TypeEnv;
+use crate::ast::*;
+use crate::mir;
+
+/// How a statement can complete normally, i.e. continue with the next statement
+enum Exit {
+    /// The statement has no branches that could be described, e.g. a call or the end of a block
+    End,
+    /// A specific branch falls through
+    Branch(String),
+}
+
+impl Exit {
+    /// Uses the given branch as the description, unless a more specific one is known
+    fn within(self, branch: impl Into<String>) -> Exit {
+        match self {
+            Exit::End => Exit::Branch(branch.into()),
+            specific => specific,
+        }
+    }
+}
+
+/// Checks that every path through a function with a declared return type ends in a `return`.
+/// Inferred return types are not checked, since the function may only return on some paths.
+/// Paths that the control flow graph of a function shows to be unreachable are ignored.
+pub(super) fn check(module: &Module) -> Result<(), String> {
+    let mut types = TypeEnv::new(module);
+    let methods = module.structs.iter().flat_map(|def| &def.methods);
+    for func in module.func.iter().chain(methods) {
+        if func.ret_type.is_none() {
+            continue;
+        }
+        if let Some(exit) = fall_through(&func.body) {
+            // The control flow graph of the function also knows which branches are never taken,
+            // since their condition is constant. Lowering fails if the end can be reached.
+            if mir::lower_function(func, &mut types).is_ok() {
+                continue;
+            }
+            let reason = match exit {
+                Exit::End => "at the end of its body".to_owned(),
+                Exit::Branch(branch) => branch,
+            };
+            return Err(format!(
+                "Function '{}' does not return a value {}",
+                func.name, reason
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if execution never continues after the statement, because
+/// every path through it returns, breaks, continues or loops forever
+pub fn diverges(statement: &Statement) -> bool {
+    fall_through(statement).is_none()
+}
+
+/// Returns how the statement can complete normally, or `None` if it can not
+fn fall_through(statement: &Statement) -> Option<Exit> {
+    match statement {
+        Statement::Return(_) | Statement::Break | Statement::Continue => None,
+        Statement::Unsafe(body) => fall_through(body),
+        Statement::Block(statements, _) => {
+            if statements.iter().any(diverges) {
+                return None;
+            }
+            match statements.last() {
+                Some(last) => fall_through(last),
+                None => Some(Exit::End),
+            }
+        }
+        Statement::If(_, _, None) => Some(Exit::Branch(
+            "if the condition of an `if` statement is false, since it has no `else` branch"
+                .to_owned(),
+        )),
+        Statement::If(_, if_branch, Some(else_branch)) => {
+            if let Some(exit) = fall_through(if_branch) {
+                return Some(exit.within("in the `if` branch of an `if` statement"));
+            }
+            fall_through(else_branch)
+                .map(|exit| exit.within("in the `else` branch of an `if` statement"))
+        }
+        Statement::Match(_, arms) => {
+            for (i, arm) in arms.iter().enumerate() {
+                let body = match arm {
+                    MatchArm::Case(_, body) | MatchArm::Else(body) => body,
+                };
+                if let Some(exit) = fall_through(body) {
+                    return Some(exit.within(format!("in arm {} of a `match` statement", i + 1)));
+                }
+            }
+            if MatchArm::is_exhaustive(arms) {
+                None
+            } else {
+                Some(Exit::Branch(
+                    "if no arm of a `match` statement matches, since it has no `else` arm"
+                        .to_owned(),
+                ))
+            }
+        }
+        Statement::While(Expression::Bool(true), body) if !body.breaks() => None,
+        Statement::While(_, _) => Some(Exit::Branch("after a `while` loop ends".to_owned())),
+        Statement::For(_, _, _) | Statement::ForPair(_, _, _, _) => {
+            Some(Exit::Branch("after a `for` loop ends".to_owned()))
+        }
+        _ => Some(Exit::End),
+    }
+}
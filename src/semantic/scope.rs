@@ -0,0 +1,348 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::statics::static_symbol;
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Renames all declarations of a function that shadow another declaration.
+///
+/// A variable can be redeclared, both in the same and in a nested scope.
+/// The new declaration shadows the previous one until the end of the scope.
+/// The initializer of a declaration still refers to the previous declaration,
+/// so `let x = x + 1` is valid.
+///
+/// Global variables form the outermost scope, so arguments and variables
+/// with the name of a global are renamed as well.
+///
+/// References to `static` variables are renamed to the global that holds their value.
+/// The name of the global is derived from the `owner` of the variable, which is the
+/// function or method that declares it.
+pub(super) fn resolve_shadowing(func: &mut Function, owner: &str, globals: &[String]) {
+    let mut taken: HashSet<String> = globals.iter().cloned().collect();
+    collect_names(&func.body, &mut taken);
+
+    let mut resolver = Resolver {
+        scopes: vec![globals.iter().map(|g| (g.clone(), g.clone())).collect()],
+        taken,
+        owner: owner.to_owned(),
+    };
+    resolver.scopes.push(HashMap::new());
+    for arg in &mut func.arguments {
+        arg.name = resolver.declare(&arg.name);
+    }
+    resolver.resolve_statement(&mut func.body);
+}
+
+struct Resolver {
+    /// Block-scoped variable -> unique name mappings
+    scopes: Vec<HashMap<String, String>>,
+    /// Names that occur in the function, new names must not collide with them
+    taken: HashSet<String>,
+    /// Function or method that is resolved
+    owner: String,
+}
+
+impl Resolver {
+    /// Binds a variable in the current scope and returns its unique name
+    fn declare(&mut self, name: &str) -> String {
+        let unique = if self.lookup(name).is_some() {
+            let mut counter = 1;
+            while self.taken.contains(&format!("{}_{}", name, counter)) {
+                counter += 1;
+            }
+            format!("{}_{}", name, counter)
+        } else {
+            name.to_owned()
+        };
+        self.taken.insert(unique.clone());
+
+        self.scopes
+            .last_mut()
+            .expect("expected last scope to be present")
+            .insert(name.to_owned(), unique.clone());
+        unique
+    }
+
+    fn lookup(&self, name: &str) -> Option<&String> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn rename(&self, name: &mut String) {
+        if let Some(unique) = self.lookup(name) {
+            *name = unique.clone();
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Block(statements, scope) => {
+                self.scopes.push(HashMap::new());
+                statements
+                    .iter_mut()
+                    .for_each(|s| self.resolve_statement(s));
+                self.scopes.pop();
+
+                // The scope has to reflect the new names
+                *scope = statements
+                    .iter()
+                    .filter_map(|s| match s {
+                        Statement::Declare(var, _) => Some(var.clone()),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            Statement::Unsafe(body) => self.resolve_statement(body),
+            Statement::Declare(var, expr) => {
+                // The initializer still refers to the previous declaration
+                if let Some(expr) = expr {
+                    self.resolve_expression(expr);
+                }
+                var.name = self.declare(&var.name);
+            }
+            Statement::Destructure(fields, expr) => {
+                self.resolve_expression(expr);
+                for (_, var) in fields {
+                    var.name = self.declare(&var.name);
+                }
+            }
+            Statement::Static(var, expr) => {
+                self.resolve_expression(expr);
+                let name = var.name.clone();
+                var.name = self.declare(&name);
+                self.scopes
+                    .last_mut()
+                    .expect("expected last scope to be present")
+                    .insert(name, static_symbol(&self.owner, &var.name));
+            }
+            Statement::Assign(lhs, rhs) => {
+                self.resolve_expression(lhs);
+                self.resolve_expression(rhs);
+            }
+            Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::Defer(expr)
+            | Statement::Spawn(expr) => self.resolve_expression(expr),
+            Statement::If(cond, if_branch, else_branch) => {
+                self.resolve_expression(cond);
+                self.resolve_statement(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Statement::While(cond, body) => {
+                self.resolve_expression(cond);
+                self.resolve_statement(body);
+            }
+            Statement::For(var, expr, body) => {
+                self.resolve_expression(expr);
+                self.scopes.push(HashMap::new());
+                var.name = self.declare(&var.name);
+                self.resolve_statement(body);
+                self.scopes.pop();
+            }
+            Statement::ForPair(key, value, expr, body) => {
+                self.resolve_expression(expr);
+                self.scopes.push(HashMap::new());
+                key.name = self.declare(&key.name);
+                value.name = self.declare(&value.name);
+                self.resolve_statement(body);
+                self.scopes.pop();
+            }
+            Statement::Match(subject, arms) => {
+                self.resolve_expression(subject);
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(expr, s) => {
+                            self.resolve_expression(expr);
+                            self.resolve_statement(s);
+                        }
+                        MatchArm::Else(s) => self.resolve_statement(s),
+                    }
+                }
+            }
+            Statement::Return(None)
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Inline(..) => {}
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Variable(name) => self.rename(name),
+            Expression::ArrayAccess(name, index) => {
+                self.rename(name);
+                self.resolve_expression(index);
+            }
+            Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+                args.iter_mut().for_each(|e| self.resolve_expression(e))
+            }
+            Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+                self.resolve_expression(lhs);
+                self.resolve_expression(rhs);
+            }
+            Expression::StructInitialization(_, fields) => {
+                fields.values_mut().for_each(|e| self.resolve_expression(e))
+            }
+            Expression::StructUpdate(_, base, fields) => {
+                self.resolve_expression(base);
+                fields.values_mut().for_each(|e| self.resolve_expression(e))
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expression::Try(inner)
+            | Expression::Await(inner)
+            | Expression::Reference(inner)
+            | Expression::Dereference(inner)
+            | Expression::Load(_, inner) => self.resolve_expression(inner),
+            // Field names are not variables, only the accessed object is
+            Expression::FieldAccess(obj, field) => {
+                self.resolve_expression(obj);
+                if let Expression::FunctionCall(_, args) = &mut **field {
+                    args.iter_mut().for_each(|e| self.resolve_expression(e));
+                }
+            }
+            Expression::Int(_)
+            | Expression::Str(_)
+            | Expression::Bool(_)
+            | Expression::Selff
+            | Expression::SizeOf(_) => {}
+        }
+    }
+}
+
+/// Collects all variable and function names that occur in a statement
+pub(super) fn collect_names(statement: &Statement, names: &mut HashSet<String>) {
+    match statement {
+        Statement::Block(statements, _) => statements.iter().for_each(|s| collect_names(s, names)),
+        Statement::Unsafe(body) => collect_names(body, names),
+        Statement::Declare(var, expr) => {
+            names.insert(var.name.clone());
+            if let Some(expr) = expr {
+                collect_expression_names(expr, names);
+            }
+        }
+        Statement::Destructure(fields, expr) => {
+            names.extend(fields.iter().map(|(_, var)| var.name.clone()));
+            collect_expression_names(expr, names);
+        }
+        Statement::Static(var, expr) => {
+            names.insert(var.name.clone());
+            collect_expression_names(expr, names);
+        }
+        Statement::Assign(lhs, rhs) => {
+            collect_expression_names(lhs, names);
+            collect_expression_names(rhs, names);
+        }
+        Statement::Return(Some(expr))
+        | Statement::Exp(expr)
+        | Statement::Defer(expr)
+        | Statement::Spawn(expr) => collect_expression_names(expr, names),
+        Statement::If(cond, if_branch, else_branch) => {
+            collect_expression_names(cond, names);
+            collect_names(if_branch, names);
+            if let Some(else_branch) = else_branch {
+                collect_names(else_branch, names);
+            }
+        }
+        Statement::While(cond, body) => {
+            collect_expression_names(cond, names);
+            collect_names(body, names);
+        }
+        Statement::For(var, expr, body) => {
+            names.insert(var.name.clone());
+            collect_expression_names(expr, names);
+            collect_names(body, names);
+        }
+        Statement::ForPair(key, value, expr, body) => {
+            names.insert(key.name.clone());
+            names.insert(value.name.clone());
+            collect_expression_names(expr, names);
+            collect_names(body, names);
+        }
+        Statement::Match(subject, arms) => {
+            collect_expression_names(subject, names);
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(expr, s) => {
+                        collect_expression_names(expr, names);
+                        collect_names(s, names);
+                    }
+                    MatchArm::Else(s) => collect_names(s, names),
+                }
+            }
+        }
+        Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+}
+
+fn collect_expression_names(expr: &Expression, names: &mut HashSet<String>) {
+    match expr {
+        Expression::Variable(name) => {
+            names.insert(name.clone());
+        }
+        Expression::ArrayAccess(name, index) => {
+            names.insert(name.clone());
+            collect_expression_names(index, names);
+        }
+        // Generated names may not hide functions either
+        Expression::FunctionCall(name, args) => {
+            names.insert(name.clone());
+            args.iter().for_each(|e| collect_expression_names(e, names));
+        }
+        Expression::Array(_, elements) => elements
+            .iter()
+            .for_each(|e| collect_expression_names(e, names)),
+        Expression::BinOp(lhs, _, rhs)
+        | Expression::FieldAccess(lhs, rhs)
+        | Expression::Store(_, lhs, rhs) => {
+            collect_expression_names(lhs, names);
+            collect_expression_names(rhs, names);
+        }
+        Expression::StructInitialization(_, fields) => fields
+            .values()
+            .for_each(|e| collect_expression_names(e, names)),
+        Expression::StructUpdate(_, base, fields) => {
+            collect_expression_names(base, names);
+            fields
+                .values()
+                .for_each(|e| collect_expression_names(e, names))
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                collect_expression_names(key, names);
+                collect_expression_names(value, names);
+            }
+        }
+        Expression::Try(inner)
+        | Expression::Await(inner)
+        | Expression::Reference(inner)
+        | Expression::Dereference(inner)
+        | Expression::Load(_, inner) => collect_expression_names(inner, names),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::SizeOf(_) => {}
+    }
+}
@@ -0,0 +1,136 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::types::collect_variables;
+use crate::ast::*;
+use std::collections::HashSet;
+
+/// Returns the name of the global that holds a `static` variable of a function
+pub(super) fn static_symbol(owner: &str, name: &str) -> String {
+    format!("_static_{}_{}", owner, name)
+}
+
+/// Returns the owner of the static variables of a method
+pub(super) fn method_owner(def: &str, method: &str) -> String {
+    format!("{}_{}", def, method)
+}
+
+/// Moves the `static` variables of all functions and methods to the globals of the module.
+/// References to them have already been renamed by the scope resolution.
+///
+/// Statics are initialized together with the globals before `main` is called,
+/// so their initializers can not refer to local variables.
+pub(super) fn hoist_statics(module: &mut Module) -> Result<(), String> {
+    let globals: HashSet<String> = module.globals.iter().map(|g| g.var.name.clone()).collect();
+    let mut statics = Vec::new();
+    for func in &mut module.func {
+        let owner = func.name.clone();
+        hoist_function_statics(func, &owner, &globals, &mut statics)?;
+    }
+    for def in &mut module.structs {
+        for method in &mut def.methods {
+            let owner = method_owner(&def.name, &method.name);
+            hoist_function_statics(method, &owner, &globals, &mut statics)?;
+        }
+    }
+    module.globals.append(&mut statics);
+    Ok(())
+}
+
+fn hoist_function_statics(
+    func: &mut Function,
+    owner: &str,
+    globals: &HashSet<String>,
+    statics: &mut Vec<Global>,
+) -> Result<(), String> {
+    let mut found = Vec::new();
+    remove_statics(&mut func.body, &mut found);
+    let symbols: HashSet<String> = found
+        .iter()
+        .map(|(var, _)| static_symbol(owner, &var.name))
+        .collect();
+    for (var, value) in found {
+        let mut used = Vec::new();
+        collect_variables(&value, &mut used);
+        if let Some(local) = used
+            .iter()
+            .find(|name| !globals.contains(*name) && !symbols.contains(*name))
+        {
+            return Err(format!(
+                "Static variable '{}' can not be initialized with local variable '{}' in function '{}'",
+                var.name, local, func.name
+            ));
+        }
+        statics.push(Global {
+            var: Variable {
+                name: static_symbol(owner, &var.name),
+                ..var
+            },
+            value,
+            constant: false,
+            public: false,
+        });
+    }
+    Ok(())
+}
+
+/// Removes the static variables of a statement in the order of their declaration
+fn remove_statics(statement: &mut Statement, found: &mut Vec<(Variable, Expression)>) {
+    match statement {
+        Statement::Block(statements, _) => {
+            for mut statement in std::mem::take(statements) {
+                match statement {
+                    Statement::Static(var, value) => found.push((var, value)),
+                    _ => {
+                        remove_statics(&mut statement, found);
+                        statements.push(statement);
+                    }
+                }
+            }
+        }
+        // Statics outside of a block, e.g. in the arm of a match statement
+        Statement::Static(var, value) => {
+            found.push((var.clone(), value.clone()));
+            *statement = Statement::Block(Vec::new(), Vec::new());
+        }
+        Statement::If(_, if_branch, else_branch) => {
+            remove_statics(if_branch, found);
+            if let Some(else_branch) = else_branch {
+                remove_statics(else_branch, found);
+            }
+        }
+        Statement::While(_, body)
+        | Statement::Unsafe(body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(_, _, _, body) => remove_statics(body, found),
+        Statement::Match(_, arms) => {
+            for arm in arms {
+                match arm {
+                    MatchArm::Case(_, body) | MatchArm::Else(body) => remove_statics(body, found),
+                }
+            }
+        }
+        Statement::Declare(..)
+        | Statement::Destructure(..)
+        | Statement::Assign(..)
+        | Statement::Return(_)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..)
+        | Statement::Exp(_)
+        | Statement::Defer(_)
+        | Statement::Spawn(_) => {}
+    }
+}
@@ -0,0 +1,1682 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::ast::*;
+use crate::lexer::tokenize;
+use crate::parser::parse;
+use crate::semantic::{analyze, check, lower_overflow_checks};
+
+fn analyze_raw(raw: &str) -> Module {
+    let tokens = tokenize(raw).unwrap();
+    let mut module = parse(tokens, Some(raw.to_string()), "".into()).unwrap();
+    analyze(&mut module);
+    module
+}
+
+fn body_of<'a>(module: &'a Module, name: &str) -> &'a Vec<Statement> {
+    let func = module.func.iter().find(|f| f.name == name).unwrap();
+    match &func.body {
+        Statement::Block(statements, _) => statements,
+        _ => panic!("Function body should be a block"),
+    }
+}
+
+fn declared_name(statement: &Statement) -> &str {
+    match statement {
+        Statement::Declare(var, _) => &var.name,
+        _ => panic!("Statement should be a declaration"),
+    }
+}
+
+#[test]
+fn test_unique_names_are_kept() {
+    let raw = "
+    fn main() {
+        let x = 1
+        let y = x
+    }
+    ";
+    let module = analyze_raw(raw);
+    let body = body_of(&module, "main");
+    assert_eq!(declared_name(&body[0]), "x");
+    assert_eq!(declared_name(&body[1]), "y");
+}
+
+#[test]
+fn test_redeclaration_in_same_scope() {
+    let raw = "
+    fn main() {
+        let x = 1
+        let x = x + 1
+        println(x)
+    }
+    ";
+    let module = analyze_raw(raw);
+    let body = body_of(&module, "main");
+    assert_eq!(
+        body[1],
+        Statement::Declare(
+            Variable {
+                name: "x_1".into(),
+                ty: Some(Type::Int),
+                pos: None,
+                reference: false,
+            },
+            Some(Expression::BinOp(
+                Box::new(Expression::Variable("x".into())),
+                BinOp::Addition,
+                Box::new(Expression::Int(1))
+            ))
+        )
+    );
+    assert_eq!(
+        body[2],
+        Statement::Exp(Expression::FunctionCall(
+            "println".into(),
+            vec![Expression::Variable("x_1".into())]
+        ))
+    );
+}
+
+#[test]
+fn test_shadowing_in_nested_scope() {
+    let raw = "
+    fn main() {
+        let x = 1
+        if true {
+            let x = 2
+            println(x)
+        }
+        println(x)
+    }
+    ";
+    let module = analyze_raw(raw);
+    let body = body_of(&module, "main");
+    match &body[1] {
+        Statement::If(_, if_branch, _) => match &**if_branch {
+            Statement::Block(statements, scope) => {
+                assert_eq!(declared_name(&statements[0]), "x_1");
+                assert_eq!(scope[0].name, "x_1");
+            }
+            _ => panic!("If branch should be a block"),
+        },
+        _ => panic!("Statement should be an if statement"),
+    }
+    assert_eq!(
+        body[2],
+        Statement::Exp(Expression::FunctionCall(
+            "println".into(),
+            vec![Expression::Variable("x".into())]
+        ))
+    );
+}
+
+#[test]
+fn test_shadowing_of_arguments() {
+    let raw = "
+    fn foo(x: int) {
+        let x = x * 2
+    }
+    ";
+    let module = analyze_raw(raw);
+    assert_eq!(declared_name(&body_of(&module, "foo")[0]), "x_1");
+}
+
+#[test]
+fn test_renaming_avoids_collisions() {
+    let raw = "
+    fn main() {
+        let x = 1
+        let x_1 = 2
+        let x = 3
+    }
+    ";
+    let module = analyze_raw(raw);
+    let body = body_of(&module, "main");
+    assert_eq!(declared_name(&body[1]), "x_1");
+    assert_eq!(declared_name(&body[2]), "x_2");
+}
+
+#[test]
+fn test_shadowing_of_globals() {
+    let raw = "
+    let count = 0
+
+    fn foo(count: int) {
+        let x = count
+    }
+
+    fn main() {
+        count += 1
+        let count = 2
+    }
+    ";
+    let module = analyze_raw(raw);
+    let foo = module.func.iter().find(|f| f.name == "foo").unwrap();
+    assert_eq!(foo.arguments[0].name, "count_1");
+    let body = body_of(&module, "main");
+    assert!(matches!(
+        &body[0],
+        Statement::Exp(Expression::BinOp(lhs, _, _)) if **lhs == Expression::Variable("count".into())
+    ));
+    assert_eq!(declared_name(&body[1]), "count_1");
+}
+
+fn check_raw(raw: &str) -> Result<(), String> {
+    let mut module = analyze_raw(raw);
+    check(&mut module)
+}
+
+#[test]
+fn test_string_operators() {
+    let raw = "
+    fn greet(name: string): string {
+        return \"Hello \" + name
+    }
+
+    fn main() {
+        let s = greet(\"World\") + \"!\"
+        s += \"?\"
+        if s == \"Hello World!?\" && s != \"\" {
+            println(s)
+        }
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+}
+
+#[test]
+fn test_invalid_string_operators() {
+    let cases = [
+        (
+            "\"a\" - \"b\"",
+            "Operator '-' can not be applied to strings",
+        ),
+        ("\"a\" + 1", "Cannot add int to string"),
+        ("1 + \"a\"", "Cannot add string to int"),
+        ("\"a\" == true", "Cannot compare string with bool"),
+        (
+            "\"a\" < \"b\"",
+            "Strings can only be compared with '==' and '!='",
+        ),
+    ];
+    for (expr, error) in &cases {
+        let raw = format!(
+            "fn main() {{\n    let s: string = \"x\"\n    println({})\n}}",
+            expr
+        );
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn test_variable_types_are_checked() {
+    let raw = "
+    fn main() {
+        let s = \"a\"
+        let n = 1
+        let t = s + n
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Cannot add int to string in function 'main'".into())
+    );
+}
+
+#[test]
+fn test_unknown_types_are_not_checked() {
+    let raw = "
+    fn main(x: any) {
+        let s = x + \"a\"
+        let b = x == 1
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+}
+
+#[test]
+fn test_string_methods_are_lowered() {
+    let raw = "
+    fn main() {
+        let s = \"a,b\"
+        let parts = s.split(\",\")
+        let n = s.trim().len()
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let body = body_of(&module, "main");
+    assert_eq!(
+        body[1],
+        Statement::Declare(
+            Variable {
+                name: "parts".into(),
+                ty: Some(Type::Array(Box::new(Type::Str), None)),
+                pos: None,
+                reference: false,
+            },
+            Some(Expression::FunctionCall(
+                "_str_split".into(),
+                vec![
+                    Expression::Variable("s".into()),
+                    Expression::Str(",".into())
+                ]
+            ))
+        )
+    );
+    assert_eq!(
+        body[2],
+        Statement::Declare(
+            Variable {
+                name: "n".into(),
+                ty: Some(Type::Int),
+                pos: None,
+                reference: false,
+            },
+            Some(Expression::FunctionCall(
+                "_str_len".into(),
+                vec![Expression::FunctionCall(
+                    "_str_trim".into(),
+                    vec![Expression::Variable("s".into())]
+                )]
+            ))
+        )
+    );
+}
+
+#[test]
+fn test_invalid_string_methods() {
+    let cases = [
+        ("s.reverse()", "No method 'reverse' on string"),
+        ("s.length", "No field 'length' on string"),
+        (
+            "s.contains()",
+            "Method 'string.contains' expects 1 argument, found 0",
+        ),
+        (
+            "s.substring(1)",
+            "Method 'string.substring' expects 2 arguments, found 1",
+        ),
+        (
+            "s.split(1)",
+            "Argument 1 of method 'string.split' must be string, found int",
+        ),
+        ("s.len() + s", "Cannot add string to int"),
+    ];
+    for (expr, error) in &cases {
+        let raw = format!(
+            "fn main() {{\n    let s: string = \"x\"\n    println({})\n}}",
+            expr
+        );
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn test_vector_methods() {
+    let raw = "
+    fn main() {
+        let v = [1, 2]
+        v.push(3)
+        let last = v.pop()
+        let rest = v.slice(0, 1).concat([4])
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let body = body_of(&module, "main");
+    assert_eq!(
+        body[1],
+        Statement::Exp(Expression::FunctionCall(
+            "_vec_push".into(),
+            vec![Expression::Variable("v".into()), Expression::Int(3)]
+        ))
+    );
+    match (&body[2], &body[3]) {
+        (Statement::Declare(last, _), Statement::Declare(rest, _)) => {
+            assert_eq!(last.ty, Some(Type::Int));
+            assert_eq!(rest.ty, Some(Type::Array(Box::new(Type::Int), None)));
+        }
+        other => panic!("Expected declarations, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_vector_methods() {
+    let cases = [
+        (
+            "v.push(\"a\")",
+            "Argument 1 of method 'int[].push' must be int, found string",
+        ),
+        (
+            "v.concat([\"a\"])",
+            "Argument 1 of method 'int[].concat' must be int[], found string[]",
+        ),
+        (
+            "v.pop(1)",
+            "Method 'int[].pop' expects 0 arguments, found 1",
+        ),
+        ("v.sort()", "No method 'sort' on int[]"),
+    ];
+    for (expr, error) in &cases {
+        let raw = format!("fn main() {{\n    let v = [1, 2]\n    {}\n}}", expr);
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            expr
+        );
+    }
+}
+
+#[test]
+fn test_map_methods() {
+    let raw = "
+    fn main() {
+        let ages = { \"alice\": 31 }
+        ages.insert(\"bob\", 27)
+        let age = ages.get(\"bob\")
+        let names = ages.keys()
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let body = body_of(&module, "main");
+    assert_eq!(
+        body[1],
+        Statement::Exp(Expression::FunctionCall(
+            "_map_insert".into(),
+            vec![
+                Expression::Variable("ages".into()),
+                Expression::Str("bob".into()),
+                Expression::Int(27)
+            ]
+        ))
+    );
+    match (&body[0], &body[2], &body[3]) {
+        (Statement::Declare(ages, _), Statement::Declare(age, _), Statement::Declare(names, _)) => {
+            assert_eq!(
+                ages.ty,
+                Some(Type::Map(Box::new(Type::Str), Box::new(Type::Int)))
+            );
+            assert_eq!(age.ty, Some(Type::Int));
+            assert_eq!(names.ty, Some(Type::Array(Box::new(Type::Str), None)));
+        }
+        other => panic!("Expected declarations, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_maps() {
+    let cases = [
+        (
+            "let m = { \"a\": 1, 2: 3 }",
+            "Keys of a map must have the same type, found string and int",
+        ),
+        (
+            "let m = { \"a\": 1, \"b\": \"c\" }",
+            "Values of a map must have the same type, found int and string",
+        ),
+        (
+            "let m = { [1]: 1 }",
+            "Keys of maps must be of type int, string or bool, found int[]",
+        ),
+        (
+            "let m: map<int[], int>",
+            "Keys of maps must be of type int, string or bool, found int[]",
+        ),
+        (
+            "let m = { 1: 2 }\n    m.insert(\"a\", 2)",
+            "Argument 1 of method 'map<int, int>.insert' must be int, found string",
+        ),
+    ];
+    for (statement, error) in &cases {
+        let raw = format!("fn main() {{\n    {}\n}}", statement);
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            statement
+        );
+    }
+}
+
+#[test]
+fn test_main_return_type() {
+    assert!(check_raw("fn main(): int {\n    return 1\n}").is_ok());
+    assert_eq!(
+        check_raw("fn main(): string {\n    return \"1\"\n}"),
+        Err("Function 'main' must return int or nothing, found string".into())
+    );
+}
+
+#[test]
+fn test_format_is_lowered() {
+    let raw = "
+    fn main() {
+        let s = format(\"%s is %d%%\", \"x\", 5)
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let body = body_of(&module, "main");
+    let concat = |lhs, rhs| Expression::BinOp(Box::new(lhs), BinOp::Addition, Box::new(rhs));
+    match &body[0] {
+        Statement::Declare(var, Some(value)) => {
+            assert_eq!(var.ty, Some(Type::Str));
+            assert_eq!(
+                *value,
+                concat(
+                    concat(
+                        concat(Expression::Str("x".into()), Expression::Str(" is ".into())),
+                        Expression::FunctionCall("_int_to_str".into(), vec![Expression::Int(5)])
+                    ),
+                    Expression::Str("%".into())
+                )
+            );
+        }
+        other => panic!("Expected a declaration, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_format() {
+    let cases = [
+        (
+            "format(\"%d\", \"a\")",
+            "Argument 2 of 'format' must be int, found string",
+        ),
+        (
+            "format(\"%s %s\", \"a\")",
+            "Missing argument for format specifier '%s'",
+        ),
+        (
+            "format(\"%s\", \"a\", 1)",
+            "Argument 3 of 'format' has no format specifier",
+        ),
+        ("format(\"%x\", 1)", "Unknown format specifier '%x'"),
+        (
+            "format(\"100%\")",
+            "Incomplete format specifier at the end of a format string",
+        ),
+        (
+            "let f = \"%d\"\n    format(f, 1)",
+            "The first argument of 'format' must be a string literal",
+        ),
+    ];
+    for (statement, error) in &cases {
+        let raw = format!("fn main() {{\n    {}\n}}", statement);
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            statement
+        );
+    }
+}
+
+#[test]
+fn test_try_operator_is_lowered() {
+    let raw = "
+    fn parse(s: string): int! {
+        if s == \"\" {
+            return error(\"empty\")
+        }
+        return 1
+    }
+
+    fn twice(s: string): int! {
+        return parse(s)? * 2
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let result = |name: &str, arg| Expression::FunctionCall(name.into(), vec![arg]);
+    match &body_of(&module, "parse")[..] {
+        [Statement::If(_, then, None), Statement::Return(Some(ok))] => {
+            assert_eq!(
+                **then,
+                Statement::Block(
+                    vec![Statement::Return(Some(result(
+                        "_result_err",
+                        Expression::Str("empty".into())
+                    )))],
+                    Vec::new()
+                )
+            );
+            assert_eq!(*ok, result("_result_ok", Expression::Int(1)));
+        }
+        other => panic!("Unexpected body {:?}", other),
+    }
+
+    let tmp = Expression::Variable("result_1".into());
+    match &body_of(&module, "twice")[..] {
+        [Statement::Declare(var, Some(value)), Statement::If(cond, then, None), Statement::Return(Some(ret))] =>
+        {
+            assert_eq!(var.name, "result_1");
+            assert_eq!(var.ty, Some(Type::Result(Box::new(Type::Int))));
+            assert!(matches!(value, Expression::FunctionCall(name, _) if name == "parse"));
+            assert_eq!(*cond, result("_result_is_error", tmp.clone()));
+            assert_eq!(
+                **then,
+                Statement::Block(vec![Statement::Return(Some(tmp.clone()))], Vec::new())
+            );
+            assert_eq!(
+                *ret,
+                result(
+                    "_result_ok",
+                    Expression::BinOp(
+                        Box::new(result("_result_value", tmp)),
+                        BinOp::Multiplication,
+                        Box::new(Expression::Int(2))
+                    )
+                )
+            );
+        }
+        other => panic!("Unexpected body {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_results() {
+    let cases = [
+        (
+            "fn main() {\n    let x = parse()?\n}",
+            "The '?' operator can only be used in functions that return a result in function 'main'",
+        ),
+        (
+            "fn f(): int! {\n    return 1?\n}",
+            "The '?' operator can only be applied to results, found int in function 'f'",
+        ),
+        (
+            "fn f(): int! {\n    while parse()? > 1 {\n    }\n    return 1\n}",
+            "The '?' operator can not be used in the condition of a loop in function 'f'",
+        ),
+        (
+            "fn f(): int! {\n    return error(1)\n}",
+            "The message of an error must be a string, found int in function 'f'",
+        ),
+        (
+            "fn f(): int! {\n    return error()\n}",
+            "'error' expects 1 argument in function 'f'",
+        ),
+        (
+            "fn f(): int! {\n    return \"a\"\n}",
+            "Expected return type int!, found string in function 'f'",
+        ),
+    ];
+    for (source, error) in &cases {
+        let raw = format!("fn parse(): int! {{\n    return 1\n}}\n{}", source);
+        assert_eq!(check_raw(&raw), Err(error.to_string()), "{}", source);
+    }
+}
+
+#[test]
+fn test_invalid_defer() {
+    let raw = "
+    fn parse(): int! {
+        return 1
+    }
+
+    fn f(): int! {
+        defer parse()?
+        return 1
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("The '?' operator can not be used in a deferred expression in function 'f'".into())
+    );
+
+    let raw = "
+    fn main() {
+        match 1 {
+            1 => defer println(1)
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("'defer' can only be used inside of a block in function 'main'".into())
+    );
+}
+
+#[test]
+fn test_struct_defaults_are_inserted() {
+    let raw = "
+    struct Config {
+        name: string
+        retries: int = 3
+    }
+
+    fn main() {
+        let config = new Config {
+            name: \"x\"
+        }
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    match &body_of(&module, "main")[0] {
+        Statement::Declare(_, Some(Expression::StructInitialization(_, fields))) => {
+            assert_eq!(*fields["retries"], Expression::Int(3));
+        }
+        other => panic!("Expected struct initialization, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_struct_defaults() {
+    let cases = [
+        (
+            "retries: int = 3",
+            "name: \"x\"",
+            "Missing field 'verbose' in initialization of struct 'Config' in function 'main'",
+        ),
+        (
+            "retries: int = \"3\"",
+            "name: \"x\"\n verbose: true",
+            "Default value of field 'retries' in struct 'Config' must be int, found string",
+        ),
+        (
+            "retries: int = limit",
+            "name: \"x\"\n verbose: true",
+            "Default value of field 'retries' in struct 'Config' can not refer to variables",
+        ),
+    ];
+    for (field, init, error) in &cases {
+        let raw = format!(
+            "struct Config {{\n name: string\n {}\n verbose: bool\n }}\n fn main() {{\n let c = new Config {{\n {}\n }}\n }}",
+            field, init
+        );
+        assert_eq!(check_raw(&raw), Err(error.to_string()), "{}", field);
+    }
+}
+
+#[test]
+fn test_invalid_struct_update() {
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn main() {
+        let p = new Point {
+            ..1
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Fields of struct 'Point' can not be copied from int in function 'main'".into())
+    );
+}
+
+#[test]
+fn test_patterns_are_desugared() {
+    let raw = "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    fn main(p: Point, names: string[]) {
+        let { x } = p
+        for (i, name) in names {
+        }
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    match &body_of(&module, "main")[..] {
+        [Statement::Declare(x, Some(Expression::FieldAccess(obj, field))), Statement::Declare(counter, Some(Expression::Int(0))), Statement::For(name, Expression::Variable(names), body)] =>
+        {
+            assert_eq!(x.name, "x");
+            assert_eq!(x.ty, Some(Type::Int));
+            assert_eq!(**obj, Expression::Variable("p".into()));
+            assert_eq!(**field, Expression::Variable("x".into()));
+            assert_eq!(counter.name, "index_1");
+            assert_eq!(name.name, "name");
+            assert_eq!(names, "names");
+            match &**body {
+                Statement::Block(statements, _) => assert!(matches!(
+                    &statements[0],
+                    Statement::Declare(i, Some(Expression::Variable(c))) if i.name == "i" && c == "index_1"
+                )),
+                other => panic!("Expected block, got {:?}", other),
+            }
+        }
+        other => panic!("Unexpected body {:?}", other),
+    }
+}
+
+#[test]
+fn test_invalid_patterns() {
+    let cases = [
+        ("let { z } = p", "Struct 'Point' has no field 'z'"),
+        (
+            "let { x } = 1",
+            "Only structs can be destructured, found int",
+        ),
+        (
+            "for (a, b) in \"ab\" {\n    }",
+            "Only maps, arrays and structs can be iterated in pairs, found string",
+        ),
+        (
+            "for (a, b) in p {\n    }",
+            "Struct 'Point' can not be iterated. Declare the functions 'Point_len(value: Point): int' and 'Point_get(value: Point, index: int)'",
+        ),
+    ];
+    for (statement, error) in &cases {
+        let raw = format!(
+            "struct Point {{\n    x: int\n}}\nfn main(p: Point) {{\n    {}\n}}",
+            statement
+        );
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!("{} in function 'main'", error)),
+            "{}",
+            statement
+        );
+    }
+}
+
+#[test]
+fn test_global_types_are_inferred() {
+    let raw = "
+    let names = [\"a\", \"b\"]
+    let first = names[0]
+
+    fn main() {}
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    assert_eq!(module.globals[1].var.ty, Some(Type::Str));
+}
+
+#[test]
+fn test_invalid_globals() {
+    let cases = [
+        (
+            "let a = b\nlet b = 1",
+            "Global 'b' is used before its declaration in the initializer of 'a'",
+        ),
+        (
+            "let a = a + 1",
+            "Global 'a' is used before its declaration in the initializer of 'a'",
+        ),
+        (
+            "let a = 1\nlet a = 2",
+            "Global 'a' is declared more than once",
+        ),
+        (
+            "let main = 1",
+            "Global 'main' has the same name as a function",
+        ),
+        ("let a: string = 1", "Global 'a' must be string, found int"),
+        (
+            "const A = 1\nfn f() {\n    A = 2\n}",
+            "Can not assign to constant 'A' in function 'f'",
+        ),
+        (
+            "const A = 1\nfn f() {\n    A += 2\n}",
+            "Can not assign to constant 'A' in function 'f'",
+        ),
+    ];
+    for (globals, error) in &cases {
+        let raw = format!("{}\nfn main() {{}}", globals);
+        assert_eq!(check_raw(&raw), Err(error.to_string()), "{}", globals);
+    }
+}
+
+#[test]
+fn test_statics_are_moved_to_globals() {
+    let raw = "
+    fn next_id(): int {
+        static id = 0
+        id += 1
+        if id > 10 {
+            static id = 5
+            return id
+        }
+        return id
+    }
+
+    fn main() {}
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let globals: Vec<_> = module.globals.iter().map(|g| g.var.name.as_str()).collect();
+    assert_eq!(globals, ["_static_next_id_id", "_static_next_id_id_1"]);
+    let body = body_of(&module, "next_id");
+    assert!(matches!(
+        &body[0],
+        Statement::Exp(Expression::BinOp(lhs, _, _)) if **lhs == Expression::Variable("_static_next_id_id".into())
+    ));
+    assert!(matches!(
+        body.last(),
+        Some(Statement::Return(Some(Expression::Variable(name)))) if name == "_static_next_id_id"
+    ));
+}
+
+#[test]
+fn test_static_initializer_can_not_use_locals() {
+    let raw = "
+    fn f(n: int) {
+        static x = n
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err(
+            "Static variable 'x' can not be initialized with local variable 'n' in function 'f'"
+                .into()
+        )
+    );
+}
+
+#[test]
+fn test_return_types_are_inferred() {
+    let raw = "
+    fn fib(n: int) {
+        if n < 2 {
+            return n
+        }
+        return fib(n - 1) + fib(n - 2)
+    }
+
+    fn name() {
+        let names = [\"a\", \"b\"]
+        return names[0]
+    }
+
+    fn label() {
+        return name()
+    }
+
+    fn log() {
+        println(1)
+    }
+
+    fn main() {}
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let ret_type = |name: &str| {
+        module
+            .func
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap()
+            .ret_type
+            .clone()
+    };
+    assert_eq!(ret_type("fib"), Some(Type::Int));
+    assert_eq!(ret_type("name"), Some(Type::Str));
+    assert_eq!(ret_type("label"), Some(Type::Str));
+    assert_eq!(ret_type("log"), None);
+}
+
+#[test]
+fn test_conflicting_return_types() {
+    let raw = "
+    fn f(b: bool) {
+        if b {
+            return 1
+        }
+        return \"one\"
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Function 'f' returns both int and string".into())
+    );
+}
+
+#[test]
+fn test_missing_returns_are_reported() {
+    let raw = "
+    fn sign(n: int): int {
+        if n < 0 {
+            return 0 - 1
+        } else if n > 0 {
+            return 1
+        }
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Function 'sign' does not return a value if the condition of an `if` statement is false, since it has no `else` branch".into())
+    );
+
+    let raw = "
+    fn name(n: int): string {
+        match n {
+            1 => return \"one\"
+            2 => println(n)
+            else => return \"many\"
+        }
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Function 'name' does not return a value in arm 2 of a `match` statement".into())
+    );
+
+    let raw = "
+    fn first(arr: int[]): int {
+        for x in arr {
+            return x
+        }
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Function 'first' does not return a value after a `for` loop ends".into())
+    );
+}
+
+#[test]
+fn test_complete_returns_are_accepted() {
+    let raw = "
+    fn sign(n: int): int {
+        if n < 0 {
+            return 0 - 1
+        } else if n > 0 {
+            return 1
+        } else {
+            return 0
+        }
+    }
+
+    fn name(n: int): string {
+        match n {
+            1 => return \"one\"
+            else => return \"many\"
+        }
+    }
+
+    fn forever(): int {
+        while true {
+            println(\"running\")
+        }
+    }
+
+    fn strict(n: int): int {
+        let enabled = true
+        if enabled {
+            return n
+        }
+    }
+
+    fn invert(b: bool): bool {
+        match b {
+            true => return false
+            false => return true
+        }
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+}
+
+#[test]
+fn test_match_cases() {
+    let raw = "
+    fn main() {
+        match 2 {
+            1 => println(1)
+            2 => println(2)
+            1 => println(3)
+            else => println(4)
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Duplicate case 1 in `match` statement in function 'main'".into())
+    );
+
+    let raw = "
+    fn main() {
+        let x = 2
+        while x > 0 {
+            match x {
+                1 => println(1)
+                2 => println(2)
+            }
+            x -= 1
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("`match` statement needs an `else` arm, since its cases do not cover every value in function 'main'".into())
+    );
+
+    let raw = "
+    fn main() {
+        match true {
+            true => println(1)
+        }
+    }
+    ";
+    assert!(check_raw(raw).is_err());
+
+    let raw = "
+    fn main() {
+        match \"b\" {
+            \"a\" => println(1)
+            \"b\" => println(2)
+            else => println(3)
+        }
+        match false {
+            true => println(1)
+            false => println(2)
+        }
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+}
+
+#[test]
+fn test_variables_must_be_assigned_before_use() {
+    let raw = "
+    fn main() {
+        if true {
+            let x = 1
+        }
+        println(x)
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Variable 'x' in function 'main' may be used before it is assigned".into())
+    );
+
+    let raw = "
+    fn f(n: int) {
+        let name
+        match n {
+            1 => name = \"one\"
+            2 => name = \"two\"
+        }
+        println(name)
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Variable 'name' in function 'f' may be used before it is assigned".into())
+    );
+}
+
+#[test]
+fn test_variables_assigned_on_every_path() {
+    let raw = "
+    fn f(n: int): int {
+        let x
+        if n > 0 {
+            x = 1
+        } else if n < 0 {
+            x = 2
+        } else {
+            return 0
+        }
+
+        let name
+        match n {
+            1 => name = \"one\"
+            else => name = \"many\"
+        }
+        println(name)
+        return x
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+}
+
+#[test]
+fn test_overflow_checks_are_lowered() {
+    let raw = "
+    fn main() {
+        let x = 1
+        x += 2 * x
+        let s = \"a\" + \"b\"
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    lower_overflow_checks(&mut module);
+
+    let location = || Expression::Str("function 'main'".into());
+    let x = || Expression::Variable("x".into());
+    let product = Expression::FunctionCall(
+        "_mul_checked".into(),
+        vec![Expression::Int(2), x(), location()],
+    );
+    assert_eq!(
+        body_of(&module, "main")[1],
+        Statement::Assign(
+            Box::new(x()),
+            Box::new(Expression::FunctionCall(
+                "_add_checked".into(),
+                vec![x(), product, location()]
+            ))
+        )
+    );
+    // Only integers are checked
+    assert!(matches!(
+        &body_of(&module, "main")[2],
+        Statement::Declare(_, Some(Expression::BinOp(_, BinOp::Addition, _)))
+    ));
+}
+
+#[test]
+fn test_size_of_is_evaluated() {
+    let raw = "
+    struct Entry {
+        used: bool
+        key: string
+    }
+
+    fn main() {
+        let a = size_of<Entry>()
+        let b = size_of<int[]>()
+    }
+    ";
+    let mut module = analyze_raw(raw);
+    check(&mut module).unwrap();
+    let body = body_of(&module, "main");
+    assert!(matches!(
+        &body[0],
+        Statement::Declare(_, Some(Expression::Int(16)))
+    ));
+    assert!(matches!(
+        &body[1],
+        Statement::Declare(_, Some(Expression::Int(8)))
+    ));
+
+    let mut module = analyze_raw("fn main() {\n let a = size_of<any>()\n}");
+    assert!(check(&mut module).is_err());
+}
+
+#[test]
+fn test_reference_arguments() {
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn move_right(ref p: Point) {
+        p.x += 1
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    fn increment(ref n: int) {
+        n += 1
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Only structs can be passed by reference, found int for argument 'n' of function 'increment'".into())
+    );
+}
+
+#[test]
+fn test_references() {
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn set(out: &int, value: int) {
+        *out = value
+    }
+
+    fn main() {
+        let n = 1
+        let p = new Point { x: 1 }
+        let numbers = [1, 2]
+        set(&n, 2)
+        set(&p.x, 3)
+        set(&numbers[0], *&n)
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    fn dangling(): &int {
+        let n = 1
+        return &n
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Can not return a reference to local variable 'n' in function 'dangling'".into())
+    );
+
+    let raw = "
+    fn main() {
+        let r = &1
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err(
+            "Only variables, fields and elements of arrays can be referenced in function 'main'"
+                .into()
+        )
+    );
+
+    let raw = "
+    fn main() {
+        let n = 1
+        let m = *n
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Only references can be dereferenced, found int in function 'main'".into())
+    );
+
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn reset(p: &Point) {
+        *p = new Point { x: 0 }
+    }
+
+    fn main() {}
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Struct 'Point' can not be assigned through a reference, assign its fields instead in function 'reset'".into())
+    );
+}
+
+#[test]
+fn test_unsafe_blocks() {
+    let raw = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(16)
+        unsafe {
+            store<int>(p, 1)
+            let q = p + 8
+            store<bool>(q, load<int>(p) == 1)
+        }
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(16)
+        let n = load<int>(p)
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("'load' can only be used in an unsafe block in function 'main'".into())
+    );
+
+    let raw = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(16) + 8
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Pointer arithmetic can only be used in an unsafe block in function 'main'".into())
+    );
+
+    let raw = "
+    fn main() {
+        let n = 1
+        unsafe {
+            store<int>(n, 2)
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("The address of 'store' must be ptr, found int in function 'main'".into())
+    );
+
+    let raw = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(16)
+        unsafe {
+            store<int>(p, \"one\")
+        }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("'store' expects a value of type int, found string in function 'main'".into())
+    );
+}
+
+#[test]
+fn test_literal_regex_patterns_are_checked() {
+    let valid = [
+        r"^(\\d+)-(?:[a-z_]|\\s)*?$",
+        r"colou?r{1,3}|x{2,}",
+        r"[\\]\\-]+\\.\\*",
+        "[é-ü]+",
+        "",
+    ];
+    for pattern in valid {
+        let raw = format!(
+            "fn main() {{\n    _regex_find(\"{}\", \"text\", 0)\n}}",
+            pattern
+        );
+        assert_eq!(check_raw(&raw), Ok(()), "{}", pattern);
+    }
+
+    let invalid = [
+        ("(a", "(a", "Unterminated group"),
+        ("a)", "a)", "Unmatched ')'"),
+        ("*a", "*a", "Nothing to repeat"),
+        ("^*", "^*", "Nothing to repeat"),
+        ("a**", "a**", "Nothing to repeat"),
+        ("(?<name>a)", "(?<name>a)", "Invalid group"),
+        ("[z-a]", "[z-a]", "Range out of order in character class"),
+        (r"[\\d-z]", r"[\d-z]", "Invalid character class"),
+        ("[a", "[a", "Unterminated character class"),
+        ("a{2,1}", "a{2,1}", "numbers out of order in {} quantifier"),
+        ("a{2", "a{2", "Incomplete quantifier"),
+        ("a}", "a}", "Lone quantifier brackets"),
+        (r"\\q", r"\q", "Invalid escape"),
+    ];
+    for (source, pattern, error) in invalid {
+        let raw = format!(
+            "fn main() {{\n    println(regex_matches(\"{}\", \"text\"))\n}}",
+            source
+        );
+        assert_eq!(
+            check_raw(&raw),
+            Err(format!(
+                "Invalid regular expression '{}': {} in function 'main'",
+                pattern, error
+            )),
+            "{}",
+            pattern
+        );
+    }
+}
+
+#[test]
+fn test_spawn() {
+    let raw = "
+    fn produce(ch: chan<int>, n: int) {
+        ch.send(n)
+    }
+
+    fn main() {
+        let ch: chan<int> = channel()
+        spawn produce(ch, 1)
+        let n: int = ch.receive().value()
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    fn main() {
+        let ch: chan<int> = channel()
+        ch.send(\"one\")
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err(
+            "Argument 1 of method 'chan<int>.send' must be int, found string in function 'main'"
+                .into()
+        )
+    );
+
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    fn move_right(ref p: Point) {
+        p.x += 1
+    }
+
+    fn main() {
+        let p = new Point { x: 1 }
+        spawn move_right(p)
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Function 'move_right' takes a struct by reference and can not be spawned in function 'main'".into())
+    );
+
+    let raw = "
+    fn show(n: &int) {
+        println(*n)
+    }
+
+    fn main() {
+        let n = 1
+        spawn show(&n)
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err(
+            "References can not be passed to a spawned call of function 'show' in function 'main'"
+                .into()
+        )
+    );
+}
+
+#[test]
+fn test_async_functions() {
+    let raw = "
+    async fn fetch_number(): int {
+        return 1
+    }
+
+    async fn main() {
+        let n = await fetch_number() + 1
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    async fn fetch_number(): int {
+        return 1
+    }
+
+    async fn main() {
+        let n = fetch_number()
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Call of async function 'fetch_number' must be awaited in function 'main'".into())
+    );
+
+    let raw = "
+    async fn fetch_number(): int {
+        return 1
+    }
+
+    fn main() {
+        let n = await fetch_number()
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("'await' can only be used in async functions in function 'main'".into())
+    );
+
+    let raw = "
+    fn number(): int {
+        return 1
+    }
+
+    async fn main() {
+        let n = await number()
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err(
+            "Function 'number' is not async, so its call can not be awaited in function 'main'"
+                .into()
+        )
+    );
+
+    let raw = "
+    async fn work() {}
+
+    fn main() {
+        spawn work()
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("Async function 'work' can not be spawned in function 'main'".into())
+    );
+}
+
+#[test]
+fn test_comptime_functions() {
+    let raw = "
+    @comptime
+    fn powers(n: int): int[] {
+        let result: int[] = []
+        let power = 1
+        while result.len() < n {
+            result.push(power)
+            power *= 2
+        }
+        return result
+    }
+
+    @comptime
+    fn total(n: int): int {
+        let sum = 0
+        for power in powers(n) {
+            sum += power
+        }
+        return sum
+    }
+
+    fn main() {
+        println(total(4))
+    }
+    ";
+    assert_eq!(check_raw(raw), Ok(()));
+
+    let raw = "
+    @comptime
+    fn greet(name: string): string {
+        println(name)
+        return name
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("@comptime function 'greet' can not call 'println', which is not annotated with @comptime".into())
+    );
+
+    let raw = "
+    let offset = 1
+
+    @comptime
+    fn shift(n: int): int {
+        return n + offset
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("@comptime function 'shift' can not use global 'offset', since its value is only known at runtime".into())
+    );
+
+    let raw = "
+    struct Point {
+        x: int
+    }
+
+    @comptime
+    fn origin(): Point {
+        return new Point { x: 0 }
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("@comptime function 'origin' can not return this type, since only integers, booleans, strings and arrays of them are computed at compile time".into())
+    );
+
+    let raw = "
+    @comptime
+    fn words(s: string): int {
+        return s.split(\" \").len()
+    }
+    ";
+    assert_eq!(
+        check_raw(raw),
+        Err("@comptime function 'words' can not call method 'split', only 'len', 'push', 'pop' can be called at compile time".into())
+    );
+}
@@ -0,0 +1,1403 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::pattern::desugar_pattern;
+use super::regex;
+use super::result::hoist_try;
+use super::scope;
+use crate::ast::attributes::{DERIVE_EQ, DERIVE_SHOW};
+use crate::ast::types::Type;
+use crate::ast::*;
+use crate::layout::Layouts;
+use std::collections::{HashMap, HashSet};
+
+/// Return types of the builtin functions that are implemented by every backend.
+/// Builtins that are written in Antimony are declared by `lib/builtin.sb` instead.
+fn builtins() -> Vec<(&'static str, Option<Type>)> {
+    vec![
+        ("_printf", None),
+        ("_exit", None),
+        ("_assert", None),
+        ("_panic", None),
+        ("_read_file", Some(Type::Str)),
+        ("_write_file", None),
+        ("_append_file", None),
+        ("_file_exists", Some(Type::Bool)),
+        ("_remove_file", None),
+        ("_args", Some(Type::Array(Box::new(Type::Str), None))),
+        ("_env", Some(Type::Str)),
+        ("_has_env", Some(Type::Bool)),
+        ("_exec", Some(Type::Int)),
+        ("_chan_new", Some(Type::Chan(Box::new(Type::Any)))),
+        ("_read_line", Some(Type::Str)),
+        ("_int_to_str", Some(Type::Str)),
+        ("_str_join", Some(Type::Str)),
+        ("_regex_find", Some(Type::Array(Box::new(Type::Int), None))),
+        ("_str_char_code", Some(Type::Int)),
+        ("_str_from_code_point", Some(Type::Str)),
+        ("_tcp_connect", Some(Type::Int)),
+        ("_tcp_read", Some(Type::Str)),
+        ("_tcp_write", Some(Type::Bool)),
+        ("_tcp_close", None),
+        ("_atomic_new", Some(Type::Ptr)),
+        ("_atomic_load", Some(Type::Int)),
+        ("_atomic_store", None),
+        ("_atomic_add", Some(Type::Int)),
+        ("_atomic_compare_exchange", Some(Type::Bool)),
+        ("_mutex_lock", None),
+        ("_mutex_try_lock", Some(Type::Bool)),
+        ("_mutex_unlock", None),
+        ("_rwlock_read", None),
+        ("_rwlock_write", None),
+        ("_rwlock_unlock", None),
+        ("_random", Some(Type::Int)),
+        ("_random_seed", None),
+        ("_now_millis", Some(Type::Int)),
+        ("_now_micros", Some(Type::Int)),
+        ("_result_ok", Some(Type::Result(Box::new(Type::Any)))),
+        ("_result_err", Some(Type::Result(Box::new(Type::Any)))),
+        ("_sleep", None),
+        ("_bit_and", Some(Type::Int)),
+        ("_bit_or", Some(Type::Int)),
+        ("_bit_xor", Some(Type::Int)),
+        ("_shift_left", Some(Type::Int)),
+        ("_shift_right", Some(Type::Int)),
+        ("_count_ones", Some(Type::Int)),
+        ("_leading_zeros", Some(Type::Int)),
+        ("_trailing_zeros", Some(Type::Int)),
+        ("_rotate_left", Some(Type::Int)),
+        ("_add_checked", Some(Type::Int)),
+        ("_sub_checked", Some(Type::Int)),
+        ("_mul_checked", Some(Type::Int)),
+        ("_check_index", Some(Type::Int)),
+    ]
+}
+
+/// Types of the functions, structures and variables that are visible to an expression
+pub struct TypeEnv {
+    functions: SymbolTable,
+    /// Structure -> (field -> type) mappings
+    structs: HashMap<String, HashMap<String, Type>>,
+    /// Structure -> (field, default value) mappings in declaration order
+    defaults: HashMap<String, Vec<(String, Option<Expression>)>>,
+    /// Methods are looked up by `<struct>.<method>`
+    methods: SymbolTable,
+    /// (structure, derive) of the functions that are derived with `@derive`, e.g. `eq`
+    derives: HashSet<(String, String)>,
+    /// Variables of the current function. Variables are unique within
+    /// a function after semantic analysis, so a single scope suffices.
+    variables: HashMap<String, Type>,
+    /// Types of global variables that have been declared so far
+    globals: HashMap<String, Type>,
+    /// Global variables that can not be assigned to
+    constants: HashSet<String>,
+    /// Return type of the current function
+    return_type: Option<Type>,
+    /// Names that occur in the current function, temporaries must not collide with them
+    taken: HashSet<String>,
+    /// Sizes of the types, used to evaluate `size_of`
+    layouts: Layouts,
+    /// Whether the current statement is inside of an `unsafe` block
+    in_unsafe: bool,
+    /// Functions that take a struct by reference
+    by_reference: HashSet<String>,
+    /// Functions and JavaScript bindings whose calls have to be awaited
+    async_functions: HashSet<String>,
+    /// Whether the current function is async, so that it can await calls
+    in_async: bool,
+}
+
+impl TypeEnv {
+    pub fn new(module: &Module) -> Self {
+        let mut structs = HashMap::new();
+        let mut defaults = HashMap::new();
+        let mut methods = SymbolTable::new();
+        let mut derives = HashSet::new();
+        for def in &module.structs {
+            for derive in [DERIVE_EQ, DERIVE_SHOW] {
+                if def.derives(derive) {
+                    derives.insert((def.name.clone(), derive.to_owned()));
+                }
+            }
+            let fields = def
+                .fields
+                .iter()
+                .filter_map(|field| Some((field.name.clone(), field.ty.clone()?)))
+                .collect();
+            structs.insert(def.name.clone(), fields);
+            let values = def
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), def.defaults.get(&field.name).cloned()))
+                .collect();
+            defaults.insert(def.name.clone(), values);
+            for method in &def.methods {
+                methods.insert(
+                    format!("{}.{}", def.name, method.name),
+                    method.ret_type.clone(),
+                );
+            }
+        }
+
+        let mut functions = module.get_symbol_table();
+        for (name, ret_type) in builtins() {
+            functions.insert(name.to_string(), ret_type);
+        }
+
+        Self {
+            functions,
+            structs,
+            defaults,
+            methods,
+            derives,
+            variables: HashMap::new(),
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            return_type: None,
+            taken: HashSet::new(),
+            layouts: Layouts::new(&module.structs),
+            in_unsafe: false,
+            by_reference: module
+                .func
+                .iter()
+                .filter(|func| func.arguments.iter().any(|arg| arg.reference))
+                .map(|func| func.name.clone())
+                .collect(),
+            async_functions: module
+                .func
+                .iter()
+                .filter(|func| func.is_async)
+                .map(|func| func.name.clone())
+                .chain(
+                    module
+                        .externs
+                        .iter()
+                        .filter(|func| func.is_async)
+                        .map(|func| func.name.clone()),
+                )
+                .collect(),
+            in_async: false,
+        }
+    }
+
+    /// Forgets the variables of the previous function
+    pub fn enter_function(&mut self, func: &Function) {
+        self.variables.clear();
+        self.taken.clear();
+        self.taken.extend(self.globals.keys().cloned());
+        for arg in &func.arguments {
+            self.declare(&arg.name, arg.ty.clone());
+            self.taken.insert(arg.name.clone());
+        }
+        scope::collect_names(&func.body, &mut self.taken);
+        self.return_type = func.ret_type.clone();
+        self.in_async = func.is_async;
+    }
+
+    /// Returns the type of a field of a structure
+    pub fn field_type(&self, name: &str, field: &str) -> Option<&Type> {
+        self.structs.get(name)?.get(field)
+    }
+
+    /// Returns the function that `@derive` generates for the struct of a type, e.g. `Point_eq`
+    fn derived_function(&self, ty: &Option<Type>, derive: &str, suffix: &str) -> Option<String> {
+        match ty {
+            Some(Type::Struct(name))
+                if self.derives.contains(&(name.clone(), derive.to_owned())) =>
+            {
+                Some(format!("{}_{}", name, suffix))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the symbol and the return type of a function that a struct declares for the
+    /// iteration protocol, e.g. `Stack_len`. Functions of other modules are looked up by
+    /// their module-qualified symbol as well, since they may already be mangled.
+    pub(super) fn protocol_function(
+        &self,
+        name: &str,
+        suffix: &str,
+    ) -> Option<(String, Option<Type>)> {
+        let function = format!("{}_{}", name, suffix);
+        if let Some(ty) = self.functions.get(&function) {
+            return Some((function, ty.clone()));
+        }
+        let qualified = format!(".{}", function);
+        let mut symbols = self
+            .functions
+            .iter()
+            .filter(|(symbol, _)| symbol.ends_with(&qualified));
+        match (symbols.next(), symbols.next()) {
+            (Some((symbol, ty)), None) => Some((symbol.clone(), ty.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn return_type(&self) -> Option<&Type> {
+        self.return_type.as_ref()
+    }
+
+    /// Returns a new variable name that is unique within the current function
+    pub(super) fn temporary(&mut self, prefix: &str) -> String {
+        let mut counter = 1;
+        while self.taken.contains(&format!("{}_{}", prefix, counter)) {
+            counter += 1;
+        }
+        let name = format!("{}_{}", prefix, counter);
+        self.taken.insert(name.clone());
+        name
+    }
+
+    pub fn declare(&mut self, name: &str, ty: Option<Type>) {
+        match ty {
+            Some(ty) => self.variables.insert(name.to_owned(), ty),
+            None => self.variables.remove(name),
+        };
+    }
+
+    /// Returns the type of an expression, if it can be determined
+    pub fn expression_type(&self, expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Int(_) => Some(Type::Int),
+            Expression::Str(_) => Some(Type::Str),
+            Expression::Bool(_) => Some(Type::Bool),
+            Expression::SizeOf(_) => Some(Type::Int),
+            Expression::Array(len, elements) => {
+                let ty = self.expression_type(elements.first()?)?;
+                Some(Type::Array(Box::new(ty), Some(*len)))
+            }
+            Expression::Map(entries) => {
+                let (key, value) = entries.first()?;
+                Some(Type::Map(
+                    Box::new(self.expression_type(key)?),
+                    Box::new(self.expression_type(value)?),
+                ))
+            }
+            Expression::Variable(name) => self.variable_type(name).cloned(),
+            Expression::ArrayAccess(name, _) => match self.variable_type(name) {
+                Some(Type::Array(ty, _)) => Some(*ty.clone()),
+                _ => None,
+            },
+            Expression::FunctionCall(name, args) => match self.functions.get(name) {
+                Some(ty) => ty.clone(),
+                None => self.builtin_call_type(name, args),
+            },
+            Expression::BinOp(lhs, op, rhs) => {
+                binop_type(&self.expression_type(lhs), op, &self.expression_type(rhs))
+                    .ok()
+                    .flatten()
+            }
+            Expression::StructInitialization(name, _) | Expression::StructUpdate(name, ..) => {
+                Some(Type::Struct(name.clone()))
+            }
+            Expression::FieldAccess(obj, field) => {
+                let name = match self.expression_type(obj)? {
+                    Type::Struct(name) => name,
+                    ty => {
+                        return match &**field {
+                            Expression::FunctionCall(method, _) => builtin_method(&ty, method)?.2,
+                            _ => None,
+                        }
+                    }
+                };
+                match &**field {
+                    Expression::Variable(field) => self.structs.get(&name)?.get(field).cloned(),
+                    Expression::FunctionCall(method, _) => self
+                        .methods
+                        .get(&format!("{}.{}", name, method))
+                        .cloned()
+                        .flatten(),
+                    _ => None,
+                }
+            }
+            Expression::Try(operand) => match self.expression_type(operand)? {
+                Type::Result(ty) => Some(*ty),
+                _ => None,
+            },
+            Expression::Await(call) => self.expression_type(call),
+            Expression::Reference(target) => {
+                Some(Type::Ref(Box::new(self.expression_type(target)?)))
+            }
+            Expression::Dereference(operand) => match self.expression_type(operand)? {
+                Type::Ref(ty) => Some(*ty),
+                _ => None,
+            },
+            Expression::Load(ty, _) => Some(ty.clone()),
+            Expression::Store(..) | Expression::Selff => None,
+        }
+    }
+
+    /// Sets the return type of a function, or of a method named `<struct>.<method>`
+    pub fn declare_return_type(&mut self, name: &str, ty: Option<Type>) {
+        let table = if name.contains('.') {
+            &mut self.methods
+        } else {
+            &mut self.functions
+        };
+        table.insert(name.to_owned(), ty);
+    }
+
+    pub fn declare_global(&mut self, name: &str, ty: Option<Type>) {
+        match ty {
+            Some(ty) => self.globals.insert(name.to_owned(), ty),
+            None => self.globals.remove(name),
+        };
+    }
+
+    /// Returns the type of a local or global variable. Locals never share
+    /// the name of a global after semantic analysis.
+    fn variable_type(&self, name: &str) -> Option<&Type> {
+        self.variables.get(name).or_else(|| self.globals.get(name))
+    }
+
+    /// Returns the type of a call of a builtin function that implements a method
+    fn builtin_call_type(&self, name: &str, args: &[Expression]) -> Option<Type> {
+        let (ty, method) = if let Some(method) = name.strip_prefix("_str_") {
+            (Type::Str, method)
+        } else if let Some(method) = name
+            .strip_prefix("_vec_")
+            .or_else(|| name.strip_prefix("_map_"))
+            .or_else(|| name.strip_prefix("_result_"))
+            .or_else(|| name.strip_prefix("_chan_"))
+        {
+            (self.expression_type(args.first()?)?, method)
+        } else {
+            return None;
+        };
+        builtin_method(&ty, method)?.2
+    }
+}
+
+/// Returns the name of the builtin function implementing a method of a builtin type,
+/// the types of its arguments and its return type
+pub fn builtin_method(ty: &Type, name: &str) -> Option<(String, Vec<Type>, Option<Type>)> {
+    let (prefix, (args, ret_type)) = match ty {
+        Type::Str => (
+            "_str",
+            match name {
+                "len" => (vec![], Some(Type::Int)),
+                "chars" => (vec![], Some(Type::Array(Box::new(Type::Str), None))),
+                "split" => (
+                    vec![Type::Str],
+                    Some(Type::Array(Box::new(Type::Str), None)),
+                ),
+                "trim" => (vec![], Some(Type::Str)),
+                "contains" => (vec![Type::Str], Some(Type::Bool)),
+                "substring" => (vec![Type::Int, Type::Int], Some(Type::Str)),
+                "replace" => (vec![Type::Str, Type::Str], Some(Type::Str)),
+                _ => return None,
+            },
+        ),
+        Type::Array(elem, _) => {
+            let elem = *elem.clone();
+            let array = Type::Array(Box::new(elem.clone()), None);
+            (
+                "_vec",
+                match name {
+                    "push" => (vec![elem], None),
+                    "pop" => (vec![], Some(elem)),
+                    "len" => (vec![], Some(Type::Int)),
+                    "slice" => (vec![Type::Int, Type::Int], Some(array)),
+                    "concat" => (vec![array.clone()], Some(array)),
+                    _ => return None,
+                },
+            )
+        }
+        Type::Map(key, value) => {
+            let (key, value) = (*key.clone(), *value.clone());
+            (
+                "_map",
+                match name {
+                    "insert" => (vec![key, value], None),
+                    "get" => (vec![key], Some(value)),
+                    "remove" => (vec![key], None),
+                    "contains" => (vec![key], Some(Type::Bool)),
+                    "keys" => (vec![], Some(Type::Array(Box::new(key), None))),
+                    "len" => (vec![], Some(Type::Int)),
+                    _ => return None,
+                },
+            )
+        }
+        Type::Chan(ty) => (
+            "_chan",
+            match name {
+                "send" => (vec![*ty.clone()], None),
+                "receive" => (vec![], Some(Type::Result(ty.clone()))),
+                "close" => (vec![], None),
+                "len" => (vec![], Some(Type::Int)),
+                _ => return None,
+            },
+        ),
+        Type::Result(ty) => (
+            "_result",
+            match name {
+                "is_ok" => (vec![], Some(Type::Bool)),
+                "is_error" => (vec![], Some(Type::Bool)),
+                "value" => (vec![], Some(*ty.clone())),
+                "error" => (vec![], Some(Type::Str)),
+                _ => return None,
+            },
+        ),
+        _ => return None,
+    };
+    Some((format!("{}_{}", prefix, name), args, ret_type))
+}
+
+/// Returns true if a value of the given type can be used where the expected type is required.
+/// The capacity of arrays is not taken into account.
+pub(super) fn is_compatible(expected: &Type, actual: &Type) -> bool {
+    match (expected, actual) {
+        (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Array(expected, _), Type::Array(actual, _)) => is_compatible(expected, actual),
+        (Type::Map(expected_key, expected_value), Type::Map(actual_key, actual_value)) => {
+            is_compatible(expected_key, actual_key) && is_compatible(expected_value, actual_value)
+        }
+        (Type::Result(expected), Type::Result(actual))
+        | (Type::Ref(expected), Type::Ref(actual))
+        | (Type::Chan(expected), Type::Chan(actual)) => is_compatible(expected, actual),
+        (expected, actual) => expected == actual,
+    }
+}
+
+/// Returns the type of a binary operation, or an error if the operator
+/// can not be applied to the operands. Unknown operand types are not checked.
+pub fn binop_type(
+    lhs: &Option<Type>,
+    op: &BinOp,
+    rhs: &Option<Type>,
+) -> Result<Option<Type>, String> {
+    let is_str = |ty: &Option<Type>| ty == &Some(Type::Str);
+    let is_known = |ty: &Option<Type>| !matches!(ty, None | Some(Type::Any));
+
+    match op {
+        BinOp::Equal | BinOp::NotEqual => {
+            if (is_str(lhs) || is_str(rhs)) && is_known(lhs) && is_known(rhs) && lhs != rhs {
+                return Err(format!(
+                    "Cannot compare {} with {}",
+                    lhs.as_ref().unwrap(),
+                    rhs.as_ref().unwrap()
+                ));
+            }
+            Ok(Some(Type::Bool))
+        }
+        BinOp::LessThan
+        | BinOp::LessThanOrEqual
+        | BinOp::GreaterThan
+        | BinOp::GreaterThanOrEqual => {
+            if is_str(lhs) || is_str(rhs) {
+                return Err("Strings can only be compared with '==' and '!='".to_owned());
+            }
+            Ok(Some(Type::Bool))
+        }
+        BinOp::And | BinOp::Or => Ok(Some(Type::Bool)),
+        // Raw pointers are moved by a number of bytes
+        BinOp::Addition | BinOp::Subtraction | BinOp::AddAssign | BinOp::SubtractAssign
+            if lhs == &Some(Type::Ptr) && matches!(rhs, None | Some(Type::Int | Type::Any)) =>
+        {
+            Ok(Some(Type::Ptr))
+        }
+        _ if lhs == &Some(Type::Ptr) || rhs == &Some(Type::Ptr) => Err(format!(
+            "Operator '{}' can not be applied to {} and {}",
+            op,
+            lhs.as_ref().unwrap_or(&Type::Any),
+            rhs.as_ref().unwrap_or(&Type::Any)
+        )),
+        BinOp::Addition | BinOp::AddAssign if is_str(lhs) || is_str(rhs) => match (lhs, rhs) {
+            (Some(l), Some(r)) if l != r && is_known(lhs) && is_known(rhs) => {
+                Err(format!("Cannot add {} to {}", r, l))
+            }
+            _ => Ok(Some(Type::Str)),
+        },
+        _ if is_str(lhs) || is_str(rhs) => {
+            Err(format!("Operator '{}' can not be applied to strings", op))
+        }
+        // Either operand of an addition could be a string
+        BinOp::Addition | BinOp::AddAssign if !is_known(lhs) || !is_known(rhs) => Ok(None),
+        _ => Ok(if is_known(lhs) {
+            lhs.clone()
+        } else {
+            rhs.clone()
+        }),
+    }
+}
+
+/// Reports operators and string methods that are applied to operands of the wrong type.
+/// Calls of string methods are replaced by calls of the builtin functions implementing them.
+pub(super) fn check(module: &mut Module) -> Result<(), String> {
+    check_main(module)?;
+
+    let mut env = TypeEnv::new(module);
+    for def in &module.structs {
+        check_defaults(&env, def)?;
+    }
+    check_globals(&mut env, &mut module.globals)?;
+    let methods = module
+        .structs
+        .iter_mut()
+        .flat_map(|def| def.methods.iter_mut());
+    for func in module.func.iter_mut().chain(methods) {
+        check_references(&func.arguments, false)
+            .map_err(|e| format!("{} of function '{}'", e, func.name))?;
+        env.enter_function(func);
+        check_statement(&mut env, &mut func.body)
+            .map_err(|e| format!("{} in function '{}'", e, func.name))?;
+    }
+    for func in &module.externs {
+        check_references(&func.arguments, true)
+            .map_err(|e| format!("{} of function '{}'", e, func.name))?;
+    }
+    Ok(())
+}
+
+/// Only structures can be passed by reference, and only to functions of the program
+fn check_references(arguments: &[Variable], external: bool) -> Result<(), String> {
+    for arg in arguments.iter().filter(|arg| arg.reference) {
+        if external {
+            return Err(format!(
+                "Argument '{}' can not be passed by reference to an external function",
+                arg.name
+            ));
+        }
+        if !matches!(arg.ty, Some(Type::Struct(_))) {
+            return Err(format!(
+                "Only structs can be passed by reference, found {} for argument '{}'",
+                arg.ty.clone().unwrap_or(Type::Any),
+                arg.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Globals are initialized in the order of their declaration, so an initializer
+/// can only refer to globals that are declared before it
+fn check_globals(env: &mut TypeEnv, globals: &mut [Global]) -> Result<(), String> {
+    let names: HashSet<String> = globals.iter().map(|g| g.var.name.clone()).collect();
+    for global in globals.iter_mut() {
+        let name = global.var.name.clone();
+        if env.functions.contains_key(&name) {
+            return Err(format!("Global '{}' has the same name as a function", name));
+        }
+        if env.globals.contains_key(&name) {
+            return Err(format!("Global '{}' is declared more than once", name));
+        }
+        let mut used = Vec::new();
+        collect_variables(&global.value, &mut used);
+        if let Some(var) = used
+            .iter()
+            .find(|var| names.contains(*var) && !env.globals.contains_key(*var))
+        {
+            return Err(format!(
+                "Global '{}' is used before its declaration in the initializer of '{}'",
+                var, name
+            ));
+        }
+
+        check_expression(env, &mut global.value)
+            .map_err(|e| format!("{} in global '{}'", e, name))?;
+        let actual = env.expression_type(&global.value);
+        match (&global.var.ty, actual) {
+            (Some(expected), Some(actual)) if !is_compatible(expected, &actual) => {
+                return Err(format!(
+                    "Global '{}' must be {}, found {}",
+                    name, expected, actual
+                ))
+            }
+            (None, actual) => global.var.ty = actual,
+            _ => {}
+        }
+        if let Some(Type::Map(key, _)) = &global.var.ty {
+            check_map_key(key)?;
+        }
+
+        // Globals of an unknown type are still declared, so that later initializers may use them
+        env.declare_global(&name, Some(global.var.ty.clone().unwrap_or(Type::Any)));
+        if global.constant {
+            env.constants.insert(name);
+        }
+    }
+    Ok(())
+}
+
+/// Collects the names of all variables an expression refers to
+pub(super) fn collect_variables(expr: &Expression, names: &mut Vec<String>) {
+    match expr {
+        Expression::Variable(name) => names.push(name.clone()),
+        Expression::ArrayAccess(name, index) => {
+            names.push(name.clone());
+            collect_variables(index, names);
+        }
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::SizeOf(_) => {}
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+            args.iter().for_each(|arg| collect_variables(arg, names))
+        }
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            collect_variables(lhs, names);
+            collect_variables(rhs, names);
+        }
+        Expression::StructInitialization(_, fields) => fields
+            .values()
+            .for_each(|field| collect_variables(field, names)),
+        Expression::StructUpdate(_, base, fields) => {
+            collect_variables(base, names);
+            fields
+                .values()
+                .for_each(|field| collect_variables(field, names))
+        }
+        // Only the object of a field access is a variable, the field is not
+        Expression::FieldAccess(obj, field) => {
+            collect_variables(obj, names);
+            if let Expression::FunctionCall(_, args) = &**field {
+                args.iter().for_each(|arg| collect_variables(arg, names))
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries {
+                collect_variables(key, names);
+                collect_variables(value, names);
+            }
+        }
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => collect_variables(operand, names),
+    }
+}
+
+/// Default values of fields are evaluated where the structure is initialized,
+/// so they can not refer to variables
+fn check_defaults(env: &TypeEnv, def: &StructDef) -> Result<(), String> {
+    for field in &def.fields {
+        let value = match def.defaults.get(&field.name) {
+            Some(value) => value,
+            None => continue,
+        };
+        if refers_to_variables(value) {
+            return Err(format!(
+                "Default value of field '{}' in struct '{}' can not refer to variables",
+                field.name, def.name
+            ));
+        }
+        if let (Some(expected), Some(actual)) = (&field.ty, env.expression_type(value)) {
+            if !is_compatible(expected, &actual) {
+                return Err(format!(
+                    "Default value of field '{}' in struct '{}' must be {}, found {}",
+                    field.name, def.name, expected, actual
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn refers_to_variables(expr: &Expression) -> bool {
+    match expr {
+        Expression::Variable(_) | Expression::Selff | Expression::ArrayAccess(..) => true,
+        Expression::Int(_) | Expression::Str(_) | Expression::Bool(_) | Expression::SizeOf(_) => {
+            false
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+            args.iter().any(refers_to_variables)
+        }
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            refers_to_variables(lhs) || refers_to_variables(rhs)
+        }
+        Expression::StructInitialization(_, fields) => {
+            fields.values().any(|field| refers_to_variables(field))
+        }
+        Expression::StructUpdate(_, base, fields) => {
+            refers_to_variables(base) || fields.values().any(|field| refers_to_variables(field))
+        }
+        Expression::FieldAccess(obj, field) => {
+            refers_to_variables(obj) || refers_to_variables(field)
+        }
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| refers_to_variables(key) || refers_to_variables(value)),
+        Expression::Try(operand)
+        | Expression::Await(operand)
+        | Expression::Reference(operand)
+        | Expression::Dereference(operand)
+        | Expression::Load(_, operand) => refers_to_variables(operand),
+    }
+}
+
+/// The return value of `main` is the exit code of the program
+fn check_main(module: &Module) -> Result<(), String> {
+    let main = module.func.iter().find(|func| func.name == "main");
+    match main.and_then(|func| func.ret_type.as_ref()) {
+        None | Some(Type::Int) => Ok(()),
+        Some(ty) => Err(format!(
+            "Function 'main' must return int or nothing, found {}",
+            ty
+        )),
+    }
+}
+
+fn check_statement(env: &mut TypeEnv, statement: &mut Statement) -> Result<(), String> {
+    match statement {
+        Statement::Block(statements, _) => {
+            let mut checked = Vec::with_capacity(statements.len());
+            for mut statement in statements.drain(..) {
+                for mut hoisted in hoist_try(env, &mut statement)? {
+                    // Operands of '?' are declared without a type, unlike the
+                    // conditions of short-circuiting operators
+                    let operand =
+                        matches!(&hoisted, Statement::Declare(var, _) if var.ty.is_none());
+                    check_statement(env, &mut hoisted)?;
+                    if let (true, Statement::Declare(var, _)) = (operand, &hoisted) {
+                        check_try_operand(&var.ty)?;
+                    }
+                    checked.push(hoisted);
+                }
+                for mut statement in desugar_pattern(env, statement)? {
+                    check_statement(env, &mut statement)?;
+                    checked.push(statement);
+                }
+            }
+            *statements = checked;
+        }
+        // Patterns outside of a block, e.g. in the arm of a match statement
+        Statement::For(_, expr, _)
+            if matches!(env.expression_type(expr), Some(Type::Struct(_) | Type::Str)) =>
+        {
+            let desugared = desugar_pattern(env, statement.clone())?;
+            *statement = Statement::Block(desugared, Vec::new());
+            check_statement(env, statement)?;
+        }
+        Statement::Destructure(..) | Statement::ForPair(..) => {
+            let desugared = desugar_pattern(env, statement.clone())?;
+            *statement = Statement::Block(desugared, Vec::new());
+            check_statement(env, statement)?;
+        }
+        Statement::Declare(var, expr) => {
+            if let Some(expr) = expr {
+                check_expression(env, expr)?;
+            }
+            if let Some(Type::Map(key, _)) = &var.ty {
+                check_map_key(key)?;
+            }
+            // Backends that need the types of variables rely on inferred types
+            if var.ty.is_none() {
+                var.ty = expr.as_ref().and_then(|e| env.expression_type(e));
+            }
+            env.declare(&var.name, var.ty.clone());
+        }
+        Statement::Assign(lhs, rhs) => {
+            check_constant_assignment(env, lhs)?;
+            check_expression(env, lhs)?;
+            check_expression(env, rhs)?;
+            // Native code would copy the struct, while JavaScript would replace the referenced object
+            if let (Expression::Dereference(_), Some(Type::Struct(name))) =
+                (&**lhs, env.expression_type(lhs))
+            {
+                return Err(format!(
+                    "Struct '{}' can not be assigned through a reference, assign its fields instead",
+                    name
+                ));
+            }
+        }
+        Statement::Return(Some(expr)) => {
+            check_expression(env, expr)?;
+            // Local variables do not outlive the function
+            if let Expression::Reference(target) = expr {
+                if let Expression::Variable(name) = &**target {
+                    if env.variables.contains_key(name) {
+                        return Err(format!(
+                            "Can not return a reference to local variable '{}'",
+                            name
+                        ));
+                    }
+                }
+            }
+            // Values returned from functions that return a result are successful results
+            if let Some(Type::Result(value_ty)) = env.return_type() {
+                match env.expression_type(expr) {
+                    Some(Type::Result(_)) => {}
+                    Some(ty) if !is_compatible(value_ty, &ty) => {
+                        return Err(format!("Expected return type {}!, found {}", value_ty, ty))
+                    }
+                    _ => *expr = Expression::FunctionCall("_result_ok".into(), vec![expr.clone()]),
+                }
+            }
+        }
+        Statement::Exp(expr) | Statement::Defer(expr) => check_expression(env, expr)?,
+        Statement::Spawn(expr) => {
+            check_spawn(env, expr)?;
+            check_expression(env, expr)?;
+        }
+        Statement::If(cond, if_branch, else_branch) => {
+            check_expression(env, cond)?;
+            check_statement(env, if_branch)?;
+            if let Some(else_branch) = else_branch {
+                check_statement(env, else_branch)?;
+            }
+        }
+        Statement::While(cond, body) => {
+            check_expression(env, cond)?;
+            check_statement(env, body)?;
+        }
+        Statement::Unsafe(body) => {
+            let outer = std::mem::replace(&mut env.in_unsafe, true);
+            let checked = check_statement(env, body);
+            env.in_unsafe = outer;
+            checked?;
+        }
+        Statement::For(var, expr, body) => {
+            check_expression(env, expr)?;
+            if let Some(Type::Array(ty, _)) = env.expression_type(expr) {
+                var.ty = Some(*ty);
+            }
+            env.declare(&var.name, var.ty.clone());
+            check_statement(env, body)?;
+        }
+        Statement::Match(subject, arms) => {
+            check_expression(env, subject)?;
+            for arm in arms {
+                let body = match arm {
+                    MatchArm::Case(expr, s) => {
+                        check_expression(env, expr)?;
+                        s
+                    }
+                    MatchArm::Else(s) => s,
+                };
+                // A deferred expression would be evaluated right away
+                if let Statement::Defer(_) = body {
+                    return Err("'defer' can only be used inside of a block".into());
+                }
+                check_statement(env, body)?;
+            }
+        }
+        Statement::Static(..) => unreachable!("Static variables are moved to the globals"),
+        Statement::Return(None)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Inline(..) => {}
+    }
+    Ok(())
+}
+
+/// The task of a spawned call may run after the function that spawned it has returned,
+/// so it can not be passed references to the variables of the function
+fn check_spawn(env: &TypeEnv, call: &Expression) -> Result<(), String> {
+    let (name, args) = match call {
+        Expression::FunctionCall(name, args) => (name, args),
+        _ => return Err("Only function calls can be spawned".into()),
+    };
+    if env.async_functions.contains(name) {
+        return Err(format!("Async function '{}' can not be spawned", name));
+    }
+    if env.by_reference.contains(name) {
+        return Err(format!(
+            "Function '{}' takes a struct by reference and can not be spawned",
+            name
+        ));
+    }
+    if args
+        .iter()
+        .any(|arg| matches!(arg, Expression::Reference(_)))
+    {
+        return Err(format!(
+            "References can not be passed to a spawned call of function '{}'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Constants are only assigned by their initializer
+fn check_constant_assignment(env: &TypeEnv, target: &Expression) -> Result<(), String> {
+    match target {
+        Expression::Variable(name) if env.constants.contains(name) => {
+            Err(format!("Can not assign to constant '{}'", name))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_expression(env: &TypeEnv, expr: &mut Expression) -> Result<(), String> {
+    match expr {
+        Expression::BinOp(lhs, op, rhs) => {
+            if matches!(
+                op,
+                BinOp::AddAssign
+                    | BinOp::SubtractAssign
+                    | BinOp::MultiplyAssign
+                    | BinOp::DivideAssign
+            ) {
+                check_constant_assignment(env, lhs)?;
+            }
+            check_expression(env, lhs)?;
+            check_expression(env, rhs)?;
+            let (lhs_type, rhs_type) = (env.expression_type(lhs), env.expression_type(rhs));
+            let ty = binop_type(&lhs_type, op, &rhs_type)?;
+            if ty == Some(Type::Ptr) && !env.in_unsafe {
+                return Err("Pointer arithmetic can only be used in an unsafe block".into());
+            }
+            // Structs that derive `eq` are compared by their fields
+            if let (BinOp::Equal | BinOp::NotEqual, Some(eq)) =
+                (&op, env.derived_function(&lhs_type, DERIVE_EQ, "eq"))
+            {
+                if let Some(rhs_type) = rhs_type.filter(|ty| Some(ty) != lhs_type.as_ref()) {
+                    return Err(format!(
+                        "Cannot compare {} with {}",
+                        lhs_type.unwrap_or(Type::Any),
+                        rhs_type
+                    ));
+                }
+                let call = Expression::FunctionCall(eq, vec![*lhs.clone(), *rhs.clone()]);
+                *expr = match op {
+                    BinOp::Equal => call,
+                    _ => Expression::BinOp(
+                        Box::new(call),
+                        BinOp::Equal,
+                        Box::new(Expression::Bool(false)),
+                    ),
+                };
+            }
+        }
+        Expression::FunctionCall(name, _) if env.async_functions.contains(name) => {
+            return Err(format!("Call of async function '{}' must be awaited", name));
+        }
+        Expression::Await(call) => {
+            if !env.in_async {
+                return Err("'await' can only be used in async functions".into());
+            }
+            match &mut **call {
+                Expression::FunctionCall(name, args) if env.async_functions.contains(name) => {
+                    for arg in args.iter_mut() {
+                        check_expression(env, arg)?;
+                    }
+                }
+                Expression::FunctionCall(name, _) => {
+                    return Err(format!(
+                        "Function '{}' is not async, so its call can not be awaited",
+                        name
+                    ))
+                }
+                _ => return Err("Only calls of async functions can be awaited".into()),
+            }
+        }
+        Expression::FunctionCall(name, args)
+            if name == "format" && !env.functions.contains_key(name) =>
+        {
+            for arg in args.iter_mut() {
+                check_expression(env, arg)?;
+            }
+            *expr = lower_format(env, args)?;
+        }
+        Expression::FunctionCall(name, args)
+            if name == "error" && !env.functions.contains_key(name) =>
+        {
+            for arg in args.iter_mut() {
+                check_expression(env, arg)?;
+            }
+            if args.len() != 1 {
+                return Err("'error' expects 1 argument".into());
+            }
+            match env.expression_type(&args[0]) {
+                Some(ty) if !is_compatible(&Type::Str, &ty) => {
+                    return Err(format!(
+                        "The message of an error must be a string, found {}",
+                        ty
+                    ))
+                }
+                _ => *expr = Expression::FunctionCall("_result_err".into(), args.clone()),
+            }
+        }
+        // The length of strings, arrays, maps and structs that declare the iteration protocol
+        Expression::FunctionCall(name, args)
+            if name == "len" && !env.functions.contains_key(name) =>
+        {
+            for arg in args.iter_mut() {
+                check_expression(env, arg)?;
+            }
+            if args.len() != 1 {
+                return Err("'len' expects 1 argument".into());
+            }
+            let function = match env.expression_type(&args[0]) {
+                Some(Type::Struct(name)) => env
+                    .protocol_function(&name, PROTOCOL_LEN)
+                    .map(|(function, _)| function)
+                    .ok_or_else(|| {
+                        format!(
+                            "Struct '{0}' has no length. Declare the function '{0}_{1}(value: {0}): int'",
+                            name, PROTOCOL_LEN
+                        )
+                    })?,
+                Some(ty) => match builtin_method(&ty, PROTOCOL_LEN) {
+                    Some((function, ..)) => function,
+                    None => return Err(format!("{} has no length", ty)),
+                },
+                None => return Err("The type of the argument of 'len' must be known".into()),
+            };
+            *expr = Expression::FunctionCall(function, args.clone());
+        }
+        // Structs that derive `show` are printed as their description
+        Expression::FunctionCall(name, args) if name == "print" || name == "println" => {
+            for arg in args.iter_mut() {
+                check_expression(env, arg)?;
+                let ty = env.expression_type(arg);
+                if let Some(show) = env.derived_function(&ty, DERIVE_SHOW, "to_string") {
+                    *arg = Expression::FunctionCall(show, vec![arg.clone()]);
+                }
+            }
+        }
+        // Patterns of regular expressions that are literals are checked before the program runs
+        Expression::FunctionCall(name, args) if regex::takes_pattern(name) => {
+            for arg in args.iter_mut() {
+                check_expression(env, arg)?;
+            }
+            if let Some(Expression::Str(pattern)) = args.first() {
+                regex::check_pattern(pattern).map_err(|error| {
+                    format!("Invalid regular expression '{}': {}", pattern, error)
+                })?;
+            }
+        }
+        Expression::FunctionCall(_, args) | Expression::Array(_, args) => {
+            for arg in args {
+                check_expression(env, arg)?;
+            }
+        }
+        Expression::ArrayAccess(name, index) => {
+            // An index of a UTF-8 string may point into the middle of a character
+            if env.variable_type(name) == Some(&Type::Str) {
+                return Err(format!(
+                    "String '{}' can not be indexed, use chars() or substring() instead",
+                    name
+                ));
+            }
+            check_expression(env, index)?
+        }
+        Expression::StructInitialization(name, fields) => {
+            // Omitted fields take their default values
+            for (field, default) in env.defaults.get(name).into_iter().flatten() {
+                if fields.contains_key(field) {
+                    continue;
+                }
+                match default {
+                    Some(value) => {
+                        fields.insert(field.clone(), Box::new(value.clone()));
+                    }
+                    None => {
+                        return Err(format!(
+                            "Missing field '{}' in initialization of struct '{}'",
+                            field, name
+                        ))
+                    }
+                }
+            }
+            for field in fields.values_mut() {
+                check_expression(env, field)?;
+            }
+        }
+        Expression::StructUpdate(name, base, fields) => {
+            check_expression(env, base)?;
+            match env.expression_type(base) {
+                Some(Type::Struct(base_name)) if base_name == *name => {}
+                Some(ty) if ty != Type::Any => {
+                    return Err(format!(
+                        "Fields of struct '{}' can not be copied from {}",
+                        name, ty
+                    ))
+                }
+                _ => {}
+            }
+            for field in fields.values_mut() {
+                check_expression(env, field)?;
+            }
+        }
+        Expression::FieldAccess(obj, field) => {
+            check_expression(env, obj)?;
+            if let Expression::FunctionCall(_, args) = &mut **field {
+                for arg in args.iter_mut() {
+                    check_expression(env, arg)?;
+                }
+            }
+            if let Some(
+                ty
+                @ (Type::Str | Type::Array(..) | Type::Map(..) | Type::Chan(_) | Type::Result(_)),
+            ) = env.expression_type(obj)
+            {
+                *expr = builtin_method_call(env, &ty, obj, field)?;
+            }
+        }
+        Expression::Map(entries) => {
+            for (key, value) in entries.iter_mut() {
+                check_expression(env, key)?;
+                check_expression(env, value)?;
+            }
+            check_map_literal(env, entries)?;
+        }
+        // Operators in positions that can be hoisted have been lowered already
+        Expression::Try(_) => return Err("The '?' operator can not be used here".into()),
+        Expression::Reference(target) => {
+            match &**target {
+                Expression::Variable(name) if env.constants.contains(name) => {
+                    return Err(format!("Can not take a reference to constant '{}'", name))
+                }
+                Expression::FieldAccess(_, field) if matches!(**field, Expression::Variable(_)) => {
+                }
+                Expression::Variable(_) | Expression::ArrayAccess(..) => {}
+                _ => {
+                    return Err(
+                        "Only variables, fields and elements of arrays can be referenced".into(),
+                    )
+                }
+            }
+            check_expression(env, target)?;
+        }
+        Expression::Dereference(operand) => {
+            check_expression(env, operand)?;
+            match env.expression_type(operand) {
+                None | Some(Type::Ref(_)) | Some(Type::Any) => {}
+                Some(ty) => {
+                    return Err(format!("Only references can be dereferenced, found {}", ty))
+                }
+            }
+        }
+        Expression::Load(ty, address) => {
+            check_expression(env, address)?;
+            check_raw_access(env, "load", ty, address)?;
+        }
+        Expression::Store(ty, address, value) => {
+            check_expression(env, address)?;
+            check_expression(env, value)?;
+            check_raw_access(env, "store", ty, address)?;
+            match env.expression_type(value) {
+                Some(actual) if !is_compatible(ty, &actual) => {
+                    return Err(format!(
+                        "'store' expects a value of type {}, found {}",
+                        ty, actual
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Expression::SizeOf(ty) => *expr = Expression::Int(env.layouts.of(ty)?.size as usize),
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Variable(_)
+        | Expression::Selff => {}
+    }
+    Ok(())
+}
+
+/// Reports reads and writes of raw memory outside of unsafe blocks.
+/// Only values that fit into a register can be read and written.
+fn check_raw_access(
+    env: &TypeEnv,
+    name: &str,
+    ty: &Type,
+    address: &Expression,
+) -> Result<(), String> {
+    if !env.in_unsafe {
+        return Err(format!("'{}' can only be used in an unsafe block", name));
+    }
+    if !matches!(ty, Type::Int | Type::Bool | Type::Ptr | Type::Str) {
+        return Err(format!(
+            "'{}' can not be used with values of type {}",
+            name, ty
+        ));
+    }
+    match env.expression_type(address) {
+        None | Some(Type::Ptr) | Some(Type::Any) => Ok(()),
+        Some(ty) => Err(format!(
+            "The address of '{}' must be ptr, found {}",
+            name, ty
+        )),
+    }
+}
+
+/// Reports operands of the `?` operator that are not results
+fn check_try_operand(ty: &Option<Type>) -> Result<(), String> {
+    match ty {
+        None | Some(Type::Result(_)) | Some(Type::Any) => Ok(()),
+        Some(other) => Err(format!(
+            "The '?' operator can only be applied to results, found {}",
+            other
+        )),
+    }
+}
+
+/// Lowers `format(fmt, args...)` to a concatenation of the parts of the format string
+/// and the arguments, which are converted to strings according to their format specifiers
+fn lower_format(env: &TypeEnv, args: &[Expression]) -> Result<Expression, String> {
+    let (fmt, args) = match args.split_first() {
+        Some((Expression::Str(fmt), args)) => (fmt, args),
+        _ => return Err("The first argument of 'format' must be a string literal".into()),
+    };
+
+    let mut parts: Vec<Expression> = Vec::new();
+    let mut literal = String::new();
+    let mut args = args.iter().enumerate();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        let specifier = chars.next().ok_or_else(|| {
+            "Incomplete format specifier at the end of a format string".to_owned()
+        })?;
+        let (expected, conversion) = match specifier {
+            '%' => {
+                literal.push('%');
+                continue;
+            }
+            'd' => (Type::Int, Some("_int_to_str")),
+            'b' => (Type::Bool, Some("_bool_to_str")),
+            's' => (Type::Str, None),
+            other => return Err(format!("Unknown format specifier '%{}'", other)),
+        };
+        let (i, arg) = args
+            .next()
+            .ok_or_else(|| format!("Missing argument for format specifier '%{}'", specifier))?;
+        let ty = env.expression_type(arg);
+        // Structs that derive `show` are formatted as their description
+        let show = match specifier {
+            's' => env.derived_function(&ty, DERIVE_SHOW, "to_string"),
+            _ => None,
+        };
+        match ty {
+            Some(_) if show.is_some() => {}
+            Some(actual) if !is_compatible(&expected, &actual) => {
+                return Err(format!(
+                    "Argument {} of 'format' must be {}, found {}",
+                    i + 2,
+                    expected,
+                    actual
+                ))
+            }
+            _ => {}
+        }
+
+        if !literal.is_empty() {
+            parts.push(Expression::Str(std::mem::take(&mut literal)));
+        }
+        parts.push(match show.as_deref().or(conversion) {
+            Some(function) => Expression::FunctionCall(function.into(), vec![arg.clone()]),
+            None => arg.clone(),
+        });
+    }
+    if let Some((i, _)) = args.next() {
+        return Err(format!(
+            "Argument {} of 'format' has no format specifier",
+            i + 2
+        ));
+    }
+    if !literal.is_empty() || parts.is_empty() {
+        parts.push(Expression::Str(literal));
+    }
+
+    let mut parts = parts.into_iter();
+    let first = parts.next().expect("Format string has at least one part");
+    Ok(parts.fold(first, |lhs, rhs| {
+        Expression::BinOp(Box::new(lhs), BinOp::Addition, Box::new(rhs))
+    }))
+}
+
+/// Native backends can only hash integers, booleans and strings
+fn check_map_key(ty: &Type) -> Result<(), String> {
+    match ty {
+        Type::Int | Type::Str | Type::Bool | Type::Any => Ok(()),
+        other => Err(format!(
+            "Keys of maps must be of type int, string or bool, found {}",
+            other
+        )),
+    }
+}
+
+/// Reports keys and values of a map literal whose types differ from the first entry
+fn check_map_literal(env: &TypeEnv, entries: &[(Expression, Expression)]) -> Result<(), String> {
+    let types: Vec<(Option<Type>, Option<Type>)> = entries
+        .iter()
+        .map(|(key, value)| (env.expression_type(key), env.expression_type(value)))
+        .collect();
+    let (first_key, first_value) = match types.first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+    if let Some(key) = first_key {
+        check_map_key(key)?;
+    }
+
+    for (key, value) in &types[1..] {
+        for (kind, first, ty) in [("Keys", first_key, key), ("Values", first_value, value)] {
+            if let (Some(first), Some(ty)) = (first, ty) {
+                if !is_compatible(first, ty) {
+                    return Err(format!(
+                        "{} of a map must have the same type, found {} and {}",
+                        kind, first, ty
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the arguments of a method of a builtin type and returns the call of its builtin function
+fn builtin_method_call(
+    env: &TypeEnv,
+    ty: &Type,
+    obj: &Expression,
+    field: &Expression,
+) -> Result<Expression, String> {
+    let (method, args) = match field {
+        Expression::FunctionCall(method, args) => (method, args),
+        Expression::Variable(name) => return Err(format!("No field '{}' on {}", name, ty)),
+        _ => return Err(format!("Invalid field access on {}", ty)),
+    };
+    let (builtin, arg_types, _) =
+        builtin_method(ty, method).ok_or_else(|| format!("No method '{}' on {}", method, ty))?;
+
+    if args.len() != arg_types.len() {
+        return Err(format!(
+            "Method '{}.{}' expects {} argument{}, found {}",
+            ty,
+            method,
+            arg_types.len(),
+            if arg_types.len() == 1 { "" } else { "s" },
+            args.len()
+        ));
+    }
+    for (i, (arg, expected)) in args.iter().zip(&arg_types).enumerate() {
+        match env.expression_type(arg) {
+            Some(actual) if !is_compatible(expected, &actual) => {
+                return Err(format!(
+                    "Argument {} of method '{}.{}' must be {}, found {}",
+                    i + 1,
+                    ty,
+                    method,
+                    expected,
+                    actual
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let mut call_args = vec![obj.clone()];
+    call_args.extend(args.iter().cloned());
+    Ok(Expression::FunctionCall(builtin, call_args))
+}
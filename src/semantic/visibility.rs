@@ -0,0 +1,254 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::types::Type;
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Checks that modules only use the functions, structs and globals of other modules
+/// that are declared with `pub`. Builtins and the standard library are not checked.
+pub(super) fn check(modules: &[Module]) -> Result<(), String> {
+    // Name -> (kind, module) of declarations that are private
+    let mut private: HashMap<&str, (&str, &str)> = HashMap::new();
+    let mut public: HashSet<&str> = HashSet::new();
+    for module in modules {
+        let declarations = module
+            .func
+            .iter()
+            .map(|f| ("Function", f.name.as_str(), f.public))
+            .chain(
+                module
+                    .structs
+                    .iter()
+                    .map(|d| ("Struct", d.name.as_str(), d.public)),
+            )
+            .chain(
+                module
+                    .globals
+                    .iter()
+                    .map(|g| ("Global variable", g.var.name.as_str(), g.public)),
+            );
+        for (kind, name, is_public) in declarations {
+            if is_public {
+                public.insert(name);
+            } else {
+                private.insert(name, (kind, &module.path));
+            }
+        }
+    }
+    // A public declaration with the same name is used instead
+    private.retain(|name, _| !public.contains(name));
+
+    for module in modules {
+        let mut references = References::default();
+        for global in &module.globals {
+            references.expression(&global.value);
+        }
+        for def in &module.structs {
+            def.fields
+                .iter()
+                .filter_map(|f| f.ty.as_ref())
+                .for_each(|ty| references.ty(ty));
+        }
+        let methods = module.structs.iter().flat_map(|def| &def.methods);
+        for func in module.func.iter().chain(methods) {
+            references.locals.clear();
+            for arg in &func.arguments {
+                references.declare(arg);
+            }
+            func.ret_type.iter().for_each(|ty| references.ty(ty));
+            references.statement(&func.body);
+        }
+
+        let own: HashSet<&str> = module
+            .func
+            .iter()
+            .map(|f| f.name.as_str())
+            .chain(module.structs.iter().map(|d| d.name.as_str()))
+            .chain(module.globals.iter().map(|g| g.var.name.as_str()))
+            .collect();
+        for name in &references.names {
+            if own.contains(name.as_str()) {
+                continue;
+            }
+            if let Some((kind, owner)) = private.get(name.as_str()) {
+                return Err(format!(
+                    "{} '{}' is private to module '{}' and can not be used by module '{}'. Declare it with `pub` to use it in other modules",
+                    kind, name, owner, module.path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Names of the functions, structs and globals that are referenced by a module
+#[derive(Default)]
+struct References {
+    names: Vec<String>,
+    /// Local variables of the current function, which shadow globals
+    locals: HashSet<String>,
+}
+
+impl References {
+    fn declare(&mut self, var: &Variable) {
+        self.locals.insert(var.name.clone());
+        var.ty.iter().for_each(|ty| self.ty(ty));
+    }
+
+    fn variable(&mut self, name: &str) {
+        if !self.locals.contains(name) {
+            self.names.push(name.to_owned());
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Struct(name) => self.names.push(name.clone()),
+            Type::Array(inner, _) | Type::Result(inner) | Type::Ref(inner) | Type::Chan(inner) => {
+                self.ty(inner)
+            }
+            Type::Map(key, value) => {
+                self.ty(key);
+                self.ty(value);
+            }
+            Type::Any | Type::Int | Type::Str | Type::Bool | Type::Ptr => {}
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(statements, _) => statements.iter().for_each(|s| self.statement(s)),
+            Statement::Declare(var, value) => {
+                self.declare(var);
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Statement::Static(var, value) => {
+                self.declare(var);
+                self.expression(value);
+            }
+            Statement::Destructure(bindings, value) => {
+                bindings.iter().for_each(|(_, var)| self.declare(var));
+                self.expression(value);
+            }
+            Statement::Assign(lhs, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Statement::If(condition, if_branch, else_branch) => {
+                self.expression(condition);
+                self.statement(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Statement::While(condition, body) => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            Statement::Unsafe(body) => self.statement(body),
+            Statement::For(var, iterable, body) => {
+                self.declare(var);
+                self.expression(iterable);
+                self.statement(body);
+            }
+            Statement::ForPair(key, value, iterable, body) => {
+                self.declare(key);
+                self.declare(value);
+                self.expression(iterable);
+                self.statement(body);
+            }
+            Statement::Match(subject, arms) => {
+                self.expression(subject);
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(case, body) => {
+                            self.expression(case);
+                            self.statement(body);
+                        }
+                        MatchArm::Else(body) => self.statement(body),
+                    }
+                }
+            }
+            Statement::Exp(expr) | Statement::Defer(expr) | Statement::Spawn(expr) => {
+                self.expression(expr)
+            }
+            Statement::Break | Statement::Continue | Statement::Inline(..) => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Variable(name) => self.variable(name),
+            Expression::ArrayAccess(name, index) => {
+                self.variable(name);
+                self.expression(index);
+            }
+            Expression::FunctionCall(name, args) => {
+                self.names.push(name.clone());
+                args.iter().for_each(|arg| self.expression(arg));
+            }
+            Expression::Array(_, elements) => elements.iter().for_each(|e| self.expression(e)),
+            Expression::BinOp(lhs, _, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::Load(ty, address) => {
+                self.ty(ty);
+                self.expression(address);
+            }
+            Expression::Store(ty, address, value) => {
+                self.ty(ty);
+                self.expression(address);
+                self.expression(value);
+            }
+            Expression::StructInitialization(name, fields) => {
+                self.names.push(name.clone());
+                fields.values().for_each(|e| self.expression(e));
+            }
+            Expression::StructUpdate(name, base, fields) => {
+                self.names.push(name.clone());
+                self.expression(base);
+                fields.values().for_each(|e| self.expression(e));
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Expression::Try(inner)
+            | Expression::Await(inner)
+            | Expression::Reference(inner)
+            | Expression::Dereference(inner) => self.expression(inner),
+            // Only the object is a name, fields and methods belong to its struct
+            Expression::FieldAccess(obj, field) => {
+                self.expression(obj);
+                if let Expression::FunctionCall(_, args) = &**field {
+                    args.iter().for_each(|arg| self.expression(arg));
+                }
+            }
+            Expression::SizeOf(ty) => self.ty(ty),
+            Expression::Int(_) | Expression::Str(_) | Expression::Bool(_) | Expression::Selff => {}
+        }
+    }
+}
@@ -13,4 +13,7 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+mod test_compile;
+mod test_differential;
 mod test_examples;
+mod test_golden;
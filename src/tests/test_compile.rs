@@ -0,0 +1,1418 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::builder::{BuildOptions, Builder};
+use crate::diagnostics::Severity;
+use crate::generator::js::{JsRuntime, ModuleFormat};
+use crate::generator::{Arch, Target};
+use crate::warnings::WarningOptions;
+use crate::{check, compile, compile_with_options};
+
+#[test]
+fn test_compile_js() {
+    let output = compile("fn main() { println(\"Hello\") }", Target::JS).unwrap();
+    assert!(output.contains("function main()"));
+    // The standard library is included
+    assert!(output.contains("function println("));
+}
+
+#[test]
+fn test_compile_js_exit_code() {
+    let output = compile("fn main(): int { return 1 }", Target::JS).unwrap();
+    assert!(output.ends_with("process.exitCode = main();"));
+}
+
+#[test]
+fn test_compile_qbe_includes_stdlib() {
+    let output = compile("fn main() { println(read_file(\"in.txt\")) }", Target::Qbe).unwrap();
+    assert!(output.contains("function l $read_file(l "));
+    assert!(output.contains("=l call $_read_file(l "));
+}
+
+#[test]
+fn test_compile_qbe_debug_limits_call_depth() {
+    let source = "fn main() { println(1) }";
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(!output.contains("_sb_call_depth"));
+
+    let options = BuildOptions {
+        debug: true,
+        ..BuildOptions::default()
+    };
+    let output = compile_with_options(source, Target::Qbe, options).unwrap();
+    assert!(output.contains("data $global._sb_call_depth"));
+    assert!(output.contains("maximum call depth of 10000 exceeded"));
+}
+
+#[test]
+fn test_compile_checks_array_bounds() {
+    let source = "
+    fn main() {
+        let arr = [1, 2]
+        println(arr[first()])
+    }
+
+    @unsafe_index
+    fn first(): int {
+        let arr = [0]
+        return arr[0]
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("arr[_check_index(arr,first(),\"function 'main'\")]"));
+    assert!(output.contains("return arr[0]"));
+
+    let options = BuildOptions {
+        release: true,
+        ..BuildOptions::default()
+    };
+    let output = compile_with_options(source, Target::JS, options).unwrap();
+    assert!(output.contains("arr[first()]"));
+}
+
+#[test]
+fn test_compile_reports_errors() {
+    let diagnostics = compile("fn main() {", Target::JS).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+}
+
+#[test]
+fn test_compile_with_denied_warnings() {
+    let source = "fn main() { let x = 1 }";
+    let options = BuildOptions {
+        warnings: WarningOptions::from_flags(&[], &[], true).unwrap(),
+        ..BuildOptions::default()
+    };
+    let diagnostics = compile_with_options(source, Target::C, options).unwrap_err();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].lint.as_deref(), Some("unused-variable"));
+    assert_eq!(diagnostics[1].message, "Aborting due to 1 denied warning");
+}
+
+#[test]
+fn test_check() {
+    let diagnostics = check("fn main() { let x = 1 }");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert!(check("fn main() { println(1) }").is_empty());
+}
+
+#[test]
+fn test_build_from_memory() {
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source("import \"lib/math\"\nfn main() { println(add(1, 2)) }".into())
+        .with_file(
+            "playground/lib/math.sb".into(),
+            "pub fn add(x: int, y: int): int { return x + y }".into(),
+        );
+    let mut buffer = Box::new(Vec::new());
+    b.build(&Target::C).unwrap();
+    b.generate(&Target::C, &mut buffer).unwrap();
+    let output = String::from_utf8(*buffer).unwrap();
+    assert!(output.contains("int add(int x, int y)"));
+}
+
+#[test]
+fn test_build_with_prelude() {
+    let mut b = Builder::new(
+        "playground/main.sb".into(),
+        BuildOptions {
+            prelude: Some("playground/prelude.sb".into()),
+            ..BuildOptions::default()
+        },
+    )
+    .with_source("fn main() { greet() }".into())
+    .with_file(
+        "playground/prelude.sb".into(),
+        "pub fn greet() { println(\"Hello\") }".into(),
+    );
+    let mut buffer = Box::new(Vec::new());
+    b.build(&Target::JS).unwrap();
+    b.generate(&Target::JS, &mut buffer).unwrap();
+    let output = String::from_utf8(*buffer).unwrap();
+    assert!(output.contains("function greet()"));
+    assert!(output.contains("function println(msg)"));
+}
+
+#[test]
+fn test_circular_imports_are_reported() {
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source("import \"a\"\nfn main() { a() }".into())
+        .with_file(
+            "playground/a.sb".into(),
+            "import \"lib/b\"\npub fn a() { b() }".into(),
+        )
+        .with_file(
+            "playground/lib/b.sb".into(),
+            "pub fn b() {}\nimport \"../a\"".into(),
+        );
+    let error = b.build(&Target::JS).unwrap_err();
+    assert_eq!(
+        error,
+        "Circular import: playground/a.sb -> playground/lib/b.sb -> playground/a.sb
+ --> playground/a.sb:1:5
+   1 | import \"lib/b\"
+     |     ^
+ --> playground/lib/b.sb:2:6
+   2 | import \"../a\"
+     |      ^"
+    );
+
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source("import \"main\"\nfn main() {}".into());
+    let error = b.build(&Target::JS).unwrap_err();
+    assert!(error.starts_with("Circular import: playground/main.sb -> playground/main.sb\n"));
+}
+
+#[test]
+fn test_modules_imported_twice_are_built_once() {
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source("import \"a\"\nimport \"lib/b\"\nfn main() { a() b() }".into())
+        .with_file(
+            "playground/a.sb".into(),
+            "import \"lib/util\"\npub fn a() { util() }".into(),
+        )
+        .with_file(
+            "playground/lib/b.sb".into(),
+            "import \"util\"\npub fn b() { util() }".into(),
+        )
+        .with_file("playground/lib/util.sb".into(), "pub fn util() {}".into());
+    let mut buffer = Box::new(Vec::new());
+    b.build(&Target::JS).unwrap();
+    b.generate(&Target::JS, &mut buffer).unwrap();
+    let output = String::from_utf8(*buffer).unwrap();
+    assert_eq!(output.matches("function util()").count(), 1);
+}
+
+#[test]
+fn test_embed_files() {
+    let build = |target: &Target| {
+        let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+            .with_source("import \"lib/data\"\nfn main() { print(greeting()) }".into())
+            // Files are resolved relative to the module that embeds them
+            .with_file(
+                "playground/lib/data.sb".into(),
+                "pub fn greeting(): string { return include_str!(\"../assets/greeting.txt\") }"
+                    .into(),
+            )
+            .with_file(
+                "playground/assets/greeting.txt".into(),
+                "Hello \"world\"\n\\o/\n".into(),
+            );
+        let mut buffer = Box::new(Vec::new());
+        b.build(target).unwrap();
+        b.generate(target, &mut buffer).unwrap();
+        String::from_utf8(*buffer).unwrap()
+    };
+    assert!(build(&Target::JS).contains("return \"Hello \\\"world\\\"\\n\\\\o/\\n\";"));
+    assert!(build(&Target::Qbe)
+        .contains("= { b \"Hello \", b 34, b \"world\", b 34, b 10, b 92, b \"o/\", b 10, b 0 }"));
+
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source("fn main() {\n    println(include_str!(\"missing.txt\"))\n}".into());
+    let error = b.build(&Target::JS).unwrap_err();
+    assert!(error.starts_with("playground/main.sb:2: Could not embed file playground/missing.txt"));
+}
+
+#[test]
+fn test_compile_without_prelude() {
+    let source = "fn main() { _printf(_bool_to_str(true)) }";
+    let options = BuildOptions {
+        no_prelude: true,
+        keep_all: true,
+        ..BuildOptions::default()
+    };
+    let output = compile_with_options(source, Target::JS, options).unwrap();
+    assert!(!output.contains("function println("));
+    // The compiler relies on the builtins, so they are kept
+    assert!(output.contains("function _bool_to_str(b)"));
+}
+
+#[test]
+fn test_compile_inline_code() {
+    let source = "
+    fn main() {
+        let name = \"world\"
+        js! {
+            console.log(`Hello ${name}`)
+        }
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("var name = \"world\";\nconsole.log(`Hello ${name}`);"));
+
+    let errors = compile(source, Target::Qbe).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Function 'main' contains a 'js!' block, which can not be compiled to qbe"
+    );
+}
+
+#[test]
+fn test_compile_builtins_written_in_antimony() {
+    let source = "
+    fn main() {
+        _printf(_bool_to_str(_sqrt(16) == 4))
+    }
+    ";
+    let js = compile(source, Target::JS).unwrap();
+    assert!(js.contains("function _bool_to_str(b)"));
+    assert!(js.contains("function _sqrt(n)"));
+
+    let c = compile(source, Target::C).unwrap();
+    assert!(c.contains("char * _bool_to_str(bool b) {"));
+    assert!(c.contains("int _sqrt(int n) {"));
+    if !super::test_golden::installed("node") {
+        return;
+    }
+
+    // Integers of JavaScript have roots beyond those of 32-bit ints
+    let source = "
+    fn main() {
+        println(_sqrt(10000000000))
+        println(_sqrt(9007199254740991))
+        println(_sqrt(2147483647))
+    }
+    ";
+    let output = super::test_golden::run_js(&compile(source, Target::JS).unwrap());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "100000\n94906265\n46340\n"
+    );
+}
+
+#[test]
+fn test_compile_unsafe_code() {
+    let source = "
+    extern \"C\" fn malloc(size: int): ptr
+
+    fn main() {
+        let p = malloc(8)
+        unsafe {
+            store<int>(p, 1)
+        }
+    }
+    ";
+    let output = compile(source, Target::C).unwrap();
+    assert!(output.contains("(*(int *)(p) = 1)"));
+
+    let errors = compile(source, Target::JS).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Function 'main' contains an unsafe block, which can not be compiled to js"
+    );
+}
+
+#[test]
+fn test_compile_selects_code_of_target() {
+    let source = "
+    @cfg(target = \"js\")
+    fn backend(): string {
+        return \"js\"
+    }
+
+    @cfg(target = \"qbe\")
+    fn backend(): string {
+        return \"qbe\"
+    }
+
+    fn main() {
+        println(backend())
+        if target_js {
+            js! { console.log(1) }
+        } else {
+            println(2)
+        }
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("return \"js\""));
+    assert!(!output.contains("return \"qbe\""));
+    assert!(output.contains("console.log(1)"));
+
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("data $string.backend.1 = { b \"qbe\", b 0 }"));
+    assert!(!output.contains("console.log"));
+
+    let errors = compile("@cfg(target = \"go\")\nfn main() {}", Target::JS).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Unknown target 'go' in @cfg of function 'main'"
+    );
+}
+
+#[test]
+fn test_compile_checks_attributes() {
+    let source = "
+    @export
+    fn api(): int {
+        return 1
+    }
+
+    fn unused(): int {
+        return 2
+    }
+
+    fn main() {}
+    ";
+    let options = BuildOptions {
+        opt_level: 1,
+        ..BuildOptions::default()
+    };
+    let output = compile_with_options(source, Target::JS, options).unwrap();
+    assert!(output.contains("function api()"));
+    assert!(!output.contains("function unused()"));
+
+    for (source, error) in [
+        (
+            "@unknown\nfn main() {}",
+            "Unknown attribute @unknown on function 'main'",
+        ),
+        (
+            "@test\nstruct Foo {}\nfn main() {}",
+            "Attribute @test on struct 'Foo' is not allowed, it can only be applied to: function",
+        ),
+        (
+            "@inline(\"always\")\nfn main() {}",
+            "Attribute @inline on function 'main' takes no positional argument",
+        ),
+        (
+            "@cfg(arch = \"x86\")\nfn main() {}",
+            "Unknown argument 'arch' of attribute @cfg on function 'main', expected 'target'",
+        ),
+    ] {
+        let errors = compile(source, Target::JS).unwrap_err();
+        assert_eq!(errors.last().unwrap().message, error);
+    }
+}
+
+#[test]
+fn test_compile_derives_json() {
+    let source = "
+    @derive(json)
+    struct Point {
+        x: int
+        y: int = 0
+    }
+
+    @derive(json)
+    struct Line {
+        points: Point[]
+    }
+
+    fn main() {
+        let line = new Line { points: [new Point { x: 1 }] }
+        let json = json_stringify(Line_to_json(line))
+        let parsed = Line_from_json(json_parse(json).value()).value()
+        println(parsed.points.len())
+    }
+    ";
+    for target in [Target::JS, Target::Qbe] {
+        let output = compile(source, target).unwrap();
+        for function in [
+            "Line_to_json",
+            "Line_from_json",
+            "Point_to_json",
+            "Point_from_json",
+        ] {
+            assert!(output.contains(function), "{} is missing", function);
+        }
+    }
+
+    for (source, error) in [
+        (
+            "@derive(yaml)\nstruct A {}\nfn main() {}",
+            "Unknown derive 'yaml' on struct 'A', expected one of 'json', 'eq', 'show'",
+        ),
+        (
+            "struct B {}\n@derive(json)\nstruct A {\n b: B\n}\nfn main() {}",
+            "Field 'b' of struct 'A' has type 'B', which does not derive json. Annotate struct 'B' with @derive(json)",
+        ),
+        (
+            "@derive(json)\nstruct A {\n m: map<string, int>\n}\nfn main() {}",
+            "Field 'm' of struct 'A' has type 'map<string, int>', which can not be converted to JSON",
+        ),
+        (
+            "@derive(json)\nstruct A {}\nfn A_to_json(a: A) {}\nfn main() {}",
+            "Function 'A_to_json' is already declared, but is generated by @derive(json)",
+        ),
+    ] {
+        let errors = compile(source, Target::JS).unwrap_err();
+        assert_eq!(errors.last().unwrap().message, error);
+    }
+
+    let options = BuildOptions {
+        no_prelude: true,
+        ..BuildOptions::default()
+    };
+    let errors = compile_with_options(
+        "@derive(json)\nstruct A {}\nfn main() {}",
+        Target::JS,
+        options,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "@derive(json) on struct 'A' needs the json module of the standard library, which is not available with --no-prelude"
+    );
+}
+
+#[test]
+fn test_compile_derives_eq_and_show() {
+    let source = "
+    @derive(eq, show)
+    struct Point {
+        x: int
+        y: int
+    }
+
+    fn main() {
+        let a = new Point { x: 1 y: 2 }
+        let b = new Point { x: 1 y: 2 }
+        if a == b {
+            println(a)
+        }
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("Point_eq(a, b)"));
+    assert!(output.contains("println(Point_to_string(a))"));
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("$Point_eq"));
+    assert!(output.contains("$Point_to_string"));
+
+    for (source, error) in [
+        (
+            "@derive(eq)\nstruct A {\n m: map<string, int>\n}\nfn main() {}",
+            "Field 'm' of struct 'A' has type 'map<string, int>', which can not be compared",
+        ),
+        (
+            "struct B {}\n@derive(show)\nstruct A {\n b: B\n}\nfn main() {}",
+            "Field 'b' of struct 'A' has type 'B', which does not derive show. Annotate struct 'B' with @derive(show)",
+        ),
+        (
+            "@derive(eq)\nstruct A {}\nfn main() {\n let a = new A {}\n println(a == 1)\n}",
+            "Cannot compare A with int in function 'main'",
+        ),
+        (
+            "@derive(eq, show, json, eq)\nstruct A {}\nfn main() {}",
+            "Attribute @derive on struct 'A' takes at most 3 positional arguments",
+        ),
+    ] {
+        let errors = compile(source, Target::JS).unwrap_err();
+        assert_eq!(errors.last().unwrap().message, error);
+    }
+}
+
+#[test]
+fn test_compile_iteration_protocol() {
+    let source = "
+    struct Range {
+        start: int
+        end: int
+    }
+
+    fn Range_len(range: Range): int {
+        return range.end - range.start
+    }
+
+    fn Range_get(range: Range, index: int): int {
+        return range.start + index
+    }
+
+    fn main() {
+        let range = new Range { start: 2 end: 5 }
+        for n in range {
+            println(n)
+        }
+        println(len(range))
+    }
+    ";
+    for target in [Target::JS, Target::Qbe, Target::C] {
+        let output = compile(source, target).unwrap();
+        assert!(output.contains("Range_get"), "Range_get is not called");
+    }
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("while (index_1 < Range_len(range))"));
+    assert!(output.contains("println(Range_len(range))"));
+
+    // The functions of another module are mangled in QBE output
+    let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+        .with_source(
+            "import \"lib/range\"\nfn main() {\n for n in new Range { start: 0 end: 2 } {\n println(n)\n }\n}".into(),
+        )
+        .with_file(
+            "playground/lib/range.sb".into(),
+            source[..source.find("fn main").unwrap()]
+                .replace("fn ", "pub fn ")
+                .replace("struct", "pub struct"),
+        );
+    let mut buffer = Box::new(Vec::new());
+    b.build(&Target::Qbe)
+        .and_then(|_| b.generate(&Target::Qbe, &mut buffer))
+        .unwrap();
+    let output = String::from_utf8(*buffer).unwrap();
+    assert!(output.contains("call $lib.range.Range_get("));
+
+    for (source, error) in [
+        (
+            "struct A {}\nfn main() {\n for x in new A {} {\n println(x)\n }\n}",
+            "Struct 'A' can not be iterated. Declare the functions 'A_len(value: A): int' and 'A_get(value: A, index: int)' in function 'main'",
+        ),
+        (
+            "struct A {}\nfn main() {\n println(len(new A {}))\n}",
+            "Struct 'A' has no length. Declare the function 'A_len(value: A): int' in function 'main'",
+        ),
+        (
+            "fn main() {\n println(len(1))\n}",
+            "int has no length in function 'main'",
+        ),
+    ] {
+        let errors = compile(source, Target::JS).unwrap_err();
+        assert_eq!(errors.last().unwrap().message, error);
+    }
+}
+
+#[test]
+fn test_private_declarations_are_not_visible_to_other_modules() {
+    let build = |math: &str| {
+        let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+            .with_source("import \"math\"\nfn main() { println(add(1, 2)) }".into())
+            .with_file("playground/math.sb".into(), math.into());
+        let mut buffer = Box::new(Vec::new());
+        b.build(&Target::JS)
+            .and_then(|_| b.generate(&Target::JS, &mut buffer))
+            .map(|_| String::from_utf8(*buffer).unwrap())
+    };
+
+    let error = build("fn add(x: int, y: int): int { return x + y }").unwrap_err();
+    assert_eq!(
+        error,
+        "Function 'add' is private to module 'playground/math.sb' and can not be used by module 'playground/main.sb'. Declare it with `pub` to use it in other modules"
+    );
+
+    let output = build("pub fn add(x: int, y: int): int { return x + y }").unwrap();
+    assert!(output.contains("module.exports = { add };"));
+}
+
+#[test]
+fn test_qbe_symbols_are_qualified_with_their_module() {
+    let build = |math: &str| {
+        let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+            .with_source(
+                "import \"lib/math\"\nfn helper(): int { return 1 }\nfn main() { println(helper() + add(2, 3)) }"
+                    .into(),
+            )
+            .with_file("playground/lib/math.sb".into(), math.into());
+        let mut buffer = Box::new(Vec::new());
+        b.build(&Target::Qbe)
+            .and_then(|_| b.generate(&Target::Qbe, &mut buffer))
+            .map(|_| String::from_utf8(*buffer).unwrap())
+    };
+
+    let output = build(
+        "fn helper(): int { return 10 }
+        pub fn add(x: int, y: int): int { return x + y + helper() }
+        @no_mangle
+        pub fn c_add(x: int, y: int): int { return add(x, y) }",
+    )
+    .unwrap();
+    assert!(output.contains("\nfunction w $helper() {"));
+    assert!(output.contains("function w $lib.math.helper() {"));
+    assert!(output.contains("export function w $lib.math.add("));
+    assert!(output.contains("export function w $c_add("));
+    assert!(output.contains("call $helper()"));
+    assert!(output.contains("call $lib.math.helper()"));
+    assert!(output.contains("call $lib.math.add("));
+    assert!(output.contains("call $println("));
+}
+
+#[test]
+fn test_import_aliases() {
+    let build = |main: &str, target: Target| {
+        let greeter = |name: &str| {
+            format!(
+                "fn name(): string {{ return \"{}\" }}\npub fn greet(): string {{ return \"Hello from \" + name() }}",
+                name
+            )
+        };
+        let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+            .with_source(main.into())
+            .with_file("playground/en.sb".into(), greeter("en"))
+            .with_file("playground/lib/de.sb".into(), greeter("de"));
+        let mut buffer = Box::new(Vec::new());
+        b.build(&target)
+            .and_then(|_| b.generate(&target, &mut buffer))
+            .map(|_| String::from_utf8(*buffer).unwrap())
+    };
+    let imports = "import \"en\" as en\nimport \"lib/de\" as de\n";
+
+    let main = format!(
+        "{}fn main() {{ println(en::greet()) println(de::greet()) }}",
+        imports
+    );
+    let output = build(&main, Target::JS).unwrap();
+    assert!(output.contains("function en_name()"));
+    assert!(output.contains("function lib_de_name()"));
+    assert!(output.contains("println(en_greet());\nprintln(lib_de_greet());"));
+    let output = build(&main, Target::Qbe).unwrap();
+    assert!(output.contains("function l $lib.de.name() {"));
+    assert!(output.contains("call $lib.de.greet()"));
+
+    let errors = [
+        (
+            "greet()",
+            "Function 'greet' called in module 'playground/main.sb' is declared by more than one module, call it through the alias of an import",
+        ),
+        ("fr::greet()", "Unknown import alias 'fr' in module 'playground/main.sb'"),
+        (
+            "en::hello()",
+            "Module 'playground/en.sb' does not declare a function 'hello'",
+        ),
+        (
+            "en::name()",
+            "Function 'name' is private to module 'playground/en.sb' and can not be used by module 'playground/main.sb'. Declare it with `pub` to use it in other modules",
+        ),
+    ];
+    for (call, error) in errors {
+        let main = format!("{}fn main() {{ println({}) }}", imports, call);
+        assert_eq!(build(&main, Target::JS).unwrap_err(), error);
+    }
+}
+
+#[test]
+fn test_selective_imports() {
+    let build = |main: &str| {
+        let greeter = |name: &str| {
+            format!(
+                "fn name(): string {{ return \"{}\" }}\npub fn greet(): string {{ return \"Hello from \" + name() }}\npub struct Greeting {{ text: string }}",
+                name
+            )
+        };
+        let mut b = Builder::new("playground/main.sb".into(), BuildOptions::default())
+            .with_source(main.into())
+            .with_file("playground/en.sb".into(), greeter("en"))
+            .with_file("playground/de.sb".into(), greeter("de"));
+        let mut buffer = Box::new(Vec::new());
+        b.build(&Target::JS)
+            .and_then(|_| b.generate(&Target::JS, &mut buffer))
+            .map(|_| String::from_utf8(*buffer).unwrap())
+    };
+
+    // The selected function is used instead of the one of the other module
+    let output =
+        build("import { greet } from \"en\"\nimport \"de\"\nfn main() { println(greet()) }")
+            .unwrap();
+    assert!(output.contains("println(en_greet());"));
+
+    let errors = [
+        (
+            "import { hello } from \"en\"",
+            "Module 'playground/en.sb' does not declare 'hello', which is imported by module 'playground/main.sb'",
+        ),
+        (
+            "import { name } from \"en\"",
+            "Function 'name' is private to module 'playground/en.sb' and can not be used by module 'playground/main.sb'. Declare it with `pub` to use it in other modules",
+        ),
+    ];
+    for (import, error) in errors {
+        let main = format!("{}\nfn main() {{ println(1) }}", import);
+        assert_eq!(build(&main).unwrap_err(), error);
+    }
+}
+
+#[test]
+fn test_compile_js_module_formats() {
+    let source = "pub fn add(x: int, y: int): int { return x + y }";
+    let compile_as = |module_format| {
+        let options = BuildOptions {
+            module_format,
+            ..BuildOptions::default()
+        };
+        compile_with_options(source, Target::JS, options).unwrap()
+    };
+
+    let cjs = compile_as(ModuleFormat::Cjs);
+    assert!(cjs.ends_with("module.exports = { add };\n"));
+
+    let esm = compile_as(ModuleFormat::Esm);
+    assert!(esm.starts_with("import { createRequire } from \"module\";"));
+    assert!(esm.ends_with("export { add };\n"));
+
+    let iife = compile_as(ModuleFormat::Iife);
+    assert!(iife.starts_with("(function () {\n"));
+    assert!(iife.contains("Object.assign(globalThis, { add });"));
+    assert!(iife.ends_with("})();\n"));
+}
+
+#[test]
+fn test_compile_for_architectures() {
+    let source = "fn main() { println(\"Hello\") }";
+    let compile_for = |target, arch| {
+        let options = BuildOptions {
+            arch: Some(arch),
+            ..BuildOptions::default()
+        };
+        compile_with_options(source, target, options)
+    };
+
+    assert!(compile_for(Target::Qbe, Arch::Riscv64).is_ok());
+    assert!(compile_for(Target::X86, Arch::Amd64).is_ok());
+    let errors = compile_for(Target::X86, Arch::Arm64).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "The x86 target can not compile for arm64, only for amd64"
+    );
+}
+
+#[test]
+fn test_compile_js_runtimes() {
+    let source = "fn main(): int { println(\"Hello\") return 0 }";
+    let compile_for = |js_runtime: JsRuntime| {
+        let options = BuildOptions {
+            module_format: js_runtime.default_module_format(),
+            js_runtime,
+            ..BuildOptions::default()
+        };
+        compile_with_options(source, Target::JS, options).unwrap()
+    };
+
+    let node = compile_for(JsRuntime::Node);
+    assert!(node.contains("process.stdout.write("));
+    assert!(node.ends_with("process.exitCode = main();"));
+
+    let deno = compile_for(JsRuntime::Deno);
+    assert!(deno.contains("Deno.stdout"));
+    assert!(!deno.contains("process."));
+    assert!(!deno.contains("require("));
+    assert!(deno.ends_with("Deno.exitCode = main();"));
+
+    let browser = compile_for(JsRuntime::Browser);
+    assert!(browser.starts_with("(function () {\n"));
+    assert!(browser.contains("console.log("));
+    assert!(!browser.contains("process."));
+    assert!(!browser.contains("require("));
+
+    let options = BuildOptions {
+        js_runtime: JsRuntime::Deno,
+        ..BuildOptions::default()
+    };
+    let errors = compile_with_options(source, Target::JS, options).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "The cjs module format is not supported by the deno runtime, use esm or iife"
+    );
+}
+
+#[test]
+fn test_compile_js_includes_only_used_stdlib_functions() {
+    let source = "
+    fn main() {
+        assert(max(1, 2) == 2)
+        println(\"Hello\")
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    for used in [
+        "function println(",
+        "function max(",
+        "function _printf(",
+        "function _exit(",
+    ] {
+        assert!(output.contains(used), "{} is missing", used);
+    }
+    for unused in [
+        "function read_file(",
+        "function min(",
+        "function _read_file(",
+        "function _sqrt(",
+    ] {
+        assert!(!output.contains(unused), "{} is included", unused);
+    }
+}
+
+#[test]
+fn test_compile_json_uses_backend_parser() {
+    let source = "
+    fn main() {
+        let value = json_parse(\"[1, 2]\")
+        println(json_stringify(value.value()))
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("JSON.parse(text)"));
+    assert!(!output.contains("function _json_parse_value("));
+
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("function l $json_parse(l "));
+    assert!(output.contains("function l $_json_parse_value(l "));
+}
+
+#[test]
+fn test_compile_escapes_quotes_in_strings() {
+    let source = "fn main() { println(\"say \\\"hi\\\"\") }";
+    for target in [Target::JS, Target::C] {
+        let output = compile(source, target).unwrap();
+        assert!(output.contains("\"say \\\"hi\\\"\""));
+    }
+}
+
+#[test]
+fn test_compile_utf8_strings() {
+    let source = "
+    fn main() {
+        let s = \"αβ\"
+        for c in s {
+            println(c)
+        }
+        println(s.chars().len())
+    }
+    ";
+    for target in [Target::JS, Target::Qbe] {
+        let output = compile(source, target).unwrap();
+        assert!(output.contains("_str_chars("));
+    }
+
+    let errors = compile(
+        "fn main() {\n let s = \"abc\"\n println(s[0])\n}",
+        Target::JS,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "String 's' can not be indexed, use chars() or substring() instead in function 'main'"
+    );
+
+    let dir = std::env::temp_dir().join("sb_test_compile_utf8_strings");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.sb");
+    std::fs::write(&path, b"fn main() {\n    println(\"\xff\")\n}\n").unwrap();
+    let error = Builder::new(path.clone(), BuildOptions::default())
+        .build(&Target::JS)
+        .unwrap_err();
+    assert_eq!(
+        error,
+        format!("{}:2: File is not valid UTF-8", path.display())
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_compile_regex() {
+    let source = "
+    fn main() {
+        let m = regex_find(\"(\\\\d+)-(\\\\d+)\", \"tel 12-345\")
+        println(m.text)
+        println(regex_replace(\"\\\\s+\", \"a  b\", \" \"))
+    }
+    ";
+    for target in [Target::JS, Target::Qbe] {
+        let output = compile(source, target).unwrap();
+        assert!(output.contains("_regex_find("));
+    }
+
+    let errors = compile(
+        "fn main() {\n println(regex_matches(\"(a|b\", \"a\"))\n}",
+        Target::JS,
+    )
+    .unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Invalid regular expression '(a|b': Unterminated group in function 'main'"
+    );
+}
+
+#[test]
+fn test_compile_http_get() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let source = format!(
+        "
+    fn main() {{
+        let response = http_get(\"http://127.0.0.1:{port}/greeting\").value()
+        println(response.status)
+        println(http_header(response, \"content-type\"))
+        println(response.body)
+        println(tcp_connect(\"127.0.0.1\", {port}).is_error())
+        println(http_get(\"https://example.com\").error())
+    }}
+    "
+    );
+    for target in [Target::JS, Target::Qbe] {
+        let output = compile(&source, target).unwrap();
+        assert!(output.contains("_tcp_connect("));
+    }
+    if !super::test_golden::installed("node") {
+        return;
+    }
+
+    // Answers the request of `http_get`, and splits the response inside of a character
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // Later connections are refused
+        drop(listener);
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        let response = "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nGrüße".as_bytes();
+        let split = response.len() - 4;
+        stream.write_all(&response[..split]).unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        stream.write_all(&response[split..]).unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    let js = compile(&source, Target::JS).unwrap();
+    let output = super::test_golden::run_js(&js);
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with(&format!(
+        "GET /greeting HTTP/1.0\r\nHost: 127.0.0.1:{port}\r\n"
+    )));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "200\ntext/plain\nGrüße\ntrue\nCould not get 'https://example.com': https is not supported\n"
+    );
+}
+
+#[test]
+fn test_compile_exec() {
+    let source = "
+    fn main() {
+        let result = exec(\"ls\", [\"-l\"])
+        println(result.exit_code)
+    }
+    ";
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("=w call $_exec(l "));
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("function _exec(cmd, args, output)"));
+    assert!(output.contains("spawnSync"));
+}
+
+#[test]
+fn test_compile_spawn() {
+    let source = "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    fn show(p: Point, ch: chan<int>) {
+        println(p.x)
+        ch.send(p.y)
+    }
+
+    fn main() {
+        let ch: chan<int> = channel()
+        let p = new Point { x: 1 y: 2 }
+        spawn show(p, ch)
+        p.x = 3
+        spawn show(p, ch)
+        println(ch.receive().value())
+        spawn show(p, ch)
+        println(\"end\")
+    }
+    ";
+    // Each spawned call is run by a function that reads the copied arguments
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("call $_spawn(l $_sb_task.main.1, l "));
+    assert!(output.contains("function $_sb_task.main.3(l %env) {"));
+    assert!(output.contains("call $show(:struct.1 %ptr.1, l %arg.2)"));
+    assert!(output.contains("call $_sb_main()\n\tcall $_run_tasks()"));
+
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("_spawn(show, [p, ch]);"));
+    assert!(output.contains("main();\n_run_tasks();"));
+    if !super::test_golden::installed("node") {
+        return;
+    }
+    // Receiving runs tasks until a value arrives, the others run after main has returned
+    let output = super::test_golden::run_js(&output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\nend\n3\n3\n");
+
+    // Programs that do not spawn do not run the tasks
+    let output = compile("fn main() { println(1) }", Target::Qbe).unwrap();
+    assert!(!output.contains("_run_tasks"));
+}
+
+#[test]
+fn test_compile_sync() {
+    let source = "
+    fn add(counter: Atomic, lock: Mutex, n: int) {
+        mutex_lock(lock)
+        atomic_add(counter, n)
+        mutex_unlock(lock)
+    }
+
+    fn main() {
+        let counter = atomic(0)
+        let lock = mutex()
+        spawn add(counter, lock, 2)
+        add(counter, lock, 1)
+        println(atomic_load(counter))
+    }
+    ";
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("=l call $_atomic_new(w "));
+    assert!(output.contains("=w call $_atomic_add(l "));
+    assert!(output.contains("call $_mutex_lock(l "));
+
+    // JavaScript has no shared memory, so the builtins are not available through other functions either
+    let errors = compile(source, Target::JS).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Function 'add' calls 'mutex_lock', which needs the native runtime and is not available for target js"
+    );
+
+    // Functions that are only compiled for native targets can use them
+    let source = "
+    @cfg(target = \"qbe\")
+    fn count(): int {
+        let counter = atomic(1)
+        return atomic_load(counter)
+    }
+
+    fn main() {
+        if target_js {
+            println(1)
+        } else {
+            println(count())
+        }
+    }
+    ";
+    assert!(compile(source, Target::JS).is_ok());
+}
+
+#[test]
+fn test_compile_comptime() {
+    let source = "
+    @comptime
+    fn squares(n: int): int[] {
+        let result: int[] = []
+        let i = 0
+        while i < n {
+            result.push(i * i)
+            i += 1
+        }
+        return result
+    }
+
+    @comptime
+    fn fib(n: int): int {
+        if n < 2 {
+            return n
+        }
+        return fib(n - 1) + fib(n - 2)
+    }
+
+    const SQUARES = squares(4)
+
+    fn main() {
+        let n = 10
+        println(fib(20))
+        println(fib(n))
+        println(fib(3) - fib(6))
+    }
+    ";
+    // Calls with constant arguments are replaced by their result, others are left to runtime
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("const SQUARES = [0, 1, 4, 9];"));
+    assert!(output.contains("println(6765);"));
+    assert!(output.contains("println(fib(n));"));
+    assert!(output.contains("println(2 - 8);"));
+    assert!(!output.contains("function squares("));
+
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("w 6765"));
+    assert!(output.contains("call $fib(w %tmp."));
+
+    let error = |body: &str| {
+        let source = format!(
+            "
+            @comptime
+            fn f(n: int): int {{
+                {}
+            }}
+
+            fn main() {{
+                println(f(3))
+            }}
+            ",
+            body
+        );
+        let errors = compile(&source, Target::JS).unwrap_err();
+        errors.last().unwrap().message.clone()
+    };
+    assert_eq!(
+        error("while true { n += 1 }\n return n"),
+        "Could not evaluate the call of 'f' at compile time: exceeded the limit of 1000000 steps"
+    );
+    assert_eq!(
+        error("return f(n + 1)"),
+        "Could not evaluate the call of 'f' at compile time: exceeded the limit of 200 nested calls"
+    );
+    assert_eq!(
+        error("return n / (n - 3)"),
+        "Could not evaluate the call of 'f' at compile time: attempt to divide by zero"
+    );
+    assert_eq!(
+        error("return n * 1000000000"),
+        "Could not evaluate the call of 'f' at compile time: attempt to multiply with overflow"
+    );
+    assert_eq!(
+        error("let values = [1, 2]\n return values[n]"),
+        "Could not evaluate the call of 'f' at compile time: index out of bounds: the length is 2 but the index is 3"
+    );
+}
+
+#[test]
+fn test_compile_removes_unused_declarations() {
+    let source = "
+    struct Point {
+        x: int
+        y: int
+    }
+
+    struct Line {
+        from: Point
+        to: Point
+    }
+
+    struct Unused {
+        name: string
+    }
+
+    fn scale(n: int): int {
+        return n * 2
+    }
+
+    fn norm(p: Point): int {
+        return scale(p.x)
+    }
+
+    fn helper(): Line {
+        return new Line { from: new Point { x: 0 y: 0 } to: new Point { x: 1 y: 1 } }
+    }
+
+    fn main() {
+        let line = new Line { from: new Point { x: 0 y: 0 } to: new Point { x: 1 y: 1 } }
+        println(norm(line.to))
+    }
+    ";
+    let output = compile(source, Target::JS).unwrap();
+    assert!(output.contains("function Point("));
+    assert!(output.contains("function Line("));
+    assert!(output.contains("function scale("));
+    assert!(!output.contains("Unused"));
+    assert!(!output.contains("helper"));
+    assert!(!output.contains("function read_file("));
+
+    // Structs of the QBE backend are numbered in declaration order
+    let output = compile(source, Target::Qbe).unwrap();
+    assert!(output.contains("type :struct.2 = { :struct.1, :struct.1 }"));
+    assert!(!output.contains(":struct.3"));
+    assert!(output.contains("function w $scale("));
+    assert!(!output.contains("helper"));
+
+    let options = BuildOptions {
+        keep_all: true,
+        ..BuildOptions::default()
+    };
+    let output = compile_with_options(source, Target::JS, options).unwrap();
+    assert!(output.contains("function Unused("));
+    assert!(output.contains("function helper("));
+    assert!(output.contains("function read_file("));
+}
+
+#[test]
+fn test_compile_js_imports() {
+    let source = "
+    import js \"path\" {
+        fn basename(path: string): string
+        fn extname(path: string): string
+    }
+
+    fn main() {
+        if target_js {
+            println(basename(\"/tmp/file.txt\"))
+        }
+    }
+    ";
+    let compile_as = |module_format| {
+        let options = BuildOptions {
+            module_format,
+            ..BuildOptions::default()
+        };
+        compile_with_options(source, Target::JS, options)
+    };
+
+    let cjs = compile_as(ModuleFormat::Cjs).unwrap();
+    assert!(cjs.contains("const { basename, extname } = require(\"path\");\n"));
+    let esm = compile_as(ModuleFormat::Esm).unwrap();
+    assert!(esm.contains("import { basename, extname } from \"path\";\n"));
+    assert!(compile_as(ModuleFormat::Iife).is_err());
+
+    // Calls limited to JavaScript do not prevent compiling to other targets
+    assert!(compile_with_options(source, Target::C, BuildOptions::default()).is_ok());
+    let errors = compile(&source.replace("if target_js", "if true"), Target::C).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Function 'basename' of JavaScript module 'path' is not available for target c"
+    );
+}
+
+#[test]
+fn test_compile_async() {
+    let source = "
+    import js \"timers/promises\" {
+        async fn setTimeout(ms: int, value: string): string
+    }
+
+    async fn delayed(value: string): string {
+        if target_js {
+            return await setTimeout(10, value)
+        }
+        return value
+    }
+
+    async fn main(): int {
+        println(await delayed(\"later\").len())
+        return 3
+    }
+    ";
+    let js = compile(source, Target::JS).unwrap();
+    assert!(js.contains("async function delayed(value)"));
+    assert!(js.contains("println(_str_len((await delayed(\"later\"))));"));
+    assert!(js.contains("main().then((code) => {\n  process.exitCode = code;\n});"));
+    if super::test_golden::installed("node") {
+        let output = super::test_golden::run_js(&js);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "5\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    // Other backends call async functions like any other function
+    let qbe = compile(source, Target::Qbe).unwrap();
+    assert!(qbe.contains("=l call $delayed(l $string.main.1)"));
+
+    // Async tests are awaited by the test harness
+    let options = BuildOptions {
+        test: true,
+        ..BuildOptions::default()
+    };
+    let source = "
+    async fn answer(): int {
+        return 42
+    }
+
+    @test
+    async fn test_answer() {
+        assert(await answer() == 42)
+    }
+    ";
+    let js = compile_with_options(source, Target::JS, options).unwrap();
+    assert!(js.contains("async function main()"));
+    assert!(js.contains("(await test_answer());"));
+}
+
+#[test]
+fn test_generate_typescript_declarations() {
+    let source = "
+    struct Point {
+        x: int
+        y: int
+
+        fn norm(): int {
+            return self.x * self.x + self.y * self.y
+        }
+    }
+
+    pub fn origin(): Point {
+        return new Point { x: 0 y: 0 }
+    }
+
+    pub fn parse(s: string): int! {
+        return 1
+    }
+
+    pub async fn load(path: string): string {
+        return path
+    }
+
+    fn hidden(names: string[]) {}
+    ";
+    let options = BuildOptions {
+        declarations: true,
+        ..BuildOptions::default()
+    };
+    let mut b = Builder::new("lib.sb".into(), options).with_source(source.into());
+    b.build(&Target::JS).unwrap();
+    b.generate(&Target::JS, &mut Box::new(Vec::new())).unwrap();
+    assert_eq!(
+        b.declarations().unwrap(),
+        "type Result<T> = { ok: true; value: T } | { ok: false; error: string };
+export interface Point {
+    x: number;
+    y: number;
+    norm(): number;
+}
+export declare function origin(): Point;
+export declare function parse(s: string): Result<number>;
+export declare function load(path: string): Promise<string>;
+"
+    );
+}
+
+#[test]
+fn test_compile_bench_harness() {
+    let source = "
+    fn square(n: int): int {
+        return n * n
+    }
+
+    @bench
+    fn bench_square() {
+        square(12)
+    }
+
+    fn main() {}
+    ";
+    let options = BuildOptions {
+        bench: true,
+        ..BuildOptions::default()
+    };
+    for target in [Target::JS, Target::Qbe] {
+        let output = compile_with_options(source, target, options.clone()).unwrap();
+        assert!(output.contains("_now_micros"));
+        assert!(output.contains("bench bench_square "));
+    }
+
+    let options = BuildOptions {
+        bench: true,
+        ..BuildOptions::default()
+    };
+    let errors = compile_with_options("@bench\nfn b(n: int) {}", Target::JS, options).unwrap_err();
+    assert_eq!(
+        errors.last().unwrap().message,
+        "Benchmark function 'b' must not take any arguments"
+    );
+}
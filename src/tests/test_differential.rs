@@ -0,0 +1,80 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Runs every example compiled to JavaScript and to a native executable, and checks
+// that both print the same output and exit with the same code. Examples that one of
+// the backends can not compile are skipped. Requires node and QBE to be installed.
+use super::test_golden::{examples, generate, installed, run_js};
+use crate::driver::{self, LinkOptions};
+use crate::generator::{Arch, Target};
+use std::fs;
+use std::process::{Command, Output, Stdio};
+
+fn run_native(ir: &str) -> Output {
+    let executable = std::env::temp_dir().join(format!("antimony-diff-{}", std::process::id()));
+    driver::link(
+        ir.as_bytes(),
+        Arch::host(),
+        &executable,
+        &LinkOptions::default(),
+        None,
+    )
+    .unwrap();
+    let output = Command::new(&executable)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(&executable);
+    output
+}
+
+#[test]
+fn test_backends_agree() {
+    if !installed("node") || !installed("qbe") {
+        return;
+    }
+
+    let mut failures = Vec::new();
+    for example in examples() {
+        let source = fs::read_to_string(&example).unwrap();
+        let js = generate(&source, Target::JS, Arch::host());
+        let ir = generate(&source, Target::Qbe, Arch::host());
+        let (js, ir) = match (js, ir) {
+            (Some(js), Some(ir)) => (js, ir),
+            _ => continue,
+        };
+
+        let js = run_js(&js);
+        let native = run_native(&ir);
+        if js.status.code() != native.status.code() {
+            failures.push(format!(
+                "{}: exit code js {:?}, native {:?}",
+                example.display(),
+                js.status.code(),
+                native.status.code()
+            ));
+        }
+        if js.stdout != native.stdout {
+            failures.push(format!(
+                "{}: stdout differs\n--- js\n{}--- native\n{}",
+                example.display(),
+                String::from_utf8_lossy(&js.stdout),
+                String::from_utf8_lossy(&native.stdout)
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,154 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+// Compiles every example with every backend and compares the output against the
+// golden files in `examples/golden`. Programs compiled to JavaScript are also run
+// with node, if it is installed, and their stdout is compared as well. The runtime that
+// the C backend copies into its output is left out, so only the generated program is compared.
+//
+// Run the tests with `UPDATE_GOLDEN=1` to write the current output to the golden files.
+use crate::builder::BuildOptions;
+use crate::compile_with_options;
+use crate::generator::{Arch, Target};
+use std::fs;
+use std::io::Write;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const GOLDEN_DIR: &str = "examples/golden";
+
+/// Comments around the runtime that the C backend copies into every program
+const RUNTIME_START: &str = "/* START runtime */";
+const RUNTIME_END: &str = "/* END runtime */";
+
+/// Backends that are compiled without optional features
+const TARGETS: [Target; 4] = [Target::JS, Target::C, Target::Qbe, Target::X86];
+
+fn update_golden() -> bool {
+    std::env::var_os("UPDATE_GOLDEN").is_some()
+}
+
+pub(super) fn examples() -> Vec<PathBuf> {
+    let mut examples: Vec<PathBuf> = fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sb"))
+        .collect();
+    examples.sort();
+    examples
+}
+
+/// Output of a backend, or `None` if the backend does not support the program.
+/// Some backends panic on unsupported language features, so panics are caught as well.
+pub(super) fn generate(source: &str, target: Target, arch: Arch) -> Option<String> {
+    let options = BuildOptions {
+        arch: Some(arch),
+        ..BuildOptions::default()
+    };
+    panic::catch_unwind(|| compile_with_options(source, target, options))
+        .ok()?
+        .ok()
+}
+
+/// Replaces the runtime in C output with a placeholder, so that the golden files
+/// only contain the generated program
+fn without_runtime(output: &str) -> String {
+    match (output.find(RUNTIME_START), output.find(RUNTIME_END)) {
+        (Some(start), Some(end)) => format!(
+            "{}/* runtime.c */{}",
+            &output[..start],
+            &output[end + RUNTIME_END.len()..]
+        ),
+        _ => output.to_string(),
+    }
+}
+
+/// Compares `actual` against the golden file at `path`, where `None` means that the file should not exist.
+/// Returns a description of the mismatch.
+fn compare(path: &Path, actual: Option<&str>) -> Option<String> {
+    let expected = fs::read_to_string(path).ok();
+    if expected.as_deref() == actual {
+        return None;
+    }
+    if update_golden() {
+        match actual {
+            Some(actual) => fs::write(path, actual).unwrap(),
+            None => fs::remove_file(path).unwrap(),
+        }
+        return None;
+    }
+    Some(match (expected, actual) {
+        (None, _) => format!("{}: golden file is missing", path.display()),
+        (_, None) => format!("{}: the program no longer compiles", path.display()),
+        (Some(_), Some(_)) => format!("{}: output differs from the golden file", path.display()),
+    })
+}
+
+pub(super) fn installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("-v")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+pub(super) fn run_js(js: &str) -> Output {
+    let mut child = Command::new("node")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(js.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_golden_output() {
+    fs::create_dir_all(GOLDEN_DIR).unwrap();
+    let run = installed("node");
+
+    let mut failures = Vec::new();
+    for example in examples() {
+        let name = example.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&example).unwrap();
+        for target in TARGETS.iter() {
+            // Native output should not depend on the machine the tests run on
+            let output = generate(&source, *target, Arch::Amd64);
+            let golden = Path::new(GOLDEN_DIR).join(format!("{}.{}", name, target.extension()));
+            let program = output.as_deref().map(without_runtime);
+            failures.extend(compare(&golden, program.as_deref()));
+
+            if let (Target::JS, Some(js), true) = (target, &output, run) {
+                let golden = Path::new(GOLDEN_DIR).join(format!("{}.stdout", name));
+                let stdout = String::from_utf8_lossy(&run_js(js).stdout).into_owned();
+                failures.extend(compare(&golden, Some(&stdout)));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nRun the tests with UPDATE_GOLDEN=1 to accept the new output",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,180 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use serde_json::json;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests;
+
+/// How the timings of a build are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimingsFormat {
+    /// The total time of each phase, printed as a table
+    #[default]
+    Table,
+    /// Every measured phase in the Chrome trace event format, e.g. for `chrome://tracing`
+    Json,
+}
+
+impl FromStr for TimingsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(TimingsFormat::Table),
+            "json" => Ok(TimingsFormat::Json),
+            _ => Err(format!(
+                "Unknown timings format: {}, expected table or json",
+                s
+            )),
+        }
+    }
+}
+
+/// A measured phase of the compiler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub name: String,
+    /// Module the phase processed, e.g. for lexing and parsing
+    pub file: Option<String>,
+    /// Time since the measurement started
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Time spent in the phases of the compiler.
+/// Nothing is measured if the timings are disabled, since clocks are not available on every platform.
+#[derive(Debug, Default)]
+pub struct Timings {
+    origin: Option<Instant>,
+    events: Vec<Event>,
+}
+
+impl Timings {
+    /// Creates timings that measure phases from now on
+    pub fn new() -> Self {
+        Timings {
+            origin: Some(Instant::now()),
+            events: Vec::new(),
+        }
+    }
+
+    /// Creates timings that do not measure anything
+    pub fn disabled() -> Self {
+        Timings::default()
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Starts measuring a phase, which ends when it is passed to [`Timings::stop`]
+    pub fn start(&self) -> Option<Instant> {
+        self.origin.map(|_| Instant::now())
+    }
+
+    /// Records a phase that started at the given point in time
+    pub fn stop(&mut self, name: &str, file: Option<&str>, started: Option<Instant>) {
+        let (Some(origin), Some(started)) = (self.origin, started) else {
+            return;
+        };
+        self.events.push(Event {
+            name: name.to_owned(),
+            file: file.map(str::to_owned),
+            start: started.duration_since(origin),
+            duration: started.elapsed(),
+        });
+    }
+
+    /// Measures the time a phase takes
+    pub fn time<T>(&mut self, name: &str, phase: impl FnOnce() -> T) -> T {
+        let started = self.start();
+        let result = phase();
+        self.stop(name, None, started);
+        result
+    }
+
+    pub fn render(&self, format: TimingsFormat) -> String {
+        match format {
+            TimingsFormat::Table => self.to_table(),
+            TimingsFormat::Json => self.to_chrome_trace(),
+        }
+    }
+
+    /// Renders the total time of each phase, in the order the phases first ran
+    pub fn to_table(&self) -> String {
+        let mut phases: Vec<(&str, Duration)> = Vec::new();
+        for event in &self.events {
+            match phases.iter_mut().find(|(name, _)| *name == event.name) {
+                Some((_, total)) => *total += event.duration,
+                None => phases.push((&event.name, event.duration)),
+            }
+        }
+        let total: Duration = phases.iter().map(|(_, duration)| *duration).sum();
+        let width = phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(["Phase".len(), "Total".len()])
+            .max()
+            .unwrap_or_default();
+
+        let mut table = format!("{:<width$}  {:>10}  {:>6}\n", "Phase", "Time", "Share");
+        for (name, duration) in phases {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                duration.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            table += &format!(
+                "{:<width$}  {:>10}  {:>5.1}%\n",
+                name,
+                format_duration(duration),
+                share
+            );
+        }
+        table += &format!("{:<width$}  {:>10}\n", "Total", format_duration(total));
+        table
+    }
+
+    /// Renders every phase as a complete event of the Chrome trace event format
+    pub fn to_chrome_trace(&self) -> String {
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .map(|event| {
+                let mut value = json!({
+                    "name": event.name,
+                    "cat": "compiler",
+                    "ph": "X",
+                    "ts": event.start.as_micros() as u64,
+                    "dur": event.duration.as_micros() as u64,
+                    "pid": 1,
+                    "tid": 1,
+                });
+                if let Some(file) = &event.file {
+                    value["args"] = json!({ "file": file });
+                }
+                value
+            })
+            .collect();
+        json!({ "traceEvents": events }).to_string()
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
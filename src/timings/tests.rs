@@ -0,0 +1,82 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use super::{Event, Timings, TimingsFormat};
+use std::time::Duration;
+
+fn event(name: &str, file: Option<&str>, start: u64, duration: u64) -> Event {
+    Event {
+        name: name.to_owned(),
+        file: file.map(str::to_owned),
+        start: Duration::from_millis(start),
+        duration: Duration::from_millis(duration),
+    }
+}
+
+fn timings(events: Vec<Event>) -> Timings {
+    Timings {
+        origin: None,
+        events,
+    }
+}
+
+#[test]
+fn test_disabled_timings_measure_nothing() {
+    let mut timings = Timings::disabled();
+    assert_eq!(timings.time("Parsing", || 42), 42);
+    assert!(timings.events().is_empty());
+
+    let mut timings = Timings::new();
+    timings.time("Parsing", || ());
+    assert_eq!(timings.events().len(), 1);
+    assert_eq!(timings.events()[0].name, "Parsing");
+}
+
+#[test]
+fn test_timings_table() {
+    let timings = timings(vec![
+        event("Lexing", Some("main.sb"), 0, 10),
+        event("Lexing", Some("lib.sb"), 10, 20),
+        event("Code generation (js)", None, 30, 10),
+    ]);
+    assert_eq!(
+        timings.render(TimingsFormat::Table),
+        "\
+Phase                       Time   Share
+Lexing                   30.00ms   75.0%
+Code generation (js)     10.00ms   25.0%
+Total                    40.00ms
+"
+    );
+}
+
+#[test]
+fn test_timings_chrome_trace() {
+    let timings = timings(vec![
+        event("Lexing", Some("main.sb"), 1, 2),
+        event("Semantic analysis", None, 3, 4),
+    ]);
+    let trace: serde_json::Value =
+        serde_json::from_str(&timings.render(TimingsFormat::Json)).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["name"], "Lexing");
+    assert_eq!(events[0]["ph"], "X");
+    assert_eq!(events[0]["ts"], 1000);
+    assert_eq!(events[0]["dur"], 2000);
+    assert_eq!(events[0]["args"]["file"], "main.sb");
+    assert_eq!(events[1]["name"], "Semantic analysis");
+    assert!(events[1].get("args").is_none());
+}
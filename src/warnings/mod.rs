@@ -0,0 +1,857 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::attributes::{BENCH, DEPRECATED, EXPORT, TEST};
+use crate::ast::types::Type;
+use crate::ast::*;
+use crate::builder::resolve_import;
+use crate::diagnostics::Diagnostic;
+use crate::lexer::Position;
+use crate::package::Packages;
+use crate::semantic::returns;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(test)]
+mod tests;
+
+/// Kinds of warnings the compiler can emit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedImport,
+    UnusedFunction,
+    UnreachableCode,
+    RedeclaredVariable,
+    ShadowedBinding,
+    ImplicitAny,
+    UntypedSignature,
+    UnconditionalRecursion,
+    Deprecated,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 10] = [
+        Lint::UnusedVariable,
+        Lint::UnusedImport,
+        Lint::UnusedFunction,
+        Lint::UnreachableCode,
+        Lint::RedeclaredVariable,
+        Lint::ShadowedBinding,
+        Lint::ImplicitAny,
+        Lint::UntypedSignature,
+        Lint::UnconditionalRecursion,
+        Lint::Deprecated,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedImport => "unused-import",
+            Lint::UnusedFunction => "unused-function",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::RedeclaredVariable => "redeclared-variable",
+            Lint::ShadowedBinding => "shadowed-binding",
+            Lint::ImplicitAny => "implicit-any",
+            Lint::UntypedSignature => "untyped-signature",
+            Lint::UnconditionalRecursion => "unconditional-recursion",
+            Lint::Deprecated => "deprecated",
+        }
+    }
+
+    /// Level of the lint if it is not configured otherwise
+    fn default_level(&self) -> LintLevel {
+        match self {
+            // Most values are untyped, so this would be too noisy by default
+            Lint::ImplicitAny | Lint::UntypedSignature => LintLevel::Allow,
+            // Shadowing in a nested scope is an intended language feature
+            Lint::ShadowedBinding => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .iter()
+            .find(|lint| lint.name() == s)
+            .copied()
+            .ok_or_else(|| format!("no lint {} found", s))
+    }
+}
+
+/// How a lint is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// Lint configuration, usually provided by the `-W`, `-A`,
+/// `--deny-warnings` and `--strict` flags
+#[derive(Debug, Clone, Default)]
+pub struct WarningOptions {
+    /// Lints that are explicitly configured
+    levels: HashMap<Lint, LintLevel>,
+    /// Level of all lints that are not explicitly configured
+    all: Option<LintLevel>,
+    /// Turns all warnings into errors
+    pub deny_warnings: bool,
+    /// Denies implicit `any` and untyped signatures, regardless of other flags
+    pub strict: bool,
+}
+
+impl WarningOptions {
+    /// Constructs options from lint names passed to `-W` and `-A`.
+    /// The special name `all` refers to every lint. Lints that are
+    /// named explicitly take precedence over `all`.
+    pub fn from_flags(
+        warn: &[String],
+        allow: &[String],
+        deny_warnings: bool,
+    ) -> Result<Self, String> {
+        let mut options = WarningOptions {
+            deny_warnings,
+            ..Default::default()
+        };
+
+        for (names, level) in &[(warn, LintLevel::Warn), (allow, LintLevel::Allow)] {
+            for name in names.iter() {
+                if name == "all" {
+                    options.all = Some(*level);
+                } else {
+                    options.levels.insert(name.parse()?, *level);
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Returns the level a lint is reported with
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        if self.strict && matches!(lint, Lint::ImplicitAny | Lint::UntypedSignature) {
+            return LintLevel::Deny;
+        }
+
+        let level = self
+            .levels
+            .get(&lint)
+            .copied()
+            .or(self.all)
+            .unwrap_or_else(|| lint.default_level());
+
+        match level {
+            LintLevel::Warn if self.deny_warnings => LintLevel::Deny,
+            other => other,
+        }
+    }
+}
+
+/// Checks the modules of a program for suspicious code.
+/// Lints that are allowed are not reported, denied lints are reported as errors.
+pub fn check(modules: &[Module], options: &WarningOptions, packages: &Packages) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+    for module in modules {
+        for func in module.func.iter().filter(|f| f.has_attribute(DEPRECATED)) {
+            let note = func
+                .attribute(DEPRECATED)
+                .and_then(|attr| attr.positional());
+            checker
+                .deprecated_functions
+                .insert(func.name.clone(), note.map(str::to_owned));
+        }
+        for def in module
+            .structs
+            .iter()
+            .filter(|d| d.has_attribute(DEPRECATED))
+        {
+            let note = def
+                .attributes
+                .iter()
+                .find(|attr| attr.name == DEPRECATED)
+                .and_then(|attr| attr.positional());
+            checker
+                .deprecated_structs
+                .insert(def.name.clone(), note.map(str::to_owned));
+        }
+    }
+
+    for module in modules {
+        checker.file = module.path.clone();
+        checker.symbols.clear();
+        for global in &module.globals {
+            checker.location = Some(format!("global '{}'", global.var.name));
+            checker.use_type(&global.var.ty);
+            checker.check_expression(&global.value);
+        }
+        for func in &module.func {
+            checker.check_function(func);
+            checker.check_recursion(func);
+        }
+        for def in &module.structs {
+            def.fields
+                .iter()
+                .for_each(|field| checker.use_type(&field.ty));
+            for method in &def.methods {
+                checker.check_function(method);
+            }
+        }
+        checker.check_unused_imports(module, modules, packages);
+    }
+    checker.check_unused_functions(modules);
+
+    checker
+        .warnings
+        .into_iter()
+        .filter_map(|(lint, diagnostic)| match options.level(lint) {
+            LintLevel::Allow => None,
+            LintLevel::Warn => Some(diagnostic),
+            LintLevel::Deny => Some(Diagnostic {
+                severity: crate::diagnostics::Severity::Error,
+                ..diagnostic
+            }),
+        })
+        .collect()
+}
+
+/// A variable that has been declared in a function
+struct Binding {
+    name: String,
+    pos: Option<Position>,
+    is_argument: bool,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Checker {
+    /// File of the module that is currently checked
+    file: String,
+    /// Bindings of the function that is currently checked
+    bindings: Vec<Binding>,
+    /// Block-scoped variable -> binding mappings
+    scopes: Vec<HashMap<String, usize>>,
+    /// Names of all called functions
+    calls: HashSet<String>,
+    /// Names of functions, structs and globals referenced by the current module
+    symbols: HashSet<String>,
+    /// Functions annotated with `@deprecated`, and the note of the annotation
+    deprecated_functions: HashMap<String, Option<String>>,
+    /// Structs annotated with `@deprecated`, and the note of the annotation
+    deprecated_structs: HashMap<String, Option<String>>,
+    /// The function or global that is currently checked, unless it is deprecated itself
+    location: Option<String>,
+    warnings: Vec<(Lint, Diagnostic)>,
+}
+
+impl Checker {
+    fn warn(&mut self, lint: Lint, message: String, pos: Option<Position>) -> &mut Diagnostic {
+        let diagnostic = Diagnostic::warning(message)
+            .with_lint(lint.name())
+            .with_file(&self.file)
+            .with_pos(pos);
+        self.warnings.push((lint, diagnostic));
+        &mut self.warnings.last_mut().unwrap().1
+    }
+
+    fn check_function(&mut self, func: &Function) {
+        // Deprecated functions may use each other
+        self.location =
+            (!func.has_attribute(DEPRECATED)).then(|| format!("function '{}'", func.name));
+        self.bindings.clear();
+        self.scopes.push(HashMap::new());
+        for arg in &func.arguments {
+            self.declare(arg, true);
+        }
+        self.use_type(&func.ret_type);
+        self.check_signature(func);
+
+        self.check_statement(&func.body);
+        self.scopes.pop();
+
+        let unused: Vec<(String, Option<Position>, bool)> = self
+            .bindings
+            .iter()
+            .filter(|b| !b.used && !b.name.starts_with('_'))
+            .map(|b| (b.name.clone(), b.pos, b.is_argument))
+            .collect();
+        for (name, pos, is_argument) in unused {
+            let kind = if is_argument { "Parameter" } else { "Variable" };
+            self.warn(
+                Lint::UnusedVariable,
+                format!(
+                    "{} '{}' in function '{}' is never used",
+                    kind, name, func.name
+                ),
+                pos,
+            )
+            .help = Some(format!(
+                "if this is intentional, prefix it with an underscore: `_{}`",
+                name
+            ));
+        }
+    }
+
+    /// Reports parameters and return types of type `any`, and functions that
+    /// return a value without declaring its type
+    fn check_signature(&mut self, func: &Function) {
+        for arg in &func.arguments {
+            if arg.ty == Some(Type::Any) {
+                self.warn(
+                    Lint::UntypedSignature,
+                    format!(
+                        "Parameter '{}' of function '{}' has type 'any'",
+                        arg.name, func.name
+                    ),
+                    arg.pos,
+                );
+            }
+        }
+        match &func.ret_type {
+            Some(Type::Any) => {
+                self.warn(
+                    Lint::UntypedSignature,
+                    format!("Function '{}' returns 'any'", func.name),
+                    None,
+                );
+            }
+            None if returns_value(&func.body) => {
+                self.warn(
+                    Lint::UntypedSignature,
+                    format!(
+                        "Function '{}' returns a value, but does not declare its type",
+                        func.name
+                    ),
+                    None,
+                )
+                .help = Some("add a return type to the signature".to_owned());
+            }
+            _ => {}
+        }
+    }
+
+    /// Reports functions that call themselves before they could return,
+    /// which recurses until the stack overflows
+    fn check_recursion(&mut self, func: &Function) {
+        if let Statement::Block(statements, _) = &func.body {
+            if recurses(&func.name, statements) == Some(true) {
+                self.warn(
+                    Lint::UnconditionalRecursion,
+                    format!("Function '{}' calls itself unconditionally", func.name),
+                    None,
+                )
+                .help = Some(format!(
+                    "add a condition that returns without calling '{}'",
+                    func.name
+                ));
+            }
+        }
+    }
+
+    /// Reports imports that do not provide any symbol used by the module,
+    /// and names of selective imports that are never used
+    fn check_unused_imports(&mut self, module: &Module, modules: &[Module], packages: &Packages) {
+        // Modules without definitions only bundle their imports
+        if module.func.is_empty() && module.structs.is_empty() && module.globals.is_empty() {
+            return;
+        }
+
+        let mut selected: Vec<(&String, &(String, Position))> = module.selected.iter().collect();
+        selected.sort_by_key(|(_, (_, pos))| pos.raw);
+        for (name, (_, pos)) in &selected {
+            if !self.symbols.contains(*name) {
+                self.warn(
+                    Lint::UnusedImport,
+                    format!("Imported symbol '{}' is never used", name),
+                    Some(*pos),
+                )
+                .help = Some("remove it from the import".to_owned());
+            }
+        }
+
+        // Selective imports are checked by their names
+        let mut imports: Vec<(&String, &Position)> = module
+            .imports
+            .iter()
+            .filter(|(import, _)| !selected.iter().any(|(_, (path, _))| path == *import))
+            .collect();
+        imports.sort_by_key(|(_, pos)| pos.raw);
+
+        for (import, pos) in imports {
+            let provided = provided_symbols(&module.path, import, modules, packages);
+            if !provided.iter().any(|symbol| self.symbols.contains(symbol)) {
+                self.warn(
+                    Lint::UnusedImport,
+                    format!("Import '{}' is never used", import),
+                    Some(*pos),
+                )
+                .help = Some("remove the import".to_owned());
+            }
+        }
+    }
+
+    fn check_unused_functions(&mut self, modules: &[Module]) {
+        // Without a main function, the program is a library
+        if !modules
+            .iter()
+            .flat_map(|m| &m.func)
+            .any(|f| f.name == "main")
+        {
+            return;
+        }
+
+        for module in modules {
+            self.file = module.path.clone();
+            for func in &module.func {
+                // Tests and benchmarks are called by their harness, public and exported functions by other code
+                if func.name != "main"
+                    && !func.name.starts_with('_')
+                    && !func.has_attribute(TEST)
+                    && !func.has_attribute(BENCH)
+                    && !func.public
+                    && !func.has_attribute(EXPORT)
+                    && !self.calls.contains(&func.name)
+                    // Loops over the struct call it once the types are known
+                    && !module.structs.iter().any(|def| def.is_protocol_function(func))
+                {
+                    self.warn(
+                        Lint::UnusedFunction,
+                        format!("Function '{}' is never called", func.name),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reports the use of a function or struct that is annotated with `@deprecated`
+    /// `deprecation` is the note of the `@deprecated` attribute, if the item has one
+    fn check_deprecated(&mut self, kind: &str, name: &str, deprecation: Option<Option<String>>) {
+        if let (Some(note), Some(location)) = (deprecation, &self.location) {
+            let message = format!("Use of deprecated {} '{}' in {}", kind, name, location);
+            self.warn(Lint::Deprecated, message, None).help = note;
+        }
+    }
+
+    fn declare(&mut self, var: &Variable, is_argument: bool) {
+        let redeclared = self
+            .scopes
+            .last()
+            .is_some_and(|scope| scope.contains_key(&var.name));
+        if var.name.starts_with('_') {
+            // Placeholders are meant to be redeclared
+        } else if redeclared {
+            self.warn(
+                Lint::RedeclaredVariable,
+                format!("Variable '{}' is already declared in this scope", var.name),
+                var.pos,
+            );
+        } else if self.resolve(&var.name).is_some() {
+            self.warn(
+                Lint::ShadowedBinding,
+                format!("Variable '{}' shadows an earlier declaration", var.name),
+                var.pos,
+            );
+        }
+        if !is_argument && matches!(var.ty, None | Some(Type::Any)) {
+            self.warn(
+                Lint::ImplicitAny,
+                format!(
+                    "Type of variable '{}' could not be inferred and defaults to 'any'",
+                    var.name
+                ),
+                var.pos,
+            );
+        }
+        self.use_type(&var.ty);
+
+        self.bindings.push(Binding {
+            name: var.name.clone(),
+            pos: var.pos,
+            is_argument,
+            used: false,
+        });
+        let index = self.bindings.len() - 1;
+        self.scopes
+            .last_mut()
+            .expect("expected last scope to be present")
+            .insert(var.name.clone(), index);
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+    }
+
+    fn use_variable(&mut self, name: &str) {
+        match self.resolve(name) {
+            Some(index) => self.bindings[index].used = true,
+            // Variables that are not declared in the function may be globals of another module
+            None => {
+                self.symbols.insert(name.to_owned());
+            }
+        }
+    }
+
+    /// Marks structs referenced by a type as used
+    fn use_type(&mut self, ty: &Option<Type>) {
+        match ty {
+            Some(Type::Struct(name)) => {
+                self.symbols.insert(name.clone());
+            }
+            Some(Type::Array(inner, _)) => self.use_type(&Some(*inner.clone())),
+            Some(Type::Map(key, value)) => {
+                self.use_type(&Some(*key.clone()));
+                self.use_type(&Some(*value.clone()));
+            }
+            Some(Type::Result(inner)) => self.use_type(&Some(*inner.clone())),
+            _ => {}
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(statements, _) => {
+                self.scopes.push(HashMap::new());
+                let jump = statements.iter().position(returns::diverges);
+                if let Some(pos) = jump {
+                    if pos + 1 < statements.len() {
+                        let message = match statements[pos] {
+                            Statement::Return(_) | Statement::Break | Statement::Continue => {
+                                "Unreachable statement after return, break or continue"
+                            }
+                            // Branches that all return, or an endless loop
+                            _ => "Unreachable statement after a statement that never completes",
+                        };
+                        self.warn(Lint::UnreachableCode, message.to_owned(), None);
+                    }
+                }
+                statements.iter().for_each(|s| self.check_statement(s));
+                self.scopes.pop();
+            }
+            Statement::Declare(var, expr) => {
+                // The initializer can not refer to the variable itself
+                if let Some(expr) = expr {
+                    self.check_expression(expr);
+                }
+                self.declare(var, false);
+            }
+            Statement::Destructure(fields, expr) => {
+                self.check_expression(expr);
+                for (_, var) in fields {
+                    self.declare(var, false);
+                }
+            }
+            Statement::Static(var, expr) => {
+                self.check_expression(expr);
+                self.declare(var, false);
+            }
+            Statement::Assign(lhs, rhs) => {
+                // Assigning to a variable is not a use, but indexing it is
+                match &**lhs {
+                    Expression::Variable(_) => {}
+                    other => self.check_expression(other),
+                }
+                self.check_expression(rhs);
+            }
+            Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::Defer(expr)
+            | Statement::Spawn(expr) => self.check_expression(expr),
+            Statement::If(cond, if_branch, else_branch) => {
+                self.check_expression(cond);
+                self.check_statement(if_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_statement(else_branch);
+                }
+            }
+            Statement::While(cond, body) => {
+                self.check_expression(cond);
+                self.check_statement(body);
+            }
+            Statement::Unsafe(body) => self.check_statement(body),
+            Statement::For(var, expr, body) => {
+                self.check_expression(expr);
+                self.scopes.push(HashMap::new());
+                self.declare(var, true);
+                // Loop variables are not reported, since they can not be omitted
+                let index = self.bindings.len() - 1;
+                self.bindings[index].used = true;
+                self.check_statement(body);
+                self.scopes.pop();
+            }
+            Statement::ForPair(key, value, expr, body) => {
+                self.check_expression(expr);
+                self.scopes.push(HashMap::new());
+                for var in [key, value] {
+                    self.declare(var, true);
+                    let index = self.bindings.len() - 1;
+                    self.bindings[index].used = true;
+                }
+                self.check_statement(body);
+                self.scopes.pop();
+            }
+            Statement::Match(subject, arms) => {
+                self.check_expression(subject);
+                for arm in arms {
+                    match arm {
+                        MatchArm::Case(expr, s) => {
+                            self.check_expression(expr);
+                            self.check_statement(s);
+                        }
+                        MatchArm::Else(s) => self.check_statement(s),
+                    }
+                }
+            }
+            Statement::Return(None) | Statement::Break | Statement::Continue => {}
+            Statement::Inline(_, code) => {
+                for word in inline_identifiers(code) {
+                    self.use_variable(word);
+                    self.calls.insert(word.to_owned());
+                    self.symbols.insert(word.to_owned());
+                }
+            }
+        }
+    }
+
+    fn check_expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Variable(name) => self.use_variable(name),
+            Expression::ArrayAccess(name, index) => {
+                self.use_variable(name);
+                self.check_expression(index);
+            }
+            Expression::FunctionCall(name, args) => {
+                // Calls through the alias of an import use the function of the imported module
+                let name = match name.split_once("::") {
+                    Some((_, name)) => name,
+                    None => name,
+                };
+                self.calls.insert(name.to_owned());
+                self.symbols.insert(name.to_owned());
+                let note = self.deprecated_functions.get(name).cloned();
+                self.check_deprecated("function", name, note);
+                args.iter().for_each(|e| self.check_expression(e));
+            }
+            Expression::Array(_, elements) => {
+                elements.iter().for_each(|e| self.check_expression(e))
+            }
+            Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+                self.check_expression(lhs);
+                self.check_expression(rhs);
+            }
+            Expression::StructInitialization(name, fields) => {
+                self.symbols.insert(name.clone());
+                let note = self.deprecated_structs.get(name).cloned();
+                self.check_deprecated("struct", name, note);
+                fields.values().for_each(|e| self.check_expression(e))
+            }
+            Expression::StructUpdate(name, base, fields) => {
+                self.symbols.insert(name.clone());
+                let note = self.deprecated_structs.get(name).cloned();
+                self.check_deprecated("struct", name, note);
+                self.check_expression(base);
+                fields.values().for_each(|e| self.check_expression(e))
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.check_expression(key);
+                    self.check_expression(value);
+                }
+            }
+            Expression::Try(inner)
+            | Expression::Await(inner)
+            | Expression::Reference(inner)
+            | Expression::Dereference(inner)
+            | Expression::Load(_, inner) => self.check_expression(inner),
+            // Field names are not variables, only the accessed object is
+            Expression::FieldAccess(obj, field) => {
+                self.check_expression(obj);
+                if let Expression::FunctionCall(_, args) = &**field {
+                    args.iter().for_each(|e| self.check_expression(e));
+                }
+            }
+            Expression::Int(_)
+            | Expression::Str(_)
+            | Expression::Bool(_)
+            | Expression::Selff
+            | Expression::SizeOf(_) => {}
+        }
+    }
+}
+
+/// Returns whether straight-line code calls the function `name` before it could leave
+/// the function. `None` means that the end of the statements is reached without a call.
+fn recurses(name: &str, statements: &[Statement]) -> Option<bool> {
+    for statement in statements {
+        let evaluated = match statement {
+            Statement::Declare(_, Some(expr))
+            | Statement::Destructure(_, expr)
+            | Statement::Static(_, expr)
+            | Statement::Return(Some(expr))
+            | Statement::Exp(expr)
+            | Statement::If(expr, _, _)
+            | Statement::While(expr, _)
+            | Statement::For(_, expr, _)
+            | Statement::ForPair(_, _, expr, _)
+            | Statement::Match(expr, _) => vec![expr],
+            Statement::Assign(lhs, rhs) => vec![&**rhs, &**lhs],
+            Statement::Block(statements, _) => match recurses(name, statements) {
+                None => continue,
+                result => return result,
+            },
+            Statement::Unsafe(body) => match recurses(name, std::slice::from_ref(body)) {
+                None => continue,
+                result => return result,
+            },
+            _ => Vec::new(),
+        };
+        if evaluated.iter().any(|expr| calls(name, expr)) {
+            return Some(true);
+        }
+        // Branches could leave the function before the next statement is reached
+        if can_leave(statement) || returns::diverges(statement) {
+            return Some(false);
+        }
+    }
+    None
+}
+
+/// Returns true if evaluating the expression always calls the function `name`
+fn calls(name: &str, expr: &Expression) -> bool {
+    match expr {
+        Expression::FunctionCall(callee, args) => {
+            callee == name || args.iter().any(|arg| calls(name, arg))
+        }
+        // The right operand is not evaluated if the left one decides the result
+        Expression::BinOp(lhs, BinOp::And | BinOp::Or, _) => calls(name, lhs),
+        Expression::BinOp(lhs, _, rhs) | Expression::Store(_, lhs, rhs) => {
+            calls(name, lhs) || calls(name, rhs)
+        }
+        Expression::ArrayAccess(_, index) => calls(name, index),
+        Expression::Array(_, elements) => elements.iter().any(|e| calls(name, e)),
+        Expression::StructInitialization(_, fields) => fields.values().any(|e| calls(name, e)),
+        Expression::StructUpdate(_, base, fields) => {
+            calls(name, base) || fields.values().any(|e| calls(name, e))
+        }
+        Expression::Map(entries) => entries
+            .iter()
+            .any(|(key, value)| calls(name, key) || calls(name, value)),
+        Expression::Try(inner)
+        | Expression::Await(inner)
+        | Expression::Reference(inner)
+        | Expression::Dereference(inner)
+        | Expression::Load(_, inner) => calls(name, inner),
+        Expression::FieldAccess(obj, field) => {
+            calls(name, obj)
+                || matches!(&**field, Expression::FunctionCall(_, args) if args.iter().any(|e| calls(name, e)))
+        }
+        Expression::Int(_)
+        | Expression::Str(_)
+        | Expression::Bool(_)
+        | Expression::Selff
+        | Expression::Variable(_)
+        | Expression::SizeOf(_) => false,
+    }
+}
+
+/// Returns true if a nested statement contains a `return`, `break` or `continue`,
+/// or exits the program
+fn can_leave(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) | Statement::Break | Statement::Continue => true,
+        Statement::Exp(Expression::FunctionCall(name, _)) => name == "_panic" || name == "exit",
+        Statement::Block(statements, _) => statements.iter().any(can_leave),
+        Statement::If(_, if_branch, else_branch) => {
+            can_leave(if_branch) || else_branch.as_deref().is_some_and(can_leave)
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(_, _, _, body)
+        | Statement::Unsafe(body) => can_leave(body),
+        Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+            MatchArm::Case(_, body) | MatchArm::Else(body) => can_leave(body),
+        }),
+        _ => false,
+    }
+}
+
+/// Returns true if the statement contains a return with a value
+fn returns_value(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(value) => value.is_some(),
+        Statement::Block(statements, _) => statements.iter().any(returns_value),
+        Statement::If(_, if_branch, else_branch) => {
+            returns_value(if_branch) || else_branch.as_deref().is_some_and(returns_value)
+        }
+        Statement::While(_, body)
+        | Statement::For(_, _, body)
+        | Statement::ForPair(_, _, _, body)
+        | Statement::Unsafe(body) => returns_value(body),
+        Statement::Match(_, arms) => arms.iter().any(|arm| match arm {
+            MatchArm::Case(_, body) | MatchArm::Else(body) => returns_value(body),
+        }),
+        _ => false,
+    }
+}
+
+/// Returns the names of all functions and structs an import makes available,
+/// including the ones of modules that are imported by the imported module
+fn provided_symbols(
+    importer: &str,
+    import: &str,
+    modules: &[Module],
+    packages: &Packages,
+) -> HashSet<String> {
+    let mut symbols = HashSet::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut queue = vec![resolve_import(Path::new(importer), import, packages)
+        .display()
+        .to_string()];
+
+    while let Some(path) = queue.pop() {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        if let Some(module) = modules.iter().find(|m| m.path == path) {
+            symbols.extend(module.func.iter().map(|f| f.name.clone()));
+            symbols.extend(module.externs.iter().map(|f| f.name.clone()));
+            symbols.extend(module.structs.iter().map(|s| s.name.clone()));
+            symbols.extend(module.globals.iter().map(|g| g.var.name.clone()));
+            queue.extend(module.imports.keys().map(|i| {
+                resolve_import(Path::new(&module.path), i, packages)
+                    .display()
+                    .to_string()
+            }));
+        }
+    }
+
+    symbols
+}
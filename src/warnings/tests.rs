@@ -0,0 +1,508 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::ast::Module;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::lexer::tokenize;
+use crate::package::Packages;
+use crate::parser::parse;
+use crate::warnings::*;
+
+fn check_raw(raw: &str, options: &WarningOptions) -> Vec<Diagnostic> {
+    let tokens = tokenize(raw).unwrap();
+    let module = parse(tokens, Some(raw.to_string()), "test.sb".into()).unwrap();
+    check(&[module], options, &Packages::new())
+}
+
+fn check_modules(sources: &[(&str, &str)]) -> Vec<Diagnostic> {
+    let modules: Vec<Module> = sources
+        .iter()
+        .map(|(path, raw)| {
+            let tokens = tokenize(raw).unwrap();
+            parse(tokens, Some(raw.to_string()), path.to_string()).unwrap()
+        })
+        .collect();
+    check(&modules, &WarningOptions::default(), &Packages::new())
+}
+
+fn lints_of(diagnostics: &[Diagnostic]) -> Vec<String> {
+    diagnostics
+        .iter()
+        .map(|d| d.lint.clone().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_unused_variable() {
+    let raw = "
+    fn main() {
+        let x = 5
+        let y = 6
+        let _z = 7
+        println(y)
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unused-variable"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Variable 'x' in function 'main' is never used"
+    );
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].file, Some("test.sb".into()));
+    assert_eq!(diagnostics[0].pos.map(|pos| pos.line), Some(3));
+    assert_eq!(
+        diagnostics[0].help,
+        Some("if this is intentional, prefix it with an underscore: `_x`".into())
+    );
+}
+
+#[test]
+fn test_unused_parameter() {
+    let raw = "
+    fn main() {
+        foo(1, 2)
+    }
+
+    fn foo(x: int, _y: int) {}
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unused-variable"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Parameter 'x' in function 'foo' is never used"
+    );
+}
+
+#[test]
+fn test_unused_import() {
+    let diagnostics = check_modules(&[
+        (
+            "main.sb",
+            "import \"foo\"\nimport \"bar\"\nfn main() { foo() }",
+        ),
+        ("foo.sb", "fn foo() {}"),
+        ("bar.sb", "fn bar() {}"),
+    ]);
+    let imports: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.lint == Some("unused-import".into()))
+        .collect();
+    assert_eq!(imports.len(), 1);
+    assert_eq!(imports[0].message, "Import 'bar' is never used");
+    assert_eq!(imports[0].pos.map(|pos| pos.line), Some(2));
+}
+
+#[test]
+fn test_unused_selected_import() {
+    let diagnostics = check_modules(&[
+        (
+            "main.sb",
+            "import { foo, bar } from \"foo\"\nimport { baz } from \"baz\"\nfn main() { foo() }",
+        ),
+        ("foo.sb", "pub fn foo() {}\npub fn bar() {}"),
+        ("baz.sb", "pub fn baz() {}"),
+    ]);
+    let imports: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.lint == Some("unused-import".into()))
+        .collect();
+    assert_eq!(imports.len(), 2);
+    assert_eq!(imports[0].message, "Imported symbol 'bar' is never used");
+    assert_eq!(imports[0].pos.map(|pos| pos.line), Some(1));
+    assert_eq!(imports[1].message, "Imported symbol 'baz' is never used");
+    assert_eq!(imports[1].pos.map(|pos| pos.line), Some(2));
+}
+
+#[test]
+fn test_import_used_by_struct_and_transitive_import() {
+    let diagnostics = check_modules(&[
+        (
+            "main.sb",
+            "import \"foo\"\nimport \"bar\"\nfn main() { let x: Foo = baz()\nprintln(x) }",
+        ),
+        ("foo.sb", "struct Foo {\nx: int\n}"),
+        ("bar.sb", "import \"baz\""),
+        ("baz.sb", "fn baz(): int { return 1 }"),
+    ]);
+    assert!(diagnostics
+        .iter()
+        .all(|d| d.lint != Some("unused-import".into())));
+}
+
+#[test]
+fn test_assignment_is_not_a_use() {
+    let raw = "
+    fn main() {
+        let x = 5
+        x = 6
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unused-variable"]);
+}
+
+#[test]
+fn test_unused_function() {
+    let raw = "
+    fn main() {
+        foo()
+    }
+
+    fn foo() {}
+
+    fn bar() {}
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unused-function"]);
+    assert_eq!(diagnostics[0].message, "Function 'bar' is never called");
+}
+
+#[test]
+fn test_no_unused_functions_in_libraries() {
+    let raw = "
+    fn foo() {}
+    ";
+    assert!(check_raw(raw, &WarningOptions::default()).is_empty());
+}
+
+#[test]
+fn test_protocol_functions_are_used_by_loops() {
+    let raw = "
+    struct Stack {
+        items: int[]
+    }
+
+    fn Stack_len(stack: Stack): int {
+        return stack.items.len()
+    }
+
+    fn Stack_get(stack: Stack, index: int): int {
+        let items = stack.items
+        return items[index]
+    }
+
+    fn Queue_len(stack: Stack): int {
+        return stack.items.len()
+    }
+
+    fn main() {}
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unused-function"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Function 'Queue_len' is never called"
+    );
+}
+
+#[test]
+fn test_unreachable_code() {
+    let raw = "
+    fn main() {
+        while true {
+            break
+            println(1)
+        }
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unreachable-code"]);
+
+    let raw = "
+    fn sign(n: int): int {
+        if n < 0 {
+            return 0 - 1
+        } else {
+            return 1
+        }
+        return 0
+    }
+
+    fn main() {
+        println(sign(1))
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unreachable-code"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Unreachable statement after a statement that never completes"
+    );
+}
+
+#[test]
+fn test_shadowed_binding() {
+    let raw = "
+    fn main() {
+        let x = 1
+        if true {
+            let x = 2
+            println(x)
+        }
+        println(x)
+    }
+    ";
+    assert!(check_raw(raw, &WarningOptions::default()).is_empty());
+
+    let options = WarningOptions::from_flags(&["shadowed-binding".into()], &[], false).unwrap();
+    let diagnostics = check_raw(raw, &options);
+    assert_eq!(lints_of(&diagnostics), vec!["shadowed-binding"]);
+}
+
+#[test]
+fn test_redeclared_variable() {
+    let raw = "
+    fn main() {
+        let x = 1
+        let x = x + 1
+        println(x)
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["redeclared-variable"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Variable 'x' is already declared in this scope"
+    );
+}
+
+#[test]
+fn test_implicit_any_is_allowed_by_default() {
+    let raw = "
+    fn main() {
+        let x = foo()
+        println(x)
+    }
+
+    fn foo() {}
+    ";
+    assert!(check_raw(raw, &WarningOptions::default()).is_empty());
+
+    let options = WarningOptions::from_flags(&["implicit-any".into()], &[], false).unwrap();
+    let diagnostics = check_raw(raw, &options);
+    assert_eq!(lints_of(&diagnostics), vec!["implicit-any"]);
+
+    // Types of nested declarations are inferred as well
+    let raw = "
+    fn main() {
+        let names = [\"a\"]
+        for name in names {
+            let upper = name
+            println(upper)
+        }
+    }
+    ";
+    assert!(check_raw(raw, &options).is_empty());
+}
+
+#[test]
+fn test_strict_denies_untyped_code() {
+    let raw = "
+    fn main() {
+        let x = foo(1)
+        println(x)
+    }
+
+    fn foo(n: any) {
+        return n
+    }
+    ";
+    let options = WarningOptions::from_flags(&[], &["all".into()], false).unwrap();
+    assert!(check_raw(raw, &options).is_empty());
+
+    let options = WarningOptions {
+        strict: true,
+        ..options
+    };
+    let diagnostics = check_raw(raw, &options);
+    assert_eq!(
+        lints_of(&diagnostics),
+        vec!["implicit-any", "untyped-signature", "untyped-signature"]
+    );
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+    assert_eq!(
+        diagnostics[1].message,
+        "Parameter 'n' of function 'foo' has type 'any'"
+    );
+    assert_eq!(
+        diagnostics[2].message,
+        "Function 'foo' returns a value, but does not declare its type"
+    );
+
+    let raw = "
+    fn main() {
+        println(double(2))
+    }
+
+    fn double(n: int): int {
+        return n * 2
+    }
+    ";
+    assert!(check_raw(raw, &options).is_empty());
+}
+
+#[test]
+fn test_allow_lint() {
+    let raw = "
+    fn main() {
+        let x = 5
+    }
+    ";
+    let options = WarningOptions::from_flags(&[], &["unused-variable".into()], false).unwrap();
+    assert!(check_raw(raw, &options).is_empty());
+
+    let options = WarningOptions::from_flags(&[], &["all".into()], false).unwrap();
+    assert!(check_raw(raw, &options).is_empty());
+}
+
+#[test]
+fn test_explicit_lint_overrides_all() {
+    let options =
+        WarningOptions::from_flags(&["unused-variable".into()], &["all".into()], false).unwrap();
+    assert_eq!(options.level(Lint::UnusedVariable), LintLevel::Warn);
+    assert_eq!(options.level(Lint::UnusedFunction), LintLevel::Allow);
+}
+
+#[test]
+fn test_deny_warnings() {
+    let raw = "
+    fn main() {
+        let x = 5
+    }
+    ";
+    let options = WarningOptions::from_flags(&[], &[], true).unwrap();
+    let diagnostics = check_raw(raw, &options);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+}
+
+#[test]
+fn test_unknown_lint() {
+    assert!(WarningOptions::from_flags(&["foo".into()], &[], false).is_err());
+}
+
+#[test]
+fn test_render_diagnostic() {
+    let diagnostic = Diagnostic::warning("Function 'bar' is never called".into())
+        .with_lint("unused-function")
+        .with_file("test.sb");
+    assert_eq!(
+        diagnostic.render(None),
+        "warning[unused-function]: Function 'bar' is never called\n --> test.sb"
+    );
+}
+
+#[test]
+fn test_render_diagnostic_with_position() {
+    let raw = "fn main() {\n    let x = 1\n}\n";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(
+        diagnostics[0].render(Some(raw)),
+        [
+            "warning[unused-variable]: Variable 'x' in function 'main' is never used",
+            " --> test.sb:2:9",
+            "   2 |     let x = 1",
+            "     |         ^",
+            "     = help: if this is intentional, prefix it with an underscore: `_x`",
+        ]
+        .join("\n")
+    );
+}
+
+#[test]
+fn test_unconditional_recursion() {
+    let raw = "
+    fn main() {
+        count(1)
+    }
+
+    fn count(n: int) {
+        println(n)
+        count(n + 1)
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["unconditional-recursion"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Function 'count' calls itself unconditionally"
+    );
+
+    let raw = "
+    fn main() {
+        println(fib(10))
+    }
+
+    fn fib(n: int): int {
+        if n < 2 {
+            return n
+        }
+        return fib(n - 1) + fib(n - 2)
+    }
+
+    fn check(n: int) {
+        if n < 0 {
+            panic(\"negative\")
+        }
+        n > 0 && check(n - 1)
+    }
+    ";
+    let options = WarningOptions::from_flags(&[], &["unused-function".into()], false).unwrap();
+    assert!(check_raw(raw, &options).is_empty());
+}
+
+#[test]
+fn test_deprecated() {
+    let raw = "
+    @deprecated(\"use Vector instead\")
+    struct Point {
+        x: int
+    }
+
+    @deprecated
+    fn old(): int {
+        return 1
+    }
+
+    @deprecated(\"use new_point instead\")
+    fn old_point(): Point {
+        return new Point { x: old() }
+    }
+
+    @export
+    fn exported() {}
+
+    fn main() {
+        println(old())
+        println(old_point().x)
+    }
+    ";
+    let diagnostics = check_raw(raw, &WarningOptions::default());
+    assert_eq!(lints_of(&diagnostics), vec!["deprecated", "deprecated"]);
+    assert_eq!(
+        diagnostics[0].message,
+        "Use of deprecated function 'old' in function 'main'"
+    );
+    assert_eq!(diagnostics[0].help, None);
+    assert_eq!(
+        diagnostics[1].message,
+        "Use of deprecated function 'old_point' in function 'main'"
+    );
+    assert_eq!(
+        diagnostics[1].help.as_deref(),
+        Some("use new_point instead")
+    );
+}
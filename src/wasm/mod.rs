@@ -0,0 +1,61 @@
+/**
+ * Copyright 2021 Garrit Franke
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+use crate::diagnostics::Diagnostic;
+use crate::generator::Target;
+use crate::{lexer, parser, SOURCE_NAME};
+use wasm_bindgen::prelude::*;
+
+/// Renders diagnostics of a single module program
+fn render(diagnostics: &[Diagnostic], source: &str) -> String {
+    diagnostics
+        .iter()
+        .map(|d| d.render(d.file.as_ref().map(|_| source)))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, JsValue> {
+    serde_json::to_string(value).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Returns the tokens of a program as JSON
+#[wasm_bindgen]
+pub fn tokenize(source: &str) -> Result<String, JsValue> {
+    let tokens = lexer::tokenize(source).map_err(|e| JsValue::from_str(&e))?;
+    to_json(&tokens)
+}
+
+/// Returns the syntax tree of a program as JSON
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, JsValue> {
+    let tokens = lexer::tokenize(source).map_err(|e| JsValue::from_str(&e))?;
+    let module = parser::parse(tokens, Some(source.to_owned()), SOURCE_NAME.to_owned())
+        .map_err(|e| JsValue::from_str(&e))?;
+    to_json(&module)
+}
+
+/// Compiles a program to JavaScript. Throws the rendered diagnostics if compilation fails
+#[wasm_bindgen]
+pub fn compile(source: &str) -> Result<String, JsValue> {
+    crate::compile(source, Target::JS)
+        .map_err(|diagnostics| JsValue::from_str(&render(&diagnostics, source)))
+}
+
+/// Returns the errors and warnings of a program as JSON
+#[wasm_bindgen]
+pub fn check(source: &str) -> Result<String, JsValue> {
+    to_json(&crate::check(source))
+}